@@ -0,0 +1,226 @@
+//! Axis aligned boxes of block positions and the different orders to iterate over them in
+
+use crate::BlockPos;
+use std::iter::FusedIterator;
+
+/// The width of a chunk section in blocks
+const SECTION_WIDTH: i32 = 16;
+
+/// An axis aligned box of block positions. Both corners are part of the box.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: BlockPos,
+    pub max: BlockPos,
+}
+
+impl Aabb {
+    /// Creates the box between two opposite corners, which can be given in any order
+    pub fn new(first: BlockPos, second: BlockPos) -> Aabb {
+        Aabb {
+            min: first.min(second),
+            max: first.max(second),
+        }
+    }
+
+    /// Returns the smallest box that contains every position, or `None` if there are none
+    pub fn enclosing(positions: impl IntoIterator<Item = BlockPos>) -> Option<Aabb> {
+        let mut positions = positions.into_iter();
+        let first = positions.next()?;
+        Some(positions.fold(Aabb::new(first, first), |aabb, pos| Aabb {
+            min: aabb.min.min(pos),
+            max: aabb.max.max(pos),
+        }))
+    }
+
+    /// The amount of blocks along each axis
+    pub fn size(self) -> BlockPos {
+        self.max - self.min + BlockPos::new(1, 1, 1)
+    }
+
+    /// The amount of blocks in the box
+    pub fn volume(self) -> usize {
+        let size = self.size();
+        size.x as usize * size.y as usize * size.z as usize
+    }
+
+    pub fn contains(self, pos: BlockPos) -> bool {
+        self.min.x <= pos.x
+            && pos.x <= self.max.x
+            && self.min.y <= pos.y
+            && pos.y <= self.max.y
+            && self.min.z <= pos.z
+            && pos.z <= self.max.z
+    }
+
+    /// Returns the part of the box that is also in `other`, if there is any
+    pub fn intersection(self, other: Aabb) -> Option<Aabb> {
+        let min = self.min.max(other.min);
+        let max = self.max.min(other.max);
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            return None;
+        }
+        Some(Aabb { min, max })
+    }
+
+    pub fn offset(self, offset: BlockPos) -> Aabb {
+        Aabb {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    /// The index of `pos` when iterating with [`Aabb::iter`]. This is how blocks are laid out
+    /// in clipboards and schematics.
+    pub fn index_of(self, pos: BlockPos) -> usize {
+        let size = self.size();
+        let rel = pos - self.min;
+        ((rel.y * size.z + rel.z) * size.x + rel.x) as usize
+    }
+
+    /// Iterates over the box by y, then z, then x, with x changing the fastest. This is the
+    /// same order blocks are stored in within chunk sections.
+    pub fn iter(self) -> AabbIter {
+        AabbIter {
+            aabb: self,
+            next: Some(self.min),
+        }
+    }
+
+    /// Splits the box along chunk section borders. The parts are ordered by chunk x, then
+    /// chunk z, then section y.
+    pub fn sections(self) -> impl Iterator<Item = Aabb> {
+        section_ranges(self.min.x, self.max.x).flat_map(move |(min_x, max_x)| {
+            section_ranges(self.min.z, self.max.z).flat_map(move |(min_z, max_z)| {
+                section_ranges(self.min.y, self.max.y).map(move |(min_y, max_y)| Aabb {
+                    min: BlockPos::new(min_x, min_y, min_z),
+                    max: BlockPos::new(max_x, max_y, max_z),
+                })
+            })
+        })
+    }
+
+    /// Iterates over the box section by section, and in Morton order (Z-order) within every
+    /// section. Positions that are close to each other are visited close to each other in
+    /// time, which keeps anything indexed in this order local in memory.
+    pub fn morton_iter(self) -> impl Iterator<Item = BlockPos> {
+        self.sections().flat_map(|section| {
+            let mask = !(SECTION_WIDTH - 1);
+            let base = BlockPos::new(
+                section.min.x & mask,
+                section.min.y & mask,
+                section.min.z & mask,
+            );
+            (0..(SECTION_WIDTH * SECTION_WIDTH * SECTION_WIDTH) as u32)
+                .map(move |code| base + morton_decode(code))
+                .filter(move |&pos| section.contains(pos))
+        })
+    }
+}
+
+impl IntoIterator for Aabb {
+    type Item = BlockPos;
+    type IntoIter = AabbIter;
+
+    fn into_iter(self) -> AabbIter {
+        self.iter()
+    }
+}
+
+/// The parts of `min..=max` that are in the same section
+fn section_ranges(min: i32, max: i32) -> impl Iterator<Item = (i32, i32)> {
+    (min.div_euclid(SECTION_WIDTH)..=max.div_euclid(SECTION_WIDTH)).map(move |section| {
+        let start = section * SECTION_WIDTH;
+        (start.max(min), (start + SECTION_WIDTH - 1).min(max))
+    })
+}
+
+/// Splits the interleaved bits of a Morton code into the x, y and z position in a section
+fn morton_decode(code: u32) -> BlockPos {
+    let compact = |bits: u32| {
+        let mut value = 0;
+        for i in 0..4 {
+            value |= ((bits >> (i * 3)) & 1) << i;
+        }
+        value as i32
+    };
+    BlockPos::new(compact(code), compact(code >> 1), compact(code >> 2))
+}
+
+/// Iterates over an [`Aabb`] by y, then z, then x
+pub struct AabbIter {
+    aabb: Aabb,
+    next: Option<BlockPos>,
+}
+
+impl Iterator for AabbIter {
+    type Item = BlockPos;
+
+    fn next(&mut self) -> Option<BlockPos> {
+        let pos = self.next?;
+        let Aabb { min, max } = self.aabb;
+        let mut next = pos;
+        next.x += 1;
+        if next.x > max.x {
+            next.x = min.x;
+            next.z += 1;
+            if next.z > max.z {
+                next.z = min.z;
+                next.y += 1;
+            }
+        }
+        self.next = if next.y <= max.y { Some(next) } else { None };
+        Some(pos)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self.next {
+            Some(next) => self.aabb.volume() - self.aabb.index_of(next),
+            None => 0,
+        };
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for AabbIter {}
+
+impl FusedIterator for AabbIter {}
+
+#[test]
+fn aabb_iter_order() {
+    let aabb = Aabb::new(BlockPos::new(1, 1, 1), BlockPos::new(0, 0, 0));
+    let positions: Vec<_> = aabb.iter().collect();
+    assert_eq!(positions.len(), aabb.volume());
+    assert_eq!(aabb.iter().len(), 8);
+    for (i, pos) in positions.iter().enumerate() {
+        assert_eq!(aabb.index_of(*pos), i);
+    }
+    assert_eq!(positions[1], BlockPos::new(1, 0, 0));
+    assert_eq!(positions[2], BlockPos::new(0, 0, 1));
+    assert_eq!(positions[4], BlockPos::new(0, 1, 0));
+}
+
+#[test]
+fn aabb_sections_are_aligned() {
+    let aabb = Aabb::new(BlockPos::new(-3, 0, 5), BlockPos::new(20, 17, 5));
+    let sections: Vec<_> = aabb.sections().collect();
+    assert_eq!(sections.len(), 6);
+    assert_eq!(
+        sections[0],
+        Aabb::new(BlockPos::new(-3, 0, 5), BlockPos::new(-1, 15, 5))
+    );
+    assert_eq!(
+        sections[5],
+        Aabb::new(BlockPos::new(16, 16, 5), BlockPos::new(20, 17, 5))
+    );
+    let volume: usize = sections.iter().map(|section| section.volume()).sum();
+    assert_eq!(volume, aabb.volume());
+}
+
+#[test]
+fn aabb_morton_iter_visits_every_position_once() {
+    let aabb = Aabb::new(BlockPos::new(-5, 3, 7), BlockPos::new(18, 6, 9));
+    let mut positions: Vec<_> = aabb.morton_iter().collect();
+    assert_eq!(positions.len(), aabb.volume());
+    positions.sort_by_key(|&pos| aabb.index_of(pos));
+    assert!(positions.into_iter().eq(aabb.iter()));
+}
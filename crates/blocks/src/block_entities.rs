@@ -5,7 +5,7 @@ use std::collections::HashMap;
 use std::str::FromStr;
 
 /// A single item in an inventory
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct InventoryEntry {
     pub id: u32,
     pub slot: i8,
@@ -13,7 +13,7 @@ pub struct InventoryEntry {
     pub nbt: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SignBlockEntity {
     pub rows: [String; 4],
 }
@@ -58,6 +58,17 @@ impl ContainerType {
         }
     }
 
+    /// The number of items (stacking to 64) a container has to hold for comparators to read
+    /// `ss` from it
+    pub fn items_for_ss(self, ss: u8) -> usize {
+        let slots = self.num_slots() as u32;
+        (match ss {
+            0 => 0,
+            15.. => slots * 64,
+            _ => ((32 * slots * ss as u32) as f32 / 7.0 - 1.0).ceil() as u32,
+        }) as usize
+    }
+
     pub fn window_type(self) -> u8 {
         // https://wiki.vg/Inventory
         match self {
@@ -68,7 +79,7 @@ impl ContainerType {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum BlockEntity {
     Comparator {
         output_strength: u8,
@@ -95,6 +106,27 @@ impl BlockEntity {
         }
     }
 
+    /// A container of type `ty` filled with just enough redstone dust for comparators to read
+    /// `ss` from it
+    pub fn container_with_ss(ty: ContainerType, ss: u8) -> BlockEntity {
+        let items_needed = ty.items_for_ss(ss);
+        let inventory = (0..items_needed)
+            .step_by(64)
+            .enumerate()
+            .map(|(slot, items_added)| InventoryEntry {
+                id: Item::Redstone {}.get_id(),
+                slot: slot as i8,
+                count: (items_needed - items_added).min(64) as i8,
+                nbt: None,
+            })
+            .collect();
+        BlockEntity::Container {
+            comparator_override: ss.min(15),
+            inventory,
+            ty,
+        }
+    }
+
     fn load_container(slots_nbt: &[nbt::Value], ty: ContainerType) -> Option<BlockEntity> {
         use nbt::Value;
         let num_slots = ty.num_slots();
@@ -150,36 +182,50 @@ impl BlockEntity {
         })
     }
 
+    /// Reads the text of a sign, either from the `Text1` to `Text4` tags or from the
+    /// `front_text` compound that newer versions use. Missing rows are left empty.
+    fn load_sign_rows(nbt: &HashMap<String, nbt::Value>) -> [String; 4] {
+        use nbt::Value;
+        let mut rows: [String; 4] = Default::default();
+        let front_messages = match nbt.get("front_text") {
+            Some(Value::Compound(front)) => match front.get("messages") {
+                Some(Value::List(messages)) => messages.as_slice(),
+                _ => &[],
+            },
+            _ => &[],
+        };
+        for (i, row) in rows.iter_mut().enumerate() {
+            let text = nbt
+                .get(&format!("Text{}", i + 1))
+                .or_else(|| front_messages.get(i));
+            if let Some(Value::String(text)) = text {
+                *row = text.clone();
+            }
+        }
+        rows
+    }
+
     pub fn from_nbt(nbt: &HashMap<String, nbt::Value>) -> Option<BlockEntity> {
         use nbt::Value;
         let id = nbt_unwrap_val!(&nbt.get("Id").or_else(|| nbt.get("id"))?, Value::String);
+        // Empty containers are often saved without any items
+        let items = match nbt.get("Items") {
+            Some(Value::List(items)) => items.as_slice(),
+            _ => &[],
+        };
         match id.as_ref() {
             "minecraft:comparator" => Some(BlockEntity::Comparator {
-                output_strength: *nbt_unwrap_val!(&nbt["OutputSignal"], Value::Int) as u8,
-            }),
-            "minecraft:furnace" => BlockEntity::load_container(
-                nbt_unwrap_val!(&nbt["Items"], Value::List),
-                ContainerType::Furnace,
-            ),
-            "minecraft:barrel" => BlockEntity::load_container(
-                nbt_unwrap_val!(&nbt["Items"], Value::List),
-                ContainerType::Barrel,
-            ),
-            "minecraft:hopper" => BlockEntity::load_container(
-                nbt_unwrap_val!(&nbt["Items"], Value::List),
-                ContainerType::Hopper,
-            ),
-            "minecraft:sign" => Some({
-                BlockEntity::Sign(Box::new(SignBlockEntity {
-                    rows: [
-                        // This cloning is really dumb
-                        nbt_unwrap_val!(nbt["Text1"].clone(), Value::String),
-                        nbt_unwrap_val!(nbt["Text2"].clone(), Value::String),
-                        nbt_unwrap_val!(nbt["Text3"].clone(), Value::String),
-                        nbt_unwrap_val!(nbt["Text4"].clone(), Value::String),
-                    ],
-                }))
+                output_strength: match nbt.get("OutputSignal") {
+                    Some(Value::Int(ss)) => (*ss).clamp(0, 15) as u8,
+                    _ => 0,
+                },
             }),
+            "minecraft:furnace" => BlockEntity::load_container(items, ContainerType::Furnace),
+            "minecraft:barrel" => BlockEntity::load_container(items, ContainerType::Barrel),
+            "minecraft:hopper" => BlockEntity::load_container(items, ContainerType::Hopper),
+            "minecraft:sign" => Some(BlockEntity::Sign(Box::new(SignBlockEntity {
+                rows: BlockEntity::load_sign_rows(nbt),
+            }))),
             _ => None,
         }
     }
@@ -188,26 +234,28 @@ impl BlockEntity {
         if sign_only && !matches!(self, BlockEntity::Sign(_)) {
             return None;
         }
+        Some(nbt::Blob::with_content(self.nbt_content()))
+    }
 
+    /// The nbt tags of the block entity, including its id
+    pub fn nbt_content(&self) -> HashMap<String, nbt::Value> {
         use nbt::Value;
         match self {
-            BlockEntity::Sign(sign) => Some({
+            BlockEntity::Sign(sign) => {
                 let [r1, r2, r3, r4] = sign.rows.clone();
-                nbt::Blob::with_content(map! {
+                map! {
                     "Text1" => Value::String(r1),
                     "Text2" => Value::String(r2),
                     "Text3" => Value::String(r3),
                     "Text4" => Value::String(r4),
                     "id" => Value::String("minecraft:sign".to_owned())
-                })
-            }),
-            BlockEntity::Comparator { output_strength } => Some({
-                nbt::Blob::with_content(map! {
-                    "OutputSignal" => Value::Int(*output_strength as i32),
-                    "id" => Value::String("minecraft:comparator".to_owned())
-                })
-            }),
-            BlockEntity::Container { inventory, ty, .. } => Some({
+                }
+            }
+            BlockEntity::Comparator { output_strength } => map! {
+                "OutputSignal" => Value::Int(*output_strength as i32),
+                "id" => Value::String("minecraft:comparator".to_owned())
+            },
+            BlockEntity::Container { inventory, ty, .. } => {
                 let mut items = Vec::new();
                 for entry in inventory {
                     let nbt = map! {
@@ -221,11 +269,33 @@ impl BlockEntity {
                     // }
                     items.push(nbt::Value::Compound(nbt));
                 }
-                nbt::Blob::with_content(map! {
+                map! {
                     "id" => Value::String(ty.to_string()),
                     "Items" => Value::List(items)
-                })
-            }),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn containers_filled_for_ss_are_read_as_that_ss() {
+    for ty in [
+        ContainerType::Furnace,
+        ContainerType::Barrel,
+        ContainerType::Hopper,
+    ] {
+        for ss in 0..=15 {
+            let container = BlockEntity::container_with_ss(ty, ss);
+            let loaded = BlockEntity::from_nbt(&container.nbt_content()).unwrap();
+            let BlockEntity::Container {
+                comparator_override,
+                ..
+            } = loaded
+            else {
+                panic!("{:?} was not loaded as a container", ty);
+            };
+            assert_eq!(comparator_override, ss, "{:?}", ty);
         }
     }
 }
@@ -8,6 +8,7 @@ use std::collections::HashMap;
 #[derive(Clone, Copy, Debug)]
 pub enum FlipDirection {
     FlipX,
+    FlipY,
     FlipZ,
 }
 
@@ -54,12 +55,9 @@ noop_block_transform!(
     u32,
     bool,
     BlockColorVariant,
-    BlockFacing,
-    TrapdoorHalf,
     SignType,
-    ButtonFace,
-    LeverFace,
     ComparatorMode,
+    Instrument,
 );
 
 impl BlockTransform for BlockDirection {
@@ -75,6 +73,7 @@ impl BlockTransform for BlockDirection {
                 BlockDirection::South => *self = BlockDirection::North,
                 _ => {}
             },
+            FlipDirection::FlipY => {}
         }
     }
 
@@ -88,6 +87,31 @@ impl BlockTransform for BlockDirection {
     }
 }
 
+impl BlockTransform for BlockFacing {
+    fn flip(&mut self, dir: FlipDirection) {
+        *self = match (dir, *self) {
+            (FlipDirection::FlipX, BlockFacing::East) => BlockFacing::West,
+            (FlipDirection::FlipX, BlockFacing::West) => BlockFacing::East,
+            (FlipDirection::FlipY, BlockFacing::Up) => BlockFacing::Down,
+            (FlipDirection::FlipY, BlockFacing::Down) => BlockFacing::Up,
+            (FlipDirection::FlipZ, BlockFacing::North) => BlockFacing::South,
+            (FlipDirection::FlipZ, BlockFacing::South) => BlockFacing::North,
+            (_, facing) => facing,
+        }
+    }
+
+    fn rotate90(&mut self) {
+        *self = match self {
+            BlockFacing::North => BlockFacing::East,
+            BlockFacing::East => BlockFacing::South,
+            BlockFacing::South => BlockFacing::West,
+            BlockFacing::West => BlockFacing::North,
+            BlockFacing::Up => BlockFacing::Up,
+            BlockFacing::Down => BlockFacing::Down,
+        }
+    }
+}
+
 impl Block {
     pub fn has_block_entity(self) -> bool {
         matches!(
@@ -116,6 +140,53 @@ impl Block {
             | 8145..=8146 // Tall Fern
         )
     }
+
+    /// The instrument that a note block on top of this block plays
+    pub fn instrument(self) -> Instrument {
+        match self {
+            Block::Glowstone {} => return Instrument::Pling,
+            Block::Wool { .. } => return Instrument::Guitar,
+            // Stone
+            Block::Stone {}
+            | Block::Sandstone {}
+            | Block::Quartz {}
+            | Block::SmoothQuartz {}
+            | Block::SmoothStoneSlab { .. }
+            | Block::QuartzSlab { .. }
+            | Block::Concrete { .. }
+            | Block::Terracotta {}
+            | Block::ColoredTerracotta { .. }
+            | Block::CoalBlock {}
+            | Block::Furnace { .. }
+            | Block::Observer { .. }
+            | Block::StonePressurePlate { .. } => return Instrument::Basedrum,
+            // Glass
+            Block::Glass {} | Block::StainedGlass { .. } => return Instrument::Hat,
+            // Wood
+            Block::NoteBlock { .. }
+            | Block::Barrel { .. }
+            | Block::Composter { .. }
+            | Block::Sign { .. }
+            | Block::WallSign { .. } => return Instrument::Bass,
+            _ => {}
+        }
+        match self.get_id() {
+            4016 => Instrument::Flute,            // Clay
+            1483 => Instrument::Bell,             // Gold block
+            8134 => Instrument::Chime,            // Packed ice
+            9506..=9508 => Instrument::Xylophone, // Bone block
+            1484 => Instrument::IronXylophone,    // Iron block
+            4069 => Instrument::CowBell,          // Soul sand
+            4067 => Instrument::Didgeridoo,       // Pumpkin
+            5609 => Instrument::Bit,              // Emerald block
+            8113..=8115 => Instrument::Banjo,     // Hay bale
+            14 => Instrument::Basedrum,           // Cobblestone
+            15..=20 => Instrument::Bass,          // Planks
+            66..=68 => Instrument::Snare,         // Sand, red sand and gravel
+            9704..=9719 => Instrument::Snare,     // Concrete powder
+            _ => Instrument::Harp,
+        }
+    }
 }
 
 #[test]
@@ -140,6 +211,148 @@ fn comparator_id_test() {
     assert_eq!(new, original);
 }
 
+#[test]
+fn note_block_instruments_test() {
+    assert_eq!(Block::Air {}.instrument(), Instrument::Harp);
+    assert_eq!(Block::Stone {}.instrument(), Instrument::Basedrum);
+    assert_eq!(Block::Glowstone {}.instrument(), Instrument::Pling);
+    assert_eq!(Block::from_id(1483).instrument(), Instrument::Bell);
+    assert_eq!(Block::from_id(4016).instrument(), Instrument::Flute);
+}
+
+#[test]
+fn note_block_id_test() {
+    let original = Block::NoteBlock {
+        instrument: Instrument::Guitar,
+        note: 12,
+        powered: true,
+    };
+    let id = original.get_id();
+    assert_eq!(id, 655);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+}
+
+#[test]
+fn hopper_id_test() {
+    let original = Block::Hopper {
+        enabled: false,
+        facing: BlockFacing::East,
+    };
+    let id = original.get_id();
+    assert_eq!(id, 6943);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+}
+
+#[test]
+fn piston_id_test() {
+    let original = Block::StickyPiston {
+        extended: true,
+        facing: BlockFacing::Up,
+    };
+    let id = original.get_id();
+    assert_eq!(id, 1389);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+
+    let original = Block::PistonHead {
+        head: PistonHead {
+            facing: BlockFacing::East,
+            short: false,
+            sticky: true,
+        },
+    };
+    let id = original.get_id();
+    assert_eq!(id, 1423);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+    assert_eq!(original.properties()["type"], "sticky");
+}
+
+#[test]
+fn observer_id_test() {
+    let original = Block::Observer {
+        facing: BlockFacing::Down,
+        powered: true,
+    };
+    let id = original.get_id();
+    assert_eq!(id, 9520);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+}
+
+#[test]
+fn target_id_test() {
+    let original = Block::Target { power: 9 };
+    let id = original.get_id();
+    assert_eq!(id, 16023);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+}
+
+#[test]
+fn daylight_detector_id_test() {
+    let original = Block::DaylightDetector {
+        inverted: true,
+        power: 4,
+    };
+    let id = original.get_id();
+    assert_eq!(id, 6904);
+    let new = Block::from_id(id);
+    assert_eq!(new, original);
+    assert_eq!(
+        Block::from_name("daylight_detector").map(Block::get_id),
+        Some(6916)
+    );
+}
+
+#[test]
+fn transform_orientations_test() {
+    let mut observer = Block::Observer {
+        facing: BlockFacing::North,
+        powered: false,
+    };
+    observer.rotate(RotateAmt::Rotate270);
+    assert_eq!(
+        observer,
+        Block::Observer {
+            facing: BlockFacing::West,
+            powered: false
+        }
+    );
+    observer.flip(FlipDirection::FlipX);
+    assert_eq!(
+        observer,
+        Block::Observer {
+            facing: BlockFacing::East,
+            powered: false
+        }
+    );
+
+    let mut sign = Block::Sign {
+        sign_type: SignType(0),
+        rotation: SignRotation(1),
+    };
+    sign.rotate(RotateAmt::Rotate90);
+    assert_eq!(sign.properties()["rotation"], "5");
+    sign.flip(FlipDirection::FlipX);
+    assert_eq!(sign.properties()["rotation"], "11");
+    sign.flip(FlipDirection::FlipZ);
+    assert_eq!(sign.properties()["rotation"], "13");
+
+    let mut lever = Block::Lever {
+        lever: Lever::new(LeverFace::Floor, BlockDirection::North, false),
+    };
+    lever.flip(FlipDirection::FlipY);
+    assert_eq!(
+        lever,
+        Block::Lever {
+            lever: Lever::new(LeverFace::Ceiling, BlockDirection::North, false),
+        }
+    );
+}
+
 macro_rules! blocks {
     (
         $(
@@ -509,38 +722,38 @@ blocks! {
     Sign {
         props: {
             sign_type: SignType,
-            rotation: u32
+            rotation: SignRotation
         },
-        get_id: (sign_type.0 << 5) + (rotation << 1) + 3439,
+        get_id: (sign_type.0 << 5) + (rotation.0 << 1) + 3439,
         from_id_offset: 3439,
         from_id(id): 3439..=3629 => {
             sign_type: SignType(id >> 5),
-            rotation: (id & 0b11110) >> 1
+            rotation: SignRotation((id & 0b11110) >> 1)
         },
         from_names(_name): {
             "oak_sign" => {
                 sign_type: SignType(0),
-                rotation: 0
+                rotation: SignRotation(0)
             },
             "spruce_sign" => {
                 sign_type: SignType(1),
-                rotation: 0
+                rotation: SignRotation(0)
             },
             "birch_sign" => {
                 sign_type: SignType(2),
-                rotation: 0
+                rotation: SignRotation(0)
             },
             "jungle_sign" => {
                 sign_type: SignType(3),
-                rotation: 0
+                rotation: SignRotation(0)
             },
             "acacia_sign" => {
                 sign_type: SignType(4),
-                rotation: 0
+                rotation: SignRotation(0)
             },
             "dark_oak_sign" => {
                 sign_type: SignType(5),
-                rotation: 0
+                rotation: SignRotation(0)
             }
         },
         get_name: match sign_type.0 {
@@ -700,22 +913,104 @@ blocks! {
     },
     Observer {
         props: {
-            facing: BlockFacing
+            facing: BlockFacing,
+            powered: bool
         },
-        get_id: (facing.get_id() << 1) + 9510,
+        get_id: (facing.get_id() << 1) + (!powered as u32) + 9510,
         from_id_offset: 9510,
         from_id(id): 9510..=9521 => {
-            facing: BlockFacing::from_id(id >> 1)
+            facing: BlockFacing::from_id(id >> 1),
+            powered: (id & 1) == 0
         },
         from_names(_name): {
             "observer" => {
-                facing: Default::default()
+                facing: Default::default(),
+                powered: false
             }
         },
         get_name: "observer",
-        solid: true,
         cube: true,
     },
+    Piston {
+        props: {
+            extended: bool,
+            facing: BlockFacing
+        },
+        get_id: 1404 + !extended as u32 * 6 + facing.get_id(),
+        from_id_offset: 1404,
+        from_id(id): 1404..=1415 => {
+            extended: id < 6,
+            facing: BlockFacing::from_id(id % 6)
+        },
+        from_names(_name): {
+            "piston" => {
+                extended: false,
+                facing: BlockFacing::North
+            }
+        },
+        get_name: "piston",
+        cube: true,
+    },
+    StickyPiston {
+        props: {
+            extended: bool,
+            facing: BlockFacing
+        },
+        get_id: 1385 + !extended as u32 * 6 + facing.get_id(),
+        from_id_offset: 1385,
+        from_id(id): 1385..=1396 => {
+            extended: id < 6,
+            facing: BlockFacing::from_id(id % 6)
+        },
+        from_names(_name): {
+            "sticky_piston" => {
+                extended: false,
+                facing: BlockFacing::North
+            }
+        },
+        get_name: "sticky_piston",
+        cube: true,
+    },
+    PistonHead {
+        props: {
+            head: PistonHead
+        },
+        get_id: 1416 + (head.facing.get_id() << 2) + ((!head.short as u32) << 1) + head.sticky as u32,
+        from_id_offset: 1416,
+        from_id(id): 1416..=1439 => {
+            head: PistonHead {
+                facing: BlockFacing::from_id(id >> 2),
+                short: id & 0b10 == 0,
+                sticky: id & 1 == 1,
+            }
+        },
+        from_names(_name): {
+            "piston_head" => {
+                head: Default::default()
+            }
+        },
+        get_name: "piston_head",
+    },
+    MovingPiston {
+        props: {
+            moving: MovingPiston
+        },
+        get_id: 1456 + (moving.facing.get_id() << 1) + moving.sticky as u32,
+        from_id_offset: 1456,
+        from_id(id): 1456..=1467 => {
+            moving: MovingPiston {
+                facing: BlockFacing::from_id(id >> 1),
+                sticky: id & 1 == 1,
+            }
+        },
+        from_names(_name): {
+            "moving_piston" => {
+                moving: Default::default()
+            }
+        },
+        get_name: "moving_piston",
+        transparent: true,
+    },
     SeaPickle {
         props: {
             pickles: u8
@@ -732,12 +1027,38 @@ blocks! {
         },
         get_name: "sea_pickle",
     },
+    DaylightDetector {
+        props: {
+            inverted: bool,
+            power: u8
+        },
+        get_id: 6900 + !inverted as u32 * 16 + power as u32,
+        from_id_offset: 6900,
+        from_id(id): 6900..=6931 => {
+            inverted: id < 16,
+            power: (id & 0xF) as u8
+        },
+        from_names(_name): {
+            "daylight_detector" => {
+                inverted: false,
+                power: 0
+            }
+        },
+        get_name: "daylight_detector",
+    },
     Target {
-        props: {},
-        get_id: 16014,
-        from_id(_id): 16014 => {},
+        props: {
+            power: u8
+        },
+        get_id: 16014 + power as u32,
+        from_id_offset: 16014,
+        from_id(id): 16014..=16029 => {
+            power: id as u8
+        },
         from_names(_name): {
-            "target" => {}
+            "target" => {
+                power: 0
+            }
         },
         get_name: "target",
         solid: true,
@@ -787,11 +1108,34 @@ blocks! {
         cube: true,
     },
     Hopper {
-        props: {},
-        get_id: 6939,
-        from_id(_id): 6939 => {},
+        props: {
+            enabled: bool,
+            facing: BlockFacing
+        },
+        get_id: 6934 + !enabled as u32 * 5 + match facing {
+            // Hoppers can't face up
+            BlockFacing::Down | BlockFacing::Up => 0,
+            BlockFacing::North => 1,
+            BlockFacing::South => 2,
+            BlockFacing::West => 3,
+            BlockFacing::East => 4,
+        },
+        from_id_offset: 6934,
+        from_id(id): 6934..=6943 => {
+            enabled: id < 5,
+            facing: match id % 5 {
+                0 => BlockFacing::Down,
+                1 => BlockFacing::North,
+                2 => BlockFacing::South,
+                3 => BlockFacing::West,
+                _ => BlockFacing::East,
+            }
+        },
         from_names(_name): {
-            "hopper" => {}
+            "hopper" => {
+                enabled: true,
+                facing: BlockFacing::Down
+            }
         },
         get_name: "hopper",
         transparent: true,
@@ -808,6 +1152,30 @@ blocks! {
         solid: true,
         cube: true,
     },
+    NoteBlock {
+        props: {
+            instrument: Instrument,
+            note: u32,
+            powered: bool
+        },
+        get_id: instrument.get_id() * 50 + note * 2 + !powered as u32 + 281,
+        from_id_offset: 281,
+        from_id(id): 281..=1080 => {
+            instrument: Instrument::from_id(id / 50),
+            note: (id % 50) >> 1,
+            powered: (id & 1) == 0
+        },
+        from_names(_name): {
+            "note_block" => {
+                instrument: Instrument::Harp,
+                note: 0,
+                powered: false
+            }
+        },
+        get_name: "note_block",
+        solid: true,
+        cube: true,
+    },
     CoalBlock {
         props: {},
         get_id: 8133,
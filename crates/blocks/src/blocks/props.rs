@@ -1,4 +1,6 @@
-use super::{BlockDirection, BlockProperty, BlockTransform, FlipDirection};
+use super::{BlockDirection, BlockFacing, BlockProperty, BlockTransform, FlipDirection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, BlockProperty, BlockTransform)]
@@ -36,7 +38,7 @@ impl RedstoneRepeater {
     }
 }
 
-#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ComparatorMode {
     #[default]
     Compare,
@@ -155,6 +157,18 @@ impl ToString for LeverFace {
     }
 }
 
+impl BlockTransform for LeverFace {
+    fn rotate90(&mut self) {}
+
+    fn flip(&mut self, dir: FlipDirection) {
+        *self = match (dir, *self) {
+            (FlipDirection::FlipY, LeverFace::Floor) => LeverFace::Ceiling,
+            (FlipDirection::FlipY, LeverFace::Ceiling) => LeverFace::Floor,
+            (_, face) => face,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default, BlockProperty, BlockTransform)]
 pub struct Lever {
     pub face: LeverFace,
@@ -222,6 +236,18 @@ impl ToString for ButtonFace {
     }
 }
 
+impl BlockTransform for ButtonFace {
+    fn rotate90(&mut self) {}
+
+    fn flip(&mut self, dir: FlipDirection) {
+        *self = match (dir, *self) {
+            (FlipDirection::FlipY, ButtonFace::Floor) => ButtonFace::Ceiling,
+            (FlipDirection::FlipY, ButtonFace::Ceiling) => ButtonFace::Floor,
+            (_, face) => face,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default, BlockProperty, BlockTransform)]
 pub struct StoneButton {
     pub face: ButtonFace,
@@ -345,6 +371,7 @@ impl BlockTransform for RedstoneWire {
                 south: self.north,
                 ..*self
             },
+            FlipDirection::FlipY => *self,
         }
     }
 }
@@ -390,3 +417,221 @@ impl FromStr for TrapdoorHalf {
         })
     }
 }
+
+impl BlockTransform for TrapdoorHalf {
+    fn rotate90(&mut self) {}
+
+    fn flip(&mut self, dir: FlipDirection) {
+        if let FlipDirection::FlipY = dir {
+            *self = match self {
+                TrapdoorHalf::Top => TrapdoorHalf::Bottom,
+                TrapdoorHalf::Bottom => TrapdoorHalf::Top,
+            }
+        }
+    }
+}
+
+/// The direction of a standing sign in sixteenths of a full turn, starting at south and
+/// going clockwise
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SignRotation(pub u32);
+
+impl FromStr for SignRotation {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SignRotation(s.parse::<u32>()? & 15))
+    }
+}
+
+impl ToString for SignRotation {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl BlockTransform for SignRotation {
+    fn rotate90(&mut self) {
+        self.0 = (self.0 + 4) & 15;
+    }
+
+    fn flip(&mut self, dir: FlipDirection) {
+        self.0 = match dir {
+            // East and west are 4 and 12
+            FlipDirection::FlipX => (16 - self.0) & 15,
+            // North and south are 8 and 0
+            FlipDirection::FlipZ => (24 - self.0) & 15,
+            FlipDirection::FlipY => self.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Hash)]
+pub enum Instrument {
+    #[default]
+    Harp,
+    Basedrum,
+    Snare,
+    Hat,
+    Bass,
+    Flute,
+    Bell,
+    Guitar,
+    Chime,
+    Xylophone,
+    IronXylophone,
+    CowBell,
+    Didgeridoo,
+    Bit,
+    Banjo,
+    Pling,
+}
+
+impl Instrument {
+    const VALUES: [Instrument; 16] = [
+        Instrument::Harp,
+        Instrument::Basedrum,
+        Instrument::Snare,
+        Instrument::Hat,
+        Instrument::Bass,
+        Instrument::Flute,
+        Instrument::Bell,
+        Instrument::Guitar,
+        Instrument::Chime,
+        Instrument::Xylophone,
+        Instrument::IronXylophone,
+        Instrument::CowBell,
+        Instrument::Didgeridoo,
+        Instrument::Bit,
+        Instrument::Banjo,
+        Instrument::Pling,
+    ];
+
+    pub fn from_id(id: u32) -> Instrument {
+        Instrument::VALUES[id as usize]
+    }
+
+    pub fn get_id(self) -> u32 {
+        self as u32
+    }
+
+    /// The midi key of the lowest note (note 0) this instrument can play. Every instrument
+    /// has a range of two octaves.
+    pub fn base_key(self) -> u8 {
+        match self {
+            Instrument::Bass | Instrument::Didgeridoo => 30,
+            Instrument::Guitar => 42,
+            Instrument::Flute | Instrument::CowBell => 66,
+            Instrument::Bell | Instrument::Chime | Instrument::Xylophone => 78,
+            _ => 54,
+        }
+    }
+}
+
+impl ToString for Instrument {
+    fn to_string(&self) -> String {
+        match self {
+            Instrument::Harp => "harp",
+            Instrument::Basedrum => "basedrum",
+            Instrument::Snare => "snare",
+            Instrument::Hat => "hat",
+            Instrument::Bass => "bass",
+            Instrument::Flute => "flute",
+            Instrument::Bell => "bell",
+            Instrument::Guitar => "guitar",
+            Instrument::Chime => "chime",
+            Instrument::Xylophone => "xylophone",
+            Instrument::IronXylophone => "iron_xylophone",
+            Instrument::CowBell => "cow_bell",
+            Instrument::Didgeridoo => "didgeridoo",
+            Instrument::Bit => "bit",
+            Instrument::Banjo => "banjo",
+            Instrument::Pling => "pling",
+        }
+        .to_owned()
+    }
+}
+
+impl FromStr for Instrument {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Instrument::VALUES
+            .iter()
+            .copied()
+            .find(|instrument| instrument.to_string() == s)
+            .ok_or(())
+    }
+}
+
+fn encode_piston_type(sticky: bool, props: &mut HashMap<&'static str, String>) {
+    let ty = if sticky { "sticky" } else { "normal" };
+    props.insert("type", ty.to_owned());
+}
+
+fn decode_piston_type(sticky: &mut bool, props: &HashMap<&str, &str>) {
+    if let Some(&ty) = props.get("type") {
+        *sticky = ty == "sticky";
+    }
+}
+
+// The piston head and moving piston properties are implemented by hand because the vanilla
+// property name `type` is a keyword.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, BlockTransform)]
+pub struct PistonHead {
+    pub facing: BlockFacing,
+    pub short: bool,
+    pub sticky: bool,
+}
+
+impl Default for PistonHead {
+    fn default() -> Self {
+        PistonHead {
+            facing: BlockFacing::North,
+            short: false,
+            sticky: false,
+        }
+    }
+}
+
+impl BlockProperty for PistonHead {
+    fn encode(self, props: &mut HashMap<&'static str, String>, _name: &'static str) {
+        self.facing.encode(props, "facing");
+        self.short.encode(props, "short");
+        encode_piston_type(self.sticky, props);
+    }
+
+    fn decode(&mut self, props: &HashMap<&str, &str>, _name: &str) {
+        self.facing.decode(props, "facing");
+        self.short.decode(props, "short");
+        decode_piston_type(&mut self.sticky, props);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, BlockTransform)]
+pub struct MovingPiston {
+    pub facing: BlockFacing,
+    pub sticky: bool,
+}
+
+impl Default for MovingPiston {
+    fn default() -> Self {
+        MovingPiston {
+            facing: BlockFacing::North,
+            sticky: false,
+        }
+    }
+}
+
+impl BlockProperty for MovingPiston {
+    fn encode(self, props: &mut HashMap<&'static str, String>, _name: &'static str) {
+        self.facing.encode(props, "facing");
+        encode_piston_type(self.sticky, props);
+    }
+
+    fn decode(&mut self, props: &HashMap<&str, &str>, _name: &str) {
+        self.facing.decode(props, "facing");
+        decode_piston_type(&mut self.sticky, props);
+    }
+}
@@ -17,13 +17,7 @@ impl ItemStack {
             ContainerType::Hopper => Item::Hopper {},
             ContainerType::Furnace => Item::Furnace {},
         };
-        let slots = container_ty.num_slots() as u32;
-
-        let items_needed = match ss {
-            0 => 0,
-            15 => slots * 64,
-            _ => ((32 * slots * ss as u32) as f32 / 7.0 - 1.0).ceil() as u32,
-        } as usize;
+        let items_needed = container_ty.items_for_ss(ss);
 
         let nbt = match items_needed {
             0 => None,
@@ -243,6 +237,12 @@ items! {
         from_id(_id): 607 => {},
         block: true,
     },
+    NoteBlock {
+        props: {},
+        get_id: 608,
+        from_id(_id): 608 => {},
+        block: true,
+    },
     RedstoneBlock {
         props: {},
         get_id: 587,
@@ -312,6 +312,24 @@ items! {
         from_id(_id): 589 => {},
         block: true,
     },
+    Piston {
+        props: {},
+        get_id: 590,
+        from_id(_id): 590 => {},
+        block: true,
+    },
+    StickyPiston {
+        props: {},
+        get_id: 591,
+        from_id(_id): 591 => {},
+        block: true,
+    },
+    Observer {
+        props: {},
+        get_id: 594,
+        from_id(_id): 594 => {},
+        block: true,
+    },
     Sign {
         props: {
             sign_type: u32
@@ -329,6 +347,12 @@ items! {
         from_id(_id): 1043 => {},
         block: true,
     },
+    DaylightDetector {
+        props: {},
+        get_id: 602,
+        from_id(_id): 602 => {},
+        block: true,
+    },
     Target {
         props: {},
         get_id: 599,
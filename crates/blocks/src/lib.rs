@@ -1,3 +1,4 @@
+pub mod aabb;
 pub mod block_entities;
 pub mod blocks;
 pub mod items;
@@ -7,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str::FromStr;
 
-#[derive(PartialEq, Eq, Copy, Clone, Debug, Serialize, Deserialize, Hash)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default, Serialize, Deserialize, Hash)]
 pub struct BlockPos {
     pub x: i32,
     pub y: i32,
@@ -115,7 +116,7 @@ where
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BlockFace {
     Bottom,
     Top,
@@ -389,6 +390,30 @@ impl BlockFacing {
             other => other,
         }
     }
+
+    pub fn opposite(self) -> BlockFacing {
+        use BlockFacing::*;
+        match self {
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+            Up => Down,
+            Down => Up,
+        }
+    }
+
+    pub fn block_face(self) -> BlockFace {
+        use BlockFacing::*;
+        match self {
+            North => BlockFace::North,
+            South => BlockFace::South,
+            East => BlockFace::East,
+            West => BlockFace::West,
+            Up => BlockFace::Top,
+            Down => BlockFace::Bottom,
+        }
+    }
 }
 
 impl ToString for BlockFacing {
@@ -3,28 +3,42 @@ use std::time::Instant;
 
 use criterion::*;
 use mchprs_blocks::BlockPos;
-use mchprs_core::plot::{PlotWorld, PLOT_WIDTH};
+use mchprs_core::plot::{PlotSize, PlotWorld};
 use mchprs_core::redpiler::{Compiler, CompilerOptions};
+use mchprs_core::redstone::daylight_detector::NOON;
 use mchprs_core::world::storage::Chunk;
 use mchprs_save_data::plot_data::PlotData;
 
 const START_BUTTON: BlockPos = BlockPos::new(187, 99, 115);
 
 fn load_world(path: impl AsRef<Path>) -> PlotWorld {
-    let data = PlotData::load_from_file(path).unwrap();
+    let size = PlotSize::default();
+    let data = PlotData::load_from_file(path, size.sections).unwrap();
 
     let chunks: Vec<Chunk> = data
         .chunk_data
         .into_iter()
         .enumerate()
-        .map(|(i, c)| Chunk::load(i as i32 / PLOT_WIDTH, i as i32 % PLOT_WIDTH, c))
+        .map(|(i, c)| {
+            Chunk::load(
+                i as i32 / size.width(),
+                i as i32 % size.width(),
+                c,
+                size.sections,
+            )
+        })
         .collect();
     PlotWorld {
         x: 0,
         z: 0,
+        size,
         chunks,
         to_be_ticked: data.pending_ticks,
         packet_senders: Vec::new(),
+        note_events: Vec::new(),
+        update_queue: Default::default(),
+        daytime: NOON,
+        locational_updates: false,
     }
 }
 
@@ -34,7 +48,9 @@ fn init_compiler() -> Compiler {
 
     let options = CompilerOptions::parse("-O");
     let bounds = world.get_corners();
-    compiler.compile(&mut world, bounds, options, Vec::new());
+    compiler
+        .compile(&mut world, bounds, options, Vec::new())
+        .unwrap();
     compiler.on_use_block(START_BUTTON);
     compiler
 }
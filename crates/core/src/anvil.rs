@@ -0,0 +1,735 @@
+//! Imports and exports vanilla worlds saved in the Anvil format, used by the `mchprs import` and
+//! `mchprs export` subcommands. Coordinates are kept as they are, so a build at the same position
+//! in vanilla ends up in the plot that covers that position and the other way around. Blocks in
+//! the imported area replace the blocks of the plot, including air, so plots should be imported
+//! while the server is stopped. Exported chunks replace the chunks of the vanilla world entirely.
+//!
+//! https://minecraft.wiki/w/Region_file_format
+//! https://minecraft.wiki/w/Chunk_format
+
+use crate::plot::{PlotWorld, PLOT_SIZE};
+use crate::world::World;
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use nbt::Value;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// The width of a region in chunks
+const REGION_WIDTH: i32 = 32;
+const SECTOR_LEN: usize = 4096;
+/// The data version of 1.13, before which blocks were stored by numeric id
+const DATA_VERSION_1_13: i32 = 1451;
+/// The data version of 1.16, before which block states could be split between two longs
+const DATA_VERSION_1_16: i32 = 2566;
+/// The data version of 1.18.2, which exported chunks are saved as
+const DATA_VERSION_1_18_2: i32 = 2975;
+/// The sections of a 1.18 world, which goes from y -64 up to y 320
+const VANILLA_SECTIONS: Range<i32> = -4..20;
+
+/// A block of a section palette, which is the name of the block if it isn't supported
+type PaletteEntry = Result<u32, String>;
+
+struct Section {
+    y: i32,
+    palette: Vec<PaletteEntry>,
+    /// Indices into the palette in the order y, z, x
+    blocks: Vec<u16>,
+}
+
+impl Section {
+    fn get(&self, x: i32, y: i32, z: i32) -> &PaletteEntry {
+        let index = ((y << 8) | (z << 4) | x) as usize;
+        &self.palette[self.blocks[index] as usize]
+    }
+}
+
+struct AnvilChunk {
+    sections: Vec<Section>,
+    block_entities: Vec<(BlockPos, BlockEntity)>,
+}
+
+#[derive(Default)]
+pub struct ImportReport {
+    pub chunks: usize,
+    /// The plots that were written to
+    pub plots: Vec<(i32, i32)>,
+    /// The blocks that aren't supported by name, with the amount of them that were replaced by
+    /// air
+    pub unsupported: BTreeMap<String, usize>,
+    /// The amount of blocks that were below 0 or above the height of the world
+    pub outside_height: usize,
+}
+
+#[derive(Default)]
+pub struct ExportReport {
+    pub chunks: usize,
+    /// The plots that were exported
+    pub plots: Vec<(i32, i32)>,
+    /// The amount of blocks above the height of a vanilla world, which were left out
+    pub outside_height: usize,
+}
+
+/// The compression type and the data of the chunk at `index` of a region file, which is `None`
+/// if it was never generated
+fn raw_chunk(region: &[u8], index: usize) -> Result<Option<(u8, &[u8])>> {
+    let location = BigEndian::read_u32(&region[index * 4..]);
+    let offset = (location >> 8) as usize * SECTOR_LEN;
+    if offset == 0 || location & 0xFF == 0 {
+        return Ok(None);
+    }
+    let header = region
+        .get(offset..offset + 5)
+        .context("chunk is outside of the region file")?;
+    let len = BigEndian::read_u32(header) as usize;
+    let data = region
+        .get(offset + 5..offset + 4 + len)
+        .context("chunk is outside of the region file")?;
+    Ok(Some((header[4], data)))
+}
+
+/// Reads the chunk at `index` of a region file, which is `None` if it was never generated
+fn read_chunk(region: &[u8], index: usize) -> Result<Option<nbt::Blob>> {
+    let Some((compression, mut data)) = raw_chunk(region, index)? else {
+        return Ok(None);
+    };
+    Ok(Some(match compression {
+        1 => nbt::Blob::from_gzip_reader(&mut data)?,
+        2 => nbt::Blob::from_zlib_reader(&mut data)?,
+        3 => nbt::Blob::from_reader(&mut data)?,
+        compression => bail!("chunk uses unsupported compression type {}", compression),
+    }))
+}
+
+fn palette_entry(entry: &Value) -> Result<PaletteEntry> {
+    let Value::Compound(entry) = entry else {
+        bail!("palette entry is not a compound");
+    };
+    let Some(Value::String(name)) = entry.get("Name") else {
+        bail!("palette entry doesn't have a name");
+    };
+    let block = match name.strip_prefix("minecraft:") {
+        Some("cave_air" | "void_air") => Some(Block::Air {}),
+        Some(name) => Block::from_name(name),
+        None => None,
+    };
+    let Some(mut block) = block else {
+        return Ok(Err(name.clone()));
+    };
+    if let Some(Value::Compound(properties)) = entry.get("Properties") {
+        let properties = properties
+            .iter()
+            .filter_map(|(key, val)| match val {
+                Value::String(val) => Some((key.as_str(), val.as_str())),
+                _ => None,
+            })
+            .collect();
+        block.set_properties(properties);
+    }
+    Ok(Ok(block.get_id()))
+}
+
+/// Unpacks 4096 palette indices from longs. Since 1.16, entries are never split between two
+/// longs, so the bits at the end of each long are unused. Before that, they are `spanning`.
+fn unpack_blocks(longs: &[i64], palette_len: usize, spanning: bool) -> Option<Vec<u16>> {
+    if palette_len <= 1 {
+        return Some(vec![0; 4096]);
+    }
+    let bits = (usize::BITS - (palette_len - 1).leading_zeros()).max(4) as usize;
+    let per_long = 64 / bits;
+    let mask = (1u64 << bits) - 1;
+    (0..4096)
+        .map(|i| {
+            let shifted = if spanning {
+                let start = i * bits;
+                let (long, offset) = (start / 64, start % 64);
+                let low = *longs.get(long)? as u64 >> offset;
+                match offset + bits > 64 {
+                    true => low | (*longs.get(long + 1)? as u64) << (64 - offset),
+                    false => low,
+                }
+            } else {
+                *longs.get(i / per_long)? as u64 >> ((i % per_long) * bits)
+            };
+            let entry = shifted & mask;
+            (entry < palette_len as u64).then_some(entry as u16)
+        })
+        .collect()
+}
+
+fn load_section(section: &HashMap<String, Value>, spanning: bool) -> Result<Option<Section>> {
+    let y = match section.get("Y") {
+        Some(Value::Byte(y)) => *y as i32,
+        Some(Value::Int(y)) => *y,
+        _ => bail!("section doesn't have a height"),
+    };
+    // Since 1.18, the palette and the blocks are in `block_states`
+    let (palette, longs) = match section.get("block_states") {
+        Some(Value::Compound(states)) => (states.get("palette"), states.get("data")),
+        _ => (section.get("Palette"), section.get("BlockStates")),
+    };
+    // Sections with only light data don't have blocks
+    let Some(Value::List(palette)) = palette else {
+        return Ok(None);
+    };
+    let palette: Vec<PaletteEntry> = palette.iter().map(palette_entry).collect::<Result<_>>()?;
+    let longs = match longs {
+        Some(Value::LongArray(longs)) => longs.as_slice(),
+        _ => &[],
+    };
+    let blocks = unpack_blocks(longs, palette.len(), spanning)
+        .with_context(|| format!("the blocks of section {} are invalid", y))?;
+    Ok(Some(Section { y, palette, blocks }))
+}
+
+fn load_chunk(nbt: &nbt::Blob) -> Result<AnvilChunk> {
+    let data_version = match nbt.get("DataVersion") {
+        Some(Value::Int(version)) => *version,
+        _ => 0,
+    };
+    if data_version < DATA_VERSION_1_13 {
+        bail!("chunks saved before 1.13 are not supported, open the world in a newer version of minecraft first");
+    }
+    let spanning = data_version < DATA_VERSION_1_16;
+    // Before 1.18, everything is inside of a `Level` compound
+    let (sections, block_entities) = match nbt.get("Level") {
+        Some(Value::Compound(level)) => (level.get("Sections"), level.get("TileEntities")),
+        _ => (nbt.get("sections"), nbt.get("block_entities")),
+    };
+    let mut chunk = AnvilChunk {
+        sections: Vec::new(),
+        block_entities: Vec::new(),
+    };
+    if let Some(Value::List(sections)) = sections {
+        for section in sections {
+            if let Value::Compound(section) = section {
+                chunk.sections.extend(load_section(section, spanning)?);
+            }
+        }
+    }
+    if let Some(Value::List(block_entities)) = block_entities {
+        for block_entity in block_entities {
+            let Value::Compound(nbt) = block_entity else {
+                continue;
+            };
+            let (Some(Value::Int(x)), Some(Value::Int(y)), Some(Value::Int(z))) =
+                (nbt.get("x"), nbt.get("y"), nbt.get("z"))
+            else {
+                continue;
+            };
+            if let Some(block_entity) = BlockEntity::from_nbt(nbt) {
+                chunk
+                    .block_entities
+                    .push((BlockPos::new(*x, *y, *z), block_entity));
+            }
+        }
+    }
+    Ok(chunk)
+}
+
+/// Finds the region files in `path`, which is either a world or its region folder
+fn region_files(path: &Path) -> Result<HashMap<(i32, i32), PathBuf>> {
+    let dir = match path.join("region").is_dir() {
+        true => path.join("region"),
+        false => path.to_owned(),
+    };
+    let mut regions = HashMap::new();
+    for entry in fs::read_dir(&dir).with_context(|| format!("error reading {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let coords: Vec<&str> = name.split('.').collect();
+        if let ["r", x, z, "mca"] = coords[..] {
+            if let (Ok(x), Ok(z)) = (x.parse(), z.parse()) {
+                regions.insert((x, z), path);
+            }
+        }
+    }
+    if regions.is_empty() {
+        bail!("there are no region files in {}", dir.display());
+    }
+    Ok(regions)
+}
+
+fn read_region(path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path).with_context(|| format!("error reading {}", path.display()))?;
+    // Regions that were created but never written to are empty
+    if !data.is_empty() && data.len() < 2 * SECTOR_LEN {
+        bail!("region file {} is too short", path.display());
+    }
+    Ok(data)
+}
+
+/// Imports the blocks of `chunk` at `chunk_x` and `chunk_z` that are within `bounds` into
+/// `world`. Sections that aren't in the chunk are air.
+fn import_chunk(
+    world: &mut PlotWorld,
+    chunk: &AnvilChunk,
+    (chunk_x, chunk_z): (i32, i32),
+    (min, max): (BlockPos, BlockPos),
+    report: &mut ImportReport,
+) {
+    let height = world.size.block_height();
+    let inside = |pos: BlockPos| pos.min(min) == min && pos.max(max) == max;
+    let outside_height = |section: &&Section| section.y < 0 || section.y * 16 + 16 > height;
+    for section in chunk.sections.iter().filter(outside_height) {
+        for (index, &block) in section.blocks.iter().enumerate() {
+            let pos = BlockPos::new(
+                (chunk_x << 4) | (index & 0xF) as i32,
+                section.y * 16 + (index >> 8) as i32,
+                (chunk_z << 4) | ((index >> 4) & 0xF) as i32,
+            );
+            let air = section.palette[block as usize] == Ok(0);
+            if !air && inside(pos) && !(0..height).contains(&pos.y) {
+                report.outside_height += 1;
+            }
+        }
+    }
+
+    for y in min.y.max(0)..=max.y.min(height - 1) {
+        let section = chunk.sections.iter().find(|section| section.y == y >> 4);
+        for z in (chunk_z << 4).max(min.z)..=((chunk_z << 4) + 15).min(max.z) {
+            for x in (chunk_x << 4).max(min.x)..=((chunk_x << 4) + 15).min(max.x) {
+                let pos = BlockPos::new(x, y, z);
+                let id = match section.map(|section| section.get(x & 0xF, y & 0xF, z & 0xF)) {
+                    Some(Ok(id)) => *id,
+                    Some(Err(name)) => {
+                        *report.unsupported.entry(name.clone()).or_default() += 1;
+                        0
+                    }
+                    None => 0,
+                };
+                world.set_block_raw(pos, id);
+                world.delete_block_entity(pos);
+            }
+        }
+    }
+    for (pos, block_entity) in &chunk.block_entities {
+        if inside(*pos) && (0..height).contains(&pos.y) {
+            world.set_block_entity(*pos, block_entity.clone());
+        }
+    }
+}
+
+/// The corners of `bounds`, which covers everything if there are no bounds
+fn bounds_or_everything(bounds: Option<(BlockPos, BlockPos)>) -> (BlockPos, BlockPos) {
+    match bounds {
+        Some((first_pos, second_pos)) => (first_pos.min(second_pos), first_pos.max(second_pos)),
+        None => (
+            BlockPos::new(i32::MIN, i32::MIN, i32::MIN),
+            BlockPos::new(i32::MAX, i32::MAX, i32::MAX),
+        ),
+    }
+}
+
+/// Imports the blocks between `first_pos` and `second_pos` of the vanilla world at `path` into
+/// the plots that cover them, or the whole world if no bounds are given
+pub fn import(path: &Path, bounds: Option<(BlockPos, BlockPos)>) -> Result<ImportReport> {
+    let regions = region_files(path)?;
+    let (min, max) = bounds_or_everything(bounds);
+
+    // Every generated chunk within the bounds, by the plot it is in
+    let scale = PLOT_SIZE.scale;
+    let mut plots: BTreeMap<(i32, i32), Vec<(i32, i32)>> = BTreeMap::new();
+    for (&(region_x, region_z), path) in &regions {
+        let region = read_region(path)?;
+        if region.is_empty() {
+            continue;
+        }
+        for index in 0..(REGION_WIDTH * REGION_WIDTH) as usize {
+            if BigEndian::read_u32(&region[index * 4..]) == 0 {
+                continue;
+            }
+            let chunk_x = region_x * REGION_WIDTH + index as i32 % REGION_WIDTH;
+            let chunk_z = region_z * REGION_WIDTH + index as i32 / REGION_WIDTH;
+            let inside = (min.x >> 4..=max.x >> 4).contains(&chunk_x)
+                && (min.z >> 4..=max.z >> 4).contains(&chunk_z);
+            if inside {
+                plots
+                    .entry((chunk_x >> scale, chunk_z >> scale))
+                    .or_default()
+                    .push((chunk_x, chunk_z));
+            }
+        }
+    }
+
+    let mut report = ImportReport::default();
+    for ((plot_x, plot_z), chunks) in plots {
+        let (mut world, tps) = PlotWorld::open(plot_x, plot_z)?;
+        let mut region_cache: HashMap<(i32, i32), Vec<u8>> = HashMap::new();
+        for (chunk_x, chunk_z) in chunks {
+            let region_pos = (
+                chunk_x.div_euclid(REGION_WIDTH),
+                chunk_z.div_euclid(REGION_WIDTH),
+            );
+            if !region_cache.contains_key(&region_pos) {
+                region_cache.insert(region_pos, read_region(&regions[&region_pos])?);
+            }
+            let region = &region_cache[&region_pos];
+            let index =
+                chunk_z.rem_euclid(REGION_WIDTH) * REGION_WIDTH + chunk_x.rem_euclid(REGION_WIDTH);
+            let context = || format!("error reading chunk {},{}", chunk_x, chunk_z);
+            let Some(nbt) = read_chunk(region, index as usize).with_context(context)? else {
+                continue;
+            };
+            let chunk = load_chunk(&nbt).with_context(context)?;
+            import_chunk(
+                &mut world,
+                &chunk,
+                (chunk_x, chunk_z),
+                (min, max),
+                &mut report,
+            );
+            report.chunks += 1;
+        }
+        world.save_to_disk(tps)?;
+        info!("Imported plot {},{}", plot_x, plot_z);
+        report.plots.push((plot_x, plot_z));
+    }
+    Ok(report)
+}
+
+/// Packs palette indices into longs the way 1.16 and later do, see [`unpack_blocks`]
+fn pack_blocks(blocks: &[u16], palette_len: usize) -> Vec<i64> {
+    if palette_len <= 1 {
+        return Vec::new();
+    }
+    let bits = (usize::BITS - (palette_len - 1).leading_zeros()).max(4) as usize;
+    let per_long = 64 / bits;
+    blocks
+        .chunks(per_long)
+        .map(|entries| {
+            entries.iter().enumerate().fold(0u64, |long, (i, &entry)| {
+                long | (entry as u64) << (i * bits)
+            }) as i64
+        })
+        .collect()
+}
+
+fn palette_nbt(block: Block) -> Value {
+    let mut entry = HashMap::new();
+    let name = format!("minecraft:{}", block.get_name());
+    entry.insert("Name".to_owned(), Value::String(name));
+    let properties = block.properties();
+    if !properties.is_empty() {
+        let properties = properties
+            .into_iter()
+            .map(|(key, val)| (key.to_owned(), Value::String(val)))
+            .collect();
+        entry.insert("Properties".to_owned(), Value::Compound(properties));
+    }
+    Value::Compound(entry)
+}
+
+/// The section at `section_y` of the chunk at `chunk_x` and `chunk_z`, with the blocks outside
+/// of the bounds or the plot left as air
+fn export_section(
+    world: &PlotWorld,
+    (chunk_x, chunk_z): (i32, i32),
+    section_y: i32,
+    inside: impl Fn(BlockPos) -> bool,
+) -> Value {
+    let height = world.size.block_height();
+    let mut palette = vec![0];
+    let mut blocks = Vec::with_capacity(4096);
+    for index in 0..4096 {
+        let pos = BlockPos::new(
+            (chunk_x << 4) | (index & 0xF),
+            section_y * 16 + (index >> 8),
+            (chunk_z << 4) | ((index >> 4) & 0xF),
+        );
+        let id = match inside(pos) && (0..height).contains(&pos.y) {
+            true => world.get_block_raw(pos),
+            false => 0,
+        };
+        let entry = match palette.iter().position(|&entry| entry == id) {
+            Some(entry) => entry,
+            None => {
+                palette.push(id);
+                palette.len() - 1
+            }
+        };
+        blocks.push(entry as u16);
+    }
+
+    let mut states = HashMap::new();
+    let data = pack_blocks(&blocks, palette.len());
+    if !data.is_empty() {
+        states.insert("data".to_owned(), Value::LongArray(data));
+    }
+    let palette = palette
+        .into_iter()
+        .map(|id| palette_nbt(Block::from_id(id)))
+        .collect();
+    states.insert("palette".to_owned(), Value::List(palette));
+    let mut biomes = HashMap::new();
+    biomes.insert(
+        "palette".to_owned(),
+        Value::List(vec![Value::String("minecraft:plains".to_owned())]),
+    );
+    let mut section = HashMap::new();
+    section.insert("Y".to_owned(), Value::Byte(section_y as i8));
+    section.insert("block_states".to_owned(), Value::Compound(states));
+    section.insert("biomes".to_owned(), Value::Compound(biomes));
+    Value::Compound(section)
+}
+
+/// The chunk at `chunk_x` and `chunk_z` of `world` as a 1.18.2 chunk. Lighting and heightmaps
+/// are left out, vanilla calculates them when the chunk is loaded.
+fn export_chunk(
+    world: &PlotWorld,
+    (chunk_x, chunk_z): (i32, i32),
+    (min, max): (BlockPos, BlockPos),
+    report: &mut ExportReport,
+) -> nbt::Blob {
+    let inside = |pos: BlockPos| pos.min(min) == min && pos.max(max) == max;
+    let height = world.size.block_height();
+    let vanilla_height = VANILLA_SECTIONS.end * 16;
+    for y in vanilla_height..height {
+        for z in 0..16 {
+            for x in 0..16 {
+                let pos = BlockPos::new((chunk_x << 4) | x, y, (chunk_z << 4) | z);
+                if inside(pos) && world.get_block_raw(pos) != 0 {
+                    report.outside_height += 1;
+                }
+            }
+        }
+    }
+
+    let sections = VANILLA_SECTIONS
+        .map(|section_y| export_section(world, (chunk_x, chunk_z), section_y, inside))
+        .collect();
+    let mut block_entities = Vec::new();
+    if let Some(chunk) = world.get_chunk(chunk_x, chunk_z) {
+        for (pos, block_entity) in &chunk.block_entities {
+            if !inside(*pos) || pos.y >= vanilla_height {
+                continue;
+            }
+            let mut nbt = block_entity.nbt_content();
+            nbt.insert("x".to_owned(), Value::Int(pos.x));
+            nbt.insert("y".to_owned(), Value::Int(pos.y));
+            nbt.insert("z".to_owned(), Value::Int(pos.z));
+            nbt.insert("keepPacked".to_owned(), Value::Byte(0));
+            block_entities.push(Value::Compound(nbt));
+        }
+    }
+
+    let mut nbt = HashMap::new();
+    nbt.insert("DataVersion".to_owned(), Value::Int(DATA_VERSION_1_18_2));
+    nbt.insert("xPos".to_owned(), Value::Int(chunk_x));
+    nbt.insert("yPos".to_owned(), Value::Int(VANILLA_SECTIONS.start));
+    nbt.insert("zPos".to_owned(), Value::Int(chunk_z));
+    nbt.insert("Status".to_owned(), Value::String("full".to_owned()));
+    nbt.insert("LastUpdate".to_owned(), Value::Long(0));
+    nbt.insert("InhabitedTime".to_owned(), Value::Long(0));
+    nbt.insert("isLightOn".to_owned(), Value::Byte(0));
+    nbt.insert("sections".to_owned(), Value::List(sections));
+    nbt.insert("block_entities".to_owned(), Value::List(block_entities));
+    nbt::Blob::with_content(nbt)
+}
+
+/// The chunks of a region file, as their compression type and data. Chunks are replaced one at a
+/// time and the rest of the region is kept as it was.
+struct RegionWriter {
+    chunks: Vec<Option<(u8, Vec<u8>)>>,
+}
+
+impl RegionWriter {
+    fn open(path: &Path) -> Result<RegionWriter> {
+        let mut chunks = vec![None; (REGION_WIDTH * REGION_WIDTH) as usize];
+        if path.exists() {
+            let region = read_region(path)?;
+            if !region.is_empty() {
+                for (index, chunk) in chunks.iter_mut().enumerate() {
+                    let data = raw_chunk(&region, index)
+                        .with_context(|| format!("error reading {}", path.display()))?;
+                    *chunk = data.map(|(compression, data)| (compression, data.to_vec()));
+                }
+            }
+        }
+        Ok(RegionWriter { chunks })
+    }
+
+    fn set_chunk(&mut self, (chunk_x, chunk_z): (i32, i32), nbt: &nbt::Blob) -> Result<()> {
+        let mut data = Vec::new();
+        nbt.to_zlib_writer(&mut data)?;
+        let index =
+            chunk_z.rem_euclid(REGION_WIDTH) * REGION_WIDTH + chunk_x.rem_euclid(REGION_WIDTH);
+        self.chunks[index as usize] = Some((2, data));
+        Ok(())
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        // The locations and the timestamps each take up the first two sectors
+        let mut header = vec![0; 2 * SECTOR_LEN];
+        let mut sectors = Vec::new();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let Some((compression, data)) = chunk else {
+                continue;
+            };
+            let offset = 2 + sectors.len() / SECTOR_LEN;
+            let mut len = [0; 4];
+            BigEndian::write_u32(&mut len, data.len() as u32 + 1);
+            sectors.extend_from_slice(&len);
+            sectors.push(*compression);
+            sectors.extend_from_slice(data);
+            sectors.resize(sectors.len().next_multiple_of(SECTOR_LEN), 0);
+            let count = 2 + sectors.len() / SECTOR_LEN - offset;
+            if count > 0xFF {
+                bail!("chunk {} of {} is too large", index, path.display());
+            }
+            BigEndian::write_u32(&mut header[index * 4..], (offset << 8 | count) as u32);
+            BigEndian::write_u32(&mut header[SECTOR_LEN + index * 4..], timestamp);
+        }
+        header.extend_from_slice(&sectors);
+        fs::write(path, header).with_context(|| format!("error writing {}", path.display()))
+    }
+}
+
+/// The plots that have been saved in the world folder
+fn saved_plots() -> Result<Vec<(i32, i32)>> {
+    let mut plots = Vec::new();
+    for entry in fs::read_dir("./world/plots").context("error reading ./world/plots")? {
+        let name = entry?.file_name();
+        let Some((x, z)) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix('p'))
+            .and_then(|name| name.split_once(','))
+        else {
+            continue;
+        };
+        if let (Ok(x), Ok(z)) = (x.parse(), z.parse()) {
+            plots.push((x, z));
+        }
+    }
+    plots.sort();
+    Ok(plots)
+}
+
+/// Exports the blocks between `first_pos` and `second_pos` of the saved plots into the vanilla
+/// world at `path`, or every saved plot if no bounds are given. The world is created if it
+/// doesn't exist yet.
+pub fn export(path: &Path, bounds: Option<(BlockPos, BlockPos)>) -> Result<ExportReport> {
+    let (min, max) = bounds_or_everything(bounds);
+    let region_dir = path.join("region");
+    fs::create_dir_all(&region_dir)
+        .with_context(|| format!("error creating {}", region_dir.display()))?;
+
+    let mut report = ExportReport::default();
+    let mut regions: BTreeMap<(i32, i32), RegionWriter> = BTreeMap::new();
+    let width = PLOT_SIZE.width();
+    for (plot_x, plot_z) in saved_plots()? {
+        let chunks: Vec<(i32, i32)> = (0..width * width)
+            .map(|i| (plot_x * width + i % width, plot_z * width + i / width))
+            .filter(|&(chunk_x, chunk_z)| {
+                (min.x >> 4..=max.x >> 4).contains(&chunk_x)
+                    && (min.z >> 4..=max.z >> 4).contains(&chunk_z)
+            })
+            .collect();
+        if chunks.is_empty() {
+            continue;
+        }
+        let (world, _) = PlotWorld::open(plot_x, plot_z)?;
+        for (chunk_x, chunk_z) in chunks {
+            let nbt = export_chunk(&world, (chunk_x, chunk_z), (min, max), &mut report);
+            let region_pos = (
+                chunk_x.div_euclid(REGION_WIDTH),
+                chunk_z.div_euclid(REGION_WIDTH),
+            );
+            let region = match regions.entry(region_pos) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let path = region_dir.join(format!("r.{}.{}.mca", region_pos.0, region_pos.1));
+                    entry.insert(RegionWriter::open(&path)?)
+                }
+            };
+            region.set_chunk((chunk_x, chunk_z), &nbt)?;
+            report.chunks += 1;
+        }
+        info!("Exported plot {},{}", plot_x, plot_z);
+        report.plots.push((plot_x, plot_z));
+    }
+    for ((region_x, region_z), region) in &regions {
+        region.write(&region_dir.join(format!("r.{}.{}.mca", region_x, region_z)))?;
+    }
+    Ok(report)
+}
+
+#[test]
+fn anvil_sections_are_unpacked() {
+    let entry = |name: &str, properties: &[(&str, &str)]| {
+        let mut entry = HashMap::new();
+        entry.insert("Name".to_owned(), Value::String(name.to_owned()));
+        if !properties.is_empty() {
+            let properties = properties
+                .iter()
+                .map(|(key, val)| (key.to_string(), Value::String(val.to_string())))
+                .collect();
+            entry.insert("Properties".to_owned(), Value::Compound(properties));
+        }
+        Value::Compound(entry)
+    };
+    let palette = vec![
+        entry("minecraft:air", &[]),
+        entry("minecraft:redstone_lamp", &[("lit", "true")]),
+        entry("minecraft:sculk_sensor", &[]),
+    ];
+    // 4 bits per block, the lamp is the first block and the sensor is the 17th
+    let mut longs = vec![0i64; 256];
+    longs[0] = 1;
+    longs[1] = 2;
+    let mut states = HashMap::new();
+    states.insert("palette".to_owned(), Value::List(palette));
+    states.insert("data".to_owned(), Value::LongArray(longs));
+    let mut section = HashMap::new();
+    section.insert("Y".to_owned(), Value::Byte(-1));
+    section.insert("block_states".to_owned(), Value::Compound(states));
+
+    let section = load_section(&section, false).unwrap().unwrap();
+    assert_eq!(section.y, -1);
+    let lamp = Block::RedstoneLamp { lit: true }.get_id();
+    assert_eq!(section.get(0, 0, 0), &Ok(lamp));
+    assert_eq!(section.get(1, 0, 0), &Ok(0));
+    assert_eq!(
+        section.get(0, 0, 1),
+        &Err("minecraft:sculk_sensor".to_owned())
+    );
+    assert_eq!(unpack_blocks(&[], 2, false), None);
+    // With 5 bits, the 13th block is split between the first two longs
+    let mut longs = vec![0i64; 320];
+    longs[0] = (0b0110 << 60) as i64;
+    longs[1] = 1;
+    let blocks = unpack_blocks(&longs, 32, true).unwrap();
+    assert_eq!(blocks[12], 0b10110);
+}
+
+#[test]
+fn exported_chunks_can_be_imported() {
+    let blocks: Vec<u16> = (0..4096).map(|i| (i * 7 % 19) as u16).collect();
+    let longs = pack_blocks(&blocks, 19);
+    assert_eq!(longs.len(), 4096 / 12);
+    assert_eq!(unpack_blocks(&longs, 19, false).unwrap(), blocks);
+
+    let mut region = RegionWriter {
+        chunks: vec![None; 1024],
+    };
+    let mut nbt = nbt::Blob::new();
+    nbt.insert("DataVersion", DATA_VERSION_1_18_2).unwrap();
+    region.set_chunk((-1, 2), &nbt).unwrap();
+    let path = std::env::temp_dir().join("mchprs_anvil_export_test.mca");
+    region.write(&path).unwrap();
+    let data = read_region(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(read_chunk(&data, 2 * 32 + 31).unwrap(), Some(nbt));
+    assert_eq!(read_chunk(&data, 0).unwrap(), None);
+}
@@ -0,0 +1,168 @@
+//! Online mode. Players that log in are sent the public key of the server, with which they
+//! encrypt a shared secret that the connection is encrypted with from then on. The client tells
+//! the session server that it joined a server with a hash of that secret, and the server asks
+//! the session server whether it did, which is only the case if the player owns their account.
+//!
+//! https://wiki.vg/Protocol_Encryption
+
+use crate::config::CONFIG;
+use mchprs_network::packets::clientbound::CEncryptionRequest;
+use mchprs_network::packets::serverbound::SEncryptionResponse;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
+use rustc_hash::FxHashMap;
+use sha1::{Digest, Sha1};
+use std::time::{Duration, Instant};
+
+/// How long a client has to answer an encryption request. Clients that disconnect during the
+/// login never answer, so their pending logins are removed after this.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct PendingLogin {
+    username: String,
+    verify_token: [u8; 4],
+    requested_at: Instant,
+}
+
+/// A login whose encryption response was verified, but which still has to be checked with the
+/// session server
+pub struct EncryptedLogin {
+    pub username: String,
+    pub shared_secret: [u8; 16],
+    pub server_hash: String,
+}
+
+pub struct Authenticator {
+    /// The key pair is generated once when the server starts and used for every login
+    private_key: RsaPrivateKey,
+    /// The public key encoded as DER, which is how it is sent to clients
+    public_key: Vec<u8>,
+    /// The players that were sent an encryption request, by the id of their connection
+    pending: FxHashMap<u32, PendingLogin>,
+}
+
+impl Authenticator {
+    pub fn new() -> Authenticator {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)
+            .expect("failed to generate the server key pair");
+        let public_key = private_key
+            .to_public_key()
+            .to_public_key_der()
+            .expect("failed to encode the server public key")
+            .as_bytes()
+            .to_vec();
+        Authenticator {
+            private_key,
+            public_key,
+            pending: FxHashMap::default(),
+        }
+    }
+
+    /// Whether `username` has to be authenticated, which isn't the case for the accounts in the
+    /// `online_mode_bypass` option
+    pub fn requires_auth(username: &str) -> bool {
+        !CONFIG
+            .online_mode_bypass
+            .split(',')
+            .any(|name| name.trim().eq_ignore_ascii_case(username))
+    }
+
+    pub fn request_encryption(&mut self, client_id: u32, username: String) -> CEncryptionRequest {
+        self.remove_expired();
+        let verify_token = rand::random();
+        self.pending.insert(
+            client_id,
+            PendingLogin {
+                username,
+                verify_token,
+                requested_at: Instant::now(),
+            },
+        );
+        CEncryptionRequest {
+            server_id: String::new(),
+            public_key: self.public_key.clone(),
+            verify_token: verify_token.to_vec(),
+        }
+    }
+
+    /// Decrypts the shared secret of an encryption response. The error is the reason the player
+    /// is disconnected with.
+    pub fn finish_encryption(
+        &mut self,
+        client_id: u32,
+        response: &SEncryptionResponse,
+    ) -> Result<EncryptedLogin, &'static str> {
+        self.remove_expired();
+        let pending = self
+            .pending
+            .remove(&client_id)
+            .ok_or("Unexpected encryption response")?;
+        let verify_token = self
+            .private_key
+            .decrypt(Pkcs1v15Encrypt, &response.verify_token)
+            .map_err(|_| "Invalid verify token")?;
+        if verify_token != pending.verify_token {
+            return Err("Invalid verify token");
+        }
+        let shared_secret: [u8; 16] = self
+            .private_key
+            .decrypt(Pkcs1v15Encrypt, &response.shared_secret)
+            .ok()
+            .and_then(|secret| secret.try_into().ok())
+            .ok_or("Invalid shared secret")?;
+        Ok(EncryptedLogin {
+            username: pending.username,
+            server_hash: server_hash(&shared_secret, &self.public_key),
+            shared_secret,
+        })
+    }
+
+    fn remove_expired(&mut self) {
+        self.pending
+            .retain(|_, pending| pending.requested_at.elapsed() < LOGIN_TIMEOUT);
+    }
+}
+
+/// The hash the client and the server send to the session server. The server id is always empty,
+/// so it is the SHA-1 hash of the shared secret and the public key, written as a signed hex
+/// number without leading zeros.
+fn server_hash(shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hash: [u8; 20] = Sha1::new()
+        .chain_update(shared_secret)
+        .chain_update(public_key)
+        .finalize()
+        .into();
+    let negative = hash[0] & 0x80 != 0;
+    if negative {
+        // Two's complement
+        let mut carry = true;
+        for byte in hash.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                (*byte, carry) = byte.overflowing_add(1);
+            }
+        }
+    }
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let hex = hex.trim_start_matches('0');
+    match negative {
+        true => format!("-{}", hex),
+        false => hex.to_owned(),
+    }
+}
+
+#[test]
+fn server_hashes_are_signed_hex() {
+    assert_eq!(
+        server_hash(b"Notch", b""),
+        "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+    );
+    assert_eq!(
+        server_hash(b"jeb_", b""),
+        "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+    );
+    assert_eq!(
+        server_hash(b"simon", b""),
+        "88e16a1019277b15d58faf0541e11910eb756f6"
+    );
+}
@@ -0,0 +1,135 @@
+//! Headless benchmarks of the redpiler backends, used by the `mchprs bench` subcommand. A
+//! schematic or plot save file is compiled and ticked without starting the server, and the
+//! results are reported as json so that they can be compared by scripts.
+
+use crate::convert;
+use crate::redpiler::{Compiler, CompilerOptions};
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::BlockPos;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Instant;
+
+pub struct BenchOptions {
+    pub ticks: u64,
+    /// Blocks that are used before ticking, like the button that starts a circuit
+    pub use_blocks: Vec<BlockPos>,
+    pub compiler: CompilerOptions,
+}
+
+impl Default for BenchOptions {
+    fn default() -> BenchOptions {
+        BenchOptions {
+            ticks: 10000,
+            use_blocks: Vec::new(),
+            compiler: Default::default(),
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Parses `--ticks=[ticks]` and `--use=[x],[y],[z]`, every other argument is passed on as a
+    /// compile flag
+    pub fn parse(args: &[String]) -> Result<BenchOptions> {
+        let mut options = BenchOptions::default();
+        let mut compile_flags = Vec::new();
+        for arg in args {
+            if let Some(ticks) = arg.strip_prefix("--ticks=") {
+                options.ticks = ticks
+                    .parse()
+                    .with_context(|| format!("invalid number of ticks: {}", ticks))?;
+            } else if let Some(pos) = arg.strip_prefix("--use=") {
+                options.use_blocks.push(parse_pos(pos)?);
+            } else {
+                compile_flags.push(arg.as_str());
+            }
+        }
+        options.compiler = CompilerOptions::parse(&compile_flags.join(" "));
+        Ok(options)
+    }
+}
+
+fn parse_pos(str: &str) -> Result<BlockPos> {
+    let coords: Vec<i32> = str
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid position: {}", str))?;
+    let [x, y, z] = coords[..] else {
+        bail!("invalid position: {}", str);
+    };
+    Ok(BlockPos::new(x, y, z))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub backend: &'static str,
+    pub compile_ms: f64,
+    pub ticks: u64,
+    pub tick_ms: f64,
+    pub ticks_per_second: f64,
+    /// The most memory the process used at once, only known on Linux
+    pub peak_memory_kib: Option<u64>,
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Compiles the circuit in the file at `path` and runs it for the given number of ticks
+pub fn run(path: &Path, options: BenchOptions) -> Result<BenchReport> {
+    let mut world = convert::load_plot(path)?;
+    let bounds = world.get_corners();
+    let ticks = std::mem::take(&mut world.to_be_ticked);
+
+    let mut compiler = Compiler::default();
+    let start = Instant::now();
+    compiler.compile(&mut world, bounds, options.compiler, ticks)?;
+    let compile_time = start.elapsed();
+    let backend = compiler.backend_name().unwrap_or("none");
+
+    for &pos in &options.use_blocks {
+        compiler.on_use_block(pos);
+    }
+    let start = Instant::now();
+    for _ in 0..options.ticks {
+        compiler.tick();
+    }
+    let tick_time = start.elapsed();
+
+    Ok(BenchReport {
+        backend,
+        compile_ms: compile_time.as_secs_f64() * 1000.0,
+        ticks: options.ticks,
+        tick_ms: tick_time.as_secs_f64() * 1000.0,
+        ticks_per_second: options.ticks as f64 / tick_time.as_secs_f64(),
+        peak_memory_kib: peak_memory_kib(),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn peak_memory_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_memory_kib() -> Option<u64> {
+    None
+}
+
+#[test]
+fn bench_options_are_parsed() {
+    let args: Vec<String> = ["--ticks=500", "--use=1,2,3", "-O", "--backend=direct"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let options = BenchOptions::parse(&args).unwrap();
+    assert_eq!(options.ticks, 500);
+    assert_eq!(options.use_blocks, vec![BlockPos::new(1, 2, 3)]);
+    assert!(options.compiler.optimize);
+    assert!(BenchOptions::parse(&["--use=1,2".to_owned()]).is_err());
+}
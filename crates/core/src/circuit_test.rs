@@ -0,0 +1,26 @@
+//! Headless runs of redpiler test scripts, used by the `mchprs test` subcommand. A schematic or
+//! plot save file is compiled without starting the server and the script is run against it, so
+//! that circuits can be tested by CI.
+
+use crate::convert;
+use crate::redpiler::script::{self, AssertionResult, TestScript};
+use crate::redpiler::{Compiler, CompilerOptions};
+use anyhow::Result;
+use std::path::Path;
+
+/// Compiles the circuit in the file at `path` with `options` and runs the test script at
+/// `script_path` against it
+pub fn run(
+    path: &Path,
+    script_path: &Path,
+    options: CompilerOptions,
+) -> Result<Vec<AssertionResult>> {
+    let script = TestScript::load(script_path)?;
+    let mut world = convert::load_plot(path)?;
+    let bounds = world.get_corners();
+    let ticks = std::mem::take(&mut world.to_be_ticked);
+
+    let mut compiler = Compiler::default();
+    compiler.compile(&mut world, bounds, options, ticks)?;
+    script::run(&mut compiler, &script)
+}
@@ -1,6 +1,8 @@
 use crate::permissions::PermissionsConfig;
+use crate::plot::template::PlotTemplate;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use toml_edit::{value, Document};
@@ -68,11 +70,31 @@ gen_config! {
     motd: String = "Minecraft High Performance Redstone Server".to_string(),
     chat_format: String = "<{username}> {message}".to_string(),
     max_players: i64 = 99999,
+    plot_scale: i64 = 4,
+    world_height: i64 = 256,
     view_distance: i64 = 8,
     bungeecord: bool = false,
+    velocity_secret: String = "".to_string(),
+    online_mode: bool = false,
+    online_mode_bypass: String = "".to_string(),
     whitelist: bool = false,
     schemati: bool = false,
     luckperms: Option<PermissionsConfig> = None,
     block_in_hitbox: bool = true,
-    auto_redpiler: bool = true
+    auto_redpiler: bool = true,
+    plot_templates: Option<HashMap<String, PlotTemplate>> = None,
+    note_block_sounds: Option<HashMap<String, String>> = None,
+    max_note_sounds: i64 = 64,
+    max_block_updates: i64 = 1000000,
+    max_loaded_sections: i64 = 0,
+    section_cache_size: i64 = 0,
+    target_pulse_ticks: i64 = 10,
+    redpiler_cache: bool = true,
+    backup_interval: i64 = 0,
+    backup_count: i64 = 24,
+    edit_history: bool = true,
+    worldedit_undo_memory: i64 = 64,
+    remote_api_address: String = "".to_string(),
+    remote_api_token: String = "".to_string(),
+    metrics_address: String = "".to_string()
 }
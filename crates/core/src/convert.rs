@@ -0,0 +1,186 @@
+//! Offline conversion between the schematic formats supported by the server and plot save
+//! files, used by the `mchprs convert` subcommand.
+
+use crate::plot::template::PlotTemplate;
+use crate::plot::worldedit::litematic::load_litematic_file;
+use crate::plot::worldedit::schematic::{
+    load_schematic_file, save_schematic_file, SchematicVersion,
+};
+use crate::plot::worldedit::{create_clipboard, paste_clipboard, WorldEditClipboard};
+use crate::plot::{PlotWorld, PLOT_SIZE};
+use crate::redstone::daylight_detector::NOON;
+use crate::world::storage::Chunk;
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::BlockPos;
+use mchprs_save_data::plot_data::{PlotData, Tps};
+use std::path::Path;
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConvertFormat {
+    /// A Sponge schematic (`.schem`). When reading, both versions are accepted.
+    Sponge(SchematicVersion),
+    /// A Litematica schematic (`.litematic`) with all of its regions, which can only be read
+    Litematic,
+    /// A plot save file as found in `./world/plots`
+    PlotSnapshot,
+}
+
+impl ConvertFormat {
+    /// Guess the format of a file from its extension. Plot save files don't have one.
+    pub fn from_path(path: &Path) -> Result<ConvertFormat> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("schem") => Ok(ConvertFormat::Sponge(SchematicVersion::V2)),
+            Some("litematic") => Ok(ConvertFormat::Litematic),
+            None => Ok(ConvertFormat::PlotSnapshot),
+            Some(ext) => bail!("unsupported file extension: .{}", ext),
+        }
+    }
+}
+
+pub(crate) fn empty_world() -> PlotWorld {
+    let template = PlotTemplate::default();
+    let chunks = template
+        .generate_plot(*PLOT_SIZE, 0, 0)
+        .expect("default plot template should be valid");
+    PlotWorld {
+        x: 0,
+        z: 0,
+        size: *PLOT_SIZE,
+        chunks,
+        to_be_ticked: Vec::new(),
+        packet_senders: Vec::new(),
+        note_events: Vec::new(),
+        update_queue: Default::default(),
+        daytime: NOON,
+        locational_updates: false,
+    }
+}
+
+fn load_plot_world(path: &Path) -> Result<PlotWorld> {
+    let size = *PLOT_SIZE;
+    let data = PlotData::load_from_file(path, size.sections)
+        .with_context(|| format!("error loading plot save file at {}", path.display()))?;
+    let chunks: Vec<Chunk> = data
+        .chunk_data
+        .into_iter()
+        .enumerate()
+        .map(|(i, c)| {
+            Chunk::load(
+                i as i32 / size.width(),
+                i as i32 % size.width(),
+                c,
+                size.sections,
+            )
+        })
+        .collect();
+    if chunks.len() != size.num_chunks() {
+        bail!("the plot save file has the wrong number of chunks for this plot size");
+    }
+    Ok(PlotWorld {
+        x: 0,
+        z: 0,
+        size,
+        chunks,
+        to_be_ticked: data.pending_ticks,
+        packet_senders: Vec::new(),
+        note_events: Vec::new(),
+        update_queue: Default::default(),
+        daytime: NOON,
+        locational_updates: false,
+    })
+}
+
+fn save_plot_world(path: &Path, mut world: PlotWorld) -> Result<()> {
+    let data = PlotData {
+        tps: Tps::Limited(10),
+        chunk_data: world
+            .chunks
+            .iter_mut()
+            .map(|c| c.save())
+            .collect::<Result<_, _>>()?,
+        pending_ticks: world.to_be_ticked,
+    };
+    data.save_to_file(path)
+        .with_context(|| format!("error saving plot save file at {}", path.display()))?;
+    Ok(())
+}
+
+fn load(path: &Path, format: ConvertFormat) -> Result<WorldEditClipboard> {
+    Ok(match format {
+        ConvertFormat::Sponge(_) => load_schematic_file(path)
+            .with_context(|| format!("error loading schematic at {}", path.display()))?,
+        ConvertFormat::Litematic => load_litematic_file(path)
+            .with_context(|| format!("error loading litematic at {}", path.display()))?,
+        ConvertFormat::PlotSnapshot => {
+            let mut world = load_plot_world(path)?;
+            let (first_pos, second_pos) = world.get_corners();
+            create_clipboard(&mut world, first_pos, first_pos, second_pos)
+        }
+    })
+}
+
+fn save(path: &Path, format: ConvertFormat, clipboard: &WorldEditClipboard) -> Result<()> {
+    match format {
+        ConvertFormat::Sponge(version) => save_schematic_file(path, clipboard, version)
+            .with_context(|| format!("error saving schematic at {}", path.display())),
+        ConvertFormat::Litematic => bail!("litematics can only be converted from, not to"),
+        ConvertFormat::PlotSnapshot => save_plot_world(path, paste_into_plot(clipboard)?),
+    }
+}
+
+/// Pastes `clipboard` into the middle of an empty plot
+fn paste_into_plot(clipboard: &WorldEditClipboard) -> Result<PlotWorld> {
+    let (size_x, size_y, size_z) = (
+        clipboard.size_x as i32,
+        clipboard.size_y as i32,
+        clipboard.size_z as i32,
+    );
+    // Leave room for the plot border and floor
+    let floor_height = PlotTemplate::default().floor_height as i32;
+    let (width, height) = (PLOT_SIZE.block_width(), PLOT_SIZE.block_height());
+    if size_x > width - 2 || size_z > width - 2 || size_y > height - floor_height {
+        bail!(
+            "a {}x{}x{} schematic does not fit inside of a plot",
+            size_x,
+            size_y,
+            size_z
+        );
+    }
+    let mut world = empty_world();
+    // Center the schematic on top of the plot floor
+    let min_corner = BlockPos::new((width - size_x) / 2, floor_height, (width - size_z) / 2);
+    let origin =
+        min_corner + BlockPos::new(clipboard.offset_x, clipboard.offset_y, clipboard.offset_z);
+    paste_clipboard(&mut world, clipboard, origin, false);
+    Ok(world)
+}
+
+/// Loads a schematic or plot save file as a plot, with schematics placed like `mchprs convert`
+/// places them
+pub(crate) fn load_plot(path: &Path) -> Result<PlotWorld> {
+    match ConvertFormat::from_path(path)? {
+        ConvertFormat::PlotSnapshot => load_plot_world(path),
+        format => paste_into_plot(&load(path, format)?),
+    }
+}
+
+/// Convert the file at `input` into the format of `output`, with formats deduced from the
+/// file extensions. `output_format` can be used to override the output format.
+pub fn convert(input: &Path, output: &Path, output_format: Option<ConvertFormat>) -> Result<()> {
+    let input_format = ConvertFormat::from_path(input)?;
+    let output_format = match output_format {
+        Some(format) => format,
+        None => ConvertFormat::from_path(output)?,
+    };
+    let clipboard = load(input, input_format)?;
+    save(output, output_format, &clipboard)?;
+    info!(
+        "Converted {} ({:?}) to {} ({:?})",
+        input.display(),
+        input_format,
+        output.display(),
+        output_format
+    );
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Experimental features are disabled by default, so that unstable code paths can ship without
+//! affecting anyone who didn't ask for them. They can be enabled for the whole server or for a
+//! single plot with `/features`, and are stored in the plot database.
+
+use crate::plot::database;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Patching the running circuit when blocks change, with `--incremental`
+    IncrementalRedpiler,
+    /// Delaying dust with a `[latency]` sign, with `--wire-delay`
+    WireDelay,
+    /// Updating the neighbors of dust in the order vanilla does, which depends on its location
+    LocationalUpdates,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [
+        Feature::IncrementalRedpiler,
+        Feature::WireDelay,
+        Feature::LocationalUpdates,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::IncrementalRedpiler => "incremental-redpiler",
+            Feature::WireDelay => "wire-delay",
+            Feature::LocationalUpdates => "locational-updates",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Feature::IncrementalRedpiler => "`/redpiler compile --incremental`",
+            Feature::WireDelay => "`/redpiler compile --wire-delay`",
+            Feature::LocationalUpdates => "vanilla's locational update order for dust",
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Feature {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.name() == s)
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureScope {
+    Server,
+    Plot(i32, i32),
+}
+
+/// Where a feature is enabled for a plot, if it is enabled at all
+pub fn enabled_scope(feature: Feature, plot_x: i32, plot_z: i32) -> Option<FeatureScope> {
+    if database::is_feature_enabled(feature.name(), None) {
+        Some(FeatureScope::Server)
+    } else if database::is_feature_enabled(feature.name(), Some((plot_x, plot_z))) {
+        Some(FeatureScope::Plot(plot_x, plot_z))
+    } else {
+        None
+    }
+}
+
+pub fn is_enabled(feature: Feature, plot_x: i32, plot_z: i32) -> bool {
+    enabled_scope(feature, plot_x, plot_z).is_some()
+}
+
+pub fn set_enabled(feature: Feature, scope: FeatureScope, enabled: bool) {
+    let plot = match scope {
+        FeatureScope::Server => None,
+        FeatureScope::Plot(x, z) => Some((x, z)),
+    };
+    database::set_feature_enabled(feature.name(), plot, enabled);
+}
@@ -1,13 +1,13 @@
 use crate::config::CONFIG;
 use crate::player::Player;
 use crate::plot::PlotWorld;
-use crate::plot::PLOT_BLOCK_HEIGHT;
 use crate::redstone;
+use crate::redstone::updates::BlockUpdate;
 use crate::world::World;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::*;
 use mchprs_blocks::items::{Item, ItemStack};
-use mchprs_blocks::{BlockFace, BlockPos, SignType};
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos, SignType};
 use mchprs_network::packets::clientbound::{COpenSignEditor, ClientBoundPacket};
 use mchprs_world::TickPriority;
 
@@ -25,60 +25,55 @@ pub fn on_use(
             if repeater.delay > 4 {
                 repeater.delay -= 4;
             }
-            world.set_block(pos, Block::RedstoneRepeater { repeater });
+            redstone::set_block(world, pos, Block::RedstoneRepeater { repeater });
             ActionResult::Success
         }
         Block::RedstoneComparator { comparator } => {
             let mut comparator = comparator;
             comparator.mode = comparator.mode.toggle();
             redstone::comparator::tick(comparator, world, pos);
-            world.set_block(pos, Block::RedstoneComparator { comparator });
+            redstone::set_block(world, pos, Block::RedstoneComparator { comparator });
             ActionResult::Success
         }
-        Block::Lever { mut lever } => {
-            lever.powered = !lever.powered;
-            world.set_block(pos, Block::Lever { lever });
-            redstone::update_surrounding_blocks(world, pos);
-            match lever.face {
-                LeverFace::Ceiling => {
-                    redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Top));
-                }
-                LeverFace::Floor => {
-                    redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Bottom));
-                }
-                LeverFace::Wall => redstone::update_surrounding_blocks(
-                    world,
-                    pos.offset(lever.facing.opposite().block_face()),
-                ),
-            }
+        Block::Lever { lever } => {
+            flip_lever(world, pos, lever);
             ActionResult::Success
         }
-        Block::StoneButton { mut button } => {
-            if !button.powered {
-                button.powered = true;
-                world.set_block(pos, Block::StoneButton { button });
-                world.schedule_tick(pos, 10, TickPriority::Normal);
-                redstone::update_surrounding_blocks(world, pos);
-                match button.face {
-                    ButtonFace::Ceiling => {
-                        redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Top));
-                    }
-                    ButtonFace::Floor => {
-                        redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Bottom));
-                    }
-                    ButtonFace::Wall => redstone::update_surrounding_blocks(
-                        world,
-                        pos.offset(button.facing.opposite().block_face()),
-                    ),
-                }
-            }
+        Block::StoneButton { button } => {
+            press_button(world, pos, button);
+            ActionResult::Success
+        }
+        Block::NoteBlock {
+            instrument,
+            note,
+            powered,
+        } => {
+            let note = (note + 1) % 25;
+            redstone::set_block(
+                world,
+                pos,
+                Block::NoteBlock {
+                    instrument,
+                    note,
+                    powered,
+                },
+            );
+            redstone::play_note_block(world, pos, instrument, note);
             ActionResult::Success
         }
         Block::RedstoneWire { wire } => redstone::wire::on_use(wire, world, pos),
+        Block::DaylightDetector { inverted, .. } => {
+            let inverted = !inverted;
+            let power = redstone::daylight_detector::power(world.daytime(), inverted);
+            redstone::set_block(world, pos, Block::DaylightDetector { inverted, power });
+            redstone::update_surrounding_blocks(world, pos);
+            ActionResult::Success
+        }
         Block::SeaPickle { pickles } => {
             if let Some(Item::SeaPickle {}) = item_in_hand {
                 if pickles < 4 {
-                    world.set_block(
+                    redstone::set_block(
+                        world,
                         pos,
                         Block::SeaPickle {
                             pickles: pickles + 1,
@@ -100,6 +95,46 @@ pub fn on_use(
     }
 }
 
+pub fn flip_lever(world: &mut impl World, pos: BlockPos, mut lever: Lever) {
+    lever.powered = !lever.powered;
+    redstone::set_block(world, pos, Block::Lever { lever });
+    redstone::update_surrounding_blocks(world, pos);
+    match lever.face {
+        LeverFace::Ceiling => {
+            redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Top));
+        }
+        LeverFace::Floor => {
+            redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Bottom));
+        }
+        LeverFace::Wall => redstone::update_surrounding_blocks(
+            world,
+            pos.offset(lever.facing.opposite().block_face()),
+        ),
+    }
+}
+
+pub fn press_button(world: &mut impl World, pos: BlockPos, mut button: StoneButton) {
+    if button.powered {
+        return;
+    }
+    button.powered = true;
+    redstone::set_block(world, pos, Block::StoneButton { button });
+    world.schedule_tick(pos, 10, TickPriority::Normal);
+    redstone::update_surrounding_blocks(world, pos);
+    match button.face {
+        ButtonFace::Ceiling => {
+            redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Top));
+        }
+        ButtonFace::Floor => {
+            redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Bottom));
+        }
+        ButtonFace::Wall => redstone::update_surrounding_blocks(
+            world,
+            pos.offset(button.facing.opposite().block_face()),
+        ),
+    }
+}
+
 pub fn get_state_for_placement(
     world: &impl World,
     pos: BlockPos,
@@ -161,7 +196,22 @@ pub fn get_state_for_placement(
             lit: redstone::redstone_lamp_should_be_lit(world, pos),
         },
         Item::RedstoneBlock {} => Block::RedstoneBlock {},
-        Item::Hopper {} => Block::Hopper {},
+        Item::NoteBlock {} => Block::NoteBlock {
+            instrument: world.get_block(pos.offset(BlockFace::Bottom)).instrument(),
+            note: 0,
+            powered: redstone::redstone_lamp_should_be_lit(world, pos),
+        },
+        Item::Hopper {} => Block::Hopper {
+            // Hoppers face away from the clicked block, but never up
+            enabled: true,
+            facing: match context.block_face {
+                BlockFace::Top | BlockFace::Bottom => BlockFacing::Down,
+                BlockFace::North => BlockFacing::South,
+                BlockFace::South => BlockFacing::North,
+                BlockFace::West => BlockFacing::East,
+                BlockFace::East => BlockFacing::West,
+            },
+        },
         Item::Terracotta {} => Block::Terracotta {},
         Item::ColoredTerracotta { color } => Block::ColoredTerracotta { color },
         Item::Concrete { color } => Block::Concrete { color },
@@ -179,11 +229,25 @@ pub fn get_state_for_placement(
                 false,
             ),
         },
+        Item::Piston {} => Block::Piston {
+            extended: false,
+            facing: context.player.get_facing().opposite(),
+        },
+        Item::StickyPiston {} => Block::StickyPiston {
+            extended: false,
+            facing: context.player.get_facing().opposite(),
+        },
+        Item::Observer {} => Block::Observer {
+            facing: context.player.get_facing(),
+            powered: false,
+        },
         Item::Sign { sign_type } => match context.block_face {
             BlockFace::Bottom => Block::Air {},
             BlockFace::Top => Block::Sign {
                 sign_type: SignType(sign_type),
-                rotation: (((180.0 + context.player.yaw) * 16.0 / 360.0) + 0.5).floor() as u32 & 15,
+                rotation: SignRotation(
+                    (((180.0 + context.player.yaw) * 16.0 / 360.0) + 0.5).floor() as u32 & 15,
+                ),
             },
             _ => Block::WallSign {
                 sign_type: SignType(sign_type),
@@ -194,7 +258,11 @@ pub fn get_state_for_placement(
             wire: redstone::wire::get_state_for_placement(world, pos),
         },
         Item::Barrel {} => Block::Barrel {},
-        Item::Target {} => Block::Target {},
+        Item::Target {} => Block::Target { power: 0 },
+        Item::DaylightDetector {} => Block::DaylightDetector {
+            inverted: false,
+            power: redstone::daylight_detector::power(world.daytime(), false),
+        },
         Item::StainedGlass { color } => Block::StainedGlass { color },
         Item::SmoothStoneSlab {} => Block::SmoothStoneSlab {},
         Item::QuartzSlab {} => Block::QuartzSlab {},
@@ -243,13 +311,17 @@ pub fn place_in_world(
             }
         };
     }
-    world.set_block(pos, block);
+    redstone::set_block(world, pos, block);
     change_surrounding_blocks(world, pos);
     if let Block::RedstoneWire { .. } = block {
         redstone::update_wire_neighbors(world, pos);
     } else {
         redstone::update_surrounding_blocks(world, pos);
     }
+    if let Block::Piston { .. } | Block::StickyPiston { .. } = block {
+        // Pistons that are placed next to a power source extend right away
+        redstone::update(block, world, pos);
+    }
 }
 
 pub fn destroy(block: Block, world: &mut impl World, pos: BlockPos) {
@@ -259,12 +331,12 @@ pub fn destroy(block: Block, world: &mut impl World, pos: BlockPos) {
 
     match block {
         Block::RedstoneWire { .. } => {
-            world.set_block(pos, Block::Air {});
+            redstone::set_block(world, pos, Block::Air {});
             change_surrounding_blocks(world, pos);
             redstone::update_wire_neighbors(world, pos);
         }
         Block::Lever { lever } => {
-            world.set_block(pos, Block::Air {});
+            redstone::set_block(world, pos, Block::Air {});
             // This is a horrible idea, don't do this.
             // One day this will be fixed, but for now... too bad!
             match lever.face {
@@ -288,8 +360,25 @@ pub fn destroy(block: Block, world: &mut impl World, pos: BlockPos) {
                 }
             }
         }
+        Block::Piston {
+            extended: true,
+            facing,
+        }
+        | Block::StickyPiston {
+            extended: true,
+            facing,
+        } => {
+            redstone::set_block(world, pos, Block::Air {});
+            let head_pos = facing.offset_pos(pos, 1);
+            if matches!(world.get_block(head_pos), Block::PistonHead { .. }) {
+                redstone::set_block(world, head_pos, Block::Air {});
+                redstone::update_surrounding_blocks(world, head_pos);
+            }
+            change_surrounding_blocks(world, pos);
+            redstone::update_surrounding_blocks(world, pos);
+        }
         _ => {
-            world.set_block(pos, Block::Air {});
+            redstone::set_block(world, pos, Block::Air {});
             change_surrounding_blocks(world, pos);
             redstone::update_surrounding_blocks(world, pos);
         }
@@ -359,28 +448,40 @@ pub fn change(block: Block, world: &mut impl World, pos: BlockPos, direction: Bl
     }
     if let Block::RedstoneWire { wire } = block {
         let new_state = redstone::wire::on_neighbor_changed(wire, world, pos, direction);
-        if world.set_block(pos, Block::RedstoneWire { wire: new_state }) {
+        if redstone::set_block(world, pos, Block::RedstoneWire { wire: new_state }) {
             redstone::update_wire_neighbors(world, pos);
         }
     }
+    if let Block::NoteBlock { note, powered, .. } = block {
+        if direction == BlockFace::Top {
+            // The block below changed, which decides the instrument
+            let instrument = world.get_block(pos.offset(BlockFace::Bottom)).instrument();
+            redstone::set_block(
+                world,
+                pos,
+                Block::NoteBlock {
+                    instrument,
+                    note,
+                    powered,
+                },
+            );
+        }
+    }
 }
 
 fn change_surrounding_blocks(world: &mut impl World, pos: BlockPos) {
+    let mut updates = Vec::with_capacity(18);
     for direction in &BlockFace::values() {
         let neighbor_pos = pos.offset(*direction);
-        let block = world.get_block(neighbor_pos);
-        change(block, world, neighbor_pos, *direction);
+        updates.push(BlockUpdate::Change(neighbor_pos, *direction));
 
         // Also change diagonal blocks
-
         let up_pos = neighbor_pos.offset(BlockFace::Top);
-        let up_block = world.get_block(up_pos);
-        change(up_block, world, up_pos, *direction);
-
+        updates.push(BlockUpdate::Change(up_pos, *direction));
         let down_pos = neighbor_pos.offset(BlockFace::Bottom);
-        let down_block = world.get_block(down_pos);
-        change(down_block, world, down_pos, *direction);
+        updates.push(BlockUpdate::Change(down_pos, *direction));
     }
+    redstone::queue_updates(world, updates);
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -432,7 +533,7 @@ pub fn use_item_on_block(
         return false;
     }
 
-    if can_place && (0..PLOT_BLOCK_HEIGHT).contains(&block_pos.y) {
+    if can_place && (0..world.size.block_height()).contains(&block_pos.y) {
         let block = get_state_for_placement(world, block_pos, item.item_type, &ctx);
 
         match block {
@@ -454,3 +555,63 @@ pub fn use_item_on_block(
         true
     }
 }
+
+#[test]
+fn note_blocks_play_the_instrument_of_the_block_below() {
+    use crate::plot::{compile_test_world, redpiler_test_world};
+    use crate::redpiler::CompilerOptions;
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(9, 9, 10);
+    let note_pos = BlockPos::new(10, 9, 10);
+    let support_pos = BlockPos::new(10, 8, 10);
+    world.set_block(
+        lever_pos,
+        Block::Lever {
+            lever: Lever::new(LeverFace::Floor, BlockDirection::North, false),
+        },
+    );
+    world.set_block(
+        note_pos,
+        Block::NoteBlock {
+            instrument: Instrument::Harp,
+            note: 3,
+            powered: false,
+        },
+    );
+    let instrument = |world: &PlotWorld| match world.get_block(note_pos) {
+        Block::NoteBlock { instrument, .. } => instrument,
+        block => panic!("expected a note block, found {:?}", block),
+    };
+
+    place_in_world(Block::Glowstone {}, &mut world, support_pos, &None);
+    assert_eq!(instrument(&world), Instrument::Pling);
+    destroy(Block::Glowstone {}, &mut world, support_pos);
+    assert_eq!(instrument(&world), Instrument::Harp);
+    world.flush_block_changes();
+
+    // The instrument is patched into a running circuit as well
+    let options = CompilerOptions {
+        incremental: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+    assert!(redpiler.suspend(&mut world));
+    // Gold block
+    place_in_world(Block::from_id(1483), &mut world, support_pos, &None);
+    let changed = world.changed_positions();
+    redpiler
+        .recompile(&mut world, &changed, Vec::new())
+        .unwrap();
+
+    redpiler.on_use_block(lever_pos);
+    redpiler.tick();
+    redpiler.flush(&mut world);
+    let events: Vec<_> = world
+        .note_events
+        .iter()
+        .map(|event| (event.pos, event.instrument, event.note))
+        .collect();
+    assert_eq!(events, [(note_pos, Instrument::Bell, 3)]);
+}
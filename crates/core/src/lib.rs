@@ -2,16 +2,27 @@
 
 #[macro_use]
 mod utils;
+pub mod anvil;
+mod auth;
+pub mod bench;
 mod chat;
+pub mod circuit_test;
 mod config;
+pub mod convert;
+mod features;
 mod interaction;
+mod metrics;
+pub mod parity;
 mod permissions;
 mod player;
 pub mod plot;
 mod profile;
 pub mod redpiler;
 pub mod redstone;
+mod remote;
 pub mod server;
+mod velocity;
+mod websocket;
 pub mod world;
 
 #[macro_use]
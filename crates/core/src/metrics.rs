@@ -0,0 +1,248 @@
+//! Metrics of the running plots in the Prometheus text format, served over HTTP at
+//! `/metrics` on `metrics_address` so that they can be scraped and graphed. Plots publish their
+//! metrics every second while they are running and remove them when they unload.
+
+use crate::config::CONFIG;
+use mchprs_network::{BYTES_RECEIVED, BYTES_SENT};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// How often plots publish their metrics
+pub const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
+pub struct PlotMetrics {
+    /// The ticks per second over the last 10 seconds
+    pub tps: f32,
+    /// `None` if the plot runs as fast as it can
+    pub target_tps: Option<f32>,
+    pub players: usize,
+    /// The backend that is running the circuit, if redpiler is active
+    pub backend: Option<&'static str>,
+    /// The size of the graph after the last compile
+    pub nodes: usize,
+    pub edges: usize,
+    /// How long the last compile took
+    pub compile_time: Option<Duration>,
+}
+
+static PLOTS: Lazy<Mutex<BTreeMap<(i32, i32), PlotMetrics>>> = Lazy::new(Default::default);
+
+pub fn is_enabled() -> bool {
+    !CONFIG.metrics_address.is_empty()
+}
+
+pub fn publish(plot_x: i32, plot_z: i32, metrics: PlotMetrics) {
+    PLOTS.lock().unwrap().insert((plot_x, plot_z), metrics);
+}
+
+pub fn remove(plot_x: i32, plot_z: i32) {
+    PLOTS.lock().unwrap().remove(&(plot_x, plot_z));
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, ty: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, ty);
+}
+
+/// Writes a gauge with a sample for every plot that has a value for it
+fn write_plot_gauge(
+    out: &mut String,
+    plots: &BTreeMap<(i32, i32), PlotMetrics>,
+    name: &str,
+    help: &str,
+    value: impl Fn(&PlotMetrics) -> Option<f64>,
+) {
+    write_header(out, name, help, "gauge");
+    for ((x, z), metrics) in plots {
+        let value = match value(metrics) {
+            Some(value) if value.is_infinite() => "+Inf".to_owned(),
+            Some(value) => value.to_string(),
+            None => continue,
+        };
+        let _ = writeln!(
+            out,
+            "{}{{plot_x=\"{}\",plot_z=\"{}\"}} {}",
+            name, x, z, value
+        );
+    }
+}
+
+/// Renders the metrics of `plots` in the Prometheus text format
+fn render(plots: &BTreeMap<(i32, i32), PlotMetrics>) -> String {
+    let mut out = String::new();
+    write_header(
+        &mut out,
+        "mchprs_plots_loaded",
+        "Number of plots that are running",
+        "gauge",
+    );
+    let _ = writeln!(out, "mchprs_plots_loaded {}", plots.len());
+
+    write_plot_gauge(
+        &mut out,
+        plots,
+        "mchprs_plot_tps",
+        "Redstone ticks per second over the last 10 seconds",
+        |m| Some(m.tps as f64),
+    );
+    write_plot_gauge(
+        &mut out,
+        plots,
+        "mchprs_plot_target_tps",
+        "Redstone ticks per second the plot is set to",
+        |m| Some(m.target_tps.map_or(f64::INFINITY, |tps| tps as f64)),
+    );
+    write_plot_gauge(
+        &mut out,
+        plots,
+        "mchprs_plot_players",
+        "Number of players in the plot",
+        |m| Some(m.players as f64),
+    );
+    write_plot_gauge(
+        &mut out,
+        plots,
+        "mchprs_plot_redpiler_nodes",
+        "Number of nodes in the graph of the last compile",
+        |m| Some(m.nodes as f64),
+    );
+    write_plot_gauge(
+        &mut out,
+        plots,
+        "mchprs_plot_redpiler_edges",
+        "Number of links in the graph of the last compile",
+        |m| Some(m.edges as f64),
+    );
+    write_plot_gauge(
+        &mut out,
+        plots,
+        "mchprs_plot_redpiler_compile_seconds",
+        "How long the last compile took",
+        |m| m.compile_time.map(|time| time.as_secs_f64()),
+    );
+
+    let name = "mchprs_plot_redpiler_backend";
+    write_header(
+        &mut out,
+        name,
+        "The backend running the compiled circuit, only present while redpiler is active",
+        "gauge",
+    );
+    for ((x, z), metrics) in plots {
+        if let Some(backend) = metrics.backend {
+            let _ = writeln!(
+                out,
+                "{}{{plot_x=\"{}\",plot_z=\"{}\",backend=\"{}\"}} 1",
+                name, x, z, backend
+            );
+        }
+    }
+
+    for (name, help, counter) in [
+        (
+            "mchprs_network_sent_bytes_total",
+            "Bytes sent to all players",
+            &BYTES_SENT,
+        ),
+        (
+            "mchprs_network_received_bytes_total",
+            "Bytes received from all players",
+            &BYTES_RECEIVED,
+        ),
+    ] {
+        write_header(&mut out, name, help, "counter");
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+    out
+}
+
+fn handle_request(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // Only the request line is needed, the rest of the request is ignored
+    let mut buf = [0; 1024];
+    let len = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(&PLOTS.lock().unwrap())),
+        _ => ("404 Not Found", "Not Found\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Starts serving the metrics if `metrics_address` is set
+pub fn start() {
+    if !is_enabled() {
+        return;
+    }
+    let address = CONFIG.metrics_address.clone();
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(
+                "Failed to bind the metrics endpoint to {}: {}",
+                address, err
+            );
+            return;
+        }
+    };
+    info!("Serving metrics on http://{}/metrics", address);
+    thread::Builder::new()
+        .name("Metrics".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(handle_request);
+                if let Err(err) = result {
+                    warn!("Failed to answer a metrics request: {}", err);
+                }
+            }
+        })
+        .unwrap();
+}
+
+#[test]
+fn metrics_are_labelled_by_plot() {
+    let mut plots = BTreeMap::new();
+    plots.insert(
+        (0, -1),
+        PlotMetrics {
+            tps: 9.5,
+            target_tps: Some(10.0),
+            players: 2,
+            backend: Some("direct"),
+            nodes: 120,
+            edges: 300,
+            compile_time: Some(Duration::from_millis(250)),
+        },
+    );
+    plots.insert((3, 4), PlotMetrics::default());
+    let text = render(&plots);
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines.contains(&"mchprs_plots_loaded 2"));
+    assert!(lines.contains(&"mchprs_plot_tps{plot_x=\"0\",plot_z=\"-1\"} 9.5"));
+    assert!(lines.contains(&"mchprs_plot_target_tps{plot_x=\"3\",plot_z=\"4\"} +Inf"));
+    assert!(lines.contains(
+        &"mchprs_plot_redpiler_backend{plot_x=\"0\",plot_z=\"-1\",backend=\"direct\"} 1"
+    ));
+    assert!(
+        lines.contains(&"mchprs_plot_redpiler_compile_seconds{plot_x=\"0\",plot_z=\"-1\"} 0.25")
+    );
+    // Plots without redpiler don't have a backend or compile time
+    assert!(!text.contains("backend{plot_x=\"3\""));
+    assert!(!text.contains("compile_seconds{plot_x=\"3\""));
+}
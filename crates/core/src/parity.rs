@@ -0,0 +1,240 @@
+//! Vanilla parity testing. A parity case is a schematic of a contraption together with a
+//! trace of its inputs and the outputs recorded in vanilla. Every case is run with the
+//! interpreted redstone implementation and with every redpiler backend, and the outputs are
+//! compared tick by tick.
+//!
+//! A trace is a text file next to the schematic with the same name and a `.trace` extension:
+//!
+//! ```text
+//! # Positions are relative to the minimum corner of the schematic
+//! input lever 0 1 0
+//! output lamp 2 1 0
+//! # <redstone tick> <name>=<value> ...
+//! 0 lever=1
+//! 2 lamp=15
+//! ```
+//!
+//! Inputs are levers or buttons and are set to on when their value is non-zero. Outputs are
+//! compared by signal strength. On each tick, outputs are checked before inputs are changed.
+
+use crate::convert::empty_world;
+use crate::interaction;
+use crate::plot::display::world_signal_strength;
+use crate::plot::worldedit::schematic::load_schematic_file;
+use crate::plot::worldedit::{paste_clipboard, WorldEditClipboard};
+use crate::plot::PlotWorld;
+use crate::redpiler::{BackendKind, Compiler, CompilerOptions};
+use crate::world::World;
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Where the minimum corner of the schematic is pasted, right on top of the plot floor
+const ORIGIN: BlockPos = BlockPos::new(16, 8, 16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Interpreted,
+    Redpiler(BackendKind),
+}
+
+impl Engine {
+    /// The interpreted implementation followed by every redpiler backend
+    pub fn all() -> Vec<Engine> {
+        let mut engines = vec![Engine::Interpreted];
+        engines.extend(
+            BackendKind::BACKENDS
+                .iter()
+                .map(|&kind| Engine::Redpiler(kind)),
+        );
+        engines
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Interpreted => write!(f, "interpreted"),
+            Engine::Redpiler(kind) => write!(f, "redpiler ({})", kind.to_string()),
+        }
+    }
+}
+
+pub struct ParityCase {
+    pub name: String,
+    clipboard: WorldEditClipboard,
+    inputs: HashMap<String, BlockPos>,
+    outputs: HashMap<String, BlockPos>,
+    /// The input values to set and output values to check, by redstone tick
+    steps: BTreeMap<u32, Vec<(String, u8)>>,
+}
+
+impl ParityCase {
+    /// Loads a schematic and the trace next to it
+    pub fn load(schematic_path: &Path) -> Result<ParityCase> {
+        let name = schematic_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .context("invalid schematic file name")?
+            .to_owned();
+        let clipboard = load_schematic_file(schematic_path)
+            .with_context(|| format!("error loading schematic {}", schematic_path.display()))?;
+        let trace_path = schematic_path.with_extension("trace");
+        let trace = fs::read_to_string(&trace_path)
+            .with_context(|| format!("error reading trace {}", trace_path.display()))?;
+
+        let mut case = ParityCase {
+            name,
+            clipboard,
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+            steps: BTreeMap::new(),
+        };
+        for (i, line) in trace.lines().enumerate() {
+            case.parse_line(line)
+                .with_context(|| format!("{}:{}", trace_path.display(), i + 1))?;
+        }
+        Ok(case)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+        let mut words = line.split_whitespace();
+        let first = match words.next() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        match first {
+            "input" | "output" => {
+                let name = words.next().context("expected a name")?.to_owned();
+                let mut coord =
+                    || -> Result<i32> { Ok(words.next().context("expected a position")?.parse()?) };
+                let pos = BlockPos::new(coord()?, coord()?, coord()?);
+                if self.inputs.contains_key(&name) || self.outputs.contains_key(&name) {
+                    bail!("{} is defined twice", name);
+                }
+                if first == "input" {
+                    self.inputs.insert(name, pos);
+                } else {
+                    self.outputs.insert(name, pos);
+                }
+            }
+            tick => {
+                let tick: u32 = tick
+                    .parse()
+                    .with_context(|| format!("invalid tick: {}", tick))?;
+                let step = self.steps.entry(tick).or_default();
+                for word in words {
+                    let (name, value) = word
+                        .split_once('=')
+                        .with_context(|| format!("expected <name>=<value>, found {}", word))?;
+                    if !self.inputs.contains_key(name) && !self.outputs.contains_key(name) {
+                        bail!("{} is not an input or output", name);
+                    }
+                    step.push((name.to_owned(), value.parse()?));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the case with `engine`, returning an error on the first output that doesn't match
+    pub fn run(&self, engine: Engine) -> Result<()> {
+        let mut world = empty_world();
+        let cb = &self.clipboard;
+        let offset = BlockPos::new(cb.offset_x, cb.offset_y, cb.offset_z);
+        paste_clipboard(&mut world, cb, ORIGIN + offset, false);
+
+        let mut redpiler = Compiler::default();
+        if let Engine::Redpiler(backend) = engine {
+            let options = CompilerOptions {
+                backend,
+                ..Default::default()
+            };
+            let bounds = world.get_corners();
+            let ticks = world.to_be_ticked.drain(..).collect();
+            redpiler.compile(&mut world, bounds, options, ticks)?;
+        }
+
+        let last_tick = self.steps.keys().next_back().copied().unwrap_or(0);
+        for tick in 0..=last_tick {
+            let step = self.steps.get(&tick).map(Vec::as_slice).unwrap_or(&[]);
+            for (name, expected) in step {
+                if let Some(&pos) = self.outputs.get(name) {
+                    let actual = world_signal_strength(&world, ORIGIN + pos);
+                    if actual != *expected {
+                        bail!(
+                            "expected {} to be {} at tick {}, but it was {}",
+                            name,
+                            expected,
+                            tick,
+                            actual
+                        );
+                    }
+                }
+            }
+            for (name, value) in step {
+                if let Some(&pos) = self.inputs.get(name) {
+                    set_input(&mut world, &mut redpiler, ORIGIN + pos, *value > 0)
+                        .with_context(|| format!("error setting {} at tick {}", name, tick))?;
+                }
+            }
+            if redpiler.is_active {
+                redpiler.tick();
+                redpiler.flush(&mut world);
+            } else {
+                world.tick_interpreted();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_input(
+    world: &mut PlotWorld,
+    redpiler: &mut Compiler,
+    pos: BlockPos,
+    on: bool,
+) -> Result<()> {
+    let needs_use = match world.get_block(pos) {
+        Block::Lever { lever } => lever.powered != on,
+        Block::StoneButton { button } => on && !button.powered,
+        block => bail!("{:?} is not a lever or button", block),
+    };
+    if !needs_use {
+        return Ok(());
+    }
+    if redpiler.is_active {
+        redpiler.on_use_block(pos);
+        return Ok(());
+    }
+    match world.get_block(pos) {
+        Block::Lever { lever } => interaction::flip_lever(world, pos, lever),
+        Block::StoneButton { button } => interaction::press_button(world, pos, button),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Loads every schematic in `dir` that has a trace next to it, sorted by name
+pub fn load_corpus(dir: &Path) -> Result<Vec<ParityCase>> {
+    let mut cases = Vec::new();
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("error reading parity corpus at {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let is_schematic = path.extension().map_or(false, |ext| ext == "schem");
+        if is_schematic && path.with_extension("trace").exists() {
+            cases.push(ParityCase::load(&path)?);
+        }
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
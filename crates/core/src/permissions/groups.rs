@@ -0,0 +1,163 @@
+//! Permission groups that are configured in `permissions.toml`, which are used when LuckPerms
+//! isn't. Every player is in the `default` group, and can be put into more groups by their name or
+//! uuid. Groups can inherit the permissions of other groups.
+
+use super::{PermissionNode, PlayerPermissionsCache};
+use crate::utils::HyphenatedUUID;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use tracing::error;
+
+const PERMISSIONS_FILE: &str = "permissions.toml";
+const DEFAULT_GROUP: &str = "default";
+
+static GROUPS: Lazy<PermissionGroups> = Lazy::new(|| PermissionGroups::load(PERMISSIONS_FILE));
+
+const DEFAULT_PERMISSIONS: &str = r#"# Every player is in the default group.
+# Nodes can end with a wildcard, like `worldedit.*`, and nodes that start with a `-` are denied.
+# More specific nodes take precedence over wildcards.
+[groups.default]
+permissions = ["*"]
+
+# [groups.builder]
+# inherits = ["default"]
+# permissions = ["-mchprs.stop", "-mchprs.whitelist", "-plots.admin.*"]
+
+# Players are put into groups by their name or uuid.
+[players]
+# Steve = ["builder"]
+"#;
+
+#[derive(Deserialize, Default)]
+struct Group {
+    #[serde(default)]
+    inherits: Vec<String>,
+    #[serde(default)]
+    permissions: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct PermissionGroups {
+    #[serde(default)]
+    groups: HashMap<String, Group>,
+    #[serde(default)]
+    players: HashMap<String, Vec<String>>,
+}
+
+impl PermissionGroups {
+    fn load(path: &str) -> PermissionGroups {
+        let str = fs::read_to_string(path).unwrap_or_else(|_| {
+            if let Err(err) = fs::write(path, DEFAULT_PERMISSIONS) {
+                error!("Failed to write {}: {}", path, err);
+            }
+            DEFAULT_PERMISSIONS.to_owned()
+        });
+        toml::from_str(&str).unwrap_or_else(|err| {
+            // Nobody has any permissions rather than everyone having all of them
+            error!("Failed to parse {}: {}", path, err);
+            PermissionGroups::default()
+        })
+    }
+
+    /// The groups of a player, followed by the groups they inherit from, without duplicates
+    fn player_groups(&self, uuid: u128, username: &str) -> Vec<&str> {
+        let uuid = HyphenatedUUID(uuid).to_string();
+        let mut groups: Vec<&str> = Vec::new();
+        for (player, player_groups) in &self.players {
+            if player.eq_ignore_ascii_case(username) || player.eq_ignore_ascii_case(&uuid) {
+                for group in player_groups {
+                    if !groups.contains(&group.as_str()) {
+                        groups.push(group);
+                    }
+                }
+            }
+        }
+
+        let mut i = 0;
+        loop {
+            if i == groups.len() {
+                // The default group comes after the groups the player is put in explicitly
+                if groups.contains(&DEFAULT_GROUP) {
+                    break;
+                }
+                groups.push(DEFAULT_GROUP);
+            }
+            if let Some(group) = self.groups.get(groups[i]) {
+                for inherited in &group.inherits {
+                    if !groups.contains(&inherited.as_str()) {
+                        groups.push(inherited);
+                    }
+                }
+            }
+            i += 1;
+        }
+        groups
+    }
+
+    fn player_cache(&self, uuid: u128, username: &str) -> PlayerPermissionsCache {
+        let mut nodes = Vec::new();
+        for name in self.player_groups(uuid, username) {
+            let Some(group) = self.groups.get(name) else {
+                continue;
+            };
+            let start = nodes.len();
+            nodes.extend(
+                group
+                    .permissions
+                    .iter()
+                    .map(|node| match node.strip_prefix('-') {
+                        Some(node) => PermissionNode::global(node, 0),
+                        None => PermissionNode::global(node, 1),
+                    }),
+            );
+            // The first node that matches is used, so wildcards go after more specific nodes
+            nodes[start..].sort_by_key(|node| std::cmp::Reverse(node.specificity()));
+        }
+        PlayerPermissionsCache { nodes }
+    }
+}
+
+/// Resolves the permissions of a player from the groups they are in
+pub fn load_player_cache(uuid: u128, username: &str) -> PlayerPermissionsCache {
+    GROUPS.player_cache(uuid, username)
+}
+
+#[test]
+fn groups_are_resolved() {
+    let groups: PermissionGroups = toml::from_str(
+        r#"
+        [groups.default]
+        permissions = ["plots.*", "worldedit.*", "-plots.admin.*", "-worldedit.region.stack"]
+
+        [groups.builder]
+        inherits = ["default"]
+        permissions = ["worldedit.region.stack", "redpiler.compile"]
+
+        [groups.admin]
+        inherits = ["builder"]
+        permissions = ["*"]
+
+        [players]
+        Steve = ["builder"]
+        "00000000-0000-0000-0000-000000000001" = ["admin"]
+        "#,
+    )
+    .unwrap();
+
+    let alex = groups.player_cache(2, "Alex");
+    assert_eq!(alex.get_node_val("plots.claim"), Some(1));
+    assert_eq!(alex.get_node_val("plots.admin.interact.other"), Some(0));
+    assert_eq!(alex.get_node_val("worldedit.region.stack"), Some(0));
+    assert_eq!(alex.get_node_val("redpiler.compile"), None);
+
+    let steve = groups.player_cache(3, "steve");
+    assert_eq!(steve.get_node_val("worldedit.region.stack"), Some(1));
+    assert_eq!(steve.get_node_val("redpiler.compile"), Some(1));
+    assert_eq!(steve.get_node_val("plots.admin.interact.other"), Some(0));
+
+    let admin = groups.player_cache(1, "Admin");
+    assert_eq!(admin.get_node_val("mchprs.stop"), Some(1));
+    assert_eq!(admin.get_node_val("plots.admin.interact.other"), Some(1));
+}
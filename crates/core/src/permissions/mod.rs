@@ -1,3 +1,5 @@
+mod groups;
+
 use crate::config::CONFIG;
 use crate::utils::HyphenatedUUID;
 use anyhow::{anyhow, Context, Result};
@@ -6,6 +8,8 @@ use mysql::{OptsBuilder, Pool, PooledConn, Row};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
+pub use groups::load_player_cache as load_group_cache;
+
 static POOL: OnceCell<Pool> = OnceCell::new();
 
 fn conn() -> Result<PooledConn> {
@@ -33,22 +37,46 @@ struct PermissionNode {
 }
 
 impl PermissionNode {
+    fn parse_path(str: &str) -> Vec<PathSegment> {
+        str.split('.')
+            .map(|s| match s {
+                "*" => PathSegment::WildCard,
+                s => PathSegment::Named(s.to_owned()),
+            })
+            .collect()
+    }
+
+    /// A node that applies on every server
+    fn global(str: &str, value: i32) -> PermissionNode {
+        PermissionNode {
+            path: PermissionNode::parse_path(str),
+            value,
+            server_context: "global".to_owned(),
+        }
+    }
+
+    /// The number of segments that aren't a wildcard
+    fn specificity(&self) -> usize {
+        self.path
+            .iter()
+            .filter(|segment| matches!(segment, PathSegment::Named(_)))
+            .count()
+    }
+
     fn matches(&self, str: &str) -> bool {
         if self.server_context != "global" && self.server_context != config().server_context {
             return false;
         }
 
-        for (i, segment) in str.split('.').enumerate() {
-            match &self.path[i] {
-                PathSegment::WildCard => return true,
-                PathSegment::Named(name) => {
-                    if name != segment {
-                        return false;
-                    }
-                }
+        let mut segments = str.split('.');
+        for path_segment in &self.path {
+            match (path_segment, segments.next()) {
+                (PathSegment::WildCard, Some(_)) => return true,
+                (PathSegment::Named(name), Some(segment)) if name == segment => {}
+                _ => return false,
             }
         }
-        true
+        segments.next().is_none()
     }
 }
 
@@ -113,15 +141,8 @@ pub fn load_player_cache(uuid: u128) -> Result<PlayerPermissionsCache> {
     let mut nodes = Vec::new();
     for row in res {
         let path_str = String::from_value(row[2].clone());
-        let path = path_str
-            .split('.')
-            .map(|s| match s {
-                "*" => PathSegment::WildCard,
-                s => PathSegment::Named(s.to_owned()),
-            })
-            .collect();
         let node = PermissionNode {
-            path,
+            path: PermissionNode::parse_path(&path_str),
             server_context: FromValue::from_value(row[4].clone()),
             value: FromValue::from_value(row[3].clone()),
         };
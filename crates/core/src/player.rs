@@ -1,14 +1,16 @@
 use crate::chat::ChatComponent;
 use crate::config::CONFIG;
 use crate::permissions::{self, PlayerPermissionsCache};
-use crate::plot::worldedit::{WorldEditClipboard, WorldEditUndo};
-use crate::plot::PLOT_SCALE;
+use crate::plot::display::OverlaySettings;
+use crate::plot::worldedit::{Brush, WorldEditClipboard, WorldEditHistory};
+use crate::plot::PLOT_SIZE;
 use crate::utils::HyphenatedUUID;
 use byteorder::{BigEndian, ReadBytesExt};
 use mchprs_blocks::block_entities::{ContainerType, InventoryEntry};
 use mchprs_blocks::items::{Item, ItemStack};
 use mchprs_blocks::{BlockDirection, BlockFacing, BlockPos};
 use mchprs_network::packets::clientbound::*;
+use mchprs_network::packets::serverbound::ServerBoundPacket;
 use mchprs_network::packets::{PacketEncoder, SlotData};
 use mchprs_network::{PlayerConn, PlayerPacketSender};
 use serde::{Deserialize, Serialize};
@@ -17,12 +19,17 @@ use std::fmt;
 use std::fs::{self, OpenOptions};
 use std::io::{Cursor, Write};
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::{Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tracing::{error, warn};
 
 pub type EntityId = u32;
 static ENTITY_ID_COUNTER: AtomicU32 = AtomicU32::new(0);
 
+/// Allocate a new unique entity id
+pub fn next_entity_id() -> EntityId {
+    ENTITY_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Gamemode {
     Creative,
@@ -110,7 +117,7 @@ impl PlayerPos {
 
     pub fn plot_pos(self) -> (i32, i32) {
         let (chunk_x, chunk_z) = self.chunk_pos();
-        (chunk_x >> PLOT_SCALE, chunk_z >> PLOT_SCALE)
+        (chunk_x >> PLOT_SIZE.scale, chunk_z >> PLOT_SIZE.scale)
     }
 }
 
@@ -149,19 +156,29 @@ pub struct Player {
     pub last_keep_alive_received: Instant,
     /// The last time the keep alive packet was sent.
     last_keep_alive_sent: Instant,
+    /// The round trip time of the last keep alive packet.
+    pub ping: Option<Duration>,
     /// The worldedit first position.
     pub first_position: Option<BlockPos>,
     /// The worldedit second position.
     pub second_position: Option<BlockPos>,
     /// The worldedit current clipboard.
     pub worldedit_clipboard: Option<WorldEditClipboard>,
+    /// The brushes that are bound to items, see `/brush`
+    pub brushes: Vec<Brush>,
     /// The saved sections used for worldedit //undo
     /// Each entry stores the plot coords and the clipboard
-    pub worldedit_undo: Vec<WorldEditUndo>,
-    pub worldedit_redo: Vec<WorldEditUndo>,
+    pub worldedit_undo: WorldEditHistory,
+    pub worldedit_redo: WorldEditHistory,
+    /// The signal overlay settings, if the player turned it on
+    pub signal_overlay: Option<OverlaySettings>,
+    /// Clicking blocks shows their history instead of changing them, see `/history inspect`
+    pub inspecting: bool,
     /// Commands are stored so they can be handled after packets
     pub command_queue: Vec<String>,
-    permissions_cache: Option<PlayerPermissionsCache>,
+    /// Packets that arrived while the plot was busy, which are handled before newer packets
+    pub held_packets: Vec<Box<dyn ServerBoundPacket>>,
+    permissions_cache: PlayerPermissionsCache,
 }
 
 impl fmt::Debug for Player {
@@ -201,10 +218,11 @@ impl Player {
                 nbt,
             });
         }
-        let permissions_cache = CONFIG
-            .luckperms
-            .is_some()
-            .then(|| permissions::load_player_cache(uuid).unwrap());
+        let permissions_cache = match CONFIG.luckperms {
+            Some(_) => permissions::load_player_cache(uuid).unwrap(),
+            None => permissions::load_group_cache(uuid, &username),
+        };
+        let undo_memory = CONFIG.worldedit_undo_memory.max(0) as usize * 1024 * 1024;
         Player {
             uuid,
             username,
@@ -220,7 +238,7 @@ impl Player {
             yaw: player_data.rotation[1],
             last_chunk_x: 0,
             last_chunk_z: 0,
-            entity_id: ENTITY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+            entity_id: next_entity_id(),
             client,
             flying: player_data.flying,
             sprinting: false,
@@ -231,12 +249,23 @@ impl Player {
             fly_speed: player_data.fly_speed,
             last_keep_alive_received: Instant::now(),
             last_keep_alive_sent: Instant::now(),
+            ping: None,
             first_position: None,
             second_position: None,
             worldedit_clipboard: None,
-            worldedit_undo: Vec::new(),
-            worldedit_redo: Vec::new(),
+            brushes: Vec::new(),
+            worldedit_undo: WorldEditHistory::new(
+                format!("./world/undo/{:032x}-undo", uuid),
+                undo_memory,
+            ),
+            worldedit_redo: WorldEditHistory::new(
+                format!("./world/undo/{:032x}-redo", uuid),
+                undo_memory,
+            ),
+            signal_overlay: None,
+            inspecting: false,
             command_queue: Vec::new(),
+            held_packets: Vec::new(),
             permissions_cache,
         }
     }
@@ -323,6 +352,13 @@ impl Player {
         chunk_x != self.last_chunk_x || chunk_z != self.last_chunk_z
     }
 
+    /// Updates `last_keep_alive_received` and the ping of the player
+    pub fn keep_alive_received(&mut self) {
+        let now = Instant::now();
+        self.ping = Some(now - self.last_keep_alive_sent);
+        self.last_keep_alive_received = now;
+    }
+
     /// Sends the keep alive packet to the client and updates `last_keep_alive_sent`
     pub fn send_keep_alive(&mut self) {
         let keep_alive = CKeepAlive {
@@ -386,6 +422,21 @@ impl Player {
         self.client.send_packet(&player_position_and_look);
     }
 
+    /// Receives the packets that arrived so far and holds on to them until the plot handles
+    /// packets again. Chat messages that `take` returns true for are taken out instead of held.
+    /// Returns true if there was such a message.
+    pub fn hold_packets(&mut self, take: impl Fn(&str) -> bool) -> bool {
+        let mut taken = false;
+        for packet in self.client.receive_packets() {
+            if packet.chat_message().map_or(false, &take) {
+                taken = true;
+            } else {
+                self.held_packets.push(packet);
+            }
+        }
+        taken
+    }
+
     /// Sends the `ChatMessage` packet containing the raw json data.
     /// Position 0: chat (chat box)
     pub fn send_raw_chat(&self, sender: u128, message: String) {
@@ -398,6 +449,17 @@ impl Player {
         self.client.send_packet(&chat_message);
     }
 
+    /// Shows `message` above the hotbar of the player, replacing what was there before
+    pub fn send_action_bar(&self, message: &str) {
+        let chat_message = CChatMessage {
+            message: json!({ "text": message }).to_string(),
+            sender: 0,
+            position: 2,
+        }
+        .encode();
+        self.client.send_packet(&chat_message);
+    }
+
     /// Sends a raw chat message to the player
     pub fn send_chat_message(&self, sender: u128, message: &[ChatComponent]) {
         let json = json!({ "text": "", "extra": message }).to_string();
@@ -472,20 +534,25 @@ impl Player {
         self.client.send_packet(&change_game_state);
     }
 
+    /// Whether the player has been granted a permission node, like `plots.claim`, either by
+    /// LuckPerms or by the groups in `permissions.toml`
     pub fn has_permission(&self, node: &str) -> bool {
-        if let Some(cache) = &self.permissions_cache {
-            if let Some(val) = cache.get_node_val(node) {
-                val > 0
-            } else {
-                // Node is not in database
-                false
-            }
-        } else {
-            // Permissions is not enabled
-            true
+        match self.permissions_cache.get_node_val(node) {
+            Some(val) => val > 0,
+            // Nodes that aren't granted are denied
+            None => false,
         }
     }
 
+    /// Like [`Player::has_permission`], but nodes that aren't set at all are allowed. The
+    /// `redpiler` and `mchprs` nodes use this, because those commands could be used by everyone
+    /// before they had nodes and existing LuckPerms setups don't grant them.
+    pub fn has_permission_or_unset(&self, node: &str) -> bool {
+        self.permissions_cache
+            .get_node_val(node)
+            .map_or(true, |val| val > 0)
+    }
+
     pub fn open_container(&self, inventory: &[InventoryEntry], container_type: ContainerType) {
         let mut slots: Vec<Option<SlotData>> =
             (0..container_type.num_slots()).map(|_| None).collect();
@@ -0,0 +1,154 @@
+//! Note block sounds. The world collects the notes that are played while ticking, and once
+//! per update they are sent to every player in the plot as named sound effects. Every player
+//! gets the sounds positioned relative to where they are standing, and only a limited amount
+//! of sounds per update, so that big music machines don't flood clients with packets.
+
+use crate::player::{PacketSender, Player, PlayerPos};
+use mchprs_blocks::blocks::Instrument;
+use mchprs_blocks::BlockPos;
+use mchprs_network::packets::clientbound::{CNamedSoundEffect, ClientBoundPacket};
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+
+/// Note blocks play in the "Jukebox/Note Blocks" sound category
+const RECORDS_CATEGORY: i32 = 2;
+/// The volume vanilla note blocks play at
+const NOTE_VOLUME: f32 = 3.0;
+/// Sounds that are further away than this are moved closer to the listener. They still come
+/// from the same direction, but don't fade out for players that are far away.
+const MAX_SOUND_DISTANCE: f64 = 16.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteEvent {
+    pub pos: BlockPos,
+    pub instrument: Instrument,
+    pub note: u32,
+}
+
+/// Maps instruments to the sounds that are played for them. Instruments that are not in the
+/// mapping play their vanilla note block sound.
+pub struct NoteSounds {
+    overrides: HashMap<Instrument, String>,
+}
+
+impl NoteSounds {
+    /// Creates the mapping from a table of instrument names to sound names, as it is found in
+    /// the config. Unknown instrument names are ignored.
+    pub fn new(config: Option<&HashMap<String, String>>) -> NoteSounds {
+        let mut overrides = HashMap::new();
+        for (instrument, sound) in config.into_iter().flatten() {
+            match instrument.parse() {
+                Ok(instrument) => {
+                    overrides.insert(instrument, sound.clone());
+                }
+                Err(()) => warn!("Unknown instrument in note block sounds: {}", instrument),
+            }
+        }
+        NoteSounds { overrides }
+    }
+
+    pub fn sound_name(&self, instrument: Instrument) -> String {
+        match self.overrides.get(&instrument) {
+            Some(sound) => sound.clone(),
+            None => format!("minecraft:block.note_block.{}", instrument.to_string()),
+        }
+    }
+}
+
+/// The pitch of a note, from 0.5 for note 0 to 2.0 for note 24
+pub fn note_pitch(note: u32) -> f32 {
+    2f32.powf((note as f32 - 12.0) / 12.0)
+}
+
+fn distance_sq(listener: PlayerPos, pos: BlockPos) -> f64 {
+    let dx = pos.x as f64 + 0.5 - listener.x;
+    let dy = pos.y as f64 + 0.5 - listener.y;
+    let dz = pos.z as f64 + 0.5 - listener.z;
+    dx * dx + dy * dy + dz * dz
+}
+
+/// The position a sound at `pos` is played at for a player at `listener`
+pub fn sound_position(listener: PlayerPos, pos: BlockPos) -> PlayerPos {
+    let source = PlayerPos::new(pos.x as f64 + 0.5, pos.y as f64 + 0.5, pos.z as f64 + 0.5);
+    let distance = distance_sq(listener, pos).sqrt();
+    if distance <= MAX_SOUND_DISTANCE {
+        return source;
+    }
+    let scale = MAX_SOUND_DISTANCE / distance;
+    PlayerPos::new(
+        listener.x + (source.x - listener.x) * scale,
+        listener.y + (source.y - listener.y) * scale,
+        listener.z + (source.z - listener.z) * scale,
+    )
+}
+
+/// Picks the notes that are sent to a player at `listener`. When the same instrument plays
+/// the same note more than once, only the closest one is sent. After that, the closest
+/// `max_sounds` notes are kept.
+pub fn limit_note_events(
+    events: &[NoteEvent],
+    listener: PlayerPos,
+    max_sounds: usize,
+) -> Vec<NoteEvent> {
+    let mut events = events.to_vec();
+    events.sort_by(|a, b| distance_sq(listener, a.pos).total_cmp(&distance_sq(listener, b.pos)));
+    let mut played = HashSet::new();
+    events.retain(|event| played.insert((event.instrument, event.note)));
+    events.truncate(max_sounds);
+    events
+}
+
+/// Sends the notes played since the last update to every player
+pub fn send_note_sounds(
+    players: &[Player],
+    events: &[NoteEvent],
+    sounds: &NoteSounds,
+    max_sounds: usize,
+) {
+    if events.is_empty() {
+        return;
+    }
+    for player in players {
+        for event in limit_note_events(events, player.pos, max_sounds) {
+            let pos = sound_position(player.pos, event.pos);
+            let sound = CNamedSoundEffect {
+                sound_name: sounds.sound_name(event.instrument),
+                sound_category: RECORDS_CATEGORY,
+                x: (pos.x * 8.0) as i32,
+                y: (pos.y * 8.0) as i32,
+                z: (pos.z * 8.0) as i32,
+                volume: NOTE_VOLUME,
+                pitch: note_pitch(event.note),
+            }
+            .encode();
+            player.send_packet(&sound);
+        }
+    }
+}
+
+#[test]
+fn note_sounds_are_limited_per_player() {
+    let note = |x, instrument, note| NoteEvent {
+        pos: BlockPos::new(x, 0, 0),
+        instrument,
+        note,
+    };
+    let events = [
+        note(40, Instrument::Harp, 0),
+        note(10, Instrument::Harp, 0),
+        note(20, Instrument::Bass, 3),
+        note(30, Instrument::Harp, 1),
+    ];
+
+    // The closest copy of a duplicate note is kept, then the closest notes
+    let listener = PlayerPos::new(0.5, 0.5, 0.5);
+    let limited = limit_note_events(&events, listener, 2);
+    assert_eq!(limited, vec![events[1], events[2]]);
+
+    // Far away notes are moved closer, but keep their direction
+    let pos = sound_position(listener, events[0].pos);
+    assert!((pos.x - 16.5).abs() < 1e-9);
+    assert_eq!((pos.y, pos.z), (0.5, 0.5));
+    let pos = sound_position(listener, events[1].pos);
+    assert_eq!((pos.x, pos.y, pos.z), (10.5, 0.5, 0.5));
+}
@@ -0,0 +1,241 @@
+//! A strict vanilla timing audit of a running redpiler circuit. A copy of the plot is ticked by
+//! the interpreter next to the compiled circuit and is given the same inputs. After every tick,
+//! the state of every signal block of the circuit and the order of its pending ticks are
+//! compared, so builds that rely on the order of ticks within a game tick can be checked
+//! against the interpreter.
+
+use super::{display, PlotWorld};
+use crate::interaction;
+use crate::redpiler::{Compiler, InputEvent};
+use crate::redstone;
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockPos};
+use mchprs_world::TickPriority;
+use rustc_hash::FxHashSet;
+use std::fmt;
+
+/// A pending tick of a node, in the order the ticks happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingTick {
+    pub pos: BlockPos,
+    pub delay: u32,
+    pub priority: TickPriority,
+}
+
+#[derive(Debug)]
+pub enum Divergence {
+    State {
+        tick: u64,
+        pos: BlockPos,
+        block: Block,
+        vanilla: u8,
+        redpiler: u8,
+    },
+    Ticks {
+        tick: u64,
+        vanilla: Vec<PendingTick>,
+        redpiler: Vec<PendingTick>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::State {
+                tick,
+                pos,
+                block,
+                vanilla,
+                redpiler,
+            } => write!(
+                f,
+                "{} at {} has signal strength {} instead of {} after {} ticks",
+                block.get_name(),
+                pos,
+                redpiler,
+                vanilla,
+                tick
+            ),
+            Divergence::Ticks {
+                tick,
+                vanilla,
+                redpiler,
+            } => {
+                // Only the first tick that differs is shown, the rest follow from it
+                let first = vanilla
+                    .iter()
+                    .zip(redpiler)
+                    .position(|(a, b)| a != b)
+                    .unwrap_or(vanilla.len().min(redpiler.len()));
+                let show = |ticks: &[PendingTick]| match ticks.get(first) {
+                    Some(tick) => format!(
+                        "{} in {} ticks with priority {:?}",
+                        tick.pos, tick.delay, tick.priority
+                    ),
+                    None => "nothing".to_owned(),
+                };
+                write!(
+                    f,
+                    "pending tick {} after {} ticks is {} instead of {}",
+                    first + 1,
+                    tick,
+                    show(redpiler),
+                    show(vanilla)
+                )
+            }
+        }
+    }
+}
+
+pub struct TimingAudit {
+    world: PlotWorld,
+    tick: u64,
+}
+
+impl TimingAudit {
+    /// Starts an audit from the current state of `world`, which has to include the pending
+    /// ticks of the circuit, so it has to be taken while redpiler isn't running
+    pub fn new(world: &PlotWorld) -> TimingAudit {
+        TimingAudit {
+            world: world.shadow_copy(),
+            tick: 0,
+        }
+    }
+
+    fn input(&mut self, event: InputEvent) {
+        let world = &mut self.world;
+        match event {
+            InputEvent::Use(pos) => match world.get_block(pos) {
+                Block::Lever { lever } => interaction::flip_lever(world, pos, lever),
+                Block::StoneButton { button } if !button.powered => {
+                    interaction::press_button(world, pos, button)
+                }
+                _ => {}
+            },
+            InputEvent::PressurePlate(pos, powered) => {
+                if let Block::StonePressurePlate { .. } = world.get_block(pos) {
+                    world.set_block(pos, Block::StonePressurePlate { powered });
+                    redstone::update_surrounding_blocks(world, pos);
+                    redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Bottom));
+                }
+            }
+            InputEvent::HitTarget { pos, power, ticks } => {
+                redstone::target::hit(world, pos, power, ticks)
+            }
+        }
+    }
+
+    /// Gives the copy the inputs of the circuit and ticks it, then compares it to the circuit
+    pub fn tick(&mut self, redpiler: &mut Compiler) -> Option<Divergence> {
+        for event in redpiler.take_watched_inputs() {
+            self.input(event);
+        }
+        self.world.tick_interpreted();
+        self.tick += 1;
+
+        let snapshot = redpiler.snapshot()?;
+        let compiled: FxHashSet<BlockPos> =
+            snapshot.nodes.iter().filter_map(|node| node.pos).collect();
+        for pos in snapshot.nodes.iter().filter_map(|node| node.pos) {
+            if !display::is_signal_block(&self.world, pos) {
+                continue;
+            }
+            let block = self.world.get_block(pos);
+            let vanilla = display::world_signal_strength(&self.world, pos);
+            // Only wires and comparators keep track of their signal strength in the backend
+            let redpiler = match block {
+                Block::RedstoneWire { .. } | Block::RedstoneComparator { .. } => {
+                    redpiler.get_signal_strength(pos)
+                }
+                _ => redpiler.is_powered(pos).map(|powered| powered as u8 * 15),
+            };
+            match redpiler {
+                Some(redpiler) if redpiler != vanilla => {
+                    return Some(Divergence::State {
+                        tick: self.tick,
+                        pos,
+                        block,
+                        vanilla,
+                        redpiler,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let redpiler: Vec<PendingTick> = snapshot
+            .ticks
+            .iter()
+            .filter_map(|tick| {
+                Some(PendingTick {
+                    pos: snapshot.nodes[tick.node].pos?,
+                    delay: tick.delay,
+                    priority: tick.priority,
+                })
+            })
+            .collect();
+        let mut vanilla: Vec<PendingTick> = self
+            .world
+            .to_be_ticked
+            .iter()
+            .filter(|entry| compiled.contains(&entry.pos))
+            .map(|entry| PendingTick {
+                pos: entry.pos,
+                delay: entry.ticks_left.max(1),
+                priority: entry.tick_priority,
+            })
+            .collect();
+        // The interpreter runs ticks in this order, ticks scheduled earlier go first
+        vanilla.sort_by_key(|tick| (tick.delay, tick.priority));
+        if vanilla != redpiler {
+            return Some(Divergence::Ticks {
+                tick: self.tick,
+                vanilla,
+                redpiler,
+            });
+        }
+        None
+    }
+}
+
+#[test]
+fn timing_audit_follows_the_interpreter() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever = BlockPos::new(10, 8, 10);
+    let repeaters = [1, 2, 3].map(|delay| Block::RedstoneRepeater {
+        repeater: RedstoneRepeater {
+            delay,
+            facing: BlockDirection::West,
+            locked: false,
+            powered: false,
+        },
+    });
+    build_lever_line(&mut world, lever, &repeaters);
+    world.flush_block_changes();
+
+    let mut audit = TimingAudit::new(&world);
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+    redpiler.watch_inputs(true);
+
+    for _ in 0..2 {
+        redpiler.on_use_block(lever);
+        for tick in 0..20 {
+            redpiler.tick();
+            if let Some(divergence) = audit.tick(&mut redpiler) {
+                panic!("diverged on tick {}: {}", tick, divergence);
+            }
+        }
+    }
+
+    // An input the copy doesn't get is a divergence
+    redpiler.watch_inputs(false);
+    redpiler.on_use_block(lever);
+    redpiler.tick();
+    let divergence = audit.tick(&mut redpiler).unwrap();
+    assert!(matches!(divergence, Divergence::State { pos, .. } if pos == lever));
+}
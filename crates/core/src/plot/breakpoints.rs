@@ -0,0 +1,163 @@
+//! Breakpoints that pause the ticking of a plot when a signal in the circuit changes, like a lamp
+//! turning on or a bus reaching a value. Conditions are checked after every tick, and a
+//! breakpoint is hit when its condition becomes true, not while it stays true.
+
+use mchprs_blocks::BlockPos;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// A signal block turning on or off
+    Node { pos: BlockPos, powered: bool },
+    /// A multi-bit bus, least significant bit first, having a value
+    Bus { bits: Vec<BlockPos>, value: u64 },
+}
+
+impl Condition {
+    fn is_met(&self, signal_strength: &impl Fn(BlockPos) -> u8) -> bool {
+        match self {
+            Condition::Node { pos, powered } => (signal_strength(*pos) > 0) == *powered,
+            Condition::Bus { bits, value } => bus_value(bits, signal_strength) == *value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Node { pos, powered } => {
+                let state = if *powered { "on" } else { "off" };
+                write!(f, "{} turns {}", pos, state)
+            }
+            Condition::Bus { bits, value } => {
+                write!(f, "{} bit bus at {} is 0x{:X}", bits.len(), bits[0], value)
+            }
+        }
+    }
+}
+
+fn bus_value(bits: &[BlockPos], signal_strength: &impl Fn(BlockPos) -> u8) -> u64 {
+    bits.iter()
+        .enumerate()
+        .filter(|(_, &pos)| signal_strength(pos) > 0)
+        .fold(0, |value, (i, _)| value | (1 << i))
+}
+
+/// Parses a bus value, either decimal or hex starting with `0x`
+pub fn parse_value(str: &str) -> Option<u64> {
+    match str.strip_prefix("0x").or_else(|| str.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => str.parse().ok(),
+    }
+}
+
+pub struct Breakpoint {
+    pub id: u32,
+    pub condition: Condition,
+    /// Whether the condition was true after the last tick
+    was_met: bool,
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    next_id: u32,
+}
+
+impl Breakpoints {
+    /// Adds a breakpoint, which is first hit the next time its condition becomes true
+    pub fn add(&mut self, condition: Condition, signal_strength: impl Fn(BlockPos) -> u8) -> u32 {
+        self.next_id += 1;
+        let was_met = condition.is_met(&signal_strength);
+        self.breakpoints.push(Breakpoint {
+            id: self.next_id,
+            condition,
+            was_met,
+        });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    /// Checks every breakpoint after a tick, returning the ones whose condition became true
+    pub fn check(&mut self, signal_strength: impl Fn(BlockPos) -> u8) -> Vec<&Breakpoint> {
+        let mut hit = Vec::new();
+        for breakpoint in &mut self.breakpoints {
+            let met = breakpoint.condition.is_met(&signal_strength);
+            if met && !breakpoint.was_met {
+                hit.push(breakpoint.id);
+            }
+            breakpoint.was_met = met;
+        }
+        self.breakpoints
+            .iter()
+            .filter(|breakpoint| hit.contains(&breakpoint.id))
+            .collect()
+    }
+}
+
+#[test]
+fn breakpoints_are_hit_when_their_condition_becomes_true() {
+    use std::cell::RefCell;
+
+    let lamp = BlockPos::new(0, 0, 0);
+    let bits = vec![BlockPos::new(1, 0, 0), BlockPos::new(2, 0, 0)];
+    let powered = RefCell::new(vec![bits[1]]);
+    let signal_strength = |pos| match powered.borrow().contains(&pos) {
+        true => 15,
+        false => 0,
+    };
+
+    let mut breakpoints = Breakpoints::default();
+    let on = breakpoints.add(
+        Condition::Node {
+            pos: lamp,
+            powered: true,
+        },
+        signal_strength,
+    );
+    // The bus already has the value, so it is only hit once it changes back to it
+    let bus = breakpoints.add(Condition::Bus { bits, value: 2 }, signal_strength);
+    assert!(breakpoints.check(signal_strength).is_empty());
+
+    powered.borrow_mut().push(lamp);
+    let hit: Vec<u32> = breakpoints
+        .check(signal_strength)
+        .iter()
+        .map(|b| b.id)
+        .collect();
+    assert_eq!(hit, [on]);
+    assert!(breakpoints.check(signal_strength).is_empty());
+
+    *powered.borrow_mut() = vec![];
+    assert!(breakpoints.check(signal_strength).is_empty());
+    *powered.borrow_mut() = vec![BlockPos::new(2, 0, 0)];
+    let hit: Vec<u32> = breakpoints
+        .check(signal_strength)
+        .iter()
+        .map(|b| b.id)
+        .collect();
+    assert_eq!(hit, [bus]);
+
+    assert!(breakpoints.remove(bus));
+    assert!(!breakpoints.remove(bus));
+    assert_eq!(parse_value("0x3F"), Some(63));
+    assert_eq!(parse_value("12"), Some(12));
+    assert_eq!(parse_value("x"), None);
+}
@@ -1,24 +1,91 @@
-use super::{database, worldedit, Plot, PlotWorld};
+use super::audit::TimingAudit;
+use super::breakpoints::{self, Condition};
+use super::display::{self, DisplaySource, OverlaySettings};
+use super::music::{self, Song};
+use super::packet_handlers::ERROR_IO_ONLY;
+use super::scoreboard::RedpilerState;
+use super::{database, history, ports, worldedit, Plot, PlotWorld};
 use crate::chat::ChatComponent;
-use crate::player::{Gamemode, PacketSender, PlayerPos};
-use crate::plot::data::sleep_time_for_tps;
+use crate::config::CONFIG;
+use crate::features::{self, Feature, FeatureScope};
+use crate::player::{self, Gamemode, PacketSender, PlayerPos};
+use crate::plot::data::{parse_rtps, sleep_time_for_tps};
 use crate::profile::PlayerProfile;
-use crate::redpiler::CompilerOptions;
+use crate::redpiler::clock::{self, FastForward, Until};
+use crate::redpiler::script::TestScript;
+use crate::redpiler::verify::EquivalenceCheck;
+use crate::redpiler::{self, BackendSnapshot, CompilerOptions, Recording};
+use crate::redstone;
+use crate::redstone::daylight_detector::{self, NOON};
+use crate::redstone::update_order;
 use crate::server::Message;
+use crate::utils::HyphenatedUUID;
+use crate::world::World;
 use bitflags::_core::i32::MAX;
+use mchprs_blocks::aabb::Aabb;
+use mchprs_blocks::block_entities::{BlockEntity, ContainerType};
+use mchprs_blocks::blocks::Block;
 use mchprs_blocks::items::ItemStack;
+use mchprs_blocks::BlockPos;
 use mchprs_network::packets::clientbound::{
     CDeclareCommands, CDeclareCommandsNode as Node, CDeclareCommandsNodeParser as Parser,
     ClientBoundPacket,
 };
 use mchprs_network::packets::PacketEncoder;
 use mchprs_network::PlayerPacketSender;
-use mchprs_save_data::plot_data::Tps;
 use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashSet;
 use std::ops::Add;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Instant;
-use tracing::{debug, info, warn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+
+/// Where `/redpiler graph` writes the exported graph
+const GRAPH_EXPORT_PATH: &str = "redpiler_graph.dot";
+
+/// The most signal blocks `/probe` lists in chat
+const MAX_PROBE_LINES: usize = 64;
+
+const ERROR_RECORDING_NAME: &str =
+    "Give the recording a name made of letters, numbers, dashes and underscores.";
+
+const ERROR_PORT_NAME: &str =
+    "Port names are made of at most 32 letters, numbers, dashes and underscores.";
+
+/// The most assertion results `/test` lists in chat
+const MAX_TEST_LINES: usize = 64;
+
+/// Returns the path of the json file `name` in `dir`, if the name can't leave the directory
+fn named_json_path(dir: &str, name: &str) -> Option<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| Path::new(dir).join(format!("{}.json", name)))
+}
+
+/// How long ago something happened, like `5 minutes ago`
+pub(super) fn format_age(secs: u64) -> String {
+    let (amount, unit) = match secs {
+        0..=119 => (secs, "seconds"),
+        120..=7199 => (secs / 60, "minutes"),
+        7200..=172_799 => (secs / 3600, "hours"),
+        _ => (secs / 86400, "days"),
+    };
+    format!("{} {} ago", amount, unit)
+}
+
+/// Snapshots of circuits that are kept on disk are saved in `./snapshots`
+fn snapshot_path(name: &str) -> Option<PathBuf> {
+    named_json_path("./snapshots", name)
+}
+
+/// Recordings of circuit inputs are saved in `./recordings`
+fn recording_path(name: &str) -> Option<PathBuf> {
+    named_json_path("./recordings", name)
+}
 
 // Parses a relative or absolute coordinate relative to a reference coordinate
 fn parse_relative_coord<F: FromStr + Add + Add<Output = F>>(
@@ -47,6 +114,12 @@ impl Plot {
             "visit" | "v" => "plots.visit",
             "teleport" | "tp" => "plots.visit",
             "lock" | "unlock" => "plots.lock",
+            "recover" => "plots.recover",
+            "create" => "plots.create",
+            "trust" | "untrust" => "plots.trust",
+            "deny" | "undeny" => "plots.deny",
+            "backups" | "backup" => "plots.backup",
+            "rollback" => "plots.rollback",
             _ => {
                 self.players[player].send_error_message("Invalid argument for /plot");
                 return;
@@ -64,6 +137,24 @@ impl Plot {
                         "Plot owner is: {}",
                         database::get_cached_username(owner.clone()).unwrap_or(owner)
                     ));
+                    let names = |uuids: &HashSet<u128>| {
+                        let names: Vec<String> = uuids
+                            .iter()
+                            .map(|&uuid| {
+                                let uuid = format!("{:032x}", uuid);
+                                database::get_cached_username(uuid.clone()).unwrap_or(uuid)
+                            })
+                            .collect();
+                        names.join(", ")
+                    };
+                    if !self.trusted.is_empty() {
+                        self.players[player]
+                            .send_system_message(&format!("Trusted: {}", names(&self.trusted)));
+                    }
+                    if !self.denied.is_empty() {
+                        self.players[player]
+                            .send_system_message(&format!("Denied: {}", names(&self.denied)));
+                    }
                 } else {
                     self.players[player].send_system_message("Plot is not owned by anyone.");
                 }
@@ -86,6 +177,20 @@ impl Plot {
                     }
                 }
             }
+            "trust" | "untrust" | "deny" | "undeny" => {
+                let [name] = args else {
+                    self.players[player]
+                        .send_error_message(&format!("Usage: /plot {} <player>", command));
+                    return;
+                };
+                let access = match command {
+                    "trust" => Some(true),
+                    "deny" => Some(false),
+                    _ => None,
+                };
+                self.set_plot_access(player, name, access);
+            }
+            "backups" | "backup" | "rollback" => self.handle_backup_command(player, command, args),
             "middle" => {
                 let center = Plot::get_center(plot_x, plot_z);
                 self.players[player].teleport(PlayerPos::new(center.0, 64.0, center.1));
@@ -119,92 +224,1651 @@ impl Plot {
                     }
                 } else {
                     self.players[player]
-                        .send_system_message(&format!("{} does not own any plots.", args[0]));
+                        .send_system_message(&format!("{} does not own any plots.", args[0]));
+                }
+            }
+            "teleport" | "tp" => {
+                if args.len() != 2 {
+                    self.players[player].send_error_message("Invalid number of arguments!");
+                    return;
+                }
+
+                let new_plot_x;
+                let new_plot_z;
+                if let Ok(x_arg) = parse_relative_coord(args[0], plot_x) {
+                    new_plot_x = x_arg;
+                } else {
+                    self.players[player].send_error_message("Unable to parse x coordinate!");
+                    return;
+                }
+                if let Ok(z_arg) = parse_relative_coord(args[1], plot_z) {
+                    new_plot_z = z_arg;
+                } else {
+                    self.players[player].send_error_message("Unable to parse z coordinate!");
+                    return;
+                }
+
+                let center = Plot::get_center(new_plot_x, new_plot_z);
+                self.players[player].teleport(PlayerPos::new(center.0, 64.0, center.1));
+            }
+            "lock" => {
+                if self.locked_players.insert(self.players[player].entity_id) {
+                    let PlotWorld { x, z, .. } = self.world;
+                    let res = format!("Locked to plot ({}, {}). Use '/p unlock' to unlock.", x, z);
+                    self.players[player].send_system_message(&res);
+                } else {
+                    self.players[player]
+                        .send_system_message("You are already locked to this plot.");
+                }
+            }
+            "unlock" => {
+                if self.locked_players.remove(&self.players[player].entity_id) {
+                    self.players[player].send_system_message("You are now unlocked.");
+                } else {
+                    self.players[player].send_system_message("You are not locked to this plot.");
+                }
+            }
+            "create" => {
+                let templates = CONFIG.plot_templates.as_ref();
+                let Some(template) = args.first().and_then(|name| templates?.get(*name)) else {
+                    let mut names: Vec<&str> = templates
+                        .map(|t| t.keys().map(String::as_str).collect())
+                        .unwrap_or_default();
+                    names.sort_unstable();
+                    self.players[player].send_error_message(&format!(
+                        "/plot create <template>. Available templates: {}",
+                        names.join(", ")
+                    ));
+                    return;
+                };
+                if database::is_claimed(plot_x, plot_z).unwrap() {
+                    self.players[player].send_error_message(
+                        "Templates can only be used to create unclaimed plots!",
+                    );
+                    return;
+                }
+                if let Err(err) = self.regenerate(template) {
+                    warn!("Plot template {} is invalid: {}", args[0], err);
+                    self.players[player]
+                        .send_error_message(&format!("This template is invalid: {}", err));
+                    return;
+                }
+                self.claim_plot(plot_x, plot_z, player);
+            }
+            "recover" => {
+                if args.len() == 2 {
+                    let (Ok(x), Ok(z)) = (
+                        parse_relative_coord(args[0], plot_x),
+                        parse_relative_coord(args[1], plot_z),
+                    ) else {
+                        self.players[player].send_error_message("Unable to parse coordinates!");
+                        return;
+                    };
+                    // The plot may have crashed while loading, so it will be recovered on the
+                    // next load instead
+                    super::load_in_safe_mode(x, z);
+                    self.players[player].send_system_message(&format!(
+                        "Plot ({}, {}) will be loaded in safe mode the next time it is loaded.",
+                        x, z
+                    ));
+                    return;
+                } else if !args.is_empty() {
+                    self.players[player].send_error_message("/plot recover [x z]");
+                    return;
+                }
+
+                self.redpiler.discard();
+                self.scoreboard
+                    .set_redpiler_state(&self.players, RedpilerState::Stopped);
+                self.scoreboard
+                    .set_redpiler_options(&self.players, &Default::default());
+                let discarded = self.world.to_be_ticked.len();
+                self.world.to_be_ticked.clear();
+                self.auto_redpiler = false;
+                self.players[player].send_system_message(&format!(
+                    "Plot recovered: discarded redpiler state and {} pending ticks. Automatic redpiler is disabled for this plot.",
+                    discarded
+                ));
+            }
+            _ => self.players[player].send_error_message("Invalid argument for /plot"),
+        }
+    }
+
+    /// Trusts or denies a player on this plot, or resets them to a visitor if `access` is `None`.
+    /// Only the owner can do this, unless the player has `plots.admin.access.other`.
+    fn set_plot_access(&mut self, player: usize, name: &str, access: Option<bool>) {
+        if self.owner.is_none() {
+            self.players[player].send_error_message("This plot is not claimed.");
+            return;
+        }
+        let is_owner = self.owner == Some(self.players[player].uuid);
+        if !is_owner && !self.players[player].has_permission("plots.admin.access.other") {
+            self.players[player].send_no_permission_message();
+            return;
+        }
+        let Some(uuid_str) = database::get_cached_uuid(name) else {
+            self.players[player]
+                .send_error_message(&format!("{} has never joined this server.", name));
+            return;
+        };
+        let uuid = uuid_str.parse::<HyphenatedUUID>().unwrap().0;
+        if self.owner == Some(uuid) {
+            self.players[player]
+                .send_error_message("The owner of a plot can't be trusted or denied.");
+            return;
+        }
+
+        let (plot_x, plot_z) = (self.world.x, self.world.z);
+        self.trusted.remove(&uuid);
+        self.denied.remove(&uuid);
+        let message = match access {
+            Some(true) => {
+                database::set_plot_access(plot_x, plot_z, &uuid_str, true);
+                self.trusted.insert(uuid);
+                format!("{} can now build on this plot.", name)
+            }
+            Some(false) => {
+                database::set_plot_access(plot_x, plot_z, &uuid_str, false);
+                self.denied.insert(uuid);
+                format!("{} can no longer use anything on this plot.", name)
+            }
+            None => {
+                database::remove_plot_access(plot_x, plot_z, &uuid_str);
+                format!("{} is now a visitor on this plot.", name)
+            }
+        };
+        self.players[player].send_system_message(&message);
+    }
+
+    /// Handles `/history inspect` and `/history rollback`, see [`super::history`]
+    fn handle_history_command(&mut self, player: usize, args: &[&str]) {
+        match args {
+            ["inspect" | "i"] => {
+                if !self.players[player].has_permission("history.inspect") {
+                    self.players[player].send_no_permission_message();
+                    return;
+                }
+                let inspecting = !self.players[player].inspecting;
+                self.players[player].inspecting = inspecting;
+                self.players[player].send_system_message(match inspecting {
+                    true => "Inspector enabled. Click blocks to see who changed them.",
+                    false => "Inspector disabled.",
+                });
+            }
+            ["rollback", name, time, radius @ ..] if radius.len() <= 1 => {
+                if !self.players[player].has_permission("history.rollback")
+                    || !self.can_build(player)
+                {
+                    self.players[player].send_no_permission_message();
+                    return;
+                }
+                let Some(secs) = history::parse_duration(time) else {
+                    self.players[player]
+                        .send_error_message("Invalid time, use a time like 30m, 2h or 1d.");
+                    return;
+                };
+                let area = match radius.first().map(|radius| radius.parse::<i32>()) {
+                    None => None,
+                    Some(Ok(radius)) if radius >= 0 => {
+                        let center = self.players[player].pos.block_pos();
+                        let radius = BlockPos::new(radius, radius, radius);
+                        Some((center - radius, center + radius))
+                    }
+                    Some(_) => {
+                        self.players[player].send_error_message("Unable to parse radius");
+                        return;
+                    }
+                };
+                let Some(uuid) = database::get_cached_uuid(name) else {
+                    self.players[player]
+                        .send_error_message(&format!("{} has never joined this server.", name));
+                    return;
+                };
+                let uuid = uuid.parse::<HyphenatedUUID>().unwrap().0;
+                let reverted = self.rollback_edits(uuid, secs, area);
+                self.players[player].send_system_message(&format!(
+                    "Reverted {} blocks that {} changed in the last {}.",
+                    reverted, name, time
+                ));
+            }
+            _ => self.players[player].send_error_message(
+                "Usage: /history inspect | /history rollback <player> <time> [radius]",
+            ),
+        }
+    }
+
+    fn handle_backup_command(&mut self, player: usize, command: &str, args: &[&str]) {
+        let Some(backups) = &self.backups else {
+            self.players[player].send_error_message("Backups are disabled on this server.");
+            return;
+        };
+        // Newest first, which is the order they are listed and numbered in
+        let ids: Vec<u64> = backups.ids().iter().rev().copied().collect();
+        if command != "backups" && !self.can_build(player) {
+            self.players[player].send_no_permission_message();
+            return;
+        }
+
+        match command {
+            "backups" => {
+                if ids.is_empty() {
+                    self.players[player].send_system_message("This plot has no backups yet.");
+                    return;
+                }
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.players[player].send_system_message("Backups of this plot:");
+                for (i, id) in ids.iter().enumerate() {
+                    let age = format_age(now.saturating_sub(*id));
+                    self.players[player].send_system_message(&format!("{}: {}", i + 1, age));
+                }
+            }
+            "backup" => match self.create_backup() {
+                Ok(Some(_)) => {
+                    self.players[player].send_system_message("Made a backup of the plot.")
+                }
+                Ok(None) => self.players[player]
+                    .send_system_message("The plot didn't change since the last backup."),
+                Err(err) => {
+                    error!("Failed to back up plot: {:#}", err);
+                    self.players[player].send_error_message("The plot could not be backed up.");
+                }
+            },
+            _ => {
+                let Some(&id) = args
+                    .first()
+                    .and_then(|idx| idx.parse::<usize>().ok())
+                    .and_then(|idx| ids.get(idx.wrapping_sub(1)))
+                else {
+                    self.players[player]
+                        .send_error_message("Usage: /plot rollback <backup>, see /plot backups");
+                    return;
+                };
+                match self.rollback(id) {
+                    Ok(()) => self.players[player].send_system_message(&format!(
+                        "Rolled back the plot to backup {}. Use /plot rollback 1 to undo it.",
+                        args[0]
+                    )),
+                    Err(err) => {
+                        error!("Failed to roll back plot: {:#}", err);
+                        self.players[player].send_error_message(&format!(
+                            "The plot could not be rolled back: {}",
+                            err
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a command that starts with `/redpiler` or `/rp`
+    fn handle_redpiler_command(&mut self, player: usize, command: &str, args: &[&str]) {
+        let permission_node = match command {
+            "compile" | "c" => "redpiler.compile",
+            "verify" | "v" => "redpiler.verify",
+            "inspect" | "i" => "redpiler.inspect",
+            "reset" | "r" | "cancel" => "redpiler.reset",
+            "timings" | "t" => "redpiler.timings",
+            "record" | "replay" => "redpiler.record",
+            "graph" => "redpiler.graph",
+            "snapshot" => "redpiler.snapshot",
+            "audit" => "redpiler.audit",
+            _ => {
+                self.players[player].send_error_message("Invalid argument for /redpiler");
+                return;
+            }
+        };
+        if !self.players[player].has_permission_or_unset(permission_node) {
+            self.players[player].send_no_permission_message();
+            return;
+        }
+
+        match command {
+            "compile" | "c" => {
+                let start_time = Instant::now();
+                let args = args.join(" ");
+                let options = CompilerOptions::parse(&args);
+
+                if !self.check_compiler_options(player, &options) {
+                    return;
+                }
+
+                if options.optimize {
+                    let msg = "Redpiler optimization is highly unstable and can break builds. Use with caution!";
+                    warn!("{}", msg);
+                    self.players[player].send_system_message(msg);
+                }
+
+                let bounds = if options.selection {
+                    let player = &self.players[player];
+                    let (Some(first_pos), Some(second_pos)) =
+                        (player.first_position, player.second_position)
+                    else {
+                        player.send_error_message("Make a region selection first.");
+                        return;
+                    };
+                    // Parts of the selection outside of the plot are not compiled
+                    let (plot_min, plot_max) = self.world.get_corners();
+                    let min = first_pos.min(second_pos).max(plot_min);
+                    let max = first_pos.max(second_pos).min(plot_max);
+                    if min.max(max) != max {
+                        player.send_error_message("The selection is outside of the plot.");
+                        return;
+                    }
+                    (min, max)
+                } else {
+                    self.world.get_corners()
+                };
+
+                self.reset_redpiler();
+                self.start_redpiler(options, bounds);
+
+                let dead_code = self
+                    .redpiler
+                    .pass_timings()
+                    .iter()
+                    .filter(|timing| timing.pass == "dead-code")
+                    .map(|timing| timing.nodes_removed)
+                    .sum::<usize>();
+                if self.redpiler.is_active() && dead_code > 0 {
+                    self.players[player].send_system_message(&format!(
+                        "Removed {} nodes that never reach an output.",
+                        dead_code
+                    ));
+                }
+
+                if self.redpiler.is_active() && self.world.locational_updates {
+                    let wires =
+                        update_order::count_order_dependent_wires(&self.world, bounds.0, bounds.1);
+                    if wires > 0 {
+                        self.players[player].send_system_message(&format!(
+                            "Warning: {} pieces of dust update multiple components, so the circuit may depend on the locational update order, which redpiler doesn't emulate.",
+                            wires
+                        ));
+                    }
+                }
+
+                debug!("Compile took {:?}", start_time.elapsed());
+            }
+            "verify" | "v" => {
+                if self.redpiler.is_active() {
+                    self.players[player]
+                        .send_error_message("Redpiler has to be reset before verifying.");
+                    return;
+                }
+                // Leading numbers are the amount of ticks and the seed, the rest are flags
+                let mut numbers = args.iter().map_while(|arg| arg.parse::<u64>().ok());
+                let ticks = numbers.next().unwrap_or(1000).min(u32::MAX as u64) as u32;
+                let seed = numbers.next().unwrap_or_else(rand::random);
+                let flags = args
+                    .iter()
+                    .skip_while(|arg| arg.parse::<u64>().is_ok())
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let reference = CompilerOptions::parse(&flags);
+                if !self.check_compiler_options(player, &reference) {
+                    return;
+                }
+                let candidate = CompilerOptions {
+                    optimize: true,
+                    ..CompilerOptions::parse(&flags)
+                };
+                let reference = CompilerOptions {
+                    optimize: false,
+                    ..reference
+                };
+
+                let check = EquivalenceCheck {
+                    reference,
+                    candidate,
+                    ticks,
+                    seed,
+                };
+                let bounds = self.world.get_corners();
+                let pending_ticks = self.world.to_be_ticked.clone();
+                let start_time = Instant::now();
+                let report = match check.run(&mut self.world, bounds, pending_ticks) {
+                    Ok(report) => report,
+                    Err(err) => {
+                        self.players[player]
+                            .send_error_message(&format!("Redpiler could not compile: {}.", err));
+                        return;
+                    }
+                };
+                self.reset_timings();
+
+                let player = &self.players[player];
+                match report.divergence {
+                    Some(divergence) => player.send_error_message(&format!(
+                        "Optimized circuit diverged with seed {}: {}",
+                        seed, divergence
+                    )),
+                    None => player.send_system_message(&format!(
+                        "Optimized circuit matched for {} ticks with {} inputs and {} outputs (seed {}, took {:?}).",
+                        ticks,
+                        report.inputs,
+                        report.outputs,
+                        seed,
+                        start_time.elapsed()
+                    )),
+                }
+            }
+            "inspect" | "i" => {
+                let player = &self.players[player];
+                let pos = worldedit::ray_trace_block(
+                    &self.world,
+                    player.pos,
+                    player.pitch as f64,
+                    player.yaw as f64,
+                    10.0,
+                );
+                let Some(pos) = pos else {
+                    player.send_error_message("Trace failed");
+                    return;
+                };
+                self.redpiler.inspect(pos);
+            }
+            "reset" | "r" => {
+                self.reset_redpiler();
+            }
+            "cancel" => {
+                // Cancelling a running compile is handled while it runs
+                self.players[player].send_error_message("There is no compile running.");
+            }
+            "timings" | "t" => {
+                let player = &self.players[player];
+                let timings = self.redpiler.pass_timings();
+                if timings.is_empty() {
+                    player.send_error_message("No passes ran in the last compile.");
+                    return;
+                }
+                let total: Duration = timings.iter().map(|timing| timing.duration).sum();
+                player.send_system_message(&format!(
+                    "{} passes ran in {:?}:",
+                    timings.len(),
+                    total
+                ));
+                for timing in timings {
+                    let mut message = format!(
+                        "{}: {:?} ({} nodes, {} edges)",
+                        timing.pass, timing.duration, timing.node_count, timing.edge_count
+                    );
+                    if timing.nodes_removed > 0 {
+                        message += &format!(", removed {} nodes", timing.nodes_removed);
+                    }
+                    player.send_system_message(&message);
+                }
+            }
+            "record" => match args.first().copied() {
+                Some("start") => {
+                    self.redpiler.start_recording();
+                    self.players[player].send_system_message(
+                        "Recording the inputs of the circuit. Use /redpiler record stop [name] to save them.",
+                    );
+                }
+                Some("stop") => {
+                    let Some(path) = args.get(1).and_then(|name| recording_path(name)) else {
+                        self.players[player].send_error_message(ERROR_RECORDING_NAME);
+                        return;
+                    };
+                    let Some(recording) = self.redpiler.stop_recording() else {
+                        self.players[player].send_error_message("No inputs are being recorded.");
+                        return;
+                    };
+                    match recording.save(&path) {
+                        Ok(()) => self.players[player].send_system_message(&format!(
+                            "Saved {} inputs over {} ticks.",
+                            recording.events.len(),
+                            recording.length
+                        )),
+                        Err(err) => {
+                            error!("Failed to save recording to {}: {:?}", path.display(), err);
+                            self.players[player]
+                                .send_error_message("There was an error saving the recording.");
+                        }
+                    }
+                }
+                _ => self.players[player]
+                    .send_error_message("Usage: /redpiler record [start|stop <name>]"),
+            },
+            "graph" => self.export_graph(player, args),
+            "snapshot" => self.handle_snapshot_command(player, args),
+            "audit" => match args {
+                [] => self.start_audit(player),
+                ["stop"] => {
+                    self.stop_audit();
+                    self.players[player].send_system_message("The timing audit was stopped.");
+                }
+                _ => self.players[player].send_error_message("Usage: /redpiler audit [stop]"),
+            },
+            "replay" => {
+                let Some(path) = args.first().and_then(|name| recording_path(name)) else {
+                    self.players[player].send_error_message(ERROR_RECORDING_NAME);
+                    return;
+                };
+                if !self.redpiler.is_active() {
+                    self.players[player]
+                        .send_error_message("Redpiler has to be running to replay inputs.");
+                    return;
+                }
+                match Recording::load(&path) {
+                    Ok(recording) => {
+                        self.players[player].send_system_message(&format!(
+                            "Replaying {} inputs over {} ticks.",
+                            recording.events.len(),
+                            recording.length
+                        ));
+                        self.redpiler.start_replay(recording);
+                    }
+                    Err(err) => self.players[player]
+                        .send_error_message(&format!("Could not load the recording: {}", err)),
+                }
+            }
+            _ => self.players[player].send_error_message("Invalid argument for /redpiler"),
+        }
+    }
+
+    /// Compiles the circuit again and starts checking it against a copy of the plot that is
+    /// ticked by the interpreter
+    fn start_audit(&mut self, player: usize) {
+        let Some(options) = self.redpiler.current_flags().cloned() else {
+            self.players[player]
+                .send_error_message("Redpiler has to be running to audit the circuit.");
+            return;
+        };
+        if options.optimize {
+            self.players[player].send_error_message(
+                "Optimized circuits don't keep the ticks of every block, compile without --optimize to audit them.",
+            );
+            return;
+        }
+        // The circuit is compiled the same way, without exporting it again
+        let options = CompilerOptions {
+            export: false,
+            netlist: None,
+            ..options
+        };
+        let bounds = self.redpiler.bounds();
+        // Resetting writes the pending ticks of the circuit back to the world, which the copy
+        // needs to start from the same state
+        self.reset_redpiler();
+        self.audit = Some(TimingAudit::new(&self.world));
+        self.start_redpiler(options, bounds);
+        if !self.redpiler.is_active() {
+            self.audit = None;
+            return;
+        }
+        self.redpiler.watch_inputs(true);
+        self.players[player].send_system_message(
+            "Checking the circuit against vanilla timing after every tick. The first divergence will be reported.",
+        );
+    }
+
+    fn handle_snapshot_command(&mut self, player: usize, args: &[&str]) {
+        let (command, name, disk) = match args {
+            ["list"] => {
+                let mut names: Vec<&String> = self.snapshots.keys().collect();
+                names.sort();
+                let message = match names.is_empty() {
+                    true => "There are no snapshots in memory.".to_owned(),
+                    false => format!(
+                        "Snapshots in memory: {}",
+                        names
+                            .iter()
+                            .map(|name| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                };
+                self.players[player].send_system_message(&message);
+                return;
+            }
+            [command @ ("save" | "load"), name] => (*command, *name, false),
+            [command @ ("save" | "load"), name, "-d"] => (*command, *name, true),
+            _ => {
+                self.players[player]
+                    .send_error_message("Usage: /redpiler snapshot <save|load <name> [-d] | list>");
+                return;
+            }
+        };
+        let Some(path) = snapshot_path(name) else {
+            self.players[player].send_error_message(
+                "Snapshot names are made of letters, numbers, dashes and underscores.",
+            );
+            return;
+        };
+        if !self.redpiler.is_active() {
+            self.players[player]
+                .send_error_message("Redpiler has to be running to snapshot the circuit.");
+            return;
+        }
+
+        if command == "save" {
+            let snapshot = self.redpiler.snapshot().unwrap();
+            let nodes = snapshot.nodes.len();
+            if disk {
+                if let Err(err) = snapshot.save(&path) {
+                    error!("Failed to save snapshot to {}: {:?}", path.display(), err);
+                    self.players[player]
+                        .send_error_message("There was an error saving the snapshot.");
+                    return;
+                }
+            } else {
+                self.snapshots.insert(name.to_owned(), snapshot);
+            }
+            self.players[player]
+                .send_system_message(&format!("Saved the state of {} nodes as {}.", nodes, name));
+            return;
+        }
+
+        let snapshot = match disk {
+            true => match BackendSnapshot::load(&path) {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    self.players[player]
+                        .send_error_message(&format!("Could not load the snapshot: {}", err));
+                    return;
+                }
+            },
+            false => match self.snapshots.get(name) {
+                Some(snapshot) => snapshot.clone(),
+                None => {
+                    self.players[player]
+                        .send_error_message("There is no snapshot with that name in memory.");
+                    return;
+                }
+            },
+        };
+        match self.redpiler.restore(&mut self.world, &snapshot) {
+            Ok(()) => {
+                self.world.flush_block_changes();
+                self.players[player]
+                    .send_system_message(&format!("Restored the circuit to {}.", name));
+            }
+            Err(err) => self.players[player]
+                .send_error_message(&format!("Could not restore the snapshot: {}.", err)),
+        }
+    }
+
+    /// Returns false and tells the player if `options` use an experimental feature that isn't
+    /// enabled on this plot, or name passes that don't exist
+    fn check_compiler_options(&self, player: usize, options: &CompilerOptions) -> bool {
+        let (unknown, passes) = redpiler::check_pass_names::<PlotWorld>(options);
+        if !unknown.is_empty() {
+            self.players[player].send_error_message(&format!(
+                "Unknown redpiler passes: {}. The passes that can be reordered are: {}",
+                unknown.join(", "),
+                passes.join(", ")
+            ));
+            return false;
+        }
+        let experimental = [
+            (options.incremental, Feature::IncrementalRedpiler),
+            (options.wire_delay, Feature::WireDelay),
+        ];
+        for (used, feature) in experimental {
+            if used && !features::is_enabled(feature, self.world.x, self.world.z) {
+                self.players[player].send_error_message(&format!(
+                    "{} is an experimental feature that is disabled on this plot. It can be enabled with `/features enable {}`.",
+                    feature.description(),
+                    feature
+                ));
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Get the block position given by `args`, or the block the player is looking at
+    /// Writes the part of the compiled circuit that can be reached from the player's selection,
+    /// or the part around the targeted block, to a DOT file
+    fn export_graph(&mut self, player: usize, args: &[&str]) {
+        const USAGE: &str =
+            "Usage: /redpiler graph [links] or /redpiler graph near <links> [x y z]";
+        if !self.redpiler.is_active() {
+            self.players[player]
+                .send_error_message("Redpiler has to be running to export its graph.");
+            return;
+        }
+        let (roots, max_links, incoming) = match args {
+            ["near", links, pos @ ..] if pos.is_empty() || pos.len() == 3 => {
+                let (Ok(links), Some(pos)) = (links.parse(), self.get_target_pos(player, pos))
+                else {
+                    self.players[player].send_error_message(USAGE);
+                    return;
+                };
+                (vec![pos], Some(links), true)
+            }
+            [] | [_] => {
+                let max_links = match args.first().map(|links| links.parse()) {
+                    Some(Ok(links)) => Some(links),
+                    Some(Err(_)) => {
+                        self.players[player].send_error_message(USAGE);
+                        return;
+                    }
+                    None => None,
+                };
+                let (Some(first_pos), Some(second_pos)) = (
+                    self.players[player].first_position,
+                    self.players[player].second_position,
+                ) else {
+                    self.players[player].send_error_message("Make a region selection first.");
+                    return;
+                };
+                let roots = Aabb::new(first_pos, second_pos).iter().collect();
+                (roots, max_links, false)
+            }
+            _ => {
+                self.players[player].send_error_message(USAGE);
+                return;
+            }
+        };
+
+        let Some(dot) = self.redpiler.subgraph_dot(&roots, max_links, incoming) else {
+            return;
+        };
+        let path = Path::new(GRAPH_EXPORT_PATH);
+        match std::fs::write(path, dot) {
+            Ok(()) => self.players[player]
+                .send_system_message(&format!("The graph was exported to {}.", path.display())),
+            Err(err) => {
+                error!("Failed to write the graph to {}: {}", path.display(), err);
+                self.players[player].send_error_message("The graph could not be exported.");
+            }
+        }
+    }
+
+    fn get_target_pos(&self, player: usize, args: &[&str]) -> Option<BlockPos> {
+        let player = &self.players[player];
+        if args.len() == 3 {
+            let player_pos = player.pos.block_pos();
+            let x = parse_relative_coord(args[0], player_pos.x).ok()?;
+            let y = parse_relative_coord(args[1], player_pos.y).ok()?;
+            let z = parse_relative_coord(args[2], player_pos.z).ok()?;
+            return Some(BlockPos::new(x, y, z));
+        }
+        worldedit::ray_trace_block(
+            &self.world,
+            player.pos,
+            player.pitch as f64,
+            player.yaw as f64,
+            10.0,
+        )
+    }
+
+    fn handle_features_command(&mut self, player: usize, args: &[&str]) {
+        let (x, z) = (self.world.x, self.world.z);
+        let (enable, name, scope) = match args {
+            [] | ["list"] => {
+                let player = &self.players[player];
+                for feature in Feature::ALL {
+                    let status = match features::enabled_scope(feature, x, z) {
+                        Some(FeatureScope::Server) => "enabled on this server",
+                        Some(FeatureScope::Plot(..)) => "enabled on this plot",
+                        None => "disabled",
+                    };
+                    player.send_system_message(&format!(
+                        "{} ({}): {}",
+                        feature,
+                        feature.description(),
+                        status
+                    ));
+                }
+                return;
+            }
+            [action @ ("enable" | "disable"), name] => {
+                (*action == "enable", *name, FeatureScope::Plot(x, z))
+            }
+            [action @ ("enable" | "disable"), name, "server"] => {
+                (*action == "enable", *name, FeatureScope::Server)
+            }
+            _ => {
+                self.players[player].send_error_message(
+                    "Usage: /features [list|enable|disable] [feature] [server]",
+                );
+                return;
+            }
+        };
+        let Ok(feature) = name.parse::<Feature>() else {
+            let names: Vec<&str> = Feature::ALL.iter().map(|f| f.name()).collect();
+            self.players[player].send_error_message(&format!(
+                "Unknown feature {}, the features are: {}",
+                name,
+                names.join(", ")
+            ));
+            return;
+        };
+
+        let allowed = match scope {
+            FeatureScope::Server => self.players[player].has_permission("mchprs.features.server"),
+            FeatureScope::Plot(..) => {
+                self.owner == Some(self.players[player].uuid)
+                    || self.players[player].has_permission("plots.admin.features.other")
+            }
+        };
+        if !allowed {
+            self.players[player].send_no_permission_message();
+            return;
+        }
+
+        features::set_enabled(feature, scope, enable);
+        if feature == Feature::LocationalUpdates {
+            self.world.locational_updates = features::is_enabled(feature, x, z);
+        }
+        let message = match (enable, scope) {
+            (true, FeatureScope::Server) => format!("{} has been enabled on this server.", feature),
+            (true, FeatureScope::Plot(..)) => format!("{} has been enabled on this plot.", feature),
+            (false, FeatureScope::Server) => {
+                format!("{} has been disabled on this server.", feature)
+            }
+            (false, FeatureScope::Plot(..)) => {
+                format!("{} has been disabled on this plot.", feature)
+            }
+        };
+        self.players[player].send_system_message(&message);
+        if !enable && features::enabled_scope(feature, x, z) == Some(FeatureScope::Server) {
+            self.players[player].send_system_message(&format!(
+                "{} is still enabled for every plot on this server.",
+                feature
+            ));
+        }
+    }
+
+    fn import_song(&mut self, player: usize, file_name: &str) {
+        let is_member = self.is_member(self.players[player].uuid);
+        if !is_member && !self.players[player].has_permission("plots.worldedit.bypass") {
+            self.players[player].send_no_permission_message();
+            return;
+        }
+
+        if !SONG_NAME_REGEX.is_match(file_name) || file_name.contains("..") {
+            self.players[player].send_error_message("Filename is invalid");
+            return;
+        }
+        let path = Path::new("./music").join(file_name);
+        let song = match Song::load(&path) {
+            Ok(song) => song,
+            Err(err) => {
+                self.players[player]
+                    .send_error_message(&format!("There was an error loading the song: {}", err));
+                return;
+            }
+        };
+
+        // The song is built inside of the plot border, starting where the player stands
+        let origin = self.players[player].pos.block_pos();
+        let (first_pos, second_pos) = self.world.get_corners();
+        if origin.x <= first_pos.x
+            || origin.z <= first_pos.z
+            || origin.x >= second_pos.x
+            || origin.z >= second_pos.z
+            || origin.y < 1
+            || origin.y + 2 > second_pos.y
+        {
+            self.players[player].send_error_message("You must stand inside of the plot.");
+            return;
+        }
+        let start_time = Instant::now();
+        if let Err(err) = music::build_song(
+            &mut self.world,
+            &song,
+            origin,
+            second_pos.x - 1,
+            second_pos.z - 1,
+        ) {
+            self.players[player]
+                .send_error_message(&format!("The song could not be built: {}", err));
+            return;
+        }
+
+        let mut message = format!(
+            "Built {} notes lasting {:.1} seconds in {:?}. Flip the lever to play the song.",
+            song.num_notes(),
+            song.length() as f32 / 10.0,
+            start_time.elapsed()
+        );
+        if song.dropped_notes > 0 {
+            message += &format!(
+                " {} notes were left out because their chords were too large.",
+                song.dropped_notes
+            );
+        }
+        self.players[player].send_system_message(&message);
+    }
+
+    /// Copies the components that influence the output at the target position into the
+    /// player's clipboard
+    fn extract_circuit(&mut self, player: usize, args: &[&str]) {
+        if !self.players[player].has_permission("worldedit.clipboard.copy") {
+            self.players[player].send_no_permission_message();
+            return;
+        }
+        let Some(output) = self.get_target_pos(player, args) else {
+            self.players[player].send_error_message("Usage: /extract [x y z]");
+            return;
+        };
+
+        let start_time = Instant::now();
+        let bounds = self.world.get_corners();
+        let Some(positions) = redpiler::cone_of_influence(&self.world, bounds, output) else {
+            self.players[player].send_error_message("There is no redstone component there.");
+            return;
+        };
+        let origin = self.players[player].pos.block_pos();
+        let clipboard = worldedit::create_extract_clipboard(&mut self.world, origin, &positions);
+        self.players[player].worldedit_clipboard = Some(clipboard);
+        self.players[player].send_worldedit_message(&format!(
+            "Copied the {} components that influence the output to your clipboard. ({:?})",
+            positions.len(),
+            start_time.elapsed()
+        ));
+    }
+
+    /// Fills the targeted container so comparators read the requested signal strength from it
+    fn set_container_ss(&mut self, player: usize, args: &[&str]) {
+        let (Some(Ok(ss)), Some(pos)) = (
+            args.first().map(|ss| ss.parse::<u8>()),
+            args.get(1..)
+                .and_then(|pos| self.get_target_pos(player, pos)),
+        ) else {
+            self.players[player].send_error_message("Usage: /ss <0-15> [x y z]");
+            return;
+        };
+        if ss > 15 {
+            self.players[player]
+                .send_error_message("The signal strength must be between 0 and 15.");
+            return;
+        }
+        let ty = match self.world.get_block(pos) {
+            Block::Barrel {} => ContainerType::Barrel,
+            Block::Furnace {} => ContainerType::Furnace,
+            Block::Hopper { .. } => ContainerType::Hopper,
+            _ => {
+                self.players[player]
+                    .send_error_message("You have to be looking at a barrel, furnace or hopper.");
+                return;
+            }
+        };
+        if matches!(self.redpiler.current_flags(), Some(flags) if flags.io_only) {
+            self.players[player].send_error_message(ERROR_IO_ONLY);
+            return;
+        }
+
+        // Containers are constants in the compiled circuit, so it has to be patched or reset
+        let suspended = self.suspend_redpiler();
+        self.world
+            .set_block_entity(pos, BlockEntity::container_with_ss(ty, ss));
+        self.world.mark_changed(pos);
+        redstone::update_surrounding_blocks(&mut self.world, pos);
+        if suspended {
+            self.resume_redpiler();
+        }
+        self.world.flush_block_changes();
+        self.players[player].send_system_message(&format!(
+            "Set the {:?} at {} to signal strength {}.",
+            ty, pos, ss
+        ));
+    }
+
+    /// Changes the time of day of the plot, which daylight detectors are powered for
+    fn set_time(&mut self, player: usize, args: &[&str]) {
+        let parse_time = |time: &str| match time {
+            "day" => Some(1000),
+            "noon" => Some(NOON),
+            "night" => Some(13000),
+            "midnight" => Some(18000),
+            _ => time.parse::<i64>().ok(),
+        };
+        let daytime = match args {
+            [] => {
+                self.players[player].send_system_message(&format!(
+                    "The time of this plot is {}.",
+                    self.world.daytime
+                ));
+                return;
+            }
+            ["set", time] => parse_time(time),
+            ["add", ticks] => ticks
+                .parse::<i64>()
+                .ok()
+                .and_then(|ticks| self.world.daytime.checked_add(ticks)),
+            _ => None,
+        };
+        let Some(daytime) = daytime else {
+            self.players[player].send_error_message(
+                "Usage: /time [set <ticks|day|noon|night|midnight> | add <ticks>]",
+            );
+            return;
+        };
+        if matches!(self.redpiler.current_flags(), Some(flags) if flags.io_only) {
+            self.players[player].send_error_message(ERROR_IO_ONLY);
+            return;
+        }
+
+        self.world.daytime = daytime.rem_euclid(24000);
+        database::set_plot_time(self.world.x, self.world.z, self.world.daytime);
+        let time_update = self.world.time_update_packet();
+        for other_player in &self.players {
+            other_player.client.send_packet(&time_update);
+        }
+
+        // Daylight detectors are constants in the compiled circuit, so it has to be patched or
+        // reset
+        let suspended = self.suspend_redpiler();
+        let (first_pos, second_pos) = self.world.get_corners();
+        let changed = daylight_detector::update_all(&mut self.world, first_pos, second_pos);
+        if suspended {
+            self.resume_redpiler();
+        }
+        self.world.flush_block_changes();
+        self.players[player].send_system_message(&format!(
+            "Set the time to {}, which changed {} daylight detectors.",
+            self.world.daytime, changed
+        ));
+    }
+
+    /// Turns the floating signal strength labels around the player on or off
+    fn set_signal_overlay(&mut self, player: usize, args: &[&str]) {
+        let settings = match args {
+            [] => match self.players[player].signal_overlay {
+                Some(_) => None,
+                None => Some(OverlaySettings::default()),
+            },
+            ["off"] => None,
+            [radius, rate @ ..] if rate.len() <= 1 => {
+                let radius = match radius.parse() {
+                    Ok(radius) if (1..=OverlaySettings::MAX_RADIUS).contains(&radius) => radius,
+                    _ => {
+                        self.players[player].send_error_message(&format!(
+                            "The radius must be between 1 and {}.",
+                            OverlaySettings::MAX_RADIUS
+                        ));
+                        return;
+                    }
+                };
+                let interval = match rate.first().map(|rate| rate.parse()) {
+                    None => OverlaySettings::default().interval,
+                    Some(Ok(rate))
+                        if (1..=OverlaySettings::MAX_UPDATES_PER_SECOND).contains(&rate) =>
+                    {
+                        Duration::from_secs(1) / rate
+                    }
+                    Some(_) => {
+                        self.players[player].send_error_message(&format!(
+                            "The overlay can be updated between 1 and {} times per second.",
+                            OverlaySettings::MAX_UPDATES_PER_SECOND
+                        ));
+                        return;
+                    }
+                };
+                Some(OverlaySettings { radius, interval })
+            }
+            _ => {
+                self.players[player]
+                    .send_error_message("Usage: /overlay [off | <radius> [updates per second]]");
+                return;
+            }
+        };
+
+        self.players[player].signal_overlay = settings;
+        let message = match settings {
+            Some(settings) => format!(
+                "Showing the signal strength of every signal block within {} blocks.",
+                settings.radius
+            ),
+            None => "The signal overlay is now off.".to_owned(),
+        };
+        self.players[player].send_system_message(&message);
+    }
+
+    /// Lists the signal strength of every signal block in the player's selection, both in the
+    /// world and in the compiled circuit, with mismatches shown first
+    fn handle_port_command(&mut self, player: usize, args: &[&str]) {
+        let (plot_x, plot_z) = (self.world.x, self.world.z);
+        match args {
+            ["name", name, pos @ ..] => {
+                let Some(pos) = self.get_target_pos(player, pos) else {
+                    self.players[player].send_error_message("Usage: /port name <name> [x y z]");
+                    return;
+                };
+                if !ports::is_valid_name(name) {
+                    self.players[player].send_error_message(ERROR_PORT_NAME);
+                    return;
+                }
+                let Some(direction) = ports::port_direction(&self.world, pos) else {
+                    self.players[player].send_error_message(
+                        "Only levers, buttons, pressure plates and blocks with a signal can be ports.",
+                    );
+                    return;
+                };
+                if let Some(previous) = self.ports.insert(name.to_string(), pos) {
+                    database::remove_port(plot_x, plot_z, &previous);
+                }
+                database::set_port(plot_x, plot_z, name, pos);
+                self.players[player].send_system_message(&format!(
+                    "{} is now an {:?} port at {}.",
+                    name, direction, pos
+                ));
+            }
+            ["bus", name] => {
+                let (Some(first_pos), Some(second_pos)) = (
+                    self.players[player].first_position,
+                    self.players[player].second_position,
+                ) else {
+                    self.players[player].send_error_message("Make a region selection first.");
+                    return;
+                };
+                let bits = display::bus_bits(&self.world, first_pos, second_pos);
+                // The names of the bits end with their index, so the name is checked with it
+                if !ports::is_valid_name(&format!("{}{}", name, bits.len())) {
+                    self.players[player].send_error_message(ERROR_PORT_NAME);
+                    return;
+                }
+                if bits.is_empty() || bits.len() > 64 {
+                    self.players[player]
+                        .send_error_message("A bus must have between 1 and 64 bits");
+                    return;
+                }
+                for (i, &pos) in bits.iter().enumerate() {
+                    let bit_name = format!("{}{}", name, i);
+                    if let Some(previous) = self.ports.insert(bit_name.clone(), pos) {
+                        database::remove_port(plot_x, plot_z, &previous);
+                    }
+                    database::set_port(plot_x, plot_z, &bit_name, pos);
+                }
+                self.players[player].send_system_message(&format!(
+                    "Named the {} bits of the bus {}0 to {}{}.",
+                    bits.len(),
+                    name,
+                    name,
+                    bits.len() - 1
+                ));
+            }
+            ["remove", name] => {
+                if self.ports.remove(name).is_none() {
+                    self.players[player].send_error_message("There is no port with that name.");
+                    return;
+                }
+                database::remove_port(plot_x, plot_z, name);
+                self.players[player].send_system_message(&format!("Removed the port {}.", name));
+            }
+            ["list"] => {
+                if self.ports.is_empty() {
+                    self.players[player].send_system_message("This plot has no ports.");
+                    return;
+                }
+                let lines: Vec<String> = self
+                    .ports
+                    .iter()
+                    .map(|(name, pos)| {
+                        let direction = ports::port_direction(&self.world, pos)
+                            .map_or("Missing".to_owned(), |direction| format!("{:?}", direction));
+                        format!("{} ({}) at {}", name, direction, pos)
+                    })
+                    .collect();
+                for line in lines {
+                    self.players[player].send_system_message(&line);
+                }
+            }
+            ["get", name] => match self.port_signal_strength(name) {
+                Some(ss) => self.players[player]
+                    .send_system_message(&format!("{} has signal strength {}.", name, ss)),
+                None => {
+                    self.players[player].send_error_message("There is no port with that name.")
+                }
+            },
+            ["set", name, state] => {
+                let powered = match *state {
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        self.players[player]
+                            .send_error_message("Usage: /port set <name> <on|off>");
+                        return;
+                    }
+                };
+                if let Err(err) = self.set_port(name, powered) {
+                    self.players[player].send_error_message(err);
+                }
+            }
+            ["export"] => {
+                let path = PathBuf::from(format!("./ports/p{},{}.json", plot_x, plot_z));
+                let manifest = self.ports.manifest(&self.world);
+                let result = std::fs::create_dir_all("./ports").and_then(|_| {
+                    std::fs::write(&path, serde_json::to_string_pretty(&manifest).unwrap())
+                });
+                match result {
+                    Ok(()) => self.players[player].send_system_message(&format!(
+                        "Exported {} inputs and {} outputs to {}.",
+                        manifest.inputs.len(),
+                        manifest.outputs.len(),
+                        path.display()
+                    )),
+                    Err(err) => {
+                        error!("Failed to export ports to {}: {:?}", path.display(), err);
+                        self.players[player]
+                            .send_error_message("There was an error exporting the ports.");
+                    }
+                }
+            }
+            _ => self.players[player].send_error_message(
+                "Usage: /port <name <name> [x y z] | bus <name> | remove <name> | list | get <name> | set <name> <on|off> | export>",
+            ),
+        }
+    }
+
+    /// Runs the test script `./tests/<name>.json` against the running circuit and reports the
+    /// result of every assertion
+    fn run_test_script(&mut self, player: usize, args: &[&str]) {
+        let [name] = args else {
+            self.players[player].send_error_message("Usage: /test <name>");
+            return;
+        };
+        let Some(path) = named_json_path("./tests", name) else {
+            self.players[player].send_error_message(
+                "Test names are made of letters, numbers, dashes and underscores.",
+            );
+            return;
+        };
+        if !self.redpiler.is_active() {
+            self.players[player]
+                .send_error_message("Redpiler has to be running to test the circuit.");
+            return;
+        }
+        let script = match TestScript::load(&path) {
+            Ok(script) => script,
+            Err(err) => {
+                self.players[player]
+                    .send_error_message(&format!("Could not load the test script: {}", err));
+                return;
+            }
+        };
+        if let Err(err) = script.check_inputs(&self.redpiler) {
+            self.players[player].send_error_message(&format!("Could not run the test: {}", err));
+            return;
+        }
+
+        // The whole plot is ticked, so parts outside of a compiled selection keep up
+        let start_time = Instant::now();
+        let length = script.length();
+        let mut results = Vec::new();
+        for tick in 0..=length {
+            results.extend(script.check(tick, &self.redpiler));
+            for event in script.inputs_at(tick) {
+                self.redpiler.input(event);
+            }
+            if tick < length {
+                self.tick();
+            }
+        }
+        self.redpiler.flush(&mut self.world);
+        self.world.flush_block_changes();
+
+        let failed = results.iter().filter(|result| !result.passed()).count();
+        info!(
+            "Test {} finished after {} ticks, {} of {} assertions failed",
+            name,
+            length,
+            failed,
+            results.len()
+        );
+        let player = &self.players[player];
+        for result in results.iter().take(MAX_TEST_LINES) {
+            if result.passed() {
+                player.send_system_message(&format!("Passed: {}", result));
+            } else {
+                player.send_error_message(&format!("Failed: {}", result));
+            }
+        }
+        if results.len() > MAX_TEST_LINES {
+            player.send_system_message(&format!(
+                "{} more assertions were left out.",
+                results.len() - MAX_TEST_LINES
+            ));
+        }
+        let summary = format!(
+            "{} of {} assertions passed over {} ticks ({:?}).",
+            results.len() - failed,
+            results.len(),
+            length,
+            start_time.elapsed()
+        );
+        if failed == 0 {
+            player.send_system_message(&summary);
+        } else {
+            player.send_error_message(&summary);
+        }
+    }
+
+    fn probe_selection(&mut self, player: usize, args: &[&str]) {
+        let verbose = match args {
+            [] => false,
+            ["-v"] => true,
+            _ => {
+                self.players[player].send_error_message("Usage: /probe [-v]");
+                return;
+            }
+        };
+        let (Some(first_pos), Some(second_pos)) = (
+            self.players[player].first_position,
+            self.players[player].second_position,
+        ) else {
+            self.players[player].send_error_message("Make a region selection first.");
+            return;
+        };
+
+        let mut signals = display::probe(&self.world, &self.redpiler, first_pos, second_pos);
+        if verbose {
+            // The backend logs the full state of every node it has
+            for signal in signals.iter().filter(|signal| signal.redpiler.is_some()) {
+                self.redpiler.inspect(signal.pos);
+            }
+        }
+        signals.sort_by_key(|signal| !signal.is_mismatch());
+        let mismatches = signals.iter().filter(|signal| signal.is_mismatch()).count();
+
+        let player = &self.players[player];
+        player.send_system_message(&format!(
+            "Probed {} signal blocks, {} of them differ between the world and redpiler:",
+            signals.len(),
+            mismatches
+        ));
+        for signal in signals.iter().take(MAX_PROBE_LINES) {
+            let redpiler = match signal.redpiler {
+                Some(ss) => ss.to_string(),
+                None => "-".to_owned(),
+            };
+            let line = format!(
+                "{} {}: world {}, redpiler {}",
+                signal.pos,
+                signal.block.get_name(),
+                signal.world,
+                redpiler
+            );
+            if signal.is_mismatch() {
+                player.send_error_message(&line);
+            } else {
+                player.send_system_message(&line);
+            }
+        }
+        if signals.len() > MAX_PROBE_LINES {
+            player.send_system_message(&format!(
+                "{} more signal blocks were left out.",
+                signals.len() - MAX_PROBE_LINES
+            ));
+        }
+    }
+
+    /// Runs the circuit until the next edge of its clock, or for a number of clock cycles, as
+    /// fast as it can. The world is only updated once it is done.
+    fn fast_forward(&mut self, player: usize, args: &[&str]) {
+        let (until, port) = match args {
+            [] => (Until::Cycles(1), None),
+            ["edge", port @ ..] => (Until::Edge, port.first()),
+            [cycles, port @ ..] => match cycles.parse::<u32>() {
+                Ok(cycles) if cycles > 0 => (Until::Cycles(cycles), port.first()),
+                _ => {
+                    self.players[player]
+                        .send_error_message("Usage: /fastforward [cycles|edge] [clock port]");
+                    return;
                 }
+            },
+        };
+        if !self.redpiler.is_active() {
+            self.players[player]
+                .send_error_message("Redpiler has to be running to fast forward the circuit.");
+            return;
+        }
+        let clock = match port {
+            Some(name) => self.ports.get(name).map(|pos| (name.to_string(), pos)),
+            None => self
+                .ports
+                .iter()
+                .find(|&(name, _)| clock::is_clock_name(name))
+                .map(|(name, pos)| (name.to_owned(), pos)),
+        };
+        let Some((name, pos)) = clock else {
+            self.players[player].send_error_message(
+                "There is no clock port. Name the clock with /port name CLK or give its port.",
+            );
+            return;
+        };
+        let input = ports::port_direction(&self.world, pos) == Some(ports::PortDirection::Input);
+        let mut run = match FastForward::new(&self.redpiler, pos, input, until) {
+            Ok(run) => run,
+            Err(err) => {
+                self.players[player].send_error_message(err);
+                return;
             }
-            "teleport" | "tp" => {
-                if args.len() != 2 {
-                    self.players[player].send_error_message("Invalid number of arguments!");
+        };
+
+        let start_time = Instant::now();
+        let result = loop {
+            match run.step(&mut self.redpiler) {
+                Ok(false) => self.tick(),
+                result => break result,
+            }
+        };
+        // Only the end result is shown, the sounds of every tick on the way are skipped
+        self.world.note_events.clear();
+        self.redpiler.flush(&mut self.world);
+        self.world.flush_block_changes();
+
+        let player = &self.players[player];
+        match result {
+            Err(err) => {
+                player.send_error_message(&format!("Stopped after {} ticks: {}", run.ticks(), err))
+            }
+            Ok(_) => player.send_system_message(&format!(
+                "Fast forwarded {} by {} ({} ticks, {:?}).",
+                name,
+                match until {
+                    Until::Edge => "an edge".to_owned(),
+                    Until::Cycles(1) => "a cycle".to_owned(),
+                    Until::Cycles(cycles) => format!("{} cycles", cycles),
+                },
+                run.ticks(),
+                start_time.elapsed()
+            )),
+        }
+    }
+
+    fn handle_breakpoint_command(&mut self, player: usize, args: &[&str]) {
+        let condition = match args {
+            [state @ ("on" | "off"), pos @ ..] => {
+                let Some(pos) = self.get_target_pos(player, pos) else {
+                    self.players[player].send_error_message("Usage: /breakpoint <on|off> [x y z]");
+                    return;
+                };
+                if !display::is_signal_block(&self.world, pos) {
+                    self.players[player].send_error_message("That block has no signal to watch.");
                     return;
                 }
-
-                let new_plot_x;
-                let new_plot_z;
-                if let Ok(x_arg) = parse_relative_coord(args[0], plot_x) {
-                    new_plot_x = x_arg;
-                } else {
-                    self.players[player].send_error_message("Unable to parse x coordinate!");
+                Condition::Node {
+                    pos,
+                    powered: *state == "on",
+                }
+            }
+            ["bus", value] => {
+                let Some(value) = breakpoints::parse_value(value) else {
+                    self.players[player].send_error_message("Usage: /breakpoint bus <value>");
+                    return;
+                };
+                let (Some(first_pos), Some(second_pos)) = (
+                    self.players[player].first_position,
+                    self.players[player].second_position,
+                ) else {
+                    self.players[player].send_error_message("Make a region selection first.");
+                    return;
+                };
+                let bits = display::bus_bits(&self.world, first_pos, second_pos);
+                if bits.is_empty() || bits.len() > 64 {
+                    self.players[player]
+                        .send_error_message("A bus must have between 1 and 64 bits");
                     return;
                 }
-                if let Ok(z_arg) = parse_relative_coord(args[1], plot_z) {
-                    new_plot_z = z_arg;
-                } else {
-                    self.players[player].send_error_message("Unable to parse z coordinate!");
+                if bits.len() < 64 && value >> bits.len() != 0 {
+                    self.players[player].send_error_message(&format!(
+                        "0x{:X} doesn't fit in a {} bit bus.",
+                        value,
+                        bits.len()
+                    ));
                     return;
                 }
-
-                let center = Plot::get_center(new_plot_x, new_plot_z);
-                self.players[player].teleport(PlayerPos::new(center.0, 64.0, center.1));
+                Condition::Bus { bits, value }
             }
-            "lock" => {
-                if self.locked_players.insert(self.players[player].entity_id) {
-                    let PlotWorld { x, z, .. } = self.world;
-                    let res = format!("Locked to plot ({}, {}). Use '/p unlock' to unlock.", x, z);
-                    self.players[player].send_system_message(&res);
-                } else {
-                    self.players[player]
-                        .send_system_message("You are already locked to this plot.");
+            ["list"] => {
+                let lines: Vec<String> = self
+                    .breakpoints
+                    .iter()
+                    .map(|breakpoint| format!("{}: {}", breakpoint.id, breakpoint.condition))
+                    .collect();
+                let player = &self.players[player];
+                if lines.is_empty() {
+                    player.send_system_message("This plot has no breakpoints.");
                 }
+                for line in lines {
+                    player.send_system_message(&line);
+                }
+                return;
             }
-            "unlock" => {
-                if self.locked_players.remove(&self.players[player].entity_id) {
-                    self.players[player].send_system_message("You are now unlocked.");
+            ["remove", id] => {
+                let removed = id.parse().map_or(false, |id| self.breakpoints.remove(id));
+                if removed {
+                    self.players[player].send_system_message("Removed the breakpoint.");
                 } else {
-                    self.players[player].send_system_message("You are not locked to this plot.");
+                    self.players[player].send_error_message("There is no breakpoint with that id.");
                 }
+                return;
             }
-            _ => self.players[player].send_error_message("Invalid argument for /plot"),
+            ["clear"] => {
+                self.breakpoints.clear();
+                self.players[player].send_system_message("Removed every breakpoint.");
+                return;
+            }
+            _ => {
+                self.players[player].send_error_message(
+                    "Usage: /breakpoint <on|off [x y z] | bus <value> | list | remove <id> | clear>",
+                );
+                return;
+            }
+        };
+
+        let (world, redpiler) = (&self.world, &self.redpiler);
+        let signal_strength = |pos| {
+            redpiler
+                .get_signal_strength(pos)
+                .unwrap_or_else(|| display::world_signal_strength(world, pos))
+        };
+        let message = format!("When {}", condition);
+        let id = self.breakpoints.add(condition, signal_strength);
+        self.players[player].send_system_message(&format!("Added breakpoint {}: {}.", id, message));
+    }
+
+    fn handle_tick_command(&mut self, player: usize, args: &[&str]) {
+        match args {
+            ["freeze"] => {
+                self.frozen = !self.frozen;
+                let message = match self.frozen {
+                    true => "Ticking has been paused, use /tick step [ticks] to advance the plot.",
+                    false => "Ticking has been resumed.",
+                };
+                self.players[player].send_system_message(message);
+            }
+            ["step", ticks @ ..] => {
+                let ticks = match ticks {
+                    [] => Some(1),
+                    [ticks] => ticks.parse::<u32>().ok(),
+                    _ => None,
+                };
+                let Some(ticks) = ticks else {
+                    self.players[player].send_error_message("Usage: /tick step [ticks]");
+                    return;
+                };
+                // Stepping pauses automatic ticking, so the plot stays where it was stepped to
+                self.frozen = true;
+                self.step_ticks(ticks);
+                self.players[player].send_system_message(&format!(
+                    "Stepped {} ticks, ticking is paused until /tick freeze.",
+                    ticks
+                ));
+            }
+            _ => self.players[player].send_error_message("Usage: /tick <freeze | step [ticks]>"),
         }
     }
 
-    /// Handles a command that starts with `/redpiler` or `/rp`
-    fn handle_redpiler_command(&mut self, player: usize, command: &str, args: &[&str]) {
-        match command {
-            "compile" | "c" => {
-                let start_time = Instant::now();
-                let args = args.join(" ");
-                let options = CompilerOptions::parse(&args);
+    /// Ticks a frozen plot, sending the state of the world after every tick
+    fn step_ticks(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+            if self.redpiler.is_active() {
+                self.redpiler.flush(&mut self.world);
+            }
+            self.world.flush_block_changes();
+        }
+        self.displays
+            .update(&self.players, &self.world, &self.redpiler);
+    }
 
-                if options.optimize {
-                    let msg = "Redpiler optimization is highly unstable and can break builds. Use with caution!";
-                    warn!("{}", msg);
-                    self.players[player].send_system_message(msg);
+    fn handle_display_command(&mut self, player: usize, command: &str, args: &[&str]) {
+        match command {
+            "bind" => {
+                let Some(pos) = self.get_target_pos(player, args) else {
+                    self.players[player].send_error_message("/display bind [x y z]");
+                    return;
+                };
+                if !display::is_signal_block(&self.world, pos) {
+                    self.players[player].send_error_message("That block has no signal to display");
+                    return;
                 }
-
-                self.reset_redpiler();
-                self.start_redpiler(options);
-
-                debug!("Compile took {:?}", start_time.elapsed());
-            }
-            "inspect" | "i" => {
-                let player = &self.players[player];
-                let pos = worldedit::ray_trace_block(
-                    &self.world,
-                    player.pos,
-                    player.pitch as f64,
-                    player.yaw as f64,
-                    10.0,
+                self.displays.bind(
+                    &self.players,
+                    player::next_entity_id(),
+                    DisplaySource::Node(pos),
                 );
-                let Some(pos) = pos else {
-                    player.send_error_message("Trace failed");
+            }
+            "bind-bus" => {
+                if args.len() != 1 {
+                    self.players[player].send_error_message("/display bind-bus <name>");
+                    return;
+                }
+                let (Some(first_pos), Some(second_pos)) = (
+                    self.players[player].first_position,
+                    self.players[player].second_position,
+                ) else {
+                    self.players[player].send_error_message("Make a region selection first.");
                     return;
                 };
-                self.redpiler.inspect(pos);
+                let bits = display::bus_bits(&self.world, first_pos, second_pos);
+                if bits.is_empty() || bits.len() > 64 {
+                    self.players[player]
+                        .send_error_message("A bus must have between 1 and 64 bits");
+                    return;
+                }
+                let num_bits = bits.len();
+                let name = args[0].to_string();
+                self.displays.bind(
+                    &self.players,
+                    player::next_entity_id(),
+                    DisplaySource::Bus { name, bits },
+                );
+                self.players[player]
+                    .send_system_message(&format!("Bound display to a {} bit bus", num_bits));
             }
-            "reset" | "r" => {
-                self.reset_redpiler();
+            "unbind" => {
+                let Some(pos) = self.get_target_pos(player, args) else {
+                    self.players[player].send_error_message("/display unbind [x y z]");
+                    return;
+                };
+                if !self.displays.unbind(&self.players, pos) {
+                    self.players[player].send_error_message("There is no display bound there");
+                }
             }
-            _ => self.players[player].send_error_message("Invalid argument for /redpiler"),
+            "clear" => self.displays.clear(&self.players),
+            _ => self.players[player].send_error_message("Invalid argument for /display"),
         }
     }
 
@@ -228,6 +1892,19 @@ impl Plot {
             return false;
         }
 
+        // Commands that affect the whole server
+        let permission_node = match command {
+            "/whitelist" => Some("mchprs.whitelist"),
+            "/stop" => Some("mchprs.stop"),
+            _ => None,
+        };
+        if let Some(node) = permission_node {
+            if !self.players[player].has_permission_or_unset(node) {
+                self.players[player].send_no_permission_message();
+                return false;
+            }
+        }
+
         match command {
             "/whitelist" => match args.as_slice() {
                 ["add", username] => {
@@ -294,18 +1971,12 @@ impl Plot {
                     return false;
                 }
 
-                let tps = if let Ok(tps) = args[0].parse::<u32>() {
-                    if tps > 100000 {
-                        self.players[player]
-                            .send_error_message("The rtps cannot go higher than 100000!");
+                let tps = match parse_rtps(args[0]) {
+                    Ok(tps) => tps,
+                    Err(err) => {
+                        self.players[player].send_error_message(err);
                         return false;
                     }
-                    Tps::Limited(tps)
-                } else if !args[0].is_empty() && "unlimited".starts_with(args[0]) {
-                    Tps::Unlimited
-                } else {
-                    self.players[player].send_error_message("Unable to parse rtps!");
-                    return false;
                 };
 
                 self.sleep_time = sleep_time_for_tps(tps);
@@ -346,6 +2017,57 @@ impl Plot {
                         .send_system_message("Automatic redpiler compilation has been disabled.");
                 }
             }
+            "/features" => self.handle_features_command(player, &args),
+            "/latency" => {
+                let uuid = self.players[player].uuid;
+                if self.latency.toggle(uuid) {
+                    self.players[player].send_system_message(
+                        "Using levers and buttons now reports how long it takes until an output changes.",
+                    );
+                } else {
+                    self.players[player]
+                        .send_system_message("Latency measurements have been disabled.");
+                }
+            }
+            "/profile" => match args.first().copied() {
+                Some("start") => {
+                    self.profiler.start();
+                    self.players[player].send_system_message(
+                        "The profiler has been started. Run /profile to see the results.",
+                    );
+                }
+                Some("stop") => {
+                    self.profiler.stop();
+                    self.players[player].send_system_message("The profiler has been stopped.");
+                }
+                None if self.profiler.is_running() => {
+                    let report = self.profiler.report();
+                    if report.is_empty() {
+                        self.players[player].send_system_message("Nothing has been measured yet.");
+                    }
+                    for section in report {
+                        let message = format!(
+                            "&6{}: &ap50 {:.1?}, p95 {:.1?}, p99 {:.1?} &7({} samples)",
+                            section.section.name(),
+                            section.p50,
+                            section.p95,
+                            section.p99,
+                            section.samples
+                        );
+                        self.players[player]
+                            .send_chat_message(0, &ChatComponent::from_legacy_text(&message));
+                    }
+                }
+                None => {
+                    self.players[player].send_error_message(
+                        "The profiler is not running. Start it with /profile start.",
+                    );
+                }
+                _ => {
+                    self.players[player].send_error_message("Usage: /profile [start|stop]");
+                    return false;
+                }
+            },
             "/teleport" | "/tp" => {
                 if args.len() == 3 {
                     let player_pos = self.players[player].pos;
@@ -406,6 +2128,23 @@ impl Plot {
                 let command = args.remove(0);
                 self.handle_redpiler_command(player, command, &args);
             }
+            "/display" => {
+                if args.is_empty() {
+                    self.players[player]
+                        .send_error_message("Usage: /display <bind|bind-bus|unbind|clear> [args]");
+                    return false;
+                }
+                let command = args.remove(0);
+                self.handle_display_command(player, command, &args);
+            }
+            "/history" => self.handle_history_command(player, &args),
+            "/extract" => self.extract_circuit(player, &args),
+            "/probe" => self.probe_selection(player, &args),
+            "/overlay" => self.set_signal_overlay(player, &args),
+            "/music" => match args.as_slice() {
+                ["import", file_name] => self.import_song(player, file_name),
+                _ => self.players[player].send_error_message("Usage: /music import <midi>"),
+            },
             "/speed" => {
                 if args.len() != 1 {
                     self.players[player].send_error_message("/speed <0-10>");
@@ -457,6 +2196,13 @@ impl Plot {
                 };
                 self.change_player_gamemode(player, gamemode);
             }
+            "/ss" => self.set_container_ss(player, &args),
+            "/test" => self.run_test_script(player, &args),
+            "/fastforward" | "/ff" => self.fast_forward(player, &args),
+            "/breakpoint" | "/bp" => self.handle_breakpoint_command(player, &args),
+            "/tick" => self.handle_tick_command(player, &args),
+            "/port" => self.handle_port_command(player, &args),
+            "/time" => self.set_time(player, &args),
             "/container" => {
                 if args.len() != 2 {
                     self.players[player].send_error_message("Usage: /container [type] [power]");
@@ -508,6 +2254,8 @@ bitflags! {
     }
 }
 
+static SONG_NAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_.-]+$").unwrap());
+
 // In the future a DSL or some type of generation would be much better.
 // For more information, see https://wiki.vg/Command_Data
 /// The `DeclareCommands` packet that is sent when the player joins.
@@ -520,7 +2268,7 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
                 flags: CommandFlags::ROOT.bits() as i8,
                 children: &[
                     1, 4, 5, 6, 11, 12, 14, 16, 18, 19, 20, 21, 22, 23, 24, 26, 29, 31, 32, 34, 36,
-                    47, 49, 53, 60, 61, 63,
+                    47, 49, 53, 60, 61, 63, 65, 67, 68, 74,
                 ],
                 redirect_node: None,
                 name: None,
@@ -1106,6 +2854,114 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
                 parser: Some(Parser::String(0)),
                 suggestions_type: Some("minecraft:ask_server"),
             },
+            // 65: /extract
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[66],
+                redirect_node: None,
+                name: Some("extract"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 66: /extract [x, y, z]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("x, y, z"),
+                parser: Some(Parser::BlockPos),
+                suggestions_type: None,
+            },
+            // 67: /latency
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("latency"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 68: /features
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[69, 70, 71],
+                redirect_node: None,
+                name: Some("features"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 69: /features list
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("list"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 70: /features enable
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[72],
+                redirect_node: None,
+                name: Some("enable"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 71: /features disable
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[72],
+                redirect_node: None,
+                name: Some("disable"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 72: /features [enable|disable] [feature]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[73],
+                redirect_node: None,
+                name: Some("feature"),
+                parser: Some(Parser::String(0)),
+                suggestions_type: None,
+            },
+            // 73: /features [enable|disable] [feature] server
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("server"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 74: /profile
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[75, 76],
+                redirect_node: None,
+                name: Some("profile"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 75: /profile start
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("start"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 76: /profile stop
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("stop"),
+                parser: None,
+                suggestions_type: None,
+            },
         ],
         root_index: 0,
     }
@@ -1,8 +1,12 @@
-use super::{Plot, PlotWorld, PLOT_SECTIONS, PLOT_WIDTH};
-use anyhow::{Context, Result};
+use super::{Plot, PlotSize, PlotWorld, PLOT_SIZE};
+use crate::redstone::daylight_detector::NOON;
+use crate::world::storage::{Chunk, PlotRegion};
+use anyhow::{bail, Context, Result};
 use mchprs_save_data::plot_data::{ChunkData, PlotData, Tps};
+use mchprs_world::TickEntry;
 use once_cell::sync::Lazy;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 // TODO: where to put this?
@@ -15,48 +19,246 @@ pub fn sleep_time_for_tps(tps: Tps) -> Duration {
                 Duration::from_millis(50)
             }
         }
+        Tps::Fractional(_) => Duration::from_millis(50),
         Tps::Unlimited => Duration::ZERO,
     }
 }
 
-pub fn load_plot(path: impl AsRef<Path>) -> Result<PlotData<PLOT_SECTIONS>> {
-    let path = path.as_ref();
-    if path.exists() {
-        Ok(PlotData::load_from_file(path)
-            .with_context(|| format!("error loading plot save file at {}", path.display()))?)
+/// The highest rate `/rtps` accepts
+pub const MAX_RTPS: u32 = 100000;
+
+/// Parses the argument of `/rtps`, which is either a number of ticks per second, which doesn't
+/// have to be whole, or "unlimited"
+pub fn parse_rtps(str: &str) -> Result<Tps, &'static str> {
+    if let Ok(tps) = str.parse::<u32>() {
+        return if tps > MAX_RTPS {
+            Err("The rtps cannot go higher than 100000!")
+        } else {
+            Ok(Tps::Limited(tps))
+        };
+    }
+    if !str.is_empty() && "unlimited".starts_with(str) {
+        return Ok(Tps::Unlimited);
+    }
+    let tps = match str.parse::<f64>() {
+        Ok(tps) if tps.is_finite() && tps >= 0.0 => tps,
+        _ => return Err("Unable to parse rtps!"),
+    };
+    if tps > MAX_RTPS as f64 {
+        return Err("The rtps cannot go higher than 100000!");
+    }
+    let millis = (tps * 1000.0).round() as u32;
+    if millis == 0 && tps > 0.0 {
+        Err("The rtps cannot go lower than 0.001!")
+    } else if millis % 1000 == 0 {
+        Ok(Tps::Limited(millis / 1000))
     } else {
-        Ok(EMPTY_PLOT.clone())
+        Ok(Tps::Fractional(millis))
     }
 }
 
-pub fn empty_plot() -> PlotData<PLOT_SECTIONS> {
-    EMPTY_PLOT.clone()
+/// A plot that was loaded from its save file
+pub struct PlotSave {
+    pub chunks: Vec<Chunk>,
+    pub tps: Tps,
+    pub pending_ticks: Vec<TickEntry>,
 }
 
-static EMPTY_PLOT: Lazy<PlotData<PLOT_SECTIONS>> = Lazy::new(|| {
+/// A plot saved with another plot scale has a different number of chunks, which would end up in
+/// the wrong places
+fn check_chunk_count(num_chunks: usize, size: PlotSize) -> Result<()> {
+    if num_chunks == size.num_chunks() {
+        return Ok(());
+    }
+    // Plots are square and their width is a power of two, so the number of chunks is a power of 4
+    if num_chunks.is_power_of_two() && num_chunks.trailing_zeros() % 2 == 0 {
+        bail!(
+            "the plot was saved with plot scale {}, but the server is running plot scale {}",
+            num_chunks.trailing_zeros() / 2,
+            size.scale
+        );
+    }
+    bail!(
+        "the plot has {} chunks, which doesn't match any plot scale",
+        num_chunks
+    );
+}
+
+impl PlotSave {
+    pub fn from_data(data: PlotData, x: i32, z: i32, size: PlotSize) -> Result<PlotSave> {
+        check_chunk_count(data.chunk_data.len(), size)?;
+        let above = |chunk: &ChunkData| {
+            chunk
+                .sections
+                .iter()
+                .skip(size.sections)
+                .any(|section| section.as_ref().map_or(false, |s| s.block_count > 0))
+        };
+        if data.chunk_data.iter().any(above) {
+            bail!(
+                "the plot has blocks above the world height of {}",
+                size.block_height()
+            );
+        }
+        let chunk_x_offset = x << size.scale;
+        let chunk_z_offset = z << size.scale;
+        let chunks = data
+            .chunk_data
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| {
+                Chunk::load(
+                    chunk_x_offset + i as i32 / size.width(),
+                    chunk_z_offset + i as i32 % size.width(),
+                    c,
+                    size.sections,
+                )
+            })
+            .collect();
+        Ok(PlotSave {
+            chunks,
+            tps: data.tps,
+            pending_ticks: data.pending_ticks,
+        })
+    }
+
+    /// Only reads the block entities of each chunk, the sections are loaded from the region
+    /// file once they are accessed
+    fn from_region(region: PlotRegion, x: i32, z: i32, size: PlotSize) -> Result<PlotSave> {
+        check_chunk_count(region.num_chunks(), size)?;
+        let above = (size.sections..region.num_sections()).any(|section| {
+            (0..region.num_chunks()).any(|chunk| region.section_block_count(chunk, section) > 0)
+        });
+        if above {
+            bail!(
+                "the plot has blocks above the world height of {}",
+                size.block_height()
+            );
+        }
+        let region = Arc::new(region);
+        let chunk_x_offset = x << size.scale;
+        let chunk_z_offset = z << size.scale;
+        let chunks = (0..region.num_chunks())
+            .map(|i| {
+                Chunk::load_lazy(
+                    chunk_x_offset + i as i32 / size.width(),
+                    chunk_z_offset + i as i32 % size.width(),
+                    &region,
+                    i,
+                    size.sections,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PlotSave {
+            chunks,
+            tps: region.tps(),
+            pending_ticks: region.pending_ticks().to_vec(),
+        })
+    }
+}
+
+/// Loads the plot at `x` and `z` in a world with plots of `size`. Plots saved in a world with
+/// a different height get sections added or removed at the top to fit.
+pub fn load_plot(path: impl AsRef<Path>, x: i32, z: i32, size: PlotSize) -> Result<PlotSave> {
+    let path = path.as_ref();
+    if !path.exists() {
+        let data = match size == *PLOT_SIZE {
+            true => EMPTY_PLOT.clone(),
+            false => empty_plot(size),
+        };
+        return PlotSave::from_data(data, x, z, size);
+    }
+    let context = || format!("error loading plot save file at {}", path.display());
+    if PlotRegion::is_region_file(path).with_context(context)? {
+        let region = PlotRegion::open(path).with_context(context)?;
+        return PlotSave::from_region(region, x, z, size).with_context(context);
+    }
+    let data = PlotData::load_from_file(path, size.sections).with_context(context)?;
+    PlotSave::from_data(data, x, z, size).with_context(context)
+}
+
+fn empty_plot(size: PlotSize) -> PlotData {
     let template_path = Path::new("./world/plots/pTEMPLATE");
     if template_path.exists() {
-        PlotData::load_from_file(template_path).expect("failed to read template plot")
+        PlotData::load_from_file(template_path, size.sections)
+            .expect("failed to read template plot")
     } else {
         let mut chunks = Vec::new();
-        for chunk_x in 0..PLOT_WIDTH {
-            for chunk_z in 0..PLOT_WIDTH {
-                chunks.push(Plot::generate_chunk(8, chunk_x, chunk_z));
+        for chunk_x in 0..size.width() {
+            for chunk_z in 0..size.width() {
+                chunks.push(Plot::generate_chunk(8, chunk_x, chunk_z, size));
             }
         }
         let mut world = PlotWorld {
             x: 0,
             z: 0,
+            size,
             chunks,
             to_be_ticked: Vec::new(),
             packet_senders: Vec::new(),
+            note_events: Vec::new(),
+            update_queue: Default::default(),
+            daytime: NOON,
+            locational_updates: false,
         };
-        let chunk_data: Vec<ChunkData<PLOT_SECTIONS>> =
-            world.chunks.iter_mut().map(|c| c.save()).collect();
+        // Generated chunks aren't loaded from a region file, so saving them can't fail
+        let chunk_data: Vec<ChunkData> =
+            world.chunks.iter_mut().map(|c| c.save().unwrap()).collect();
         PlotData {
             tps: Tps::Limited(10),
             chunk_data,
             pending_ticks: Vec::new(),
         }
     }
-});
+}
+
+static EMPTY_PLOT: Lazy<PlotData> = Lazy::new(|| empty_plot(*PLOT_SIZE));
+
+#[test]
+fn rtps_can_be_fractional() {
+    assert_eq!(parse_rtps("20"), Ok(Tps::Limited(20)));
+    assert_eq!(parse_rtps("unl"), Ok(Tps::Unlimited));
+    assert_eq!(parse_rtps("0.5"), Ok(Tps::Fractional(500)));
+    // Whole numbers are stored as whole numbers however they are written
+    assert_eq!(parse_rtps("2.0"), Ok(Tps::Limited(2)));
+    assert!(parse_rtps("0.0001").is_err());
+    assert!(parse_rtps("100000.5").is_err());
+    assert!(parse_rtps("-1").is_err());
+    assert!(parse_rtps("NaN").is_err());
+
+    assert_eq!(Tps::Fractional(500).to_string(), "0.5");
+    assert_eq!(
+        Tps::Fractional(500).tick_duration(),
+        Some(Duration::from_secs(2))
+    );
+    assert_eq!(
+        Tps::Limited(20).tick_duration(),
+        Some(Duration::from_millis(50))
+    );
+    assert_eq!(Tps::Limited(0).tick_duration(), None);
+}
+
+#[test]
+fn plots_from_another_plot_scale_are_not_loaded() {
+    let size = PlotSize {
+        scale: 1,
+        ..PlotSize::default()
+    };
+    let error = |data| {
+        PlotSave::from_data(data, 0, 0, PlotSize::default())
+            .err()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(
+        error(empty_plot(size)),
+        "the plot was saved with plot scale 1, but the server is running plot scale 4"
+    );
+    let mut data = empty_plot(size);
+    data.chunk_data.pop();
+    assert_eq!(
+        error(data),
+        "the plot has 3 chunks, which doesn't match any plot scale"
+    );
+    assert!(PlotSave::from_data(empty_plot(size), 0, 0, size).is_ok());
+}
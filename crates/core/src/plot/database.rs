@@ -1,3 +1,4 @@
+use mchprs_blocks::BlockPos;
 use once_cell::sync::Lazy;
 use rusqlite::{params, Connection};
 use std::sync::{Mutex, MutexGuard};
@@ -46,6 +47,23 @@ pub fn get_cached_username(uuid: String) -> Option<String> {
         .ok()
 }
 
+/// The uuid of the player that last joined with this name
+pub fn get_cached_uuid(name: &str) -> Option<String> {
+    lock()
+        .query_row(
+            "SELECT
+                uuid
+            FROM
+                user
+            WHERE
+                name=?1 COLLATE NOCASE
+            ORDER BY id DESC",
+            params![name],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+}
+
 pub fn get_owned_plots(player: &str) -> Vec<(i32, i32)> {
     let conn = lock();
     let mut stmt = conn
@@ -110,6 +128,196 @@ pub fn ensure_user(uuid: &str, name: &str) {
         .unwrap();
 }
 
+/// Returns true if the feature is enabled for the plot, or for the whole server if `plot` is
+/// `None`
+pub fn is_feature_enabled(name: &str, plot: Option<(i32, i32)>) -> bool {
+    let (is_server, (plot_x, plot_z)) = (plot.is_none(), plot.unwrap_or_default());
+    lock()
+        .query_row(
+            "SELECT EXISTS(
+                SELECT * FROM feature
+                WHERE name = ?1 AND is_server = ?2 AND plot_x = ?3 AND plot_z = ?4
+            )",
+            params![name, is_server, plot_x, plot_z],
+            |row| row.get::<_, bool>(0),
+        )
+        .unwrap_or(false)
+}
+
+pub fn set_feature_enabled(name: &str, plot: Option<(i32, i32)>, enabled: bool) {
+    let (is_server, (plot_x, plot_z)) = (plot.is_none(), plot.unwrap_or_default());
+    let query = if enabled {
+        "INSERT OR IGNORE INTO feature(name, is_server, plot_x, plot_z) VALUES(?1, ?2, ?3, ?4)"
+    } else {
+        "DELETE FROM feature
+            WHERE name = ?1 AND is_server = ?2 AND plot_x = ?3 AND plot_z = ?4"
+    };
+    lock()
+        .execute(query, params![name, is_server, plot_x, plot_z])
+        .unwrap();
+}
+
+/// The time of day that was set for the plot with `/time`, if it was ever changed
+pub fn get_plot_time(plot_x: i32, plot_z: i32) -> Option<i64> {
+    lock()
+        .query_row(
+            "SELECT time FROM plot_time WHERE plot_x = ?1 AND plot_z = ?2",
+            params![plot_x, plot_z],
+            |row| row.get::<_, i64>(0),
+        )
+        .ok()
+}
+
+pub fn set_plot_time(plot_x: i32, plot_z: i32, time: i64) {
+    lock()
+        .execute(
+            "INSERT INTO plot_time(plot_x, plot_z, time)
+                VALUES (?1, ?2, ?3)
+                ON CONFLICT (plot_x, plot_z) DO UPDATE SET time = ?3",
+            params![plot_x, plot_z, time],
+        )
+        .unwrap();
+}
+
+/// The named inputs and outputs of the plot, see [`super::ports`]
+pub fn get_ports(plot_x: i32, plot_z: i32) -> Vec<(String, BlockPos)> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare_cached("SELECT name, x, y, z FROM port WHERE plot_x = ?1 AND plot_z = ?2")
+        .unwrap();
+    stmt.query_map(params![plot_x, plot_z], |row| {
+        Ok((
+            row.get(0)?,
+            BlockPos::new(row.get(1)?, row.get(2)?, row.get(3)?),
+        ))
+    })
+    .unwrap()
+    .map(Result::unwrap)
+    .collect()
+}
+
+pub fn set_port(plot_x: i32, plot_z: i32, name: &str, pos: BlockPos) {
+    lock()
+        .execute(
+            "INSERT INTO port(plot_x, plot_z, name, x, y, z)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (plot_x, plot_z, name) DO UPDATE SET x = ?4, y = ?5, z = ?6",
+            params![plot_x, plot_z, name, pos.x, pos.y, pos.z],
+        )
+        .unwrap();
+}
+
+pub fn remove_port(plot_x: i32, plot_z: i32, name: &str) {
+    lock()
+        .execute(
+            "DELETE FROM port WHERE plot_x = ?1 AND plot_z = ?2 AND name = ?3",
+            params![plot_x, plot_z, name],
+        )
+        .unwrap();
+}
+
+/// The players that are trusted (`true`) or denied (`false`) on the plot, see
+/// [`super::Plot::can_build`]
+pub fn get_plot_access(plot_x: i32, plot_z: i32) -> Vec<(String, bool)> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare_cached("SELECT uuid, trusted FROM plot_access WHERE plot_x = ?1 AND plot_z = ?2")
+        .unwrap();
+    stmt.query_map(params![plot_x, plot_z], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })
+    .unwrap()
+    .map(Result::unwrap)
+    .collect()
+}
+
+pub fn set_plot_access(plot_x: i32, plot_z: i32, uuid: &str, trusted: bool) {
+    lock()
+        .execute(
+            "INSERT INTO plot_access(plot_x, plot_z, uuid, trusted)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (plot_x, plot_z, uuid) DO UPDATE SET trusted = ?4",
+            params![plot_x, plot_z, uuid, trusted],
+        )
+        .unwrap();
+}
+
+pub fn remove_plot_access(plot_x: i32, plot_z: i32, uuid: &str) {
+    lock()
+        .execute(
+            "DELETE FROM plot_access WHERE plot_x = ?1 AND plot_z = ?2 AND uuid = ?3",
+            params![plot_x, plot_z, uuid],
+        )
+        .unwrap();
+}
+
+/// Logs the changes of an edit, see [`super::history`]. `bounds` contains every changed block.
+pub fn add_edit(
+    plot_x: i32,
+    plot_z: i32,
+    uuid: &str,
+    time: i64,
+    action: &str,
+    bounds: (BlockPos, BlockPos),
+    changes: &[u8],
+) {
+    let (min, max) = bounds;
+    lock()
+        .execute(
+            "INSERT INTO edit(
+                plot_x, plot_z, uuid, time, action,
+                min_x, min_y, min_z, max_x, max_y, max_z, changes
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                plot_x, plot_z, uuid, time, action, min.x, min.y, min.z, max.x, max.y, max.z,
+                changes
+            ],
+        )
+        .unwrap();
+}
+
+/// The uuid, time, action and changes of the edits whose bounds contain `pos`, newest first
+pub fn get_edits_at(
+    plot_x: i32,
+    plot_z: i32,
+    pos: BlockPos,
+) -> Vec<(String, i64, String, Vec<u8>)> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT uuid, time, action, changes FROM edit
+                WHERE plot_x = ?1 AND plot_z = ?2
+                    AND ?3 BETWEEN min_x AND max_x
+                    AND ?4 BETWEEN min_y AND max_y
+                    AND ?5 BETWEEN min_z AND max_z
+                ORDER BY id DESC
+                LIMIT 64",
+        )
+        .unwrap();
+    stmt.query_map(params![plot_x, plot_z, pos.x, pos.y, pos.z], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+    })
+    .unwrap()
+    .map(Result::unwrap)
+    .collect()
+}
+
+/// The changes of the edits a player made since `time`, newest first
+pub fn get_edits_by(plot_x: i32, plot_z: i32, uuid: &str, time: i64) -> Vec<Vec<u8>> {
+    let conn = lock();
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT changes FROM edit
+                WHERE plot_x = ?1 AND plot_z = ?2 AND uuid = ?3 AND time >= ?4
+                ORDER BY id DESC",
+        )
+        .unwrap();
+    stmt.query_map(params![plot_x, plot_z, uuid, time], |row| row.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+}
+
 pub fn init() {
     let conn = lock();
 
@@ -144,4 +352,79 @@ pub fn init() {
         [],
     )
     .unwrap();
+
+    // Features that are enabled for the whole server have a plot position of 0, 0
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS feature(
+            name TEXT NOT NULL,
+            is_server BOOLEAN NOT NULL,
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            UNIQUE(name, is_server, plot_x, plot_z)
+        )",
+        [],
+    )
+    .unwrap();
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS plot_time(
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            time INTEGER NOT NULL,
+            UNIQUE(plot_x, plot_z)
+        )",
+        [],
+    )
+    .unwrap();
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS port(
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            x INTEGER NOT NULL,
+            y INTEGER NOT NULL,
+            z INTEGER NOT NULL,
+            UNIQUE(plot_x, plot_z, name)
+        )",
+        [],
+    )
+    .unwrap();
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS plot_access(
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            uuid TEXT NOT NULL,
+            trusted BOOLEAN NOT NULL,
+            UNIQUE(plot_x, plot_z, uuid)
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS edit(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            plot_x INTEGER NOT NULL,
+            plot_z INTEGER NOT NULL,
+            uuid TEXT NOT NULL,
+            time INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            min_x INTEGER NOT NULL,
+            min_y INTEGER NOT NULL,
+            min_z INTEGER NOT NULL,
+            max_x INTEGER NOT NULL,
+            max_y INTEGER NOT NULL,
+            max_z INTEGER NOT NULL,
+            changes BLOB NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS edit_plot ON edit(plot_x, plot_z)",
+        [],
+    )
+    .unwrap();
 }
@@ -0,0 +1,552 @@
+use super::PlotWorld;
+use crate::chat::{ChatComponentBuilder, ColorCode};
+use crate::player::{self, EntityId, PacketSender, Player};
+use crate::redpiler::Compiler;
+use crate::world::World;
+use mchprs_blocks::aabb::Aabb;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_network::packets::clientbound::{
+    CDestroyEntities, CEntityMetadata, CEntityMetadataEntry, CSpawnLivingEntity, ClientBoundPacket,
+};
+use mchprs_network::packets::{PacketEncoder, PacketEncoderExt};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::time::{Duration, Instant};
+
+/// The protocol id of the armor stand entity, used as a floating text holder
+const ARMOR_STAND_ENTITY_TYPE: i32 = 1;
+
+pub enum DisplaySource {
+    /// The signal strength of a single node
+    Node(BlockPos),
+    /// A multi-bit bus, least significant bit first
+    Bus { name: String, bits: Vec<BlockPos> },
+}
+
+/// An invisible armor stand whose name is shown as floating text
+struct FloatingText {
+    entity_id: EntityId,
+    /// Where the text is shown
+    pos: BlockPos,
+    text: String,
+    color: ColorCode,
+}
+
+impl FloatingText {
+    fn spawn_packet(&self) -> CSpawnLivingEntity {
+        CSpawnLivingEntity {
+            entity_id: self.entity_id as i32,
+            entity_uuid: self.entity_id as u128,
+            entity_type: ARMOR_STAND_ENTITY_TYPE,
+            x: self.pos.x as f64 + 0.5,
+            y: self.pos.y as f64 + 0.25,
+            z: self.pos.z as f64 + 0.5,
+            yaw: 0.0,
+            pitch: 0.0,
+            head_pitch: 0.0,
+            velocity_x: 0,
+            velocity_y: 0,
+            velocity_z: 0,
+        }
+    }
+
+    fn metadata_packet(&self, full: bool) -> CEntityMetadata {
+        let name_json = ChatComponentBuilder::new(self.text.clone())
+            .color_code(self.color)
+            .finish()
+            .encode_json();
+        let mut name = vec![1];
+        name.write_string(32767, &name_json);
+
+        let mut metadata = vec![CEntityMetadataEntry {
+            index: 2,
+            metadata_type: 5,
+            value: name,
+        }];
+        if full {
+            metadata.extend([
+                // Invisible
+                CEntityMetadataEntry {
+                    index: 0,
+                    metadata_type: 0,
+                    value: vec![0x20],
+                },
+                // Custom name visible
+                CEntityMetadataEntry {
+                    index: 3,
+                    metadata_type: 7,
+                    value: vec![1],
+                },
+                // No gravity
+                CEntityMetadataEntry {
+                    index: 5,
+                    metadata_type: 7,
+                    value: vec![1],
+                },
+                // Marker armor stand (no hitbox)
+                CEntityMetadataEntry {
+                    index: 15,
+                    metadata_type: 0,
+                    value: vec![0x10],
+                },
+            ]);
+        }
+        CEntityMetadata {
+            entity_id: self.entity_id as i32,
+            metadata,
+        }
+    }
+
+    fn spawn(&self, player: &Player) {
+        player.send_packet(&self.spawn_packet().encode());
+        player.send_packet(&self.metadata_packet(true).encode());
+    }
+
+    /// Changes the text, returning true if it was different
+    fn set_text(&mut self, text: String, color: ColorCode) -> bool {
+        if text == self.text && color == self.color {
+            return false;
+        }
+        self.text = text;
+        self.color = color;
+        true
+    }
+}
+
+fn destroy_packet(entity_ids: impl Iterator<Item = EntityId>) -> PacketEncoder {
+    CDestroyEntities {
+        entity_ids: entity_ids.map(|id| id as i32).collect(),
+    }
+    .encode()
+}
+
+struct SignalDisplay {
+    text: FloatingText,
+    source: DisplaySource,
+}
+
+/// Get the signal strength of a block from the world, used when redpiler is not running
+pub(crate) fn world_signal_strength(world: &PlotWorld, pos: BlockPos) -> u8 {
+    let powered = match world.get_block(pos) {
+        Block::RedstoneWire { wire } => return wire.power,
+        Block::DaylightDetector { power, .. } => return power,
+        Block::RedstoneComparator { .. } => {
+            return match world.get_block_entity(pos) {
+                Some(BlockEntity::Comparator { output_strength }) => *output_strength,
+                _ => 0,
+            }
+        }
+        Block::RedstoneBlock {} => true,
+        Block::RedstoneRepeater { repeater } => repeater.powered,
+        Block::RedstoneTorch { lit } => lit,
+        Block::RedstoneWallTorch { lit, .. } => lit,
+        Block::RedstoneLamp { lit } => lit,
+        Block::Lever { lever } => lever.powered,
+        Block::StoneButton { button } => button.powered,
+        Block::StonePressurePlate { powered } => powered,
+        Block::IronTrapdoor { powered, .. } => powered,
+        _ => false,
+    };
+    if powered {
+        15
+    } else {
+        0
+    }
+}
+
+/// Returns true if the block at `pos` carries a signal that can be shown on a display
+pub fn is_signal_block(world: &PlotWorld, pos: BlockPos) -> bool {
+    matches!(
+        world.get_block(pos),
+        Block::RedstoneWire { .. }
+            | Block::RedstoneComparator { .. }
+            | Block::RedstoneRepeater { .. }
+            | Block::RedstoneTorch { .. }
+            | Block::RedstoneWallTorch { .. }
+            | Block::RedstoneLamp { .. }
+            | Block::Lever { .. }
+            | Block::StoneButton { .. }
+            | Block::StonePressurePlate { .. }
+            | Block::IronTrapdoor { .. }
+            | Block::DaylightDetector { .. }
+    )
+}
+
+/// Returns the signal blocks of a bus. The bus runs along the longest axis of the selection
+/// between `first_pos` and `second_pos`, starting with the least significant bit at the first
+/// position.
+pub fn bus_bits(world: &PlotWorld, first_pos: BlockPos, second_pos: BlockPos) -> Vec<BlockPos> {
+    let diff = second_pos - first_pos;
+    let len = diff.x.abs().max(diff.y.abs()).max(diff.z.abs());
+    let step = if len == 0 {
+        BlockPos::zero()
+    } else if len == diff.x.abs() {
+        BlockPos::new(diff.x.signum(), 0, 0)
+    } else if len == diff.y.abs() {
+        BlockPos::new(0, diff.y.signum(), 0)
+    } else {
+        BlockPos::new(0, 0, diff.z.signum())
+    };
+    (0..=len)
+        .map(|i| first_pos + step * i)
+        .filter(|&pos| is_signal_block(world, pos))
+        .collect()
+}
+
+/// The signal strength of a block in the world and in the compiled circuit
+pub struct ProbedSignal {
+    pub pos: BlockPos,
+    pub block: Block,
+    pub world: u8,
+    /// Only known while redpiler is running and the block is a node of the compiled circuit
+    pub redpiler: Option<u8>,
+}
+
+impl ProbedSignal {
+    pub fn is_mismatch(&self) -> bool {
+        self.redpiler.map_or(false, |ss| ss != self.world)
+    }
+}
+
+/// Reads the signal strength of every signal block between `first_pos` and `second_pos`, both
+/// from the world and from the running redpiler backend
+pub fn probe(
+    world: &PlotWorld,
+    redpiler: &Compiler,
+    first_pos: BlockPos,
+    second_pos: BlockPos,
+) -> Vec<ProbedSignal> {
+    Aabb::new(first_pos, second_pos)
+        .into_iter()
+        .filter(|&pos| is_signal_block(world, pos))
+        .map(|pos| {
+            let block = world.get_block(pos);
+            // Only wires and comparators keep track of their signal strength in the backend
+            let redpiler = match block {
+                Block::RedstoneWire { .. } | Block::RedstoneComparator { .. } => {
+                    redpiler.get_signal_strength(pos)
+                }
+                _ => redpiler
+                    .is_powered(pos)
+                    .map(|powered| if powered { 15 } else { 0 }),
+            };
+            ProbedSignal {
+                pos,
+                block,
+                world: world_signal_strength(world, pos),
+                redpiler,
+            }
+        })
+        .collect()
+}
+
+/// Floating text readouts showing the live signal strength of nodes or buses
+#[derive(Default)]
+pub struct Displays {
+    displays: Vec<SignalDisplay>,
+}
+
+impl Displays {
+    pub fn bind(&mut self, players: &[Player], entity_id: EntityId, source: DisplaySource) {
+        let pos = match &source {
+            DisplaySource::Node(pos) => *pos,
+            DisplaySource::Bus { bits, .. } => bits[bits.len() - 1],
+        };
+        // Only one display can be shown at each position
+        self.unbind(players, pos);
+        let display = SignalDisplay {
+            text: FloatingText {
+                entity_id,
+                pos: BlockPos::new(pos.x, pos.y + 1, pos.z),
+                text: String::new(),
+                color: ColorCode::Aqua,
+            },
+            source,
+        };
+        players.iter().for_each(|p| display.text.spawn(p));
+        self.displays.push(display);
+    }
+
+    /// Remove the display shown above `pos`. Returns true if there was one.
+    pub fn unbind(&mut self, players: &[Player], pos: BlockPos) -> bool {
+        let text_pos = BlockPos::new(pos.x, pos.y + 1, pos.z);
+        let Some(idx) = self.displays.iter().position(|d| d.text.pos == text_pos) else {
+            return false;
+        };
+        let display = self.displays.remove(idx);
+        let destroy = destroy_packet([display.text.entity_id].into_iter());
+        players.iter().for_each(|p| p.send_packet(&destroy));
+        true
+    }
+
+    pub fn clear(&mut self, players: &[Player]) {
+        if self.displays.is_empty() {
+            return;
+        }
+        let destroy = destroy_packet(self.displays.iter().map(|d| d.text.entity_id));
+        players.iter().for_each(|p| p.send_packet(&destroy));
+        self.displays.clear();
+    }
+
+    pub fn add_player(&self, player: &Player) {
+        self.displays.iter().for_each(|d| d.text.spawn(player));
+    }
+
+    pub fn remove_player(&self, player: &Player) {
+        if self.displays.is_empty() {
+            return;
+        }
+        player.send_packet(&destroy_packet(
+            self.displays.iter().map(|d| d.text.entity_id),
+        ));
+    }
+
+    /// Refresh the text of every display, sending metadata only for the ones that changed
+    pub fn update(&mut self, players: &[Player], world: &PlotWorld, redpiler: &Compiler) {
+        let signal_strength = |pos| {
+            redpiler
+                .get_signal_strength(pos)
+                .unwrap_or_else(|| world_signal_strength(world, pos))
+        };
+        for display in &mut self.displays {
+            let text = match &display.source {
+                DisplaySource::Node(pos) => {
+                    let ss = signal_strength(*pos);
+                    format!("{} (0x{:X})", ss, ss)
+                }
+                DisplaySource::Bus { name, bits } => {
+                    let value = bits
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &pos)| signal_strength(pos) > 0)
+                        .fold(0u64, |value, (i, _)| value | (1 << i));
+                    let hex_digits = (bits.len() + 3) / 4;
+                    format!(
+                        "{}: {} (0x{:0width$X})",
+                        name,
+                        value,
+                        value,
+                        width = hex_digits
+                    )
+                }
+            };
+            if display.text.set_text(text, ColorCode::Aqua) {
+                let packet = display.text.metadata_packet(false).encode();
+                players.iter().for_each(|p| p.send_packet(&packet));
+            }
+        }
+    }
+}
+
+/// How a player wants the signal overlay to be shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverlaySettings {
+    /// Signal blocks at most this many blocks away from the player are labelled
+    pub radius: i32,
+    /// The least amount of time between two refreshes of the labels
+    pub interval: Duration,
+}
+
+impl OverlaySettings {
+    pub const MAX_RADIUS: i32 = 16;
+    pub const MAX_UPDATES_PER_SECOND: u32 = 20;
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        OverlaySettings {
+            radius: 8,
+            interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// The labels that are currently shown to one player
+#[derive(Default)]
+struct PlayerOverlay {
+    labels: FxHashMap<BlockPos, FloatingText>,
+    last_update: Option<Instant>,
+}
+
+/// The text and color of the overlay label for a signal block
+fn overlay_label(ss: u8, pending_tick: Option<u32>) -> (String, ColorCode) {
+    let text = match pending_tick {
+        Some(ticks) => format!("{} (tick in {})", ss, ticks),
+        None => ss.to_string(),
+    };
+    let color = if pending_tick.is_some() {
+        ColorCode::Yellow
+    } else if ss > 0 {
+        ColorCode::Red
+    } else {
+        ColorCode::Gray
+    };
+    (text, color)
+}
+
+/// Per-player floating labels above every signal block near the player, showing its signal
+/// strength and when its pending tick happens. Unlike displays, only the player that turned the
+/// overlay on can see them.
+#[derive(Default)]
+pub struct Overlays {
+    overlays: FxHashMap<EntityId, PlayerOverlay>,
+}
+
+impl Overlays {
+    /// The most labels that are shown to a single player
+    const MAX_LABELS: usize = 1024;
+
+    /// Refresh the labels of every player that has the overlay turned on and is due for an update,
+    /// and remove the labels of players that turned it off
+    pub fn update(&mut self, players: &[Player], world: &PlotWorld, redpiler: &Compiler) {
+        let mut scheduled = None;
+        for player in players {
+            let Some(settings) = player.signal_overlay else {
+                if let Some(overlay) = self.overlays.remove(&player.entity_id) {
+                    Self::destroy(player, &overlay);
+                }
+                continue;
+            };
+            let overlay = self.overlays.entry(player.entity_id).or_default();
+            if overlay
+                .last_update
+                .map_or(false, |last| last.elapsed() < settings.interval)
+            {
+                continue;
+            }
+            overlay.last_update = Some(Instant::now());
+
+            let pending_ticks = scheduled.get_or_insert_with(|| {
+                let ticks = match redpiler.scheduled_ticks() {
+                    Some(ticks) => ticks,
+                    None => world
+                        .to_be_ticked
+                        .iter()
+                        .map(|entry| (entry.pos, entry.ticks_left))
+                        .collect(),
+                };
+                // The earliest tick is the one that is shown
+                let mut pending_ticks = FxHashMap::default();
+                for (pos, ticks) in ticks {
+                    let entry = pending_ticks.entry(pos).or_insert(ticks);
+                    *entry = (*entry).min(ticks);
+                }
+                pending_ticks
+            });
+            Self::update_player(player, overlay, settings, world, redpiler, pending_ticks);
+        }
+    }
+
+    fn update_player(
+        player: &Player,
+        overlay: &mut PlayerOverlay,
+        settings: OverlaySettings,
+        world: &PlotWorld,
+        redpiler: &Compiler,
+        pending_ticks: &FxHashMap<BlockPos, u32>,
+    ) {
+        let center = player.pos.block_pos();
+        let radius = BlockPos::new(settings.radius, settings.radius, settings.radius);
+        let (plot_min, plot_max) = world.get_corners();
+        let Some(area) =
+            Aabb::new(center - radius, center + radius).intersection(Aabb::new(plot_min, plot_max))
+        else {
+            Self::destroy(player, overlay);
+            overlay.labels.clear();
+            return;
+        };
+        let positions: Vec<BlockPos> = area
+            .iter()
+            .filter(|&pos| is_signal_block(world, pos))
+            .take(Self::MAX_LABELS)
+            .collect();
+
+        let shown: FxHashSet<BlockPos> = positions.iter().copied().collect();
+        let mut removed = Vec::new();
+        overlay.labels.retain(|pos, label| {
+            let keep = shown.contains(pos);
+            if !keep {
+                removed.push(label.entity_id);
+            }
+            keep
+        });
+        if !removed.is_empty() {
+            player.send_packet(&destroy_packet(removed.into_iter()));
+        }
+
+        for pos in positions {
+            let ss = redpiler
+                .get_signal_strength(pos)
+                .unwrap_or_else(|| world_signal_strength(world, pos));
+            let (text, color) = overlay_label(ss, pending_ticks.get(&pos).copied());
+            match overlay.labels.get_mut(&pos) {
+                Some(label) => {
+                    if label.set_text(text, color) {
+                        player.send_packet(&label.metadata_packet(false).encode());
+                    }
+                }
+                None => {
+                    let label = FloatingText {
+                        entity_id: player::next_entity_id(),
+                        pos: BlockPos::new(pos.x, pos.y + 1, pos.z),
+                        text,
+                        color,
+                    };
+                    label.spawn(player);
+                    overlay.labels.insert(pos, label);
+                }
+            }
+        }
+    }
+
+    fn destroy(player: &Player, overlay: &PlayerOverlay) {
+        if overlay.labels.is_empty() {
+            return;
+        }
+        player.send_packet(&destroy_packet(
+            overlay.labels.values().map(|label| label.entity_id),
+        ));
+    }
+
+    /// Removes the labels of a player that leaves the plot
+    pub fn remove_player(&mut self, player: &Player) {
+        if let Some(overlay) = self.overlays.remove(&player.entity_id) {
+            Self::destroy(player, &overlay);
+        }
+    }
+}
+
+#[test]
+fn probe_shows_where_redpiler_and_the_world_differ() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneWire;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let wire = Block::RedstoneWire {
+        wire: RedstoneWire::default(),
+    };
+    build_lever_line(&mut world, lever_pos, &[wire]);
+    let (first, second) = (BlockPos::new(10, 8, 10), BlockPos::new(12, 8, 10));
+
+    let signals = probe(&world, &Compiler::default(), first, second);
+    assert_eq!(signals.len(), 3);
+    assert!(signals.iter().all(|signal| signal.redpiler.is_none()));
+
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+    // The world is only changed once redpiler is flushed
+    redpiler.on_use_block(lever_pos);
+    redpiler.tick();
+    let signals = probe(&world, &redpiler, first, second);
+    let lever = signals
+        .iter()
+        .find(|signal| signal.pos == lever_pos)
+        .unwrap();
+    assert_eq!((lever.world, lever.redpiler), (0, Some(15)));
+    assert!(lever.is_mismatch());
+
+    redpiler.flush(&mut world);
+    let signals = probe(&world, &redpiler, first, second);
+    assert!(signals.iter().all(|signal| !signal.is_mismatch()));
+}
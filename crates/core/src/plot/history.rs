@@ -0,0 +1,223 @@
+//! The history of the blocks in a plot. Blocks that players place and break and the blocks that
+//! WorldEdit operations change are logged to the plot database with who changed them and when,
+//! so that the history of a block can be inspected and the edits of a player can be rolled back.
+//! All changes of one action are stored together, so a WorldEdit operation is a single entry.
+
+use super::commands::format_age;
+use super::worldedit::update_pasted;
+use super::{database, Plot};
+use crate::config::CONFIG;
+use crate::world::World;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use serde::{Deserialize, Serialize};
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// The most entries that are shown when inspecting a block
+const MAX_INSPECT_ENTRIES: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockChange {
+    pub pos: BlockPos,
+    pub old: u32,
+    pub new: u32,
+    /// The block entity of the old block, so that it can be restored on rollback
+    pub old_entity: Option<BlockEntity>,
+}
+
+/// A block and its block entity before a player changed it
+pub type BlockSnapshot = (BlockPos, u32, Option<BlockEntity>);
+
+fn encode_changes(changes: &[BlockChange]) -> Vec<u8> {
+    let data = bincode::serialize(changes).expect("block changes are always serializable");
+    lz4_flex::compress_prepend_size(&data)
+}
+
+fn decode_changes(data: &[u8]) -> Option<Vec<BlockChange>> {
+    let data = lz4_flex::decompress_size_prepended(data).ok()?;
+    bincode::deserialize(&data).ok()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Parses a duration like `30s`, `10m`, `2h` or `7d` into seconds
+pub fn parse_duration(str: &str) -> Option<i64> {
+    let unit = match str.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let amount: i64 = str[..str.len() - 1].parse().ok()?;
+    amount.checked_mul(unit).filter(|&secs| secs >= 0)
+}
+
+impl Plot {
+    /// Logs the blocks a player changed with `action`, if edit history is enabled
+    pub(super) fn log_edit(&self, player: usize, action: &str, changes: &[BlockChange]) {
+        if !CONFIG.edit_history || changes.is_empty() {
+            return;
+        }
+        let (min, max) = changes
+            .iter()
+            .fold((changes[0].pos, changes[0].pos), |(min, max), c| {
+                (min.min(c.pos), max.max(c.pos))
+            });
+        let uuid = format!("{:032x}", self.players[player].uuid);
+        database::add_edit(
+            self.world.x,
+            self.world.z,
+            &uuid,
+            now(),
+            action,
+            (min, max),
+            &encode_changes(changes),
+        );
+    }
+
+    /// The block at `pos` and its block entity, to be passed to `log_block_changes` after the
+    /// block was changed
+    pub(super) fn block_snapshot(&self, pos: BlockPos) -> BlockSnapshot {
+        let entity = self.world.get_block_entity(pos).cloned();
+        (pos, self.world.get_block_raw(pos), entity)
+    }
+
+    /// Logs the blocks at the positions in `before` that became a different kind of block, so
+    /// that flipping levers and changing the delay of repeaters isn't logged
+    pub(super) fn log_block_changes(
+        &self,
+        player: usize,
+        action: &str,
+        before: Vec<BlockSnapshot>,
+    ) {
+        let changes: Vec<BlockChange> = before
+            .into_iter()
+            .filter_map(|(pos, old, old_entity)| {
+                let new = self.world.get_block_raw(pos);
+                let changed = mem::discriminant(&Block::from_id(old))
+                    != mem::discriminant(&Block::from_id(new));
+                changed.then_some(BlockChange {
+                    pos,
+                    old,
+                    new,
+                    old_entity,
+                })
+            })
+            .collect();
+        self.log_edit(player, action, &changes);
+    }
+
+    /// Sends the player the latest changes of the block at `pos`
+    pub(super) fn show_block_history(&self, player: usize, pos: BlockPos) {
+        let mut lines = Vec::new();
+        for (uuid, time, action, data) in database::get_edits_at(self.world.x, self.world.z, pos) {
+            let Some(changes) = decode_changes(&data) else {
+                error!("Failed to decode the block changes of an edit at {}", pos);
+                continue;
+            };
+            // The last change is the result of the action
+            let Some(change) = changes.iter().rev().find(|c| c.pos == pos) else {
+                continue;
+            };
+            let name = database::get_cached_username(uuid.clone()).unwrap_or(uuid);
+            lines.push(format!(
+                "{}: {} {} {} -> {}",
+                format_age(now().saturating_sub(time).max(0) as u64),
+                name,
+                action,
+                Block::from_id(change.old).get_name(),
+                Block::from_id(change.new).get_name()
+            ));
+            if lines.len() == MAX_INSPECT_ENTRIES {
+                break;
+            }
+        }
+
+        let player = &self.players[player];
+        if lines.is_empty() {
+            player.send_system_message(&format!("No changes were logged at {}.", pos));
+            return;
+        }
+        player.send_system_message(&format!("Changes at {}, newest first:", pos));
+        for line in lines {
+            player.send_system_message(&line);
+        }
+    }
+
+    /// Reverts the blocks that the player with `uuid` changed in the last `secs` seconds inside
+    /// of `area`, together with their block entities, and updates the reverted blocks and their
+    /// neighbors. Blocks that were changed again since are left alone. Returns the amount of
+    /// blocks that were reverted.
+    pub(super) fn rollback_edits(
+        &mut self,
+        uuid: u128,
+        secs: i64,
+        area: Option<(BlockPos, BlockPos)>,
+    ) -> usize {
+        let uuid = format!("{:032x}", uuid);
+        let edits = database::get_edits_by(self.world.x, self.world.z, &uuid, now() - secs);
+        self.reset_redpiler();
+
+        let mut reverted = Vec::new();
+        // The newest edits are reverted first, so blocks that were changed more than once end up
+        // as they were before the oldest change
+        for data in edits {
+            let Some(changes) = decode_changes(&data) else {
+                error!("Failed to decode the block changes of an edit");
+                continue;
+            };
+            for change in changes.into_iter().rev() {
+                let in_area = area.map_or(true, |(min, max)| {
+                    change.pos.min(min) == min && change.pos.max(max) == max
+                });
+                if !in_area || self.world.get_block_raw(change.pos) != change.new {
+                    continue;
+                }
+                self.world.set_block_raw(change.pos, change.old);
+                match change.old_entity {
+                    Some(entity) => self.world.set_block_entity(change.pos, entity),
+                    None => self.world.delete_block_entity(change.pos),
+                }
+                reverted.push(change.pos);
+            }
+        }
+        update_pasted(&mut self.world, &reverted);
+        self.world.flush_block_changes();
+        reverted.len()
+    }
+}
+
+#[test]
+fn block_changes_are_encoded() {
+    let changes = vec![
+        BlockChange {
+            pos: BlockPos::new(1, 2, 3),
+            old: 0,
+            new: 1,
+            old_entity: None,
+        },
+        BlockChange {
+            pos: BlockPos::new(-4, 5, 6),
+            old: 1,
+            new: 0,
+            old_entity: Some(BlockEntity::Comparator { output_strength: 3 }),
+        },
+    ];
+    assert_eq!(decode_changes(&encode_changes(&changes)), Some(changes));
+    assert_eq!(decode_changes(&[1, 2, 3]), None);
+
+    assert_eq!(parse_duration("30s"), Some(30));
+    assert_eq!(parse_duration("2h"), Some(2 * 60 * 60));
+    assert_eq!(parse_duration("h"), None);
+    assert_eq!(parse_duration("-1d"), None);
+    assert_eq!(parse_duration("1y"), None);
+}
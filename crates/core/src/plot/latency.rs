@@ -0,0 +1,127 @@
+//! End-to-end latency measurements for input devices. Players that enabled them with `/latency`
+//! get a breakdown every time they use a lever or button: how long it took until a tick
+//! processed the input, and how long it took until the resulting change of an output block was
+//! sent to them. Together with the ping of the player, this tells network latency apart from
+//! simulation latency.
+//!
+//! The input is timestamped when the plot handles the packet, which is at most one plot update
+//! after the packet arrived.
+
+use super::PlotWorld;
+use crate::player::{PacketSender, Player};
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Traces are dropped if no output changed for this long after the input was used
+const TRACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct LatencyTrace {
+    player: u128,
+    pos: BlockPos,
+    used: Instant,
+    /// The time from using the input to the end of the first tick after it
+    consumed: Option<Duration>,
+    ticks: u32,
+}
+
+#[derive(Default)]
+pub struct LatencyTracker {
+    players: HashSet<u128>,
+    traces: Vec<LatencyTrace>,
+}
+
+fn is_output(block: Block) -> bool {
+    matches!(
+        block,
+        Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. }
+    )
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl LatencyTracker {
+    /// Turns the measurements on or off for a player. Returns true if they are now on.
+    pub fn toggle(&mut self, player: u128) -> bool {
+        if self.players.remove(&player) {
+            self.traces.retain(|trace| trace.player != player);
+            false
+        } else {
+            self.players.insert(player);
+            true
+        }
+    }
+
+    /// Starts a measurement if the player has them turned on
+    pub fn on_use(&mut self, player: u128, pos: BlockPos) {
+        if self.players.contains(&player) {
+            self.traces.push(LatencyTrace {
+                player,
+                pos,
+                used: Instant::now(),
+                consumed: None,
+                ticks: 0,
+            });
+        }
+    }
+
+    pub fn on_tick(&mut self) {
+        for trace in &mut self.traces {
+            if trace.consumed.is_none() {
+                trace.consumed = Some(trace.used.elapsed());
+            }
+            trace.ticks += 1;
+        }
+    }
+
+    /// Reports the measurements that finished, right before the changed blocks in `world` are
+    /// sent to the players
+    pub fn on_flush(&mut self, world: &PlotWorld, players: &[Player]) {
+        if self.traces.is_empty() {
+            return;
+        }
+        let changed = world.changed_positions();
+        let now = Instant::now();
+        self.traces.retain(|trace| {
+            let Some(player) = players.iter().find(|p| p.uuid == trace.player) else {
+                return false;
+            };
+            let output_changed = changed
+                .iter()
+                .any(|&pos| pos != trace.pos && is_output(world.get_block(pos)));
+            let elapsed = now - trace.used;
+            if output_changed {
+                let consumed = match trace.consumed {
+                    Some(consumed) => format!("tick after {:.2}ms", millis(consumed)),
+                    None => "no tick needed".to_string(),
+                };
+                let ping = match player.ping {
+                    Some(ping) => format!("{:.2}ms", millis(ping)),
+                    None => "unknown".to_string(),
+                };
+                player.send_system_message(&format!(
+                    "Latency for {}: {}, output sent after {:.2}ms ({} ticks), ping {}",
+                    trace.pos,
+                    consumed,
+                    millis(elapsed),
+                    trace.ticks,
+                    ping
+                ));
+                false
+            } else if elapsed > TRACE_TIMEOUT {
+                player.send_error_message(&format!(
+                    "No output changed within {}s of using {}",
+                    TRACE_TIMEOUT.as_secs(),
+                    trace.pos
+                ));
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
@@ -1,60 +1,176 @@
+mod audio;
+mod audit;
+mod breakpoints;
 pub mod commands;
 mod data;
 pub mod database;
+pub(crate) mod display;
+mod history;
+mod latency;
 mod monitor;
+mod music;
 mod packet_handlers;
+pub mod ports;
+mod pressure_plates;
+mod profiler;
+mod remote;
 mod scoreboard;
+pub mod template;
 pub mod worldedit;
 
 use crate::chat::ChatComponent;
 use crate::config::CONFIG;
+use crate::features::{self, Feature};
+use crate::metrics::{self, PlotMetrics};
 use crate::player::{EntityId, Gamemode, PacketSender, Player, PlayerPos};
-use crate::redpiler::{Compiler, CompilerOptions};
+use crate::redpiler::{
+    BackendSnapshot, CompileError, CompileProgress, Compiler, CompilerOptions, TaskMonitor,
+};
 use crate::redstone;
+use crate::redstone::daylight_detector::NOON;
+use crate::redstone::updates::UpdateQueue;
 use crate::server::{BroadcastMessage, Message, PrivMessage};
 use crate::utils::HyphenatedUUID;
-use crate::world::storage::Chunk;
+use crate::world::storage::{Chunk, PlotRegion};
 use crate::world::World;
-use anyhow::Context;
+use anyhow::{bail, Context};
 use bus::BusReader;
 use mchprs_blocks::block_entities::BlockEntity;
-use mchprs_blocks::blocks::Block;
+use mchprs_blocks::blocks::{Block, Instrument};
 use mchprs_blocks::{BlockFace, BlockPos};
 use mchprs_network::packets::clientbound::*;
+use mchprs_network::packets::PacketEncoder;
 use mchprs_network::packets::SlotData;
 use mchprs_network::PlayerPacketSender;
-use mchprs_save_data::plot_data::{ChunkData, PlotData, Tps};
+use mchprs_save_data::plot_data::{PlotBackups, PlotData, PlotLoadError, Tps};
 use mchprs_world::{TickEntry, TickPriority};
 use monitor::TimingsMonitor;
+use once_cell::sync::Lazy;
 use scoreboard::RedpilerState;
 use serde_json::json;
+use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
 use tracing::{debug, error, warn};
 
-use self::data::sleep_time_for_tps;
+use self::audio::{NoteEvent, NoteSounds};
+use self::audit::TimingAudit;
+use self::breakpoints::Breakpoints;
+use self::data::{sleep_time_for_tps, PlotSave};
+use self::display::{Displays, Overlays};
+use self::latency::LatencyTracker;
+use self::ports::Ports;
+use self::pressure_plates::PressedPlates;
+use self::profiler::{Profiler, Section};
+use self::remote::RemoteSubscriptions;
 use self::scoreboard::Scoreboard;
+use self::template::PlotTemplate;
+use self::worldedit::PatternParseResult;
+
+/// The size of the plots of a world. The world the server runs has the size set by
+/// `plot_scale` and `world_height` in the config, see [`PLOT_SIZE`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlotSize {
+    /// The width of a plot (2^n)
+    pub scale: u32,
+    /// The height of the world in sections
+    pub sections: usize,
+}
+
+impl PlotSize {
+    pub const MAX_SCALE: u32 = 8;
+    pub const MAX_SECTIONS: usize = 127;
+
+    /// A plot size from the `plot_scale` and `world_height` options, where the height is in
+    /// blocks and rounded up to a whole section
+    pub fn from_config(scale: i64, height: i64) -> PlotSize {
+        let size = PlotSize {
+            scale: scale.clamp(0, PlotSize::MAX_SCALE as i64) as u32,
+            sections: ((height + 15) / 16).clamp(1, PlotSize::MAX_SECTIONS as i64) as usize,
+        };
+        if size.scale as i64 != scale || size.block_height() as i64 != height {
+            warn!(
+                "Plot scale {} and world height {} are out of range, using plot scale {} and world height {} instead",
+                scale,
+                height,
+                size.scale,
+                size.block_height()
+            );
+        }
+        size
+    }
 
-/// The width of a plot (2^n)
-pub const PLOT_SCALE: u32 = 4;
+    /// The width of a plot counted in chunks
+    pub fn width(self) -> i32 {
+        1 << self.scale
+    }
 
-/// The width of a plot counted in chunks
-pub const PLOT_WIDTH: i32 = 2i32.pow(PLOT_SCALE);
-/// The plot width in blocks
-pub const PLOT_BLOCK_WIDTH: i32 = PLOT_WIDTH * 16;
-pub const NUM_CHUNKS: usize = PLOT_WIDTH.pow(2) as usize;
+    /// The plot width in blocks
+    pub fn block_width(self) -> i32 {
+        self.width() * 16
+    }
 
-/// The height of the world in sections (Default: 16, Max: 127)
-pub const PLOT_SECTIONS: usize = 16;
-/// The plot height in blocks
-pub const PLOT_BLOCK_HEIGHT: i32 = PLOT_SECTIONS as i32 * 16;
+    pub fn num_chunks(self) -> usize {
+        self.width().pow(2) as usize
+    }
+
+    /// The plot height in blocks
+    pub fn block_height(self) -> i32 {
+        self.sections as i32 * 16
+    }
+}
+
+impl Default for PlotSize {
+    fn default() -> PlotSize {
+        PlotSize {
+            scale: 4,
+            sections: 16,
+        }
+    }
+}
+
+/// The size of the plots of the world the server runs
+pub static PLOT_SIZE: Lazy<PlotSize> =
+    Lazy::new(|| PlotSize::from_config(CONFIG.plot_scale, CONFIG.world_height));
 
 pub const WORLD_SEND_RATE: Duration = Duration::from_millis(15);
+/// The most a plot with a limited rate catches up on after it fell behind. Anything further
+/// behind is skipped, so that a long stall doesn't turn into a long burst of ticks.
+const MAX_CATCH_UP: Duration = Duration::from_secs(1);
+/// How often cold sections are unloaded
+const UNLOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// The amount of sections loaded by every running plot together, which is kept under the
+/// `section_cache_size` option
+static LOADED_SECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// When set, every plot is loaded in safe mode
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+/// Plots that will be loaded in safe mode the next time they are loaded
+static SAFE_MODE_PLOTS: Lazy<Mutex<HashSet<(i32, i32)>>> = Lazy::new(Default::default);
+
+/// Load all plots in safe mode. In safe mode, plots are loaded without restoring pending
+/// ticks and redpiler will not be started automatically.
+pub fn enable_safe_mode() {
+    SAFE_MODE.store(true, AtomicOrdering::Relaxed);
+}
+
+/// Load the plot at the given plot coordinates in safe mode the next time it is loaded
+pub fn load_in_safe_mode(x: i32, z: i32) {
+    SAFE_MODE_PLOTS.lock().unwrap().insert((x, z));
+}
+
+/// Commands aren't handled while redpiler is compiling, so `/redpiler cancel` is picked out of
+/// the chat messages that arrive during the compile
+pub(crate) fn is_cancel_command(message: &str) -> bool {
+    let mut args = message.split_whitespace();
+    matches!(args.next(), Some("/redpiler" | "/rp")) && args.next() == Some("cancel")
+}
 
 pub struct Plot {
     pub world: PlotWorld,
@@ -78,6 +194,17 @@ pub struct Plot {
     last_player_time: Instant,
     /// The last time the world changes were sent to the player
     last_world_send_time: Instant,
+    /// The last time cold sections were unloaded
+    last_unload_time: Instant,
+    /// The last time a backup was made, see `backup_if_due`
+    last_backup_time: Instant,
+    /// Only opened when backups are enabled
+    backups: Option<PlotBackups>,
+    /// The amount of sections this plot had loaded as of the last time sections were unloaded,
+    /// which is part of `LOADED_SECTIONS`
+    loaded_sections: usize,
+    /// The last time the metrics of the plot were published
+    last_metrics_time: Instant,
     /// The duration we should sleep for after every update
     sleep_time: Duration,
     /// When this is false, the update loop will end and the thread will stop.
@@ -88,32 +215,60 @@ pub struct Plot {
     auto_redpiler: bool,
 
     owner: Option<u128>,
+    /// Players that the owner allowed to build in the plot
+    trusted: HashSet<u128>,
+    /// Players that the owner doesn't allow to use anything in the plot
+    denied: HashSet<u128>,
     async_rt: Runtime,
     scoreboard: Scoreboard,
+    displays: Displays,
+    overlays: Overlays,
+    pressure_plates: PressedPlates,
+    ports: Ports,
+    remote_subscriptions: RemoteSubscriptions,
+    note_sounds: NoteSounds,
+    latency: LatencyTracker,
+    profiler: Profiler,
+    breakpoints: Breakpoints,
+    /// Checks the running circuit against the interpreter after every tick
+    audit: Option<TimingAudit>,
+    /// Snapshots of the running circuit kept in memory by name
+    snapshots: HashMap<String, BackendSnapshot>,
+    /// Whether automatic ticking is paused, with `/tick freeze` or after a breakpoint was hit
+    frozen: bool,
 }
 
 pub struct PlotWorld {
     pub x: i32,
     pub z: i32,
+    pub size: PlotSize,
     pub chunks: Vec<Chunk>,
     pub to_be_ticked: Vec<TickEntry>,
     pub packet_senders: Vec<PlayerPacketSender>,
+    /// The notes played since the last update, which still have to be sent to players
+    pub note_events: Vec<NoteEvent>,
+    pub update_queue: UpdateQueue,
+    /// The time of day in game ticks, which only changes with `/time`
+    pub daytime: i64,
+    /// Whether the `locational-updates` feature is enabled for the plot
+    pub locational_updates: bool,
 }
 
 impl PlotWorld {
     fn get_chunk_index_for_chunk(&self, chunk_x: i32, chunk_z: i32) -> usize {
-        let local_x = chunk_x - self.x * PLOT_WIDTH;
-        let local_z = chunk_z - self.z * PLOT_WIDTH;
-        (local_x * PLOT_WIDTH + local_z).unsigned_abs() as usize
+        let width = self.size.width();
+        let local_x = chunk_x - self.x * width;
+        let local_z = chunk_z - self.z * width;
+        (local_x * width + local_z).unsigned_abs() as usize
     }
 
     fn get_chunk_index_for_block(&self, block_x: i32, block_z: i32) -> Option<usize> {
-        let chunk_x = (block_x - (self.x * PLOT_BLOCK_WIDTH)) >> 4;
-        let chunk_z = (block_z - (self.z * PLOT_BLOCK_WIDTH)) >> 4;
-        if chunk_x >= PLOT_WIDTH || chunk_z >= PLOT_WIDTH {
+        let chunk_x = (block_x - (self.x * self.size.block_width())) >> 4;
+        let chunk_z = (block_z - (self.z * self.size.block_width())) >> 4;
+        if chunk_x >= self.size.width() || chunk_z >= self.size.width() {
             return None;
         }
-        Some(((chunk_x << PLOT_SCALE) + chunk_z).unsigned_abs() as usize)
+        Some(((chunk_x << self.size.scale) + chunk_z).unsigned_abs() as usize)
     }
 
     fn flush_block_changes(&mut self) {
@@ -128,16 +283,163 @@ impl PlotWorld {
         }
     }
 
+    /// Marks the block at `pos` as changed when only its block entity changed, so incremental
+    /// redpiler compiles pick it up
+    pub fn mark_changed(&mut self, pos: BlockPos) {
+        let Some(chunk_index) = self.get_chunk_index_for_block(pos.x, pos.z) else {
+            return;
+        };
+        if !(0..self.size.block_height()).contains(&pos.y) {
+            return;
+        }
+        self.chunks[chunk_index].mark_changed(
+            (pos.x & 0xF) as u32,
+            pos.y as u32,
+            (pos.z & 0xF) as u32,
+        );
+    }
+
+    /// Returns the positions of the blocks that changed since block changes were last flushed
+    pub fn changed_positions(&self) -> Vec<BlockPos> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.changed_positions())
+            .collect()
+    }
+
+    /// Points every chunk at the region file the plot was just saved to
+    pub fn attach_region(&mut self, region: Arc<PlotRegion>) {
+        for (i, chunk) in self.chunks.iter_mut().enumerate() {
+            chunk.attach_region(&region, i);
+        }
+    }
+
+    pub fn loaded_sections(&self) -> usize {
+        self.chunks.iter().map(|c| c.loaded_sections()).sum()
+    }
+
+    /// Unloads the sections that were used least recently until at most `max_loaded` sections
+    /// are loaded. Sections that were accessed since the last call are never unloaded. Returns
+    /// the amount of sections that were unloaded.
+    pub fn unload_cold_sections(&mut self, max_loaded: usize) -> usize {
+        let to_unload = self.loaded_sections().saturating_sub(max_loaded);
+        let mut cold: Vec<(u8, usize, usize)> = Vec::new();
+        for (chunk_idx, chunk) in self.chunks.iter().enumerate() {
+            cold.extend(chunk.age_sections().map(|(y, age)| (age, chunk_idx, y)));
+        }
+        cold.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        cold.truncate(to_unload);
+        for &(_, chunk_idx, y) in &cold {
+            self.chunks[chunk_idx].unload_section(y);
+        }
+        cold.len()
+    }
+
+    /// The packet that stops the sky of clients at the time of day of the plot
+    pub fn time_update_packet(&self) -> PacketEncoder {
+        // A negative time of day tells the client not to advance it on its own, but that
+        // doesn't work for 0
+        let time_of_day = match self.daytime.rem_euclid(24000) {
+            0 => -24000,
+            time => -time,
+        };
+        CTimeUpdate {
+            world_age: 0,
+            time_of_day,
+        }
+        .encode()
+    }
+
     pub fn get_corners(&self) -> (BlockPos, BlockPos) {
-        const W: i32 = PLOT_BLOCK_WIDTH;
-        let first_pos = BlockPos::new(self.x * W, 0, self.z * W);
+        let w = self.size.block_width();
+        let first_pos = BlockPos::new(self.x * w, 0, self.z * w);
         let second_pos = BlockPos::new(
-            (self.x + 1) * W - 1,
-            PLOT_BLOCK_HEIGHT - 1,
-            (self.z + 1) * W - 1,
+            (self.x + 1) * w - 1,
+            self.size.block_height() - 1,
+            (self.z + 1) * w - 1,
         );
         (first_pos, second_pos)
     }
+
+    /// Loads the plot at `x` and `z` from the world folder without running it, for tools that
+    /// edit plots while the server is stopped. Plots that were never saved are generated.
+    pub(crate) fn open(x: i32, z: i32) -> anyhow::Result<(PlotWorld, Tps)> {
+        let path = format!("./world/plots/p{},{}", x, z);
+        let save = data::load_plot(path, x, z, *PLOT_SIZE)?;
+        let world = PlotWorld {
+            x,
+            z,
+            size: *PLOT_SIZE,
+            chunks: save.chunks,
+            to_be_ticked: save.pending_ticks,
+            packet_senders: Vec::new(),
+            note_events: Vec::new(),
+            update_queue: Default::default(),
+            daytime: NOON,
+            locational_updates: false,
+        };
+        Ok((world, save.tps))
+    }
+
+    /// Saves the plot to its file in the world folder, after which its sections are loaded
+    /// from the new file
+    pub(crate) fn save_to_disk(&mut self, tps: Tps) -> anyhow::Result<()> {
+        let chunk_data = self
+            .chunks
+            .iter_mut()
+            .map(|c| c.save())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("error reading plot {},{}", self.x, self.z))?;
+        let data = PlotData {
+            tps,
+            chunk_data,
+            pending_ticks: self.to_be_ticked.clone(),
+        };
+        // The old file can't be replaced while it is mapped on Windows, so the sections that
+        // are still loaded from it are loaded from the saved data instead, which unmaps it
+        for (chunk, data) in self.chunks.iter_mut().zip(&data.chunk_data) {
+            chunk.detach_region(data);
+        }
+        std::fs::create_dir_all("./world/plots")?;
+        let path = format!("./world/plots/p{},{}", self.x, self.z);
+        data.save_to_file(&path)
+            .with_context(|| format!("error saving plot {},{}", self.x, self.z))?;
+        // The saved sections can now be unloaded and loaded from the new file
+        match PlotRegion::open(&path) {
+            Ok(region) => self.attach_region(Arc::new(region)),
+            Err(err) => error!("Failed to open plot region {}: {}", path, err),
+        }
+        Ok(())
+    }
+
+    /// Runs a single tick of the regular (non-redpiler) redstone implementation.
+    /// A copy of the world with its pending ticks, which isn't sent to any player
+    pub(crate) fn shadow_copy(&self) -> PlotWorld {
+        PlotWorld {
+            x: self.x,
+            z: self.z,
+            size: self.size,
+            chunks: self.chunks.iter().map(Chunk::duplicate).collect(),
+            to_be_ticked: self.to_be_ticked.clone(),
+            packet_senders: Vec::new(),
+            note_events: Vec::new(),
+            update_queue: Default::default(),
+            daytime: self.daytime,
+            locational_updates: self.locational_updates,
+        }
+    }
+
+    pub(crate) fn tick_interpreted(&mut self) {
+        self.to_be_ticked
+            .sort_by_key(|e| (e.ticks_left, e.tick_priority));
+        for pending in &mut self.to_be_ticked {
+            pending.ticks_left = pending.ticks_left.saturating_sub(1);
+        }
+        while self.to_be_ticked.first().map_or(1, |e| e.ticks_left) == 0 {
+            let entry = self.to_be_ticked.remove(0);
+            redstone::tick(self.get_block(entry.pos), self, entry.pos);
+        }
+    }
 }
 
 impl World for PlotWorld {
@@ -149,7 +451,7 @@ impl World for PlotWorld {
         };
 
         // Check to see if block is within height limit
-        if pos.y >= PLOT_BLOCK_HEIGHT || pos.y < 0 {
+        if pos.y >= self.size.block_height() || pos.y < 0 {
             return false;
         }
 
@@ -233,25 +535,115 @@ impl World for PlotWorld {
     fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
         self.to_be_ticked.iter().any(|e| e.pos == pos)
     }
+
+    fn play_note(&mut self, pos: BlockPos, instrument: Instrument, note: u32) {
+        self.note_events.push(NoteEvent {
+            pos,
+            instrument,
+            note,
+        });
+    }
+
+    fn update_queue(&mut self) -> &mut UpdateQueue {
+        &mut self.update_queue
+    }
+
+    fn daytime(&self) -> i64 {
+        self.daytime
+    }
+
+    fn locational_updates(&self) -> bool {
+        self.locational_updates
+    }
 }
 
 impl Plot {
     fn tick(&mut self) {
+        self.tick_world();
+        if self.audit.is_some() {
+            self.check_audit();
+        }
+        if !self.breakpoints.is_empty() {
+            self.check_breakpoints();
+        }
+    }
+
+    /// Stops the timing audit at the first divergence, or when redpiler stops
+    fn check_audit(&mut self) {
+        let Some(audit) = &mut self.audit else {
+            return;
+        };
+        if !self.redpiler.is_active() {
+            self.stop_audit();
+            return;
+        }
+        if let Some(divergence) = audit.tick(&mut self.redpiler) {
+            warn!("Timing audit diverged: {}", divergence);
+            let message = format!("Redpiler diverged from vanilla timing: {}.", divergence);
+            for player in &self.players {
+                player.send_error_message(&message);
+            }
+            self.stop_audit();
+        }
+    }
+
+    fn stop_audit(&mut self) {
+        self.audit = None;
+        self.redpiler.watch_inputs(false);
+    }
+
+    fn tick_world(&mut self) {
         self.timings.tick();
+        self.latency.on_tick();
+        self.world.update_queue.start_tick();
+        self.tick_pressure_plates();
         if self.redpiler.is_active() {
+            let start = self.profiler.begin();
             self.redpiler.tick();
-            return;
+            self.profiler.record(Section::RedpilerTick, start);
+            // When only a selection is compiled, the rest of the plot is still interpreted
+            if !self
+                .redpiler
+                .current_flags()
+                .map_or(false, |flags| flags.selection)
+            {
+                return;
+            }
         }
 
-        self.world
-            .to_be_ticked
-            .sort_by_key(|e| (e.ticks_left, e.tick_priority));
-        for pending in &mut self.world.to_be_ticked {
-            pending.ticks_left = pending.ticks_left.saturating_sub(1);
+        let start = self.profiler.begin();
+        self.world.tick_interpreted();
+        self.profiler.record(Section::WorldTick, start);
+    }
+
+    /// Freezes the plot if a breakpoint was hit in the last tick
+    fn check_breakpoints(&mut self) {
+        let (world, redpiler) = (&self.world, &self.redpiler);
+        let signal_strength = |pos| {
+            redpiler
+                .get_signal_strength(pos)
+                .unwrap_or_else(|| display::world_signal_strength(world, pos))
+        };
+        let messages: Vec<String> = self
+            .breakpoints
+            .check(signal_strength)
+            .iter()
+            .map(|breakpoint| {
+                format!(
+                    "Hit breakpoint {}: {}. Ticking is paused, use /tick step [ticks] or /tick freeze to resume.",
+                    breakpoint.id, breakpoint.condition
+                )
+            })
+            .collect();
+        if messages.is_empty() {
+            return;
         }
-        while self.world.to_be_ticked.first().map_or(1, |e| e.ticks_left) == 0 {
-            let entry = self.world.to_be_ticked.remove(0);
-            redstone::tick(self.world.get_block(entry.pos), &mut self.world, entry.pos);
+        self.frozen = true;
+        self.redpiler.flush(&mut self.world);
+        for message in messages {
+            for player in &self.players {
+                player.send_error_message(&message);
+            }
         }
     }
 
@@ -292,25 +684,40 @@ impl Plot {
         ));
     }
 
-    fn on_player_move(&mut self, player_idx: usize, old: PlayerPos, new: PlayerPos) {
-        let old_block = old.block_pos();
-        let new_block = new.block_pos();
-
-        if let Block::StonePressurePlate { powered: true } = self.world.get_block(old_block) {
-            if !self.are_players_on_block(old_block) {
-                self.set_pressure_plate(old_block, false);
+    fn on_player_move(&mut self, player_idx: usize, new: PlayerPos) {
+        if !pressure_plates::presses_plates(&self.players[player_idx], &self.denied) {
+            return;
+        }
+        for plate in pressure_plates::plates_under(&self.world, new) {
+            if self.pressure_plates.press(plate) {
+                self.set_pressure_plate(plate, true);
             }
         }
+    }
 
-        if let Block::StonePressurePlate { powered: false } = self.world.get_block(new_block) {
-            if self.players[player_idx].on_ground {
-                self.set_pressure_plate(new_block, true);
+    /// Releases the pressed plates that nobody is standing on anymore when they are checked
+    fn tick_pressure_plates(&mut self) {
+        for plate in self.pressure_plates.tick() {
+            if !matches!(
+                self.world.get_block(plate),
+                Block::StonePressurePlate { .. }
+            ) {
+                continue;
+            }
+            let pressed = self.players.iter().any(|player| {
+                pressure_plates::presses_plates(player, &self.denied)
+                    && pressure_plates::is_on_plate(player.pos, plate)
+            });
+            if pressed {
+                self.pressure_plates.keep(plate);
+            } else {
+                self.set_pressure_plate(plate, false);
             }
         }
     }
 
     fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
-        if self.redpiler.is_active() {
+        if self.redpiler.is_compiled(pos) {
             self.redpiler.set_pressure_plate(pos, powered);
             return;
         }
@@ -327,17 +734,65 @@ impl Plot {
         }
     }
 
-    fn are_players_on_block(&mut self, pos: BlockPos) -> bool {
-        for player in &self.players {
-            if player.pos.block_pos() == pos && player.on_ground {
-                return true;
+    /// The signal strength of a block, from redpiler if it is compiled
+    pub fn signal_strength(&self, pos: BlockPos) -> u8 {
+        self.redpiler
+            .get_signal_strength(pos)
+            .unwrap_or_else(|| display::world_signal_strength(&self.world, pos))
+    }
+
+    /// The signal strength of a named port, from redpiler if it is compiled
+    pub fn port_signal_strength(&self, name: &str) -> Option<u8> {
+        let pos = self.ports.get(name)?;
+        Some(self.signal_strength(pos))
+    }
+
+    /// Turns a named lever or pressure plate on or off, or presses a named button
+    pub fn set_port(&mut self, name: &str, powered: bool) -> Result<(), &'static str> {
+        let pos = self
+            .ports
+            .get(name)
+            .ok_or("There is no port with that name.")?;
+        self.set_input(pos, powered)
+    }
+
+    /// Turns the lever or pressure plate at `pos` on or off, or presses the button at `pos`
+    pub fn set_input(&mut self, pos: BlockPos, powered: bool) -> Result<(), &'static str> {
+        let compiled = self.redpiler.is_compiled(pos);
+        match self.world.get_block(pos) {
+            Block::Lever { lever } => {
+                let is_powered = match compiled {
+                    true => self.redpiler.is_powered(pos).unwrap_or(lever.powered),
+                    false => lever.powered,
+                };
+                if is_powered == powered {
+                    return Ok(());
+                }
+                if compiled {
+                    self.redpiler.on_use_block(pos);
+                } else {
+                    crate::interaction::flip_lever(&mut self.world, pos, lever);
+                }
             }
+            Block::StoneButton { button } => {
+                if !powered {
+                    return Err("Buttons are released on their own.");
+                }
+                if compiled {
+                    self.redpiler.on_use_block(pos);
+                } else {
+                    crate::interaction::press_button(&mut self.world, pos, button);
+                }
+            }
+            Block::StonePressurePlate { .. } => self.set_pressure_plate(pos, powered),
+            _ => return Err("Only levers, buttons and pressure plates can be set."),
         }
-        false
+        self.world.flush_block_changes();
+        Ok(())
     }
 
     fn enter_plot(&mut self, player: Player) {
-        self.save();
+        self.save_or_log();
         let spawn_player = CSpawnPlayer {
             entity_id: player.entity_id as i32,
             uuid: player.uuid,
@@ -421,6 +876,7 @@ impl Plot {
             }
         }
 
+        player.client.send_packet(&self.world.time_update_packet());
         player.send_system_message(&format!(
             "Entering plot ({}, {})",
             self.world.x, self.world.z
@@ -431,6 +887,9 @@ impl Plot {
         self.scoreboard.add_player(&player);
         self.players.push(player);
         self.update_view_pos_for_player(self.players.len() - 1, true);
+        // Displays are spawned after the chunks so the client doesn't discard them
+        self.displays
+            .add_player(&self.players[self.players.len() - 1]);
     }
 
     fn get_chunk_distance(x1: i32, z1: i32, x2: i32, z2: i32) -> u32 {
@@ -452,9 +911,10 @@ impl Plot {
             self.players[player_idx].client.send_packet(&unload_chunk);
         } else if !was_loaded && should_be_loaded {
             if !Plot::chunk_in_plot_bounds(self.world.x, self.world.z, chunk_x, chunk_z) {
+                let sections = self.world.size.sections;
                 self.players[player_idx]
                     .client
-                    .send_packet(&Chunk::encode_empty_packet(chunk_x, chunk_z));
+                    .send_packet(&Chunk::encode_empty_packet(chunk_x, chunk_z, sections));
             } else {
                 let chunk_data = self.world.chunks
                     [self.world.get_chunk_index_for_chunk(chunk_x, chunk_z)]
@@ -515,22 +975,119 @@ impl Plot {
         self.timings.reset_timings();
     }
 
-    fn start_redpiler(&mut self, options: CompilerOptions) {
+    /// Compiles the blocks inside of `bounds`. Pending ticks outside of the bounds stay in the
+    /// world so that the rest of the plot keeps being interpreted.
+    fn start_redpiler(&mut self, mut options: CompilerOptions, bounds: (BlockPos, BlockPos)) {
         debug!("Starting redpiler");
-        let ticks = self.world.to_be_ticked.drain(..).collect();
+        options.port_names = self
+            .ports
+            .iter()
+            .map(|(name, pos)| (name.to_owned(), pos))
+            .collect();
+        let (ticks, outside_ticks) = self.partition_ticks(bounds);
         self.scoreboard
             .set_redpiler_state(&self.players, RedpilerState::Compiling);
         self.scoreboard
             .set_redpiler_options(&self.players, &options);
-        let bounds = self.world.get_corners();
-        self.redpiler
-            .compile(&mut self.world, bounds, options, ticks);
+
+        // The compile blocks the plot thread, so the progress is sent from the monitor. The
+        // packets of the players are held until the compile is done, except for cancel commands.
+        let players = RefCell::new(&mut self.players);
+        let report = |progress: &CompileProgress| {
+            let mut message = format!("Compiling: {} ({}%", progress.pass, progress.percent());
+            if let Some(processed) = progress.nodes_processed {
+                message += &format!(", {}/{} nodes", processed, progress.node_count);
+            }
+            message += ")";
+            let mut cancel = false;
+            for player in players.borrow_mut().iter_mut() {
+                player.send_action_bar(&message);
+                cancel |= player.hold_packets(is_cancel_command);
+            }
+            cancel
+        };
+        let monitor = TaskMonitor::new(&report);
+        let result =
+            self.redpiler
+                .compile_with_monitor(&mut self.world, bounds, options, ticks, &monitor);
+        for player in &self.players {
+            player.send_action_bar("");
+        }
+        self.finish_redpiler_compile(result, outside_ticks);
+    }
+
+    /// Splits the pending ticks into the ones inside of `bounds` and the ones outside of it
+    fn partition_ticks(&self, bounds: (BlockPos, BlockPos)) -> (Vec<TickEntry>, Vec<TickEntry>) {
+        let (min, max) = bounds;
+        self.world.to_be_ticked.iter().cloned().partition(|entry| {
+            let pos = entry.pos;
+            (min.x..=max.x).contains(&pos.x)
+                && (min.y..=max.y).contains(&pos.y)
+                && (min.z..=max.z).contains(&pos.z)
+        })
+    }
+
+    /// The compile was given the pending ticks inside of the bounds, which only leaves the ticks
+    /// outside of them in the world if it succeeded
+    fn finish_redpiler_compile(
+        &mut self,
+        result: Result<(), CompileError>,
+        outside_ticks: Vec<TickEntry>,
+    ) {
+        if let Err(err) = result {
+            let mut message = match err {
+                CompileError::Cancelled => "The compile was cancelled.".to_owned(),
+                CompileError::Unsupported(err) => {
+                    warn!("Redpiler could not compile: {}", err);
+                    format!("Redpiler could not compile: {}.", err)
+                }
+            };
+            if self.auto_redpiler {
+                // Otherwise it would be retried on every tick
+                self.auto_redpiler = false;
+                message += " Automatic redpiler has been disabled for this plot.";
+            }
+            for player in &self.players {
+                player.send_error_message(&message);
+            }
+            self.scoreboard
+                .set_redpiler_state(&self.players, RedpilerState::Stopped);
+            self.scoreboard
+                .set_redpiler_options(&self.players, &Default::default());
+            return;
+        }
+        self.world.to_be_ticked = outside_ticks;
         self.scoreboard
             .set_redpiler_state(&self.players, RedpilerState::Running);
 
         self.reset_timings();
     }
 
+    /// Stops redpiler for a block change made by a player. If the circuit was compiled
+    /// incrementally, [`Plot::resume_redpiler`] has to be called after the change to patch it
+    /// in. Otherwise redpiler is reset and false is returned.
+    fn suspend_redpiler(&mut self) -> bool {
+        if !self.redpiler.is_active() {
+            return false;
+        }
+        // Only the blocks changed by the player should be pending after this
+        self.world.flush_block_changes();
+        if self.redpiler.suspend(&mut self.world) {
+            return true;
+        }
+        self.reset_redpiler();
+        false
+    }
+
+    /// Patches the blocks that changed since [`Plot::suspend_redpiler`] into the circuit and
+    /// starts it again. This has to be called before the block changes are flushed.
+    fn resume_redpiler(&mut self) {
+        let changed = self.world.changed_positions();
+        let (ticks, outside_ticks) = self.partition_ticks(self.redpiler.bounds());
+        let result = self.redpiler.recompile(&mut self.world, &changed, ticks);
+        self.finish_redpiler_compile(result.map_err(CompileError::from), outside_ticks);
+    }
+
     /// Redpiler needs to reset implicitly in the case of any block changes done by a player. This can be
     fn reset_redpiler(&mut self) {
         if self.redpiler.is_active() {
@@ -568,8 +1125,8 @@ impl Plot {
         .encode();
         player.client.send_packet(&destroy_other_entities);
 
-        let chunk_offset_x = self.world.x << PLOT_SCALE;
-        let chunk_offset_z = self.world.z << PLOT_SCALE;
+        let chunk_offset_x = self.world.x << self.world.size.scale;
+        let chunk_offset_z = self.world.z << self.world.size.scale;
         for chunk in &self.world.chunks {
             player.client.send_packet(
                 &CUnloadChunk {
@@ -582,11 +1139,13 @@ impl Plot {
         self.destroy_entity(player.entity_id);
         self.locked_players.remove(&player.entity_id);
         self.scoreboard.remove_player(&player);
+        self.displays.remove_player(&player);
+        self.overlays.remove_player(&player);
         player
     }
 
     fn chunk_in_plot_bounds(plot_x: i32, plot_z: i32, chunk_x: i32, chunk_z: i32) -> bool {
-        let (x, z) = (chunk_x >> PLOT_SCALE, chunk_z >> PLOT_SCALE);
+        let (x, z) = (chunk_x >> PLOT_SIZE.scale, chunk_z >> PLOT_SIZE.scale);
         plot_x == x && plot_z == z
     }
 
@@ -597,16 +1156,43 @@ impl Plot {
     pub fn claim_plot(&mut self, plot_x: i32, plot_z: i32, player: usize) {
         let player = &mut self.players[player];
         database::claim_plot(plot_x, plot_z, &format!("{:032x}", player.uuid));
+        if (plot_x, plot_z) == (self.world.x, self.world.z) {
+            self.owner = Some(player.uuid);
+        }
         let center = Plot::get_center(plot_x, plot_z);
         player.teleport(PlayerPos::new(center.0, 64.0, center.1));
         player.send_system_message(&format!("Claimed plot {},{}", plot_x, plot_z));
     }
 
+    /// Whether the player is the owner of the plot or trusted by them
+    pub fn is_member(&self, uuid: u128) -> bool {
+        self.owner.is_some() && (self.owner == Some(uuid) || self.trusted.contains(&uuid))
+    }
+
+    /// Whether the owner of the plot doesn't allow the player to use anything in it
+    pub fn is_denied(&self, uuid: u128) -> bool {
+        self.denied.contains(&uuid)
+    }
+
+    /// Whether the player can place and break blocks and use levers and buttons in the plot.
+    /// Denied players can't, even if they have the permission to interact with other plots.
+    pub fn can_build(&self, player: usize) -> bool {
+        let player = &self.players[player];
+        if self.is_denied(player.uuid) {
+            return false;
+        }
+        match self.owner {
+            Some(_) if self.is_member(player.uuid) => true,
+            Some(_) => player.has_permission("plots.admin.interact.other"),
+            None => player.has_permission("plots.admin.interact.unowned"),
+        }
+    }
+
     pub fn get_center(plot_x: i32, plot_z: i32) -> (f64, f64) {
-        const WIDTH: f64 = PLOT_BLOCK_WIDTH as f64;
+        let width = PLOT_SIZE.block_width() as f64;
         (
-            plot_x as f64 * WIDTH + WIDTH / 2.0,
-            plot_z as f64 * WIDTH + WIDTH / 2.0,
+            plot_x as f64 * width + width / 2.0,
+            plot_z as f64 * width + width / 2.0,
         )
     }
 
@@ -719,6 +1305,7 @@ impl Plot {
                     }
                     self.enter_plot(player);
                 }
+                PrivMessage::Remote(request) => self.handle_remote_request(request),
             }
         }
     }
@@ -773,129 +1360,251 @@ impl Plot {
             }
         }
         // Handle received packets
+        let start = self.profiler.begin();
         for player_idx in 0..self.players.len() {
             self.handle_packets_for_player(player_idx);
         }
+        self.profiler.record(Section::Packets, start);
+    }
+
+    /// The number of ticks that can run before the world has to be sent again, based on how
+    /// long the last ticks took
+    fn ticks_per_world_send(&self) -> u64 {
+        match self.last_nspt {
+            Some(Duration::ZERO) | None => 5,
+            Some(last_nspt) => {
+                let ticks_fit = WORLD_SEND_RATE.as_nanos() / last_nspt.as_nanos();
+                if ticks_fit == 0 {
+                    // A tick previously took longer than the world send rate.
+                    // Run at least one just so we're not stuck doing nothing
+                    1
+                } else {
+                    ticks_fit as u64
+                }
+            }
+        }
+    }
+
+    /// Runs the ticks that are due at a limited rate, catching up on ticks that were missed
+    /// because the previous updates took too long
+    fn tick_limited(&mut self, dur_per_tick: Duration) {
+        self.lag_time += self.last_update_time.elapsed();
+        self.last_update_time = Instant::now();
+        // Ticks that are further behind than this are skipped instead of caught up on
+        self.lag_time = self.lag_time.min(dur_per_tick + MAX_CATCH_UP);
+        if self.lag_time <= dur_per_tick {
+            return;
+        }
+
+        // TODO: there are some problems here: redpiler should not automatically
+        // compiler as early as it is, meaning we are not running as many ticks
+        // as we should for some reason.
+        let batch_size = self.lag_time.as_nanos() as u64 / dur_per_tick.as_nanos() as u64;
+        if !self.redpiler.is_active() && self.auto_redpiler {
+            let mut ticks_completed = 0;
+            let mut running_behind = false;
+            for _ in 0..batch_size {
+                // If we're running behind, just stop right here and we can start redpiler
+                if self.timings.is_running_behind() {
+                    running_behind = true;
+                    break;
+                }
+                // The rest of the batch is left for the next update, so that packets are still
+                // handled while catching up
+                if self.last_update_time.elapsed() > WORLD_SEND_RATE {
+                    break;
+                }
+                self.tick();
+                ticks_completed += 1;
+                if self.frozen {
+                    break;
+                }
+            }
+            if ticks_completed > 0 {
+                self.last_nspt = Some(self.last_update_time.elapsed() / ticks_completed as u32);
+            }
+            if running_behind {
+                let bounds = self.world.get_corners();
+                self.start_redpiler(Default::default(), bounds);
+            } else {
+                self.lag_time -= dur_per_tick * ticks_completed as u32;
+            }
+        } else {
+            // Limit the batch size to however many ticks we can fit inside the world send rate.
+            let batch_size = batch_size.min(self.ticks_per_world_send());
+            if batch_size != 0 {
+                // Redpiler is either already running or will not be automatically started,
+                // so there's nothing special to do here, just run the batch
+                for _ in 0..batch_size {
+                    self.tick();
+                    if self.frozen {
+                        self.lag_time = Duration::ZERO;
+                        break;
+                    }
+                }
+                self.lag_time = self
+                    .lag_time
+                    .saturating_sub(dur_per_tick * batch_size as u32);
+                self.last_nspt = Some(self.last_update_time.elapsed() / (batch_size as u32));
+            }
+        }
+    }
+
+    /// Runs as many ticks as fit between two world sends
+    fn tick_unlimited(&mut self) {
+        if !self.redpiler.is_active() && self.auto_redpiler {
+            let bounds = self.world.get_corners();
+            self.start_redpiler(Default::default(), bounds);
+        }
+        self.last_update_time = Instant::now();
+        let batch_size = self.ticks_per_world_send().min(50000) as u32;
+        for _ in 0..batch_size {
+            self.tick();
+            if self.frozen {
+                break;
+            }
+        }
+        self.last_nspt = Some(self.last_update_time.elapsed() / batch_size);
+    }
+
+    fn publish_metrics(&self) {
+        let pass_timings = self.redpiler.pass_timings();
+        let graph = pass_timings.last();
+        let tps = match self.players.is_empty() {
+            // Plots only tick while players are in them
+            true => 0.0,
+            false => self
+                .timings
+                .generate_report()
+                .map_or(0.0, |report| report.ten_s),
+        };
+        metrics::publish(
+            self.world.x,
+            self.world.z,
+            PlotMetrics {
+                tps,
+                target_tps: self.tps.millis().map(|millis| millis as f32 / 1000.0),
+                players: self.players.len(),
+                backend: self.redpiler.backend_name(),
+                nodes: graph.map_or(0, |timing| timing.node_count),
+                edges: graph.map_or(0, |timing| timing.edge_count),
+                compile_time: graph
+                    .map(|_| pass_timings.iter().map(|timing| timing.duration).sum()),
+            },
+        );
+    }
+
+    /// Unloads the sections that were used least recently, so that plots without players keep
+    /// at most `max_loaded_sections` loaded and all plots together at most `section_cache_size`
+    fn unload_sections(&mut self) {
+        if self.last_unload_time.elapsed() < UNLOAD_INTERVAL {
+            return;
+        }
+        self.last_unload_time = Instant::now();
+        let mut max_loaded = usize::MAX;
+        if self.players.is_empty() && CONFIG.max_loaded_sections > 0 {
+            max_loaded = CONFIG.max_loaded_sections as usize;
+        }
+        if CONFIG.section_cache_size > 0 {
+            let total = LOADED_SECTIONS.load(AtomicOrdering::Relaxed).max(1);
+            let excess = total.saturating_sub(CONFIG.section_cache_size as usize);
+            // Every plot gives up its share of the sections over the limit
+            let share = (excess as u64 * self.loaded_sections as u64 / total as u64) as usize;
+            max_loaded = max_loaded.min(self.loaded_sections - share);
+        }
+        if max_loaded != usize::MAX {
+            let unloaded = self.world.unload_cold_sections(max_loaded);
+            if unloaded > 0 {
+                debug!("Unloaded {} cold sections", unloaded);
+            }
+        }
+
+        let loaded = self.world.loaded_sections();
+        LOADED_SECTIONS.fetch_add(loaded, AtomicOrdering::Relaxed);
+        LOADED_SECTIONS.fetch_sub(self.loaded_sections, AtomicOrdering::Relaxed);
+        self.loaded_sections = loaded;
     }
 
     fn update(&mut self) {
+        // Player actions between ticks get their own budget
+        self.world.update_queue.start_tick();
         self.handle_messages();
 
         // Only tick if there are players in the plot
         if !self.players.is_empty() {
-            self.timings.set_ticking(true);
+            self.timings.set_ticking(!self.frozen);
             self.last_player_time = Instant::now();
             match self.tps {
-                Tps::Limited(tps) if tps != 0 => {
-                    let dur_per_tick = Duration::from_micros(1_000_000 / tps as u64);
-                    self.lag_time += self.last_update_time.elapsed();
+                // A frozen plot is only ticked by commands
+                _ if self.frozen => {
                     self.last_update_time = Instant::now();
-                    if self.lag_time > dur_per_tick {
-                        // TODO: there are some problems here: redpiler should not automatically
-                        // compiler as early as it is, meaning we are not running as many ticks
-                        // as we should for some reason.
-                        let batch_size =
-                            self.lag_time.as_micros() as u64 / dur_per_tick.as_micros() as u64;
-                        // 50000 here is arbitrary. We just need a number that's not too high so we actually
-                        // get around to sending block updates.
-                        let batch_size = batch_size.min(50000);
-                        if !self.redpiler.is_active() && self.auto_redpiler {
-                            let mut ticks_completed = batch_size;
-                            for i in 0..batch_size {
-                                // If we're running behind, just stop right here and we can start redpiler
-                                if self.timings.is_running_behind() {
-                                    ticks_completed = i;
-                                    break;
-                                }
-                                self.tick();
-                            }
-                            if ticks_completed > 0 {
-                                self.last_nspt =
-                                    Some(self.last_update_time.elapsed() / ticks_completed as u32);
-                            }
-                            // Check if we stopped early, and if so, start redpiler
-                            if ticks_completed != batch_size {
-                                self.start_redpiler(Default::default());
-                            } else {
-                                self.lag_time -= dur_per_tick * batch_size as u32;
-                            }
-                        } else {
-                            // Limit the batch size to however many ticks we can fit inside the world send rate.
-                            let batch_size = batch_size.min(match self.last_nspt {
-                                Some(Duration::ZERO) | None => 5,
-                                Some(last_nspt) => {
-                                    let ticks_fit =
-                                        WORLD_SEND_RATE.as_nanos() / last_nspt.as_nanos();
-                                    if ticks_fit == 0 {
-                                        // A tick previously took longer than the world send rate.
-                                        // Run at least one just so we're not stuck doing nothing
-                                        1
-                                    } else {
-                                        ticks_fit
-                                    }
-                                }
-                            } as u64);
-                            if batch_size != 0 {
-                                // Redpiler is either already running or will not be automatically started,
-                                // so there's nothing special to do here, just run the batch
-                                for _ in 0..batch_size {
-                                    self.tick();
-                                }
-                                self.lag_time -= dur_per_tick * batch_size as u32;
-                                self.last_nspt =
-                                    Some(self.last_update_time.elapsed() / (batch_size as u32));
-                            }
-                        }
-                    }
+                    self.lag_time = Duration::ZERO;
                 }
-                Tps::Unlimited => {
-                    if !self.redpiler.is_active() && self.auto_redpiler {
-                        self.start_redpiler(Default::default());
-                    }
-                    self.last_update_time = Instant::now();
-                    let batch_size = match self.last_nspt {
-                        Some(Duration::ZERO) | None => 5,
-                        Some(last_nspt) => {
-                            let ticks_fit = WORLD_SEND_RATE.as_nanos() / last_nspt.as_nanos();
-                            if ticks_fit == 0 {
-                                // A tick previously took longer than the world send rate.
-                                // Run at least one just so we're not stuck doing nothing
-                                1
-                            } else {
-                                ticks_fit
-                            }
-                        }
-                    } as u64;
-                    if batch_size != 0 {
-                        let batch_size = batch_size.min(50000) as u32;
-                        for _ in 0..batch_size {
-                            self.tick();
-                        }
-                        self.last_nspt = Some(self.last_update_time.elapsed() / batch_size);
+                Tps::Unlimited => self.tick_unlimited(),
+                tps => {
+                    if let Some(dur_per_tick) = tps.tick_duration() {
+                        self.tick_limited(dur_per_tick);
                     }
                 }
-                _ => {}
             }
 
-            if self.redpiler.is_active() {
+            let now = Instant::now();
+            let send_world = now - self.last_world_send_time > WORLD_SEND_RATE;
+            // Redpiler's state only has to be written back into the world when it is sent, which
+            // keeps fast plots from spending most of their time on flushing
+            if send_world && self.redpiler.is_active() {
+                let start = self.profiler.begin();
                 self.redpiler.flush(&mut self.world);
+                self.profiler.record(Section::RedpilerFlush, start);
             }
-            let now = Instant::now();
-            let time_since_last_world_send = now - self.last_world_send_time;
-            if time_since_last_world_send > WORLD_SEND_RATE {
+            let note_events = std::mem::take(&mut self.world.note_events);
+            audio::send_note_sounds(
+                &self.players,
+                &note_events,
+                &self.note_sounds,
+                CONFIG.max_note_sounds.max(0) as usize,
+            );
+            if let Some(pos) = self.world.update_queue.take_dropped() {
+                let message = format!(
+                    "Block updates at {} were dropped because more than {} updates happened in one tick.",
+                    pos, CONFIG.max_block_updates
+                );
+                for player in &self.players {
+                    player.send_error_message(&message);
+                }
+            }
+            if send_world {
                 self.last_world_send_time = now;
+                self.displays
+                    .update(&self.players, &self.world, &self.redpiler);
+                self.overlays
+                    .update(&self.players, &self.world, &self.redpiler);
+                self.latency.on_flush(&self.world, &self.players);
                 self.world.flush_block_changes();
             }
         } else {
             self.timings.set_ticking(false);
-            // Unload plot after 600 seconds unless the plot should be always loaded
-            if self.last_player_time.elapsed().as_secs() > 600 && !self.always_running {
+            // Unload plot after 600 seconds unless the plot should be always loaded or remote
+            // clients are subscribed to it
+            if self.last_player_time.elapsed().as_secs() > 600
+                && !self.always_running
+                && self.remote_subscriptions.is_empty()
+            {
                 self.running = false;
                 self.timings.stop();
             }
         }
 
+        self.unload_sections();
+        self.backup_if_due();
+
+        if metrics::is_enabled() && self.last_metrics_time.elapsed() > metrics::PUBLISH_INTERVAL {
+            self.last_metrics_time = Instant::now();
+            self.publish_metrics();
+        }
+
         self.update_players();
+        self.notify_remote_subscribers();
 
         // Handle commands before removing players just in case they ran a command before leaving
         self.handle_commands();
@@ -908,32 +1617,89 @@ impl Plot {
         Runtime::new().unwrap()
     }
 
-    fn generate_chunk(layers: i32, x: i32, z: i32) -> Chunk {
-        let mut chunk = Chunk::empty(x, z);
+    fn generate_chunk(layers: i32, x: i32, z: i32, size: PlotSize) -> Chunk {
+        let template = PlotTemplate {
+            floor_height: layers as u32,
+            ..Default::default()
+        };
+        let palette = template
+            .palette()
+            .expect("default plot template should be valid");
+        template.generate_chunk(&palette, size, x, z)
+    }
+
+    /// Makes a backup of the plot if it changed since the last one, and removes the oldest
+    /// backups so that at most `backup_count` are kept
+    fn create_backup(&mut self) -> anyhow::Result<Option<u64>> {
+        if self.backups.is_none() {
+            return Ok(None);
+        }
+        if self.redpiler.is_active() {
+            self.redpiler.flush(&mut self.world);
+        }
+        let data = self.plot_data()?;
+        let backups = self.backups.as_mut().unwrap();
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let id = backups.create(time, &data)?;
+        backups.prune(CONFIG.backup_count.max(1) as usize)?;
+        Ok(id)
+    }
+
+    fn backup_if_due(&mut self) {
+        let interval = Duration::from_secs(CONFIG.backup_interval.max(0) as u64 * 60);
+        if self.backups.is_none() || self.last_backup_time.elapsed() < interval {
+            return;
+        }
+        self.last_backup_time = Instant::now();
+        if let Err(err) = self.create_backup() {
+            error!(
+                "Failed to back up plot {},{}: {:#}",
+                self.world.x, self.world.z, err
+            );
+        }
+    }
 
-        for ry in 0..layers {
-            for rx in 0..16 {
-                for rz in 0..16 {
-                    let block_x = (x << 4) | rx;
-                    let block_z = (z << 4) | rz;
+    /// Replaces the plot with backup `id`. The plot is backed up first, so that the rollback can
+    /// be undone.
+    fn rollback(&mut self, id: u64) -> anyhow::Result<()> {
+        let Some(backups) = &self.backups else {
+            bail!("backups are disabled");
+        };
+        let data = backups.restore(id)?;
+        if data.chunk_data.len() != self.world.chunks.len() {
+            bail!("the backup was made with a different plot size");
+        }
+        let save = PlotSave::from_data(data, self.world.x, self.world.z, self.world.size)?;
+        self.create_backup()?;
 
-                    if block_x % PLOT_BLOCK_WIDTH == 0
-                        || block_z % PLOT_BLOCK_WIDTH == 0
-                        || (block_x + 1) % PLOT_BLOCK_WIDTH == 0
-                        || (block_z + 1) % PLOT_BLOCK_WIDTH == 0
-                    {
-                        chunk.set_block(rx as u32, ry as u32, rz as u32, 4564); // Stone Bricks
-                    } else {
-                        chunk.set_block(rx as u32, ry as u32, rz as u32, 278); // Sandstone
-                    }
-                }
-            }
+        self.redpiler.discard();
+        self.scoreboard
+            .set_redpiler_state(&self.players, RedpilerState::Stopped);
+        self.world.chunks = save.chunks;
+        self.world.to_be_ticked = save.pending_ticks;
+        for player_idx in 0..self.players.len() {
+            self.update_view_pos_for_player(player_idx, true);
         }
-        chunk
+        self.save()
+    }
+
+    /// Replace the plot with a newly generated one from `template`
+    fn regenerate(&mut self, template: &PlotTemplate) -> PatternParseResult<()> {
+        let chunks = template.generate_plot(self.world.size, self.world.x, self.world.z)?;
+        self.redpiler.discard();
+        self.scoreboard
+            .set_redpiler_state(&self.players, RedpilerState::Stopped);
+        self.world.chunks = chunks;
+        self.world.to_be_ticked.clear();
+        for player_idx in 0..self.players.len() {
+            self.update_view_pos_for_player(player_idx, true);
+        }
+        self.save_or_log();
+        Ok(())
     }
 
     fn from_data(
-        plot_data: PlotData<PLOT_SECTIONS>,
+        plot_data: PlotSave,
         x: i32,
         z: i32,
         rx: BusReader<BroadcastMessage>,
@@ -941,37 +1707,52 @@ impl Plot {
         priv_rx: Receiver<PrivMessage>,
         always_running: bool,
     ) -> Plot {
-        let chunk_x_offset = x << PLOT_SCALE;
-        let chunk_z_offset = z << PLOT_SCALE;
-        let chunks: Vec<Chunk> = plot_data
-            .chunk_data
-            .into_iter()
-            .enumerate()
-            .map(|(i, c)| {
-                Chunk::load(
-                    chunk_x_offset + i as i32 / PLOT_WIDTH,
-                    chunk_z_offset + i as i32 % PLOT_WIDTH,
-                    c,
-                )
-            })
-            .collect();
-        if chunks.len() != NUM_CHUNKS {
-            error!("This plot has the wrong number of chunks!");
-            let possible_scale = (chunks.len() as f64).sqrt().log2();
-            error!("Note: it most likely came from a server running plot scale {}, this server is running a plot scale of {}", possible_scale, PLOT_SCALE);
+        let chunks = plot_data.chunks;
+        let size = *PLOT_SIZE;
+        let safe_mode = SAFE_MODE.load(AtomicOrdering::Relaxed)
+            || SAFE_MODE_PLOTS.lock().unwrap().remove(&(x, z));
+        if safe_mode {
+            warn!(
+                "Loading plot {},{} in safe mode, discarding {} pending ticks",
+                x,
+                z,
+                plot_data.pending_ticks.len()
+            );
         }
         let world = PlotWorld {
             x,
             z,
+            size,
             chunks,
-            to_be_ticked: plot_data.pending_ticks,
+            to_be_ticked: if safe_mode {
+                Vec::new()
+            } else {
+                plot_data.pending_ticks
+            },
             packet_senders: Vec::new(),
+            note_events: Vec::new(),
+            update_queue: UpdateQueue::new(CONFIG.max_block_updates.max(0) as u64),
+            daytime: database::get_plot_time(x, z).unwrap_or(NOON),
+            locational_updates: features::is_enabled(Feature::LocationalUpdates, x, z),
         };
         let tps = plot_data.tps;
+        let (mut trusted, mut denied) = (HashSet::new(), HashSet::new());
+        for (uuid, is_trusted) in database::get_plot_access(x, z) {
+            let uuid = uuid.parse::<HyphenatedUUID>().unwrap().0;
+            match is_trusted {
+                true => trusted.insert(uuid),
+                false => denied.insert(uuid),
+            };
+        }
         Plot {
             last_player_time: Instant::now(),
             last_update_time: Instant::now(),
             last_world_send_time: Instant::now(),
+            last_unload_time: Instant::now(),
+            last_backup_time: Instant::now(),
+            backups: None,
+            loaded_sections: 0,
+            last_metrics_time: Instant::now(),
             lag_time: Duration::new(0, 0),
             sleep_time: sleep_time_for_tps(tps),
             last_nspt: None,
@@ -981,14 +1762,28 @@ impl Plot {
             players: Vec::new(),
             locked_players: HashSet::new(),
             running: true,
-            auto_redpiler: CONFIG.auto_redpiler,
+            auto_redpiler: CONFIG.auto_redpiler && !safe_mode,
             tps,
             always_running,
             redpiler: Default::default(),
             timings: TimingsMonitor::new(tps),
             owner: database::get_plot_owner(x, z).map(|s| s.parse::<HyphenatedUUID>().unwrap().0),
+            trusted,
+            denied,
             async_rt: Plot::create_async_rt(),
             scoreboard: Default::default(),
+            displays: Default::default(),
+            overlays: Default::default(),
+            pressure_plates: Default::default(),
+            ports: database::get_ports(x, z).into_iter().collect(),
+            remote_subscriptions: Default::default(),
+            note_sounds: NoteSounds::new(CONFIG.note_block_sounds.as_ref()),
+            latency: Default::default(),
+            profiler: Default::default(),
+            breakpoints: Default::default(),
+            audit: None,
+            snapshots: HashMap::new(),
+            frozen: false,
             world,
         }
     }
@@ -1002,29 +1797,48 @@ impl Plot {
         always_running: bool,
     ) -> Plot {
         let plot_path = format!("./world/plots/p{},{}", x, z);
-        if Path::new(&plot_path).exists() {
-            let data = data::load_plot(plot_path)
-                .with_context(|| format!("error loading plot {},{}", x, z))
-                .unwrap();
-            Plot::from_data(data, x, z, rx, tx, priv_rx, always_running)
-        } else {
-            Plot::from_data(data::empty_plot(), x, z, rx, tx, priv_rx, always_running)
+        let data = data::load_plot(plot_path, x, z, *PLOT_SIZE)
+            .with_context(|| format!("error loading plot {},{}", x, z))
+            .unwrap();
+        let mut plot = Plot::from_data(data, x, z, rx, tx, priv_rx, always_running);
+        if CONFIG.redpiler_cache {
+            let cache_path = format!("./world/redpiler_cache/p{},{}", x, z);
+            plot.redpiler.set_cache_path(Some(cache_path.into()));
         }
+        if CONFIG.backup_interval > 0 {
+            let backup_path = format!("./world/backups/p{},{}", x, z);
+            match PlotBackups::open(&backup_path) {
+                Ok(backups) => plot.backups = Some(backups),
+                Err(err) => error!("Failed to open plot backups {}: {}", backup_path, err),
+            }
+        }
+        plot
     }
 
-    fn save(&mut self) {
+    fn plot_data(&mut self) -> Result<PlotData, PlotLoadError> {
         let world = &mut self.world;
-        let chunk_data: Vec<ChunkData<PLOT_SECTIONS>> =
-            world.chunks.iter_mut().map(|c| c.save()).collect();
-        let data = PlotData {
+        Ok(PlotData {
             tps: self.tps,
-            chunk_data,
+            chunk_data: world
+                .chunks
+                .iter_mut()
+                .map(|c| c.save())
+                .collect::<Result<_, _>>()?,
             pending_ticks: world.to_be_ticked.clone(),
-        };
-        data.save_to_file(format!("./world/plots/p{},{}", world.x, world.z))
-            .unwrap();
+        })
+    }
 
+    fn save(&mut self) -> anyhow::Result<()> {
+        self.world.save_to_disk(self.tps)?;
         self.reset_timings();
+        Ok(())
+    }
+
+    /// Saves the plot, logging the error if that fails
+    fn save_or_log(&mut self) {
+        if let Err(err) = self.save() {
+            error!("Failed to save plot: {:#}", err);
+        }
     }
 
     fn run(&mut self, initial_player: Option<Player>) {
@@ -1078,6 +1892,7 @@ impl Plot {
 
 impl Drop for Plot {
     fn drop(&mut self) {
+        LOADED_SECTIONS.fetch_sub(self.loaded_sections, AtomicOrdering::Relaxed);
         if !self.players.is_empty() {
             for player in &mut self.players {
                 player.save(); // just in case
@@ -1114,22 +1929,99 @@ impl Drop for Plot {
             .chunks
             .iter_mut()
             .for_each(|chunk| chunk.compress());
-        self.save();
+        self.save_or_log();
         let world = &self.world;
+        metrics::remove(world.x, world.z);
         self.message_sender
             .send(Message::PlotUnload(world.x, world.z))
             .unwrap();
     }
 }
 
-#[test]
-fn chunk_save_and_load_test() {
-    let mut chunk = Chunk::empty(1, 1);
-    chunk.set_block(13, 63, 12, 332);
-    chunk.set_block(13, 62, 12, 331);
-    let chunk_data = chunk.save();
-    let loaded_chunk = Chunk::load(1, 1, chunk_data);
-    assert_eq!(loaded_chunk.get_block(13, 63, 12), 332);
-    assert_eq!(loaded_chunk.get_block(13, 62, 12), 331);
-    assert_eq!(loaded_chunk.get_block(13, 64, 12), 0);
+#[cfg(test)]
+pub(crate) fn redpiler_test_world() -> PlotWorld {
+    let size = PlotSize::default();
+    let mut chunks = Vec::new();
+    for chunk_x in 0..size.width() {
+        for chunk_z in 0..size.width() {
+            chunks.push(Plot::generate_chunk(8, chunk_x, chunk_z, size));
+        }
+    }
+    PlotWorld {
+        x: 0,
+        z: 0,
+        size,
+        chunks,
+        to_be_ticked: Vec::new(),
+        packet_senders: Vec::new(),
+        note_events: Vec::new(),
+        update_queue: Default::default(),
+        daytime: NOON,
+        locational_updates: false,
+    }
+}
+
+/// Places a floor lever at `lever_pos`, the blocks of `line` going east from it and a lamp at
+/// the end of the line. Returns the position of the lamp.
+#[cfg(test)]
+pub(crate) fn build_lever_line(
+    world: &mut PlotWorld,
+    lever_pos: BlockPos,
+    line: &[Block],
+) -> BlockPos {
+    use mchprs_blocks::blocks::{Lever, LeverFace};
+    use mchprs_blocks::BlockDirection;
+
+    let lever = Lever::new(LeverFace::Floor, BlockDirection::North, false);
+    world.set_block(lever_pos, Block::Lever { lever });
+    let mut pos = lever_pos;
+    for &block in line {
+        pos = pos.offset(BlockFace::East);
+        world.set_block(pos, block);
+    }
+    let lamp_pos = pos.offset(BlockFace::East);
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+    lamp_pos
+}
+
+/// Compiles the whole plot with `options`, resuming the ticks that are pending in the world
+#[cfg(test)]
+pub(crate) fn compile_test_world(world: &mut PlotWorld, options: CompilerOptions) -> Compiler {
+    let mut redpiler = Compiler::default();
+    let bounds = world.get_corners();
+    let ticks = std::mem::take(&mut world.to_be_ticked);
+    redpiler.compile(world, bounds, options, ticks).unwrap();
+    redpiler
+}
+
+/// Runs `build` on two fresh worlds, then simulates one with the regular redstone
+/// implementation and the other with redpiler, checking that every block in `check`
+/// stays identical between them on every tick.
+#[cfg(test)]
+pub(crate) fn assert_redpiler_resumes_identically(
+    build: impl Fn(&mut PlotWorld),
+    check: &[BlockPos],
+    ticks: usize,
+) {
+    let mut interpreted = redpiler_test_world();
+    build(&mut interpreted);
+    let mut compiled = redpiler_test_world();
+    build(&mut compiled);
+
+    let mut redpiler = compile_test_world(&mut compiled, Default::default());
+
+    for tick in 0..ticks {
+        interpreted.tick_interpreted();
+        redpiler.tick();
+        redpiler.flush(&mut compiled);
+        for &pos in check {
+            assert_eq!(
+                interpreted.get_block(pos),
+                compiled.get_block(pos),
+                "block at {} differs after tick {}",
+                pos,
+                tick + 1
+            );
+        }
+    }
 }
@@ -8,31 +8,25 @@ use tracing::warn;
 
 #[derive(Default)]
 struct AtomicTps {
-    tps: AtomicU32,
+    /// The rate in thousandths of a tick per second
+    millis: AtomicU32,
     unlimited: AtomicBool,
 }
 
 impl AtomicTps {
     fn from_tps(tps: Tps) -> Self {
-        match tps {
-            Tps::Limited(tps) => AtomicTps {
-                tps: AtomicU32::new(tps),
-                unlimited: AtomicBool::new(false),
-            },
-            Tps::Unlimited => AtomicTps {
-                tps: AtomicU32::new(0),
-                unlimited: AtomicBool::new(true),
-            },
-        }
+        let atomic = AtomicTps::default();
+        atomic.update(tps);
+        atomic
     }
 
     fn update(&self, tps: Tps) {
-        match tps {
-            Tps::Limited(tps) => {
-                self.tps.store(tps, Ordering::Relaxed);
+        match tps.millis() {
+            Some(millis) => {
+                self.millis.store(millis, Ordering::Relaxed);
                 self.unlimited.store(false, Ordering::Relaxed);
             }
-            Tps::Unlimited => self.unlimited.store(true, Ordering::Relaxed),
+            None => self.unlimited.store(true, Ordering::Relaxed),
         }
     }
 }
@@ -143,7 +137,7 @@ impl TimingsMonitor {
 
     fn run_thread(data: Arc<MonitorData>) -> JoinHandle<()> {
         thread::spawn(move || {
-            let mut last_tps = data.tps.tps.load(Ordering::Relaxed);
+            let mut last_tps = data.tps.millis.load(Ordering::Relaxed);
             let mut last_ticks_count = data.ticks_passed.load(Ordering::Relaxed);
             let mut was_ticking_before = data.ticking.load(Ordering::Relaxed);
 
@@ -161,7 +155,7 @@ impl TimingsMonitor {
                 let ticks_passed = (ticks_count - last_ticks_count) as u32;
                 last_ticks_count = ticks_count;
 
-                let tps = data.tps.tps.load(Ordering::Relaxed);
+                let tps = data.tps.millis.load(Ordering::Relaxed);
                 let ticking = data.ticking.load(Ordering::Relaxed);
                 if !(ticking && was_ticking_before)
                     || tps != last_tps
@@ -173,9 +167,10 @@ impl TimingsMonitor {
                     continue;
                 }
 
-                // 5% threshold
-                if data.tps.unlimited.load(Ordering::Relaxed) || ticks_passed < (tps / 2) * 95 / 100
-                {
+                // 5% threshold, rates below two ticks per second are never behind since there
+                // isn't a whole tick in every interval
+                let expected_ticks = (tps / 2000) * 95 / 100;
+                if data.tps.unlimited.load(Ordering::Relaxed) || ticks_passed < expected_ticks {
                     behind_for += 1;
                 } else {
                     behind_for = 0;
@@ -186,7 +181,7 @@ impl TimingsMonitor {
                     data.too_slow.store(true, Ordering::Relaxed);
                     // warn!(
                     //     "running behind by {} ticks",
-                    //     ((tps / 2) * 95 / 100) - ticks_passed
+                    //     expected_ticks - ticks_passed
                     // );
                 }
 
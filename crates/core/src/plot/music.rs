@@ -0,0 +1,484 @@
+//! Converts MIDI files into note block songs that are built on the plot. A song is built as a
+//! line of repeaters that starts at a lever. Every time a note should play, a branch comes off
+//! of the line that powers a spine of blocks with note blocks on either side.
+
+use super::PlotWorld;
+use crate::redstone;
+use crate::world::World;
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::blocks::{Block, Instrument, Lever, LeverFace, RedstoneRepeater, RedstoneWire};
+use mchprs_blocks::{BlockColorVariant, BlockDirection, BlockPos};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The length of a redstone tick in microseconds
+const REDSTONE_TICK_MICROS: u64 = 100_000;
+/// The default MIDI tempo of 120 bpm in microseconds per quarter note
+const DEFAULT_TEMPO: u64 = 500_000;
+/// Every note block of a chord needs its own spot along the spine of a column
+const MAX_CHORD_SIZE: usize = 16;
+/// The distance between the rows of the main line
+const ROW_SPACING: i32 = MAX_CHORD_SIZE as i32 / 2 + 4;
+/// The maximum number of dust in a row before the signal strength gets too low
+const MAX_DUST_RUN: u32 = 12;
+/// The MIDI channel reserved for percussion
+const PERCUSSION_CHANNEL: u8 = 9;
+
+struct MidiNote {
+    tick: u64,
+    channel: u8,
+    key: u8,
+    program: u8,
+}
+
+struct MidiReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MidiReader<'a> {
+    fn new(data: &'a [u8]) -> MidiReader<'a> {
+        MidiReader { data, pos: 0 }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .context("unexpected end of MIDI data")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn varint(&mut self) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("variable length quantity is too long");
+    }
+}
+
+fn read_track(
+    track: &mut MidiReader<'_>,
+    notes: &mut Vec<MidiNote>,
+    tempos: &mut Vec<(u64, u64)>,
+) -> Result<()> {
+    let mut tick = 0;
+    let mut running_status = None;
+    let mut programs = [0; 16];
+    while !track.at_end() {
+        tick += track.varint()? as u64;
+        let mut status = track.u8()?;
+        if status < 0x80 {
+            // Running status, the byte we just read is the first data byte
+            status = running_status.context("data byte without a status byte")?;
+            track.pos -= 1;
+        }
+        match status {
+            0xFF => {
+                let ty = track.u8()?;
+                let len = track.varint()? as usize;
+                let data = track.bytes(len)?;
+                match (ty, data) {
+                    // Set tempo
+                    (0x51, &[a, b, c]) => {
+                        tempos.push((tick, u32::from_be_bytes([0, a, b, c]) as u64));
+                    }
+                    // End of track
+                    (0x2F, _) => break,
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = track.varint()? as usize;
+                track.bytes(len)?;
+            }
+            _ => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                match status & 0xF0 {
+                    0x90 => {
+                        let key = track.u8()?;
+                        let velocity = track.u8()?;
+                        // A note on with a velocity of 0 is a note off
+                        if velocity > 0 {
+                            notes.push(MidiNote {
+                                tick,
+                                channel,
+                                key,
+                                program: programs[channel as usize],
+                            });
+                        }
+                    }
+                    0xC0 => programs[channel as usize] = track.u8()?,
+                    0xD0 => {
+                        track.u8()?;
+                    }
+                    _ => {
+                        track.bytes(2)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The instrument used for a MIDI note. Only instruments with a block that can be placed below
+/// the note block are used.
+fn map_instrument(channel: u8, program: u8, key: u8) -> Instrument {
+    if channel == PERCUSSION_CHANNEL {
+        return match key {
+            // Bass drums
+            35 | 36 => Instrument::Basedrum,
+            _ => Instrument::Hat,
+        };
+    }
+    match program {
+        24..=31 => Instrument::Guitar,
+        32..=39 => Instrument::Bass,
+        80..=103 => Instrument::Pling,
+        _ => Instrument::Harp,
+    }
+}
+
+/// The block below a note block that selects the instrument
+fn instrument_block(instrument: Instrument) -> Block {
+    match instrument {
+        Instrument::Basedrum => Block::Stone {},
+        Instrument::Hat => Block::Glass {},
+        Instrument::Bass => Block::Composter { level: 0 },
+        Instrument::Guitar => Block::Wool {
+            color: BlockColorVariant::White,
+        },
+        Instrument::Pling => Block::Glowstone {},
+        _ => Block::Air {},
+    }
+}
+
+/// Fold a MIDI key into the two octave range of the instrument
+fn map_note(instrument: Instrument, key: u8) -> u32 {
+    let mut note = key as i32 - instrument.base_key() as i32;
+    while note < 0 {
+        note += 12;
+    }
+    while note > 24 {
+        note -= 12;
+    }
+    note as u32
+}
+
+/// The notes of a song, keyed by the redstone tick they play on
+#[derive(Default)]
+pub struct Song {
+    chords: BTreeMap<u32, Vec<(Instrument, u32)>>,
+    /// Notes that were dropped because a chord was too large
+    pub dropped_notes: usize,
+}
+
+impl Song {
+    pub fn parse_midi(data: &[u8]) -> Result<Song> {
+        let mut reader = MidiReader::new(data);
+        if reader.bytes(4)? != b"MThd" {
+            bail!("not a MIDI file");
+        }
+        let header_len = reader.u32()? as usize;
+        let _format = reader.u16()?;
+        let num_tracks = reader.u16()?;
+        let division = reader.u16()? as u64;
+        reader.bytes(header_len.saturating_sub(6))?;
+
+        let mut notes = Vec::new();
+        let mut tempos = Vec::new();
+        let mut tracks_read = 0;
+        while !reader.at_end() && tracks_read < num_tracks {
+            let id = reader.bytes(4)?;
+            let len = reader.u32()? as usize;
+            let chunk = reader.bytes(len)?;
+            // Unknown chunks must be ignored
+            if id == b"MTrk" {
+                read_track(&mut MidiReader::new(chunk), &mut notes, &mut tempos)?;
+                tracks_read += 1;
+            }
+        }
+        tempos.sort_by_key(|&(tick, _)| tick);
+
+        let to_micros = |tick: u64| -> u64 {
+            if division & 0x8000 != 0 {
+                // SMPTE timing: negative frames per second in the upper byte and ticks per
+                // frame in the lower byte
+                let fps = (-((division >> 8) as u8 as i8)) as u64;
+                let ticks_per_frame = division & 0xFF;
+                return tick * 1_000_000 / (fps * ticks_per_frame).max(1);
+            }
+            let ppq = division.max(1);
+            let mut micros = 0;
+            let mut last_tick = 0;
+            let mut tempo = DEFAULT_TEMPO;
+            for &(change_tick, new_tempo) in &tempos {
+                if change_tick >= tick {
+                    break;
+                }
+                micros += (change_tick - last_tick) * tempo / ppq;
+                last_tick = change_tick;
+                tempo = new_tempo;
+            }
+            micros + (tick - last_tick) * tempo / ppq
+        };
+
+        let mut song = Song::default();
+        for note in notes {
+            let micros = to_micros(note.tick);
+            let time = ((micros + REDSTONE_TICK_MICROS / 2) / REDSTONE_TICK_MICROS) as u32;
+            let instrument = map_instrument(note.channel, note.program, note.key);
+            let note = (instrument, map_note(instrument, note.key));
+
+            let chord = song.chords.entry(time).or_default();
+            if chord.contains(&note) {
+                continue;
+            }
+            if chord.len() == MAX_CHORD_SIZE {
+                song.dropped_notes += 1;
+                continue;
+            }
+            chord.push(note);
+        }
+        if song.chords.is_empty() {
+            bail!("the MIDI file does not contain any notes");
+        }
+        Ok(song)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Song> {
+        let data = std::fs::read(path)?;
+        Song::parse_midi(&data)
+    }
+
+    pub fn num_notes(&self) -> usize {
+        self.chords.values().map(Vec::len).sum()
+    }
+
+    /// The length of the song in redstone ticks
+    pub fn length(&self) -> u32 {
+        self.chords.keys().next_back().copied().unwrap_or(0)
+    }
+}
+
+/// A position along the main line of the song player
+struct Cell {
+    pos: BlockPos,
+    /// The direction towards the previous cell
+    from: BlockDirection,
+    /// The line turns here, so it can only hold dust
+    corner: bool,
+    /// A column can branch off of this cell without touching the rest of the line
+    column_ok: bool,
+}
+
+/// Lay out the main line in rows going back and forth along the x axis, each row further
+/// along the z axis.
+fn main_line(origin: BlockPos, max_x: i32, max_z: i32) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    let (start_x, end_x) = (origin.x, max_x);
+    let mut row_z = origin.z;
+    let mut row = 0;
+    // The note blocks of a row reach up to `ROW_SPACING - 3` past the row
+    while row_z + ROW_SPACING - 3 <= max_z {
+        let (xs, from): (Vec<i32>, _) = if row % 2 == 0 {
+            ((start_x..=end_x).collect(), BlockDirection::West)
+        } else {
+            ((start_x..=end_x).rev().collect(), BlockDirection::East)
+        };
+        let turn_x = *xs.last().unwrap();
+        for (i, &x) in xs.iter().enumerate() {
+            let first = i == 0 && row > 0;
+            let corner = first || i == xs.len() - 1;
+            cells.push(Cell {
+                pos: BlockPos::new(x, origin.y + 1, row_z),
+                from: if first { BlockDirection::North } else { from },
+                corner,
+                column_ok: !corner && x > start_x && (x - turn_x).abs() >= 3,
+            });
+        }
+        if row_z + 2 * ROW_SPACING - 3 > max_z {
+            break;
+        }
+        for dz in 1..ROW_SPACING {
+            cells.push(Cell {
+                pos: BlockPos::new(turn_x, origin.y + 1, row_z + dz),
+                from: BlockDirection::North,
+                corner: false,
+                column_ok: false,
+            });
+        }
+        row_z += ROW_SPACING;
+        row += 1;
+    }
+    cells
+}
+
+fn place_on_support(world: &mut PlotWorld, pos: BlockPos, block: Block) {
+    world.set_block(pos - BlockPos::new(0, 1, 0), Block::Stone {});
+    world.set_block(pos, block);
+}
+
+fn place_repeater(world: &mut PlotWorld, pos: BlockPos, facing: BlockDirection, delay: u8) {
+    let repeater = RedstoneRepeater {
+        delay,
+        facing,
+        locked: false,
+        powered: false,
+    };
+    place_on_support(world, pos, Block::RedstoneRepeater { repeater });
+}
+
+/// Build the song player starting at `origin`, where the lever that starts the song is placed.
+/// The player extends towards positive x and z up to `max_x` and `max_z`.
+pub fn build_song(
+    world: &mut PlotWorld,
+    song: &Song,
+    origin: BlockPos,
+    max_x: i32,
+    max_z: i32,
+) -> Result<()> {
+    let cells = main_line(origin, max_x, max_z);
+    if cells.is_empty() {
+        bail!("there is not enough space to build a song here");
+    }
+    let mut wires = Vec::new();
+    let mut place_wire = |world: &mut PlotWorld, pos: BlockPos| {
+        world.set_block(
+            pos,
+            Block::RedstoneWire {
+                wire: RedstoneWire::default(),
+            },
+        );
+        wires.push(pos);
+    };
+
+    let lever = Lever::new(LeverFace::Floor, BlockDirection::West, false);
+    place_on_support(world, cells[0].pos, Block::Lever { lever });
+
+    // The delay from the lever to the current cell
+    let mut delay = 0;
+    let mut idx = 1;
+    let mut last_column = 0;
+    let mut dust_run = 0;
+    for (&time, chord) in &song.chords {
+        // Every note is delayed by one tick so the first note can be after a repeater
+        let target = time + 1;
+        loop {
+            let Some(cell) = cells.get(idx) else {
+                bail!("the song is too long to fit here");
+            };
+            let need = target.saturating_sub(delay);
+            if need == 0 && cell.column_ok && idx - last_column >= 3 {
+                // The main line connects to the column with a repeater that powers the spine
+                world.set_block(cell.pos - BlockPos::new(0, 1, 0), Block::Stone {});
+                place_wire(world, cell.pos);
+                let branch_pos = cell.pos + BlockPos::new(0, 0, 1);
+                place_repeater(world, branch_pos, BlockDirection::North, 1);
+                for (i, &(instrument, note)) in chord.iter().enumerate() {
+                    let spine_pos = cell.pos + BlockPos::new(0, 0, 2 + i as i32 / 2);
+                    if i % 2 == 0 {
+                        world.set_block(spine_pos, Block::Stone {});
+                        place_wire(world, spine_pos + BlockPos::new(0, 1, 0));
+                    }
+                    let side = if i % 2 == 0 { -1 } else { 1 };
+                    let note_pos = spine_pos + BlockPos::new(side, 0, 0);
+                    world.set_block(
+                        note_pos - BlockPos::new(0, 1, 0),
+                        instrument_block(instrument),
+                    );
+                    world.set_block(
+                        note_pos,
+                        Block::NoteBlock {
+                            instrument,
+                            note,
+                            powered: false,
+                        },
+                    );
+                }
+                last_column = idx;
+                dust_run += 1;
+                idx += 1;
+                break;
+            }
+
+            if !cell.corner && (need > 0 || dust_run >= MAX_DUST_RUN) {
+                // If the dust is getting too long, a repeater has to be placed even though it
+                // delays the rest of the song by a tick
+                let repeater_delay = need.clamp(1, 4);
+                place_repeater(world, cell.pos, cell.from, repeater_delay as u8);
+                delay += repeater_delay;
+                dust_run = 0;
+            } else {
+                world.set_block(cell.pos - BlockPos::new(0, 1, 0), Block::Stone {});
+                place_wire(world, cell.pos);
+                dust_run += 1;
+            }
+            idx += 1;
+        }
+    }
+
+    // Now that everything is placed the wires can connect to their neighbors
+    for pos in wires {
+        let wire = redstone::wire::get_state_for_placement(world, pos);
+        world.set_block(pos, Block::RedstoneWire { wire });
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_midi_quantizes_notes() {
+    #[rustfmt::skip]
+    let data = [
+        // Header: format 0, 1 track, 96 ticks per quarter note
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 29,
+        // Tempo of 600000 microseconds per quarter note
+        0x00, 0xFF, 0x51, 0x03, 0x09, 0x27, 0xC0,
+        // Guitar on channel 0
+        0x00, 0xC0, 25,
+        // Middle C, then E a quarter note later using running status
+        0x00, 0x90, 60, 100,
+        0x60, 64, 100,
+        // Bass drum on the percussion channel at the same time
+        0x00, 0x99, 36, 100,
+        // Note off using a note on with a velocity of 0
+        0x10, 0x90, 60, 0,
+        // End of track
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let song = Song::parse_midi(&data).unwrap();
+    assert_eq!(song.num_notes(), 3);
+    assert_eq!(song.chords[&0], vec![(Instrument::Guitar, 18)]);
+    assert_eq!(
+        song.chords[&6],
+        vec![(Instrument::Guitar, 22), (Instrument::Basedrum, 6)]
+    );
+    assert_eq!(song.length(), 6);
+}
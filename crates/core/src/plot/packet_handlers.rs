@@ -1,7 +1,8 @@
-use super::Plot;
+use super::{worldedit, Plot};
 use crate::config::CONFIG;
 use crate::interaction::{self, UseOnBlockContext};
 use crate::player::{PacketSender, PlayerPos, SkinParts};
+use crate::redstone;
 use crate::server::Message;
 use crate::utils::HyphenatedUUID;
 use crate::world::World;
@@ -15,14 +16,14 @@ use mchprs_network::packets::SlotData;
 use serde_json::json;
 use std::fs;
 use std::path::PathBuf;
-use std::time::Instant;
 use tracing::error;
 
-const ERROR_IO_ONLY: &str = "This plot cannot be interacted with while redpiler is active with `--io-only`. To stop redpiler, run `/redpiler reset`.";
+pub(super) const ERROR_IO_ONLY: &str = "This plot cannot be interacted with while redpiler is active with `--io-only`. To stop redpiler, run `/redpiler reset`.";
 
 impl Plot {
     pub(super) fn handle_packets_for_player(&mut self, player: usize) {
-        let packets = self.players[player].client.receive_packets();
+        let mut packets = std::mem::take(&mut self.players[player].held_packets);
+        packets.extend(self.players[player].client.receive_packets());
         for packet in packets {
             packet.handle(self, player);
         }
@@ -79,7 +80,7 @@ impl ServerBoundPacketHandler for Plot {
     }
 
     fn handle_keep_alive(&mut self, _keep_alive: SKeepAlive, player_idx: usize) {
-        self.players[player_idx].last_keep_alive_received = Instant::now();
+        self.players[player_idx].keep_alive_received();
     }
 
     fn handle_creative_inventory_action(
@@ -193,6 +194,15 @@ impl ServerBoundPacketHandler for Plot {
             return;
         }
 
+        if self.players[player].inspecting {
+            // The client also sends a placement for the left hand
+            if player_block_placement.hand == 0 {
+                self.show_block_history(player, block_pos);
+            }
+            cancel(self);
+            return;
+        }
+
         if let Some(item) = &item_in_hand {
             let has_permission = self.players[player].has_permission("worldedit.selection.pos");
             if item.item_type == (Item::WEWand {}) && has_permission {
@@ -207,27 +217,61 @@ impl ServerBoundPacketHandler for Plot {
                 // redpiler will get reset anyways.
                 return;
             }
-        }
-
-        if let Some(owner) = self.owner {
-            let player = &mut self.players[player];
-            if owner != player.uuid && !player.has_permission("plots.admin.interact.other") {
-                player.send_no_permission_message();
+            // The client sends a use item packet next, which uses the brush
+            let item_type = item.item_type;
+            if self.players[player]
+                .brushes
+                .iter()
+                .any(|b| b.item == item_type)
+            {
                 cancel(self);
                 return;
             }
-        } else if !self.players[player].has_permission("plots.admin.interact.unowned") {
+        }
+
+        if !self.can_build(player) {
             self.players[player].send_no_permission_message();
             cancel(self);
             return;
         }
 
+        let block = self.world.get_block(block_pos);
+        let lever_or_button = matches!(block, Block::Lever { .. } | Block::StoneButton { .. });
+        // The client also sends a placement for the left hand
+        if lever_or_button && !self.players[player].crouching && player_block_placement.hand == 0 {
+            self.latency.on_use(self.players[player].uuid, block_pos);
+        }
+
+        // Projectiles aren't simulated, so target blocks are hit by using them with an empty hand
+        if matches!(block, Block::Target { .. })
+            && item_in_hand.is_none()
+            && !self.players[player].crouching
+            && player_block_placement.hand == 0
+        {
+            let cursor = (
+                player_block_placement.cursor_x,
+                player_block_placement.cursor_y,
+                player_block_placement.cursor_z,
+            );
+            let power = redstone::target::hit_strength(block_face, cursor);
+            let ticks = redstone::target::pulse_ticks();
+            if self.redpiler.is_compiled(block_pos) {
+                self.redpiler.hit_target(block_pos, power, ticks);
+            } else {
+                redstone::target::hit(&mut self.world, block_pos, power, ticks);
+                self.world.flush_block_changes();
+            }
+            return;
+        }
+
+        let mut suspended = false;
         if self.redpiler.is_active() {
-            let block = self.world.get_block(block_pos);
-            let lever_or_button = matches!(block, Block::Lever { .. } | Block::StoneButton { .. });
             if lever_or_button && !self.players[player].crouching {
-                self.redpiler.on_use_block(block_pos);
-                return;
+                // Outside of a compiled selection, levers and buttons are still interpreted
+                if self.redpiler.is_compiled(block_pos) {
+                    self.redpiler.on_use_block(block_pos);
+                    return;
+                }
             } else {
                 match self.redpiler.current_flags() {
                     Some(flags) if flags.io_only => {
@@ -237,11 +281,16 @@ impl ServerBoundPacketHandler for Plot {
                     }
                     _ => {}
                 }
-                self.reset_redpiler();
+                suspended = self.suspend_redpiler();
             }
         }
 
         if let Some(item) = item_in_hand {
+            let offset_pos = block_pos.offset(block_face);
+            let before = vec![
+                self.block_snapshot(block_pos),
+                self.block_snapshot(offset_pos),
+            ];
             let cancelled = interaction::use_item_on_block(
                 &item,
                 &mut self.world,
@@ -255,7 +304,11 @@ impl ServerBoundPacketHandler for Plot {
             if cancelled {
                 cancel(self);
             }
+            if suspended {
+                self.resume_redpiler();
+            }
             self.world.flush_block_changes();
+            self.log_block_changes(player, "placed", before);
             return;
         }
 
@@ -268,8 +321,11 @@ impl ServerBoundPacketHandler for Plot {
                 block_pos,
                 None,
             );
-            self.world.flush_block_changes();
         }
+        if suspended {
+            self.resume_redpiler();
+        }
+        self.world.flush_block_changes();
     }
 
     fn handle_chat_message(&mut self, chat_message: SChatMessage, player: usize) {
@@ -347,7 +403,7 @@ impl ServerBoundPacketHandler for Plot {
             };
             self.players[other_player].client.send_packet(&packet);
         }
-        self.on_player_move(player, old, new);
+        self.on_player_move(player, new);
     }
 
     fn handle_player_position_and_rotation(
@@ -408,7 +464,7 @@ impl ServerBoundPacketHandler for Plot {
                 .client
                 .send_packet(&entity_head_look);
         }
-        self.on_player_move(player, old, new);
+        self.on_player_move(player, new);
     }
 
     fn handle_player_rotation(&mut self, player_rotation: SPlayerRotation, player: usize) {
@@ -454,6 +510,12 @@ impl ServerBoundPacketHandler for Plot {
                 return;
             }
 
+            if self.players[player].inspecting {
+                self.show_block_history(player, block_pos);
+                self.send_block_change(block_pos, block.get_id());
+                return;
+            }
+
             // This worldedit wand stuff should probably be done in another file. It's good enough for now.
             let item_in_hand = self.players[player].inventory
                 [self.players[player].selected_slot as usize + 36]
@@ -472,14 +534,7 @@ impl ServerBoundPacketHandler for Plot {
                 }
             }
 
-            if let Some(owner) = self.owner {
-                let player = &mut self.players[player];
-                if owner != player.uuid && !player.has_permission("plots.admin.interact.other") {
-                    player.send_no_permission_message();
-                    self.send_block_change(block_pos, block.get_id());
-                    return;
-                }
-            } else if !self.players[player].has_permission("plots.admin.interact.unowned") {
+            if !self.can_build(player) {
                 self.players[player].send_no_permission_message();
                 self.send_block_change(block_pos, block.get_id());
                 return;
@@ -494,10 +549,15 @@ impl ServerBoundPacketHandler for Plot {
                 _ => {}
             }
 
-            self.reset_redpiler();
+            let suspended = self.suspend_redpiler();
 
+            let before = vec![self.block_snapshot(block_pos)];
             interaction::destroy(block, &mut self.world, block_pos);
+            if suspended {
+                self.resume_redpiler();
+            }
             self.world.flush_block_changes();
+            self.log_block_changes(player, "broke", before);
 
             let effect = CEffect {
                 effect_id: 2001,
@@ -573,6 +633,17 @@ impl ServerBoundPacketHandler for Plot {
         }
     }
 
+    fn handle_use_item(&mut self, use_item: SUseItem, player: usize) {
+        let slot = match use_item.hand {
+            0 => self.players[player].selected_slot as usize + 36,
+            _ => 45,
+        };
+        let item = self.players[player].inventory[slot].as_ref();
+        if let Some(item) = item.map(|item| item.item_type) {
+            worldedit::use_brush(self, player, item);
+        }
+    }
+
     fn handle_held_item_change(&mut self, held_item_change: SHeldItemChange, player: usize) {
         let entity_equipment = CEntityEquipment {
             entity_id: self.players[player].entity_id as i32,
@@ -0,0 +1,182 @@
+//! Named inputs and outputs of the circuit in a plot, like `CLK` or `A0` to `A7`, so that
+//! players and external tools can use them without knowing their coordinates. The ports of a
+//! plot are saved in the plot database and can be exported as a json manifest.
+
+use super::display;
+use super::PlotWorld;
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The longest name a port can have
+pub const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortDirection {
+    Input,
+    Output,
+}
+
+/// Levers, buttons and pressure plates are inputs, every other block with a signal is an output
+pub fn port_direction(world: &PlotWorld, pos: BlockPos) -> Option<PortDirection> {
+    match world.get_block(pos) {
+        Block::Lever { .. } | Block::StoneButton { .. } | Block::StonePressurePlate { .. } => {
+            Some(PortDirection::Input)
+        }
+        _ if display::is_signal_block(world, pos) => Some(PortDirection::Output),
+        _ => None,
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestPort {
+    pub name: String,
+    pub pos: BlockPos,
+    /// The name of the block, like `lever`
+    pub block: &'static str,
+}
+
+/// The interface of the circuit in a plot
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    pub plot_x: i32,
+    pub plot_z: i32,
+    pub inputs: Vec<ManifestPort>,
+    pub outputs: Vec<ManifestPort>,
+    /// Ports whose block was removed or replaced by a block without a signal
+    pub missing: Vec<ManifestPort>,
+}
+
+/// The ports of a plot by name
+#[derive(Debug, Default)]
+pub struct Ports {
+    ports: BTreeMap<String, BlockPos>,
+}
+
+impl FromIterator<(String, BlockPos)> for Ports {
+    fn from_iter<I: IntoIterator<Item = (String, BlockPos)>>(iter: I) -> Ports {
+        Ports {
+            ports: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Ports {
+    pub fn get(&self, name: &str) -> Option<BlockPos> {
+        self.ports.get(name).copied()
+    }
+
+    /// Names the block at `pos`. A block only has one name, so a previous name of the block is
+    /// returned to be removed.
+    pub fn insert(&mut self, name: String, pos: BlockPos) -> Option<String> {
+        let previous = self
+            .ports
+            .iter()
+            .find(|&(other, &other_pos)| other_pos == pos && *other != name)
+            .map(|(other, _)| other.clone());
+        if let Some(previous) = &previous {
+            self.ports.remove(previous);
+        }
+        self.ports.insert(name, pos);
+        previous
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<BlockPos> {
+        self.ports.remove(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, BlockPos)> {
+        self.ports.iter().map(|(name, pos)| (name.as_str(), *pos))
+    }
+
+    pub fn manifest(&self, world: &PlotWorld) -> Manifest {
+        let mut manifest = Manifest {
+            plot_x: world.x,
+            plot_z: world.z,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            missing: Vec::new(),
+        };
+        for (name, pos) in self.iter() {
+            let port = ManifestPort {
+                name: name.to_owned(),
+                pos,
+                block: world.get_block(pos).get_name(),
+            };
+            match port_direction(world, pos) {
+                Some(PortDirection::Input) => manifest.inputs.push(port),
+                Some(PortDirection::Output) => manifest.outputs.push(port),
+                None => manifest.missing.push(port),
+            }
+        }
+        manifest
+    }
+}
+
+#[test]
+fn ports_are_unique_per_block() {
+    let mut ports = Ports::default();
+    let pos = BlockPos::new(1, 2, 3);
+    assert_eq!(ports.insert("CLK".to_owned(), pos), None);
+    assert_eq!(ports.insert("CLK".to_owned(), pos), None);
+    // Renaming a block removes its old name
+    assert_eq!(ports.insert("A0".to_owned(), pos), Some("CLK".to_owned()));
+    assert_eq!(ports.get("CLK"), None);
+    assert_eq!(ports.get("A0"), Some(pos));
+    // A name can be moved to another block
+    let other = BlockPos::new(4, 5, 6);
+    assert_eq!(ports.insert("A0".to_owned(), other), None);
+    assert_eq!(ports.iter().collect::<Vec<_>>(), [("A0", other)]);
+
+    assert!(is_valid_name("A_7-b"));
+    assert!(!is_valid_name(""));
+    assert!(!is_valid_name("../A0"));
+}
+
+#[test]
+fn port_manifest_splits_inputs_and_outputs() {
+    use crate::plot::{build_lever_line, redpiler_test_world};
+    use serde_json::json;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[]);
+
+    let mut ports: Ports = [
+        ("CLK".to_owned(), lever_pos),
+        ("OUT".to_owned(), lamp_pos),
+        ("GONE".to_owned(), BlockPos::new(12, 8, 10)),
+    ]
+    .into_iter()
+    .collect();
+    let manifest = ports.manifest(&world);
+    let names = |ports: &[ManifestPort]| -> Vec<String> {
+        ports.iter().map(|port| port.name.clone()).collect()
+    };
+    assert_eq!(names(&manifest.inputs), ["CLK"]);
+    assert_eq!(names(&manifest.outputs), ["OUT"]);
+    assert_eq!(names(&manifest.missing), ["GONE"]);
+    assert_eq!(manifest.inputs[0].block, "lever");
+    assert_eq!(
+        serde_json::to_value(&manifest.outputs[0]).unwrap(),
+        json!({ "name": "OUT", "pos": lamp_pos, "block": "redstone_lamp" })
+    );
+
+    ports.remove("GONE");
+    assert!(ports.manifest(&world).missing.is_empty());
+}
@@ -0,0 +1,147 @@
+//! Stone pressure plates pressed by players. A plate is pressed as soon as the hitbox of a player
+//! overlaps it. Like in vanilla, a pressed plate then checks every 10 redstone ticks (20 game
+//! ticks) whether it is still being stood on and is only released by a check that finds nobody
+//! on it, so it stays powered for 10 to 20 redstone ticks after the player steps off.
+//!
+//! There are no other entities in the plot, so only players press plates.
+
+use crate::player::{Gamemode, Player, PlayerPos};
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use rustc_hash::FxHashMap;
+use std::collections::HashSet;
+
+/// The redstone ticks between two checks of a pressed plate
+pub const CHECK_INTERVAL: u32 = 10;
+
+const PLAYER_HALF_WIDTH: f64 = 0.3;
+const PLAYER_HEIGHT: f64 = 1.8;
+/// Plates detect entities everywhere above them except for a 1 pixel border
+const PLATE_INSET: f64 = 1.0 / 16.0;
+const PLATE_DETECTION_HEIGHT: f64 = 0.25;
+
+/// Whether `player` presses the plates they stand on. Spectators don't touch anything, and players
+/// in `denied` aren't allowed to use anything in the plot.
+pub fn presses_plates(player: &Player, denied: &HashSet<u128>) -> bool {
+    !matches!(player.gamemode, Gamemode::Spectator) && !denied.contains(&player.uuid)
+}
+
+/// Returns true if the hitbox of a player at `pos` overlaps the detection area of a plate at
+/// `plate`
+pub fn is_on_plate(pos: PlayerPos, plate: BlockPos) -> bool {
+    let overlaps = |min: f64, max: f64, block: i32, inset: f64, height: f64| {
+        min < block as f64 + inset + height && max > block as f64 + inset
+    };
+    let width = 1.0 - 2.0 * PLATE_INSET;
+    overlaps(
+        pos.x - PLAYER_HALF_WIDTH,
+        pos.x + PLAYER_HALF_WIDTH,
+        plate.x,
+        PLATE_INSET,
+        width,
+    ) && overlaps(
+        pos.z - PLAYER_HALF_WIDTH,
+        pos.z + PLAYER_HALF_WIDTH,
+        plate.z,
+        PLATE_INSET,
+        width,
+    ) && overlaps(
+        pos.y,
+        pos.y + PLAYER_HEIGHT,
+        plate.y,
+        0.0,
+        PLATE_DETECTION_HEIGHT,
+    )
+}
+
+/// Returns the pressure plates that a player at `pos` is pressing
+pub fn plates_under(world: &impl World, pos: PlayerPos) -> Vec<BlockPos> {
+    let floor = |val: f64| val.floor() as i32;
+    let mut plates = Vec::new();
+    for y in floor(pos.y - PLATE_DETECTION_HEIGHT)..=floor(pos.y + PLAYER_HEIGHT) {
+        for z in floor(pos.z - PLAYER_HALF_WIDTH)..=floor(pos.z + PLAYER_HALF_WIDTH) {
+            for x in floor(pos.x - PLAYER_HALF_WIDTH)..=floor(pos.x + PLAYER_HALF_WIDTH) {
+                let plate = BlockPos::new(x, y, z);
+                if matches!(world.get_block(plate), Block::StonePressurePlate { .. })
+                    && is_on_plate(pos, plate)
+                {
+                    plates.push(plate);
+                }
+            }
+        }
+    }
+    plates
+}
+
+/// The plates that are pressed, with the number of ticks until their next check
+#[derive(Default)]
+pub struct PressedPlates {
+    checks: FxHashMap<BlockPos, u32>,
+}
+
+impl PressedPlates {
+    /// Starts the checks of a plate that was stepped on. Returns false if it was already pressed.
+    pub fn press(&mut self, pos: BlockPos) -> bool {
+        if self.checks.contains_key(&pos) {
+            return false;
+        }
+        self.checks.insert(pos, CHECK_INTERVAL);
+        true
+    }
+
+    /// Advances the checks by a tick and returns the plates that have to be checked now. Plates
+    /// that are still pressed have to be passed to [`PressedPlates::keep`].
+    pub fn tick(&mut self) -> Vec<BlockPos> {
+        if self.checks.is_empty() {
+            return Vec::new();
+        }
+        let mut due = Vec::new();
+        self.checks.retain(|&pos, ticks| {
+            *ticks -= 1;
+            if *ticks == 0 {
+                due.push(pos);
+            }
+            *ticks > 0
+        });
+        due
+    }
+
+    /// Schedules the next check of a plate that is still being stood on
+    pub fn keep(&mut self, pos: BlockPos) {
+        self.checks.insert(pos, CHECK_INTERVAL);
+    }
+}
+
+#[test]
+fn players_press_plates_their_hitbox_overlaps() {
+    let plate = BlockPos::new(3, 8, 5);
+    let standing = |x, z| PlayerPos::new(x, 8.0625, z);
+    assert!(is_on_plate(standing(3.5, 5.5), plate));
+    // The edge of the hitbox reaches the plate from the next block over
+    assert!(is_on_plate(standing(2.8, 5.5), plate));
+    assert!(!is_on_plate(standing(2.7, 5.5), plate));
+    assert!(!is_on_plate(standing(3.5, 4.7), plate));
+    // Jumping high enough leaves the detection area
+    assert!(is_on_plate(PlayerPos::new(3.5, 8.2, 5.5), plate));
+    assert!(!is_on_plate(PlayerPos::new(3.5, 8.3, 5.5), plate));
+}
+
+#[test]
+fn pressed_plates_are_checked_every_interval() {
+    let pos = BlockPos::new(0, 0, 0);
+    let mut plates = PressedPlates::default();
+    assert!(plates.press(pos));
+    assert!(!plates.press(pos));
+    for _ in 1..CHECK_INTERVAL {
+        assert!(plates.tick().is_empty());
+    }
+    assert_eq!(plates.tick(), vec![pos]);
+    plates.keep(pos);
+    for _ in 1..CHECK_INTERVAL {
+        assert!(plates.tick().is_empty());
+    }
+    // Nobody is on it anymore, so it is released and can be pressed again
+    assert_eq!(plates.tick(), vec![pos]);
+    assert!(plates.press(pos));
+}
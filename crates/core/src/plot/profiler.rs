@@ -0,0 +1,141 @@
+//! A profiler that records how long the parts of a plot update take, so that players can find
+//! out what is slowing their plot down. It is started with `/profile start` and keeps a rolling
+//! window of samples for every section, from which `/profile` reports percentiles.
+//!
+//! Nothing is measured while the profiler is stopped, which keeps it from slowing down plots
+//! running at high rtps.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The number of samples that are kept for every section
+const WINDOW_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    RedpilerTick,
+    RedpilerFlush,
+    /// Ticking the blocks that redpiler isn't simulating
+    WorldTick,
+    Packets,
+}
+
+impl Section {
+    const ALL: [Section; 4] = [
+        Section::RedpilerTick,
+        Section::RedpilerFlush,
+        Section::WorldTick,
+        Section::Packets,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::RedpilerTick => "Redpiler tick",
+            Section::RedpilerFlush => "Redpiler flush",
+            Section::WorldTick => "World tick",
+            Section::Packets => "Packets",
+        }
+    }
+}
+
+pub struct SectionReport {
+    pub section: Section,
+    pub samples: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Returns the nearest-rank percentile of `sorted`, which must not be empty
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    running: bool,
+    samples: [VecDeque<Duration>; Section::ALL.len()],
+}
+
+impl Profiler {
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Starts profiling, throwing away the samples of a previous run
+    pub fn start(&mut self) {
+        self.running = true;
+        self.samples = Default::default();
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+        self.samples = Default::default();
+    }
+
+    /// Returns the start time of a section that is passed to [`Profiler::record`], or `None` if
+    /// the profiler isn't running
+    pub fn begin(&self) -> Option<Instant> {
+        self.running.then(Instant::now)
+    }
+
+    pub fn record(&mut self, section: Section, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.add_sample(section, start.elapsed());
+        }
+    }
+
+    fn add_sample(&mut self, section: Section, duration: Duration) {
+        let samples = &mut self.samples[section as usize];
+        if samples.len() == WINDOW_SIZE {
+            samples.pop_front();
+        }
+        samples.push_back(duration);
+    }
+
+    /// Reports the percentiles of every section that has samples
+    pub fn report(&self) -> Vec<SectionReport> {
+        Section::ALL
+            .iter()
+            .filter_map(|&section| {
+                let samples = &self.samples[section as usize];
+                if samples.is_empty() {
+                    return None;
+                }
+                let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+                sorted.sort_unstable();
+                Some(SectionReport {
+                    section,
+                    samples: sorted.len(),
+                    p50: percentile(&sorted, 50),
+                    p95: percentile(&sorted, 95),
+                    p99: percentile(&sorted, 99),
+                })
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn profiler_reports_percentiles() {
+    let mut profiler = Profiler::default();
+    profiler.start();
+    for micros in 1..=200 {
+        profiler.add_sample(Section::RedpilerTick, Duration::from_micros(micros));
+    }
+    let report = profiler.report();
+    assert_eq!(report.len(), 1);
+    let report = &report[0];
+    assert_eq!(report.section, Section::RedpilerTick);
+    assert_eq!(report.samples, 200);
+    assert_eq!(report.p50, Duration::from_micros(100));
+    assert_eq!(report.p95, Duration::from_micros(190));
+    assert_eq!(report.p99, Duration::from_micros(198));
+
+    // Only the most recent samples are kept
+    for _ in 0..WINDOW_SIZE {
+        profiler.add_sample(Section::RedpilerTick, Duration::from_micros(7));
+    }
+    assert_eq!(profiler.report()[0].p99, Duration::from_micros(7));
+}
@@ -0,0 +1,192 @@
+//! Answers the requests that remote clients send to this plot, see [`crate::remote`]. Plots
+//! without players don't tick on their own, so remote clients advance them with `step`.
+
+use super::Plot;
+use crate::remote::{Command, RemoteClient, RemoteRequest, Target};
+use mchprs_blocks::BlockPos;
+use serde_json::{json, Value};
+use std::time::Instant;
+
+struct Subscription {
+    client: RemoteClient,
+    /// The targets with their position and last signal strength
+    targets: Vec<(Target, BlockPos, u8)>,
+}
+
+/// The remote clients that get events when blocks in the plot change
+#[derive(Default)]
+pub struct RemoteSubscriptions {
+    subscriptions: Vec<Subscription>,
+}
+
+impl RemoteSubscriptions {
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Adds targets to the subscription of a client, along with their current signal strength
+    pub fn subscribe(&mut self, client: &RemoteClient, targets: Vec<(Target, BlockPos, u8)>) {
+        let index = self
+            .subscriptions
+            .iter()
+            .position(|subscription| subscription.client.id == client.id);
+        let index = index.unwrap_or_else(|| {
+            self.subscriptions.push(Subscription {
+                client: client.clone(),
+                targets: Vec::new(),
+            });
+            self.subscriptions.len() - 1
+        });
+        let subscription = &mut self.subscriptions[index];
+        for target in targets {
+            if !subscription
+                .targets
+                .iter()
+                .any(|(other, ..)| *other == target.0)
+            {
+                subscription.targets.push(target);
+            }
+        }
+    }
+
+    pub fn unsubscribe(&mut self, client_id: u64) {
+        self.subscriptions
+            .retain(|subscription| subscription.client.id != client_id);
+    }
+
+    /// Sends an event for every target whose signal strength changed since the last time, and
+    /// forgets the clients that disconnected
+    pub fn notify(&mut self, plot: (i32, i32), signal_strength: impl Fn(BlockPos) -> u8) {
+        self.subscriptions.retain_mut(|subscription| {
+            for (target, pos, last_ss) in &mut subscription.targets {
+                let ss = signal_strength(*pos);
+                if ss == *last_ss {
+                    continue;
+                }
+                *last_ss = ss;
+                let mut event = serde_json::to_value(&*target).unwrap();
+                event["event"] = json!("change");
+                event["plot"] = json!(plot);
+                event["ss"] = json!(ss);
+                if !subscription.client.send(&event) {
+                    return false;
+                }
+            }
+            subscription.client.is_connected()
+        });
+    }
+}
+
+impl Plot {
+    fn remote_target_pos(&self, target: &Target) -> Result<BlockPos, String> {
+        match target {
+            Target::Port { port } => self
+                .ports
+                .get(port)
+                .ok_or_else(|| format!("There is no port named {}.", port)),
+            Target::Pos { pos } => {
+                if !Plot::in_plot_bounds(self.world.x, self.world.z, pos.x, pos.z)
+                    || !(0..self.world.size.block_height()).contains(&pos.y)
+                {
+                    return Err(format!("{} is outside of the plot.", pos));
+                }
+                Ok(*pos)
+            }
+        }
+    }
+
+    fn answer_remote_request(&mut self, request: &RemoteRequest) -> Result<Value, String> {
+        match &request.command {
+            Command::Set { target, powered } => {
+                let pos = self.remote_target_pos(target)?;
+                self.set_input(pos, *powered)?;
+                Ok(Value::Null)
+            }
+            Command::Get { target } => {
+                let ss = self.signal_strength(self.remote_target_pos(target)?);
+                Ok(json!({ "ss": ss, "powered": ss > 0 }))
+            }
+            Command::Step { ticks } => {
+                for _ in 0..*ticks {
+                    self.tick();
+                }
+                self.world.flush_block_changes();
+                // Clients get the events of the step before its answer
+                self.notify_remote_subscribers();
+                Ok(json!({ "ticks": ticks }))
+            }
+            Command::Subscribe { targets } => {
+                let mut subscribed = Vec::new();
+                for target in targets {
+                    let pos = self.remote_target_pos(target)?;
+                    subscribed.push((target.clone(), pos, self.signal_strength(pos)));
+                }
+                let values: Vec<u8> = subscribed.iter().map(|&(_, _, ss)| ss).collect();
+                self.remote_subscriptions
+                    .subscribe(&request.client, subscribed);
+                Ok(json!({ "ss": values }))
+            }
+            Command::Unsubscribe => {
+                self.remote_subscriptions.unsubscribe(request.client.id);
+                Ok(Value::Null)
+            }
+            Command::Manifest => {
+                Ok(serde_json::to_value(self.ports.manifest(&self.world)).unwrap())
+            }
+        }
+    }
+
+    pub(super) fn handle_remote_request(&mut self, request: RemoteRequest) {
+        // Plots stay loaded while they are used remotely
+        self.last_player_time = Instant::now();
+        let result = self.answer_remote_request(&request);
+        request.reply(result);
+    }
+
+    pub(super) fn notify_remote_subscribers(&mut self) {
+        if self.remote_subscriptions.is_empty() {
+            return;
+        }
+        let plot = (self.world.x, self.world.z);
+        let mut subscriptions = std::mem::take(&mut self.remote_subscriptions);
+        subscriptions.notify(plot, |pos| self.signal_strength(pos));
+        self.remote_subscriptions = subscriptions;
+    }
+}
+
+#[test]
+fn subscribers_get_an_event_per_change() {
+    use std::cell::Cell;
+    use std::sync::mpsc;
+
+    let (sender, receiver) = mpsc::channel();
+    let client = RemoteClient::new(0, sender);
+    let clk = Target::Port {
+        port: "CLK".to_owned(),
+    };
+    let pos = BlockPos::new(8, 9, 4);
+    let mut subscriptions = RemoteSubscriptions::default();
+    subscriptions.subscribe(&client, vec![(clk.clone(), pos, 0)]);
+    // Subscribing to the same target again doesn't send its events twice
+    subscriptions.subscribe(&client, vec![(clk, pos, 0)]);
+
+    let ss = Cell::new(0);
+    subscriptions.notify((1, -1), |_| ss.get());
+    assert!(receiver.try_recv().is_err());
+
+    ss.set(15);
+    subscriptions.notify((1, -1), |_| ss.get());
+    subscriptions.notify((1, -1), |_| ss.get());
+    let event: Value = serde_json::from_str(&receiver.try_recv().unwrap()).unwrap();
+    assert_eq!(
+        event,
+        json!({"event": "change", "plot": [1, -1], "port": "CLK", "ss": 15})
+    );
+    assert!(receiver.try_recv().is_err());
+
+    // Clients that disconnected are forgotten
+    drop(receiver);
+    ss.set(0);
+    subscriptions.notify((1, -1), |_| ss.get());
+    assert!(subscriptions.is_empty());
+}
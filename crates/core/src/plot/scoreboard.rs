@@ -1,6 +1,6 @@
 use crate::chat::{ChatComponentBuilder, ColorCode};
 use crate::player::{PacketSender, Player};
-use crate::redpiler::CompilerOptions;
+use crate::redpiler::{BackendKind, CompilerOptions};
 use mchprs_network::packets::clientbound::{
     CDisplayScoreboard, CScoreboardObjective, CUpdateScore, ClientBoundPacket,
 };
@@ -123,6 +123,22 @@ impl Scoreboard {
         if options.io_only {
             flags.push("§b- io only");
         }
+        if options.io_only && options.visual_sync.is_some() {
+            flags.push("§b- visual sync");
+        }
+        if options.selection {
+            flags.push("§b- selection");
+        }
+        if options.wire_delay {
+            flags.push("§b- wire delay");
+        }
+        if options.incremental {
+            flags.push("§b- incremental");
+        }
+        match options.backend {
+            BackendKind::Auto => {}
+            BackendKind::Direct => flags.push("§b- direct backend"),
+        }
 
         if !flags.is_empty() {
             new_lines.push("§7Flags:".to_string());
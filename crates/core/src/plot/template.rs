@@ -0,0 +1,135 @@
+use super::worldedit::{PatternParseResult, WorldEditPattern};
+use super::PlotSize;
+use crate::world::storage::Chunk;
+use serde::{Deserialize, Serialize};
+
+/// Describes how the terrain of a new plot is generated. Blocks are given as worldedit
+/// patterns, so weighted mixes like `50%stone,50%andesite` can be used as well.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PlotTemplate {
+    /// The number of floor layers, the top of the floor is at `floor_height - 1`
+    pub floor_height: u32,
+    pub floor_block: String,
+    pub border_block: String,
+    /// The distance between gridlines in the top layer of the floor, 0 to disable
+    pub grid_spacing: u32,
+    pub grid_block: String,
+    /// The height of a wall along the north side of the plot to build inputs and outputs on,
+    /// 0 to disable
+    pub io_wall_height: u32,
+    pub io_wall_block: String,
+}
+
+impl Default for PlotTemplate {
+    fn default() -> Self {
+        PlotTemplate {
+            floor_height: 8,
+            floor_block: "sandstone".to_string(),
+            // Stone bricks
+            border_block: "4564".to_string(),
+            grid_spacing: 0,
+            grid_block: "smooth_quartz".to_string(),
+            io_wall_height: 0,
+            io_wall_block: "white_concrete".to_string(),
+        }
+    }
+}
+
+pub struct TemplatePalette {
+    floor: WorldEditPattern,
+    border: WorldEditPattern,
+    grid: WorldEditPattern,
+    io_wall: WorldEditPattern,
+}
+
+impl PlotTemplate {
+    pub fn palette(&self) -> PatternParseResult<TemplatePalette> {
+        Ok(TemplatePalette {
+            floor: self.floor_block.parse()?,
+            border: self.border_block.parse()?,
+            grid: self.grid_block.parse()?,
+            io_wall: self.io_wall_block.parse()?,
+        })
+    }
+
+    fn block_at(
+        &self,
+        palette: &TemplatePalette,
+        size: PlotSize,
+        x: i32,
+        y: u32,
+        z: i32,
+    ) -> Option<u32> {
+        let width = size.block_width();
+        let x = x.rem_euclid(width);
+        let z = z.rem_euclid(width);
+        let on_border = x == 0 || z == 0 || x == width - 1 || z == width - 1;
+
+        let pattern = if y < self.floor_height {
+            let spacing = self.grid_spacing as i32;
+            let on_grid = spacing > 0 && (x % spacing == 0 || z % spacing == 0);
+            if on_border {
+                &palette.border
+            } else if y == self.floor_height - 1 && on_grid {
+                &palette.grid
+            } else {
+                &palette.floor
+            }
+        } else if y < self.floor_height + self.io_wall_height && z == 1 && !on_border {
+            &palette.io_wall
+        } else {
+            return None;
+        };
+        Some(pattern.pick().get_id())
+    }
+
+    /// Generate the chunk at the absolute chunk coordinates `x` and `z` of a world with plots
+    /// of `size`
+    pub fn generate_chunk(
+        &self,
+        palette: &TemplatePalette,
+        size: PlotSize,
+        x: i32,
+        z: i32,
+    ) -> Chunk {
+        let mut chunk = Chunk::empty(x, z, size.sections);
+
+        let height = (self.floor_height + self.io_wall_height).min(size.block_height() as u32);
+        for ry in 0..height {
+            for rx in 0..16 {
+                for rz in 0..16 {
+                    let block_x = (x << 4) | rx;
+                    let block_z = (z << 4) | rz;
+                    if let Some(block) = self.block_at(palette, size, block_x, ry, block_z) {
+                        chunk.set_block(rx as u32, ry, rz as u32, block);
+                    }
+                }
+            }
+        }
+        chunk
+    }
+
+    /// Generate all chunks of the plot at `plot_x` and `plot_z` in storage order
+    pub fn generate_plot(
+        &self,
+        size: PlotSize,
+        plot_x: i32,
+        plot_z: i32,
+    ) -> PatternParseResult<Vec<Chunk>> {
+        let palette = self.palette()?;
+        let width = size.width();
+        let mut chunks = Vec::new();
+        for chunk_x in 0..width {
+            for chunk_z in 0..width {
+                chunks.push(self.generate_chunk(
+                    &palette,
+                    size,
+                    plot_x * width + chunk_x,
+                    plot_z * width + chunk_z,
+                ));
+            }
+        }
+        Ok(chunks)
+    }
+}
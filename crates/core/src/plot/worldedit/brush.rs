@@ -0,0 +1,150 @@
+//! Brushes are bound to an item with `/brush`. Using the item sets the blocks in a sphere or
+//! cylinder around the block the player is looking at, which can be limited to the blocks that
+//! match a mask.
+
+use super::{capture_undo, ray_trace_block, undo_changes, WorldEditPattern};
+use crate::player::PacketSender;
+use crate::plot::Plot;
+use crate::world::World;
+use mchprs_blocks::aabb::Aabb;
+use mchprs_blocks::items::Item;
+use mchprs_blocks::BlockPos;
+
+pub const MAX_BRUSH_RADIUS: u32 = 32;
+/// How far away the block a brush is used on can be
+const BRUSH_RANGE: f64 = 300.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Sphere,
+    /// A cylinder that goes up from the block the brush is used on
+    Cylinder {
+        height: u32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Brush {
+    pub item: Item,
+    pub shape: BrushShape,
+    pub radius: u32,
+    pub pattern: WorldEditPattern,
+    /// Only the blocks that match the mask are replaced
+    pub mask: Option<WorldEditPattern>,
+}
+
+impl Brush {
+    /// The positions the brush changes when it is used on `center`, limited to the blocks with
+    /// a y coordinate in `0..world_height`
+    fn positions(&self, center: BlockPos, world_height: i32) -> Vec<BlockPos> {
+        let r = self.radius as i32;
+        // The half block makes the edges round like they are in WorldEdit
+        let max_dist = (self.radius as f64 + 0.5).powi(2);
+        let (min_y, max_y) = match self.shape {
+            BrushShape::Sphere => (-r, r),
+            BrushShape::Cylinder { height } => (0, height.min(world_height as u32) as i32 - 1),
+        };
+        // Clamped before generating any positions so a huge brush can't take up a lot of memory
+        let min_y = min_y.max(-center.y);
+        let max_y = max_y.min(world_height - 1 - center.y);
+        let mut positions = Vec::new();
+        for y in min_y..=max_y {
+            for z in -r..=r {
+                for x in -r..=r {
+                    let dist = match self.shape {
+                        BrushShape::Sphere => x * x + y * y + z * z,
+                        BrushShape::Cylinder { .. } => x * x + z * z,
+                    };
+                    if dist as f64 <= max_dist {
+                        positions.push(center + BlockPos::new(x, y, z));
+                    }
+                }
+            }
+        }
+        positions
+    }
+}
+
+/// Uses the brush that is bound to `item`, if there is one, on the block the player is looking at
+pub fn use_brush(plot: &mut Plot, player_idx: usize, item: Item) {
+    let player = &plot.players[player_idx];
+    let brush = player.brushes.iter().find(|brush| brush.item == item);
+    let Some(brush) = brush.cloned() else {
+        return;
+    };
+    let wea = player.has_permission("plots.worldedit.bypass");
+    if (!wea && !plot.is_member(player.uuid)) || !player.has_permission("worldedit.brush") {
+        player.send_no_permission_message();
+        return;
+    }
+    let (pitch, yaw) = (player.pitch as f64, player.yaw as f64);
+    let Some(target) = ray_trace_block(&plot.world, player.pos, pitch, yaw, BRUSH_RANGE) else {
+        player.send_error_message("No block in sight!");
+        return;
+    };
+
+    let (plot_x, plot_z) = (plot.world.x, plot.world.z);
+    let height = plot.world.size.block_height();
+    let positions: Vec<BlockPos> = brush
+        .positions(target, height)
+        .into_iter()
+        .filter(|pos| Plot::in_plot_bounds(plot_x, plot_z, pos.x, pos.z))
+        .collect();
+    let Some(region) = Aabb::enclosing(positions.iter().copied()) else {
+        return;
+    };
+
+    plot.reset_redpiler();
+    capture_undo(
+        &mut plot.world,
+        &mut plot.players[player_idx],
+        region.min,
+        region.max,
+    );
+    for pos in positions {
+        if let Some(mask) = &brush.mask {
+            if !mask.matches(plot.world.get_block(pos)) {
+                continue;
+            }
+        }
+        plot.world.set_block_raw(pos, brush.pattern.pick().get_id());
+    }
+    plot.world.flush_block_changes();
+
+    let undo = plot.players[player_idx].worldedit_undo.last().unwrap();
+    let changes = undo_changes(&plot.world, undo);
+    plot.log_edit(player_idx, "brush", &changes);
+}
+
+#[test]
+fn brush_shapes() {
+    let brush = |shape, radius| Brush {
+        item: Item::WEWand {},
+        shape,
+        radius,
+        pattern: "stone".parse().unwrap(),
+        mask: None,
+    };
+    let center = BlockPos::new(10, 20, 30);
+
+    let sphere = brush(BrushShape::Sphere, 1).positions(center, 256);
+    assert_eq!(sphere.len(), 19);
+    assert!(sphere.contains(&BlockPos::new(11, 21, 30)));
+    assert!(!sphere.contains(&BlockPos::new(11, 21, 31)));
+
+    let cylinder = brush(BrushShape::Cylinder { height: 3 }, 2).positions(center, 256);
+    assert_eq!(cylinder.len(), 21 * 3);
+    assert!(cylinder.iter().all(|pos| (20..23).contains(&pos.y)));
+    assert!(cylinder.contains(&BlockPos::new(12, 22, 31)));
+    assert!(!cylinder.contains(&BlockPos::new(12, 20, 32)));
+
+    assert_eq!(
+        brush(BrushShape::Sphere, 0).positions(center, 256),
+        vec![center]
+    );
+
+    let clamped = brush(BrushShape::Cylinder { height: u32::MAX }, 0).positions(center, 256);
+    assert_eq!(clamped.len(), 256 - 20);
+    let clamped = brush(BrushShape::Sphere, 2).positions(BlockPos::new(0, 0, 0), 1);
+    assert!(clamped.iter().all(|pos| pos.y == 0));
+}
@@ -1,17 +1,21 @@
+use super::brush::{BrushShape, MAX_BRUSH_RADIUS};
+use super::expression::Expression;
 use super::*;
 use crate::chat::{ChatComponentBuilder, ColorCode};
 use crate::config::CONFIG;
 use crate::player::PacketSender;
-use crate::plot::PLOT_BLOCK_HEIGHT;
 use crate::utils::HyphenatedUUID;
-use mchprs_blocks::block_entities::InventoryEntry;
-use mchprs_blocks::blocks::{Block, FlipDirection, RotateAmt};
+use mchprs_blocks::blocks::{Block, RotateAmt};
 use mchprs_blocks::items::{Item, ItemStack};
 use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
 use mchprs_network::packets::clientbound::*;
 use mchprs_network::packets::SlotData;
+use netlist::load_netlist;
 use once_cell::sync::Lazy;
+use rom::{format_rom, parse_rom, RomCells};
 use schematic::{load_schematic, save_schematic};
+use std::fs;
+use std::path::Path;
 use std::time::Instant;
 use tracing::error;
 
@@ -52,16 +56,11 @@ pub(super) fn execute_set(ctx: CommandExecuteContext<'_>) {
         ctx.player.first_position.unwrap(),
         ctx.player.second_position.unwrap(),
     );
-    for x in operation.x_range() {
-        for y in operation.y_range() {
-            for z in operation.z_range() {
-                let block_pos = BlockPos::new(x, y, z);
-                let block_id = pattern.pick().get_id();
-
-                if ctx.plot.set_block_raw(block_pos, block_id) {
-                    operation.update_block();
-                }
-            }
+    for block_pos in operation.region() {
+        let block_id = pattern.pick().get_id();
+
+        if ctx.plot.set_block_raw(block_pos, block_id) {
+            operation.update_block();
         }
     }
 
@@ -74,6 +73,76 @@ pub(super) fn execute_set(ctx: CommandExecuteContext<'_>) {
     ));
 }
 
+/// The most operations an expression of `//generate` can take for the whole selection
+const GENERATE_BUDGET: u64 = 50_000_000;
+
+pub(super) fn execute_generate(ctx: CommandExecuteContext<'_>) {
+    let start_time = Instant::now();
+    let pattern = ctx.arguments[0].unwrap_pattern();
+    let variables = ["x", "y", "z", "width", "height", "length"];
+    let expression = match Expression::parse(ctx.arguments[1].unwrap_string(), &variables) {
+        Ok(expression) => expression,
+        Err(err) => {
+            ctx.player.send_error_message(&err.to_string());
+            return;
+        }
+    };
+
+    let mut operation = worldedit_start_operation(ctx.player);
+    let region = operation.region();
+    let size = region.size();
+    let generates = |block_pos: BlockPos, budget: &mut u64| {
+        let offset = block_pos - region.min;
+        let values = [
+            offset.x as f64,
+            offset.y as f64,
+            offset.z as f64,
+            size.x as f64,
+            size.y as f64,
+            size.z as f64,
+        ];
+        expression.eval_bool(&values, budget)
+    };
+    // The whole selection is checked before anything is changed, so a selection is never left
+    // half generated when the budget runs out. The positions aren't kept, because a selection
+    // can have millions of them, so the expression is evaluated again while placing the blocks.
+    let mut budget = GENERATE_BUDGET;
+    if region
+        .into_iter()
+        .any(|block_pos| generates(block_pos, &mut budget).is_none())
+    {
+        ctx.player.send_error_message(
+            "The expression takes too long to evaluate for the whole selection.",
+        );
+        return;
+    }
+
+    capture_undo(
+        ctx.plot,
+        ctx.player,
+        ctx.player.first_position.unwrap(),
+        ctx.player.second_position.unwrap(),
+    );
+    // This can't run out, the first pass used the same budget
+    let mut budget = GENERATE_BUDGET;
+    for block_pos in region {
+        if generates(block_pos, &mut budget) != Some(true) {
+            continue;
+        }
+        let block_id = pattern.pick().get_id();
+
+        if ctx.plot.set_block_raw(block_pos, block_id) {
+            operation.update_block();
+        }
+    }
+
+    ctx.player.send_worldedit_message(&format!(
+        "Operation completed: {} block(s) affected ({:?})",
+        operation.blocks_updated(),
+        start_time.elapsed()
+    ));
+}
+
 pub(super) fn execute_replace(ctx: CommandExecuteContext<'_>) {
     let start_time = Instant::now();
 
@@ -87,18 +156,12 @@ pub(super) fn execute_replace(ctx: CommandExecuteContext<'_>) {
         ctx.player.first_position.unwrap(),
         ctx.player.second_position.unwrap(),
     );
-    for x in operation.x_range() {
-        for y in operation.y_range() {
-            for z in operation.z_range() {
-                let block_pos = BlockPos::new(x, y, z);
-
-                if filter.matches(ctx.plot.get_block(block_pos)) {
-                    let block_id = pattern.pick().get_id();
+    for block_pos in operation.region() {
+        if filter.matches(ctx.plot.get_block(block_pos)) {
+            let block_id = pattern.pick().get_id();
 
-                    if ctx.plot.set_block_raw(block_pos, block_id) {
-                        operation.update_block();
-                    }
-                }
+            if ctx.plot.set_block_raw(block_pos, block_id) {
+                operation.update_block();
             }
         }
     }
@@ -119,14 +182,9 @@ pub(super) fn execute_count(ctx: CommandExecuteContext<'_>) {
 
     let mut blocks_counted = 0;
     let operation = worldedit_start_operation(ctx.player);
-    for x in operation.x_range() {
-        for y in operation.y_range() {
-            for z in operation.z_range() {
-                let block_pos = BlockPos::new(x, y, z);
-                if filter.matches(ctx.plot.get_block(block_pos)) {
-                    blocks_counted += 1;
-                }
-            }
+    for block_pos in operation.region() {
+        if filter.matches(ctx.plot.get_block(block_pos)) {
+            blocks_counted += 1;
         }
     }
 
@@ -203,12 +261,18 @@ pub(super) fn execute_move(mut ctx: CommandExecuteContext<'_>) {
 
     let clipboard = create_clipboard(ctx.plot, zero_pos, first_pos, second_pos);
     clear_area(ctx.plot, first_pos, second_pos);
-    paste_clipboard(
+    let mut changed = paste_clipboard_masked(
         ctx.plot,
         &clipboard,
         direction.offset_pos(zero_pos, move_amt as i32),
         ctx.has_flag('a'),
+        None,
     );
+    if ctx.has_flag('u') {
+        // The blocks that were moved away from also have to be updated
+        changed.extend(Aabb::new(first_pos, second_pos));
+        update_pasted(ctx.plot, &changed);
+    }
 
     if ctx.has_flag('s') {
         let first_pos = direction.offset_pos(first_pos, move_amt as i32);
@@ -241,9 +305,17 @@ pub(super) fn execute_paste(ctx: CommandExecuteContext<'_>) {
             offset_z + cb.size_z as i32,
         );
         capture_undo(ctx.plot, ctx.player, first_pos, second_pos);
-        paste_clipboard(ctx.plot, cb, pos, ctx.has_flag('a'));
+        let mask = ctx
+            .flag_argument('m')
+            .map(|mask| mask.unwrap_mask().clone());
+        let pasted = paste_clipboard_masked(ctx.plot, cb, pos, ctx.has_flag('a'), mask.as_ref());
         if ctx.has_flag('u') {
-            update(ctx.plot, first_pos, second_pos);
+            update_pasted(ctx.plot, &pasted);
+        }
+        if ctx.has_flag('s') {
+            let player = &mut ctx.player;
+            player.worldedit_set_first_position(first_pos);
+            player.worldedit_set_second_position(second_pos - BlockPos::new(1, 1, 1));
         }
         ctx.player.send_worldedit_message(&format!(
             "Your clipboard was pasted. ({:?})",
@@ -256,12 +328,15 @@ pub(super) fn execute_paste(ctx: CommandExecuteContext<'_>) {
 
 static SCHEMATI_VALIDATE_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"[a-zA-Z0-9_.]+\.schem(atic)?").unwrap());
+/// Litematica schematics can only be loaded
+static LOAD_VALIDATE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_.]+\.(schem(atic)?|litematic)$").unwrap());
 
 pub(super) fn execute_load(ctx: CommandExecuteContext<'_>) {
     let start_time = Instant::now();
 
     let mut file_name = ctx.arguments[0].unwrap_string().clone();
-    if !SCHEMATI_VALIDATE_REGEX.is_match(&file_name) {
+    if !LOAD_VALIDATE_REGEX.is_match(&file_name) || file_name.contains("..") {
         ctx.player.send_error_message("Filename is invalid");
         return;
     }
@@ -297,6 +372,158 @@ pub(super) fn execute_load(ctx: CommandExecuteContext<'_>) {
     }
 }
 
+static ROM_VALIDATE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_.]+$").unwrap());
+
+fn rom_cells(ctx: &CommandExecuteContext<'_>) -> RomCells {
+    let mask = ctx.flag_argument('t').map(|mask| mask.unwrap_mask());
+    let first_pos = ctx.player.first_position.unwrap();
+    let second_pos = ctx.player.second_position.unwrap();
+    RomCells::find(ctx.plot, first_pos, second_pos, mask)
+}
+
+pub(super) fn execute_romsave(ctx: CommandExecuteContext<'_>) {
+    let start_time = Instant::now();
+
+    let file_name = ctx.arguments[0].unwrap_string().clone();
+    if !ROM_VALIDATE_REGEX.is_match(&file_name) {
+        ctx.player.send_error_message("Filename is invalid");
+        return;
+    }
+    let cells = rom_cells(&ctx);
+    if cells.is_empty() {
+        ctx.player
+            .send_error_message("There are no ROM cells in your selection.");
+        return;
+    }
+
+    let text = format_rom(&cells.read(ctx.plot));
+    let result = fs::create_dir_all("./roms")
+        .and_then(|_| fs::write(Path::new("./roms/").join(&file_name), text));
+    match result {
+        Ok(()) => {
+            ctx.player.send_worldedit_message(&format!(
+                "The {} cells of the ROM were saved. ({:?})",
+                cells.len(),
+                start_time.elapsed()
+            ));
+        }
+        Err(err) => {
+            error!("There was an error saving a ROM: {}", err);
+            ctx.player
+                .send_error_message("There was an error saving the ROM.");
+        }
+    }
+}
+
+pub(super) fn execute_romload(ctx: CommandExecuteContext<'_>) {
+    let start_time = Instant::now();
+
+    let file_name = ctx.arguments[0].unwrap_string().clone();
+    if !ROM_VALIDATE_REGEX.is_match(&file_name) {
+        ctx.player.send_error_message("Filename is invalid");
+        return;
+    }
+    let text = match fs::read_to_string(Path::new("./roms/").join(&file_name)) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            ctx.player
+                .send_error_message("The specified ROM file could not be found.");
+            return;
+        }
+        Err(err) => {
+            error!("There was an error loading a ROM: {}", err);
+            ctx.player
+                .send_error_message("There was an error loading the ROM.");
+            return;
+        }
+    };
+    let values = match parse_rom(&text) {
+        Ok(values) => values,
+        Err(err) => {
+            ctx.player
+                .send_error_message(&format!("The ROM file is invalid: {}", err));
+            return;
+        }
+    };
+    let cells = rom_cells(&ctx);
+    if cells.is_empty() {
+        ctx.player
+            .send_error_message("There are no ROM cells in your selection.");
+        return;
+    }
+
+    // Torches are placed on top of the cells, which can be just outside of the selection
+    let first_pos = ctx.player.first_position.unwrap();
+    let second_pos = ctx.player.second_position.unwrap();
+    let top = BlockPos::new(0, 1, 0);
+    let (first_pos, second_pos) = match first_pos.y >= second_pos.y {
+        true => (first_pos + top, second_pos),
+        false => (first_pos, second_pos + top),
+    };
+    capture_undo(ctx.plot, ctx.player, first_pos, second_pos);
+    let changed = match cells.write(ctx.plot, &values) {
+        Ok(changed) => changed,
+        Err(err) => {
+            ctx.player
+                .send_error_message(&format!("The ROM file is invalid: {}", err));
+            return;
+        }
+    };
+    update_pasted(ctx.plot, &changed);
+
+    if values.len() != cells.len() {
+        ctx.player.send_error_message(&format!(
+            "The file has {} values for {} cells, only the first {} were written.",
+            values.len(),
+            cells.len(),
+            values.len().min(cells.len())
+        ));
+    }
+    ctx.player.send_worldedit_message(&format!(
+        "The ROM was loaded, {} cells changed. ({:?})",
+        changed.len(),
+        start_time.elapsed()
+    ));
+}
+
+static NETLIST_VALIDATE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_.]+\.(blif|aag)$").unwrap());
+
+pub(super) fn execute_netlist(ctx: CommandExecuteContext<'_>) {
+    let start_time = Instant::now();
+
+    let file_name = ctx.arguments[0].unwrap_string().clone();
+    if !NETLIST_VALIDATE_REGEX.is_match(&file_name) {
+        ctx.player
+            .send_error_message("Filename is invalid, it has to end in .blif or .aag");
+        return;
+    }
+
+    match load_netlist(&file_name) {
+        Ok(cb) => {
+            let size = (cb.size_x, cb.size_y, cb.size_z);
+            ctx.player.worldedit_clipboard = Some(cb);
+            ctx.player.send_worldedit_message(&format!(
+                "A layout of {}x{}x{} blocks was generated to your clipboard. Do //paste -u to place it with its corner where you stand. ({:?})",
+                size.0,
+                size.1,
+                size.2,
+                start_time.elapsed()
+            ));
+        }
+        Err(e) => match e.downcast_ref::<std::io::Error>() {
+            Some(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                ctx.player
+                    .send_error_message("The specified netlist file could not be found.");
+            }
+            _ => {
+                ctx.player
+                    .send_error_message(&format!("The netlist could not be imported: {}", e));
+            }
+        },
+    }
+}
+
 pub(super) fn execute_save(ctx: CommandExecuteContext<'_>) {
     let start_time = Instant::now();
 
@@ -335,31 +562,28 @@ pub(super) fn execute_stack(ctx: CommandExecuteContext<'_>) {
     let direction = ctx.arguments[1].unwrap_direction();
     let pos1 = ctx.player.first_position.unwrap();
     let pos2 = ctx.player.second_position.unwrap();
-    let clipboard = create_clipboard(ctx.plot, pos1, pos1, pos2);
-    let stack_offset = match direction {
-        BlockFacing::North | BlockFacing::South => clipboard.size_z,
-        BlockFacing::East | BlockFacing::West => clipboard.size_x,
-        BlockFacing::Up | BlockFacing::Down => clipboard.size_y,
-    };
-    let mut undo_cbs = Vec::new();
-    for i in 1..stack_amt + 1 {
-        let offset = (i * stack_offset) as i32;
-        let block_pos = direction.offset_pos(pos1, offset);
-        undo_cbs.push(create_clipboard(
-            ctx.plot,
-            pos1,
-            block_pos,
-            direction.offset_pos(pos2, offset),
-        ));
-        paste_clipboard(ctx.plot, &clipboard, block_pos, ctx.has_flag('a'));
+    let min = pos1.min(pos2);
+    let clipboard = create_clipboard(ctx.plot, min, pos1, pos2);
+    let (undo_cbs, pasted) = stack_clipboard(
+        ctx.plot,
+        &clipboard,
+        min,
+        direction,
+        stack_amt,
+        ctx.has_flag('a'),
+        ctx.has_flag('m'),
+    );
+    if ctx.has_flag('u') {
+        update_pasted(ctx.plot, &pasted);
     }
     let undo = WorldEditUndo {
         clipboards: undo_cbs,
-        pos: pos1,
+        pos: min,
         plot_x: ctx.plot.x,
         plot_z: ctx.plot.z,
     };
     ctx.player.worldedit_undo.push(undo);
+    ctx.player.worldedit_redo.clear();
 
     ctx.player.send_worldedit_message(&format!(
         "Your selection was stacked. ({:?})",
@@ -367,41 +591,58 @@ pub(super) fn execute_stack(ctx: CommandExecuteContext<'_>) {
     ));
 }
 
-pub(super) fn execute_undo(ctx: CommandExecuteContext<'_>) {
-    if ctx.player.worldedit_undo.is_empty() {
-        ctx.player
-            .send_error_message("There is nothing left to undo.");
-        return;
-    }
-    let undo = ctx.player.worldedit_undo.pop().unwrap();
-    if undo.plot_x != ctx.plot.x || undo.plot_z != ctx.plot.z {
-        ctx.player
-            .send_error_message("Cannot undo outside of your current plot.");
-        return;
-    }
-    let redo = WorldEditUndo {
-        clipboards: undo
+/// Restores the blocks in `entry` and updates them all at once. Returns the entry that reverses
+/// this, with the blocks that were there before.
+fn apply_history_entry(plot: &mut PlotWorld, entry: WorldEditUndo) -> WorldEditUndo {
+    let reverse = WorldEditUndo {
+        clipboards: entry
             .clipboards
             .iter()
             .map(|clipboard| {
                 let first_pos = BlockPos {
-                    x: undo.pos.x - clipboard.offset_x,
-                    y: undo.pos.y - clipboard.offset_y,
-                    z: undo.pos.z - clipboard.offset_z,
+                    x: entry.pos.x - clipboard.offset_x,
+                    y: entry.pos.y - clipboard.offset_y,
+                    z: entry.pos.z - clipboard.offset_z,
                 };
                 let second_pos = BlockPos {
                     x: first_pos.x + clipboard.size_x as i32 - 1,
                     y: first_pos.y + clipboard.size_y as i32 - 1,
                     z: first_pos.z + clipboard.size_z as i32 - 1,
                 };
-                create_clipboard(ctx.plot, undo.pos, first_pos, second_pos)
+                create_clipboard(plot, entry.pos, first_pos, second_pos)
             })
             .collect(),
-        ..undo
+        ..entry
+    };
+    // Pasting restores the block entities as well, and the blocks are updated afterwards so
+    // clipboards that overlap are only updated once
+    let mut pasted = Vec::new();
+    for clipboard in &entry.clipboards {
+        pasted.extend(paste_clipboard_masked(
+            plot, clipboard, entry.pos, false, None,
+        ));
+    }
+    update_pasted(plot, &pasted);
+    reverse
+}
+
+pub(super) fn execute_undo(ctx: CommandExecuteContext<'_>) {
+    if ctx.player.worldedit_undo.is_empty() {
+        ctx.player
+            .send_error_message("There is nothing left to undo.");
+        return;
+    }
+    let Some(undo) = ctx.player.worldedit_undo.pop() else {
+        ctx.player
+            .send_error_message("The last action could not be read from the undo history.");
+        return;
     };
-    for clipboard in &undo.clipboards {
-        paste_clipboard(ctx.plot, clipboard, undo.pos, false);
+    if undo.plot_x != ctx.plot.x || undo.plot_z != ctx.plot.z {
+        ctx.player
+            .send_error_message("Cannot undo outside of your current plot.");
+        return;
     }
+    let redo = apply_history_entry(ctx.plot, undo);
     ctx.player.worldedit_redo.push(redo);
 }
 
@@ -411,36 +652,94 @@ pub(super) fn execute_redo(ctx: CommandExecuteContext<'_>) {
             .send_error_message("There is nothing left to redo.");
         return;
     }
-    let redo = ctx.player.worldedit_redo.pop().unwrap();
+    let Some(redo) = ctx.player.worldedit_redo.pop() else {
+        ctx.player
+            .send_error_message("The last action could not be read from the redo history.");
+        return;
+    };
     if redo.plot_x != ctx.plot.x || redo.plot_z != ctx.plot.z {
         ctx.player
             .send_error_message("Cannot redo outside of your current plot.");
         return;
     }
-    let undo = WorldEditUndo {
-        clipboards: redo
-            .clipboards
-            .iter()
-            .map(|clipboard| {
-                let first_pos = BlockPos {
-                    x: redo.pos.x - clipboard.offset_x,
-                    y: redo.pos.y - clipboard.offset_y,
-                    z: redo.pos.z - clipboard.offset_z,
-                };
-                let second_pos = BlockPos {
-                    x: first_pos.x + clipboard.size_x as i32 - 1,
-                    y: first_pos.y + clipboard.size_y as i32 - 1,
-                    z: first_pos.z + clipboard.size_z as i32 - 1,
-                };
-                create_clipboard(ctx.plot, redo.pos, first_pos, second_pos)
-            })
-            .collect(),
-        ..redo
+    let undo = apply_history_entry(ctx.plot, redo);
+    ctx.player.worldedit_undo.push(undo);
+}
+
+pub(super) fn execute_brush(ctx: CommandExecuteContext<'_>) {
+    let shape = match ctx.arguments[0].unwrap_string().as_str() {
+        "sphere" | "s" => BrushShape::Sphere,
+        // A cylinder can't be higher than the plot, so the height is clamped to it
+        "cylinder" | "cyl" | "c" => BrushShape::Cylinder {
+            height: ctx.arguments[3]
+                .unwrap_uint()
+                .min(ctx.plot.size.block_height() as u32),
+        },
+        _ => {
+            ctx.player
+                .send_error_message("The shape of a brush must be sphere or cylinder.");
+            return;
+        }
     };
-    for clipboard in &redo.clipboards {
-        paste_clipboard(ctx.plot, clipboard, redo.pos, false);
+    let radius = ctx.arguments[2].unwrap_uint();
+    if radius > MAX_BRUSH_RADIUS {
+        ctx.player.send_error_message(&format!(
+            "The radius of a brush can't be more than {}.",
+            MAX_BRUSH_RADIUS
+        ));
+        return;
     }
-    ctx.player.worldedit_undo.push(undo);
+    if shape == (BrushShape::Cylinder { height: 0 }) {
+        ctx.player
+            .send_error_message("The height of a cylinder brush must be at least 1.");
+        return;
+    }
+
+    let player = ctx.player;
+    let Some(item) = &player.inventory[player.selected_slot as usize + 36] else {
+        player.send_error_message("Hold the item to bind the brush to.");
+        return;
+    };
+    let item = item.item_type;
+    // Blocks would be placed by the client before the server knows the brush was used
+    if item.is_block() {
+        player.send_error_message("Brushes can't be bound to blocks, hold a tool instead.");
+        return;
+    }
+    player.brushes.retain(|brush| brush.item != item);
+    player.brushes.push(Brush {
+        item,
+        shape,
+        radius,
+        pattern: ctx.arguments[1].unwrap_pattern().clone(),
+        mask: ctx
+            .flag_argument('m')
+            .map(|mask| mask.unwrap_mask().clone()),
+    });
+    let description = match shape {
+        BrushShape::Sphere => format!("Sphere with radius {}", radius),
+        BrushShape::Cylinder { height } => {
+            format!("Cylinder with radius {} and height {}", radius, height)
+        }
+    };
+    player.send_worldedit_message(&format!(
+        "{} brush bound to the item in your hand, right click to use it.",
+        description
+    ));
+}
+
+pub(super) fn execute_none(ctx: CommandExecuteContext<'_>) {
+    let player = ctx.player;
+    let item = player.inventory[player.selected_slot as usize + 36]
+        .as_ref()
+        .map(|item| item.item_type);
+    let count = player.brushes.len();
+    player.brushes.retain(|brush| Some(brush.item) != item);
+    if player.brushes.len() == count {
+        player.send_error_message("There is no brush bound to the item in your hand.");
+        return;
+    }
+    player.send_worldedit_message("Brush unbound from the item in your hand.");
 }
 
 pub(super) fn execute_sel(ctx: CommandExecuteContext<'_>) {
@@ -554,74 +853,7 @@ pub(super) fn execute_flip(ctx: CommandExecuteContext<'_>) {
 
     let direction = ctx.arguments[0].unwrap_direction();
     let clipboard = ctx.player.worldedit_clipboard.as_ref().unwrap();
-    let size_x = clipboard.size_x;
-    let size_y = clipboard.size_y;
-    let size_z = clipboard.size_z;
-    let volume = size_x * size_y * size_z;
-
-    let flip_pos = |mut pos: BlockPos| {
-        match direction {
-            BlockFacing::East | BlockFacing::West => pos.x = size_x as i32 - 1 - pos.x,
-            BlockFacing::North | BlockFacing::South => pos.z = size_z as i32 - 1 - pos.z,
-            BlockFacing::Up | BlockFacing::Down => pos.y = size_y as i32 - 1 - pos.y,
-        }
-        pos
-    };
-
-    let mut newcpdata = PalettedBitBuffer::new((volume) as usize, 9);
-
-    let mut c_x = 0;
-    let mut c_y = 0;
-    let mut c_z = 0;
-    for i in 0..volume {
-        let BlockPos {
-            x: n_x,
-            y: n_y,
-            z: n_z,
-        } = flip_pos(BlockPos::new(c_x, c_y, c_z));
-        let n_i = (n_y as u32 * size_x * size_z) + (n_z as u32 * size_x) + n_x as u32;
-
-        let mut block = Block::from_id(clipboard.data.get_entry(i as usize));
-        match direction {
-            BlockFacing::East | BlockFacing::West => block.flip(FlipDirection::FlipX),
-            BlockFacing::North | BlockFacing::South => block.flip(FlipDirection::FlipZ),
-            _ => {}
-        }
-        newcpdata.set_entry(n_i as usize, block.get_id());
-
-        // Ok now lets increment the coordinates for the next block
-        c_x += 1;
-
-        if c_x as u32 == size_x {
-            c_x = 0;
-            c_z += 1;
-
-            if c_z as u32 == size_z {
-                c_z = 0;
-                c_y += 1;
-            }
-        }
-    }
-
-    let offset = flip_pos(BlockPos::new(
-        clipboard.offset_x,
-        clipboard.offset_y,
-        clipboard.offset_z,
-    ));
-    let cb = WorldEditClipboard {
-        offset_x: offset.x,
-        offset_y: offset.y,
-        offset_z: offset.z,
-        size_x,
-        size_y,
-        size_z,
-        data: newcpdata,
-        block_entities: clipboard
-            .block_entities
-            .iter()
-            .map(|(pos, e)| (flip_pos(*pos), e.clone()))
-            .collect(),
-    };
+    let cb = clipboard.flipped(direction);
 
     ctx.player.worldedit_clipboard = Some(cb);
     ctx.player.send_worldedit_message(&format!(
@@ -650,84 +882,7 @@ pub(super) fn execute_rotate(ctx: CommandExecuteContext<'_>) {
     };
 
     let clipboard = ctx.player.worldedit_clipboard.as_ref().unwrap();
-    let size_x = clipboard.size_x;
-    let size_y = clipboard.size_y;
-    let size_z = clipboard.size_z;
-    let volume = size_x * size_y * size_z;
-
-    let (n_size_x, n_size_z) = match rotate_amt {
-        RotateAmt::Rotate90 | RotateAmt::Rotate270 => (size_z, size_x),
-        _ => (size_x, size_z),
-    };
-
-    let rotate_pos = |pos: BlockPos| match rotate_amt {
-        RotateAmt::Rotate90 => BlockPos {
-            x: n_size_x as i32 - 1 - pos.z,
-            y: pos.y,
-            z: pos.x,
-        },
-        RotateAmt::Rotate180 => BlockPos {
-            x: n_size_x as i32 - 1 - pos.x,
-            y: pos.y,
-            z: n_size_z as i32 - 1 - pos.z,
-        },
-        RotateAmt::Rotate270 => BlockPos {
-            x: pos.z,
-            y: pos.y,
-            z: n_size_z as i32 - 1 - pos.x,
-        },
-    };
-
-    let mut newcpdata = PalettedBitBuffer::new((volume) as usize, 9);
-
-    let mut c_x = 0;
-    let mut c_y = 0;
-    let mut c_z = 0;
-    for i in 0..volume {
-        let BlockPos {
-            x: n_x,
-            y: n_y,
-            z: n_z,
-        } = rotate_pos(BlockPos::new(c_x, c_y, c_z));
-        let n_i = (n_y as u32 * n_size_x * n_size_z) + (n_z as u32 * n_size_x) + n_x as u32;
-
-        let mut block = Block::from_id(clipboard.data.get_entry(i as usize));
-        block.rotate(rotate_amt);
-        newcpdata.set_entry(n_i as usize, block.get_id());
-
-        // Ok now lets increment the coordinates for the next block
-        c_x += 1;
-
-        if c_x as u32 == size_x {
-            c_x = 0;
-            c_z += 1;
-
-            if c_z as u32 == size_z {
-                c_z = 0;
-                c_y += 1;
-            }
-        }
-    }
-
-    let offset = rotate_pos(BlockPos::new(
-        clipboard.offset_x,
-        clipboard.offset_y,
-        clipboard.offset_z,
-    ));
-    let cb = WorldEditClipboard {
-        offset_x: offset.x,
-        offset_y: offset.y,
-        offset_z: offset.z,
-        size_x: n_size_x,
-        size_y,
-        size_z: n_size_z,
-        data: newcpdata,
-        block_entities: clipboard
-            .block_entities
-            .iter()
-            .map(|(pos, e)| (rotate_pos(*pos), e.clone()))
-            .collect(),
-    };
+    let cb = clipboard.rotated(rotate_amt);
 
     ctx.player.worldedit_clipboard = Some(cb);
     ctx.player.send_worldedit_message(&format!(
@@ -879,7 +1034,7 @@ pub(super) fn execute_ascend(ctx: CommandExecuteContext<'_>) {
     let player_pos = player.pos.block_pos();
     let mut player_y = player_pos.y;
 
-    for (y, _) in (player_y..=PLOT_BLOCK_HEIGHT).enumerate() {
+    for (y, _) in (player_y..=ctx.plot.size.block_height()).enumerate() {
         if levels == 0 {
             break;
         }
@@ -1023,60 +1178,34 @@ pub(super) fn execute_replace_container(ctx: CommandExecuteContext<'_>) {
     let new_block = match to {
         ContainerType::Furnace => Block::Furnace {},
         ContainerType::Barrel => Block::Barrel {},
-        ContainerType::Hopper => Block::Hopper {},
+        ContainerType::Hopper => Block::Hopper {
+            enabled: true,
+            facing: BlockFacing::Down,
+        },
     };
-    let slots = to.num_slots() as u32;
-
     let operation = worldedit_start_operation(ctx.player);
-    for x in operation.x_range() {
-        for y in operation.y_range() {
-            for z in operation.z_range() {
-                let pos = BlockPos::new(x, y, z);
-                let block = ctx.plot.get_block(pos);
-
-                if !matches!(
-                    block,
-                    Block::Furnace {} | Block::Barrel {} | Block::Hopper {}
-                ) {
-                    continue;
-                }
-                let block_entity = ctx.plot.get_block_entity(pos);
-                if let Some(BlockEntity::Container {
-                    comparator_override,
-                    ty,
-                    ..
-                }) = block_entity
-                {
-                    if *ty != from {
-                        continue;
-                    }
-                    let ss = *comparator_override;
-
-                    let items_needed = match ss {
-                        0 => 0,
-                        15 => slots * 64,
-                        _ => ((32 * slots * ss as u32) as f32 / 7.0 - 1.0).ceil() as u32,
-                    } as usize;
-                    let mut inventory = Vec::new();
-                    for (slot, items_added) in (0..items_needed).step_by(64).enumerate() {
-                        let count = (items_needed - items_added).min(64);
-                        inventory.push(InventoryEntry {
-                            id: Item::Redstone {}.get_id(),
-                            slot: slot as i8,
-                            count: count as i8,
-                            nbt: None,
-                        });
-                    }
-
-                    let new_entity = BlockEntity::Container {
-                        comparator_override: ss,
-                        inventory,
-                        ty: to,
-                    };
-                    ctx.plot.set_block_entity(pos, new_entity);
-                    ctx.plot.set_block(pos, new_block);
-                }
+    for pos in operation.region() {
+        let block = ctx.plot.get_block(pos);
+
+        if !matches!(
+            block,
+            Block::Furnace {} | Block::Barrel {} | Block::Hopper { .. }
+        ) {
+            continue;
+        }
+        let block_entity = ctx.plot.get_block_entity(pos);
+        if let Some(BlockEntity::Container {
+            comparator_override,
+            ty,
+            ..
+        }) = block_entity
+        {
+            if *ty != from {
+                continue;
             }
+            let new_entity = BlockEntity::container_with_ss(to, *comparator_override);
+            ctx.plot.set_block_entity(pos, new_entity);
+            ctx.plot.set_block(pos, new_block);
         }
     }
 
@@ -0,0 +1,496 @@
+//! The expression language of `//generate`. An expression is parsed once and then evaluated for
+//! every block, with a budget on the amount of operations so that a slow expression can't hang
+//! the plot. All values are numbers, comparisons and logical operators give 1 for true and 0 for
+//! false, and any value other than 0 and NaN is true.
+
+use std::fmt;
+
+/// Deeper expressions are rejected, which keeps parsing and evaluating them from overflowing the
+/// stack
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub struct ExpressionError {
+    message: String,
+}
+
+impl ExpressionError {
+    fn new(message: impl Into<String>) -> ExpressionError {
+        ExpressionError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid expression: {}", self.message)
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    /// Operators and punctuation
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "(", ")", ",",
+    "?", ":",
+];
+
+fn tokenize(str: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < str.len() {
+        let rest = &str[i..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            i += c.len_utf8();
+        } else if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..len]
+                .parse()
+                .map_err(|_| ExpressionError::new(format!("invalid number at {}", i)))?;
+            tokens.push((i, Token::Number(number)));
+            i += len;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push((i, Token::Ident(rest[..len].to_owned())));
+            i += len;
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push((i, Token::Symbol(*symbol)));
+            i += symbol.len();
+        } else {
+            return Err(ExpressionError::new(format!("unexpected '{}' at {}", c, i)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Min,
+    Max,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<(Function, usize)> {
+        Some(match name {
+            "abs" => (Function::Abs, 1),
+            "floor" => (Function::Floor, 1),
+            "ceil" => (Function::Ceil, 1),
+            "round" => (Function::Round, 1),
+            "sqrt" => (Function::Sqrt, 1),
+            "sin" => (Function::Sin, 1),
+            "cos" => (Function::Cos, 1),
+            "tan" => (Function::Tan, 1),
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            _ => return None,
+        })
+    }
+
+    fn call(self, args: &[f64]) -> f64 {
+        match self {
+            Function::Abs => args[0].abs(),
+            Function::Floor => args[0].floor(),
+            Function::Ceil => args[0].ceil(),
+            Function::Round => args[0].round(),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Tan => args[0].tan(),
+            Function::Min => args[0].min(args[1]),
+            Function::Max => args[0].max(args[1]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn from_symbol(symbol: &str) -> Option<BinaryOp> {
+        Some(match symbol {
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "^" => BinaryOp::Pow,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "&&" => BinaryOp::And,
+            "||" => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    /// Operators with a higher precedence bind tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne => 3,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+            BinaryOp::Pow => 7,
+        }
+    }
+
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let from_bool = |b: bool| if b { 1.0 } else { 0.0 };
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            // Euclidean, so patterns like `(x + z) % 2` continue past 0
+            BinaryOp::Rem => a.rem_euclid(b),
+            BinaryOp::Pow => a.powf(b),
+            BinaryOp::Eq => from_bool(a == b),
+            BinaryOp::Ne => from_bool(a != b),
+            BinaryOp::Lt => from_bool(a < b),
+            BinaryOp::Le => from_bool(a <= b),
+            BinaryOp::Gt => from_bool(a > b),
+            BinaryOp::Ge => from_bool(a >= b),
+            BinaryOp::And => from_bool(truthy(a) && truthy(b)),
+            BinaryOp::Or => from_bool(truthy(a) || truthy(b)),
+        }
+    }
+}
+
+fn truthy(value: f64) -> bool {
+    value != 0.0 && !value.is_nan()
+}
+
+#[derive(Debug)]
+enum Node {
+    Number(f64),
+    Variable(usize),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Conditional(Box<Node>, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    variables: &'a [&'a str],
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error_here(&self, what: &str) -> ExpressionError {
+        match self.tokens.get(self.pos) {
+            Some((i, _)) => ExpressionError::new(format!("{} at {}", what, i)),
+            None => ExpressionError::new(format!("{} at the end", what)),
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ExpressionError> {
+        match self.peek() {
+            Some(Token::Symbol(s)) if *s == symbol => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error_here(&format!("expected '{}'", symbol))),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), ExpressionError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ExpressionError::new("the expression is nested too deeply"));
+        }
+        Ok(())
+    }
+
+    fn parse_conditional(&mut self) -> Result<Node, ExpressionError> {
+        self.enter()?;
+        let condition = self.parse_binary(1)?;
+        let node = if self.peek() == Some(&Token::Symbol("?")) {
+            self.pos += 1;
+            let then = self.parse_conditional()?;
+            self.expect(":")?;
+            let otherwise = self.parse_conditional()?;
+            Node::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise))
+        } else {
+            condition
+        };
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    /// Parses operators with at least `min_precedence` by precedence climbing
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Node, ExpressionError> {
+        let mut lhs = self.parse_unary()?;
+        let depth = self.depth;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol(symbol)) => match BinaryOp::from_symbol(symbol) {
+                    Some(op) if op.precedence() >= min_precedence => op,
+                    _ => break,
+                },
+                _ => break,
+            };
+            self.pos += 1;
+            // `^` is right associative, the others are left associative
+            let next_precedence = match op {
+                BinaryOp::Pow => op.precedence(),
+                _ => op.precedence() + 1,
+            };
+            // Every operator nests `lhs` one level deeper
+            self.enter()?;
+            let rhs = self.parse_binary(next_precedence)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, ExpressionError> {
+        match self.peek() {
+            Some(Token::Symbol("-")) | Some(Token::Symbol("!")) => {
+                let negate = self.next() == Some(Token::Symbol("-"));
+                self.enter()?;
+                // `-x ^ 2` is `-(x ^ 2)` like it is in maths
+                let operand = self.parse_binary(BinaryOp::Pow.precedence())?;
+                self.depth -= 1;
+                Ok(match negate {
+                    true => Node::Neg(Box::new(operand)),
+                    false => Node::Not(Box::new(operand)),
+                })
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, ExpressionError> {
+        let error = self.error_here("expected a value");
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Symbol("(")) => {
+                let node = self.parse_conditional()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::Symbol("(")) {
+                    return self.parse_call(&name);
+                }
+                if let Some(i) = self.variables.iter().position(|var| *var == name) {
+                    return Ok(Node::Variable(i));
+                }
+                match name.as_str() {
+                    "pi" => Ok(Node::Number(std::f64::consts::PI)),
+                    "e" => Ok(Node::Number(std::f64::consts::E)),
+                    "true" => Ok(Node::Number(1.0)),
+                    "false" => Ok(Node::Number(0.0)),
+                    _ => Err(ExpressionError::new(format!("unknown variable '{}'", name))),
+                }
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn parse_call(&mut self, name: &str) -> Result<Node, ExpressionError> {
+        let (function, arity) = Function::from_name(name)
+            .ok_or_else(|| ExpressionError::new(format!("unknown function '{}'", name)))?;
+        self.expect("(")?;
+        let mut args = Vec::new();
+        for i in 0..arity {
+            if i > 0 {
+                self.expect(",")?;
+            }
+            args.push(self.parse_conditional()?);
+        }
+        self.expect(")")
+            .map_err(|_| ExpressionError::new(format!("{} takes {} argument(s)", name, arity)))?;
+        Ok(Node::Call(function, args))
+    }
+}
+
+#[derive(Debug)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    /// Parses an expression in which `variables` can be used, which are given in that order when
+    /// it is evaluated
+    pub fn parse(str: &str, variables: &[&str]) -> Result<Expression, ExpressionError> {
+        let mut parser = Parser {
+            tokens: tokenize(str)?,
+            pos: 0,
+            variables,
+            depth: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err(ExpressionError::new("the expression is empty"));
+        }
+        let root = parser.parse_conditional()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.error_here("unexpected token"));
+        }
+        Ok(Expression { root })
+    }
+
+    /// Evaluates the expression, which takes one step of `budget` for every operation. Returns
+    /// `None` if the budget runs out.
+    pub fn eval(&self, variables: &[f64], budget: &mut u64) -> Option<f64> {
+        eval_node(&self.root, variables, budget)
+    }
+
+    /// Evaluates the expression as a condition, see [`Expression::eval`]
+    pub fn eval_bool(&self, variables: &[f64], budget: &mut u64) -> Option<bool> {
+        self.eval(variables, budget).map(truthy)
+    }
+}
+
+fn eval_node(node: &Node, variables: &[f64], budget: &mut u64) -> Option<f64> {
+    *budget = budget.checked_sub(1)?;
+    Some(match node {
+        Node::Number(number) => *number,
+        Node::Variable(i) => variables.get(*i).copied().unwrap_or(0.0),
+        Node::Neg(operand) => -eval_node(operand, variables, budget)?,
+        Node::Not(operand) => match truthy(eval_node(operand, variables, budget)?) {
+            true => 0.0,
+            false => 1.0,
+        },
+        // The right side of `&&` and `||` is only evaluated if it is needed
+        Node::Binary(BinaryOp::And, a, b) => {
+            let a = eval_node(a, variables, budget)?;
+            match truthy(a) {
+                true => BinaryOp::And.apply(a, eval_node(b, variables, budget)?),
+                false => 0.0,
+            }
+        }
+        Node::Binary(BinaryOp::Or, a, b) => {
+            let a = eval_node(a, variables, budget)?;
+            match truthy(a) {
+                true => 1.0,
+                false => BinaryOp::Or.apply(a, eval_node(b, variables, budget)?),
+            }
+        }
+        Node::Binary(op, a, b) => {
+            let a = eval_node(a, variables, budget)?;
+            op.apply(a, eval_node(b, variables, budget)?)
+        }
+        Node::Conditional(condition, then, otherwise) => {
+            match truthy(eval_node(condition, variables, budget)?) {
+                true => eval_node(then, variables, budget)?,
+                false => eval_node(otherwise, variables, budget)?,
+            }
+        }
+        Node::Call(function, args) => {
+            let mut values = [0.0; 2];
+            for (value, arg) in values.iter_mut().zip(args) {
+                *value = eval_node(arg, variables, budget)?;
+            }
+            function.call(&values[..args.len()])
+        }
+    })
+}
+
+#[test]
+fn expressions_are_evaluated() {
+    let eval = |str: &str, vars: &[f64]| {
+        let expr = Expression::parse(str, &["x", "y", "z"]).unwrap();
+        let mut budget = u64::MAX;
+        expr.eval(vars, &mut budget).unwrap()
+    };
+    assert_eq!(eval("1 + 2 * 3", &[]), 7.0);
+    assert_eq!(eval("(1 + 2) * 3", &[]), 9.0);
+    assert_eq!(eval("2 ^ 3 ^ 2", &[]), 512.0);
+    assert_eq!(eval("-2 ^ 2", &[]), -4.0);
+    assert_eq!(eval("10 - 4 - 3", &[]), 3.0);
+    assert_eq!(eval("x % 2", &[-3.0]), 1.0);
+    assert_eq!(eval("x == y && !(z > 1)", &[2.0, 2.0, 0.0]), 1.0);
+    assert_eq!(eval("x < 0 || 1 / x > 2", &[0.25]), 1.0);
+    assert_eq!(eval("x > 5 ? y : z", &[6.0, 7.0, 8.0]), 7.0);
+    assert_eq!(eval("max(abs(x), min(y, 3))", &[-1.0, 5.0]), 3.0);
+    assert_eq!(eval("floor(sqrt(x))", &[10.0]), 3.0);
+
+    let expr = Expression::parse("1 / x", &["x"]).unwrap();
+    assert_eq!(expr.eval_bool(&[0.0], &mut 10), Some(true));
+    let expr = Expression::parse("0 / x", &["x"]).unwrap();
+    assert_eq!(expr.eval_bool(&[0.0], &mut 10), Some(false));
+}
+
+#[test]
+fn bad_expressions_are_rejected() {
+    let parse = |str: &str| Expression::parse(str, &["x"]).map(|_| ());
+    assert!(parse("").is_err());
+    assert!(parse("1 +").is_err());
+    assert!(parse("(1").is_err());
+    assert!(parse("1 2").is_err());
+    assert!(parse("x = 1").is_err());
+    assert!(parse("y").is_err());
+    assert!(parse("foo(1)").is_err());
+    assert!(parse("min(1)").is_err());
+    assert!(parse("abs(1, 2)").is_err());
+    assert!(parse("1..2").is_err());
+    assert!(parse("x ? 1").is_err());
+    assert!(parse("é").is_err());
+    assert!(parse(&"(".repeat(100_000)).is_err());
+    assert!(parse(&"-".repeat(100_000)).is_err());
+    assert!(parse(&"1+".repeat(100_000)).is_err());
+    assert!(parse(&"((x))".repeat(2)).is_err());
+    assert!(parse(&"1+".repeat(30).trim_end_matches('+')).is_ok());
+
+    let expr = Expression::parse("x * x + x", &["x"]).unwrap();
+    let mut budget = 5;
+    assert_eq!(expr.eval(&[2.0], &mut budget), Some(6.0));
+    assert_eq!(budget, 0);
+    assert_eq!(expr.eval(&[2.0], &mut budget), None);
+}
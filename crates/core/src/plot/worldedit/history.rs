@@ -0,0 +1,298 @@
+//! The undo and redo history of a player. The newest entries are kept in memory, and once they
+//! take up more than the budget the oldest ones are written to disk until they are needed again.
+//! The history only lasts for a session, so its files are removed when the player leaves.
+
+use super::{WorldEditClipboard, WorldEditUndo};
+use crate::world::storage::PalettedBitBuffer;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::BlockPos;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+use tracing::error;
+
+#[derive(Serialize, Deserialize)]
+struct SpilledClipboard {
+    offset: BlockPos,
+    size: (u32, u32, u32),
+    blocks: Vec<u32>,
+    block_entities: FxHashMap<BlockPos, BlockEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpilledUndo {
+    clipboards: Vec<SpilledClipboard>,
+    pos: BlockPos,
+    plot_x: i32,
+    plot_z: i32,
+}
+
+impl SpilledUndo {
+    fn new(undo: &WorldEditUndo) -> SpilledUndo {
+        let clipboards = undo
+            .clipboards
+            .iter()
+            .map(|cb| SpilledClipboard {
+                offset: BlockPos::new(cb.offset_x, cb.offset_y, cb.offset_z),
+                size: (cb.size_x, cb.size_y, cb.size_z),
+                blocks: (0..cb.data.entries())
+                    .map(|i| cb.data.get_entry(i))
+                    .collect(),
+                block_entities: cb.block_entities.clone(),
+            })
+            .collect();
+        SpilledUndo {
+            clipboards,
+            pos: undo.pos,
+            plot_x: undo.plot_x,
+            plot_z: undo.plot_z,
+        }
+    }
+
+    fn into_undo(self) -> WorldEditUndo {
+        let clipboards = self
+            .clipboards
+            .into_iter()
+            .map(|cb| {
+                let mut data = PalettedBitBuffer::new(cb.blocks.len(), 9);
+                for (i, &block) in cb.blocks.iter().enumerate() {
+                    data.set_entry(i, block);
+                }
+                WorldEditClipboard {
+                    offset_x: cb.offset.x,
+                    offset_y: cb.offset.y,
+                    offset_z: cb.offset.z,
+                    size_x: cb.size.0,
+                    size_y: cb.size.1,
+                    size_z: cb.size.2,
+                    data,
+                    block_entities: cb.block_entities,
+                }
+            })
+            .collect();
+        WorldEditUndo {
+            clipboards,
+            pos: self.pos,
+            plot_x: self.plot_x,
+            plot_z: self.plot_z,
+        }
+    }
+}
+
+/// Roughly the amount of bytes `undo` takes up in memory
+fn memory_size(undo: &WorldEditUndo) -> usize {
+    undo.clipboards
+        .iter()
+        .map(|cb| {
+            cb.data.memory_size()
+                + cb.block_entities.len() * mem::size_of::<(BlockPos, BlockEntity)>()
+        })
+        .sum()
+}
+
+pub struct WorldEditHistory {
+    dir: PathBuf,
+    /// The amount of bytes the entries in memory can take up before the oldest ones are written
+    /// to disk
+    budget: usize,
+    memory: usize,
+    /// The entries in memory, from old to new
+    entries: VecDeque<WorldEditUndo>,
+    /// The files of the entries on disk, from old to new, which are all older than the entries
+    /// in memory
+    spilled: Vec<PathBuf>,
+    next_file: u64,
+}
+
+impl WorldEditHistory {
+    /// Creates an empty history that writes its oldest entries to `dir`. Files that were left
+    /// behind in `dir`, for example by a crash, are removed.
+    pub fn new(dir: impl Into<PathBuf>, budget: usize) -> WorldEditHistory {
+        let dir = dir.into();
+        if dir.exists() {
+            if let Err(err) = fs::remove_dir_all(&dir) {
+                error!(
+                    "Failed to remove old undo history in {}: {}",
+                    dir.display(),
+                    err
+                );
+            }
+        }
+        WorldEditHistory {
+            dir,
+            budget,
+            memory: 0,
+            entries: VecDeque::new(),
+            spilled: Vec::new(),
+            next_file: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.spilled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The newest entry, which is always kept in memory
+    pub fn last(&self) -> Option<&WorldEditUndo> {
+        self.entries.back()
+    }
+
+    pub fn push(&mut self, undo: WorldEditUndo) {
+        self.memory += memory_size(&undo);
+        self.entries.push_back(undo);
+        while self.memory > self.budget && self.entries.len() > 1 {
+            if let Err(err) = self.spill() {
+                // The entry stays in memory, which is better than losing it
+                error!(
+                    "Failed to write undo history to {}: {}",
+                    self.dir.display(),
+                    err
+                );
+                break;
+            }
+        }
+    }
+
+    /// Removes the newest entry. Returns `None` if there is none or it couldn't be read from disk.
+    pub fn pop(&mut self) -> Option<WorldEditUndo> {
+        if self.entries.is_empty() {
+            self.unspill();
+        }
+        let undo = self.entries.pop_back()?;
+        self.memory -= memory_size(&undo);
+        // Keeps the newest entry in memory for `last`
+        if self.entries.is_empty() {
+            self.unspill();
+        }
+        Some(undo)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.memory = 0;
+        for path in self.spilled.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Writes the oldest entry in memory to disk
+    fn spill(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let undo = self.entries.front().unwrap();
+        let size = memory_size(undo);
+        let data = bincode::serialize(&SpilledUndo::new(undo))?;
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.bin", self.next_file));
+        fs::write(&path, lz4_flex::compress_prepend_size(&data))?;
+        self.next_file += 1;
+        self.spilled.push(path);
+        self.entries.pop_front();
+        self.memory -= size;
+        Ok(())
+    }
+
+    /// Reads the newest entry on disk back into memory. The entry is lost if it can't be read.
+    fn unspill(&mut self) {
+        let Some(path) = self.spilled.pop() else {
+            return;
+        };
+        let undo = fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| {
+                lz4_flex::decompress_size_prepended(&data).map_err(|err| err.to_string())
+            })
+            .and_then(|data| {
+                bincode::deserialize::<SpilledUndo>(&data).map_err(|err| err.to_string())
+            });
+        let _ = fs::remove_file(&path);
+        match undo {
+            Ok(undo) => {
+                let undo = undo.into_undo();
+                self.memory += memory_size(&undo);
+                self.entries.push_front(undo);
+            }
+            Err(err) => error!(
+                "Failed to read undo history from {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+}
+
+impl Drop for WorldEditHistory {
+    fn drop(&mut self) {
+        self.clear();
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+#[test]
+fn history_spills_to_disk() {
+    let dir = std::env::temp_dir().join(format!("mchprs_undo_test_{}", std::process::id()));
+    let undo = |id: u32| {
+        let mut data = PalettedBitBuffer::new(4096, 9);
+        for i in 0..data.entries() {
+            data.set_entry(i, id + i as u32 % 3);
+        }
+        let mut block_entities = FxHashMap::default();
+        block_entities.insert(
+            BlockPos::new(1, 2, 3),
+            BlockEntity::Comparator {
+                output_strength: id as u8,
+            },
+        );
+        WorldEditUndo {
+            clipboards: vec![WorldEditClipboard {
+                offset_x: 1,
+                offset_y: 2,
+                offset_z: 3,
+                size_x: 16,
+                size_y: 16,
+                size_z: 16,
+                data,
+                block_entities,
+            }],
+            pos: BlockPos::new(id as i32, 0, 0),
+            plot_x: 0,
+            plot_z: 0,
+        }
+    };
+
+    let budget = memory_size(&undo(0)) * 2;
+    let mut history = WorldEditHistory::new(&dir, budget);
+    for id in 0..5 {
+        history.push(undo(id));
+    }
+    assert_eq!(history.len(), 5);
+    assert_eq!(history.entries.len(), 2);
+    assert_eq!(history.spilled.len(), 3);
+
+    for id in (0..5).rev() {
+        assert_eq!(history.last().unwrap().pos.x, id as i32);
+        let undo = history.pop().unwrap();
+        let cb = &undo.clipboards[0];
+        assert_eq!(undo.pos.x, id as i32);
+        assert_eq!(cb.offset_y, 2);
+        assert_eq!(cb.data.get_entry(4095), id + 4095 % 3);
+        assert!(matches!(
+            cb.block_entities.get(&BlockPos::new(1, 2, 3)),
+            Some(BlockEntity::Comparator { output_strength }) if *output_strength == id as u8
+        ));
+    }
+    assert!(history.pop().is_none());
+
+    history.push(undo(0));
+    history.push(undo(1));
+    history.push(undo(2));
+    history.clear();
+    assert!(history.is_empty());
+    drop(history);
+    assert!(!dir.exists());
+}
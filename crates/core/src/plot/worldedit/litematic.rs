@@ -0,0 +1,374 @@
+//! Loads schematics saved by the Litematica mod. A litematic consists of regions that each
+//! have their own position relative to the origin of the placement, so all regions are put
+//! into a single clipboard that covers every region.
+//!
+//! https://github.com/maruohon/litematica/blob/pre-rewrite/fabric/1.20.x/src/main/java/fi/dy/masa/litematica/schematic/LitematicaSchematic.java
+
+use super::schematic::parse_block;
+use super::WorldEditClipboard;
+use crate::world::storage::PalettedBitBuffer;
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use nbt::Value;
+use rustc_hash::FxHashMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use tracing::warn;
+
+fn get<'a>(nbt: &'a HashMap<String, Value>, name: &str) -> Result<&'a Value> {
+    nbt.get(name)
+        .with_context(|| format!("missing nbt tag: {}", name))
+}
+
+fn get_compound<'a>(
+    nbt: &'a HashMap<String, Value>,
+    name: &str,
+) -> Result<&'a HashMap<String, Value>> {
+    match get(nbt, name)? {
+        Value::Compound(compound) => Ok(compound),
+        _ => bail!("nbt tag {} is not a compound", name),
+    }
+}
+
+fn get_int(nbt: &HashMap<String, Value>, name: &str) -> Result<i32> {
+    match get(nbt, name)? {
+        Value::Int(val) => Ok(*val),
+        _ => bail!("nbt tag {} is not an int", name),
+    }
+}
+
+fn get_vec(nbt: &HashMap<String, Value>, name: &str) -> Result<BlockPos> {
+    let vec = get_compound(nbt, name)?;
+    Ok(BlockPos::new(
+        get_int(vec, "x")?,
+        get_int(vec, "y")?,
+        get_int(vec, "z")?,
+    ))
+}
+
+/// Builds the block state string that [`parse_block`] expects from a palette entry
+fn palette_block(entry: &Value) -> Result<Block> {
+    let Value::Compound(entry) = entry else {
+        bail!("palette entry is not a compound");
+    };
+    let Value::String(name) = get(entry, "Name")? else {
+        bail!("palette entry name is not a string");
+    };
+    let mut state = name.clone();
+    if let Some(Value::Compound(properties)) = entry.get("Properties") {
+        let properties: Vec<String> = properties
+            .iter()
+            .filter_map(|(key, val)| match val {
+                Value::String(val) => Some(format!("{}={}", key, val)),
+                _ => None,
+            })
+            .collect();
+        state = format!("{}[{}]", state, properties.join(","));
+    }
+    parse_block(&state).with_context(|| format!("error parsing block: {}", state))
+}
+
+/// Reads entry `index` of an array of `bits` bit entries that are packed into longs without
+/// padding, so entries can be split between two longs
+fn packed_entry(longs: &[i64], bits: usize, index: usize) -> Option<usize> {
+    let mask = (1u64 << bits) - 1;
+    let start_bit = index * bits;
+    let start_long = start_bit / 64;
+    let end_long = (start_bit + bits - 1) / 64;
+    let offset = start_bit % 64;
+    let low = *longs.get(start_long)? as u64 >> offset;
+    let value = if start_long == end_long {
+        low
+    } else {
+        low | (*longs.get(end_long)? as u64) << (64 - offset)
+    };
+    Some((value & mask) as usize)
+}
+
+struct Region {
+    /// The minimum corner relative to the origin of the placement
+    min: BlockPos,
+    size: BlockPos,
+    blocks: Vec<u32>,
+    block_entities: Vec<(BlockPos, BlockEntity)>,
+}
+
+fn load_region(name: &str, nbt: &HashMap<String, Value>) -> Result<Region> {
+    let position = get_vec(nbt, "Position")?;
+    let signed_size = get_vec(nbt, "Size")?;
+    // Negative sizes extend the region from its position towards negative coordinates
+    let corner = |position: i32, size: i32| {
+        if size < 0 {
+            position + size + 1
+        } else {
+            position
+        }
+    };
+    let min = BlockPos::new(
+        corner(position.x, signed_size.x),
+        corner(position.y, signed_size.y),
+        corner(position.z, signed_size.z),
+    );
+    let size = BlockPos::new(
+        signed_size.x.abs(),
+        signed_size.y.abs(),
+        signed_size.z.abs(),
+    );
+
+    let Value::List(palette) = get(nbt, "BlockStatePalette")? else {
+        bail!("the palette of region {} is not a list", name);
+    };
+    let palette: Vec<u32> = palette
+        .iter()
+        .map(|entry| palette_block(entry).map(|block| block.get_id()))
+        .collect::<Result<_>>()?;
+    let Value::LongArray(longs) = get(nbt, "BlockStates")? else {
+        bail!("the block states of region {} are not a long array", name);
+    };
+    let bits = (usize::BITS - palette.len().saturating_sub(1).leading_zeros()).max(2) as usize;
+    let volume = (size.x * size.y * size.z) as usize;
+    let mut blocks = Vec::with_capacity(volume);
+    for index in 0..volume {
+        let entry = packed_entry(longs, bits, index)
+            .with_context(|| format!("the block states of region {} are too short", name))?;
+        let id = palette
+            .get(entry)
+            .with_context(|| format!("block {} is not in the palette of region {}", entry, name))?;
+        blocks.push(*id);
+    }
+
+    let mut block_entities = Vec::new();
+    if let Some(Value::List(tile_entities)) = nbt.get("TileEntities") {
+        for tile_entity in tile_entities {
+            let Value::Compound(tile_entity) = tile_entity else {
+                continue;
+            };
+            let pos = BlockPos::new(
+                get_int(tile_entity, "x")?,
+                get_int(tile_entity, "y")?,
+                get_int(tile_entity, "z")?,
+            );
+            if let Some(block_entity) = BlockEntity::from_nbt(tile_entity) {
+                block_entities.push((pos, block_entity));
+            }
+        }
+    }
+
+    Ok(Region {
+        min,
+        size,
+        blocks,
+        block_entities,
+    })
+}
+
+/// Loads every region of a litematic into one clipboard. The origin of the placement ends up
+/// where the clipboard is pasted.
+pub fn load_litematic_file(path: impl AsRef<Path>) -> Result<WorldEditClipboard> {
+    let mut file = File::open(path)?;
+    let nbt = nbt::Blob::from_gzip_reader(&mut file)?;
+    let Some(Value::Compound(regions_nbt)) = nbt.get("Regions") else {
+        bail!("the litematic does not have any regions");
+    };
+    let regions: Vec<Region> = regions_nbt
+        .iter()
+        .map(|(name, region)| match region {
+            Value::Compound(region) => {
+                load_region(name, region).with_context(|| format!("error loading region {}", name))
+            }
+            _ => bail!("region {} is not a compound", name),
+        })
+        .collect::<Result<_>>()?;
+    if regions.is_empty() {
+        bail!("the litematic does not have any regions");
+    }
+
+    let min = regions
+        .iter()
+        .map(|region| region.min)
+        .reduce(BlockPos::min)
+        .unwrap();
+    let max = regions
+        .iter()
+        .map(|region| region.min + region.size)
+        .reduce(BlockPos::max)
+        .unwrap();
+    let size = max - min;
+    let (size_x, size_y, size_z) = (size.x as u32, size.y as u32, size.z as u32);
+    let mut data = PalettedBitBuffer::new((size_x * size_y * size_z) as usize, 9);
+    let mut block_entities = FxHashMap::default();
+    let mut overlapping = false;
+    for region in regions {
+        let offset = region.min - min;
+        let mut i = 0;
+        for y in 0..region.size.y {
+            for z in 0..region.size.z {
+                for x in 0..region.size.x {
+                    let id = region.blocks[i];
+                    i += 1;
+                    // Air in a region doesn't replace the blocks of other regions
+                    if id == 0 {
+                        continue;
+                    }
+                    let pos = offset + BlockPos::new(x, y, z);
+                    let index = ((pos.y as u32 * size_z + pos.z as u32) * size_x) + pos.x as u32;
+                    overlapping |= data.get_entry(index as usize) != 0;
+                    data.set_entry(index as usize, id);
+                }
+            }
+        }
+        for (pos, block_entity) in region.block_entities {
+            block_entities.insert(pos + offset, block_entity);
+        }
+    }
+    if overlapping {
+        warn!("Regions of the litematic overlap, later regions replace earlier ones");
+    }
+
+    Ok(WorldEditClipboard {
+        offset_x: -min.x,
+        offset_y: -min.y,
+        offset_z: -min.z,
+        size_x,
+        size_y,
+        size_z,
+        data,
+        block_entities,
+    })
+}
+
+#[test]
+fn packed_entries_can_span_two_longs() {
+    // The fifth 5 bit entry starts at bit 20, the thirteenth starts at bit 60 of the first long
+    let longs = [(0b0110 << 60 | 0b10011 << 20) as i64, 1];
+    assert_eq!(packed_entry(&longs, 5, 4), Some(0b10011));
+    assert_eq!(packed_entry(&longs, 5, 12), Some(0b10110));
+    assert_eq!(packed_entry(&longs, 5, 25), None);
+}
+
+#[test]
+fn litematics_are_loaded_with_all_regions() {
+    use super::paste_clipboard;
+    use crate::plot::redpiler_test_world;
+    use crate::world::World;
+    use mchprs_blocks::blocks::{ComparatorMode, RedstoneComparator};
+    use mchprs_blocks::BlockDirection;
+
+    fn vec(x: i32, y: i32, z: i32) -> Value {
+        Value::Compound(HashMap::from([
+            ("x".to_owned(), Value::Int(x)),
+            ("y".to_owned(), Value::Int(y)),
+            ("z".to_owned(), Value::Int(z)),
+        ]))
+    }
+    fn palette_entry(name: &str, properties: &[(&str, &str)]) -> Value {
+        let mut entry = HashMap::from([("Name".to_owned(), Value::String(name.to_owned()))]);
+        if !properties.is_empty() {
+            let properties = properties
+                .iter()
+                .map(|(key, val)| (key.to_string(), Value::String(val.to_string())))
+                .collect();
+            entry.insert("Properties".to_owned(), Value::Compound(properties));
+        }
+        Value::Compound(entry)
+    }
+
+    let stone = HashMap::from([
+        ("Position".to_owned(), vec(0, 0, 0)),
+        ("Size".to_owned(), vec(2, 1, 1)),
+        (
+            "BlockStatePalette".to_owned(),
+            Value::List(vec![
+                palette_entry("minecraft:air", &[]),
+                palette_entry("minecraft:stone", &[]),
+            ]),
+        ),
+        ("BlockStates".to_owned(), Value::LongArray(vec![0b0101])),
+    ]);
+    // A negative size makes the region extend from its position towards negative coordinates
+    let comparator_entity = HashMap::from([
+        ("x".to_owned(), Value::Int(0)),
+        ("y".to_owned(), Value::Int(0)),
+        ("z".to_owned(), Value::Int(1)),
+        (
+            "id".to_owned(),
+            Value::String("minecraft:comparator".to_owned()),
+        ),
+        ("OutputSignal".to_owned(), Value::Int(7)),
+    ]);
+    let comparator = HashMap::from([
+        ("Position".to_owned(), vec(0, 2, 0)),
+        ("Size".to_owned(), vec(-1, -1, -2)),
+        (
+            "BlockStatePalette".to_owned(),
+            Value::List(vec![
+                palette_entry("minecraft:air", &[]),
+                palette_entry("minecraft:redstone_block", &[]),
+                palette_entry(
+                    "minecraft:comparator",
+                    &[
+                        ("facing", "west"),
+                        ("mode", "subtract"),
+                        ("powered", "false"),
+                    ],
+                ),
+            ]),
+        ),
+        ("BlockStates".to_owned(), Value::LongArray(vec![1 | 2 << 2])),
+        (
+            "TileEntities".to_owned(),
+            Value::List(vec![Value::Compound(comparator_entity)]),
+        ),
+    ]);
+    let mut nbt = nbt::Blob::new();
+    nbt.insert(
+        "Regions",
+        Value::Compound(HashMap::from([
+            ("stone".to_owned(), Value::Compound(stone)),
+            ("comparator".to_owned(), Value::Compound(comparator)),
+        ])),
+    )
+    .unwrap();
+    let path = std::env::temp_dir().join(format!(
+        "mchprs_litematic_test_{}.litematic",
+        std::process::id()
+    ));
+    nbt.to_gzip_writer(&mut std::fs::File::create(&path).unwrap())
+        .unwrap();
+    let clipboard = load_litematic_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        (clipboard.size_x, clipboard.size_y, clipboard.size_z),
+        (2, 3, 2)
+    );
+
+    // The origin of the placement is where the clipboard gets pasted
+    let mut world = redpiler_test_world();
+    let origin = BlockPos::new(5, 10, 5);
+    paste_clipboard(&mut world, &clipboard, origin, false);
+    let stone = Block::Stone {}.get_id();
+    assert_eq!(world.get_block_raw(origin), stone);
+    assert_eq!(world.get_block_raw(origin + BlockPos::new(1, 0, 0)), stone);
+    assert_eq!(
+        world.get_block(origin + BlockPos::new(0, 2, -1)),
+        Block::RedstoneBlock {}
+    );
+    let comparator_pos = origin + BlockPos::new(0, 2, 0);
+    assert_eq!(
+        world.get_block(comparator_pos),
+        Block::RedstoneComparator {
+            comparator: RedstoneComparator::new(
+                BlockDirection::West,
+                ComparatorMode::Subtract,
+                false
+            ),
+        }
+    );
+    assert!(matches!(
+        world.get_block_entity(comparator_pos),
+        Some(BlockEntity::Comparator { output_strength: 7 })
+    ));
+}
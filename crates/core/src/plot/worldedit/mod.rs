@@ -1,27 +1,38 @@
 //! [Worldedit](https://github.com/EngineHub/WorldEdit) and [RedstoneTools](https://github.com/paulikauro/RedstoneTools) implementation
 
+mod brush;
 mod execute;
-mod schematic;
-
+mod expression;
+mod history;
+pub mod litematic;
+pub mod netlist;
+pub mod rom;
+pub mod schematic;
+
+use super::history::BlockChange;
 use super::{Plot, PlotWorld};
+use crate::config::CONFIG;
 use crate::player::{PacketSender, Player, PlayerPos};
 use crate::redstone;
 use crate::world::storage::PalettedBitBuffer;
 use crate::world::{for_each_block_mut_optimized, World};
 use execute::*;
+use mchprs_blocks::aabb::Aabb;
 use mchprs_blocks::block_entities::{BlockEntity, ContainerType};
-use mchprs_blocks::blocks::Block;
-use mchprs_blocks::{BlockFacing, BlockPos};
+use mchprs_blocks::blocks::{Block, ButtonFace, FlipDirection, LeverFace, RotateAmt};
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
 use mchprs_utils::map;
 use once_cell::sync::Lazy;
 use rand::Rng;
 use regex::Regex;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::collections::HashMap;
 use std::fmt;
-use std::ops::RangeInclusive;
 use std::str::FromStr;
 
+pub use brush::{use_brush, Brush};
+pub use history::WorldEditHistory;
+
 // Attempts to execute a worldedit command. Returns true of the command was handled.
 // The command is not handled if it is not found in the worldedit commands and alias lists.
 pub fn execute_command(
@@ -30,6 +41,8 @@ pub fn execute_command(
     command: &str,
     args: &mut Vec<&str>,
 ) -> bool {
+    let name = command;
+    let is_member = plot.is_member(plot.players[player_idx].uuid);
     let player = &mut plot.players[player_idx];
     let command = if let Some(command) = COMMANDS.get(command) {
         command
@@ -42,19 +55,11 @@ pub fn execute_command(
         return false;
     };
 
+    // WorldEdit can only be used on plots the player owns or is trusted on
     let wea = player.has_permission("plots.worldedit.bypass");
-    if !wea {
-        if let Some(owner) = plot.owner {
-            if owner != player.uuid {
-                // tried to worldedit on plot that wasn't theirs
-                player.send_no_permission_message();
-                return true;
-            }
-        } else {
-            // tried to worldedit on unclaimed plot
-            player.send_no_permission_message();
-            return true;
-        }
+    if !wea && !is_member {
+        player.send_no_permission_message();
+        return true;
     }
 
     if !command.permission_node.is_empty() && !player.has_permission(command.permission_node) {
@@ -87,39 +92,69 @@ pub fn execute_command(
     }
 
     let flag_descs = command.flags;
+    let arg_descs = command.arguments;
+    // Everything from a text argument on belongs to it, including what looks like a flag
+    let text_start = arg_descs
+        .iter()
+        .position(|desc| matches!(desc.argument_type, ArgumentType::Text));
 
     let mut ctx_flags = Vec::new();
+    let mut flag_arguments = Vec::new();
     let mut arg_removal_idxs = Vec::new();
-    for (i, arg) in args.iter().enumerate() {
-        if arg.starts_with('-') {
-            let mut with_argument = false;
-            let flags = arg.chars();
-            for flag in flags.skip(1) {
-                if with_argument {
-                    player.send_error_message("Flag with argument must be last in grouping");
-                    return true;
-                }
-                let flag_desc = if let Some(desc) = flag_descs.iter().find(|d| d.letter == flag) {
-                    desc
-                } else {
-                    player.send_error_message(&format!("Unknown flag: {}", flag));
-                    return true;
+    let mut i = 0;
+    let mut positional = 0;
+    while i < args.len() {
+        if text_start == Some(positional) {
+            break;
+        }
+        let arg = args[i];
+        if !arg.starts_with('-') {
+            positional += 1;
+            i += 1;
+            continue;
+        }
+        arg_removal_idxs.push(i);
+        let mut with_argument = false;
+        for flag in arg.chars().skip(1) {
+            if with_argument {
+                player.send_error_message("Flag with argument must be last in grouping");
+                return true;
+            }
+            let flag_desc = if let Some(desc) = flag_descs.iter().find(|d| d.letter == flag) {
+                desc
+            } else {
+                player.send_error_message(&format!("Unknown flag: {}", flag));
+                return true;
+            };
+            if let Some(argument_type) = flag_desc.argument_type {
+                let desc = ArgumentDescription {
+                    name: "flag",
+                    argument_type,
+                    description: flag_desc.description,
+                    default: None,
                 };
-                arg_removal_idxs.push(i);
-                if flag_desc.argument_type.is_some() {
-                    arg_removal_idxs.push(i + 1);
-                    with_argument = true;
+                match Argument::parse(player, &desc, args.get(i + 1).copied()) {
+                    Ok(argument) => flag_arguments.push((flag, argument)),
+                    Err(err) => {
+                        player.send_error_message(&format!("Flag {}: {}", flag, err));
+                        return true;
+                    }
                 }
-                ctx_flags.push(flag);
+                arg_removal_idxs.push(i + 1);
+                with_argument = true;
             }
+            ctx_flags.push(flag);
         }
+        // The argument of a flag can't be another flag
+        i += if with_argument { 2 } else { 1 };
     }
 
     for idx in arg_removal_idxs.iter().rev() {
         args.remove(*idx);
     }
-
-    let arg_descs = command.arguments;
+    let text = text_start
+        .filter(|&start| args.len() > start)
+        .map(|start| args.drain(start..).collect::<Vec<_>>().join(" "));
 
     if args.len() > arg_descs.len() {
         player.send_error_message("Too many arguments.");
@@ -128,7 +163,10 @@ pub fn execute_command(
 
     let mut arguments = Vec::new();
     for (i, arg_desc) in arg_descs.iter().enumerate() {
-        let arg = args.get(i).copied();
+        let arg = match &text {
+            Some(text) if text_start == Some(i) => Some(text.as_str()),
+            _ => args.get(i).copied(),
+        };
         match Argument::parse(player, arg_desc, arg) {
             Ok(default_arg) => arguments.push(default_arg),
             Err(err) => {
@@ -137,6 +175,8 @@ pub fn execute_command(
             }
         }
     }
+    let undo_len = player.worldedit_undo.len();
+    let redo_len = player.worldedit_redo.len();
     if command.mutates_world {
         plot.reset_redpiler();
     }
@@ -145,11 +185,54 @@ pub fn execute_command(
         player: &mut plot.players[player_idx],
         arguments,
         flags: ctx_flags,
+        flag_arguments,
     };
     (command.execute_fn)(ctx);
+
+    // Operations push the blocks they replaced onto the undo stack, and undoing pushes them onto
+    // the redo stack
+    let player = &plot.players[player_idx];
+    let entry = if player.worldedit_undo.len() > undo_len {
+        player.worldedit_undo.last()
+    } else if player.worldedit_redo.len() > redo_len {
+        player.worldedit_redo.last()
+    } else {
+        None
+    };
+    if let Some(entry) = entry {
+        let changes = undo_changes(&plot.world, entry);
+        plot.log_edit(player_idx, &format!("/{}", name), &changes);
+    }
     true
 }
 
+/// The blocks that were changed since `undo` was captured, in the regions it covers
+fn undo_changes(world: &PlotWorld, undo: &WorldEditUndo) -> Vec<BlockChange> {
+    if !CONFIG.edit_history {
+        return Vec::new();
+    }
+    let mut changes = Vec::new();
+    for cb in &undo.clipboards {
+        let min = undo.pos - BlockPos::new(cb.offset_x, cb.offset_y, cb.offset_z);
+        let size = BlockPos::new(cb.size_x as i32, cb.size_y as i32, cb.size_z as i32);
+        let region = Aabb::new(min, min + size - BlockPos::new(1, 1, 1));
+        for (i, pos) in region.iter().take(cb.data.entries()).enumerate() {
+            let old = cb.data.get_entry(i);
+            let new = world.get_block_raw(pos);
+            if old != new {
+                let old_entity = cb.block_entities.get(&(pos - min)).cloned();
+                changes.push(BlockChange {
+                    pos,
+                    old,
+                    new,
+                    old_entity,
+                });
+            }
+        }
+    }
+    changes
+}
+
 #[derive(Debug)]
 struct ArgumentParseError {
     arg_type: ArgumentType,
@@ -188,6 +271,8 @@ enum ArgumentType {
     Mask,
     Pattern,
     String,
+    /// The rest of the command, which can contain spaces and dashes
+    Text,
     ContainerType,
 }
 
@@ -305,7 +390,7 @@ impl Argument {
                 Ok(pattern) => Ok(Argument::Mask(pattern)),
                 Err(err) => Err(ArgumentParseError::new(arg_type, &err.to_string())),
             },
-            ArgumentType::String => Ok(Argument::String(arg.to_owned())),
+            ArgumentType::String | ArgumentType::Text => Ok(Argument::String(arg.to_owned())),
             ArgumentType::DirectionVector => {
                 let mut vec = BlockPos::new(0, 0, 0);
                 let player_facing = player.get_facing();
@@ -401,12 +486,20 @@ struct CommandExecuteContext<'a> {
     player: &'a mut Player,
     arguments: Vec<Argument>,
     flags: Vec<char>,
+    flag_arguments: Vec<(char, Argument)>,
 }
 
 impl<'a> CommandExecuteContext<'a> {
     fn has_flag(&self, c: char) -> bool {
         self.flags.contains(&c)
     }
+
+    fn flag_argument(&self, c: char) -> Option<&Argument> {
+        self.flag_arguments
+            .iter()
+            .find(|(flag, _)| *flag == c)
+            .map(|(_, argument)| argument)
+    }
 }
 
 struct WorldeditCommand {
@@ -542,7 +635,9 @@ static COMMANDS: Lazy<HashMap<&'static str, WorldeditCommand>> = Lazy::new(|| {
             description: "Paste the clipboard's contents",
             flags: &[
                 flag!('a', None, "Skip air blocks"),
+                flag!('m', Some(ArgumentType::Mask), "Only replace blocks that match the mask"),
                 flag!('u', None, "Also update all affected blocks"),
+                flag!('s', None, "Select the pasted blocks"),
             ],
             permission_node: "worldedit.clipboard.paste",
             ..Default::default()
@@ -559,6 +654,17 @@ static COMMANDS: Lazy<HashMap<&'static str, WorldeditCommand>> = Lazy::new(|| {
             permission_node: "worldedit.history.redo",
             ..Default::default()
         },
+        "/generate" => WorldeditCommand {
+            arguments: &[
+                argument!("pattern", Pattern, "The pattern of blocks to set"),
+                argument!("expression", Text, "Where to set blocks, using x, y and z")
+            ],
+            requires_positions: true,
+            execute_fn: execute_generate,
+            description: "Sets the blocks in the region where an expression is true",
+            permission_node: "worldedit.generation.generate",
+            ..Default::default()
+        },
         "/stack" => WorldeditCommand {
             arguments: &[
                 argument!("count", UnsignedInteger, "# of copies to stack"),
@@ -568,7 +674,9 @@ static COMMANDS: Lazy<HashMap<&'static str, WorldeditCommand>> = Lazy::new(|| {
             execute_fn: execute_stack,
             description: "Repeat the contents of the selection",
             flags: &[
-                flag!('a', None, "Ignore air blocks")
+                flag!('a', None, "Ignore air blocks"),
+                flag!('m', None, "Mirror every other copy along the direction"),
+                flag!('u', None, "Also update the stacked blocks and their neighbors")
             ],
             permission_node: "worldedit.region.stack",
             ..Default::default()
@@ -583,7 +691,8 @@ static COMMANDS: Lazy<HashMap<&'static str, WorldeditCommand>> = Lazy::new(|| {
             description: "Move the contents of the selection",
             flags: &[
                 flag!('a', None, "Ignore air blocks"),
-                flag!('s', None, "Shift the selection to the target location")
+                flag!('s', None, "Shift the selection to the target location"),
+                flag!('u', None, "Also update the moved blocks and their neighbors")
             ],
             permission_node: "worldedit.region.move",
             ..Default::default()
@@ -609,6 +718,43 @@ static COMMANDS: Lazy<HashMap<&'static str, WorldeditCommand>> = Lazy::new(|| {
             mutates_world: false,
             ..Default::default()
         },
+        "/netlist" => WorldeditCommand {
+            arguments: &[
+                argument!("name", String, "The file name of the netlist to import")
+            ],
+            execute_fn: execute_netlist,
+            description: "Generates a redstone layout for a netlist into the clipboard",
+            permission_node: "worldedit.clipboard.netlist",
+            mutates_world: false,
+            ..Default::default()
+        },
+        "/romsave" => WorldeditCommand {
+            arguments: &[
+                argument!("name", String, "The file name to save the ROM to")
+            ],
+            requires_positions: true,
+            execute_fn: execute_romsave,
+            description: "Saves the contents of the ROM in the selection to a file",
+            flags: &[
+                flag!('t', Some(ArgumentType::Mask), "The cells are blocks with a torch on top for a 1, instead of containers")
+            ],
+            permission_node: "worldedit.rom.save",
+            mutates_world: false,
+            ..Default::default()
+        },
+        "/romload" => WorldeditCommand {
+            arguments: &[
+                argument!("name", String, "The file name of the ROM to load")
+            ],
+            requires_positions: true,
+            execute_fn: execute_romload,
+            description: "Writes a file to the cells of the ROM in the selection",
+            flags: &[
+                flag!('t', Some(ArgumentType::Mask), "The cells are blocks with a torch on top for a 1, instead of containers")
+            ],
+            permission_node: "worldedit.rom.load",
+            ..Default::default()
+        },
         "/save" => WorldeditCommand {
             arguments: &[
                 argument!("name", String, "The file name of the schematic to save")
@@ -719,6 +865,29 @@ static COMMANDS: Lazy<HashMap<&'static str, WorldeditCommand>> = Lazy::new(|| {
             mutates_world: false,
            ..Default::default()
         },
+        "brush" => WorldeditCommand {
+            arguments: &[
+                argument!("shape", String, "The shape of the brush, sphere or cylinder"),
+                argument!("pattern", Pattern, "The pattern of blocks to set"),
+                argument!("radius", UnsignedInteger, "The radius of the brush", 2),
+                argument!("height", UnsignedInteger, "The height of a cylinder brush", 1)
+            ],
+            execute_fn: execute_brush,
+            description: "Binds a brush to the item in your hand",
+            flags: &[
+                flag!('m', Some(ArgumentType::Mask), "Only replace blocks that match the mask"),
+            ],
+            permission_node: "worldedit.brush",
+            mutates_world: false,
+            ..Default::default()
+        },
+        "none" => WorldeditCommand {
+            execute_fn: execute_none,
+            description: "Unbinds the brush from the item in your hand",
+            permission_node: "worldedit.brush",
+            mutates_world: false,
+            ..Default::default()
+        },
         "/replacecontainer" => WorldeditCommand {
             arguments: &[
                 argument!("from", ContainerType, "The container type to replace"),
@@ -752,7 +921,10 @@ static ALIASES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
         "/h1" => "/hpos1",
         "/h2" => "/hpos2",
         "/rs" => "/rstack",
-        "/rc" => "/replacecontainer"
+        "/rc" => "/replacecontainer",
+        "br" => "brush",
+        "/gen" => "/generate",
+        "/g" => "/generate"
     }
 });
 
@@ -774,6 +946,154 @@ pub struct WorldEditClipboard {
     pub block_entities: FxHashMap<BlockPos, BlockEntity>,
 }
 
+impl WorldEditClipboard {
+    /// Mirrors the clipboard along `direction`, with the blocks flipped to match
+    pub fn flipped(&self, direction: BlockFacing) -> WorldEditClipboard {
+        let size_x = self.size_x;
+        let size_y = self.size_y;
+        let size_z = self.size_z;
+        let volume = size_x * size_y * size_z;
+
+        let flip_pos = |mut pos: BlockPos| {
+            match direction {
+                BlockFacing::East | BlockFacing::West => pos.x = size_x as i32 - 1 - pos.x,
+                BlockFacing::North | BlockFacing::South => pos.z = size_z as i32 - 1 - pos.z,
+                BlockFacing::Up | BlockFacing::Down => pos.y = size_y as i32 - 1 - pos.y,
+            }
+            pos
+        };
+
+        let mut newcpdata = PalettedBitBuffer::new((volume) as usize, 9);
+
+        let mut c_x = 0;
+        let mut c_y = 0;
+        let mut c_z = 0;
+        for i in 0..volume {
+            let BlockPos {
+                x: n_x,
+                y: n_y,
+                z: n_z,
+            } = flip_pos(BlockPos::new(c_x, c_y, c_z));
+            let n_i = (n_y as u32 * size_x * size_z) + (n_z as u32 * size_x) + n_x as u32;
+
+            let mut block = Block::from_id(self.data.get_entry(i as usize));
+            match direction {
+                BlockFacing::East | BlockFacing::West => block.flip(FlipDirection::FlipX),
+                BlockFacing::North | BlockFacing::South => block.flip(FlipDirection::FlipZ),
+                BlockFacing::Up | BlockFacing::Down => block.flip(FlipDirection::FlipY),
+            }
+            newcpdata.set_entry(n_i as usize, block.get_id());
+
+            // Ok now lets increment the coordinates for the next block
+            c_x += 1;
+
+            if c_x as u32 == size_x {
+                c_x = 0;
+                c_z += 1;
+
+                if c_z as u32 == size_z {
+                    c_z = 0;
+                    c_y += 1;
+                }
+            }
+        }
+
+        let offset = flip_pos(BlockPos::new(self.offset_x, self.offset_y, self.offset_z));
+        WorldEditClipboard {
+            offset_x: offset.x,
+            offset_y: offset.y,
+            offset_z: offset.z,
+            size_x,
+            size_y,
+            size_z,
+            data: newcpdata,
+            block_entities: self
+                .block_entities
+                .iter()
+                .map(|(pos, e)| (flip_pos(*pos), e.clone()))
+                .collect(),
+        }
+    }
+
+    /// Rotates the clipboard around the y axis, with the blocks rotated to match
+    pub fn rotated(&self, rotate_amt: RotateAmt) -> WorldEditClipboard {
+        let size_x = self.size_x;
+        let size_y = self.size_y;
+        let size_z = self.size_z;
+        let volume = size_x * size_y * size_z;
+
+        let (n_size_x, n_size_z) = match rotate_amt {
+            RotateAmt::Rotate90 | RotateAmt::Rotate270 => (size_z, size_x),
+            _ => (size_x, size_z),
+        };
+
+        let rotate_pos = |pos: BlockPos| match rotate_amt {
+            RotateAmt::Rotate90 => BlockPos {
+                x: n_size_x as i32 - 1 - pos.z,
+                y: pos.y,
+                z: pos.x,
+            },
+            RotateAmt::Rotate180 => BlockPos {
+                x: n_size_x as i32 - 1 - pos.x,
+                y: pos.y,
+                z: n_size_z as i32 - 1 - pos.z,
+            },
+            RotateAmt::Rotate270 => BlockPos {
+                x: pos.z,
+                y: pos.y,
+                z: n_size_z as i32 - 1 - pos.x,
+            },
+        };
+
+        let mut newcpdata = PalettedBitBuffer::new((volume) as usize, 9);
+
+        let mut c_x = 0;
+        let mut c_y = 0;
+        let mut c_z = 0;
+        for i in 0..volume {
+            let BlockPos {
+                x: n_x,
+                y: n_y,
+                z: n_z,
+            } = rotate_pos(BlockPos::new(c_x, c_y, c_z));
+            let n_i = (n_y as u32 * n_size_x * n_size_z) + (n_z as u32 * n_size_x) + n_x as u32;
+
+            let mut block = Block::from_id(self.data.get_entry(i as usize));
+            block.rotate(rotate_amt);
+            newcpdata.set_entry(n_i as usize, block.get_id());
+
+            // Ok now lets increment the coordinates for the next block
+            c_x += 1;
+
+            if c_x as u32 == size_x {
+                c_x = 0;
+                c_z += 1;
+
+                if c_z as u32 == size_z {
+                    c_z = 0;
+                    c_y += 1;
+                }
+            }
+        }
+
+        let offset = rotate_pos(BlockPos::new(self.offset_x, self.offset_y, self.offset_z));
+        WorldEditClipboard {
+            offset_x: offset.x,
+            offset_y: offset.y,
+            offset_z: offset.z,
+            size_x: n_size_x,
+            size_y,
+            size_z: n_size_z,
+            data: newcpdata,
+            block_entities: self
+                .block_entities
+                .iter()
+                .map(|(pos, e)| (rotate_pos(*pos), e.clone()))
+                .collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct WorldEditUndo {
     clipboards: Vec<WorldEditClipboard>,
@@ -782,6 +1102,7 @@ pub struct WorldEditUndo {
     plot_z: i32,
 }
 
+#[derive(Debug)]
 pub enum PatternParseError {
     UnknownBlock(String),
     InvalidPattern(String),
@@ -886,25 +1207,14 @@ impl WorldEditPattern {
 
 struct WorldEditOperation {
     blocks_updated: usize,
-    x_range: RangeInclusive<i32>,
-    y_range: RangeInclusive<i32>,
-    z_range: RangeInclusive<i32>,
+    region: Aabb,
 }
 
 impl WorldEditOperation {
     fn new(first_pos: BlockPos, second_pos: BlockPos) -> WorldEditOperation {
-        let start_pos = first_pos.min(second_pos);
-        let end_pos = first_pos.max(second_pos);
-
-        let x_range = start_pos.x..=end_pos.x;
-        let y_range = start_pos.y..=end_pos.y;
-        let z_range = start_pos.z..=end_pos.z;
-
         WorldEditOperation {
             blocks_updated: 0,
-            x_range,
-            y_range,
-            z_range,
+            region: Aabb::new(first_pos, second_pos),
         }
     }
 
@@ -916,14 +1226,8 @@ impl WorldEditOperation {
         self.blocks_updated
     }
 
-    fn x_range(&self) -> RangeInclusive<i32> {
-        self.x_range.clone()
-    }
-    fn y_range(&self) -> RangeInclusive<i32> {
-        self.y_range.clone()
-    }
-    fn z_range(&self) -> RangeInclusive<i32> {
-        self.z_range.clone()
+    fn region(&self) -> Aabb {
+        self.region
     }
 }
 
@@ -971,62 +1275,119 @@ fn worldedit_start_operation(player: &mut Player) -> WorldEditOperation {
     WorldEditOperation::new(first_pos, second_pos)
 }
 
-fn create_clipboard(
+pub(crate) fn create_clipboard(
     plot: &mut PlotWorld,
     origin: BlockPos,
     first_pos: BlockPos,
     second_pos: BlockPos,
 ) -> WorldEditClipboard {
-    let start_pos = first_pos.min(second_pos);
-    let end_pos = first_pos.max(second_pos);
-    let size_x = (end_pos.x - start_pos.x) as u32 + 1;
-    let size_y = (end_pos.y - start_pos.y) as u32 + 1;
-    let size_z = (end_pos.z - start_pos.z) as u32 + 1;
-    let offset = origin - start_pos;
+    let region = Aabb::new(first_pos, second_pos);
+    let size = region.size();
+    let offset = origin - region.min;
     let mut cb = WorldEditClipboard {
         offset_x: offset.x,
         offset_y: offset.y,
         offset_z: offset.z,
-        size_x,
-        size_y,
-        size_z,
-        data: PalettedBitBuffer::new((size_x * size_y * size_z) as usize, 9),
+        size_x: size.x as u32,
+        size_y: size.y as u32,
+        size_z: size.z as u32,
+        data: PalettedBitBuffer::new(region.volume(), 9),
         block_entities: FxHashMap::default(),
     };
-    let mut i = 0;
-    for y in start_pos.y..=end_pos.y {
-        for z in start_pos.z..=end_pos.z {
-            for x in start_pos.x..=end_pos.x {
-                let pos = BlockPos::new(x, y, z);
-                let id = plot.get_block_raw(pos);
-                let block = plot.get_block(BlockPos::new(x, y, z));
-                if block.has_block_entity() {
-                    if let Some(block_entity) = plot.get_block_entity(pos) {
-                        cb.block_entities
-                            .insert(pos - start_pos, block_entity.clone());
-                    }
-                }
-                cb.data.set_entry(i, id);
-                i += 1;
+    for (i, pos) in region.iter().enumerate() {
+        let id = plot.get_block_raw(pos);
+        let block = Block::from_id(id);
+        if block.has_block_entity() {
+            if let Some(block_entity) = plot.get_block_entity(pos) {
+                cb.block_entities
+                    .insert(pos - region.min, block_entity.clone());
             }
         }
+        cb.data.set_entry(i, id);
     }
     cb
 }
 
-fn clear_area(plot: &mut PlotWorld, first_pos: BlockPos, second_pos: BlockPos) {
-    let start_pos = first_pos.min(second_pos);
-    let end_pos = first_pos.max(second_pos);
-    for y in start_pos.y..=end_pos.y {
-        for z in start_pos.z..=end_pos.z {
-            for x in start_pos.x..=end_pos.x {
-                plot.set_block_raw(BlockPos::new(x, y, z), 0);
+/// Returns the blocks that the components at `positions` are placed on or get their input
+/// through, which are needed for the components to keep working when they are pasted
+fn supporting_blocks(plot: &PlotWorld, positions: &FxHashSet<BlockPos>) -> Vec<BlockPos> {
+    let mut supports = Vec::new();
+    let mut neighbor_counts: FxHashMap<BlockPos, u32> = FxHashMap::default();
+    for &pos in positions {
+        supports.push(pos.offset(BlockFace::Bottom));
+        match plot.get_block(pos) {
+            Block::RedstoneWallTorch { facing, .. } => {
+                supports.push(pos.offset(facing.opposite().block_face()));
+            }
+            Block::Lever { lever } => match lever.face {
+                LeverFace::Ceiling => supports.push(pos.offset(BlockFace::Top)),
+                LeverFace::Wall => supports.push(pos.offset(lever.facing.opposite().block_face())),
+                LeverFace::Floor => {}
+            },
+            Block::StoneButton { button } => match button.face {
+                ButtonFace::Ceiling => supports.push(pos.offset(BlockFace::Top)),
+                ButtonFace::Wall => {
+                    supports.push(pos.offset(button.facing.opposite().block_face()))
+                }
+                ButtonFace::Floor => {}
+            },
+            Block::RedstoneRepeater { repeater } => {
+                supports.push(pos.offset(repeater.facing.block_face()));
             }
+            Block::RedstoneComparator { comparator } => {
+                let input_pos = pos.offset(comparator.facing.block_face());
+                supports.push(input_pos);
+                supports.push(input_pos.offset(comparator.facing.block_face()));
+            }
+            _ => {}
+        }
+        for face in &BlockFace::values() {
+            *neighbor_counts.entry(pos.offset(*face)).or_default() += 1;
+        }
+    }
+    // Solid blocks in between components conduct power from one to the other
+    supports.extend(
+        neighbor_counts
+            .into_iter()
+            .filter(|&(pos, count)| count >= 2 && plot.get_block(pos).is_solid())
+            .map(|(pos, _)| pos),
+    );
+    supports.retain(|&pos| plot.get_block(pos) != (Block::Air {}));
+    supports
+}
+
+/// Creates a clipboard that only contains the blocks at `positions` and the blocks that
+/// support them. Everything else in the bounding box is left as air. `positions` must not be
+/// empty.
+pub(crate) fn create_extract_clipboard(
+    plot: &mut PlotWorld,
+    origin: BlockPos,
+    positions: &[BlockPos],
+) -> WorldEditClipboard {
+    let mut included: FxHashSet<BlockPos> = positions.iter().copied().collect();
+    let supports = supporting_blocks(plot, &included);
+    included.extend(supports);
+
+    let region = Aabb::enclosing(included.iter().copied()).unwrap();
+    let mut cb = create_clipboard(plot, origin, region.min, region.max);
+    for (i, pos) in region.iter().enumerate() {
+        if !included.contains(&pos) {
+            cb.data.set_entry(i, 0);
+            cb.block_entities.remove(&(pos - region.min));
         }
     }
+    cb
+}
+
+fn clear_area(plot: &mut PlotWorld, first_pos: BlockPos, second_pos: BlockPos) {
+    let region = Aabb::new(first_pos, second_pos);
+    for pos in region {
+        plot.set_block_raw(pos, 0);
+        plot.delete_block_entity(pos);
+    }
     // Send modified chunks
-    for chunk_x in (start_pos.x >> 4)..=(end_pos.x >> 4) {
-        for chunk_z in (start_pos.z >> 4)..=(end_pos.z >> 4) {
+    for chunk_x in (region.min.x >> 4)..=(region.max.x >> 4) {
+        for chunk_z in (region.min.z >> 4)..=(region.max.z >> 4) {
             if let Some(chunk) = plot.get_chunk(chunk_x, chunk_z) {
                 let chunk_data = chunk.encode_packet();
                 for player in &mut plot.packet_senders {
@@ -1037,45 +1398,108 @@ fn clear_area(plot: &mut PlotWorld, first_pos: BlockPos, second_pos: BlockPos) {
     }
 }
 
-fn paste_clipboard(plot: &mut PlotWorld, cb: &WorldEditClipboard, pos: BlockPos, ignore_air: bool) {
+pub(crate) fn paste_clipboard(
+    plot: &mut PlotWorld,
+    cb: &WorldEditClipboard,
+    pos: BlockPos,
+    ignore_air: bool,
+) {
+    paste_clipboard_masked(plot, cb, pos, ignore_air, None);
+}
+
+/// Pastes the clipboard, but only replaces the blocks in the world that match `mask`. Blocks are
+/// placed without updates, returns the positions of the blocks that were pasted so they can be
+/// updated afterwards.
+pub(crate) fn paste_clipboard_masked(
+    plot: &mut PlotWorld,
+    cb: &WorldEditClipboard,
+    pos: BlockPos,
+    ignore_air: bool,
+    mask: Option<&WorldEditPattern>,
+) -> Vec<BlockPos> {
     let offset_x = pos.x - cb.offset_x;
     let offset_y = pos.y - cb.offset_y;
     let offset_z = pos.z - cb.offset_z;
-    let mut i = 0;
-    // This can be made better, but right now it's not D:
-    let x_range = offset_x..offset_x + cb.size_x as i32;
-    let y_range = offset_y..offset_y + cb.size_y as i32;
-    let z_range = offset_z..offset_z + cb.size_z as i32;
+    let min = BlockPos::new(offset_x, offset_y, offset_z);
+    let size = BlockPos::new(cb.size_x as i32, cb.size_y as i32, cb.size_z as i32);
+    let region = Aabb::new(min, min + size - BlockPos::new(1, 1, 1));
 
+    let mut pasted = Vec::new();
+    let mut block_entities = Vec::new();
     let entries = cb.data.entries();
-    // I have no clue if these clones are going to cost anything noticeable.
-    'top_loop: for y in y_range {
-        for z in z_range.clone() {
-            for x in x_range.clone() {
-                if i >= entries {
-                    break 'top_loop;
-                }
-                let entry = cb.data.get_entry(i);
-                i += 1;
-                if ignore_air && entry == 0 {
-                    continue;
-                }
-                plot.set_block_raw(BlockPos::new(x, y, z), entry);
+    for (i, pos) in region.iter().take(entries).enumerate() {
+        let entry = cb.data.get_entry(i);
+        if ignore_air && entry == 0 {
+            continue;
+        }
+        if let Some(mask) = mask {
+            if !mask.matches(plot.get_block(pos)) {
+                continue;
             }
         }
+        plot.set_block_raw(pos, entry);
+        match cb.block_entities.get(&(pos - min)) {
+            Some(block_entity) => block_entities.push((pos, block_entity)),
+            // Otherwise a container that was pasted over would keep its comparator output
+            None => plot.delete_block_entity(pos),
+        }
+        pasted.push(pos);
     }
 
     // Send block changes before we send block entity data, otherwise it'll be ignored
     plot.flush_block_changes();
 
-    for (pos, block_entity) in &cb.block_entities {
-        let new_pos = BlockPos {
-            x: pos.x + offset_x,
-            y: pos.y + offset_y,
-            z: pos.z + offset_z,
+    for (pos, block_entity) in block_entities {
+        plot.set_block_entity(pos, block_entity.clone());
+    }
+    pasted
+}
+
+/// Pastes `count` copies of `clipboard`, which was copied with its origin at its minimum corner
+/// `min`, next to each other in `direction`. With `mirror`, every other copy is flipped along
+/// `direction`, which keeps redstone pointing the right way, e.g. towards a bus shared by two
+/// copies. Returns the clipboards to undo the stack with and the positions of the pasted blocks.
+pub(crate) fn stack_clipboard(
+    plot: &mut PlotWorld,
+    clipboard: &WorldEditClipboard,
+    min: BlockPos,
+    direction: BlockFacing,
+    count: u32,
+    ignore_air: bool,
+    mirror: bool,
+) -> (Vec<WorldEditClipboard>, Vec<BlockPos>) {
+    let mirrored = mirror.then(|| clipboard.flipped(direction));
+    let size = BlockPos::new(
+        clipboard.size_x as i32,
+        clipboard.size_y as i32,
+        clipboard.size_z as i32,
+    );
+    let stack_offset = match direction {
+        BlockFacing::North | BlockFacing::South => size.z,
+        BlockFacing::East | BlockFacing::West => size.x,
+        BlockFacing::Up | BlockFacing::Down => size.y,
+    };
+    let mut undo_cbs = Vec::new();
+    let mut pasted = Vec::new();
+    for i in 1..count as i32 + 1 {
+        let copy_min = direction.offset_pos(min, i * stack_offset);
+        let copy_max = copy_min + size - BlockPos::new(1, 1, 1);
+        undo_cbs.push(create_clipboard(plot, min, copy_min, copy_max));
+        let clipboard = match &mirrored {
+            Some(mirrored) if i % 2 == 1 => mirrored,
+            _ => clipboard,
         };
-        plot.set_block_entity(new_pos, block_entity.clone());
+        // The offset of a flipped clipboard is flipped as well
+        let offset = BlockPos::new(clipboard.offset_x, clipboard.offset_y, clipboard.offset_z);
+        pasted.extend(paste_clipboard_masked(
+            plot,
+            clipboard,
+            copy_min + offset,
+            ignore_air,
+            None,
+        ));
     }
+    (undo_cbs, pasted)
 }
 
 fn capture_undo(
@@ -1143,8 +1567,169 @@ fn expand_selection(player: &mut Player, amount: BlockPos, contract: bool) {
 }
 
 fn update(plot: &mut PlotWorld, first_pos: BlockPos, second_pos: BlockPos) {
+    plot.begin_bulk_edit();
     for_each_block_mut_optimized(plot, first_pos, second_pos, |plot, pos| {
         let block = plot.get_block(pos);
         redstone::update(block, plot, pos);
     });
+    plot.end_bulk_edit();
+}
+
+/// Updates the pasted blocks and the blocks surrounding them. This happens once the whole
+/// paste is done, and every block is only updated once even if it is next to many pasted
+/// blocks.
+pub(crate) fn update_pasted(plot: &mut PlotWorld, pasted: &[BlockPos]) {
+    let mut positions = FxHashSet::default();
+    for &pos in pasted {
+        positions.insert(pos);
+        for face in BlockFace::values() {
+            let neighbor = pos.offset(face);
+            positions.insert(neighbor);
+            positions.insert(neighbor.offset(BlockFace::Top));
+            positions.insert(neighbor.offset(BlockFace::Bottom));
+        }
+    }
+    let mut positions: Vec<BlockPos> = positions
+        .into_iter()
+        .filter(|pos| {
+            Plot::in_plot_bounds(plot.x, plot.z, pos.x, pos.z)
+                && (0..plot.size.block_height()).contains(&pos.y)
+        })
+        .collect();
+    // Sorted so that updates happen in the same order every time
+    positions.sort_by_key(|pos| (pos.y, pos.z, pos.x));
+    plot.begin_bulk_edit();
+    for pos in positions {
+        let block = plot.get_block(pos);
+        redstone::update(block, plot, pos);
+    }
+    plot.end_bulk_edit();
+}
+
+#[test]
+fn masked_paste_only_replaces_matching_blocks() {
+    use crate::plot::redpiler_test_world;
+
+    let mut world = redpiler_test_world();
+    let (first, second) = (BlockPos::new(0, 20, 0), BlockPos::new(2, 20, 0));
+    for x in 0..3 {
+        world.set_block(BlockPos::new(x, 20, 0), Block::RedstoneBlock {});
+    }
+    let clipboard = create_clipboard(&mut world, first, first, second);
+
+    let wool = Block::from_name("white_wool").unwrap();
+    world.set_block(BlockPos::new(0, 10, 0), wool);
+    world.set_block(BlockPos::new(1, 10, 0), Block::Stone {});
+    let lamp_pos = BlockPos::new(2, 11, 0);
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mask = WorldEditPattern::from_str("air,white_wool").unwrap();
+    let pasted = paste_clipboard_masked(
+        &mut world,
+        &clipboard,
+        BlockPos::new(0, 10, 0),
+        false,
+        Some(&mask),
+    );
+    assert_eq!(
+        pasted,
+        vec![BlockPos::new(0, 10, 0), BlockPos::new(2, 10, 0)]
+    );
+    assert_eq!(
+        world.get_block(BlockPos::new(0, 10, 0)),
+        Block::RedstoneBlock {}
+    );
+    assert_eq!(world.get_block(BlockPos::new(1, 10, 0)), Block::Stone {});
+    assert_eq!(
+        world.get_block(BlockPos::new(2, 10, 0)),
+        Block::RedstoneBlock {}
+    );
+
+    // Blocks are placed without updates, the lamp only turns on once the paste is updated
+    assert_eq!(
+        world.get_block(lamp_pos),
+        Block::RedstoneLamp { lit: false }
+    );
+    update_pasted(&mut world, &pasted);
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+}
+
+#[test]
+fn bulk_edits_defer_block_updates() {
+    use crate::interaction;
+    use crate::plot::redpiler_test_world;
+
+    let mut world = redpiler_test_world();
+    let lamp_pos = BlockPos::new(12, 8, 10);
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    world.begin_bulk_edit();
+    for x in 10..12 {
+        interaction::place_in_world(
+            Block::RedstoneBlock {},
+            &mut world,
+            BlockPos::new(x, 8, 10),
+            &None,
+        );
+    }
+    assert_eq!(
+        world.get_block(lamp_pos),
+        Block::RedstoneLamp { lit: false }
+    );
+    // Nested bulk edits only process the updates once the outermost one ends
+    world.begin_bulk_edit();
+    world.end_bulk_edit();
+    assert_eq!(
+        world.get_block(lamp_pos),
+        Block::RedstoneLamp { lit: false }
+    );
+    world.end_bulk_edit();
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+}
+
+#[test]
+fn stacking_can_mirror_copies() {
+    use crate::plot::redpiler_test_world;
+    use mchprs_blocks::blocks::{ComparatorMode, RedstoneComparator};
+    use mchprs_blocks::BlockDirection;
+
+    let comparator = |facing| Block::RedstoneComparator {
+        comparator: RedstoneComparator::new(facing, ComparatorMode::Compare, false),
+    };
+    let mut world = redpiler_test_world();
+    let min = BlockPos::new(0, 10, 0);
+    world.set_block(min, comparator(BlockDirection::West));
+    world.set_block_entity(min, BlockEntity::Comparator { output_strength: 5 });
+    world.set_block(BlockPos::new(1, 10, 0), Block::Stone {});
+    let clipboard = create_clipboard(&mut world, min, min, BlockPos::new(1, 10, 0));
+
+    let (undo, pasted) = stack_clipboard(
+        &mut world,
+        &clipboard,
+        min,
+        BlockFacing::East,
+        2,
+        false,
+        true,
+    );
+    assert_eq!(undo.len(), 2);
+    assert_eq!(pasted.len(), 4);
+    // The first copy is mirrored, the second one isn't
+    assert_eq!(world.get_block(BlockPos::new(2, 10, 0)), Block::Stone {});
+    assert_eq!(
+        world.get_block(BlockPos::new(3, 10, 0)),
+        comparator(BlockDirection::East)
+    );
+    assert_eq!(
+        world.get_block(BlockPos::new(4, 10, 0)),
+        comparator(BlockDirection::West)
+    );
+    assert_eq!(world.get_block(BlockPos::new(5, 10, 0)), Block::Stone {});
+    for x in [3, 4] {
+        assert!(matches!(
+            world.get_block_entity(BlockPos::new(x, 10, 0)),
+            Some(BlockEntity::Comparator { output_strength: 5 })
+        ));
+    }
+    assert!(world.get_block_entity(BlockPos::new(2, 10, 0)).is_none());
 }
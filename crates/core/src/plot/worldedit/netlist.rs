@@ -0,0 +1,277 @@
+//! Generates a redstone layout for a BLIF or ASCII AIGER netlist, so that circuits synthesized
+//! from a hardware description can be pasted into a plot. The netlist is lowered to NAND gates
+//! and every gate gets a column of the layout:
+//!
+//! - Every signal is a line of repeaters along x, with a block between every two repeaters
+//!   where the gates of the columns can take the signal. Inputs start at a lever and outputs
+//!   end at a lamp.
+//! - A gate is a line of dust along z above the lines, with a torch under it on the blocks of
+//!   the lines of its inputs. The dust is on if any of the inputs is off, and steps down at its
+//!   end to start the line of the output.
+//!
+//! Every column adds a tick of delay to the lines that pass it, so outputs can glitch before
+//! they settle. Only combinational logic can be imported, netlists with latches are rejected.
+
+use super::WorldEditClipboard;
+use crate::redpiler::netlist::{NandCircuit, NandSignal, Netlist};
+use crate::world::storage::PalettedBitBuffer;
+use anyhow::{Error, Result};
+use mchprs_blocks::aabb::Aabb;
+use mchprs_blocks::block_entities::{BlockEntity, SignBlockEntity};
+use mchprs_blocks::blocks::{
+    Block, Lever, LeverFace, RedstoneRepeater, RedstoneWire, RedstoneWireSide, SignRotation,
+};
+use mchprs_blocks::{BlockDirection, BlockPos, SignType};
+use rustc_hash::FxHashMap;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// The most dust the line of a gate has in a row before it is repeated, so that the signal
+/// still reaches the line of the output after stepping down
+const MAX_DUST: u32 = 8;
+
+/// The x of the blocks where the gate of `column` takes its inputs. Lines of inputs start at 0.
+fn column_x(column: usize) -> i32 {
+    2 * column as i32 + 2
+}
+
+fn wire(north: RedstoneWireSide, south: RedstoneWireSide, east: RedstoneWireSide) -> Block {
+    Block::RedstoneWire {
+        wire: RedstoneWire::new(north, south, east, RedstoneWireSide::None, 0),
+    }
+}
+
+/// A repeater with its output towards `facing`
+fn repeater(facing: BlockDirection) -> Block {
+    Block::RedstoneRepeater {
+        repeater: RedstoneRepeater {
+            delay: 1,
+            facing: facing.opposite(),
+            locked: false,
+            powered: false,
+        },
+    }
+}
+
+#[derive(Default)]
+struct Layout {
+    blocks: FxHashMap<BlockPos, Block>,
+    signs: FxHashMap<BlockPos, String>,
+}
+
+impl Layout {
+    fn set(&mut self, x: i32, y: i32, z: i32, block: Block) {
+        self.blocks.insert(BlockPos::new(x, y, z), block);
+    }
+
+    /// A sign with `name` on the ground at `x`, `z`, readable from `facing`
+    fn sign(&mut self, x: i32, z: i32, facing: BlockDirection, name: &str) {
+        let rotation = match facing {
+            BlockDirection::South => 0,
+            BlockDirection::West => 4,
+            BlockDirection::North => 8,
+            BlockDirection::East => 12,
+        };
+        self.set(x, 0, z, Block::Stone {});
+        self.set(
+            x,
+            1,
+            z,
+            Block::Sign {
+                sign_type: SignType(0),
+                rotation: SignRotation(rotation),
+            },
+        );
+        self.signs.insert(BlockPos::new(x, 1, z), name.to_owned());
+    }
+
+    /// A line from the source of a signal at `start_x` to the block at `end_x`
+    fn line(&mut self, z: i32, start_x: i32, end_x: i32) {
+        for x in (start_x + 2..=end_x).step_by(2) {
+            self.set(x - 1, 0, z, Block::Stone {});
+            self.set(x - 1, 1, z, repeater(BlockDirection::East));
+            self.set(x, 1, z, Block::Stone {});
+        }
+    }
+
+    fn into_clipboard(self) -> WorldEditClipboard {
+        let mut max = BlockPos::new(0, 0, 0);
+        for pos in self.blocks.keys() {
+            max = BlockPos::new(max.x.max(pos.x), max.y.max(pos.y), max.z.max(pos.z));
+        }
+        let region = Aabb::new(BlockPos::new(0, 0, 0), max);
+        let mut data = PalettedBitBuffer::new(region.volume(), 9);
+        for (&pos, block) in &self.blocks {
+            data.set_entry(region.index_of(pos), block.get_id());
+        }
+        let block_entities = self
+            .signs
+            .into_iter()
+            .map(|(pos, name)| {
+                let rows =
+                    [name.as_str(), "", "", ""].map(|row| json!({ "text": row }).to_string());
+                (pos, BlockEntity::Sign(Box::new(SignBlockEntity { rows })))
+            })
+            .collect();
+        WorldEditClipboard {
+            offset_x: 0,
+            offset_y: 0,
+            offset_z: 0,
+            size_x: max.x as u32 + 1,
+            size_y: max.y as u32 + 1,
+            size_z: max.z as u32 + 1,
+            data,
+            block_entities,
+        }
+    }
+}
+
+fn layout(circuit: &NandCircuit) -> WorldEditClipboard {
+    let mut layout = Layout::default();
+    let end_x = column_x(circuit.gates.len());
+
+    // The x of the last block every signal has to reach
+    let mut ends: FxHashMap<NandSignal, i32> = FxHashMap::default();
+    for (column, inputs) in circuit.gates.iter().enumerate() {
+        for &input in inputs {
+            ends.insert(input, column_x(column));
+        }
+    }
+    for &(_, output) in &circuit.outputs {
+        ends.insert(output, end_x);
+    }
+
+    // The z of the line of every signal and the x where it starts
+    let mut lines: FxHashMap<NandSignal, (i32, i32)> = FxHashMap::default();
+    let mut z = 0;
+    for (i, input) in circuit.inputs.iter().enumerate() {
+        layout.set(0, 0, z, Block::Stone {});
+        let lever = Lever {
+            face: LeverFace::Floor,
+            facing: BlockDirection::East,
+            powered: false,
+        };
+        layout.set(0, 1, z, Block::Lever { lever });
+        layout.sign(0, z + 1, BlockDirection::West, input);
+        lines.insert(NandSignal::Input(i), (z, 0));
+        z += 2;
+    }
+    for value in [false, true] {
+        let constant = NandSignal::Constant(value);
+        if ends.contains_key(&constant) {
+            let block = match value {
+                true => Block::RedstoneBlock {},
+                false => Block::Stone {},
+            };
+            layout.set(0, 1, z, block);
+            lines.insert(constant, (z, 0));
+            z += 2;
+        }
+    }
+
+    let mut last_z = z - 2;
+    for (column, inputs) in circuit.gates.iter().enumerate() {
+        let x = column_x(column);
+        let taps: Vec<i32> = inputs.iter().map(|input| lines[input].0).collect();
+        for &z in &taps {
+            layout.set(x, 2, z, Block::RedstoneTorch { lit: true });
+        }
+
+        let end_z = last_z + 1;
+        let start_z = taps.iter().copied().min().unwrap_or(end_z);
+        let mut dust = 0;
+        for z in start_z..=end_z {
+            layout.set(x, 3, z, Block::Stone {});
+            if dust >= MAX_DUST && z < end_z && !taps.contains(&z) {
+                layout.set(x, 4, z, repeater(BlockDirection::South));
+                dust = 0;
+            } else {
+                let side = RedstoneWireSide::Side;
+                layout.set(x, 4, z, wire(side, side, RedstoneWireSide::None));
+                dust += 1;
+            }
+        }
+
+        // Steps down to the line of the output
+        let (up, side, none) = (
+            RedstoneWireSide::Up,
+            RedstoneWireSide::Side,
+            RedstoneWireSide::None,
+        );
+        layout.set(x, 2, end_z + 1, Block::Stone {});
+        layout.set(x, 3, end_z + 1, wire(up, side, none));
+        layout.set(x, 1, end_z + 2, Block::Stone {});
+        layout.set(x, 2, end_z + 2, wire(up, side, none));
+        layout.set(x, 0, end_z + 3, Block::Stone {});
+        layout.set(x, 1, end_z + 3, wire(up, none, side));
+        last_z = end_z + 3;
+        lines.insert(NandSignal::Gate(column), (last_z, x));
+    }
+
+    for (signal, &(z, start_x)) in &lines {
+        if let Some(&end) = ends.get(signal) {
+            layout.line(z, start_x, end);
+        }
+    }
+    for (name, output) in &circuit.outputs {
+        let z = lines[output].0;
+        layout.set(end_x, 1, z, Block::RedstoneLamp { lit: false });
+        layout.sign(end_x, z + 1, BlockDirection::East, name);
+    }
+    layout.into_clipboard()
+}
+
+/// Loads a netlist from the `netlists` folder as a clipboard with its layout, where files
+/// ending in `.aag` are read as AIGER and every other file as BLIF
+pub fn load_netlist(file_name: &str) -> Result<WorldEditClipboard> {
+    let text = fs::read_to_string(Path::new("./netlists/").join(file_name))?;
+    let netlist = match file_name.ends_with(".aag") {
+        true => Netlist::from_aiger(&text),
+        false => Netlist::from_blif(&text),
+    };
+    let circuit = netlist
+        .and_then(|netlist| netlist.to_nands())
+        .map_err(Error::msg)?;
+    Ok(layout(&circuit))
+}
+
+#[test]
+fn netlist_layouts_have_a_lever_per_input_and_a_lamp_per_output() {
+    let blif = ".model xor\n.inputs a b\n.outputs y\n.names a b y\n10 1\n01 1\n.end\n";
+    let circuit = Netlist::from_blif(blif).unwrap().to_nands().unwrap();
+    let clipboard = layout(&circuit);
+    let region = Aabb::new(
+        BlockPos::new(0, 0, 0),
+        BlockPos::new(
+            clipboard.size_x as i32 - 1,
+            clipboard.size_y as i32 - 1,
+            clipboard.size_z as i32 - 1,
+        ),
+    );
+    let blocks: Vec<(BlockPos, Block)> = region
+        .iter()
+        .enumerate()
+        .map(|(i, pos)| (pos, Block::from_id(clipboard.data.get_entry(i))))
+        .collect();
+    let count = |f: fn(&Block) -> bool| blocks.iter().filter(|(_, block)| f(block)).count();
+    assert_eq!(count(|block| matches!(block, Block::Lever { .. })), 2);
+    assert_eq!(
+        count(|block| matches!(block, Block::RedstoneLamp { .. })),
+        1
+    );
+    // Every input of every gate is a torch
+    let inputs: usize = circuit.gates.iter().map(Vec::len).sum();
+    assert_eq!(
+        count(|block| matches!(block, Block::RedstoneTorch { .. })),
+        inputs
+    );
+    // Every input and output is labelled
+    assert_eq!(clipboard.block_entities.len(), 3);
+    // The lamp is at the end of the lines
+    let (lamp, _) = blocks
+        .iter()
+        .find(|(_, block)| matches!(block, Block::RedstoneLamp { .. }))
+        .unwrap();
+    assert_eq!(lamp.x, column_x(circuit.gates.len()));
+}
@@ -0,0 +1,193 @@
+//! Reads and writes the contents of ROMs, so that the program memory of a redstone computer can
+//! be reprogrammed from a file. A cell of a ROM is either a container, which holds a value from
+//! 0 to 15 as its signal strength, or a block that holds a bit as a redstone torch on top of it.
+//!
+//! Cells are ordered from the first position of the selection towards the second, along x first,
+//! then z, then y. Files have a hex digit per cell, whitespace and lines starting with `#` are
+//! ignored.
+
+use super::{PlotWorld, WorldEditPattern};
+use crate::world::World;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockPos};
+use std::fmt::Write;
+
+/// The number of cells per line of a ROM file
+const CELLS_PER_LINE: usize = 64;
+
+/// Positions from `first` to `second`, along x first, then z, then y
+fn ordered_region(first: BlockPos, second: BlockPos) -> impl Iterator<Item = BlockPos> {
+    let range = |from: i32, to: i32| -> Box<dyn Iterator<Item = i32>> {
+        match from <= to {
+            true => Box::new(from..=to),
+            false => Box::new((to..=from).rev()),
+        }
+    };
+    range(first.y, second.y).flat_map(move |y| {
+        range(first.z, second.z)
+            .flat_map(move |z| range(first.x, second.x).map(move |x| BlockPos::new(x, y, z)))
+    })
+}
+
+fn is_container(block: Block) -> bool {
+    matches!(
+        block,
+        Block::Barrel {} | Block::Furnace {} | Block::Hopper { .. }
+    )
+}
+
+/// The cells of a ROM in a selection
+pub struct RomCells {
+    positions: Vec<BlockPos>,
+    /// Whether the cells are blocks with a torch bit instead of containers
+    torches: bool,
+}
+
+impl RomCells {
+    /// Finds the containers in the selection, or the blocks matching `torch_mask` if it is set
+    pub fn find(
+        world: &PlotWorld,
+        first: BlockPos,
+        second: BlockPos,
+        torch_mask: Option<&WorldEditPattern>,
+    ) -> RomCells {
+        let positions = ordered_region(first, second)
+            .filter(|&pos| {
+                let block = world.get_block(pos);
+                match torch_mask {
+                    Some(mask) => mask.matches(block),
+                    None => is_container(block),
+                }
+            })
+            .collect();
+        RomCells {
+            positions,
+            torches: torch_mask.is_some(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    fn max_value(&self) -> u8 {
+        match self.torches {
+            true => 1,
+            false => 15,
+        }
+    }
+
+    pub fn read(&self, world: &PlotWorld) -> Vec<u8> {
+        self.positions
+            .iter()
+            .map(|&pos| match self.torches {
+                true => matches!(
+                    world.get_block(pos.offset(BlockFace::Top)),
+                    Block::RedstoneTorch { .. }
+                ) as u8,
+                false => match world.get_block_entity(pos) {
+                    Some(BlockEntity::Container {
+                        comparator_override,
+                        ..
+                    }) => *comparator_override,
+                    _ => 0,
+                },
+            })
+            .collect()
+    }
+
+    /// Writes `values` to the cells, as far as there are both. Returns the positions of the
+    /// blocks that changed so they can be updated.
+    pub fn write(&self, world: &mut PlotWorld, values: &[u8]) -> Result<Vec<BlockPos>, String> {
+        if let Some(value) = values.iter().find(|&&value| value > self.max_value()) {
+            return Err(format!(
+                "{:X} doesn't fit in a cell, which holds at most {:X}",
+                value,
+                self.max_value()
+            ));
+        }
+        let current = self.read(world);
+        let mut changed = Vec::new();
+        for ((&pos, &value), &old) in self.positions.iter().zip(values).zip(&current) {
+            if value == old {
+                continue;
+            }
+            if self.torches {
+                let top = pos.offset(BlockFace::Top);
+                let block = match value {
+                    1 => Block::RedstoneTorch { lit: true },
+                    _ => Block::Air {},
+                };
+                // Blocks other than torches on top of a cell are left alone
+                if !matches!(
+                    world.get_block(top),
+                    Block::Air {} | Block::RedstoneTorch { .. }
+                ) {
+                    continue;
+                }
+                world.set_block(top, block);
+                changed.push(top);
+            } else {
+                let Some(BlockEntity::Container { ty, .. }) = world.get_block_entity(pos) else {
+                    continue;
+                };
+                let entity = BlockEntity::container_with_ss(*ty, value);
+                world.set_block_entity(pos, entity);
+                changed.push(pos);
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Formats values as a ROM file
+pub fn format_rom(values: &[u8]) -> String {
+    let mut out = String::new();
+    for line in values.chunks(CELLS_PER_LINE) {
+        for value in line {
+            let _ = write!(out, "{:X}", value);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn parse_rom(text: &str) -> Result<Vec<u8>, String> {
+    let mut values = Vec::new();
+    for line in text.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for c in line.chars().filter(|c| !c.is_whitespace()) {
+            let value = c
+                .to_digit(16)
+                .ok_or_else(|| format!("{} is not a hex digit", c))?;
+            values.push(value as u8);
+        }
+    }
+    Ok(values)
+}
+
+#[test]
+fn rom_files_have_a_hex_digit_per_cell() {
+    let values: Vec<u8> = (0..70).map(|i| i % 16).collect();
+    let text = format_rom(&values);
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.starts_with("0123456789ABCDEF0123"));
+    assert_eq!(parse_rom(&text).unwrap(), values);
+    assert_eq!(parse_rom("# program\n1f 0\n").unwrap(), [1, 15, 0]);
+    assert!(parse_rom("12g").is_err());
+
+    // Cells are ordered from the first position, which doesn't have to be the minimum
+    let order: Vec<BlockPos> =
+        ordered_region(BlockPos::new(1, 0, 0), BlockPos::new(0, 1, 1)).collect();
+    assert_eq!(order[0], BlockPos::new(1, 0, 0));
+    assert_eq!(order[1], BlockPos::new(0, 0, 0));
+    assert_eq!(order[2], BlockPos::new(1, 0, 1));
+    assert_eq!(order[4], BlockPos::new(1, 1, 0));
+}
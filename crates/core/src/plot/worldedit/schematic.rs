@@ -1,6 +1,7 @@
 //! This implements Sponge Schematic Specification ver. 2
 //! https://github.com/SpongePowered/Schematic-Specification/blob/master/versions/schematic-2.md
 
+use super::litematic::load_litematic_file;
 use super::WorldEditClipboard;
 use crate::server::MC_DATA_VERSION;
 use crate::world::storage::PalettedBitBuffer;
@@ -9,12 +10,14 @@ use itertools::Itertools;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::BlockPos;
+use mchprs_utils::map;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rustc_hash::FxHashMap;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 macro_rules! nbt_as {
     // I'm not sure if path is the right type here.
@@ -27,7 +30,7 @@ macro_rules! nbt_as {
     };
 }
 
-fn parse_block(str: &str) -> Option<Block> {
+pub(super) fn parse_block(str: &str) -> Option<Block> {
     static RE: Lazy<Regex> =
         Lazy::new(|| Regex::new(r"(?:minecraft:)?([a-z_]+)(?:\[([a-z=,0-9]+)\])?").unwrap());
     let captures = RE.captures(str)?;
@@ -44,61 +47,140 @@ fn parse_block(str: &str) -> Option<Block> {
 }
 
 pub fn load_schematic(file_name: &str) -> Result<WorldEditClipboard> {
+    let path = Path::new("./schems/").join(file_name);
+    if file_name.ends_with(".litematic") {
+        return load_litematic_file(path);
+    }
+    load_schematic_file(path)
+}
+
+/// Load a schematic in either version 2 or version 3 of the Sponge format
+pub fn load_schematic_file(path: impl AsRef<Path>) -> Result<WorldEditClipboard> {
     use nbt::Value;
 
-    let mut file = File::open("./schems/".to_owned() + file_name)?;
+    let mut file = File::open(path)?;
     let nbt = nbt::Blob::from_gzip_reader(&mut file)?;
-    let size_x = nbt_as!(nbt["Width"], Value::Short) as u32;
-    let size_z = nbt_as!(nbt["Length"], Value::Short) as u32;
-    let size_y = nbt_as!(nbt["Height"], Value::Short) as u32;
-    let nbt_palette = nbt_as!(&nbt["Palette"], Value::Compound);
+    // Version 3 wraps everything inside of a `Schematic` compound
+    if let Some(Value::Compound(schematic)) = nbt.get("Schematic") {
+        return load_schematic_v3(schematic);
+    }
+
+    let size_x = nbt_as!(nbt["Width"], Value::Short) as u16 as u32;
+    let size_z = nbt_as!(nbt["Length"], Value::Short) as u16 as u32;
+    let size_y = nbt_as!(nbt["Height"], Value::Short) as u16 as u32;
     let metadata = nbt_as!(&nbt["Metadata"], Value::Compound);
-    let offset_x = -nbt_as!(metadata["WEOffsetX"], Value::Int);
-    let offset_y = -nbt_as!(metadata["WEOffsetY"], Value::Int);
-    let offset_z = -nbt_as!(metadata["WEOffsetZ"], Value::Int);
+    let offset = [
+        -nbt_as!(metadata["WEOffsetX"], Value::Int),
+        -nbt_as!(metadata["WEOffsetY"], Value::Int),
+        -nbt_as!(metadata["WEOffsetZ"], Value::Int),
+    ];
+    let mut parsed_block_entities = FxHashMap::default();
+    if let Some(block_entities) = nbt.get("BlockEntities") {
+        for block_entity in nbt_as!(block_entities, Value::List) {
+            let val = nbt_as!(block_entity, Value::Compound);
+            let pos = parse_block_entity_pos(val)?;
+            if let Some(parsed) = BlockEntity::from_nbt(val) {
+                parsed_block_entities.insert(pos, parsed);
+            }
+        }
+    }
+    read_block_data(
+        [size_x, size_y, size_z],
+        offset,
+        nbt_as!(&nbt["Palette"], Value::Compound),
+        nbt_as!(&nbt["BlockData"], Value::ByteArray),
+        parsed_block_entities,
+    )
+}
+
+fn load_schematic_v3(nbt: &HashMap<String, nbt::Value>) -> Result<WorldEditClipboard> {
+    use nbt::Value;
+
+    let size_x = nbt_as!(nbt["Width"], Value::Short) as u16 as u32;
+    let size_z = nbt_as!(nbt["Length"], Value::Short) as u16 as u32;
+    let size_y = nbt_as!(nbt["Height"], Value::Short) as u16 as u32;
+    let offset = match nbt.get("Offset") {
+        Some(offset) => {
+            let offset = nbt_as!(offset, Value::IntArray);
+            [-offset[0], -offset[1], -offset[2]]
+        }
+        None => [0, 0, 0],
+    };
+    let blocks = nbt_as!(&nbt["Blocks"], Value::Compound);
+    let mut parsed_block_entities = FxHashMap::default();
+    if let Some(block_entities) = blocks.get("BlockEntities") {
+        for block_entity in nbt_as!(block_entities, Value::List) {
+            let val = nbt_as!(block_entity, Value::Compound);
+            let pos = parse_block_entity_pos(val)?;
+            // Version 3 moves the block entity data into a `Data` compound
+            let mut flattened = match val.get("Data") {
+                Some(data) => nbt_as!(data, Value::Compound).clone(),
+                None => HashMap::new(),
+            };
+            if let Some(id) = val.get("Id") {
+                flattened.insert("Id".to_owned(), id.clone());
+            }
+            if let Some(parsed) = BlockEntity::from_nbt(&flattened) {
+                parsed_block_entities.insert(pos, parsed);
+            }
+        }
+    }
+    read_block_data(
+        [size_x, size_y, size_z],
+        offset,
+        nbt_as!(&blocks["Palette"], Value::Compound),
+        nbt_as!(&blocks["Data"], Value::ByteArray),
+        parsed_block_entities,
+    )
+}
+
+fn parse_block_entity_pos(nbt: &HashMap<String, nbt::Value>) -> Result<BlockPos> {
+    use nbt::Value;
+
+    let pos_array = nbt_as!(&nbt["Pos"], Value::IntArray);
+    Ok(BlockPos {
+        x: pos_array[0],
+        y: pos_array[1],
+        z: pos_array[2],
+    })
+}
+
+fn read_block_data(
+    [size_x, size_y, size_z]: [u32; 3],
+    [offset_x, offset_y, offset_z]: [i32; 3],
+    nbt_palette: &HashMap<String, nbt::Value>,
+    block_data: &[i8],
+    block_entities: FxHashMap<BlockPos, BlockEntity>,
+) -> Result<WorldEditClipboard> {
+    use nbt::Value;
+
     let mut palette: FxHashMap<u32, u32> = FxHashMap::default();
     for (k, v) in nbt_palette {
         let id = *nbt_as!(v, Value::Int) as u32;
         let block = parse_block(k).with_context(|| format!("error parsing block: {}", k))?;
         palette.insert(id, block.get_id());
     }
-    let blocks: Vec<u8> = nbt_as!(&nbt["BlockData"], Value::ByteArray)
-        .iter()
-        .map(|b| *b as u8)
-        .collect();
+    let blocks: Vec<u8> = block_data.iter().map(|b| *b as u8).collect();
     let mut data = PalettedBitBuffer::new((size_x * size_y * size_z) as usize, 9);
     let mut i = 0;
-    for y_offset in (0..size_y).map(|y| y * size_z * size_x) {
-        for z_offset in (0..size_z).map(|z| z * size_x) {
-            for x in 0..size_x {
-                let mut blockstate_id = 0;
-                // Max varint length is 5
-                for varint_len in 0..=5 {
-                    blockstate_id |= ((blocks[i] & 127) as u32) << (varint_len * 7);
-                    if (blocks[i] & 128) != 128 {
-                        i += 1;
-                        break;
-                    }
-                    i += 1;
-                }
-                let entry = *palette.get(&blockstate_id).unwrap();
-                data.set_entry((y_offset + z_offset + x) as usize, entry);
+    // Blocks are stored by y, then z, then x, which is the order of the clipboard data
+    for index in 0..data.entries() {
+        let mut blockstate_id = 0;
+        // Max varint length is 5
+        for varint_len in 0..=5 {
+            let Some(&byte) = blocks.get(i) else {
+                bail!("block data is shorter than the schematic size");
+            };
+            blockstate_id |= ((byte & 127) as u32) << (varint_len * 7);
+            i += 1;
+            if (byte & 128) != 128 {
+                break;
             }
         }
-    }
-    let block_entities = nbt_as!(&nbt["BlockEntities"], Value::List);
-    let mut parsed_block_entities = FxHashMap::default();
-    for block_entity in block_entities {
-        let val = nbt_as!(block_entity, Value::Compound);
-        let pos_array = nbt_as!(&val["Pos"], Value::IntArray);
-        let pos = BlockPos {
-            x: pos_array[0],
-            y: pos_array[1],
-            z: pos_array[2],
-        };
-        if let Some(parsed) = BlockEntity::from_nbt(val) {
-            parsed_block_entities.insert(pos, parsed);
-        }
+        let entry = *palette
+            .get(&blockstate_id)
+            .with_context(|| format!("block id {} is not in the palette", blockstate_id))?;
+        data.set_entry(index, entry);
     }
     Ok(WorldEditClipboard {
         size_x,
@@ -108,7 +190,7 @@ pub fn load_schematic(file_name: &str) -> Result<WorldEditClipboard> {
         offset_y,
         offset_z,
         data,
-        block_entities: parsed_block_entities,
+        block_entities,
     })
 }
 
@@ -139,12 +221,58 @@ struct Schematic {
     data_version: i32,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BlockContainerV3 {
+    palette: nbt::Blob,
+    #[serde(serialize_with = "nbt::i8_array")]
+    data: Vec<i8>,
+    block_entities: Vec<nbt::Blob>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SchematicV3 {
+    version: i32,
+    data_version: i32,
+    width: i16,
+    height: i16,
+    length: i16,
+    #[serde(serialize_with = "nbt::i32_array")]
+    offset: Vec<i32>,
+    blocks: BlockContainerV3,
+}
+
+/// Used as the root compound of version 3 schematics, which is wrapped in another compound
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SchematicV3Root {
+    schematic: SchematicV3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchematicVersion {
+    V2,
+    V3,
+}
+
 pub fn save_schematic(file_name: &str, clipboard: &WorldEditClipboard) -> Result<()> {
     let mut path = PathBuf::from("./schems");
     path.push(file_name);
-    fs::create_dir_all(path.parent().unwrap())?;
+    save_schematic_file(path, clipboard, SchematicVersion::V2)
+}
+
+pub fn save_schematic_file(
+    path: impl AsRef<Path>,
+    clipboard: &WorldEditClipboard,
+    version: SchematicVersion,
+) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
 
-    let mut file = File::create("./schems/".to_owned() + file_name)?;
+    let mut file = File::create(path)?;
     let size_x = clipboard.size_x;
     let size_y = clipboard.size_y;
     let size_z = clipboard.size_z;
@@ -155,42 +283,38 @@ pub fn save_schematic(file_name: &str, clipboard: &WorldEditClipboard) -> Result
 
     let mut data = Vec::new();
     let mut pallette = Vec::new();
-    for y_offset in (0..size_y).map(|y| y * size_z * size_x) {
-        for z_offset in (0..size_z).map(|z| z * size_x) {
-            for x in 0..size_x {
-                let entry = blocks.get_entry((y_offset + z_offset + x) as usize);
-                let block = Block::from_id(entry);
-
-                let name = format!("minecraft:{}", block.get_name());
-                let props = block.properties();
-                let full_name = if !props.is_empty() {
-                    let props_strs: Vec<String> = props
-                        .iter()
-                        .map(|(name, val)| format!("{}={}", name, val))
-                        .collect();
-                    format!("{}[{}]", name, props_strs.join(","))
-                } else {
-                    name
-                };
-                let mut idx = if let Some(idx) = pallette.iter().position(|s| *s == full_name) {
-                    idx
-                } else {
-                    let idx = pallette.len();
-                    pallette.push(full_name);
-                    idx
-                };
+    for index in 0..blocks.entries() {
+        let entry = blocks.get_entry(index);
+        let block = Block::from_id(entry);
 
-                loop {
-                    let mut temp = (idx & 0b1111_1111) as u8;
-                    idx >>= 7;
-                    if idx != 0 {
-                        temp |= 0b1000_0000;
-                    }
-                    data.push(temp as i8);
-                    if idx == 0 {
-                        break;
-                    }
-                }
+        let name = format!("minecraft:{}", block.get_name());
+        let props = block.properties();
+        let full_name = if !props.is_empty() {
+            let props_strs: Vec<String> = props
+                .iter()
+                .map(|(name, val)| format!("{}={}", name, val))
+                .collect();
+            format!("{}[{}]", name, props_strs.join(","))
+        } else {
+            name
+        };
+        let mut idx = if let Some(idx) = pallette.iter().position(|s| *s == full_name) {
+            idx
+        } else {
+            let idx = pallette.len();
+            pallette.push(full_name);
+            idx
+        };
+
+        loop {
+            let mut temp = (idx & 0b0111_1111) as u8;
+            idx >>= 7;
+            if idx != 0 {
+                temp |= 0b1000_0000;
+            }
+            data.push(temp as i8);
+            if idx == 0 {
+                break;
             }
         }
     }
@@ -208,23 +332,162 @@ pub fn save_schematic(file_name: &str, clipboard: &WorldEditClipboard) -> Result
         }
     }
 
-    let metadata = Metadata {
-        offset_x,
-        offset_y,
-        offset_z,
-    };
-    let schematic = Schematic {
-        width: size_x as i16,
-        length: size_z as i16,
-        height: size_y as i16,
-        block_data: data,
-        block_entities,
-        palette: encoded_pallete,
-        metadata,
-        version: 2,
-        data_version: MC_DATA_VERSION,
-    };
-    nbt::to_gzip_writer(&mut file, &schematic, Some("Schematic"))?;
+    match version {
+        SchematicVersion::V2 => {
+            let metadata = Metadata {
+                offset_x,
+                offset_y,
+                offset_z,
+            };
+            let schematic = Schematic {
+                width: size_x as i16,
+                length: size_z as i16,
+                height: size_y as i16,
+                block_data: data,
+                block_entities,
+                palette: encoded_pallete,
+                metadata,
+                version: 2,
+                data_version: MC_DATA_VERSION,
+            };
+            nbt::to_gzip_writer(&mut file, &schematic, Some("Schematic"))?;
+        }
+        SchematicVersion::V3 => {
+            // Version 3 stores the block entity data in a separate `Data` compound
+            let block_entities = clipboard
+                .block_entities
+                .iter()
+                .map(|(pos, block_entity)| {
+                    let mut data = block_entity.nbt_content();
+                    let id = data
+                        .remove("id")
+                        .unwrap_or(nbt::Value::String(String::new()));
+                    nbt::Blob::with_content(map! {
+                        "Pos" => nbt::Value::IntArray(vec![pos.x, pos.y, pos.z]),
+                        "Id" => id,
+                        "Data" => nbt::Value::Compound(data)
+                    })
+                })
+                .collect();
+            let schematic = SchematicV3Root {
+                schematic: SchematicV3 {
+                    version: 3,
+                    data_version: MC_DATA_VERSION,
+                    width: size_x as i16,
+                    height: size_y as i16,
+                    length: size_z as i16,
+                    offset: vec![offset_x, offset_y, offset_z],
+                    blocks: BlockContainerV3 {
+                        palette: encoded_pallete,
+                        data,
+                        block_entities,
+                    },
+                },
+            };
+            nbt::to_gzip_writer(&mut file, &schematic, None)?;
+        }
+    }
 
     Ok(())
 }
+
+#[test]
+fn schematics_keep_large_palettes_and_block_entities() {
+    use super::{create_clipboard, paste_clipboard};
+    use crate::plot::redpiler_test_world;
+    use crate::world::World;
+    use mchprs_blocks::aabb::Aabb;
+    use mchprs_blocks::block_entities::{ContainerType, InventoryEntry, SignBlockEntity};
+    use mchprs_blocks::blocks::{RedstoneWire, RedstoneWireSide, SignRotation};
+    use mchprs_blocks::items::Item;
+    use mchprs_blocks::SignType;
+
+    // Enough wire states that palette indices take more than one byte
+    let mut world = redpiler_test_world();
+    let sides = [
+        RedstoneWireSide::None,
+        RedstoneWireSide::Side,
+        RedstoneWireSide::Up,
+    ];
+    let mut x = 0;
+    for north in sides {
+        for east in sides {
+            for power in 0..16 {
+                let wire = RedstoneWire {
+                    north,
+                    south: RedstoneWireSide::None,
+                    east,
+                    west: RedstoneWireSide::None,
+                    power,
+                };
+                world.set_block(
+                    BlockPos::new(x % 16, 10, x / 16),
+                    Block::RedstoneWire { wire },
+                );
+                x += 1;
+            }
+        }
+    }
+    let sign_pos = BlockPos::new(0, 11, 0);
+    let sign = Block::Sign {
+        sign_type: SignType(0),
+        rotation: SignRotation(0),
+    };
+    world.set_block(sign_pos, sign);
+    let rows = [
+        "a".to_owned(),
+        "b".to_owned(),
+        "c".to_owned(),
+        "d".to_owned(),
+    ];
+    world.set_block_entity(
+        sign_pos,
+        BlockEntity::Sign(Box::new(SignBlockEntity { rows: rows.clone() })),
+    );
+    let barrel_pos = BlockPos::new(1, 11, 0);
+    world.set_block(barrel_pos, Block::Barrel {});
+    let inventory = vec![InventoryEntry {
+        id: Item::Redstone {}.get_id(),
+        slot: 0,
+        count: 64,
+        nbt: None,
+    }];
+    world.set_block_entity(
+        barrel_pos,
+        BlockEntity::Container {
+            comparator_override: 1,
+            inventory,
+            ty: ContainerType::Barrel,
+        },
+    );
+
+    let (first, second) = (BlockPos::new(0, 10, 0), BlockPos::new(15, 11, 15));
+    let clipboard = create_clipboard(&mut world, first, first, second);
+    for version in [SchematicVersion::V2, SchematicVersion::V3] {
+        let path = std::env::temp_dir().join(format!(
+            "mchprs_schematic_test_{}_{:?}.schem",
+            std::process::id(),
+            version
+        ));
+        save_schematic_file(&path, &clipboard, version).unwrap();
+        let loaded = load_schematic_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut pasted = redpiler_test_world();
+        paste_clipboard(&mut pasted, &loaded, first, false);
+        for pos in Aabb::new(first, second) {
+            assert_eq!(pasted.get_block_raw(pos), world.get_block_raw(pos));
+        }
+        let Some(BlockEntity::Sign(sign)) = pasted.get_block_entity(sign_pos) else {
+            panic!("the sign was not loaded");
+        };
+        assert_eq!(sign.rows, rows);
+        assert!(matches!(
+            pasted.get_block_entity(barrel_pos),
+            Some(BlockEntity::Container {
+                comparator_override: 1,
+                ..
+            })
+        ));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::utils::HyphenatedUUID;
 use anyhow::Result;
+use reqwest::{StatusCode, Url};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
@@ -25,4 +26,20 @@ impl PlayerProfile {
             .await?;
         Ok(res)
     }
+
+    /// Asks the session server whether `username` joined a server with `server_hash`, which is
+    /// only the case if the player is logged in to their account. Returns the profile of the
+    /// player if they did.
+    pub async fn has_joined(username: &str, server_hash: &str) -> Result<Option<PlayerProfile>> {
+        // The username comes from the client, so it is encoded instead of inserted as is
+        let url = Url::parse_with_params(
+            "https://sessionserver.mojang.com/session/minecraft/hasJoined",
+            &[("username", username), ("serverId", server_hash)],
+        )?;
+        let res = reqwest::get(url).await?;
+        if res.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        Ok(Some(res.error_for_status()?.json::<PlayerProfile>().await?))
+    }
 }
@@ -1,8 +1,10 @@
 //! The direct backend does not do code generation and operates on the `CompileNode` graph directly
 
-use super::JITBackend;
-use crate::redpiler::compile_graph::{CompileGraph, LinkType, NodeIdx};
+use super::{BackendCapabilities, BackendSnapshot, JITBackend, NodeState, ScheduledTick};
+use crate::redpiler::compile_graph::{CompileGraph, CompileLink, LinkType, NodeIdx};
+use crate::redpiler::incremental::GraphPatch;
 use crate::redpiler::{block_powered_mut, bool_to_ss};
+use crate::redstone;
 use crate::world::World;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::{Block, ComparatorMode};
@@ -13,7 +15,9 @@ use petgraph::visit::EdgeRef;
 use petgraph::Direction;
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
-use std::{fmt, mem};
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::mem;
 use tracing::{debug, trace, warn};
 
 #[derive(Debug, Default)]
@@ -73,6 +77,17 @@ mod nodes {
         pub fn into_inner(self) -> Box<[Node]> {
             self.nodes
         }
+
+        /// Adds `count` copies of `node`, returning the ids of the new nodes
+        pub fn extend(&mut self, node: Node, count: usize) -> Vec<NodeId> {
+            let start = self.nodes.len();
+            let mut nodes = std::mem::take(&mut self.nodes).into_vec();
+            nodes.resize(start + count, node);
+            self.nodes = nodes.into_boxed_slice();
+            (start..start + count)
+                .map(|idx| NodeId(idx as u32))
+                .collect()
+        }
     }
 
     impl Index<NodeId> for Nodes {
@@ -136,6 +151,10 @@ enum NodeType {
     Trapdoor,
     Wire,
     Constant,
+    Piston,
+    NoteBlock,
+    Observer,
+    Target,
 }
 
 impl NodeType {
@@ -147,6 +166,9 @@ impl NodeType {
                 | NodeType::Lever
                 | NodeType::Trapdoor
                 | NodeType::PressurePlate
+                | NodeType::Piston
+                | NodeType::NoteBlock
+                | NodeType::Target
         )
     }
 }
@@ -189,84 +211,24 @@ impl Node {
     ) -> Self {
         let node = &graph[node_idx];
 
-        const MAX_INPUTS: usize = 255;
-
-        let mut default_input_count = 0;
-        let mut side_input_count = 0;
-
-        let mut default_inputs = NodeInput { ss_counts: [0; 16] };
-        let mut side_inputs = NodeInput { ss_counts: [0; 16] };
-        for edge in graph.edges_directed(node_idx, Direction::Incoming) {
-            let weight = edge.weight();
-            let distance = weight.ss;
-            let source = edge.source();
-            let ss = graph[source].state.output_strength.saturating_sub(distance);
-            match weight.ty {
-                LinkType::Default => {
-                    if default_input_count >= MAX_INPUTS {
-                        panic!(
-                            "Exceeded the maximum number of default inputs {}",
-                            MAX_INPUTS
-                        );
-                    }
-                    default_input_count += 1;
-                    default_inputs.ss_counts[ss as usize] += 1;
-                }
-                LinkType::Side => {
-                    if side_input_count >= MAX_INPUTS {
-                        panic!("Exceeded the maximum number of side inputs {}", MAX_INPUTS);
-                    }
-                    side_input_count += 1;
-                    side_inputs.ss_counts[ss as usize] += 1;
-                }
-            }
-        }
-        stats.default_link_count += default_input_count;
-        stats.side_link_count += side_input_count;
-
-        use crate::redpiler::compile_graph::NodeType as CNodeType;
-        let updates = if node.ty != CNodeType::Constant {
-            graph
-                .edges_directed(node_idx, Direction::Outgoing)
-                .map(|edge| unsafe {
-                    let idx = edge.target();
-                    let idx = nodes_map[&idx];
-                    assert!(idx < nodes_len);
-                    // Safety: bounds checked
-                    let target_id = NodeId::from_index(idx);
-
-                    let weight = edge.weight();
-                    ForwardLink::new(target_id, weight.ty == LinkType::Side, weight.ss)
-                })
-                .collect()
-        } else {
-            SmallVec::new()
-        };
+        let inputs = compile_inputs(graph, node_idx, |source| {
+            graph[source].state.output_strength
+        });
+        stats.default_link_count += inputs.default_count;
+        stats.side_link_count += inputs.side_count;
+
+        let updates = compile_updates(graph, node_idx, |idx| unsafe {
+            let idx = nodes_map[&idx];
+            assert!(idx < nodes_len);
+            // Safety: bounds checked
+            NodeId::from_index(idx)
+        });
         stats.update_link_count += updates.len();
 
-        let ty = match node.ty {
-            CNodeType::Repeater(delay) => {
-                if side_input_count == 0 {
-                    NodeType::SimpleRepeater(delay)
-                } else {
-                    NodeType::Repeater(delay)
-                }
-            }
-            CNodeType::Torch => NodeType::Torch,
-            CNodeType::Comparator(mode) => NodeType::Comparator(mode),
-            CNodeType::Lamp => NodeType::Lamp,
-            CNodeType::Button => NodeType::Button,
-            CNodeType::Lever => NodeType::Lever,
-            CNodeType::PressurePlate => NodeType::PressurePlate,
-            CNodeType::Trapdoor => NodeType::Trapdoor,
-            CNodeType::Wire => NodeType::Wire,
-            CNodeType::Constant => NodeType::Constant,
-        };
-
         Node {
-            ty,
-            default_inputs,
-            side_inputs,
+            ty: compile_node_type(graph, node_idx, inputs.side_count),
+            default_inputs: inputs.default_inputs,
+            side_inputs: inputs.side_inputs,
             updates,
             powered: node.state.powered,
             output_power: node.state.output_strength,
@@ -277,6 +239,131 @@ impl Node {
             changed: false,
         }
     }
+
+    /// The slot of a node that was removed from the circuit, which can be reused by a node that
+    /// is added later
+    fn removed() -> Node {
+        Node {
+            ty: NodeType::Constant,
+            default_inputs: NodeInput::default(),
+            side_inputs: NodeInput::default(),
+            updates: SmallVec::new(),
+            powered: false,
+            output_power: 0,
+            locked: false,
+            facing_diode: false,
+            comparator_far_input: None,
+            pending_tick: false,
+            changed: false,
+        }
+    }
+}
+
+struct CompiledInputs {
+    default_inputs: NodeInput,
+    side_inputs: NodeInput,
+    default_count: usize,
+    side_count: usize,
+}
+
+/// Links with a distance of 15 or more never carry a signal. The clamp-weights pass removes
+/// them from the graph, but the graph that is patched into a running backend never ran it.
+fn carries_signal(link: &CompileLink) -> bool {
+    link.ss < 15
+}
+
+/// Counts the inputs of a node, where `source_power` is the output power of each source
+fn compile_inputs(
+    graph: &CompileGraph,
+    node_idx: NodeIdx,
+    source_power: impl Fn(NodeIdx) -> u8,
+) -> CompiledInputs {
+    const MAX_INPUTS: usize = 255;
+
+    let mut inputs = CompiledInputs {
+        default_inputs: NodeInput { ss_counts: [0; 16] },
+        side_inputs: NodeInput { ss_counts: [0; 16] },
+        default_count: 0,
+        side_count: 0,
+    };
+    for edge in graph.edges_directed(node_idx, Direction::Incoming) {
+        let weight = edge.weight();
+        if !carries_signal(weight) {
+            continue;
+        }
+        let distance = weight.ss;
+        let ss = source_power(edge.source()).saturating_sub(distance);
+        match weight.ty {
+            LinkType::Default => {
+                if inputs.default_count >= MAX_INPUTS {
+                    panic!(
+                        "Exceeded the maximum number of default inputs {}",
+                        MAX_INPUTS
+                    );
+                }
+                inputs.default_count += 1;
+                inputs.default_inputs.ss_counts[ss as usize] += 1;
+            }
+            LinkType::Side => {
+                if inputs.side_count >= MAX_INPUTS {
+                    panic!("Exceeded the maximum number of side inputs {}", MAX_INPUTS);
+                }
+                inputs.side_count += 1;
+                inputs.side_inputs.ss_counts[ss as usize] += 1;
+            }
+        }
+    }
+    inputs
+}
+
+/// The links to the nodes a node updates, where `node_id` is the id of each target
+fn compile_updates(
+    graph: &CompileGraph,
+    node_idx: NodeIdx,
+    node_id: impl Fn(NodeIdx) -> NodeId,
+) -> SmallVec<[ForwardLink; 18]> {
+    use crate::redpiler::compile_graph::NodeType as CNodeType;
+    if graph[node_idx].ty == CNodeType::Constant {
+        return SmallVec::new();
+    }
+    graph
+        .edges_directed(node_idx, Direction::Outgoing)
+        .filter(|edge| carries_signal(edge.weight()))
+        .map(|edge| {
+            let weight = edge.weight();
+            ForwardLink::new(
+                node_id(edge.target()),
+                weight.ty == LinkType::Side,
+                weight.ss,
+            )
+        })
+        .collect()
+}
+
+fn compile_node_type(graph: &CompileGraph, node_idx: NodeIdx, side_input_count: usize) -> NodeType {
+    use crate::redpiler::compile_graph::NodeType as CNodeType;
+    match graph[node_idx].ty {
+        CNodeType::Repeater(delay) => {
+            if side_input_count == 0 {
+                NodeType::SimpleRepeater(delay)
+            } else {
+                NodeType::Repeater(delay)
+            }
+        }
+        CNodeType::Torch => NodeType::Torch,
+        CNodeType::Comparator(mode) => NodeType::Comparator(mode),
+        CNodeType::Lamp => NodeType::Lamp,
+        CNodeType::Button => NodeType::Button,
+        CNodeType::Lever => NodeType::Lever,
+        CNodeType::PressurePlate => NodeType::PressurePlate,
+        CNodeType::Trapdoor => NodeType::Trapdoor,
+        CNodeType::Wire => NodeType::Wire,
+        CNodeType::Constant => NodeType::Constant,
+        CNodeType::Piston => NodeType::Piston,
+        CNodeType::NoteBlock => NodeType::NoteBlock,
+        CNodeType::Observer => NodeType::Observer,
+        CNodeType::Target => NodeType::Target,
+    }
 }
 
 #[derive(Default, Clone)]
@@ -317,6 +404,32 @@ impl TickScheduler {
                 }
             }
         }
+        self.clear();
+    }
+
+    /// Every scheduled node with the number of ticks until its tick
+    fn scheduled(&self) -> impl Iterator<Item = (NodeId, usize)> + '_ {
+        (1..=Self::NUM_QUEUES).flat_map(move |delay| {
+            let queues = &self.queues_deque[(self.pos + delay) % Self::NUM_QUEUES];
+            queues.0.iter().flatten().map(move |&node| (node, delay))
+        })
+    }
+
+    /// Every scheduled node with the number of ticks until its tick and its priority
+    fn entries(&self) -> impl Iterator<Item = (NodeId, usize, TickPriority)> + '_ {
+        (1..=Self::NUM_QUEUES).flat_map(move |delay| {
+            let queues = &self.queues_deque[(self.pos + delay) % Self::NUM_QUEUES];
+            queues
+                .0
+                .iter()
+                .zip(Self::priorities())
+                .flat_map(move |(queue, priority)| {
+                    queue.iter().map(move |&node| (node, delay, priority))
+                })
+        })
+    }
+
+    fn clear(&mut self) {
         for queues in self.queues_deque.iter_mut() {
             for queue in queues.0.iter_mut() {
                 queue.clear();
@@ -360,12 +473,23 @@ impl TickScheduler {
     }
 }
 
+/// Things that happened while ticking that are handled when the backend is flushed
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    NoteBlockPlay(NodeId),
+}
+
 #[derive(Default)]
 pub struct DirectBackend {
     nodes: Nodes,
     blocks: Vec<Option<(BlockPos, Block)>>,
     pos_map: FxHashMap<BlockPos, NodeId>,
+    /// The node each node of the compiled graph became, to find the nodes of a patch
+    ids: FxHashMap<NodeIdx, NodeId>,
+    /// The slots of nodes that were removed by a patch
+    free: Vec<NodeId>,
     scheduler: TickScheduler,
+    events: Vec<Event>,
 }
 
 impl DirectBackend {
@@ -373,6 +497,27 @@ impl DirectBackend {
         self.scheduler.schedule_tick(node_id, delay, priority);
     }
 
+    /// Schedules the ticks that were pending in the world when the circuit was compiled
+    fn schedule_world_ticks(&mut self, ticks: &[TickEntry]) {
+        for entry in ticks {
+            if let Some(&node) = self.pos_map.get(&entry.pos) {
+                // A tick with 0 ticks left will still be executed on the next world tick,
+                // so it has to be scheduled on the next tick here as well.
+                let delay = (entry.ticks_left as usize).max(1);
+                if delay >= TickScheduler::NUM_QUEUES {
+                    warn!(
+                        "Pending tick at {} is too far in the future ({} ticks), clamping",
+                        entry.pos, delay
+                    );
+                }
+                let delay = delay.min(TickScheduler::NUM_QUEUES - 1);
+                self.scheduler
+                    .schedule_tick(node, delay, entry.tick_priority);
+                self.nodes[node].pending_tick = true;
+            }
+        }
+    }
+
     fn set_node(&mut self, node_id: NodeId, powered: bool, new_power: u8) {
         let node = &mut self.nodes[node_id];
         let old_power = node.output_power;
@@ -398,6 +543,10 @@ impl DirectBackend {
             let new_power = new_power.saturating_sub(distance);
 
             if old_power == new_power {
+                // Observers see every change of the node, not just changes of its power
+                if matches!(self.nodes[update].ty, NodeType::Observer) {
+                    observe(&mut self.scheduler, &mut self.nodes, update);
+                }
                 continue;
             }
 
@@ -407,12 +556,30 @@ impl DirectBackend {
                 *inputs.ss_counts.get_unchecked_mut(new_power as usize) += 1;
             }
 
-            update_node(&mut self.scheduler, &mut self.nodes, update);
+            update_node(
+                &mut self.scheduler,
+                &mut self.events,
+                &mut self.nodes,
+                update,
+            );
         }
     }
 }
 
 impl JITBackend for DirectBackend {
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            name: "Direct",
+            analog: true,
+            noteblocks: true,
+            pistons: true,
+            observers: true,
+            deterministic: true,
+            // Nodes are indexed with a u32
+            max_nodes: u32::MAX as usize,
+        }
+    }
+
     fn inspect(&mut self, pos: BlockPos) {
         let Some(node_id) = self.pos_map.get(&pos) else {
             debug!("could not find node at pos {}", pos);
@@ -422,28 +589,300 @@ impl JITBackend for DirectBackend {
         debug!("Node {:?}: {:#?}", node_id, self.nodes[*node_id]);
     }
 
+    fn get_signal_strength(&self, pos: BlockPos) -> Option<u8> {
+        let node_id = self.pos_map.get(&pos)?;
+        Some(self.nodes[*node_id].output_power)
+    }
+
+    fn is_powered(&self, pos: BlockPos) -> Option<bool> {
+        let node_id = self.pos_map.get(&pos)?;
+        Some(self.nodes[*node_id].powered)
+    }
+
+    fn scheduled_ticks(&self) -> Vec<(BlockPos, u32)> {
+        self.scheduler
+            .scheduled()
+            .filter_map(|(node, delay)| {
+                let (pos, _) = self.blocks[node.index()]?;
+                Some((pos, delay as u32))
+            })
+            .collect()
+    }
+
+    fn subgraph_dot(&self, roots: &[BlockPos], max_links: Option<usize>, incoming: bool) -> String {
+        let nodes = self.nodes.inner();
+        let mut inputs = vec![Vec::new(); if incoming { nodes.len() } else { 0 }];
+        if incoming {
+            for (id, node) in nodes.iter().enumerate() {
+                for link in &node.updates {
+                    inputs[link.node().index()].push(id);
+                }
+            }
+        }
+
+        // Breadth first, so that every node gets the smallest amount of links to a root
+        let mut distances: Vec<Option<usize>> = vec![None; nodes.len()];
+        let mut queue = VecDeque::new();
+        for pos in roots {
+            if let Some(id) = self.pos_map.get(pos) {
+                if distances[id.index()].is_none() {
+                    distances[id.index()] = Some(0);
+                    queue.push_back(id.index());
+                }
+            }
+        }
+        while let Some(id) = queue.pop_front() {
+            let distance = distances[id].unwrap();
+            if max_links.map_or(false, |max_links| distance >= max_links) {
+                continue;
+            }
+            let outputs = nodes[id].updates.iter().map(|link| link.node().index());
+            let neighbors = outputs.chain(inputs.get(id).into_iter().flatten().copied());
+            for neighbor in neighbors {
+                if distances[neighbor].is_none() {
+                    distances[neighbor] = Some(distance + 1);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        let mut dot = String::from("digraph{");
+        for (id, node) in nodes.iter().enumerate() {
+            let Some(distance) = distances[id] else {
+                continue;
+            };
+            let (label, pos) = self.dot_label(id);
+            let mut state = format!("ss {}", node.output_power);
+            if node.powered {
+                state += ", powered";
+            }
+            if node.locked {
+                state += ", locked";
+            }
+            if node.pending_tick {
+                state += ", pending tick";
+            }
+            let style = if distance == 0 { ",style=bold" } else { "" };
+            let _ = write!(
+                dot,
+                "n{}[label=\"{}\\n({})\\n{}\"{}];",
+                id, label, pos, state, style
+            );
+            for link in node.updates.iter() {
+                let out_index = link.node().index();
+                if distances[out_index].is_none() {
+                    continue;
+                }
+                let color = if link.side() { ",color=\"blue\"" } else { "" };
+                let _ = write!(
+                    dot,
+                    "n{}->n{}[label=\"{}\"{}];",
+                    id,
+                    out_index,
+                    link.ss(),
+                    color
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn snapshot(&self) -> BackendSnapshot {
+        let nodes = self
+            .nodes
+            .inner()
+            .iter()
+            .zip(&self.blocks)
+            .map(|(node, block)| NodeState {
+                pos: block.map(|(pos, _)| pos),
+                powered: node.powered,
+                output_power: node.output_power,
+                locked: node.locked,
+            })
+            .collect();
+        let ticks = self
+            .scheduler
+            .entries()
+            .map(|(node, delay, priority)| ScheduledTick {
+                node: node.index(),
+                delay: delay as u32,
+                priority,
+            })
+            .collect();
+        BackendSnapshot { nodes, ticks }
+    }
+
+    fn restore(&mut self, snapshot: &BackendSnapshot) -> Result<(), String> {
+        let matches = snapshot.nodes.len() == self.blocks.len()
+            && snapshot
+                .nodes
+                .iter()
+                .zip(&self.blocks)
+                .all(|(state, block)| state.pos == block.map(|(pos, _)| pos));
+        if !matches {
+            return Err("the snapshot was taken of a different circuit".to_owned());
+        }
+        let max_delay = TickScheduler::NUM_QUEUES as u32;
+        if let Some(tick) = snapshot.ticks.iter().find(|tick| {
+            tick.node >= self.blocks.len() || tick.delay == 0 || tick.delay > max_delay
+        }) {
+            return Err(format!("the snapshot has an invalid tick: {:?}", tick));
+        }
+
+        let nodes = self.nodes.inner_mut();
+        for i in 0..nodes.len() {
+            let state = &snapshot.nodes[i];
+            let old_power = nodes[i].output_power;
+            let new_power = state.output_power.min(15);
+            let node = &mut nodes[i];
+            node.powered = state.powered;
+            node.output_power = new_power;
+            node.locked = state.locked;
+            node.pending_tick = false;
+            node.changed = true;
+            if old_power == new_power {
+                continue;
+            }
+            // The inputs of the nodes it updates are moved to the new power, like `set_node`
+            // does, but without updating those nodes
+            for j in 0..nodes[i].updates.len() {
+                let link = nodes[i].updates[j];
+                let target = &mut nodes[link.node().index()];
+                let inputs = match link.side() {
+                    true => &mut target.side_inputs,
+                    false => &mut target.default_inputs,
+                };
+                inputs.ss_counts[old_power.saturating_sub(link.ss()) as usize] -= 1;
+                inputs.ss_counts[new_power.saturating_sub(link.ss()) as usize] += 1;
+            }
+        }
+
+        self.scheduler.clear();
+        for tick in &snapshot.ticks {
+            let node = self.nodes.get(tick.node);
+            self.scheduler
+                .schedule_tick(node, tick.delay as usize, tick.priority);
+            self.nodes[node].pending_tick = true;
+        }
+        self.events.clear();
+        Ok(())
+    }
+
     fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
-        self.scheduler.reset(world, &self.blocks);
+        self.suspend(world, io_only);
+        self.nodes = Nodes::default();
+        self.pos_map.clear();
+        self.ids.clear();
+        self.free.clear();
+    }
 
-        let nodes = std::mem::take(&mut self.nodes);
+    fn suspend<W: World>(&mut self, world: &mut W, io_only: bool) {
+        for (node, _) in self.scheduler.scheduled() {
+            self.nodes[node].pending_tick = false;
+        }
+        self.scheduler.reset(world, &self.blocks);
+        self.events.clear();
 
-        for (i, node) in nodes.into_inner().iter().enumerate() {
-            let Some((pos, block)) = self.blocks[i] else {
+        for (i, node) in self.nodes.inner_mut().iter_mut().enumerate() {
+            let Some((pos, block)) = &mut self.blocks[i] else {
                 continue;
             };
             if matches!(node.ty, NodeType::Comparator(_)) {
                 let block_entity = BlockEntity::Comparator {
                     output_strength: node.output_power,
                 };
-                world.set_block_entity(pos, block_entity);
+                world.set_block_entity(*pos, block_entity);
             }
 
-            if io_only && !node.ty.is_io_block() {
-                world.set_block(pos, block);
+            // With `io_only`, blocks that aren't io blocks were never flushed, so their current
+            // state has to be written back here for the world to match the pending ticks.
+            let unflushed = node.changed || (io_only && !node.ty.is_io_block());
+            if unflushed && node.ty != NodeType::Piston {
+                apply_node_state(block, node);
+                world.set_block(*pos, *block);
             }
+            node.changed = false;
         }
+    }
 
-        self.pos_map.clear();
+    fn patch(&mut self, graph: &CompileGraph, patch: &GraphPatch, ticks: &[TickEntry]) -> bool {
+        for idx in &patch.removed {
+            let Some(id) = self.ids.remove(idx) else {
+                continue;
+            };
+            if let Some((pos, _)) = self.blocks[id.index()].take() {
+                self.pos_map.remove(&pos);
+            }
+            self.nodes[id] = Node::removed();
+            self.free.push(id);
+        }
+
+        let added = patch
+            .identified
+            .iter()
+            .filter(|idx| !self.ids.contains_key(idx))
+            .count();
+        if added > self.free.len() {
+            let count = added - self.free.len();
+            self.free.extend(self.nodes.extend(Node::removed(), count));
+            self.blocks.resize(self.nodes.inner().len(), None);
+        }
+
+        // The state of the identified nodes is read from the world, which the state of the
+        // circuit was written to when it was suspended
+        for &idx in &patch.identified {
+            let id = match self.ids.get(&idx) {
+                Some(&id) => id,
+                None => {
+                    let id = self.free.pop().unwrap();
+                    self.ids.insert(idx, id);
+                    id
+                }
+            };
+            let node = &graph[idx];
+            let block = node.block.map(|(pos, id)| (pos, Block::from_id(id)));
+            if let Some((pos, _)) = block {
+                self.pos_map.insert(pos, id);
+            }
+            self.blocks[id.index()] = block;
+            let patched = &mut self.nodes[id];
+            patched.powered = node.state.powered;
+            patched.output_power = node.state.output_strength;
+            patched.locked = node.state.repeater_locked;
+            patched.facing_diode = node.facing_diode;
+            patched.changed = false;
+        }
+
+        let ids = &self.ids;
+        let sources = patch.sources.iter().chain(&patch.identified);
+        for &idx in sources {
+            self.nodes[ids[&idx]].updates = compile_updates(graph, idx, |target| ids[&target]);
+        }
+
+        // The inputs of the nodes the identified nodes update change with their power
+        let mut inputs_changed: Vec<NodeIdx> = patch
+            .identified
+            .iter()
+            .flat_map(|&idx| graph.neighbors_directed(idx, Direction::Outgoing))
+            .chain(patch.identified.iter().copied())
+            .chain(patch.searched.iter().copied())
+            .collect();
+        inputs_changed.sort_unstable();
+        inputs_changed.dedup();
+        for idx in inputs_changed {
+            let nodes = &self.nodes;
+            let inputs = compile_inputs(graph, idx, |source| nodes[ids[&source]].output_power);
+            let node = &mut self.nodes[ids[&idx]];
+            node.ty = compile_node_type(graph, idx, inputs.side_count);
+            node.default_inputs = inputs.default_inputs;
+            node.side_inputs = inputs.side_inputs;
+            node.comparator_far_input = graph[idx].comparator_far_input;
+            derive_lock(node);
+        }
+
+        self.schedule_world_ticks(ticks);
+        true
     }
 
     fn on_use_block(&mut self, pos: BlockPos) {
@@ -475,6 +914,22 @@ impl JITBackend for DirectBackend {
         }
     }
 
+    fn hit_target(&mut self, pos: BlockPos, power: u8, ticks: u32) {
+        let node_id = self.pos_map[&pos];
+        let node = &self.nodes[node_id];
+        match node.ty {
+            NodeType::Target => {
+                if node.powered {
+                    return;
+                }
+                let ticks = (ticks as usize).clamp(1, TickScheduler::NUM_QUEUES - 1);
+                self.schedule_tick(node_id, ticks, TickPriority::Normal);
+                self.set_node(node_id, power > 0, power);
+            }
+            _ => warn!("Tried to hit a {:?} like a target block", node.ty),
+        }
+    }
+
     fn tick(&mut self) {
         let mut queues = self.scheduler.queues_this_tick();
 
@@ -557,6 +1012,26 @@ impl JITBackend for DirectBackend {
                         self.set_node(node_id, false, 0);
                     }
                 }
+                NodeType::Target => {
+                    if node.powered {
+                        self.set_node(node_id, false, 0);
+                    }
+                }
+                NodeType::Piston => {
+                    let should_extend = get_bool_input(node);
+                    if node.powered != should_extend {
+                        self.set_node(node_id, should_extend, bool_to_ss(should_extend));
+                    }
+                }
+                NodeType::Observer => {
+                    if node.powered {
+                        self.set_node(node_id, false, 0);
+                    } else {
+                        self.set_node(node_id, true, 15);
+                        let node = &mut self.nodes[node_id];
+                        schedule_tick(&mut self.scheduler, node_id, node, 1, TickPriority::Normal);
+                    }
+                }
                 _ => warn!("Node {:?} should not be ticked!", node.ty),
             }
         }
@@ -591,14 +1066,17 @@ impl JITBackend for DirectBackend {
                 self.pos_map.insert(pos, self.nodes.get(i));
             }
         }
+        self.ids = nodes_map
+            .into_iter()
+            .map(|(idx, i)| (idx, self.nodes.get(i)))
+            .collect();
+        self.free.clear();
 
-        for entry in ticks {
-            if let Some(node) = self.pos_map.get(&entry.pos) {
-                self.scheduler
-                    .schedule_tick(*node, entry.ticks_left as usize, entry.tick_priority);
-                self.nodes[*node].pending_tick = true;
-            }
+        for node in self.nodes.inner_mut() {
+            derive_lock(node);
         }
+
+        self.schedule_world_ticks(&ticks);
         // Dot file output
         // println!("{}", self);
     }
@@ -608,20 +1086,51 @@ impl JITBackend for DirectBackend {
             let Some((pos, block)) = &mut self.blocks[i] else {
                 continue;
             };
-            if node.changed && (!io_only || node.ty.is_io_block()) {
-                if let Some(powered) = block_powered_mut(block) {
-                    *powered = node.powered
-                }
-                if let Block::RedstoneWire { wire, .. } = block {
-                    wire.power = node.output_power
-                };
-                if let Block::RedstoneRepeater { repeater } = block {
-                    repeater.locked = node.locked;
-                }
+            if node.changed && matches!(node.ty, NodeType::Piston) {
+                // The piston moves its blocks in the world by itself
+                redstone::piston::set_extended(world, *pos, node.powered);
+                *block = world.get_block(*pos);
+            } else if node.changed && (!io_only || node.ty.is_io_block()) {
+                apply_node_state(block, node);
                 world.set_block(*pos, *block);
+            } else if node.changed {
+                // Kept for the next flush that writes every block
+                continue;
             }
             node.changed = false;
         }
+
+        for event in self.events.drain(..) {
+            match event {
+                Event::NoteBlockPlay(node_id) => {
+                    if let Some((
+                        pos,
+                        Block::NoteBlock {
+                            instrument, note, ..
+                        },
+                    )) = self.blocks[node_id.index()]
+                    {
+                        redstone::play_note_block(world, pos, instrument, note);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Updates `block` to match the state of the node that was compiled from it
+fn apply_node_state(block: &mut Block, node: &Node) {
+    if let Some(powered) = block_powered_mut(block) {
+        *powered = node.powered
+    }
+    if let Block::RedstoneWire { wire, .. } = block {
+        wire.power = node.output_power
+    };
+    if let Block::Target { power } = block {
+        *power = node.output_power
+    };
+    if let Block::RedstoneRepeater { repeater } = block {
+        repeater.locked = node.locked;
     }
 }
 
@@ -637,6 +1146,36 @@ fn set_node_locked(node: &mut Node, locked: bool) {
     node.changed = true;
 }
 
+/// The lock state stored in the world can be stale (e.g. after pasting a schematic),
+/// so derive it from the side inputs the same way `update_node` would.
+fn derive_lock(node: &mut Node) {
+    if let NodeType::Repeater(_) = node.ty {
+        let should_be_locked = get_bool_side(node);
+        if node.locked != should_be_locked {
+            set_node_locked(node, should_be_locked);
+        }
+    }
+}
+
+/// Starts a pulse if the node is an observer that isn't pulsing already
+fn observe(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+    let node = &mut nodes[node_id];
+    if !node.powered && !node.pending_tick {
+        schedule_tick(scheduler, node_id, node, 1, TickPriority::Normal);
+    }
+}
+
+/// Lets the observers of a node know that it changed in `update_node`, where the change doesn't
+/// go through the outgoing links
+fn notify_observers(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+    for i in 0..nodes[node_id].updates.len() {
+        let update = nodes[node_id].updates[i].node();
+        if matches!(nodes[update].ty, NodeType::Observer) {
+            observe(scheduler, nodes, update);
+        }
+    }
+}
+
 fn schedule_tick(
     scheduler: &mut TickScheduler,
     node_id: NodeId,
@@ -679,19 +1218,24 @@ fn get_all_input(node: &Node) -> (u8, u8) {
 }
 
 #[inline(always)]
-fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+fn update_node(
+    scheduler: &mut TickScheduler,
+    events: &mut Vec<Event>,
+    nodes: &mut Nodes,
+    node_id: NodeId,
+) {
     let node = &nodes[node_id];
 
     match node.ty {
         NodeType::Repeater(delay) => {
             let node = &mut nodes[node_id];
             let should_be_locked = get_bool_side(node);
-            if !node.locked && should_be_locked {
-                set_node_locked(node, true);
-            } else if node.locked && !should_be_locked {
-                set_node_locked(node, false);
+            if node.locked != should_be_locked {
+                set_node_locked(node, should_be_locked);
+                notify_observers(scheduler, nodes, node_id);
             }
 
+            let node = &mut nodes[node_id];
             if !node.locked && !node.pending_tick {
                 let should_be_powered = get_bool_input(node);
                 if should_be_powered != node.powered {
@@ -764,6 +1308,7 @@ fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId
                 schedule_tick(scheduler, node_id, node, 2, TickPriority::Normal);
             } else if !lit && should_be_lit {
                 set_node(node, true);
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::Trapdoor => {
@@ -771,6 +1316,28 @@ fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId
             if node.powered != should_be_powered {
                 let node = &mut nodes[node_id];
                 set_node(node, should_be_powered);
+                notify_observers(scheduler, nodes, node_id);
+            }
+        }
+        NodeType::NoteBlock => {
+            let should_be_powered = get_bool_input(node);
+            if node.powered != should_be_powered {
+                let node = &mut nodes[node_id];
+                set_node(node, should_be_powered);
+                notify_observers(scheduler, nodes, node_id);
+                if should_be_powered {
+                    events.push(Event::NoteBlockPlay(node_id));
+                }
+            }
+        }
+        NodeType::Piston => {
+            if node.pending_tick {
+                return;
+            }
+            let should_extend = get_bool_input(node);
+            if node.powered != should_extend {
+                let node = &mut nodes[node_id];
+                schedule_tick(scheduler, node_id, node, 1, TickPriority::Normal);
             }
         }
         NodeType::Wire => {
@@ -779,12 +1346,34 @@ fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId
                 let node = &mut nodes[node_id];
                 node.output_power = input_power;
                 node.changed = true;
+                notify_observers(scheduler, nodes, node_id);
             }
         }
+        NodeType::Observer => observe(scheduler, nodes, node_id),
         _ => {} // panic!("Node {:?} should not be updated!", node.state),
     }
 }
 
+impl DirectBackend {
+    fn dot_label(&self, id: usize) -> (String, String) {
+        let node = &self.nodes.inner()[id];
+        let label = match node.ty {
+            NodeType::Constant => format!("Constant: {}", node.output_power),
+            _ => format!("{:?}", node.ty)
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .to_string(),
+        };
+        let pos = if let Some((pos, _)) = self.blocks[id] {
+            format!("{}, {}, {}", pos.x, pos.y, pos.z)
+        } else {
+            "No Pos".to_string()
+        };
+        (label, pos)
+    }
+}
+
 impl fmt::Display for DirectBackend {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("digraph{")?;
@@ -792,19 +1381,7 @@ impl fmt::Display for DirectBackend {
             if matches!(node.ty, NodeType::Wire) {
                 continue;
             }
-            let label = match node.ty {
-                NodeType::Constant => format!("Constant: {}", node.output_power),
-                _ => format!("{:?}", node.ty)
-                    .split_whitespace()
-                    .next()
-                    .unwrap()
-                    .to_string(),
-            };
-            let pos = if let Some((pos, _)) = self.blocks[id] {
-                format!("{}, {}, {}", pos.x, pos.y, pos.z)
-            } else {
-                "No Pos".to_string()
-            };
+            let (label, pos) = self.dot_label(id);
             write!(f, "n{}[label=\"{}\\n({})\"];", id, label, pos,)?;
             for link in node.updates.iter() {
                 let out_index = link.node().index();
@@ -833,3 +1410,286 @@ fn calculate_comparator_output(mode: ComparatorMode, input_strength: u8, power_o
         ComparatorMode::Subtract => input_strength.saturating_sub(power_on_sides),
     }
 }
+
+#[test]
+fn redpiler_resumes_pending_repeater_ticks() {
+    use crate::plot::{assert_redpiler_resumes_identically, PlotWorld};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    // A redstone block feeding a repeater chain into a lamp, saved halfway through
+    // the first repeater's delay.
+    let build = |world: &mut PlotWorld| {
+        world.set_block(BlockPos::new(10, 8, 10), Block::RedstoneBlock {});
+        for x in 11..13 {
+            let repeater = RedstoneRepeater {
+                delay: 3,
+                facing: BlockDirection::West,
+                locked: false,
+                powered: false,
+            };
+            world.set_block(
+                BlockPos::new(x, 8, 10),
+                Block::RedstoneRepeater { repeater },
+            );
+        }
+        world.set_block(BlockPos::new(13, 8, 10), Block::RedstoneLamp { lit: false });
+        world.schedule_tick(BlockPos::new(11, 8, 10), 2, TickPriority::High);
+    };
+    let check: Vec<BlockPos> = (11..14).map(|x| BlockPos::new(x, 8, 10)).collect();
+    assert_redpiler_resumes_identically(build, &check, 10);
+}
+
+#[test]
+fn redpiler_resumes_locked_repeaters() {
+    use crate::plot::{assert_redpiler_resumes_identically, PlotWorld};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    // A repeater that got locked while its tick was still pending, so the tick
+    // must not change its state. The locking repeater is then released by its own tick.
+    let build = |world: &mut PlotWorld| {
+        world.set_block(BlockPos::new(10, 8, 10), Block::RedstoneBlock {});
+        let repeater = RedstoneRepeater {
+            delay: 1,
+            facing: BlockDirection::West,
+            locked: true,
+            powered: false,
+        };
+        world.set_block(
+            BlockPos::new(11, 8, 10),
+            Block::RedstoneRepeater { repeater },
+        );
+        world.set_block(BlockPos::new(12, 8, 10), Block::RedstoneLamp { lit: false });
+        let side = RedstoneRepeater {
+            delay: 4,
+            facing: BlockDirection::South,
+            locked: false,
+            powered: true,
+        };
+        world.set_block(
+            BlockPos::new(11, 8, 11),
+            Block::RedstoneRepeater { repeater: side },
+        );
+        world.schedule_tick(BlockPos::new(11, 8, 10), 1, TickPriority::High);
+        world.schedule_tick(BlockPos::new(11, 8, 11), 3, TickPriority::Higher);
+    };
+    let check = [
+        BlockPos::new(11, 8, 10),
+        BlockPos::new(12, 8, 10),
+        BlockPos::new(11, 8, 11),
+    ];
+    assert_redpiler_resumes_identically(build, &check, 12);
+}
+
+#[test]
+fn redpiler_derives_outdated_repeater_locks() {
+    use crate::plot::{assert_redpiler_resumes_identically, PlotWorld};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    // A repeater whose locked state doesn't match its side input, like after a paste without
+    // updates. Its input turns on after 2 ticks, which makes the world update the lock.
+    let build = |world: &mut PlotWorld, locked: bool, side_powered: bool| {
+        let repeater = |locked, powered, facing| Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                delay: 1,
+                facing,
+                locked,
+                powered,
+            },
+        };
+        world.set_block(BlockPos::new(9, 8, 10), Block::RedstoneBlock {});
+        world.set_block(
+            BlockPos::new(10, 8, 10),
+            repeater(false, false, BlockDirection::West),
+        );
+        world.set_block(
+            BlockPos::new(11, 8, 10),
+            repeater(locked, false, BlockDirection::West),
+        );
+        world.set_block(BlockPos::new(12, 8, 10), Block::RedstoneLamp { lit: false });
+        if side_powered {
+            world.set_block(BlockPos::new(11, 8, 12), Block::RedstoneBlock {});
+        }
+        world.set_block(
+            BlockPos::new(11, 8, 11),
+            repeater(false, side_powered, BlockDirection::South),
+        );
+        world.schedule_tick(BlockPos::new(10, 8, 10), 2, TickPriority::High);
+    };
+    let lamp = [BlockPos::new(12, 8, 10)];
+    // Locked without a side input, the repeater unlocks and passes the signal on
+    assert_redpiler_resumes_identically(|world| build(world, true, false), &lamp, 20);
+    // Unlocked with a powered side input, the repeater locks and stays off
+    assert_redpiler_resumes_identically(|world| build(world, false, true), &lamp, 20);
+}
+
+#[test]
+fn redpiler_plays_note_blocks() {
+    use crate::plot::{compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::{Instrument, Lever, LeverFace};
+    use mchprs_blocks::{BlockDirection, BlockFace};
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(9, 8, 10);
+    let note_pos = BlockPos::new(10, 8, 10);
+    world.set_block(
+        lever_pos,
+        Block::Lever {
+            lever: Lever::new(LeverFace::Floor, BlockDirection::North, false),
+        },
+    );
+    world.set_block(
+        note_pos,
+        Block::NoteBlock {
+            instrument: Instrument::Bit,
+            note: 5,
+            powered: false,
+        },
+    );
+
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+
+    // Every time the note block turns on a note is played, even without a flush in between
+    for _ in 0..3 {
+        redpiler.on_use_block(lever_pos);
+        redpiler.tick();
+    }
+    redpiler.flush(&mut world);
+    let events: Vec<_> = world
+        .note_events
+        .iter()
+        .map(|event| (event.pos, event.instrument, event.note))
+        .collect();
+    assert_eq!(events, [(note_pos, Instrument::Bit, 5); 2]);
+    assert!(matches!(
+        world.get_block(note_pos),
+        Block::NoteBlock { powered: true, .. }
+    ));
+
+    // A note block with a block on top of it doesn't play
+    world.note_events.clear();
+    world.set_block(note_pos.offset(BlockFace::Top), Block::Stone {});
+    for _ in 0..2 {
+        redpiler.on_use_block(lever_pos);
+        redpiler.tick();
+    }
+    redpiler.flush(&mut world);
+    assert!(world.note_events.is_empty());
+}
+
+#[test]
+fn graph_export_only_contains_the_reachable_subgraph() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use crate::redpiler::Compiler;
+
+    let mut world = redpiler_test_world();
+    for z in [10, 14] {
+        build_lever_line(&mut world, BlockPos::new(10, 8, z), &[]);
+    }
+
+    assert!(Compiler::default()
+        .subgraph_dot(&[BlockPos::new(10, 8, 10)], None, false)
+        .is_none());
+    let redpiler = compile_test_world(&mut world, Default::default());
+
+    let dot = redpiler
+        .subgraph_dot(&[BlockPos::new(10, 8, 10)], None, false)
+        .unwrap();
+    assert!(dot.contains("(10, 8, 10)"));
+    assert!(dot.contains("(11, 8, 10)"));
+    assert!(!dot.contains("(10, 8, 14)"));
+    assert!(!dot.contains("(11, 8, 14)"));
+
+    // Following links backwards from the lamp finds its lever, but nothing goes past the limit
+    let dot = redpiler
+        .subgraph_dot(&[BlockPos::new(11, 8, 14)], Some(1), true)
+        .unwrap();
+    assert!(dot.contains("(10, 8, 14)"));
+    let dot = redpiler
+        .subgraph_dot(&[BlockPos::new(11, 8, 14)], Some(0), true)
+        .unwrap();
+    assert!(!dot.contains("(10, 8, 14)"));
+}
+
+#[test]
+fn redpiler_reports_scheduled_ticks() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use crate::redpiler::Compiler;
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let repeater_pos = BlockPos::new(11, 8, 10);
+    let repeater = RedstoneRepeater {
+        delay: 3,
+        facing: BlockDirection::West,
+        locked: false,
+        powered: false,
+    };
+    let repeater = Block::RedstoneRepeater { repeater };
+    build_lever_line(&mut world, lever_pos, &[repeater]);
+
+    assert_eq!(Compiler::default().scheduled_ticks(), None);
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+    assert_eq!(redpiler.scheduled_ticks(), Some(Vec::new()));
+
+    redpiler.on_use_block(lever_pos);
+    assert_eq!(redpiler.scheduled_ticks(), Some(vec![(repeater_pos, 3)]));
+    redpiler.tick();
+    assert_eq!(redpiler.scheduled_ticks(), Some(vec![(repeater_pos, 2)]));
+}
+
+#[test]
+fn redpiler_restores_snapshots() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever = BlockPos::new(10, 8, 10);
+    let repeater = RedstoneRepeater {
+        delay: 2,
+        facing: BlockDirection::West,
+        locked: false,
+        powered: false,
+    };
+    let repeater = Block::RedstoneRepeater { repeater };
+    let lamp = build_lever_line(&mut world, lever, &[repeater; 2]);
+    world.flush_block_changes();
+
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+
+    // Taken while the signal is still on its way to the lamp
+    redpiler.on_use_block(lever);
+    redpiler.tick();
+    let snapshot = redpiler.snapshot().unwrap();
+    let scheduled = redpiler.scheduled_ticks().unwrap();
+    assert!(!scheduled.is_empty());
+    for _ in 0..10 {
+        redpiler.tick();
+    }
+    redpiler.on_use_block(lever);
+    assert_eq!(redpiler.is_powered(lamp), Some(true));
+
+    redpiler.restore(&mut world, &snapshot).unwrap();
+    assert_eq!(redpiler.snapshot().unwrap(), snapshot);
+    assert_eq!(redpiler.scheduled_ticks().unwrap(), scheduled);
+    assert_eq!(redpiler.is_powered(lever), Some(true));
+    assert_eq!(redpiler.is_powered(lamp), Some(false));
+    assert!(matches!(
+        world.get_block(lamp),
+        Block::RedstoneLamp { lit: false }
+    ));
+    // The circuit runs on from the snapshot like it did the first time
+    for _ in 0..10 {
+        redpiler.tick();
+    }
+    assert_eq!(redpiler.is_powered(lamp), Some(true));
+
+    let mut other = snapshot.clone();
+    other.nodes.pop();
+    assert!(redpiler.restore(&mut world, &other).is_err());
+}
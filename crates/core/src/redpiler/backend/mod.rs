@@ -1,21 +1,117 @@
 pub mod direct;
 
 use super::compile_graph::CompileGraph;
+use super::incremental::GraphPatch;
 use crate::world::World;
 use enum_dispatch::enum_dispatch;
 use mchprs_blocks::BlockPos;
-use mchprs_world::TickEntry;
+use mchprs_world::{TickEntry, TickPriority};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Describes what a backend is able to simulate. This is checked against the requirements of
+/// the compile graph so that a backend never silently simulates a circuit incorrectly.
+#[derive(Debug, Clone, Copy)]
+pub struct BackendCapabilities {
+    pub name: &'static str,
+    /// Signal strengths other than 0 and 15, used by comparators
+    pub analog: bool,
+    pub noteblocks: bool,
+    pub pistons: bool,
+    pub observers: bool,
+    /// Running the same inputs always produces the same outputs on the same ticks
+    pub deterministic: bool,
+    pub max_nodes: usize,
+}
+
+/// The state of a node in a [`BackendSnapshot`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeState {
+    /// The block the node was compiled from, if it has one
+    pub pos: Option<BlockPos>,
+    pub powered: bool,
+    pub output_power: u8,
+    /// Only for repeaters
+    pub locked: bool,
+}
+
+/// A tick that was scheduled when a [`BackendSnapshot`] was taken
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledTick {
+    /// The index of the node in [`BackendSnapshot::nodes`]
+    pub node: usize,
+    pub delay: u32,
+    pub priority: TickPriority,
+}
+
+/// The full state of a running circuit, which can be restored as long as the circuit is compiled
+/// the same way again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendSnapshot {
+    pub nodes: Vec<NodeState>,
+    pub ticks: Vec<ScheduledTick>,
+}
+
+impl BackendSnapshot {
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<BackendSnapshot> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
 
 #[enum_dispatch]
 pub trait JITBackend {
+    fn capabilities(&self) -> BackendCapabilities;
     fn compile(&mut self, graph: CompileGraph, ticks: Vec<TickEntry>);
     fn tick(&mut self);
     fn on_use_block(&mut self, pos: BlockPos);
     fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool);
+    /// Powers the target block at `pos` with `power` for `ticks` redstone ticks, unless it is
+    /// still powered from an earlier hit
+    fn hit_target(&mut self, pos: BlockPos, power: u8, ticks: u32);
+    /// Writes the changed nodes to the world. With `io_only`, only io blocks are written and
+    /// the other changes are kept until a flush without it.
     fn flush<W: World>(&mut self, world: &mut W, io_only: bool);
     fn reset<W: World>(&mut self, world: &mut W, io_only: bool);
+    /// Writes the state of the circuit and its scheduled ticks to the world like
+    /// [`JITBackend::reset`], but keeps the circuit so that it can be patched with
+    /// [`JITBackend::patch`] after blocks were changed
+    fn suspend<W: World>(&mut self, world: &mut W, io_only: bool) {
+        self.reset(world, io_only);
+    }
+    /// Patches the nodes in `patch` into a suspended circuit, where `graph` is the patched
+    /// graph the circuit was compiled from before any passes ran on it, and starts it again
+    /// with `ticks`. Returns false if the backend can't be patched, in which case it has to be
+    /// compiled again.
+    fn patch(&mut self, _graph: &CompileGraph, _patch: &GraphPatch, _ticks: &[TickEntry]) -> bool {
+        false
+    }
     /// Inspect block for debugging
     fn inspect(&mut self, pos: BlockPos);
+    /// Returns the current output signal strength of the node at `pos`, if there is one
+    fn get_signal_strength(&self, pos: BlockPos) -> Option<u8>;
+    /// Returns whether the node at `pos` is currently powered, if there is one
+    fn is_powered(&self, pos: BlockPos) -> Option<bool>;
+    /// Returns the position of every node with a scheduled tick and the number of ticks until it
+    /// happens
+    fn scheduled_ticks(&self) -> Vec<(BlockPos, u32)>;
+    /// Returns the nodes at `roots` and the nodes that can be reached from them in at most
+    /// `max_links` links as a DOT graph, labelled with their positions and current state. With
+    /// `incoming`, links are also followed backwards to the nodes that update the roots.
+    fn subgraph_dot(&self, roots: &[BlockPos], max_links: Option<usize>, incoming: bool) -> String;
+    fn snapshot(&self) -> BackendSnapshot;
+    /// Restores the state of a snapshot taken of the same circuit, marking every node as changed
+    /// so that the next flush writes the whole circuit
+    fn restore(&mut self, snapshot: &BackendSnapshot) -> Result<(), String>;
 }
 
 #[cfg(feature = "jit_cranelift")]
@@ -34,3 +130,52 @@ impl Default for BackendDispatcher {
         Self::DirectBackend(Default::default())
     }
 }
+
+impl BackendDispatcher {
+    /// Returns a fresh backend of `kind`, or `None` for `BackendKind::Auto`
+    pub fn new(kind: BackendKind) -> Option<BackendDispatcher> {
+        match kind {
+            BackendKind::Auto => None,
+            BackendKind::Direct => Some(Self::DirectBackend(Default::default())),
+        }
+    }
+
+    /// Every backend in order of preference for automatic selection
+    pub fn candidates() -> Vec<BackendDispatcher> {
+        vec![Self::DirectBackend(Default::default())]
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Pick a backend based on the compiled circuit
+    #[default]
+    Auto,
+    Direct,
+}
+
+impl BackendKind {
+    /// Every backend that can be selected explicitly
+    pub const BACKENDS: [BackendKind; 1] = [BackendKind::Direct];
+}
+
+impl FromStr for BackendKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => BackendKind::Auto,
+            "direct" => BackendKind::Direct,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl ToString for BackendKind {
+    fn to_string(&self) -> String {
+        match self {
+            BackendKind::Auto => "auto".to_owned(),
+            BackendKind::Direct => "direct".to_owned(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Compiled graphs are saved to disk, so that a circuit that hasn't changed doesn't have to go
+//! through the passes again when it is compiled after a restart. The saved graph is keyed by a
+//! hash of the blocks inside of the compiled bounds and of the compile options, and is only
+//! used if both are still the same.
+//!
+//! The backends build their nodes from the graph quickly, so only the graph is saved.
+
+use super::compile_graph::{CompileGraph, CompileLink, CompileNode};
+use super::CompilerOptions;
+use crate::world::{for_each_block_optimized, World};
+use mchprs_blocks::BlockPos;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, NodeIndexable};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Has to be increased whenever the graph or the passes change in a way that makes graphs
+/// saved by an older version wrong
+const FORMAT_VERSION: u32 = 1;
+
+pub type CacheKey = [u8; 16];
+
+#[derive(Serialize, Deserialize)]
+struct SavedGraph {
+    nodes: Vec<CompileNode>,
+    /// The source, target and weight of every link, with the nodes referred to by their index
+    /// in `nodes`
+    links: Vec<(u32, u32, CompileLink)>,
+}
+
+/// Graphs can't be loaded from the cache if they are compiled with these options
+pub fn is_supported(options: &CompilerOptions) -> bool {
+    // Exporting happens in a pass, and incremental compiles need to search the world anyway
+    !options.export && options.netlist.is_none() && !options.incremental
+}
+
+/// Hashes everything that the compiled graph depends on
+pub fn key<W: World>(
+    world: &W,
+    bounds: (BlockPos, BlockPos),
+    options: &CompilerOptions,
+) -> CacheKey {
+    let mut context = md5::Context::new();
+    context.consume(FORMAT_VERSION.to_le_bytes());
+    let options_key = format!(
+        "{} {} {} {:?} {:?}",
+        options.optimize,
+        options.io_only,
+        options.wire_delay,
+        options.passes,
+        options.disabled_passes
+    );
+    context.consume(options_key.as_bytes());
+    for pos in [bounds.0, bounds.1] {
+        context.consume(bincode::serialize(&pos).unwrap());
+    }
+    for_each_block_optimized(world, bounds.0, bounds.1, |pos| {
+        context.consume(bincode::serialize(&pos).unwrap());
+        context.consume(world.get_block_raw(pos).to_le_bytes());
+        if let Some(entity) = world.get_block_entity(pos) {
+            context.consume(bincode::serialize(entity).unwrap());
+        }
+    });
+    context.compute().0
+}
+
+/// Loads the graph saved at `path` if it was saved with the same key
+pub fn load(path: &Path, key: CacheKey) -> Option<CompileGraph> {
+    let file = File::open(path).ok()?;
+    let mut reader = BufReader::new(file);
+    let header: Result<(u32, CacheKey), _> = bincode::deserialize_from(&mut reader);
+    if header.ok()? != (FORMAT_VERSION, key) {
+        debug!("The cached graph at {} is outdated", path.display());
+        return None;
+    }
+    let saved: SavedGraph = match bincode::deserialize_from(&mut reader) {
+        Ok(saved) => saved,
+        Err(err) => {
+            warn!(
+                "Failed to load the cached graph at {}: {}",
+                path.display(),
+                err
+            );
+            return None;
+        }
+    };
+
+    let mut graph = CompileGraph::with_capacity(saved.nodes.len(), saved.links.len());
+    let indices: Vec<_> = saved
+        .nodes
+        .into_iter()
+        .map(|node| graph.add_node(node))
+        .collect();
+    for (source, target, link) in saved.links {
+        let (Some(&source), Some(&target)) =
+            (indices.get(source as usize), indices.get(target as usize))
+        else {
+            warn!("The cached graph at {} is corrupted", path.display());
+            return None;
+        };
+        graph.add_edge(source, target, link);
+    }
+    Some(graph)
+}
+
+/// Saves `graph` to `path`, replacing the graph that was saved there before
+pub fn save(path: &Path, key: CacheKey, graph: &CompileGraph) {
+    // The node indices of a stable graph can have holes, which are left out in the saved graph
+    let mut indices = vec![0; graph.node_bound()];
+    for (i, idx) in graph.node_indices().enumerate() {
+        indices[idx.index()] = i as u32;
+    }
+    let saved = SavedGraph {
+        nodes: graph.node_indices().map(|idx| graph[idx].clone()).collect(),
+        links: graph
+            .edge_references()
+            .map(|edge| {
+                let source = indices[edge.source().index()];
+                let target = indices[edge.target().index()];
+                (source, target, edge.weight().clone())
+            })
+            .collect(),
+    };
+
+    if let Err(err) = write_graph(path, key, &saved) {
+        warn!(
+            "Failed to save the compiled graph to {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+fn write_graph(path: &Path, key: CacheKey, saved: &SavedGraph) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(&mut writer, &(FORMAT_VERSION, key))?;
+    bincode::serialize_into(&mut writer, saved)?;
+    Ok(())
+}
+
+#[test]
+fn redpiler_loads_unchanged_circuits_from_the_cache() {
+    use crate::plot::{build_lever_line, redpiler_test_world, PlotWorld};
+    use crate::redpiler::Compiler;
+    use mchprs_blocks::blocks::Block;
+
+    let mut world = redpiler_test_world();
+    build_lever_line(&mut world, BlockPos::new(10, 8, 10), &[]);
+    let bounds = world.get_corners();
+    let path = std::env::temp_dir().join(format!("mchprs_cache_test_{}", std::process::id()));
+
+    let compile = |world: &mut PlotWorld| {
+        let mut redpiler = Compiler::default();
+        redpiler.set_cache_path(Some(path.clone()));
+        redpiler
+            .compile(world, bounds, Default::default(), Vec::new())
+            .unwrap();
+        redpiler
+    };
+    assert!(!compile(&mut world).pass_timings().is_empty());
+    let mut redpiler = compile(&mut world);
+    assert!(redpiler.pass_timings().is_empty());
+    redpiler.on_use_block(BlockPos::new(10, 8, 10));
+    redpiler.tick();
+    redpiler.flush(&mut world);
+    assert_eq!(
+        world.get_block(BlockPos::new(11, 8, 10)),
+        Block::RedstoneLamp { lit: true }
+    );
+    redpiler.reset(&mut world, bounds);
+
+    // Changing a block compiles the circuit again
+    world.set_block(BlockPos::new(12, 8, 10), Block::RedstoneLamp { lit: false });
+    assert!(!compile(&mut world).pass_timings().is_empty());
+
+    std::fs::remove_file(path).unwrap();
+}
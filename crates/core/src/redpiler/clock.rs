@@ -0,0 +1,170 @@
+//! Runs a compiled circuit by the cycles of its clock instead of by ticks, so that programs on a
+//! redstone computer can be stepped through without waiting for every tick in real time.
+//!
+//! The clock is either an input, like a lever, or an output that the circuit drives itself,
+//! like the lamp of a clock loop. An input clock is only flipped once the circuit has settled,
+//! so every half of a cycle takes as many ticks as the circuit needs. For an output clock, the
+//! circuit is ticked until the clock changes.
+
+use super::replay::InputEvent;
+use super::Compiler;
+use mchprs_blocks::BlockPos;
+
+/// The most ticks a fast forward can take, so that a clock that never changes or a circuit
+/// that never settles doesn't hang the plot
+pub const MAX_TICKS: u32 = 1_000_000;
+
+/// Whether a port with `name` is the clock of the circuit, like `CLK`
+pub fn is_clock_name(name: &str) -> bool {
+    ["clk", "clock"]
+        .iter()
+        .any(|clock| name.eq_ignore_ascii_case(clock))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// The next time the clock turns on or off
+    Edge,
+    /// A number of full cycles, in which the clock turns on and off once
+    Cycles(u32),
+}
+
+impl Until {
+    fn edges(self) -> u32 {
+        match self {
+            Until::Edge => 1,
+            Until::Cycles(cycles) => cycles.saturating_mul(2),
+        }
+    }
+}
+
+/// A fast forward of a circuit in progress
+pub struct FastForward {
+    clock: BlockPos,
+    /// Whether the clock is an input that is flipped, instead of an output that is watched
+    input: bool,
+    edges_left: u32,
+    powered: bool,
+    ticks: u32,
+}
+
+impl FastForward {
+    pub fn new(
+        redpiler: &Compiler,
+        clock: BlockPos,
+        input: bool,
+        until: Until,
+    ) -> Result<FastForward, &'static str> {
+        let powered = redpiler
+            .is_powered(clock)
+            .ok_or("The clock is not part of the compiled circuit.")?;
+        Ok(FastForward {
+            clock,
+            input,
+            edges_left: until.edges(),
+            powered,
+            ticks: 0,
+        })
+    }
+
+    /// The ticks the circuit has been ticked for so far
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    fn observe(&mut self, redpiler: &Compiler) {
+        let powered = redpiler.is_powered(self.clock).unwrap_or(self.powered);
+        if powered != self.powered {
+            self.powered = powered;
+            self.edges_left = self.edges_left.saturating_sub(1);
+        }
+    }
+
+    /// Prepares the next tick, flipping an input clock if the circuit has settled. Returns
+    /// whether the fast forward is finished instead, in which case the circuit shouldn't be
+    /// ticked anymore.
+    pub fn step(&mut self, redpiler: &mut Compiler) -> Result<bool, String> {
+        self.observe(redpiler);
+        if self.input {
+            let settled = redpiler
+                .scheduled_ticks()
+                .map_or(true, |ticks| ticks.is_empty());
+            if settled {
+                if self.edges_left == 0 {
+                    return Ok(true);
+                }
+                let edges_left = self.edges_left;
+                redpiler.input(InputEvent::Use(self.clock));
+                self.observe(redpiler);
+                if self.edges_left == edges_left {
+                    return Err("The clock input didn't change when it was used.".to_owned());
+                }
+            }
+        } else if self.edges_left == 0 {
+            return Ok(true);
+        }
+
+        if self.ticks >= MAX_TICKS {
+            return Err(match self.input {
+                true => format!("The circuit didn't settle within {} ticks.", MAX_TICKS),
+                false => format!("The clock didn't change within {} ticks.", MAX_TICKS),
+            });
+        }
+        self.ticks += 1;
+        Ok(false)
+    }
+}
+
+#[test]
+fn clock_ports_are_found_by_name() {
+    assert!(is_clock_name("CLK"));
+    assert!(is_clock_name("clock"));
+    assert!(!is_clock_name("CLK2"));
+    assert_eq!(Until::Cycles(3).edges(), 6);
+}
+
+#[test]
+fn fast_forward_runs_by_clock_edges() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use crate::world::World;
+    use mchprs_blocks::blocks::{Block, RedstoneRepeater};
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let clock = BlockPos::new(10, 8, 10);
+    let repeater = RedstoneRepeater {
+        delay: 3,
+        facing: BlockDirection::West,
+        locked: false,
+        powered: false,
+    };
+    let repeater = Block::RedstoneRepeater { repeater };
+    let lamp = build_lever_line(&mut world, clock, &[repeater; 2]);
+    world.flush_block_changes();
+
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+    fn run_until(redpiler: &mut Compiler, pos: BlockPos, input: bool, until: Until) -> u32 {
+        let mut run = FastForward::new(redpiler, pos, input, until).unwrap();
+        while !run.step(redpiler).unwrap() {
+            redpiler.tick();
+        }
+        run.ticks()
+    }
+
+    // The clock input is flipped and the circuit runs until it settles
+    let settle_ticks = run_until(&mut redpiler, clock, true, Until::Edge);
+    assert!(settle_ticks >= 2);
+    assert_eq!(redpiler.is_powered(lamp), Some(true));
+    assert!(redpiler.scheduled_ticks().unwrap().is_empty());
+    let ticks = run_until(&mut redpiler, clock, true, Until::Cycles(2));
+    assert!(ticks >= 4 * settle_ticks);
+    assert_eq!(redpiler.is_powered(clock), Some(true));
+    assert_eq!(redpiler.is_powered(lamp), Some(true));
+
+    // An output clock is watched until it changes
+    redpiler.on_use_block(clock);
+    let ticks = run_until(&mut redpiler, lamp, false, Until::Edge);
+    assert!(ticks >= 2);
+    assert_eq!(redpiler.is_powered(lamp), Some(false));
+    assert!(FastForward::new(&redpiler, BlockPos::new(0, 8, 0), false, Until::Edge).is_err());
+}
@@ -1,10 +1,11 @@
 use mchprs_blocks::blocks::ComparatorMode;
 use mchprs_blocks::BlockPos;
 use petgraph::stable_graph::{NodeIndex, StableGraph};
+use serde::{Deserialize, Serialize};
 
 pub type NodeIdx = NodeIndex;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeType {
     Repeater(u8),
     Torch,
@@ -16,15 +17,23 @@ pub enum NodeType {
     Trapdoor,
     Wire,
     Constant,
+    Piston,
+    NoteBlock,
+    Observer,
+    /// Outputs an analog signal for a while after it is hit
+    Target,
 }
 
 impl NodeType {
     pub fn is_output(self) -> bool {
-        matches!(self, NodeType::Lamp | NodeType::Trapdoor)
+        matches!(
+            self,
+            NodeType::Lamp | NodeType::Trapdoor | NodeType::Piston | NodeType::NoteBlock
+        )
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NodeState {
     pub powered: bool,
     pub repeater_locked: bool,
@@ -64,7 +73,7 @@ impl NodeState {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompileNode {
     pub ty: NodeType,
     pub block: Option<(BlockPos, u32)>,
@@ -74,35 +83,35 @@ pub struct CompileNode {
     pub comparator_far_input: Option<u8>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LinkType {
     Default,
     Side,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompileLink {
     pub ty: LinkType,
     pub ss: u8,
+    /// Extra redstone ticks from latency annotations along the wire, see `InsertDelays`
+    pub delay: u8,
 }
 
 impl CompileLink {
     pub fn new(ty: LinkType, ss: u8) -> CompileLink {
-        CompileLink { ty, ss }
+        CompileLink { ty, ss, delay: 0 }
     }
 
     pub fn default(ss: u8) -> CompileLink {
-        CompileLink {
-            ty: LinkType::Default,
-            ss,
-        }
+        CompileLink::new(LinkType::Default, ss)
     }
 
     pub fn side(ss: u8) -> CompileLink {
-        CompileLink {
-            ty: LinkType::Side,
-            ss,
-        }
+        CompileLink::new(LinkType::Side, ss)
+    }
+
+    pub fn with_delay(self, delay: u8) -> CompileLink {
+        CompileLink { delay, ..self }
     }
 }
 
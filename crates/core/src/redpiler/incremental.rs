@@ -0,0 +1,462 @@
+//! Incremental compilation keeps the unoptimized graph of a circuit around after it has been
+//! compiled, so that a block change only has to identify and search the part of the circuit
+//! around the changed blocks instead of the whole plot.
+//!
+//! While the inputs of a node are searched, every position that is read from the world is
+//! recorded. When blocks change, only the blocks at the changed positions and next to them are
+//! identified again, and only the nodes at the changed positions and the nodes that read one of
+//! them while searching are searched again. The nodes that changed are then patched into the
+//! running backend, which keeps the state of the rest of the circuit.
+//!
+//! The optimization passes merge and remove nodes across the whole graph, so incremental
+//! compilation is not available together with `--optimize`, and neither with the options that
+//! add nodes or export the graph after the input search. Circuits with pistons are always
+//! compiled from scratch, since blocks carried by pistons depend on the state of the piston.
+
+use super::compile_graph::{CompileGraph, NodeIdx, NodeType};
+use super::passes::{identify_node, search_inputs};
+use super::CompilerOptions;
+use crate::redstone::updates::UpdateQueue;
+use crate::world::storage::Chunk;
+use crate::world::{non_empty_sections, World};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::{BlockFace, BlockPos};
+use mchprs_world::TickPriority;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cell::RefCell;
+
+/// Wraps a world to record every position that is read from it
+struct RecordingWorld<'a, W: World> {
+    world: &'a W,
+    reads: RefCell<Vec<BlockPos>>,
+}
+
+impl<'a, W: World> RecordingWorld<'a, W> {
+    fn new(world: &'a W) -> Self {
+        RecordingWorld {
+            world,
+            reads: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns the positions that were read since the last call
+    fn take_reads(&self) -> Vec<BlockPos> {
+        let mut reads = self.reads.take();
+        reads.sort_unstable_by_key(|pos| (pos.x, pos.y, pos.z));
+        reads.dedup();
+        reads
+    }
+}
+
+impl<'a, W: World> World for RecordingWorld<'a, W> {
+    fn get_block_raw(&self, pos: BlockPos) -> u32 {
+        self.reads.borrow_mut().push(pos);
+        self.world.get_block_raw(pos)
+    }
+
+    fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        self.reads.borrow_mut().push(pos);
+        self.world.get_block_entity(pos)
+    }
+
+    fn get_chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.world.get_chunk(x, z)
+    }
+
+    fn is_cursed(&self) -> bool {
+        self.world.is_cursed()
+    }
+
+    fn daytime(&self) -> i64 {
+        self.world.daytime()
+    }
+
+    fn set_block_raw(&mut self, _: BlockPos, _: u32) -> bool {
+        unreachable!("the world is not changed while searching inputs")
+    }
+
+    fn delete_block_entity(&mut self, _: BlockPos) {
+        unreachable!("the world is not changed while searching inputs")
+    }
+
+    fn set_block_entity(&mut self, _: BlockPos, _: BlockEntity) {
+        unreachable!("the world is not changed while searching inputs")
+    }
+
+    fn get_chunk_mut(&mut self, _: i32, _: i32) -> Option<&mut Chunk> {
+        unreachable!("the world is not changed while searching inputs")
+    }
+
+    fn schedule_tick(&mut self, _: BlockPos, _: u32, _: TickPriority) {
+        unreachable!("the world is not changed while searching inputs")
+    }
+
+    fn pending_tick_at(&mut self, _: BlockPos) -> bool {
+        unreachable!("the world is not changed while searching inputs")
+    }
+
+    fn update_queue(&mut self) -> &mut UpdateQueue {
+        unreachable!("the world is not changed while searching inputs")
+    }
+}
+
+fn in_bounds(bounds: (BlockPos, BlockPos), pos: BlockPos) -> bool {
+    let (min, max) = bounds;
+    (min.x..=max.x).contains(&pos.x)
+        && (min.y..=max.y).contains(&pos.y)
+        && (min.z..=max.z).contains(&pos.z)
+}
+
+pub fn is_supported(options: &CompilerOptions) -> bool {
+    options.incremental
+        && !options.optimize
+        && !options.wire_delay
+        && !options.export
+        && options.netlist.is_none()
+}
+
+/// The nodes that changed in an [`IncrementalGraph::update`], which have to be patched into the
+/// backend
+#[derive(Debug, Default)]
+pub struct GraphPatch {
+    /// Nodes that were removed from the graph. Their indices can be reused by added nodes.
+    pub removed: Vec<NodeIdx>,
+    /// Nodes that were added or identified again, whose state has to be taken from the graph
+    pub identified: Vec<NodeIdx>,
+    /// Nodes whose inputs were searched again
+    pub searched: Vec<NodeIdx>,
+    /// Nodes whose outgoing links might have changed
+    pub sources: Vec<NodeIdx>,
+}
+
+/// The unoptimized graph of a circuit, along with what is needed to patch it
+pub struct IncrementalGraph {
+    graph: CompileGraph,
+    bounds: (BlockPos, BlockPos),
+    wire_delay: bool,
+    pos_map: FxHashMap<BlockPos, NodeIdx>,
+    /// The positions that were read while searching the inputs of each node
+    footprints: FxHashMap<NodeIdx, Vec<BlockPos>>,
+    /// The nodes that read each position while their inputs were searched
+    readers: FxHashMap<BlockPos, Vec<NodeIdx>>,
+}
+
+impl IncrementalGraph {
+    /// Identifies and searches the whole circuit inside of `bounds`. Returns `None` if the
+    /// circuit can't be compiled incrementally.
+    pub fn new<W: World>(
+        world: &W,
+        bounds: (BlockPos, BlockPos),
+        options: &CompilerOptions,
+    ) -> Option<IncrementalGraph> {
+        let mut graph = CompileGraph::new();
+        let mut pos_map = FxHashMap::default();
+        for section in non_empty_sections(world, bounds.0, bounds.1) {
+            for pos in section.morton_iter() {
+                if let Some(node) = identify_node(world, pos, false) {
+                    if node.ty == NodeType::Piston {
+                        return None;
+                    }
+                    pos_map.insert(pos, graph.add_node(node));
+                }
+            }
+        }
+
+        let mut incremental = IncrementalGraph {
+            graph,
+            bounds,
+            wire_delay: options.wire_delay,
+            pos_map,
+            footprints: FxHashMap::default(),
+            readers: FxHashMap::default(),
+        };
+        let targets: Vec<NodeIdx> = incremental.graph.node_indices().collect();
+        incremental.search(world, &targets);
+        Some(incremental)
+    }
+
+    /// The graph before any of the passes after the input search ran on it
+    pub fn graph(&self) -> &CompileGraph {
+        &self.graph
+    }
+
+    /// Patches the graph after the blocks at `changed` were changed. Only the nodes in the
+    /// returned patch are up to date, the state of the other nodes is whatever it was when the
+    /// graph was built. Returns `None` if the change can't be patched in, in which case the
+    /// graph can't be used anymore and has to be built from scratch.
+    pub fn update<W: World>(&mut self, world: &W, changed: &[BlockPos]) -> Option<GraphPatch> {
+        let mut patch = GraphPatch::default();
+        let mut targets = FxHashSet::default();
+        for pos in changed {
+            targets.extend(self.pos_map.get(pos).copied());
+            targets.extend(self.readers.get(pos).into_iter().flatten().copied());
+        }
+
+        // Nodes next to a changed block are identified again as well, since whether a diode
+        // faces another diode depends on the block behind it
+        let mut positions = Vec::with_capacity(changed.len() * 7);
+        for &pos in changed {
+            positions.push(pos);
+            positions.extend(BlockFace::values().iter().map(|face| pos.offset(*face)));
+        }
+        // Sorted so that the same change always results in the same graph
+        positions.sort_unstable_by_key(|pos| (pos.x, pos.y, pos.z));
+        positions.dedup();
+
+        let mut sources = FxHashSet::default();
+        for pos in positions {
+            let node = match in_bounds(self.bounds, pos) {
+                true => identify_node(world, pos, false),
+                false => None,
+            };
+            let old = self.pos_map.get(&pos).copied();
+            match (old, node) {
+                (None, None) => {}
+                (Some(idx), Some(node))
+                    if self.graph[idx].ty == node.ty
+                        && self.graph[idx].facing_diode == node.facing_diode =>
+                {
+                    let old = &mut self.graph[idx];
+                    old.block = node.block;
+                    old.state = node.state;
+                    patch.identified.push(idx);
+                }
+                (old, node) => {
+                    if node.as_ref().map(|node| node.ty) == Some(NodeType::Piston) {
+                        return None;
+                    }
+                    if let Some(idx) = old {
+                        sources.extend(self.graph.neighbors_directed(idx, Direction::Incoming));
+                        self.remove_node(idx);
+                        targets.remove(&idx);
+                        patch.removed.push(idx);
+                    }
+                    if let Some(node) = node {
+                        let idx = self.graph.add_node(node);
+                        self.pos_map.insert(pos, idx);
+                        targets.insert(idx);
+                        patch.identified.push(idx);
+                    }
+                    // Links from the node at this position are found by searching the nodes
+                    // that read it
+                    targets.extend(self.readers.get(&pos).into_iter().flatten().copied());
+                }
+            }
+        }
+
+        let mut targets: Vec<NodeIdx> = targets.into_iter().collect();
+        targets.sort_unstable();
+        for &idx in &targets {
+            sources.extend(self.graph.neighbors_directed(idx, Direction::Incoming));
+            let incoming: Vec<_> = self
+                .graph
+                .edges_directed(idx, Direction::Incoming)
+                .map(|edge| edge.id())
+                .collect();
+            for edge in incoming {
+                self.graph.remove_edge(edge);
+            }
+            self.graph[idx].comparator_far_input = None;
+            self.forget_footprint(idx);
+        }
+        self.search(world, &targets);
+        for &idx in &targets {
+            sources.extend(self.graph.neighbors_directed(idx, Direction::Incoming));
+        }
+
+        // Removed nodes can still be in here if they linked to each other
+        patch.sources = sources
+            .into_iter()
+            .filter(|&idx| self.graph.contains_node(idx))
+            .collect();
+        patch.sources.sort_unstable();
+        patch.searched = targets;
+        Some(patch)
+    }
+
+    fn search<W: World>(&mut self, world: &W, targets: &[NodeIdx]) {
+        let world = RecordingWorld::new(world);
+        let mut footprints = Vec::with_capacity(targets.len());
+        search_inputs(&world, &mut self.graph, targets, self.wire_delay, |idx| {
+            footprints.push((idx, world.take_reads()));
+        });
+        for (idx, footprint) in footprints {
+            for &pos in &footprint {
+                self.readers.entry(pos).or_default().push(idx);
+            }
+            self.footprints.insert(idx, footprint);
+        }
+    }
+
+    fn forget_footprint(&mut self, idx: NodeIdx) {
+        let Some(footprint) = self.footprints.remove(&idx) else {
+            return;
+        };
+        for pos in footprint {
+            if let Some(readers) = self.readers.get_mut(&pos) {
+                readers.retain(|&reader| reader != idx);
+                if readers.is_empty() {
+                    self.readers.remove(&pos);
+                }
+            }
+        }
+    }
+
+    fn remove_node(&mut self, idx: NodeIdx) {
+        self.forget_footprint(idx);
+        if let Some((pos, _)) = self.graph[idx].block {
+            self.pos_map.remove(&pos);
+        }
+        self.graph.remove_node(idx);
+    }
+}
+
+#[test]
+fn redpiler_patches_placed_blocks_incrementally() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::Block;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let wire = Block::RedstoneWire {
+        wire: Default::default(),
+    };
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[wire; 2]);
+    world.flush_block_changes();
+
+    let options = CompilerOptions {
+        incremental: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+
+    // A lamp is placed under the wire while the circuit is running
+    let placed_pos = BlockPos::new(12, 7, 10);
+    assert!(redpiler.suspend(&mut world));
+    world.set_block(placed_pos, Block::RedstoneLamp { lit: false });
+    let changed = world.changed_positions();
+    assert_eq!(changed, vec![placed_pos]);
+    redpiler
+        .recompile(&mut world, &changed, Vec::new())
+        .unwrap();
+    assert!(redpiler.get_signal_strength(placed_pos).is_some());
+
+    for lit in [true, false] {
+        redpiler.on_use_block(lever_pos);
+        for _ in 0..4 {
+            redpiler.tick();
+        }
+        redpiler.flush(&mut world);
+        assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit });
+        assert_eq!(world.get_block(placed_pos), Block::RedstoneLamp { lit });
+    }
+}
+
+#[test]
+fn redpiler_patches_broken_blocks_incrementally() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use crate::redpiler::Compiler;
+    use crate::redstone;
+    use mchprs_blocks::blocks::Block;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let wire = Block::RedstoneWire {
+        wire: Default::default(),
+    };
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[wire; 2]);
+    world.flush_block_changes();
+
+    let options = CompilerOptions {
+        incremental: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+    redpiler.on_use_block(lever_pos);
+    redpiler.tick();
+    redpiler.flush(&mut world);
+    world.flush_block_changes();
+    assert_eq!(redpiler.is_powered(lamp_pos), Some(true));
+
+    // The wire next to the lamp is broken while the lever is on
+    let broken_pos = BlockPos::new(12, 8, 10);
+    assert!(redpiler.suspend(&mut world));
+    world.set_block(broken_pos, Block::Air {});
+    let changed = world.changed_positions();
+    redstone::update_surrounding_blocks(&mut world, broken_pos);
+    let ticks = std::mem::take(&mut world.to_be_ticked);
+    redpiler.recompile(&mut world, &changed, ticks).unwrap();
+    assert_eq!(redpiler.get_signal_strength(broken_pos), None);
+    // The rest of the circuit keeps its state
+    assert_eq!(redpiler.is_powered(lever_pos), Some(true));
+
+    let mut lamp_after_ticks = |redpiler: &mut Compiler| {
+        for _ in 0..4 {
+            redpiler.tick();
+        }
+        redpiler.flush(&mut world);
+        world.get_block(lamp_pos)
+    };
+    assert_eq!(
+        lamp_after_ticks(&mut redpiler),
+        Block::RedstoneLamp { lit: false }
+    );
+    // The lever doesn't reach the lamp anymore
+    redpiler.on_use_block(lever_pos);
+    redpiler.on_use_block(lever_pos);
+    assert_eq!(
+        lamp_after_ticks(&mut redpiler),
+        Block::RedstoneLamp { lit: false }
+    );
+}
+
+#[test]
+fn refilled_containers_are_patched_into_redpiler() {
+    use crate::plot::{compile_test_world, redpiler_test_world};
+    use crate::redstone;
+    use mchprs_blocks::block_entities::ContainerType;
+    use mchprs_blocks::blocks::{Block, RedstoneComparator};
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let barrel_pos = BlockPos::new(10, 8, 10);
+    let comparator_pos = BlockPos::new(11, 8, 10);
+    world.set_block(barrel_pos, Block::Barrel {});
+    world.set_block_entity(
+        barrel_pos,
+        BlockEntity::container_with_ss(ContainerType::Barrel, 0),
+    );
+    let comparator = RedstoneComparator {
+        facing: BlockDirection::West,
+        ..Default::default()
+    };
+    world.set_block(comparator_pos, Block::RedstoneComparator { comparator });
+    world.set_block(BlockPos::new(12, 8, 10), Block::RedstoneLamp { lit: false });
+    world.flush_block_changes();
+
+    let options = CompilerOptions {
+        incremental: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+    assert_eq!(redpiler.get_signal_strength(comparator_pos), Some(0));
+
+    // Only the block entity changes, so the barrel has to be marked as changed by hand
+    assert!(redpiler.suspend(&mut world));
+    world.set_block_entity(
+        barrel_pos,
+        BlockEntity::container_with_ss(ContainerType::Barrel, 5),
+    );
+    world.mark_changed(barrel_pos);
+    let changed = world.changed_positions();
+    assert_eq!(changed, vec![barrel_pos]);
+    redstone::update_surrounding_blocks(&mut world, barrel_pos);
+    let ticks = std::mem::take(&mut world.to_be_ticked);
+    redpiler.recompile(&mut world, &changed, ticks).unwrap();
+    for _ in 0..2 {
+        redpiler.tick();
+    }
+    assert_eq!(redpiler.get_signal_strength(comparator_pos), Some(5));
+}
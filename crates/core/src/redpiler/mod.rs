@@ -1,19 +1,41 @@
 mod backend;
+mod cache;
+pub mod clock;
 mod compile_graph;
 // mod debug_graph;
+mod incremental;
+pub mod netlist;
 mod passes;
+mod piston;
+mod replay;
+pub mod script;
+mod task_monitor;
+pub mod verify;
 
-use crate::redpiler::passes::make_default_pass_manager;
+pub use crate::redpiler::passes::PassTiming;
+use crate::redpiler::passes::{make_default_pass_manager, make_incremental_pass_manager};
 use crate::redstone;
-use crate::world::{for_each_block_mut_optimized, World};
+use crate::world::{for_each_block_mut_optimized, for_each_block_optimized, World};
 use backend::JITBackend;
+use compile_graph::{CompileGraph, CompileNode, NodeType};
+use incremental::IncrementalGraph;
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
-use std::time::Instant;
-use tracing::{debug, error, trace, warn};
+use netlist::NetlistFormat;
+use petgraph::Direction;
+use replay::Replay;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, trace, warn};
 
 use self::backend::BackendDispatcher;
+pub use self::backend::{BackendCapabilities, BackendKind, BackendSnapshot};
+pub use self::replay::{InputEvent, Recording};
+pub use self::task_monitor::{CompileProgress, TaskMonitor};
 
 fn bool_to_ss(b: bool) -> u8 {
     match b {
@@ -33,15 +55,71 @@ fn block_powered_mut(block: &mut Block) -> Option<&mut bool> {
         Block::StonePressurePlate { powered } => powered,
         Block::RedstoneLamp { lit } => lit,
         Block::IronTrapdoor { powered, .. } => powered,
+        Block::NoteBlock { powered, .. } => powered,
+        Block::Observer { powered, .. } => powered,
         _ => return None,
     })
 }
 
-#[derive(Default)]
+/// A step of a custom order of optimization passes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PassStep {
+    Pass(String),
+    /// Passes that are repeated until they stop changing the size of the graph
+    Fixpoint(Vec<String>),
+}
+
+/// Parses a comma separated list of passes, in which passes in parentheses form a fixpoint
+/// group, e.g. `dedup-links,(constant-fold,coalesce)`
+fn parse_pass_steps(str: &str) -> Option<Vec<PassStep>> {
+    let mut steps = Vec::new();
+    let mut group: Option<Vec<String>> = None;
+    for name in str.split(',') {
+        let opens = name.starts_with('(');
+        let closes = name.ends_with(')');
+        let name = name.trim_start_matches('(').trim_end_matches(')');
+        if name.is_empty() || (opens && group.is_some()) || (closes && group.is_none() && !opens) {
+            return None;
+        }
+        match &mut group {
+            Some(passes) => passes.push(name.to_owned()),
+            None if opens => group = Some(vec![name.to_owned()]),
+            None => steps.push(PassStep::Pass(name.to_owned())),
+        }
+        if closes {
+            steps.push(PassStep::Fixpoint(group.take()?));
+        }
+    }
+    match group {
+        Some(_) => None,
+        None => Some(steps),
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct CompilerOptions {
     pub optimize: bool,
     pub export: bool,
     pub io_only: bool,
+    /// Only compile the worldedit selection, the rest of the plot stays interpreted
+    pub selection: bool,
+    /// Delay signals through wires with latency annotations
+    pub wire_delay: bool,
+    /// Patch the compiled circuit when blocks are changed instead of stopping redpiler
+    pub incremental: bool,
+    pub backend: BackendKind,
+    /// With `io_only`, the blocks that aren't io blocks are still written to the world this
+    /// often, so the circuit can be watched without sending every change
+    pub visual_sync: Option<Duration>,
+    /// Replaces the default order of the optimization passes
+    pub passes: Option<Vec<PassStep>>,
+    /// Passes that are skipped, by name
+    pub disabled_passes: Vec<String>,
+    /// Writes the compiled circuit as a netlist in this format
+    pub netlist: Option<NetlistFormat>,
+    /// The names of the ports of the plot, which the exported netlist uses for its inputs and
+    /// outputs
+    pub port_names: Vec<(String, BlockPos)>,
 }
 
 impl CompilerOptions {
@@ -53,6 +131,37 @@ impl CompilerOptions {
                 "--optimize" | "-O" => co.optimize = true,
                 "--export" | "-E" => co.export = true,
                 "--io-only" | "-I" => co.io_only = true,
+                "--selection" | "-selection" | "-S" => co.selection = true,
+                "--wire-delay" | "-W" => co.wire_delay = true,
+                "--incremental" | "-N" => co.incremental = true,
+                _ if option.starts_with("--passes=") => {
+                    match parse_pass_steps(&option["--passes=".len()..]) {
+                        Some(steps) => co.passes = Some(steps),
+                        None => warn!("Invalid pass order: {}", option),
+                    }
+                }
+                _ if option.starts_with("--disable-pass=") => {
+                    let names = option["--disable-pass=".len()..].split(',');
+                    co.disabled_passes.extend(names.map(str::to_owned));
+                }
+                _ if option.starts_with("--visual-sync=") => {
+                    match option["--visual-sync=".len()..].parse() {
+                        Ok(millis) => co.visual_sync = Some(Duration::from_millis(millis)),
+                        Err(_) => warn!("Invalid visual sync interval: {}", option),
+                    }
+                }
+                _ if option.starts_with("--netlist=") => {
+                    match option["--netlist=".len()..].parse() {
+                        Ok(format) => co.netlist = Some(format),
+                        Err(()) => warn!("Unrecognized netlist format: {}", option),
+                    }
+                }
+                _ if option.starts_with("--backend=") => {
+                    match option["--backend=".len()..].parse() {
+                        Ok(backend) => co.backend = backend,
+                        Err(()) => warn!("Unrecognized backend: {}", option),
+                    }
+                }
                 // FIXME: use actual error handling
                 _ => warn!("Unrecognized option: {}", option),
             }
@@ -61,11 +170,212 @@ impl CompilerOptions {
     }
 }
 
+/// The compile graph needs a feature that the selected backend is not able to simulate
+#[derive(Debug)]
+pub struct UnsupportedFeatureError {
+    pub backend: &'static str,
+    pub feature: String,
+    pub positions: Vec<BlockPos>,
+}
+
+impl fmt::Display for UnsupportedFeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MAX_SHOWN: usize = 5;
+
+        write!(
+            f,
+            "{} backend does not support {}",
+            self.backend, self.feature
+        )?;
+        if self.positions.is_empty() {
+            return Ok(());
+        }
+        let shown: Vec<String> = self
+            .positions
+            .iter()
+            .take(MAX_SHOWN)
+            .map(|pos| pos.to_string())
+            .collect();
+        write!(f, " at positions {}", shown.join(", "))?;
+        if self.positions.len() > MAX_SHOWN {
+            write!(f, " and {} more", self.positions.len() - MAX_SHOWN)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnsupportedFeatureError {}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Unsupported(UnsupportedFeatureError),
+    /// The [`TaskMonitor`] of the compile asked for it to be cancelled
+    Cancelled,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Unsupported(err) => err.fmt(f),
+            CompileError::Cancelled => write!(f, "the compile was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<UnsupportedFeatureError> for CompileError {
+    fn from(err: UnsupportedFeatureError) -> CompileError {
+        CompileError::Unsupported(err)
+    }
+}
+
+/// The features a compiled circuit needs from a backend to be simulated correctly
+struct GraphRequirements {
+    analog: Vec<BlockPos>,
+    noteblocks: Vec<BlockPos>,
+    pistons: Vec<BlockPos>,
+    observers: Vec<BlockPos>,
+    /// Pistons that move blocks the graph doesn't know about next to other components
+    entangled_pistons: Vec<BlockPos>,
+    node_count: usize,
+}
+
+impl GraphRequirements {
+    fn new<W: World>(graph: &CompileGraph, world: &W, bounds: (BlockPos, BlockPos)) -> Self {
+        let analog = graph
+            .node_weights()
+            .filter(|node| matches!(node.ty, NodeType::Comparator(_) | NodeType::Target))
+            .filter_map(|node| node.block.map(|(pos, _)| pos))
+            .collect();
+        // Note blocks, pistons and observers are found in the world, so that the ones that were
+        // optimized out of the graph are also reported
+        let mut noteblocks = Vec::new();
+        let mut pistons = Vec::new();
+        let mut observers = Vec::new();
+        for_each_block_optimized(world, bounds.0, bounds.1, |pos| {
+            match world.get_block(pos) {
+                Block::NoteBlock { .. } => noteblocks.push(pos),
+                Block::Piston { .. } | Block::StickyPiston { .. } => pistons.push(pos),
+                Block::Observer { .. } => observers.push(pos),
+                _ => {}
+            }
+        });
+        GraphRequirements {
+            analog,
+            noteblocks,
+            pistons,
+            observers,
+            entangled_pistons: piston::find_entangled_pistons(world, bounds),
+            node_count: graph.node_count(),
+        }
+    }
+
+    /// Finds the requirements of `nodes` alone, which are part of a graph with `node_count`
+    /// nodes. This is used for the nodes that are patched into a running circuit, which can
+    /// only be compiled incrementally without `--optimize`, so none of them were removed.
+    fn from_nodes(nodes: &[&CompileNode], node_count: usize) -> Self {
+        let positions_of = |matches: fn(NodeType) -> bool| -> Vec<BlockPos> {
+            nodes
+                .iter()
+                .filter(|node| matches(node.ty))
+                .filter_map(|node| node.block.map(|(pos, _)| pos))
+                .collect()
+        };
+        GraphRequirements {
+            analog: positions_of(|ty| matches!(ty, NodeType::Comparator(_) | NodeType::Target)),
+            noteblocks: positions_of(|ty| ty == NodeType::NoteBlock),
+            pistons: positions_of(|ty| ty == NodeType::Piston),
+            observers: positions_of(|ty| ty == NodeType::Observer),
+            entangled_pistons: Vec::new(),
+            node_count,
+        }
+    }
+
+    fn check(&self, capabilities: &BackendCapabilities) -> Result<(), UnsupportedFeatureError> {
+        let unsupported = |feature: &str, positions: &[BlockPos]| UnsupportedFeatureError {
+            backend: capabilities.name,
+            feature: feature.to_string(),
+            positions: positions.to_vec(),
+        };
+        if !capabilities.analog && !self.analog.is_empty() {
+            return Err(unsupported(
+                "analog signals from comparators and target blocks",
+                &self.analog,
+            ));
+        }
+        if !capabilities.noteblocks && !self.noteblocks.is_empty() {
+            return Err(unsupported("note blocks", &self.noteblocks));
+        }
+        if !capabilities.pistons && !self.pistons.is_empty() {
+            return Err(unsupported("pistons", &self.pistons));
+        }
+        if !capabilities.observers && !self.observers.is_empty() {
+            return Err(unsupported("observers", &self.observers));
+        }
+        if !self.entangled_pistons.is_empty() {
+            return Err(unsupported(
+                "pistons that move blocks next to other components",
+                &self.entangled_pistons,
+            ));
+        }
+        if self.node_count > capabilities.max_nodes {
+            let feature = format!(
+                "more than {} nodes (the circuit has {})",
+                capabilities.max_nodes, self.node_count
+            );
+            return Err(unsupported(&feature, &[]));
+        }
+        Ok(())
+    }
+}
+
+/// Picks the most preferred backend that is able to simulate the circuit
+fn select_backend(requirements: &GraphRequirements) -> BackendDispatcher {
+    let analog_percent = if requirements.node_count == 0 {
+        0.0
+    } else {
+        requirements.analog.len() as f32 / requirements.node_count as f32 * 100.0
+    };
+    for backend in BackendDispatcher::candidates() {
+        let capabilities = backend.capabilities();
+        match requirements.check(&capabilities) {
+            Ok(()) => {
+                info!(
+                    "Selected the {} backend for {} nodes ({:.1}% analog, {} note blocks)",
+                    capabilities.name,
+                    requirements.node_count,
+                    analog_percent,
+                    requirements.noteblocks.len()
+                );
+                return backend;
+            }
+            Err(err) => debug!("Not selecting backend: {}", err),
+        }
+    }
+    // No backend can simulate the circuit, the capability check will report why
+    BackendDispatcher::default()
+}
+
 #[derive(Default)]
 pub struct Compiler {
     is_active: bool,
     jit: Option<BackendDispatcher>,
     options: CompilerOptions,
+    bounds: (BlockPos, BlockPos),
+    /// The graph that is patched when blocks change, if the circuit was compiled incrementally
+    incremental: Option<IncrementalGraph>,
+    pass_timings: Vec<PassTiming>,
+    /// Where the compiled graph is saved, so that it can be loaded instead of being compiled
+    /// again if the circuit hasn't changed
+    cache_path: Option<PathBuf>,
+    /// The inputs that are being recorded
+    recording: Option<Recording>,
+    replay: Option<Replay>,
+    /// Every input given to the circuit since they were last taken, including replayed ones
+    watched_inputs: Option<Vec<InputEvent>>,
+    /// When the whole circuit was last written to the world with `visual_sync`
+    last_visual_sync: Option<Instant>,
 }
 
 impl Compiler {
@@ -73,6 +383,36 @@ impl Compiler {
         self.is_active
     }
 
+    pub fn set_cache_path(&mut self, path: Option<PathBuf>) {
+        self.cache_path = path;
+    }
+
+    /// Returns true if redpiler is running and `pos` is inside of the compiled bounds
+    pub fn is_compiled(&self, pos: BlockPos) -> bool {
+        let (min, max) = self.bounds;
+        self.is_active
+            && (min.x..=max.x).contains(&pos.x)
+            && (min.y..=max.y).contains(&pos.y)
+            && (min.z..=max.z).contains(&pos.z)
+    }
+
+    pub fn bounds(&self) -> (BlockPos, BlockPos) {
+        self.bounds
+    }
+
+    /// How long each pass took in the last compile
+    pub fn pass_timings(&self) -> &[PassTiming] {
+        &self.pass_timings
+    }
+
+    /// The name of the backend that is running the circuit
+    pub fn backend_name(&self) -> Option<&'static str> {
+        match (self.is_active, &self.jit) {
+            (true, Some(jit)) => Some(jit.capabilities().name),
+            _ => None,
+        }
+    }
+
     pub fn current_flags(&self) -> Option<&CompilerOptions> {
         match self.is_active {
             true => Some(&self.options),
@@ -80,47 +420,185 @@ impl Compiler {
         }
     }
 
-    /// Use just-in-time compilation with a `JITBackend` such as `CraneliftBackend` or `LLVMBackend`.
-    /// Requires recompilation to take effect.
-    pub fn use_jit(&mut self, jit: BackendDispatcher) {
-        self.jit = Some(jit);
+    /// Compiles the circuit inside of `bounds`. If the backend is not able to simulate the
+    /// circuit, redpiler stays inactive and the world is left untouched.
+    pub fn compile<W: World>(
+        &mut self,
+        world: &mut W,
+        bounds: (BlockPos, BlockPos),
+        options: CompilerOptions,
+        ticks: Vec<TickEntry>,
+    ) -> Result<(), UnsupportedFeatureError> {
+        let monitor = TaskMonitor::default();
+        self.compile_with_monitor(world, bounds, options, ticks, &monitor)
+            .map_err(|err| match err {
+                CompileError::Unsupported(err) => err,
+                CompileError::Cancelled => unreachable!("compile without a monitor was cancelled"),
+            })
     }
 
-    pub fn compile<W: World>(
+    /// Like [`Compiler::compile`], but reports the progress of the compile to `monitor`. If the
+    /// monitor cancels the compile, redpiler stays inactive and the world is left untouched.
+    pub fn compile_with_monitor<W: World>(
         &mut self,
         world: &mut W,
         bounds: (BlockPos, BlockPos),
         options: CompilerOptions,
         ticks: Vec<TickEntry>,
-    ) {
+        monitor: &TaskMonitor<'_>,
+    ) -> Result<(), CompileError> {
         debug!("Starting compile");
         let start = Instant::now();
 
-        self.is_active = true;
+        self.incremental = None;
+        let input = CompilerInput {
+            world,
+            bounds,
+            monitor,
+        };
+        let incremental = match incremental::is_supported(&options) {
+            true => IncrementalGraph::new(world, bounds, &options),
+            false => None,
+        };
+        let (graph, pass_timings) = match &incremental {
+            Some(incremental) => {
+                let mut graph = incremental.graph().clone();
+                let timings = make_incremental_pass_manager::<W>()
+                    .run_passes_on(&mut graph, &options, &input);
+                (graph, timings)
+            }
+            None => self.run_default_passes(&options, &input),
+        };
+        self.pass_timings = pass_timings;
+        if monitor.is_cancelled() {
+            debug!("Compile cancelled after {:?}", start.elapsed());
+            return Err(CompileError::Cancelled);
+        }
 
-        let input = CompilerInput { world, bounds };
-        let pass_manager = make_default_pass_manager::<W>();
-        let graph = pass_manager.run_passes(&options, &input);
+        let requirements = GraphRequirements::new(&graph, world, bounds);
+        self.start_backend(graph, &requirements, options, ticks)?;
+        self.incremental = incremental;
+        self.bounds = bounds;
+        debug!("Compile completed in {:?}", start.elapsed());
+        Ok(())
+    }
 
-        // TODO: Remove this once there is proper backend switching
-        if self.jit.is_none() {
-            self.use_jit(Default::default());
+    /// Runs the passes on a new graph, unless the same graph was saved in the cache before. No
+    /// passes ran if the graph was loaded from the cache.
+    fn run_default_passes<W: World>(
+        &self,
+        options: &CompilerOptions,
+        input: &CompilerInput<'_, W>,
+    ) -> (CompileGraph, Vec<PassTiming>) {
+        let cache = match &self.cache_path {
+            Some(path) if cache::is_supported(options) => {
+                Some((path, cache::key(input.world, input.bounds, options)))
+            }
+            _ => None,
+        };
+        if let Some((path, key)) = cache {
+            if let Some(graph) = cache::load(path, key) {
+                debug!("Loaded the compiled graph from {}", path.display());
+                return (graph, Vec::new());
+            }
         }
 
-        if let Some(jit) = &mut self.jit {
-            trace!("Compiling backend");
-            let start = Instant::now();
-            jit.compile(graph, ticks);
-            trace!("Backend compiled in {:?}", start.elapsed());
-        } else {
-            error!("Cannot compile without JIT variant selected");
+        let mut graph = CompileGraph::new();
+        let timings = make_default_pass_manager::<W>().run_passes_on(&mut graph, options, input);
+        if let Some((path, key)) = cache {
+            if !input.monitor.is_cancelled() {
+                cache::save(path, key, &graph);
+            }
+        }
+        (graph, timings)
+    }
+
+    /// Starts the circuit again after the blocks at `changed` were changed while it was
+    /// suspended with [`Compiler::suspend`]. Only the part of the graph around the changed
+    /// blocks is identified and searched again and patched into the backend, unless the change
+    /// can't be patched in.
+    pub fn recompile<W: World>(
+        &mut self,
+        world: &mut W,
+        changed: &[BlockPos],
+        ticks: Vec<TickEntry>,
+    ) -> Result<(), UnsupportedFeatureError> {
+        let options = mem::take(&mut self.options);
+        let bounds = self.bounds;
+        let (Some(mut incremental), Some(mut jit)) = (self.incremental.take(), self.jit.take())
+        else {
+            return self.compile(world, bounds, options, ticks);
+        };
+
+        debug!(
+            "Starting incremental compile of {} changed blocks",
+            changed.len()
+        );
+        let start = Instant::now();
+        let Some(patch) = incremental.update(world, changed) else {
+            debug!("The changed blocks could not be patched in, compiling from scratch");
+            return self.compile(world, bounds, options, ticks);
+        };
+
+        let graph = incremental.graph();
+        let identified: Vec<&CompileNode> =
+            patch.identified.iter().map(|&idx| &graph[idx]).collect();
+        GraphRequirements::from_nodes(&identified, graph.node_count())
+            .check(&jit.capabilities())?;
+        if !jit.patch(graph, &patch, &ticks) {
+            debug!("The backend can't be patched, compiling from scratch");
+            return self.compile(world, bounds, options, ticks);
         }
 
+        self.jit = Some(jit);
+        self.is_active = true;
         self.options = options;
-        debug!("Compile completed in {:?}", start.elapsed());
+        self.incremental = Some(incremental);
+        debug!("Incremental compile completed in {:?}", start.elapsed());
+        Ok(())
+    }
+
+    fn start_backend(
+        &mut self,
+        graph: CompileGraph,
+        requirements: &GraphRequirements,
+        options: CompilerOptions,
+        ticks: Vec<TickEntry>,
+    ) -> Result<(), UnsupportedFeatureError> {
+        let mut jit = match BackendDispatcher::new(options.backend) {
+            Some(jit) => jit,
+            None => select_backend(requirements),
+        };
+        requirements.check(&jit.capabilities())?;
+
+        trace!("Compiling backend");
+        let start = Instant::now();
+        jit.compile(graph, ticks);
+        trace!("Backend compiled in {:?}", start.elapsed());
+
+        self.jit = Some(jit);
+        self.is_active = true;
+        self.options = options;
+        Ok(())
+    }
+
+    /// Stops the circuit so that blocks can be changed by a player. If it was compiled
+    /// incrementally, the graph is kept and [`Compiler::recompile`] has to be called after the
+    /// change. Otherwise nothing is done and false is returned.
+    pub fn suspend<W: World>(&mut self, world: &mut W) -> bool {
+        if !self.is_active || self.incremental.is_none() {
+            return false;
+        }
+        self.is_active = false;
+        if let Some(jit) = &mut self.jit {
+            jit.suspend(world, self.options.io_only);
+        }
+        true
     }
 
     pub fn reset<W: World>(&mut self, world: &mut W, bounds: (BlockPos, BlockPos)) {
+        self.incremental = None;
+        self.replay = None;
         if self.is_active {
             self.is_active = false;
             if let Some(jit) = &mut self.jit {
@@ -140,6 +618,15 @@ impl Compiler {
         self.options = Default::default();
     }
 
+    /// Throw away the backend without writing its state back into the world
+    pub fn discard(&mut self) {
+        self.is_active = false;
+        self.incremental = None;
+        self.replay = None;
+        self.jit = None;
+        self.options = Default::default();
+    }
+
     fn backend(&mut self) -> &mut BackendDispatcher {
         assert!(
             self.is_active,
@@ -153,22 +640,161 @@ impl Compiler {
     }
 
     pub fn tick(&mut self) {
+        if let Some(replay) = &mut self.replay {
+            let events = replay.next_tick();
+            if replay.is_finished() {
+                self.replay = None;
+            }
+            for event in events {
+                self.apply_input(event);
+            }
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.length += 1;
+        }
         self.backend().tick();
     }
 
     pub fn on_use_block(&mut self, pos: BlockPos) {
-        self.backend().on_use_block(pos);
+        self.input(InputEvent::Use(pos));
     }
 
     pub fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
-        self.backend().set_pressure_plate(pos, powered);
+        self.input(InputEvent::PressurePlate(pos, powered));
+    }
+
+    pub fn hit_target(&mut self, pos: BlockPos, power: u8, ticks: u32) {
+        self.input(InputEvent::HitTarget { pos, power, ticks });
+    }
+
+    /// Gives an input to the circuit, recording it if inputs are being recorded
+    pub fn input(&mut self, event: InputEvent) {
+        if let Some(recording) = &mut self.recording {
+            recording.record(event);
+        }
+        self.apply_input(event);
+    }
+
+    fn apply_input(&mut self, event: InputEvent) {
+        if let Some(inputs) = &mut self.watched_inputs {
+            inputs.push(event);
+        }
+        let backend = self.backend();
+        match event {
+            InputEvent::Use(pos) => backend.on_use_block(pos),
+            InputEvent::PressurePlate(pos, powered) => backend.set_pressure_plate(pos, powered),
+            InputEvent::HitTarget { pos, power, ticks } => backend.hit_target(pos, power, ticks),
+        }
+    }
+
+    /// Starts or stops keeping the inputs of the circuit until they are taken
+    pub fn watch_inputs(&mut self, watch: bool) {
+        self.watched_inputs = watch.then(Vec::new);
+    }
+
+    pub fn take_watched_inputs(&mut self) -> Vec<InputEvent> {
+        self.watched_inputs
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Starts recording the inputs of the circuit, throwing away a previous recording
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording::default());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Gives the circuit the inputs of `recording` on the ticks they were recorded on, starting
+    /// with the next tick. The replay stops when redpiler is reset.
+    pub fn start_replay(&mut self, recording: Recording) {
+        self.replay = Some(Replay::new(recording));
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
     }
 
     pub fn flush<W: World>(&mut self, world: &mut W) {
-        let io_only = self.options.io_only;
+        let mut io_only = self.options.io_only;
+        if let Some(interval) = self.options.visual_sync.filter(|_| io_only) {
+            let due = self
+                .last_visual_sync
+                .map_or(true, |last| last.elapsed() >= interval);
+            if due {
+                self.last_visual_sync = Some(Instant::now());
+                io_only = false;
+            }
+        }
         self.backend().flush(world, io_only);
     }
 
+    /// Returns the live signal strength of the node at `pos` while redpiler is running
+    pub fn get_signal_strength(&self, pos: BlockPos) -> Option<u8> {
+        if !self.is_active {
+            return None;
+        }
+        self.jit.as_ref()?.get_signal_strength(pos)
+    }
+
+    /// Returns whether the node at `pos` is powered while redpiler is running
+    pub fn is_powered(&self, pos: BlockPos) -> Option<bool> {
+        if !self.is_active {
+            return None;
+        }
+        self.jit.as_ref()?.is_powered(pos)
+    }
+
+    /// Takes a snapshot of the full state of the running circuit
+    pub fn snapshot(&self) -> Option<BackendSnapshot> {
+        if !self.is_active {
+            return None;
+        }
+        Some(self.jit.as_ref()?.snapshot())
+    }
+
+    /// Restores the state of the running circuit from a snapshot and writes it to the world
+    pub fn restore<W: World>(
+        &mut self,
+        world: &mut W,
+        snapshot: &BackendSnapshot,
+    ) -> Result<(), String> {
+        if !self.is_active {
+            return Err("redpiler is not running".to_owned());
+        }
+        self.backend().restore(snapshot)?;
+        self.backend().flush(world, false);
+        Ok(())
+    }
+
+    /// Returns the pending ticks of the running circuit, see [`JITBackend::scheduled_ticks`]
+    pub fn scheduled_ticks(&self) -> Option<Vec<(BlockPos, u32)>> {
+        if !self.is_active {
+            return None;
+        }
+        Some(self.jit.as_ref()?.scheduled_ticks())
+    }
+
+    /// Exports a part of the running circuit as a DOT graph, see [`JITBackend::subgraph_dot`]
+    pub fn subgraph_dot(
+        &self,
+        roots: &[BlockPos],
+        max_links: Option<usize>,
+        incoming: bool,
+    ) -> Option<String> {
+        if !self.is_active {
+            return None;
+        }
+        Some(self.jit.as_ref()?.subgraph_dot(roots, max_links, incoming))
+    }
+
     pub fn inspect(&mut self, pos: BlockPos) {
         if let Some(backend) = &mut self.jit {
             backend.inspect(pos);
@@ -178,7 +804,272 @@ impl Compiler {
     }
 }
 
+/// Returns the pass names in `options` that don't refer to a pass that can be used that way,
+/// along with the names of the passes that can be reordered
+pub fn check_pass_names<'o, W: World>(
+    options: &'o CompilerOptions,
+) -> (Vec<&'o str>, Vec<&'static str>) {
+    let passes = make_default_pass_manager::<W>();
+    (passes.unknown_passes(options), passes.optimization_passes())
+}
+
+/// Returns the positions of every component that can influence the component at `output`,
+/// including `output` itself and the wires between them, or `None` if there is no component at
+/// `output`.
+pub fn cone_of_influence<W: World>(
+    world: &W,
+    bounds: (BlockPos, BlockPos),
+    output: BlockPos,
+) -> Option<Vec<BlockPos>> {
+    // Wires are only part of the graph when it is not optimized
+    let options = CompilerOptions::default();
+    let monitor = TaskMonitor::default();
+    let input = CompilerInput {
+        world,
+        bounds,
+        monitor: &monitor,
+    };
+    let graph = make_default_pass_manager::<W>().run_passes(&options, &input);
+
+    let output_idx = graph
+        .node_indices()
+        .find(|&idx| matches!(graph[idx].block, Some((pos, _)) if pos == output))?;
+    let mut cone = HashSet::new();
+    let mut stack = vec![output_idx];
+    while let Some(idx) = stack.pop() {
+        if cone.insert(idx) {
+            stack.extend(graph.neighbors_directed(idx, Direction::Incoming));
+        }
+    }
+
+    let mut positions = Vec::new();
+    for idx in graph.node_indices() {
+        let node = &graph[idx];
+        let Some((pos, _)) = node.block else {
+            continue;
+        };
+        // Wires never power anything in the graph, they only show up as targets of the
+        // components that power them. Wires powered by the output lead away from the cone.
+        let included = cone.contains(&idx)
+            || (node.ty == NodeType::Wire
+                && graph
+                    .neighbors_directed(idx, Direction::Incoming)
+                    .any(|source| source != output_idx && cone.contains(&source)));
+        if !included {
+            continue;
+        }
+        positions.push(pos);
+        if node.ty == NodeType::Piston {
+            if let Some(carried) = piston::carried_block(world, pos) {
+                positions.push(carried.retracted_pos);
+                positions.push(carried.extended_pos);
+            }
+        }
+    }
+    Some(positions)
+}
+
 pub struct CompilerInput<'w, W: World> {
     pub world: &'w W,
     pub bounds: (BlockPos, BlockPos),
+    pub monitor: &'w TaskMonitor<'w>,
+}
+
+#[test]
+fn redpiler_runs_custom_pass_order() {
+    use crate::plot::{compile_test_world, redpiler_test_world, PlotWorld};
+
+    let options = CompilerOptions::parse(
+        "-O --passes=dedup-links,(constant-fold,coalesce) --disable-pass=unreachable-output",
+    );
+    let fixpoint = vec!["constant-fold".to_owned(), "coalesce".to_owned()];
+    assert_eq!(
+        options.passes,
+        Some(vec![
+            PassStep::Pass("dedup-links".to_owned()),
+            PassStep::Fixpoint(fixpoint),
+        ])
+    );
+    let (unknown, _) = check_pass_names::<PlotWorld>(&options);
+    assert!(unknown.is_empty());
+    let invalid = CompilerOptions::parse("-O --passes=input-search,(coalesce --disable-pass=foo");
+    assert_eq!(invalid.passes, None);
+    let (unknown, _) = check_pass_names::<PlotWorld>(&invalid);
+    assert_eq!(unknown, vec!["foo"]);
+
+    let mut world = redpiler_test_world();
+    world.set_block(BlockPos::new(10, 8, 10), Block::RedstoneBlock {});
+    world.set_block(BlockPos::new(11, 8, 10), Block::RedstoneLamp { lit: false });
+    let redpiler = compile_test_world(&mut world, options);
+    let passes: Vec<&str> = redpiler
+        .pass_timings()
+        .iter()
+        .map(|timing| timing.pass)
+        .collect();
+    assert_eq!(
+        &passes[..4],
+        [
+            "identify-nodes",
+            "input-search",
+            "clamp-weights",
+            "dedup-links"
+        ]
+    );
+    assert!(!passes.contains(&"unreachable-output"));
+    // The fixpoint group runs at least twice, the second time without changing the graph
+    assert!(passes[4..].starts_with(&["constant-fold", "coalesce", "constant-fold", "coalesce"]));
+}
+
+#[test]
+fn redpiler_compiles_selection() {
+    use crate::plot::redpiler_test_world;
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    // A repeater chain where only the second half is compiled, so the first compiled
+    // repeater is powered from outside of the bounds.
+    let mut world = redpiler_test_world();
+    world.set_block(BlockPos::new(10, 8, 10), Block::RedstoneBlock {});
+    for x in 11..13 {
+        let repeater = RedstoneRepeater {
+            delay: 1,
+            facing: BlockDirection::West,
+            locked: false,
+            powered: false,
+        };
+        world.set_block(
+            BlockPos::new(x, 8, 10),
+            Block::RedstoneRepeater { repeater },
+        );
+    }
+    world.set_block(BlockPos::new(13, 8, 10), Block::RedstoneLamp { lit: false });
+
+    let mut redpiler = Compiler::default();
+    let bounds = (BlockPos::new(12, 8, 10), BlockPos::new(13, 8, 10));
+    redpiler
+        .compile(&mut world, bounds, Default::default(), Vec::new())
+        .unwrap();
+    assert!(redpiler.is_compiled(BlockPos::new(12, 8, 10)));
+    assert!(!redpiler.is_compiled(BlockPos::new(11, 8, 10)));
+    assert_eq!(redpiler.get_signal_strength(BlockPos::new(11, 8, 10)), None);
+}
+
+#[test]
+fn redpiler_io_only_reset_writes_back_circuit_state() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let repeater = RedstoneRepeater {
+        delay: 1,
+        facing: BlockDirection::West,
+        locked: false,
+        powered: false,
+    };
+    let repeater = Block::RedstoneRepeater { repeater };
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[repeater; 2]);
+
+    let options = CompilerOptions {
+        io_only: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+
+    // Stop halfway through the chain, the repeaters were never flushed to the world
+    redpiler.on_use_block(lever_pos);
+    redpiler.tick();
+    redpiler.flush(&mut world);
+    let bounds = world.get_corners();
+    redpiler.reset(&mut world, bounds);
+
+    let powered = RedstoneRepeater {
+        powered: true,
+        ..repeater
+    };
+    assert_eq!(
+        world.get_block(BlockPos::new(11, 8, 10)),
+        Block::RedstoneRepeater { repeater: powered }
+    );
+    assert_eq!(
+        world.get_block(BlockPos::new(12, 8, 10)),
+        Block::RedstoneRepeater { repeater }
+    );
+    assert!(world.pending_tick_at(BlockPos::new(12, 8, 10)));
+
+    // The pending tick carries the signal on to the lamp
+    for _ in 0..2 {
+        world.tick_interpreted();
+    }
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+}
+
+#[test]
+fn extract_copies_cone_of_influence() {
+    use crate::plot::worldedit;
+    use crate::plot::{build_lever_line, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    // A lever powering a lamp through a wire and a repeater, next to an unrelated lever
+    // powering another lamp
+    let mut world = redpiler_test_world();
+    let wire = Block::RedstoneWire {
+        wire: Default::default(),
+    };
+    let repeater = RedstoneRepeater {
+        delay: 1,
+        facing: BlockDirection::West,
+        locked: false,
+        powered: false,
+    };
+    let repeater = Block::RedstoneRepeater { repeater };
+    build_lever_line(&mut world, BlockPos::new(10, 8, 10), &[wire, repeater]);
+    build_lever_line(&mut world, BlockPos::new(10, 8, 12), &[]);
+
+    let bounds = world.get_corners();
+    let mut cone = cone_of_influence(&world, bounds, BlockPos::new(13, 8, 10)).unwrap();
+    cone.sort_by_key(|pos| pos.x);
+    let expected: Vec<BlockPos> = (10..14).map(|x| BlockPos::new(x, 8, 10)).collect();
+    assert_eq!(cone, expected);
+    assert!(cone_of_influence(&world, bounds, BlockPos::new(13, 9, 10)).is_none());
+
+    // The floor below the components is copied along with them
+    let cb = worldedit::create_extract_clipboard(&mut world, BlockPos::new(10, 8, 10), &cone);
+    assert_eq!((cb.size_x, cb.size_y, cb.size_z), (4, 2, 1));
+}
+
+#[test]
+fn io_only_visual_sync_writes_the_whole_circuit() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneWire;
+
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let wire_pos = BlockPos::new(11, 8, 10);
+    let lamp_pos = BlockPos::new(12, 8, 10);
+    for visual_sync in [None, Some(Duration::ZERO)] {
+        let mut world = redpiler_test_world();
+        let wire = Block::RedstoneWire {
+            wire: RedstoneWire::default(),
+        };
+        build_lever_line(&mut world, lever_pos, &[wire]);
+
+        let options = CompilerOptions {
+            io_only: true,
+            visual_sync,
+            ..Default::default()
+        };
+        let mut redpiler = compile_test_world(&mut world, options);
+        redpiler.on_use_block(lever_pos);
+        redpiler.tick();
+        redpiler.flush(&mut world);
+
+        assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+        let Block::RedstoneWire { wire } = world.get_block(wire_pos) else {
+            panic!("the wire is gone");
+        };
+        let expected = if visual_sync.is_some() { 15 } else { 0 };
+        assert_eq!(wire.power, expected);
+    }
 }
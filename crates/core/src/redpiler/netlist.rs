@@ -0,0 +1,1038 @@
+//! Lowers a compile graph to a netlist of gates and latches, which can be written as structural
+//! Verilog or as BLIF, so that circuits can be run through synthesis and verification tools.
+//!
+//! One clock cycle of the netlist is one redstone tick. Every node only sees the state the
+//! other nodes had at the end of the previous tick, so circuits that depend on the order of
+//! ticks that happen at the same time can behave differently. Signals are boolean: comparators
+//! can't be exported, and target blocks are inputs that are either on or off.
+
+use super::compile_graph::{CompileGraph, LinkType, NodeIdx, NodeType};
+use mchprs_blocks::BlockPos;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::FxHashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The name of the clock input, which ticks once per redstone tick
+const CLOCK: &str = "clk";
+
+/// Port names that can't be used as identifiers in Verilog
+const RESERVED: &[&str] = &[
+    CLOCK,
+    "always",
+    "and",
+    "assign",
+    "begin",
+    "buf",
+    "case",
+    "default",
+    "else",
+    "end",
+    "endmodule",
+    "for",
+    "if",
+    "initial",
+    "inout",
+    "input",
+    "integer",
+    "module",
+    "nand",
+    "negedge",
+    "nor",
+    "not",
+    "or",
+    "output",
+    "posedge",
+    "reg",
+    "wire",
+    "xnor",
+    "xor",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetlistFormat {
+    Verilog,
+    Blif,
+}
+
+impl NetlistFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            NetlistFormat::Verilog => "v",
+            NetlistFormat::Blif => "blif",
+        }
+    }
+}
+
+impl FromStr for NetlistFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "verilog" | "v" => Ok(NetlistFormat::Verilog),
+            "blif" => Ok(NetlistFormat::Blif),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A gate with the truth table of a BLIF `.names` line: the output is on if the inputs match any
+/// of the rows, where `None` matches both values
+#[derive(Debug, Clone)]
+struct Gate {
+    inputs: Vec<String>,
+    output: String,
+    rows: Vec<Vec<Option<bool>>>,
+}
+
+impl Gate {
+    fn constant(output: String, value: bool) -> Gate {
+        Gate {
+            inputs: Vec::new(),
+            output,
+            rows: if value { vec![Vec::new()] } else { Vec::new() },
+        }
+    }
+
+    fn or(output: String, inputs: Vec<String>) -> Gate {
+        let rows = (0..inputs.len())
+            .map(|i| {
+                (0..inputs.len())
+                    .map(|j| (i == j).then_some(true))
+                    .collect()
+            })
+            .collect();
+        Gate {
+            inputs,
+            output,
+            rows,
+        }
+    }
+
+    /// A gate with the truth table of `f`, which is given the values of the inputs
+    fn from_fn(output: String, inputs: Vec<String>, f: impl Fn(&[bool]) -> bool) -> Gate {
+        let mut rows = Vec::new();
+        for bits in 0..1u32 << inputs.len() {
+            let values: Vec<bool> = (0..inputs.len()).map(|i| bits >> i & 1 != 0).collect();
+            if f(&values) {
+                rows.push(values.into_iter().map(Some).collect());
+            }
+        }
+        Gate {
+            inputs,
+            output,
+            rows,
+        }
+    }
+
+    fn eval(&self, value: impl Fn(&str) -> bool) -> bool {
+        self.rows.iter().any(|row| {
+            row.iter()
+                .zip(&self.inputs)
+                .all(|(bit, input)| bit.map_or(true, |bit| bit == value(input)))
+        })
+    }
+}
+
+/// A register that takes the value of `input` every tick
+#[derive(Debug, Clone)]
+struct Latch {
+    input: String,
+    output: String,
+    init: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Netlist {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    gates: Vec<Gate>,
+    latches: Vec<Latch>,
+}
+
+/// One redstone tick of a repeater with a delay of `delay`, like the direct backend ticks it.
+/// `pending` is the number of ticks until the scheduled tick of the repeater, or 0 if it doesn't
+/// have one. A tick that is scheduled now happens `delay` ticks later, where the tick that
+/// notices the change counts as the first. Returns the new `powered` and `pending`.
+fn repeater_tick(delay: u8, input: bool, locked: bool, powered: bool, pending: u8) -> (bool, u8) {
+    let (mut powered, mut pending) = (powered, pending);
+    if !locked && pending == 0 && input != powered {
+        pending = delay;
+    }
+    if pending > 0 {
+        pending -= 1;
+        // Locked repeaters ignore their scheduled tick
+        if pending == 0 && !locked {
+            if powered && !input {
+                powered = false;
+            } else if !powered {
+                powered = true;
+                // Short pulses are extended to the delay of the repeater
+                if !input {
+                    pending = delay;
+                }
+            }
+        }
+    }
+    (powered, pending)
+}
+
+/// Turns a port name into an identifier that Verilog and BLIF both accept
+fn sanitize(name: &str) -> String {
+    let name = name.replace('-', "_");
+    if RESERVED.contains(&name.as_str()) || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("p_{}", name)
+    } else {
+        name
+    }
+}
+
+fn type_name(ty: NodeType) -> &'static str {
+    match ty {
+        NodeType::Lever => "lever",
+        NodeType::Button => "button",
+        NodeType::PressurePlate => "pressure_plate",
+        NodeType::Target => "target",
+        NodeType::Lamp => "lamp",
+        NodeType::Trapdoor => "trapdoor",
+        NodeType::Piston => "piston",
+        NodeType::NoteBlock => "note_block",
+        _ => "node",
+    }
+}
+
+/// Names blocks without a port by their type and position, like `lever_3_9_m4`
+fn position_name(ty: NodeType, pos: BlockPos) -> String {
+    let coord = |c: i32| match c < 0 {
+        true => format!("m{}", -c),
+        false => c.to_string(),
+    };
+    format!(
+        "{}_{}_{}_{}",
+        type_name(ty),
+        coord(pos.x),
+        coord(pos.y),
+        coord(pos.z)
+    )
+}
+
+fn is_input(ty: NodeType) -> bool {
+    matches!(
+        ty,
+        NodeType::Lever | NodeType::Button | NodeType::PressurePlate | NodeType::Target
+    )
+}
+
+/// Lowers `graph` to a netlist. Inputs and outputs are named after their port in `port_names`,
+/// or after their position.
+pub fn lower(
+    graph: &CompileGraph,
+    port_names: &[(String, BlockPos)],
+    name: &str,
+) -> Result<Netlist, String> {
+    let port_names: FxHashMap<BlockPos, &str> = port_names
+        .iter()
+        .map(|(name, pos)| (*pos, name.as_str()))
+        .collect();
+    let ids: FxHashMap<NodeIdx, usize> = graph
+        .node_indices()
+        .enumerate()
+        .map(|(id, idx)| (idx, id))
+        .collect();
+    let signal = |idx: NodeIdx| -> String {
+        let node = &graph[idx];
+        let port = node.block.and_then(|(pos, _)| port_names.get(&pos));
+        match (port, node.block) {
+            (Some(port), _) => sanitize(port),
+            (None, Some((pos, _))) if is_input(node.ty) || node.ty.is_output() => {
+                position_name(node.ty, pos)
+            }
+            _ => format!("_n{}", ids[&idx]),
+        }
+    };
+    // The nodes that power `idx` through links of type `ty`
+    let sources = |idx: NodeIdx, ty: LinkType| -> Vec<String> {
+        graph
+            .edges_directed(idx, Direction::Incoming)
+            .filter(|edge| edge.weight().ty == ty)
+            .filter(|edge| {
+                let source = &graph[edge.source()];
+                let ss = match source.ty {
+                    NodeType::Constant => source.state.output_strength,
+                    _ => 15,
+                };
+                ss > edge.weight().ss
+            })
+            .map(|edge| signal(edge.source()))
+            .collect()
+    };
+
+    let mut netlist = Netlist {
+        name: sanitize(name),
+        ..Default::default()
+    };
+    for idx in graph.node_indices() {
+        let node = &graph[idx];
+        let out = signal(idx);
+        let internal = format!("_n{}", ids[&idx]);
+        let input = format!("{}_in", internal);
+        let powered = node.state.powered;
+        let named = node
+            .block
+            .map_or(false, |(pos, _)| port_names.contains_key(&pos));
+        if node.ty.is_output() || named && !is_input(node.ty) {
+            netlist.outputs.push(out.clone());
+        }
+        match node.ty {
+            NodeType::Lever | NodeType::Button | NodeType::PressurePlate | NodeType::Target => {
+                netlist.inputs.push(out);
+            }
+            NodeType::Constant => {
+                netlist
+                    .gates
+                    .push(Gate::constant(out, node.state.output_strength > 0));
+            }
+            NodeType::Comparator(_) => {
+                let pos = node.block.map(|(pos, _)| pos.to_string());
+                return Err(format!(
+                    "The comparator at {} needs analog signals, which netlists can't represent.",
+                    pos.as_deref().unwrap_or("an unknown position")
+                ));
+            }
+            NodeType::Trapdoor | NodeType::NoteBlock | NodeType::Wire => {
+                netlist
+                    .gates
+                    .push(Gate::or(out, sources(idx, LinkType::Default)));
+            }
+            NodeType::Piston => {
+                netlist
+                    .gates
+                    .push(Gate::or(input.clone(), sources(idx, LinkType::Default)));
+                netlist.latches.push(Latch {
+                    input,
+                    output: out,
+                    init: powered,
+                });
+            }
+            NodeType::Torch => {
+                let next = format!("{}_next", internal);
+                netlist
+                    .gates
+                    .push(Gate::or(input.clone(), sources(idx, LinkType::Default)));
+                netlist
+                    .gates
+                    .push(Gate::from_fn(next.clone(), vec![input], |v| !v[0]));
+                netlist.latches.push(Latch {
+                    input: next,
+                    output: out,
+                    init: powered,
+                });
+            }
+            NodeType::Lamp => {
+                // Lamps turn on right away and turn off two ticks after they lose power
+                let (d1, d2) = (format!("{}_d1", internal), format!("{}_d2", internal));
+                netlist
+                    .gates
+                    .push(Gate::or(input.clone(), sources(idx, LinkType::Default)));
+                netlist.latches.push(Latch {
+                    input: input.clone(),
+                    output: d1.clone(),
+                    init: powered,
+                });
+                netlist.latches.push(Latch {
+                    input: d1.clone(),
+                    output: d2.clone(),
+                    init: powered,
+                });
+                netlist.gates.push(Gate::or(out, vec![input, d1, d2]));
+            }
+            NodeType::Observer => {
+                let prev = format!("{}_prev", internal);
+                let next = format!("{}_next", internal);
+                netlist
+                    .gates
+                    .push(Gate::or(input.clone(), sources(idx, LinkType::Default)));
+                netlist.latches.push(Latch {
+                    input: input.clone(),
+                    output: prev.clone(),
+                    init: false,
+                });
+                // A change starts a pulse of one tick, unless the observer is still pulsing
+                netlist.gates.push(Gate::from_fn(
+                    next.clone(),
+                    vec![input, prev, out.clone()],
+                    |v| v[0] != v[1] && !v[2],
+                ));
+                netlist.latches.push(Latch {
+                    input: next,
+                    output: out,
+                    init: powered,
+                });
+            }
+            NodeType::Repeater(delay) => {
+                let lock = format!("{}_lock", internal);
+                netlist
+                    .gates
+                    .push(Gate::or(input.clone(), sources(idx, LinkType::Default)));
+                netlist
+                    .gates
+                    .push(Gate::or(lock.clone(), sources(idx, LinkType::Side)));
+                // The number of ticks until the scheduled tick of the repeater is kept in a
+                // counter
+                let bits = (u8::BITS - delay.leading_zeros()) as usize;
+                let counter: Vec<String> = (0..bits)
+                    .map(|bit| format!("{}_c{}", internal, bit))
+                    .collect();
+                let mut gate_inputs = vec![input, lock, out.clone()];
+                gate_inputs.extend(counter.iter().cloned());
+                let step = move |v: &[bool]| {
+                    let pending = v[3..]
+                        .iter()
+                        .enumerate()
+                        .fold(0, |acc, (bit, &on)| acc | (on as u8) << bit);
+                    repeater_tick(delay, v[0], v[1], v[2], pending)
+                };
+                let next = format!("{}_next", internal);
+                netlist
+                    .gates
+                    .push(Gate::from_fn(next.clone(), gate_inputs.clone(), |v| {
+                        step(v).0
+                    }));
+                netlist.latches.push(Latch {
+                    input: next,
+                    output: out,
+                    init: powered,
+                });
+                for (bit, output) in counter.into_iter().enumerate() {
+                    let next = format!("{}_next", output);
+                    netlist
+                        .gates
+                        .push(Gate::from_fn(next.clone(), gate_inputs.clone(), |v| {
+                            step(v).1 >> bit & 1 != 0
+                        }));
+                    netlist.latches.push(Latch {
+                        input: next,
+                        output,
+                        init: false,
+                    });
+                }
+            }
+        }
+    }
+    Ok(netlist)
+}
+
+impl Netlist {
+    pub fn to_verilog(&self) -> String {
+        let mut out = String::new();
+        let ports: Vec<&str> = std::iter::once(CLOCK)
+            .chain(self.inputs.iter().map(String::as_str))
+            .chain(self.outputs.iter().map(String::as_str))
+            .collect();
+        let _ = writeln!(
+            out,
+            "// Generated by MCHPRS, one clock cycle is one redstone tick"
+        );
+        let _ = writeln!(out, "module {}({});", self.name, ports.join(", "));
+        let _ = writeln!(out, "  input {};", CLOCK);
+        for input in &self.inputs {
+            let _ = writeln!(out, "  input {};", input);
+        }
+        for output in &self.outputs {
+            let _ = writeln!(out, "  output {};", output);
+        }
+        for latch in &self.latches {
+            let _ = writeln!(out, "  reg {};", latch.output);
+        }
+        for gate in &self.gates {
+            if !self.outputs.contains(&gate.output) {
+                let _ = writeln!(out, "  wire {};", gate.output);
+            }
+        }
+
+        for gate in &self.gates {
+            let products: Vec<String> = gate
+                .rows
+                .iter()
+                .map(|row| {
+                    let literals: Vec<String> = row
+                        .iter()
+                        .zip(&gate.inputs)
+                        .filter_map(|(bit, input)| match bit {
+                            Some(true) => Some(input.clone()),
+                            Some(false) => Some(format!("~{}", input)),
+                            None => None,
+                        })
+                        .collect();
+                    match literals.is_empty() {
+                        true => "1'b1".to_owned(),
+                        false => format!("({})", literals.join(" & ")),
+                    }
+                })
+                .collect();
+            let expr = match products.is_empty() {
+                true => "1'b0".to_owned(),
+                false => products.join(" | "),
+            };
+            let _ = writeln!(out, "  assign {} = {};", gate.output, expr);
+        }
+
+        if !self.latches.is_empty() {
+            let _ = writeln!(out, "  initial begin");
+            for latch in &self.latches {
+                let _ = writeln!(out, "    {} = 1'b{};", latch.output, latch.init as u8);
+            }
+            let _ = writeln!(out, "  end");
+            let _ = writeln!(out, "  always @(posedge {}) begin", CLOCK);
+            for latch in &self.latches {
+                let _ = writeln!(out, "    {} <= {};", latch.output, latch.input);
+            }
+            let _ = writeln!(out, "  end");
+        }
+        let _ = writeln!(out, "endmodule");
+        out
+    }
+
+    pub fn to_blif(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# Generated by MCHPRS, one clock cycle is one redstone tick"
+        );
+        let _ = writeln!(out, ".model {}", self.name);
+        let _ = writeln!(out, ".inputs {} {}", CLOCK, self.inputs.join(" "));
+        let _ = writeln!(out, ".outputs {}", self.outputs.join(" "));
+        let _ = writeln!(out, ".clock {}", CLOCK);
+        for gate in &self.gates {
+            let _ = writeln!(out, ".names {} {}", gate.inputs.join(" "), gate.output);
+            for row in &gate.rows {
+                let bits: String = row
+                    .iter()
+                    .map(|bit| match bit {
+                        Some(true) => '1',
+                        Some(false) => '0',
+                        None => '-',
+                    })
+                    .collect();
+                let _ = match bits.is_empty() {
+                    true => writeln!(out, "1"),
+                    false => writeln!(out, "{} 1", bits),
+                };
+            }
+        }
+        for latch in &self.latches {
+            let _ = writeln!(
+                out,
+                ".latch {} {} re {} {}",
+                latch.input, latch.output, CLOCK, latch.init as u8
+            );
+        }
+        let _ = writeln!(out, ".end");
+        out
+    }
+
+    pub fn write(&self, format: NetlistFormat) -> String {
+        match format {
+            NetlistFormat::Verilog => self.to_verilog(),
+            NetlistFormat::Blif => self.to_blif(),
+        }
+    }
+
+    /// Parses the first model of a BLIF file. Inputs that are declared as a `.clock` are left
+    /// out, since one tick of a redstone circuit is already a clock cycle.
+    pub fn from_blif(text: &str) -> Result<Netlist, String> {
+        let mut netlist = Netlist::default();
+        let mut clocks = Vec::new();
+        // The truth table that the rows that follow a `.names` belong to, and whether its rows
+        // are for the output being on or off
+        let mut cover: Option<(Gate, Option<bool>)> = None;
+        let mut lines = text.lines().map(|line| line.split('#').next().unwrap());
+        while let Some(line) = lines.next() {
+            let mut line = line.to_owned();
+            while line.ends_with('\\') {
+                line.pop();
+                line.push(' ');
+                line.push_str(lines.next().unwrap_or_default());
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some(&first) = words.first() else {
+                continue;
+            };
+            if !first.starts_with('.') {
+                let Some((gate, on)) = &mut cover else {
+                    return Err(format!("Unexpected line: {}", line.trim()));
+                };
+                let (bits, value) = match words[..] {
+                    [value] if gate.inputs.is_empty() => ("", value),
+                    [bits, value] if bits.len() == gate.inputs.len() => (bits, value),
+                    _ => return Err(format!("Invalid row of {}: {}", gate.output, line.trim())),
+                };
+                let value = match value {
+                    "1" => true,
+                    "0" => false,
+                    _ => return Err(format!("Invalid row of {}: {}", gate.output, line.trim())),
+                };
+                if on.replace(value) == Some(!value) {
+                    return Err(format!("The rows of {} mix on and off values", gate.output));
+                }
+                let mut row = Vec::new();
+                for bit in bits.chars() {
+                    row.push(match bit {
+                        '1' => Some(true),
+                        '0' => Some(false),
+                        '-' => None,
+                        _ => {
+                            return Err(format!("Invalid row of {}: {}", gate.output, line.trim()))
+                        }
+                    });
+                }
+                gate.rows.push(row);
+                continue;
+            }
+
+            if let Some((gate, on)) = cover.take() {
+                netlist.push_cover(gate, on.unwrap_or(true));
+            }
+            let args = words[1..].iter().map(|&word| word.to_owned());
+            match first {
+                ".model" if !netlist.name.is_empty() => break,
+                ".model" => netlist.name = words.get(1).unwrap_or(&"circuit").to_string(),
+                ".inputs" => netlist.inputs.extend(args),
+                ".outputs" => netlist.outputs.extend(args),
+                ".clock" => clocks.extend(args),
+                ".names" => {
+                    let Some((output, inputs)) = words[1..].split_last() else {
+                        return Err("A .names line needs an output".to_owned());
+                    };
+                    let gate = Gate {
+                        inputs: inputs.iter().map(|&input| input.to_owned()).collect(),
+                        output: output.to_string(),
+                        rows: Vec::new(),
+                    };
+                    cover = Some((gate, None));
+                }
+                ".latch" => {
+                    let (input, output) = match words[..] {
+                        [_, input, output, ..] => (input, output),
+                        _ => return Err(format!("Invalid latch: {}", line.trim())),
+                    };
+                    // The initial value comes last, where 2 and 3 mean don't care and unknown
+                    let init = words.len() > 3 && words[words.len() - 1] == "1";
+                    netlist.latches.push(Latch {
+                        input: input.to_owned(),
+                        output: output.to_owned(),
+                        init,
+                    });
+                }
+                ".end" => break,
+                _ => return Err(format!("Unsupported BLIF directive: {}", first)),
+            }
+        }
+        if let Some((gate, on)) = cover {
+            netlist.push_cover(gate, on.unwrap_or(true));
+        }
+        netlist.inputs.retain(|input| !clocks.contains(input));
+        Ok(netlist)
+    }
+
+    /// Adds the gate of a `.names`, whose rows are either for the output being on or off
+    fn push_cover(&mut self, mut gate: Gate, on: bool) {
+        if !on {
+            let output = std::mem::replace(&mut gate.output, format!("{}$off", gate.output));
+            let inverted = Gate {
+                inputs: vec![gate.output.clone()],
+                output,
+                rows: vec![vec![Some(false)]],
+            };
+            self.gates.push(gate);
+            self.gates.push(inverted);
+        } else {
+            self.gates.push(gate);
+        }
+    }
+
+    /// Parses a file in the ASCII AIGER format (`aag`). The and gates are named after their
+    /// variable like `_a12`, the inputs and outputs use the names of the symbol table if it has
+    /// them.
+    pub fn from_aiger(text: &str) -> Result<Netlist, String> {
+        let mut lines = text.lines();
+        let header: Vec<&str> = lines
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let counts = match header[..] {
+            ["aag", ref counts @ ..] if counts.len() >= 5 => counts
+                .iter()
+                .map(|count| count.parse::<usize>())
+                .collect::<Result<Vec<usize>, _>>()
+                .map_err(|_| "Invalid AIGER header".to_owned())?,
+            ["aig", ..] => return Err("Only the ASCII AIGER format (aag) is supported".to_owned()),
+            _ => return Err("Invalid AIGER header".to_owned()),
+        };
+        let (num_inputs, num_latches, num_outputs, num_ands) =
+            (counts[1], counts[2], counts[3], counts[4]);
+
+        let mut next_literals = |count: usize, len: usize| -> Result<Vec<Vec<usize>>, String> {
+            let mut all = Vec::new();
+            for _ in 0..count {
+                let line = lines.next().ok_or("The AIGER file ends early")?;
+                let literals = line
+                    .split_whitespace()
+                    .map(|literal| literal.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .map_err(|_| format!("Invalid AIGER line: {}", line))?;
+                if literals.len() < len {
+                    return Err(format!("Invalid AIGER line: {}", line));
+                }
+                all.push(literals);
+            }
+            Ok(all)
+        };
+        let inputs = next_literals(num_inputs, 1)?;
+        let latches = next_literals(num_latches, 2)?;
+        let outputs = next_literals(num_outputs, 1)?;
+        let ands = next_literals(num_ands, 3)?;
+
+        let mut input_names: Vec<String> = (0..num_inputs).map(|i| format!("i{}", i)).collect();
+        let mut output_names: Vec<String> = (0..num_outputs).map(|i| format!("o{}", i)).collect();
+        for line in lines {
+            if line == "c" {
+                break;
+            }
+            let Some((symbol, name)) = line.split_once(' ') else {
+                continue;
+            };
+            let mut chars = symbol.chars();
+            let symbols = match chars.next() {
+                Some('i') => &mut input_names,
+                Some('o') => &mut output_names,
+                _ => continue,
+            };
+            let index = chars.as_str().parse::<usize>().ok();
+            if let Some(slot) = index.and_then(|index| symbols.get_mut(index)) {
+                *slot = name.to_owned();
+            }
+        }
+
+        let mut names: FxHashMap<usize, String> = FxHashMap::default();
+        for (literals, name) in inputs.iter().zip(&input_names) {
+            names.insert(literals[0] / 2, name.clone());
+        }
+        let name =
+            |names: &FxHashMap<usize, String>, literal: usize| match names.get(&(literal / 2)) {
+                Some(name) => name.clone(),
+                None => format!("_a{}", literal / 2),
+            };
+        let mut netlist = Netlist {
+            name: "circuit".to_owned(),
+            inputs: input_names,
+            outputs: output_names.clone(),
+            ..Default::default()
+        };
+        netlist.gates.push(Gate::constant(name(&names, 0), false));
+        for literals in &latches {
+            netlist.latches.push(Latch {
+                input: name(&names, literals[1]),
+                output: name(&names, literals[0]),
+                init: literals.get(2) == Some(&1),
+            });
+        }
+        for literals in &ands {
+            let inputs = vec![name(&names, literals[1]), name(&names, literals[2])];
+            let row = vec![Some(literals[1] % 2 == 0), Some(literals[2] % 2 == 0)];
+            netlist.gates.push(Gate {
+                inputs,
+                output: name(&names, literals[0]),
+                rows: vec![row],
+            });
+        }
+        for (literals, output) in outputs.iter().zip(output_names) {
+            netlist.gates.push(Gate {
+                inputs: vec![name(&names, literals[0])],
+                output,
+                rows: vec![vec![Some(literals[0] % 2 == 0)]],
+            });
+        }
+        Ok(netlist)
+    }
+
+    /// Lowers the gates that the outputs depend on to a circuit of NAND gates, with every
+    /// `.names` as a sum of products. Latches can't be lowered.
+    pub fn to_nands(&self) -> Result<NandCircuit, String> {
+        let mut builder = NandBuilder {
+            netlist: self,
+            drivers: self
+                .gates
+                .iter()
+                .enumerate()
+                .map(|(i, gate)| (gate.output.as_str(), i))
+                .collect(),
+            signals: self
+                .inputs
+                .iter()
+                .enumerate()
+                .map(|(i, input)| (input.as_str(), Some(NandSignal::Input(i))))
+                .collect(),
+            circuit: NandCircuit {
+                inputs: self.inputs.clone(),
+                ..Default::default()
+            },
+            cache: FxHashMap::default(),
+        };
+        for output in &self.outputs {
+            let mut signal = builder.signal(output)?;
+            if builder
+                .circuit
+                .outputs
+                .iter()
+                .any(|&(_, other)| other == signal)
+            {
+                // Every output gets its own lamp, so outputs that are the same signal are
+                // buffered
+                let gates = &mut builder.circuit.gates;
+                gates.push(vec![signal]);
+                gates.push(vec![NandSignal::Gate(gates.len() - 1)]);
+                signal = NandSignal::Gate(gates.len() - 1);
+            }
+            builder.circuit.outputs.push((output.clone(), signal));
+        }
+        Ok(builder.circuit)
+    }
+}
+
+/// A signal of a [`NandCircuit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum NandSignal {
+    /// The input of the netlist at this index
+    Input(usize),
+    Constant(bool),
+    /// The output of the gate at this index
+    Gate(usize),
+}
+
+/// A netlist as a circuit of NAND gates, which is easy to build out of redstone torches
+#[derive(Debug, Default)]
+pub struct NandCircuit {
+    pub inputs: Vec<String>,
+    /// The inputs of every gate. Gates only use the gates that come before them, and a gate
+    /// without inputs is always off.
+    pub gates: Vec<Vec<NandSignal>>,
+    /// The outputs by name, where every output is a different signal
+    pub outputs: Vec<(String, NandSignal)>,
+}
+
+struct NandBuilder<'a> {
+    netlist: &'a Netlist,
+    /// The gate of the netlist that drives a signal
+    drivers: FxHashMap<&'a str, usize>,
+    /// The lowered signals, or `None` while a signal is being lowered
+    signals: FxHashMap<&'a str, Option<NandSignal>>,
+    circuit: NandCircuit,
+    /// The gates by their inputs, so that gates like the inverse of an input are only built once
+    cache: FxHashMap<Vec<NandSignal>, NandSignal>,
+}
+
+impl<'a> NandBuilder<'a> {
+    fn nand(&mut self, mut inputs: Vec<NandSignal>) -> NandSignal {
+        if inputs.contains(&NandSignal::Constant(false)) {
+            return NandSignal::Constant(true);
+        }
+        inputs.retain(|&input| input != NandSignal::Constant(true));
+        inputs.sort();
+        inputs.dedup();
+        match inputs[..] {
+            [] => return NandSignal::Constant(false),
+            // The inverse of an inverter is its input
+            [NandSignal::Gate(gate)] if self.circuit.gates[gate].len() == 1 => {
+                return self.circuit.gates[gate][0];
+            }
+            _ => {}
+        }
+        if let Some(&signal) = self.cache.get(&inputs) {
+            return signal;
+        }
+        let signal = NandSignal::Gate(self.circuit.gates.len());
+        self.circuit.gates.push(inputs.clone());
+        self.cache.insert(inputs, signal);
+        signal
+    }
+
+    fn signal(&mut self, name: &'a str) -> Result<NandSignal, String> {
+        match self.signals.get(name) {
+            Some(Some(signal)) => return Ok(*signal),
+            Some(None) => return Err(format!("{} is part of a combinational loop", name)),
+            None => {}
+        }
+        let Some(&driver) = self.drivers.get(name) else {
+            return match self
+                .netlist
+                .latches
+                .iter()
+                .any(|latch| latch.output == name)
+            {
+                true => Err(format!(
+                    "{} is the output of a latch, only combinational logic can be imported",
+                    name
+                )),
+                false => Err(format!("{} is never driven", name)),
+            };
+        };
+        self.signals.insert(name, None);
+        let gate = &self.netlist.gates[driver];
+        let mut inputs = Vec::new();
+        for input in &gate.inputs {
+            inputs.push(self.signal(input)?);
+        }
+        // The output is the NAND of the NANDs of every row, which is the OR of the products
+        let mut products = Vec::new();
+        for row in &gate.rows {
+            let mut literals = Vec::new();
+            for (bit, &input) in row.iter().zip(&inputs) {
+                match bit {
+                    Some(true) => literals.push(input),
+                    Some(false) => literals.push(self.nand(vec![input])),
+                    None => {}
+                }
+            }
+            products.push(self.nand(literals));
+        }
+        let signal = self.nand(products);
+        self.signals.insert(name, Some(signal));
+        Ok(signal)
+    }
+}
+
+#[test]
+fn netlists_delay_signals_like_repeaters() {
+    use super::compile_graph::{CompileLink, CompileNode, NodeState};
+    use std::collections::HashMap;
+
+    let node = |ty, pos: Option<BlockPos>| CompileNode {
+        ty,
+        block: pos.map(|pos| (pos, 0)),
+        state: NodeState::default(),
+        facing_diode: false,
+        comparator_far_input: None,
+    };
+    let mut graph = CompileGraph::new();
+    let lever = graph.add_node(node(NodeType::Lever, Some(BlockPos::new(0, 9, 0))));
+    let repeater = graph.add_node(node(NodeType::Repeater(3), None));
+    let lamp = graph.add_node(node(NodeType::Lamp, Some(BlockPos::new(4, 9, -1))));
+    graph.add_edge(lever, repeater, CompileLink::default(0));
+    graph.add_edge(repeater, lamp, CompileLink::default(0));
+    let ports = [("CLK".to_owned(), BlockPos::new(0, 9, 0))];
+    let netlist = lower(&graph, &ports, "adder").unwrap();
+    assert_eq!(netlist.inputs, ["CLK"]);
+    assert_eq!(netlist.outputs, ["lamp_4_9_m1"]);
+
+    // Runs the netlist for a tick, with the gates settled before the latches take their inputs
+    let mut values: HashMap<String, bool> = netlist
+        .latches
+        .iter()
+        .map(|latch| (latch.output.clone(), latch.init))
+        .collect();
+    let mut lamp_states = Vec::new();
+    for tick in 0..10 {
+        // A pulse of one tick, which the repeater extends to three ticks
+        values.insert("CLK".to_owned(), tick == 0);
+        for _ in 0..netlist.gates.len() {
+            for gate in &netlist.gates {
+                let value = gate.eval(|name| values.get(name).copied().unwrap_or(false));
+                values.insert(gate.output.clone(), value);
+            }
+        }
+        lamp_states.push(values["lamp_4_9_m1"]);
+        let next: Vec<(String, bool)> = netlist
+            .latches
+            .iter()
+            .map(|latch| (latch.output.clone(), values[&latch.input]))
+            .collect();
+        values.extend(next);
+    }
+    // The repeater turns on 3 ticks after the lever and stays on for 3 ticks, then the lamp
+    // takes 2 ticks to turn off
+    let on: Vec<usize> = (0..10).filter(|&tick| lamp_states[tick]).collect();
+    assert_eq!(on, [3, 4, 5, 6, 7]);
+
+    let verilog = netlist.to_verilog();
+    assert!(verilog.contains("module adder(clk, CLK, lamp_4_9_m1);"));
+    assert!(verilog.contains("always @(posedge clk) begin"));
+    let blif = netlist.to_blif();
+    assert!(blif.contains(".inputs clk CLK"));
+    assert!(blif.contains(".latch _n1_next _n1 re clk 0"));
+
+    graph.add_node(node(
+        NodeType::Comparator(mchprs_blocks::blocks::ComparatorMode::Compare),
+        None,
+    ));
+    assert!(lower(&graph, &ports, "adder").is_err());
+}
+
+#[test]
+fn imported_netlists_lower_to_nand_gates() {
+    fn eval(circuit: &NandCircuit, inputs: &[bool]) -> Vec<bool> {
+        let mut gates: Vec<bool> = Vec::new();
+        let value = |gates: &[bool], signal| match signal {
+            NandSignal::Input(input) => inputs[input],
+            NandSignal::Constant(value) => value,
+            NandSignal::Gate(gate) => gates[gate],
+        };
+        for gate in &circuit.gates {
+            let output = !gate.iter().all(|&input| value(&gates, input));
+            gates.push(output);
+        }
+        let outputs = circuit.outputs.iter();
+        outputs.map(|&(_, signal)| value(&gates, signal)).collect()
+    }
+
+    let blif = "
+        # A full adder, with the carry as the rows where it is off
+        .model adder
+        .inputs a b cin clk
+        .outputs sum cout \\
+            not_a
+        .clock clk
+        .names a b cin sum
+        100 1
+        010 1
+        001 1
+        111 1
+        .names a b cin cout
+        00- 0
+        0-0 0
+        -00 0
+        .names a not_a
+        0 1
+        .end
+    ";
+    let circuit = Netlist::from_blif(blif).unwrap().to_nands().unwrap();
+    assert_eq!(circuit.inputs, ["a", "b", "cin"]);
+    for bits in 0..8 {
+        let inputs: Vec<bool> = (0..3).map(|i| bits >> i & 1 != 0).collect();
+        let count = inputs.iter().filter(|&&input| input).count();
+        assert_eq!(
+            eval(&circuit, &inputs),
+            [count % 2 == 1, count >= 2, !inputs[0]]
+        );
+    }
+
+    let aiger = "aag 7 2 0 2 3\n2\n4\n6\n12\n6 13 15\n12 2 4\n14 3 5\ni0 x\ni1 y\no0 s\no1 c\nc\nhalf adder\n";
+    let circuit = Netlist::from_aiger(aiger).unwrap().to_nands().unwrap();
+    assert_eq!(circuit.inputs, ["x", "y"]);
+    assert_eq!(circuit.outputs[0].0, "s");
+    for bits in 0..4 {
+        let inputs = [bits & 1 != 0, bits & 2 != 0];
+        assert_eq!(
+            eval(&circuit, &inputs),
+            [inputs[0] != inputs[1], inputs[0] && inputs[1]]
+        );
+    }
+
+    let latch = ".model counter\n.outputs q\n.latch d q 0\n.names q d\n0 1\n.end\n";
+    assert!(Netlist::from_blif(latch).unwrap().to_nands().is_err());
+    let cycle = ".model loop\n.outputs a\n.names b a\n1 1\n.names a b\n1 1\n.end\n";
+    assert!(Netlist::from_blif(cycle).unwrap().to_nands().is_err());
+}
@@ -6,6 +6,10 @@ use crate::world::World;
 pub struct ClampWeights;
 
 impl<W: World> Pass<W> for ClampWeights {
+    fn id(&self) -> &'static str {
+        "clamp-weights"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         graph.retain_edges(|g, edge| g[edge].ss < 15);
     }
@@ -8,6 +8,10 @@ use petgraph::Direction;
 pub struct Coalesce;
 
 impl<W: World> Pass<W> for Coalesce {
+    fn id(&self) -> &'static str {
+        "coalesce"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         for i in 0..graph.node_bound() {
             let idx = NodeIdx::new(i);
@@ -16,9 +20,9 @@ impl<W: World> Pass<W> for Coalesce {
             }
 
             let node = &graph[idx];
-            // Comparators depend on the link weight as well as the type,
+            // Comparators and wires depend on the link weight as well as the type,
             // we could implement that later if it's beneficial enough.
-            if matches!(node.ty, NodeType::Comparator(_)) || node.ty.is_output() {
+            if matches!(node.ty, NodeType::Comparator(_) | NodeType::Wire) || node.ty.is_output() {
                 continue;
             }
 
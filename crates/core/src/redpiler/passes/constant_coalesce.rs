@@ -9,6 +9,10 @@ use rustc_hash::FxHashMap;
 pub struct ConstantCoalesce;
 
 impl<W: World> Pass<W> for ConstantCoalesce {
+    fn id(&self) -> &'static str {
+        "constant-coalesce"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         let mut constant_nodes = FxHashMap::default();
 
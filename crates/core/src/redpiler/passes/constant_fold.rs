@@ -10,6 +10,10 @@ use tracing::trace;
 pub struct ConstantFold;
 
 impl<W: World> Pass<W> for ConstantFold {
+    fn id(&self) -> &'static str {
+        "constant-fold"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         loop {
             let num_folded = fold(graph);
@@ -77,7 +81,9 @@ fn fold(graph: &mut CompileGraph) -> usize {
                 }
             }
             NodeType::Repeater(_) => {
-                if graph[idx].state.repeater_locked {
+                // The locked state of the block can be out of date, so it is derived from the
+                // side inputs like the backend does
+                if side_power > 0 {
                     graph[idx].state.output_strength
                 } else if default_power > 0 {
                     15
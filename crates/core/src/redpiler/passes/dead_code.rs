@@ -0,0 +1,105 @@
+//! # [`DeadCode`]
+//!
+//! Removes every node that can't influence an output, not even through side links. Starting
+//! from the outputs, the graph is searched backwards along all links, and nodes that were never
+//! reached are removed. This also removes latches and clocks that feed back into themselves but
+//! are never read, since none of the nodes in such a loop are reachable from an output.
+//!
+//! Inputs are always kept, since they can still be used by players.
+
+use super::Pass;
+use crate::redpiler::compile_graph::{CompileGraph, NodeType};
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use crate::world::World;
+use petgraph::Direction;
+use rustc_hash::FxHashSet;
+use tracing::debug;
+
+pub struct DeadCode;
+
+fn is_kept(ty: NodeType) -> bool {
+    ty.is_output()
+        || matches!(
+            ty,
+            NodeType::Lever | NodeType::Button | NodeType::PressurePlate | NodeType::Target
+        )
+}
+
+impl<W: World> Pass<W> for DeadCode {
+    fn id(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
+        let mut stack: Vec<_> = graph
+            .node_indices()
+            .filter(|&idx| is_kept(graph[idx].ty))
+            .collect();
+        let mut live = FxHashSet::default();
+        while let Some(idx) = stack.pop() {
+            if live.insert(idx) {
+                stack.extend(graph.neighbors_directed(idx, Direction::Incoming));
+            }
+        }
+
+        let dead: Vec<_> = graph
+            .node_indices()
+            .filter(|idx| !live.contains(idx))
+            .collect();
+        for &idx in &dead {
+            graph.remove_node(idx);
+        }
+        debug!("Removed {} nodes that never reach an output", dead.len());
+    }
+}
+
+#[test]
+fn redpiler_removes_loops_that_never_reach_an_output() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::{Block, RedstoneRepeater};
+    use mchprs_blocks::{BlockDirection, BlockPos};
+
+    // Four repeaters powering each other through blocks in a ring, and a lamp next to it that
+    // is powered by a lever
+    let mut world = redpiler_test_world();
+    let ring = [
+        (BlockPos::new(11, 8, 10), BlockDirection::West),
+        (BlockPos::new(12, 8, 11), BlockDirection::North),
+        (BlockPos::new(11, 8, 12), BlockDirection::East),
+        (BlockPos::new(10, 8, 11), BlockDirection::South),
+    ];
+    for (pos, facing) in ring {
+        let repeater = RedstoneRepeater {
+            delay: 1,
+            facing,
+            locked: false,
+            powered: false,
+        };
+        world.set_block(pos, Block::RedstoneRepeater { repeater });
+    }
+    for (x, z) in [(10, 10), (12, 10), (12, 12), (10, 12)] {
+        world.set_block(BlockPos::new(x, 8, z), Block::Stone {});
+    }
+    let lever_pos = BlockPos::new(14, 8, 10);
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[]);
+    world.flush_block_changes();
+
+    let options = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+    for (pos, _) in ring {
+        assert_eq!(redpiler.get_signal_strength(pos), None);
+    }
+    let dead_code = redpiler
+        .pass_timings()
+        .iter()
+        .find(|timing| timing.pass == "dead-code")
+        .unwrap();
+    assert_eq!(dead_code.nodes_removed, 4);
+
+    redpiler.on_use_block(lever_pos);
+    redpiler.tick();
+    assert_eq!(redpiler.is_powered(lamp_pos), Some(true));
+}
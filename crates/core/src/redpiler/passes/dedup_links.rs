@@ -15,6 +15,10 @@ use petgraph::Direction;
 pub struct DedupLinks;
 
 impl<W: World> Pass<W> for DedupLinks {
+    fn id(&self) -> &'static str {
+        "dedup-links"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         for i in 0..graph.node_bound() {
             let idx = NodeIdx::new(i);
@@ -55,6 +55,10 @@ fn convert_node(
             CNodeType::Trapdoor => NodeType::Trapdoor,
             CNodeType::Wire => NodeType::Wire,
             CNodeType::Constant => NodeType::Constant,
+            CNodeType::Piston => NodeType::Piston,
+            CNodeType::NoteBlock => NodeType::NoteBlock,
+            CNodeType::Observer => NodeType::Observer,
+            CNodeType::Target => NodeType::Target,
         },
         block: node.block.map(|(pos, id)| {
             (
@@ -81,6 +85,10 @@ fn convert_node(
 pub struct ExportGraph;
 
 impl<W: World> Pass<W> for ExportGraph {
+    fn id(&self) -> &'static str {
+        "export-graph"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         let mut nodes_map =
             FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
@@ -0,0 +1,50 @@
+//! # [`ExportNetlist`]
+//!
+//! Writes the compiled circuit to `redpiler_netlist.v` or `redpiler_netlist.blif`, see
+//! [`netlist`](crate::redpiler::netlist). Inputs and outputs are named after their port.
+
+use super::Pass;
+use crate::redpiler::compile_graph::CompileGraph;
+use crate::redpiler::netlist;
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use crate::world::World;
+use std::fs;
+use tracing::{info, warn};
+
+pub struct ExportNetlist;
+
+impl<W: World> Pass<W> for ExportNetlist {
+    fn id(&self) -> &'static str {
+        "export-netlist"
+    }
+
+    fn run_pass(
+        &self,
+        graph: &mut CompileGraph,
+        options: &CompilerOptions,
+        _: &CompilerInput<'_, W>,
+    ) {
+        let Some(format) = options.netlist else {
+            return;
+        };
+        let netlist = match netlist::lower(graph, &options.port_names, "circuit") {
+            Ok(netlist) => netlist,
+            Err(err) => {
+                warn!("Failed to export the netlist: {}", err);
+                return;
+            }
+        };
+        let path = format!("redpiler_netlist.{}", format.extension());
+        fs::write(&path, netlist.write(format)).unwrap();
+        info!(
+            "Exported {} inputs and {} outputs to {}",
+            netlist.inputs.len(),
+            netlist.outputs.len(),
+            path
+        );
+    }
+
+    fn should_run(&self, options: &CompilerOptions) -> bool {
+        options.netlist.is_some()
+    }
+}
@@ -3,22 +3,30 @@
 //! This pass populates the graph with nodes using the input given in [`CompilerInput`].
 //! This pass is *mandatory*. Without it, the graph will never be populated.
 //!
-//! If `optimize` is set in [`CompilerOptions`], redstone wires will not be added to the graph.
+//! If `optimize` is set in [`CompilerOptions`], redstone wires will not be added to the graph,
+//! unless an observer is watching them.
+//!
+//! Redstone blocks carried by sticky pistons are not added either, the piston node acts as the
+//! power source instead. See [`crate::redpiler::piston`].
 //!
 //! There are no requirements for this pass.
 
 use super::Pass;
 use crate::redpiler::compile_graph::{CompileGraph, CompileNode, NodeState, NodeType};
-use crate::redpiler::{CompilerInput, CompilerOptions};
+use crate::redpiler::{piston, CompilerInput, CompilerOptions};
 use crate::redstone;
-use crate::world::{for_each_block_optimized, World};
+use crate::world::{non_empty_sections, World};
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::{Block, RedstoneComparator, RedstoneRepeater};
-use mchprs_blocks::BlockPos;
+use mchprs_blocks::{BlockFace, BlockPos};
 
 pub struct IdentifyNodes;
 
 impl<W: World> Pass<W> for IdentifyNodes {
+    fn id(&self) -> &'static str {
+        "identify-nodes"
+    }
+
     fn run_pass(
         &self,
         graph: &mut CompileGraph,
@@ -30,9 +38,14 @@ impl<W: World> Pass<W> for IdentifyNodes {
 
         let (first_pos, second_pos) = input.bounds;
 
-        for_each_block_optimized(plot, first_pos, second_pos, |pos| {
-            for_pos(ignore_wires, plot, graph, pos)
-        });
+        // Nodes are added in Morton order, so that nodes which are close to each other in the
+        // world also end up close to each other in the backend
+        for section in non_empty_sections(plot, first_pos, second_pos) {
+            for pos in section.morton_iter() {
+                for_pos(ignore_wires, plot, graph, pos);
+            }
+        }
+        remove_carried_blocks(plot, graph);
     }
 
     fn should_run(&self, _: &CompilerOptions) -> bool {
@@ -42,12 +55,21 @@ impl<W: World> Pass<W> for IdentifyNodes {
 }
 
 fn for_pos<W: World>(ignore_wires: bool, world: &W, graph: &mut CompileGraph, pos: BlockPos) {
+    if let Some(node) = identify_node(world, pos, ignore_wires) {
+        graph.add_node(node);
+    }
+}
+
+/// Returns the node for the block at `pos`, if it is a component
+pub fn identify_node<W: World>(
+    world: &W,
+    pos: BlockPos,
+    ignore_wires: bool,
+) -> Option<CompileNode> {
     let id = world.get_block_raw(pos);
     let block = Block::from_id(id);
 
-    let Some((ty, state)) = identify_block(block, pos, world) else {
-        return;
-    };
+    let (ty, state) = identify_block(block, pos, world)?;
 
     let facing_diode = match block {
         Block::RedstoneRepeater {
@@ -64,17 +86,47 @@ fn for_pos<W: World>(ignore_wires: bool, world: &W, graph: &mut CompileGraph, po
         _ => false,
     };
 
-    if ignore_wires && ty == NodeType::Wire {
-        return;
+    if ignore_wires && ty == NodeType::Wire && !is_observed(world, pos) {
+        return None;
     }
 
-    graph.add_node(CompileNode {
+    Some(CompileNode {
         ty,
         block: Some((pos, id)),
         state,
 
         facing_diode,
         comparator_far_input: None,
+    })
+}
+
+/// Returns true if an observer is watching the block at `pos`
+fn is_observed<W: World>(world: &W, pos: BlockPos) -> bool {
+    BlockFace::values().iter().any(|face| {
+        let observer_pos = pos.offset(*face);
+        matches!(
+            world.get_block(observer_pos),
+            Block::Observer { facing, .. } if facing.offset_pos(observer_pos, 1) == pos
+        )
+    })
+}
+
+/// Redstone blocks carried by sticky pistons are part of the piston node
+fn remove_carried_blocks<W: World>(world: &W, graph: &mut CompileGraph) {
+    let carried: Vec<BlockPos> = graph
+        .node_weights()
+        .filter(|node| node.ty == NodeType::Piston)
+        .filter_map(|node| node.block)
+        .filter_map(|(pos, _)| piston::carried_block(world, pos))
+        .flat_map(|carried| [carried.retracted_pos, carried.extended_pos])
+        .collect();
+    if carried.is_empty() {
+        return;
+    }
+    graph.retain_nodes(|graph, idx| {
+        let node = &graph[idx];
+        !(node.ty == NodeType::Constant
+            && matches!(node.block, Some((pos, _)) if carried.contains(&pos)))
     });
 }
 
@@ -112,7 +164,15 @@ fn identify_block<W: World>(
             (NodeType::PressurePlate, NodeState::simple(powered))
         }
         Block::IronTrapdoor { powered, .. } => (NodeType::Trapdoor, NodeState::simple(powered)),
+        Block::NoteBlock { powered, .. } => (NodeType::NoteBlock, NodeState::simple(powered)),
+        Block::Piston { extended, .. } | Block::StickyPiston { extended, .. } => {
+            (NodeType::Piston, NodeState::simple(extended))
+        }
+        Block::Observer { powered, .. } => (NodeType::Observer, NodeState::simple(powered)),
+        Block::Target { power } => (NodeType::Target, NodeState::comparator(power > 0, power)),
         Block::RedstoneBlock {} => (NodeType::Constant, NodeState::ss(15)),
+        // The time of day only changes with `/time`, which recompiles the changed detectors
+        Block::DaylightDetector { power, .. } => (NodeType::Constant, NodeState::ss(power)),
         block if redstone::has_comparator_override(block) => (
             NodeType::Constant,
             NodeState::ss(redstone::get_comparator_override(block, world, pos)),
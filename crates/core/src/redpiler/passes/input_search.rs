@@ -4,10 +4,11 @@
 //! This pass is *mandatory*. Without it, there would be no links between nodes.
 
 use super::Pass;
-use crate::redpiler::compile_graph::{CompileGraph, CompileLink, LinkType, NodeIdx};
-use crate::redpiler::{CompilerInput, CompilerOptions};
+use crate::redpiler::compile_graph::{CompileGraph, CompileLink, LinkType, NodeIdx, NodeType};
+use crate::redpiler::{piston, CompilerInput, CompilerOptions, TaskMonitor};
 use crate::redstone::{self, wire};
 use crate::world::World;
+use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::{Block, ButtonFace, LeverFace};
 use mchprs_blocks::{BlockDirection, BlockFace, BlockPos};
 use petgraph::visit::NodeIndexable;
@@ -17,14 +18,18 @@ use std::collections::VecDeque;
 pub struct InputSearch;
 
 impl<W: World> Pass<W> for InputSearch {
+    fn id(&self) -> &'static str {
+        "input-search"
+    }
+
     fn run_pass(
         &self,
         graph: &mut CompileGraph,
-        _: &CompilerOptions,
+        options: &CompilerOptions,
         input: &CompilerInput<'_, W>,
     ) {
-        let mut state = InputSearchState::new(input.world, graph);
-        state.search();
+        let mut state = InputSearchState::new(input.world, graph, options.wire_delay);
+        state.search(input.monitor);
     }
 
     fn should_run(&self, _: &CompilerOptions) -> bool {
@@ -33,27 +38,109 @@ impl<W: World> Pass<W> for InputSearch {
     }
 }
 
+/// Searches the inputs of `targets` only, linking them to any node that is already in the
+/// graph. `searched` is called after each target, so that the caller can see what was read
+/// from the world to find its inputs.
+pub fn search_inputs<W: World>(
+    world: &W,
+    graph: &mut CompileGraph,
+    targets: &[NodeIdx],
+    wire_delay: bool,
+    mut searched: impl FnMut(NodeIdx),
+) {
+    let mut state = InputSearchState::new(world, graph, wire_delay);
+    for &idx in targets {
+        let block = state.graph[idx].block.unwrap();
+        state.search_node(idx, block);
+        searched(idx);
+    }
+}
+
 struct InputSearchState<'a, W: World> {
     world: &'a W,
     graph: &'a mut CompileGraph,
     pos_map: FxHashMap<BlockPos, NodeIdx>,
+    /// Whether to read latency annotations on wires
+    wire_delay: bool,
+    /// The extended positions of redstone blocks carried by sticky pistons
+    carried: FxHashMap<BlockPos, NodeIdx>,
 }
 
 impl<'a, W: World> InputSearchState<'a, W> {
-    fn new(world: &'a W, graph: &'a mut CompileGraph) -> InputSearchState<'a, W> {
+    fn new(world: &'a W, graph: &'a mut CompileGraph, wire_delay: bool) -> InputSearchState<'a, W> {
         let mut pos_map = FxHashMap::default();
+        let mut carried = FxHashMap::default();
         for id in graph.node_indices() {
             let (pos, _) = graph[id].block.unwrap();
             pos_map.insert(pos, id);
+            if graph[id].ty == NodeType::Piston {
+                if let Some(block) = piston::carried_block(world, pos) {
+                    carried.insert(block.extended_pos, id);
+                }
+            }
         }
+        // The piston node powers everything next to the extended position of its block
+        pos_map.extend(carried.iter().map(|(&pos, &id)| (pos, id)));
 
         InputSearchState {
             world,
             graph,
             pos_map,
+            wire_delay,
+            carried,
+        }
+    }
+
+    /// Gets the block at `pos`, with carried redstone blocks always at their extended position
+    fn get_block(&self, pos: BlockPos) -> Block {
+        if self.carried.contains_key(&pos) {
+            Block::RedstoneBlock {}
+        } else {
+            self.world.get_block(pos)
         }
     }
 
+    /// Links the node at `source_pos` to `target`. When only part of the world is compiled,
+    /// there might not be a node for sources outside of the bounds, so they are ignored.
+    fn add_link(&mut self, source_pos: BlockPos, target: NodeIdx, link: CompileLink) {
+        if let Some(&source) = self.pos_map.get(&source_pos) {
+            self.graph.add_edge(source, target, link);
+        }
+    }
+
+    /// Returns the extra delay in redstone ticks that signals get when passing through the
+    /// wire at `pos`. Wires are annotated with a wall sign on the block below them that
+    /// reads `[latency]` on the first line and the number of ticks on the second line.
+    fn wire_latency(&self, pos: BlockPos) -> u8 {
+        if !self.wire_delay {
+            return 0;
+        }
+        let support_pos = pos.offset(BlockFace::Bottom);
+        let mut latency = 0u8;
+        let directions = [
+            BlockDirection::North,
+            BlockDirection::South,
+            BlockDirection::East,
+            BlockDirection::West,
+        ];
+        for direction in directions {
+            let sign_pos = support_pos.offset(direction.block_face());
+            match self.get_block(sign_pos) {
+                Block::WallSign { facing, .. } if facing == direction => {}
+                _ => continue,
+            }
+            if let Some(BlockEntity::Sign(sign)) = self.world.get_block_entity(sign_pos) {
+                let first_line = sign_text(&sign.rows[0]);
+                if first_line.trim().eq_ignore_ascii_case("[latency]") {
+                    if let Ok(ticks) = sign_text(&sign.rows[1]).trim().parse::<u8>() {
+                        latency = latency.saturating_add(ticks);
+                    }
+                }
+            }
+        }
+        latency
+    }
+
     fn provides_weak_power(&self, block: Block, side: BlockFace) -> bool {
         match block {
             Block::RedstoneTorch { .. } => true,
@@ -62,6 +149,9 @@ impl<'a, W: World> InputSearchState<'a, W> {
             Block::Lever { .. } => true,
             Block::StoneButton { .. } => true,
             Block::StonePressurePlate { .. } => true,
+            Block::Observer { facing, .. } if facing.block_face() == side => true,
+            Block::Target { .. } => true,
+            Block::DaylightDetector { .. } => true,
             Block::RedstoneRepeater { repeater } if repeater.facing.block_face() == side => true,
             Block::RedstoneComparator { comparator } if comparator.facing.block_face() == side => {
                 true
@@ -89,6 +179,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
             },
             Block::RedstoneRepeater { .. } => self.provides_weak_power(block, side),
             Block::RedstoneComparator { .. } => self.provides_weak_power(block, side),
+            Block::Observer { .. } => self.provides_weak_power(block, side),
             _ => false,
         }
     }
@@ -102,18 +193,27 @@ impl<'a, W: World> InputSearchState<'a, W> {
         pos: BlockPos,
         link_ty: LinkType,
         distance: u8,
+        delay: u8,
         start_node: NodeIdx,
         search_wire: bool,
     ) {
         if block.is_solid() {
+            // Target blocks conduct power and give off power themselves
+            if self.provides_weak_power(block, side) {
+                self.add_link(
+                    pos,
+                    start_node,
+                    CompileLink::new(link_ty, distance).with_delay(delay),
+                );
+            }
             for side in &BlockFace::values() {
                 let pos = pos.offset(*side);
-                let block = self.world.get_block(pos);
+                let block = self.get_block(pos);
                 if self.provides_strong_power(block, *side) {
-                    self.graph.add_edge(
-                        self.pos_map[&pos],
+                    self.add_link(
+                        pos,
                         start_node,
-                        CompileLink::new(link_ty, distance),
+                        CompileLink::new(link_ty, distance).with_delay(delay),
                     );
                 }
 
@@ -123,7 +223,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
                     }
                     match side {
                         BlockFace::Top => {
-                            self.search_wire(start_node, pos, link_ty, distance);
+                            self.search_wire(start_node, pos, link_ty, distance, delay);
                         }
                         BlockFace::Bottom => {}
                         _ => {
@@ -135,21 +235,21 @@ impl<'a, W: World> InputSearchState<'a, W> {
                                 )
                                 .is_none()
                             {
-                                self.search_wire(start_node, pos, link_ty, distance);
+                                self.search_wire(start_node, pos, link_ty, distance, delay);
                             }
                         }
                     }
                 }
             }
         } else if self.provides_weak_power(block, side) {
-            self.graph.add_edge(
-                self.pos_map[&pos],
+            self.add_link(
+                pos,
                 start_node,
-                CompileLink::new(link_ty, distance),
+                CompileLink::new(link_ty, distance).with_delay(delay),
             );
         } else if let Block::RedstoneWire { wire } = block {
             match side {
-                BlockFace::Top => self.search_wire(start_node, pos, link_ty, distance),
+                BlockFace::Top => self.search_wire(start_node, pos, link_ty, distance, delay),
                 BlockFace::Bottom => {}
                 _ => {
                     let direction = side.to_direction();
@@ -160,7 +260,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
                         )
                         .is_none()
                     {
-                        self.search_wire(start_node, pos, link_ty, distance);
+                        self.search_wire(start_node, pos, link_ty, distance, delay);
                     }
                 }
             }
@@ -172,24 +272,27 @@ impl<'a, W: World> InputSearchState<'a, W> {
         start_node: NodeIdx,
         root_pos: BlockPos,
         link_ty: LinkType,
-        mut distance: u8,
+        distance: u8,
+        delay: u8,
     ) {
         let mut queue: VecDeque<BlockPos> = VecDeque::new();
+        // The distance and the annotated latency along the shortest path to each wire
         let mut discovered = FxHashMap::default();
 
-        discovered.insert(root_pos, distance);
+        let root_delay = delay.saturating_add(self.wire_latency(root_pos));
+        discovered.insert(root_pos, (distance, root_delay));
         queue.push_back(root_pos);
 
         while !queue.is_empty() {
             let pos = queue.pop_front().unwrap();
-            distance = discovered[&pos];
+            let (distance, delay) = discovered[&pos];
 
             let up_pos = pos.offset(BlockFace::Top);
-            let up_block = self.world.get_block(up_pos);
+            let up_block = self.get_block(up_pos);
 
             for side in &BlockFace::values() {
                 let neighbor_pos = pos.offset(*side);
-                let neighbor = self.world.get_block(neighbor_pos);
+                let neighbor = self.get_block(neighbor_pos);
 
                 self.get_redstone_links(
                     neighbor,
@@ -197,13 +300,15 @@ impl<'a, W: World> InputSearchState<'a, W> {
                     neighbor_pos,
                     link_ty,
                     distance,
+                    delay,
                     start_node,
                     false,
                 );
 
                 if is_wire(self.world, neighbor_pos) && !discovered.contains_key(&neighbor_pos) {
                     queue.push_back(neighbor_pos);
-                    discovered.insert(neighbor_pos, discovered[&pos] + 1);
+                    let next_delay = delay.saturating_add(self.wire_latency(neighbor_pos));
+                    discovered.insert(neighbor_pos, (distance + 1, next_delay));
                 }
 
                 if side.is_horizontal() {
@@ -213,7 +318,9 @@ impl<'a, W: World> InputSearchState<'a, W> {
                             && !discovered.contains_key(&neighbor_up_pos)
                         {
                             queue.push_back(neighbor_up_pos);
-                            discovered.insert(neighbor_up_pos, discovered[&pos] + 1);
+                            let next_delay =
+                                delay.saturating_add(self.wire_latency(neighbor_up_pos));
+                            discovered.insert(neighbor_up_pos, (distance + 1, next_delay));
                         }
                     }
 
@@ -223,7 +330,9 @@ impl<'a, W: World> InputSearchState<'a, W> {
                             && !discovered.contains_key(&neighbor_down_pos)
                         {
                             queue.push_back(neighbor_down_pos);
-                            discovered.insert(neighbor_down_pos, discovered[&pos] + 1);
+                            let next_delay =
+                                delay.saturating_add(self.wire_latency(neighbor_down_pos));
+                            discovered.insert(neighbor_down_pos, (distance + 1, next_delay));
                         }
                     }
                 }
@@ -233,13 +342,14 @@ impl<'a, W: World> InputSearchState<'a, W> {
 
     fn search_diode_inputs(&mut self, id: NodeIdx, pos: BlockPos, facing: BlockDirection) {
         let input_pos = pos.offset(facing.block_face());
-        let input_block = self.world.get_block(input_pos);
+        let input_block = self.get_block(input_pos);
         self.get_redstone_links(
             input_block,
             facing.block_face(),
             input_pos,
             LinkType::Default,
             0,
+            0,
             id,
             true,
         )
@@ -247,25 +357,23 @@ impl<'a, W: World> InputSearchState<'a, W> {
 
     fn search_repeater_side(&mut self, id: NodeIdx, pos: BlockPos, side: BlockDirection) {
         let side_pos = pos.offset(side.block_face());
-        let side_block = self.world.get_block(side_pos);
+        let side_block = self.get_block(side_pos);
         if redstone::is_diode(side_block) && self.provides_weak_power(side_block, side.block_face())
         {
-            self.graph
-                .add_edge(self.pos_map[&side_pos], id, CompileLink::side(0));
+            self.add_link(side_pos, id, CompileLink::side(0));
         }
     }
 
     fn search_comparator_side(&mut self, id: NodeIdx, pos: BlockPos, side: BlockDirection) {
         let side_pos = pos.offset(side.block_face());
-        let side_block = self.world.get_block(side_pos);
+        let side_block = self.get_block(side_pos);
         if (redstone::is_diode(side_block)
             && self.provides_weak_power(side_block, side.block_face()))
             || matches!(side_block, Block::RedstoneBlock { .. })
         {
-            self.graph
-                .add_edge(self.pos_map[&side_pos], id, CompileLink::side(0));
+            self.add_link(side_pos, id, CompileLink::side(0));
         } else if matches!(side_block, Block::RedstoneWire { .. }) {
-            self.search_wire(id, side_pos, LinkType::Side, 0)
+            self.search_wire(id, side_pos, LinkType::Side, 0, 0)
         }
     }
 
@@ -273,26 +381,28 @@ impl<'a, W: World> InputSearchState<'a, W> {
         match Block::from_id(block_id) {
             Block::RedstoneTorch { .. } => {
                 let bottom_pos = pos.offset(BlockFace::Bottom);
-                let bottom_block = self.world.get_block(bottom_pos);
+                let bottom_block = self.get_block(bottom_pos);
                 self.get_redstone_links(
                     bottom_block,
                     BlockFace::Top,
                     bottom_pos,
                     LinkType::Default,
                     0,
+                    0,
                     id,
                     true,
                 );
             }
             Block::RedstoneWallTorch { facing, .. } => {
                 let wall_pos = pos.offset(facing.opposite().block_face());
-                let wall_block = self.world.get_block(wall_pos);
+                let wall_block = self.get_block(wall_pos);
                 self.get_redstone_links(
                     wall_block,
                     facing.opposite().block_face(),
                     wall_pos,
                     LinkType::Default,
                     0,
+                    0,
                     id,
                     true,
                 );
@@ -304,15 +414,14 @@ impl<'a, W: World> InputSearchState<'a, W> {
                 self.search_comparator_side(id, pos, facing.rotate_ccw());
 
                 let input_pos = pos.offset(facing.block_face());
-                let input_block = self.world.get_block(input_pos);
+                let input_block = self.get_block(input_pos);
                 if redstone::has_comparator_override(input_block) {
-                    self.graph
-                        .add_edge(self.pos_map[&input_pos], id, CompileLink::default(0));
+                    self.add_link(input_pos, id, CompileLink::default(0));
                 } else {
                     self.search_diode_inputs(id, pos, facing);
 
                     let far_input_pos = input_pos.offset(facing.block_face());
-                    let far_input_block = self.world.get_block(far_input_pos);
+                    let far_input_block = self.get_block(far_input_pos);
                     if input_block.is_solid() && redstone::has_comparator_override(far_input_block)
                     {
                         let far_override = redstone::get_comparator_override(
@@ -332,18 +441,65 @@ impl<'a, W: World> InputSearchState<'a, W> {
                 self.search_repeater_side(id, pos, facing.rotate_ccw());
             }
             Block::RedstoneWire { .. } => {
-                self.search_wire(id, pos, LinkType::Default, 0);
+                self.search_wire(id, pos, LinkType::Default, 0, 0);
             }
-            Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } => {
+            Block::Piston { facing, .. } | Block::StickyPiston { facing, .. } => {
                 for face in &BlockFace::values() {
+                    if *face == facing.block_face() {
+                        continue;
+                    }
                     let neighbor_pos = pos.offset(*face);
-                    let neighbor_block = self.world.get_block(neighbor_pos);
+                    let neighbor_block = self.get_block(neighbor_pos);
                     self.get_redstone_links(
                         neighbor_block,
                         *face,
                         neighbor_pos,
                         LinkType::Default,
                         0,
+                        0,
+                        id,
+                        true,
+                    );
+                }
+                // Quasi-connectivity
+                let up_pos = pos.offset(BlockFace::Top);
+                for face in &BlockFace::values() {
+                    if *face == BlockFace::Bottom {
+                        continue;
+                    }
+                    let neighbor_pos = up_pos.offset(*face);
+                    let neighbor_block = self.get_block(neighbor_pos);
+                    self.get_redstone_links(
+                        neighbor_block,
+                        *face,
+                        neighbor_pos,
+                        LinkType::Default,
+                        0,
+                        0,
+                        id,
+                        true,
+                    );
+                }
+            }
+            Block::Observer { facing, .. } => {
+                // Observers don't care about the power of the block they are watching, only
+                // about it changing. Blocks that aren't nodes never change while compiled.
+                let observed_pos = facing.offset_pos(pos, 1);
+                if self.get_block(observed_pos) != (Block::Air {}) {
+                    self.add_link(observed_pos, id, CompileLink::default(0));
+                }
+            }
+            Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. } => {
+                for face in &BlockFace::values() {
+                    let neighbor_pos = pos.offset(*face);
+                    let neighbor_block = self.get_block(neighbor_pos);
+                    self.get_redstone_links(
+                        neighbor_block,
+                        *face,
+                        neighbor_pos,
+                        LinkType::Default,
+                        0,
+                        0,
                         id,
                         true,
                     );
@@ -353,8 +509,12 @@ impl<'a, W: World> InputSearchState<'a, W> {
         }
     }
 
-    fn search(&mut self) {
+    fn search(&mut self, monitor: &TaskMonitor) {
         for i in 0..self.graph.node_bound() {
+            monitor.set_nodes_processed(i);
+            if monitor.is_cancelled() {
+                return;
+            }
             let idx = NodeIdx::new(i);
             if !self.graph.contains_node(idx) {
                 continue;
@@ -365,6 +525,90 @@ impl<'a, W: World> InputSearchState<'a, W> {
     }
 }
 
+/// Sign rows are stored as json text components
+fn sign_text(row: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(row) {
+        Ok(serde_json::Value::Object(component)) => match component.get("text") {
+            Some(serde_json::Value::String(text)) => text.clone(),
+            _ => String::new(),
+        },
+        _ => row.to_owned(),
+    }
+}
+
 fn is_wire(world: &dyn World, pos: BlockPos) -> bool {
     matches!(world.get_block(pos), Block::RedstoneWire { .. })
 }
+
+#[test]
+fn redpiler_compiles_far_input_comparators() {
+    use crate::interaction;
+    use crate::plot::{compile_test_world, redpiler_test_world, PlotWorld};
+    use mchprs_blocks::blocks::{ComparatorMode, Lever, RedstoneComparator};
+
+    // A subtract mode comparator reading a composter through a block, minus a comparator
+    // reading a cake on its side. The lever on the block overrides the far input.
+    let comparator_pos = BlockPos::new(12, 8, 10);
+    let side_pos = BlockPos::new(12, 8, 11);
+    let lever_pos = BlockPos::new(11, 9, 10);
+    let build = |world: &mut PlotWorld| {
+        world.set_block(BlockPos::new(10, 8, 10), Block::Composter { level: 7 });
+        world.set_block(BlockPos::new(11, 8, 10), Block::Stone {});
+        let lever = Lever::new(LeverFace::Floor, BlockDirection::North, false);
+        world.set_block(lever_pos, Block::Lever { lever });
+        let comparator = RedstoneComparator {
+            facing: BlockDirection::West,
+            mode: ComparatorMode::Subtract,
+            powered: false,
+        };
+        world.set_block(comparator_pos, Block::RedstoneComparator { comparator });
+        let side = RedstoneComparator {
+            facing: BlockDirection::South,
+            mode: ComparatorMode::Compare,
+            powered: false,
+        };
+        world.set_block(side_pos, Block::RedstoneComparator { comparator: side });
+        world.set_block(BlockPos::new(12, 8, 12), Block::Cake { bites: 5 });
+        // Read by a lamp, so that optimizing doesn't remove the comparators
+        world.set_block(BlockPos::new(13, 8, 10), Block::RedstoneLamp { lit: false });
+
+        for pos in [side_pos, comparator_pos] {
+            redstone::update(world.get_block(pos), world, pos);
+        }
+        for _ in 0..4 {
+            world.tick_interpreted();
+        }
+    };
+    let output = |world: &PlotWorld| match world.get_block_entity(comparator_pos) {
+        Some(BlockEntity::Comparator { output_strength }) => *output_strength,
+        _ => 0,
+    };
+
+    for optimize in [false, true] {
+        let mut interpreted = redpiler_test_world();
+        build(&mut interpreted);
+        assert_eq!(output(&interpreted), 7 - 4);
+
+        let mut compiled = redpiler_test_world();
+        build(&mut compiled);
+        let options = CompilerOptions {
+            optimize,
+            ..Default::default()
+        };
+        let mut redpiler = compile_test_world(&mut compiled, options);
+
+        for expected in [15 - 4, 7 - 4] {
+            let Block::Lever { lever } = interpreted.get_block(lever_pos) else {
+                unreachable!();
+            };
+            interaction::flip_lever(&mut interpreted, lever_pos, lever);
+            redpiler.on_use_block(lever_pos);
+            for _ in 0..4 {
+                interpreted.tick_interpreted();
+                redpiler.tick();
+            }
+            assert_eq!(output(&interpreted), expected);
+            assert_eq!(redpiler.get_signal_strength(comparator_pos), Some(expected));
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! # [`InsertDelays`]
+//!
+//! This pass implements the wire timing model enabled with `--wire-delay`. Links that pass
+//! through wires with latency annotations are given extra delay by the input search. Each of
+//! these links is replaced by a chain of repeaters without blocks that add up to the delay,
+//! which is what the wire would behave like once the planned repeaters are placed.
+//!
+//! Like real repeaters, the inserted repeaters output full signal strength, so analog signals
+//! through delayed links are only preserved as on or off.
+
+use super::Pass;
+use crate::redpiler::compile_graph::{CompileGraph, CompileLink, CompileNode, NodeState, NodeType};
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use crate::world::World;
+use petgraph::visit::EdgeRef;
+
+/// The longest delay a single repeater can have
+const MAX_REPEATER_DELAY: u8 = 4;
+
+pub struct InsertDelays;
+
+impl<W: World> Pass<W> for InsertDelays {
+    fn id(&self) -> &'static str {
+        "insert-delays"
+    }
+
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
+        let delayed: Vec<_> = graph
+            .edge_references()
+            .filter(|edge| edge.weight().delay > 0)
+            .map(|edge| edge.id())
+            .collect();
+
+        for edge_idx in delayed {
+            let (source, target) = graph.edge_endpoints(edge_idx).unwrap();
+            let link = graph.remove_edge(edge_idx).unwrap();
+            let powered = graph[source].state.output_strength > link.ss;
+
+            let mut remaining = link.delay;
+            let mut prev = source;
+            let mut prev_link = CompileLink::default(link.ss);
+            while remaining > 0 {
+                let delay = remaining.min(MAX_REPEATER_DELAY);
+                remaining -= delay;
+                let repeater = graph.add_node(CompileNode {
+                    ty: NodeType::Repeater(delay),
+                    block: None,
+                    state: NodeState::repeater(powered, false),
+                    facing_diode: false,
+                    comparator_far_input: None,
+                });
+                graph.add_edge(prev, repeater, prev_link);
+                prev = repeater;
+                prev_link = CompileLink::default(0);
+            }
+            // The distance is applied again after the last repeater, so that the target sees
+            // the same signal strength as it would without the delay for full strength signals
+            graph.add_edge(prev, target, CompileLink::new(link.ty, link.ss));
+        }
+    }
+
+    fn should_run(&self, options: &CompilerOptions) -> bool {
+        options.wire_delay
+    }
+}
+
+#[test]
+fn redpiler_delays_annotated_wires() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use mchprs_blocks::block_entities::{BlockEntity, SignBlockEntity};
+    use mchprs_blocks::blocks::Block;
+    use mchprs_blocks::{BlockDirection, BlockPos, SignType};
+    use serde_json::json;
+
+    // A lever powering a lamp through a line of wire, where the middle wire has a latency
+    // annotation of 2 ticks on the block below it
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let wire = Block::RedstoneWire {
+        wire: Default::default(),
+    };
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[wire; 3]);
+    let sign_pos = BlockPos::new(12, 7, 11);
+    world.set_block(
+        sign_pos,
+        Block::WallSign {
+            sign_type: SignType(0),
+            facing: BlockDirection::South,
+        },
+    );
+    let rows = ["[latency]", "2", "", ""].map(|line| json!({ "text": line }).to_string());
+    world.set_block_entity(
+        sign_pos,
+        BlockEntity::Sign(Box::new(SignBlockEntity { rows })),
+    );
+
+    let options = CompilerOptions {
+        wire_delay: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+
+    redpiler.on_use_block(lever_pos);
+    for lit in [false, false, true] {
+        redpiler.flush(&mut world);
+        assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit });
+        redpiler.tick();
+    }
+}
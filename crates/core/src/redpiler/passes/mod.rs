@@ -2,33 +2,78 @@ mod clamp_weights;
 mod coalesce;
 mod constant_coalesce;
 mod constant_fold;
+mod dead_code;
 mod dedup_links;
 mod export_graph;
+mod export_netlist;
 mod identify_nodes;
 mod input_search;
+mod insert_delays;
 mod unreachable_output;
 
 use crate::world::World;
 
 use super::compile_graph::CompileGraph;
-use super::{CompilerInput, CompilerOptions};
-use std::time::Instant;
+use super::{CompilerInput, CompilerOptions, PassStep};
+use std::time::{Duration, Instant};
 use tracing::trace;
 
+pub use identify_nodes::identify_node;
+pub use input_search::search_inputs;
+
 pub const fn make_default_pass_manager<'w, W: World>() -> PassManager<'w, W> {
     PassManager::new(&[
         &identify_nodes::IdentifyNodes,
         &input_search::InputSearch,
         &clamp_weights::ClampWeights,
+        &insert_delays::InsertDelays,
         &dedup_links::DedupLinks,
         &constant_fold::ConstantFold,
         &unreachable_output::UnreachableOutput,
+        &dead_code::DeadCode,
         &constant_coalesce::ConstantCoalesce,
         &coalesce::Coalesce,
         &export_graph::ExportGraph,
+        &export_netlist::ExportNetlist,
     ])
 }
 
+/// The passes that run after the input search. Incremental compilation builds and patches the
+/// unoptimized graph itself, and runs these on a copy of it.
+pub const fn make_incremental_pass_manager<'w, W: World>() -> PassManager<'w, W> {
+    PassManager::new(&[
+        &clamp_weights::ClampWeights,
+        &insert_delays::InsertDelays,
+        &dedup_links::DedupLinks,
+        &constant_fold::ConstantFold,
+        &unreachable_output::UnreachableOutput,
+        &dead_code::DeadCode,
+        &constant_coalesce::ConstantCoalesce,
+        &coalesce::Coalesce,
+        &export_graph::ExportGraph,
+        &export_netlist::ExportNetlist,
+    ])
+}
+
+/// Fixpoint groups stop after this many iterations even if the graph keeps changing
+const MAX_FIXPOINT_ITERATIONS: usize = 16;
+
+/// The position of the current pass among all passes of a compile
+struct Progress {
+    pass_index: usize,
+    pass_count: usize,
+}
+
+/// How long a pass took in the last compile, and the size of the graph after it
+#[derive(Debug, Clone)]
+pub struct PassTiming {
+    pub pass: &'static str,
+    pub duration: Duration,
+    pub node_count: usize,
+    pub edge_count: usize,
+    pub nodes_removed: usize,
+}
+
 pub struct PassManager<'p, W: World> {
     passes: &'p [&'p dyn Pass<W>],
 }
@@ -38,34 +83,201 @@ impl<'p, W: World> PassManager<'p, W> {
         Self { passes }
     }
 
+    /// The names of the passes that can be reordered
+    pub fn optimization_passes(&self) -> Vec<&'static str> {
+        self.passes
+            .iter()
+            .filter(|&&pass| is_optimization(pass))
+            .map(|pass| pass.id())
+            .collect()
+    }
+
+    /// Returns the names in `options` that don't refer to a pass of this pass manager that can
+    /// be disabled. Only optimization passes can be reordered.
+    pub fn unknown_passes<'o>(&self, options: &'o CompilerOptions) -> Vec<&'o str> {
+        let exists = |name: &str, optimization_only: bool| {
+            self.passes.iter().any(|&pass| {
+                pass.id() == name
+                    && !is_mandatory(pass)
+                    && (!optimization_only || is_optimization(pass))
+            })
+        };
+        let ordered = options.passes.iter().flatten().flat_map(|step| match step {
+            PassStep::Pass(name) => std::slice::from_ref(name),
+            PassStep::Fixpoint(names) => names.as_slice(),
+        });
+        let mut unknown: Vec<&str> = ordered
+            .map(String::as_str)
+            .filter(|name| !exists(name, true))
+            .collect();
+        unknown.extend(
+            options
+                .disabled_passes
+                .iter()
+                .map(String::as_str)
+                .filter(|name| !exists(name, false)),
+        );
+        unknown
+    }
+
     pub fn run_passes(
         &self,
         options: &CompilerOptions,
         input: &CompilerInput<'_, W>,
     ) -> CompileGraph {
         let mut graph = CompileGraph::new();
+        self.run_passes_on(&mut graph, options, input);
+        graph
+    }
 
+    /// Runs the passes in their default order, unless the order of the optimization passes is
+    /// set in `options`. In that case they run where the first optimization pass would have.
+    pub fn run_passes_on(
+        &self,
+        graph: &mut CompileGraph,
+        options: &CompilerOptions,
+        input: &CompilerInput<'_, W>,
+    ) -> Vec<PassTiming> {
+        let mut timings = Vec::new();
+        let mut progress = Progress {
+            pass_index: 0,
+            pass_count: self.pass_count(options),
+        };
+        let mut ran_custom_order = false;
         for &pass in self.passes {
-            if !pass.should_run(options) {
-                trace!("Skipping pass: {}", pass.name());
-                continue;
+            if let Some(steps) = &options.passes {
+                if is_optimization(pass) {
+                    if !ran_custom_order {
+                        ran_custom_order = true;
+                        for step in steps {
+                            self.run_step(step, graph, options, input, &mut progress, &mut timings);
+                        }
+                    }
+                    continue;
+                }
             }
+            self.run_pass(pass, graph, options, input, &mut progress, &mut timings);
+        }
+        timings
+    }
 
-            trace!("Running pass: {}", pass.name());
-            let start = Instant::now();
+    /// The number of passes that are reported as the progress of a compile, passes of a
+    /// fixpoint group are only counted once
+    fn pass_count(&self, options: &CompilerOptions) -> usize {
+        let Some(steps) = &options.passes else {
+            return self.passes.len();
+        };
+        let fixed = self
+            .passes
+            .iter()
+            .filter(|&&pass| !is_optimization(pass))
+            .count();
+        let ordered: usize = steps
+            .iter()
+            .map(|step| match step {
+                PassStep::Pass(_) => 1,
+                PassStep::Fixpoint(names) => names.len(),
+            })
+            .sum();
+        fixed + ordered
+    }
 
-            pass.run_pass(&mut graph, options, input);
+    fn run_step(
+        &self,
+        step: &PassStep,
+        graph: &mut CompileGraph,
+        options: &CompilerOptions,
+        input: &CompilerInput<'_, W>,
+        progress: &mut Progress,
+        timings: &mut Vec<PassTiming>,
+    ) {
+        let find = |name: &str| self.passes.iter().find(|pass| pass.id() == name).copied();
+        match step {
+            PassStep::Pass(name) => match find(name) {
+                Some(pass) => self.run_pass(pass, graph, options, input, progress, timings),
+                None => progress.pass_index += 1,
+            },
+            PassStep::Fixpoint(names) => {
+                let first_index = progress.pass_index;
+                for iteration in 1..=MAX_FIXPOINT_ITERATIONS {
+                    // Every iteration is reported as the same passes again
+                    progress.pass_index = first_index;
+                    let size = (graph.node_count(), graph.edge_count());
+                    for pass in names.iter().filter_map(|name| find(name)) {
+                        self.run_pass(pass, graph, options, input, progress, timings);
+                    }
+                    if (graph.node_count(), graph.edge_count()) == size {
+                        trace!("Reached fixpoint after {} iterations", iteration);
+                        break;
+                    }
+                }
+                progress.pass_index = first_index + names.len();
+            }
+        }
+    }
 
-            trace!("Completed pass in {:?}", start.elapsed());
-            trace!("node_count: {}", graph.node_count());
-            trace!("edge_count: {}", graph.edge_count());
+    fn run_pass(
+        &self,
+        pass: &dyn Pass<W>,
+        graph: &mut CompileGraph,
+        options: &CompilerOptions,
+        input: &CompilerInput<'_, W>,
+        progress: &mut Progress,
+        timings: &mut Vec<PassTiming>,
+    ) {
+        let pass_index = progress.pass_index;
+        progress.pass_index += 1;
+        let disabled =
+            !is_mandatory(pass) && options.disabled_passes.iter().any(|id| id == pass.id());
+        if !pass.should_run(options) || disabled || input.monitor.is_cancelled() {
+            trace!("Skipping pass: {}", pass.name());
+            return;
         }
 
-        graph
+        trace!("Running pass: {}", pass.name());
+        input.monitor.start_pass(
+            pass.id(),
+            pass_index,
+            progress.pass_count,
+            graph.node_count(),
+        );
+        let start = Instant::now();
+        let node_count = graph.node_count();
+
+        pass.run_pass(graph, options, input);
+
+        let duration = start.elapsed();
+        trace!("Completed pass in {:?}", duration);
+        trace!("node_count: {}", graph.node_count());
+        trace!("edge_count: {}", graph.edge_count());
+        timings.push(PassTiming {
+            pass: pass.id(),
+            duration,
+            node_count: graph.node_count(),
+            edge_count: graph.edge_count(),
+            nodes_removed: node_count.saturating_sub(graph.node_count()),
+        });
     }
 }
 
+/// Mandatory passes run without any options, and are never skipped
+fn is_mandatory<W: World>(pass: &dyn Pass<W>) -> bool {
+    pass.should_run(&Default::default())
+}
+
+/// Optimization passes only run with `--optimize`, so they are the ones that can be reordered
+fn is_optimization<W: World>(pass: &dyn Pass<W>) -> bool {
+    let optimize = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    pass.should_run(&optimize) && !is_mandatory(pass)
+}
+
 pub trait Pass<W: World> {
+    /// The name used to refer to the pass in compiler options
+    fn id(&self) -> &'static str;
+
     fn run_pass(
         &self,
         graph: &mut CompileGraph,
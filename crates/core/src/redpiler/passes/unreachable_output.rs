@@ -19,6 +19,10 @@ use petgraph::Direction;
 pub struct UnreachableOutput;
 
 impl<W: World> Pass<W> for UnreachableOutput {
+    fn id(&self) -> &'static str {
+        "unreachable-output"
+    }
+
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
         for i in 0..graph.node_bound() {
             let idx = NodeIdx::new(i);
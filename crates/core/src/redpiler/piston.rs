@@ -0,0 +1,189 @@
+//! Pistons are compiled as nodes that move their blocks in the world when their state is
+//! flushed. The moved blocks are not part of the graph, so this only works when they don't
+//! interact with the rest of the circuit. The one exception is a redstone block carried by a
+//! sticky piston, which is compiled as a power source at its extended position that is on
+//! while the piston is extended. This is what piston-extender-based gates are made of.
+//!
+//! Wires next to a carried redstone block keep the shape they had when the circuit was
+//! compiled.
+
+use crate::redstone::piston;
+use crate::world::{for_each_block_optimized, World};
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+
+/// A redstone block that moves along with a sticky piston
+pub struct CarriedBlock {
+    pub retracted_pos: BlockPos,
+    pub extended_pos: BlockPos,
+}
+
+fn piston_state(block: Block) -> Option<(bool, bool, BlockFacing)> {
+    match block {
+        Block::Piston { extended, facing } => Some((false, extended, facing)),
+        Block::StickyPiston { extended, facing } => Some((true, extended, facing)),
+        _ => None,
+    }
+}
+
+/// Returns the redstone block that is carried back and forth by the sticky piston at `pos`
+pub fn carried_block(world: &impl World, pos: BlockPos) -> Option<CarriedBlock> {
+    let (sticky, extended, facing) = piston_state(world.get_block(pos))?;
+    let retracted_pos = facing.offset_pos(pos, 1);
+    let extended_pos = facing.offset_pos(pos, 2);
+    let carried = if extended {
+        matches!(world.get_block(retracted_pos), Block::PistonHead { head } if head.facing == facing)
+            && world.get_block(extended_pos) == (Block::RedstoneBlock {})
+    } else {
+        world.get_block(retracted_pos) == (Block::RedstoneBlock {})
+            && world.get_block(extended_pos) == (Block::Air {})
+    };
+    if !sticky || !carried {
+        return None;
+    }
+    Some(CarriedBlock {
+        retracted_pos,
+        extended_pos,
+    })
+}
+
+/// Returns every position the piston at `pos` can move blocks into or out of. After the
+/// first extension, a piston only moves blocks between the two positions in front of it.
+fn moved_positions(world: &impl World, pos: BlockPos) -> Vec<BlockPos> {
+    let Some((sticky, extended, facing)) = piston_state(world.get_block(pos)) else {
+        return Vec::new();
+    };
+    let len = if extended {
+        let pull_pos = facing.offset_pos(pos, 2);
+        if sticky && piston::is_pullable(world.get_block(pull_pos)) {
+            2
+        } else {
+            1
+        }
+    } else {
+        match piston::blocks_to_push(world, pos, facing) {
+            Some(pushed) => pushed.len() as i32 + 1,
+            None => 0,
+        }
+    };
+    (1..=len).map(|n| facing.offset_pos(pos, n)).collect()
+}
+
+/// Blocks that take part in a circuit and would notice a block moving next to them
+fn is_component(block: Block) -> bool {
+    matches!(
+        block,
+        Block::RedstoneWire { .. }
+            | Block::RedstoneTorch { .. }
+            | Block::RedstoneWallTorch { .. }
+            | Block::RedstoneRepeater { .. }
+            | Block::RedstoneComparator { .. }
+            | Block::RedstoneLamp { .. }
+            | Block::Lever { .. }
+            | Block::StoneButton { .. }
+            | Block::StonePressurePlate { .. }
+            | Block::IronTrapdoor { .. }
+            | Block::NoteBlock { .. }
+            | Block::RedstoneBlock {}
+            | Block::Piston { .. }
+            | Block::StickyPiston { .. }
+            | Block::Observer { .. }
+            | Block::DaylightDetector { .. }
+    )
+}
+
+fn is_entangled(world: &impl World, pos: BlockPos) -> bool {
+    let moved = moved_positions(world, pos);
+    let carried = carried_block(world, pos);
+    for &moved_pos in &moved {
+        let block = world.get_block(moved_pos);
+        if is_component(block) && block != (Block::RedstoneBlock {}) {
+            // This component would be destroyed
+            return true;
+        }
+        if matches!(&carried, Some(carried) if carried.extended_pos == moved_pos) {
+            // The carried block is part of the graph at this position
+            continue;
+        }
+        for face in &BlockFace::values() {
+            let neighbor_pos = moved_pos.offset(*face);
+            if neighbor_pos == pos || moved.contains(&neighbor_pos) {
+                continue;
+            }
+            if is_component(world.get_block(neighbor_pos)) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Finds the pistons in `bounds` that move blocks next to other components. The graph doesn't
+/// know about these blocks, so these pistons can't be compiled.
+pub fn find_entangled_pistons(world: &impl World, bounds: (BlockPos, BlockPos)) -> Vec<BlockPos> {
+    let mut entangled = Vec::new();
+    for_each_block_optimized(world, bounds.0, bounds.1, |pos| {
+        if piston_state(world.get_block(pos)).is_some() && is_entangled(world, pos) {
+            entangled.push(pos);
+        }
+    });
+    entangled
+}
+
+#[test]
+fn redpiler_compiles_carried_redstone_block() {
+    use crate::plot::{compile_test_world, redpiler_test_world};
+    use mchprs_blocks::blocks::{Lever, LeverFace};
+    use mchprs_blocks::BlockDirection;
+
+    // A lever powering a sticky piston that moves a redstone block next to a lamp
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(9, 8, 10);
+    let lamp_pos = BlockPos::new(13, 8, 10);
+    world.set_block(
+        lever_pos,
+        Block::Lever {
+            lever: Lever::new(LeverFace::Floor, BlockDirection::North, false),
+        },
+    );
+    world.set_block(
+        BlockPos::new(10, 8, 10),
+        Block::StickyPiston {
+            extended: false,
+            facing: BlockFacing::East,
+        },
+    );
+    world.set_block(BlockPos::new(11, 8, 10), Block::RedstoneBlock {});
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+
+    redpiler.on_use_block(lever_pos);
+    redpiler.tick();
+    redpiler.flush(&mut world);
+    assert_eq!(
+        world.get_block(BlockPos::new(12, 8, 10)),
+        Block::RedstoneBlock {}
+    );
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+
+    redpiler.on_use_block(lever_pos);
+    for lit in [true, true, false] {
+        redpiler.tick();
+        redpiler.flush(&mut world);
+        assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit });
+    }
+    assert_eq!(
+        world.get_block(BlockPos::new(11, 8, 10)),
+        Block::RedstoneBlock {}
+    );
+
+    // A piston that would push a block next to the lamp can't be compiled
+    redpiler.discard();
+    world.set_block(BlockPos::new(11, 8, 10), Block::Stone {});
+    let bounds = world.get_corners();
+    let err = redpiler
+        .compile(&mut world, bounds, Default::default(), Vec::new())
+        .unwrap_err();
+    assert_eq!(err.positions, vec![BlockPos::new(10, 8, 10)]);
+}
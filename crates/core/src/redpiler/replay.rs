@@ -0,0 +1,160 @@
+//! Recording and replaying the inputs of a compiled circuit. Every input is recorded together
+//! with the number of ticks since the recording started, so that replaying it against a
+//! recompiled circuit, possibly with another backend, gives it exactly the same inputs on the
+//! same ticks.
+
+use mchprs_blocks::BlockPos;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Has to be increased whenever the format of a recording changes
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputEvent {
+    /// A lever was flipped or a button was pressed
+    Use(BlockPos),
+    PressurePlate(BlockPos, bool),
+    HitTarget {
+        pos: BlockPos,
+        power: u8,
+        ticks: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    version: u32,
+    /// The number of ticks that passed while recording
+    pub length: u64,
+    /// Events happen after the given number of ticks have passed, before the next tick
+    pub events: Vec<(u64, InputEvent)>,
+}
+
+impl Default for Recording {
+    fn default() -> Recording {
+        Recording {
+            version: FORMAT_VERSION,
+            length: 0,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Recording {
+    pub fn record(&mut self, event: InputEvent) {
+        self.events.push((self.length, event));
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Recording> {
+        let recording: Recording = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if recording.version != FORMAT_VERSION {
+            anyhow::bail!("unsupported recording version {}", recording.version);
+        }
+        Ok(recording)
+    }
+}
+
+/// A recording that is being played back
+pub struct Replay {
+    recording: Recording,
+    tick: u64,
+    next_event: usize,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Replay {
+        Replay {
+            recording,
+            tick: 0,
+            next_event: 0,
+        }
+    }
+
+    /// Returns the inputs that happen before the next tick and advances to it
+    pub fn next_tick(&mut self) -> Vec<InputEvent> {
+        let events: Vec<InputEvent> = self.recording.events[self.next_event..]
+            .iter()
+            .take_while(|(tick, _)| *tick <= self.tick)
+            .map(|&(_, event)| event)
+            .collect();
+        self.next_event += events.len();
+        self.tick += 1;
+        events
+    }
+
+    /// Inputs can be recorded after the last tick, so this is only true once the tick after
+    /// the end of the recording got its inputs
+    pub fn is_finished(&self) -> bool {
+        self.tick > self.recording.length
+    }
+}
+
+#[test]
+fn redpiler_replays_recorded_inputs() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world, PlotWorld};
+    use crate::redpiler::{Compiler, CompilerOptions};
+    use crate::world::World;
+    use mchprs_blocks::blocks::{Block, RedstoneRepeater};
+    use mchprs_blocks::BlockDirection;
+
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let lamp_pos = BlockPos::new(13, 8, 10);
+    let build = || {
+        let mut world = redpiler_test_world();
+        let repeater = RedstoneRepeater {
+            delay: 2,
+            facing: BlockDirection::West,
+            locked: false,
+            powered: false,
+        };
+        let repeater = Block::RedstoneRepeater { repeater };
+        build_lever_line(&mut world, lever_pos, &[repeater; 2]);
+        world
+    };
+    let lamp_states = |redpiler: &mut Compiler, world: &mut PlotWorld| {
+        let mut states = Vec::new();
+        for _ in 0..30 {
+            redpiler.tick();
+            redpiler.flush(world);
+            states.push(world.get_block(lamp_pos));
+        }
+        states
+    };
+
+    let mut world = build();
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+    redpiler.start_recording();
+    redpiler.tick();
+    redpiler.on_use_block(lever_pos);
+    for _ in 0..5 {
+        redpiler.tick();
+    }
+    redpiler.on_use_block(lever_pos);
+    let recording = redpiler.stop_recording().unwrap();
+    assert_eq!(recording.length, 6);
+    assert_eq!(recording.events.len(), 2);
+    let expected = lamp_states(&mut redpiler, &mut world);
+
+    let mut world = build();
+    let options = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+    redpiler.start_replay(recording);
+    for _ in 0..6 {
+        redpiler.tick();
+    }
+    assert_eq!(lamp_states(&mut redpiler, &mut world), expected);
+    assert!(!redpiler.is_replaying());
+}
@@ -0,0 +1,214 @@
+//! Scripted tests of a compiled circuit. A test script gives the circuit inputs on fixed ticks,
+//! like a [`Recording`](super::Recording), and lists the signal strengths that blocks are
+//! expected to have on other ticks. Scripts are json files, for example:
+//!
+//! ```json
+//! {
+//!   "inputs": [[0, {"Use": {"x": 3, "y": 9, "z": 4}}]],
+//!   "assertions": [
+//!     {"tick": 0, "pos": {"x": 8, "y": 9, "z": 4}, "powered": false},
+//!     {"tick": 4, "pos": {"x": 8, "y": 9, "z": 4}, "ss": 15, "name": "output on"}
+//!   ]
+//! }
+//! ```
+
+use super::replay::InputEvent;
+use super::Compiler;
+use anyhow::{bail, Context};
+use mchprs_blocks::BlockPos;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expected {
+    Powered(bool),
+    Ss(u8),
+}
+
+impl Expected {
+    fn matches(self, ss: u8) -> bool {
+        match self {
+            Expected::Powered(powered) => (ss > 0) == powered,
+            Expected::Ss(expected) => ss == expected,
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Powered(true) => write!(f, "powered"),
+            Expected::Powered(false) => write!(f, "unpowered"),
+            Expected::Ss(ss) => write!(f, "signal strength {}", ss),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assertion {
+    /// The assertion is checked after this many ticks have passed, before the inputs of the tick
+    pub tick: u64,
+    pub pos: BlockPos,
+    #[serde(flatten)]
+    pub expected: Expected,
+    /// Shown in the report instead of the position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TestScript {
+    /// Inputs happen after the given number of ticks have passed, before the next tick
+    #[serde(default)]
+    pub inputs: Vec<(u64, InputEvent)>,
+    pub assertions: Vec<Assertion>,
+}
+
+impl TestScript {
+    pub fn load(path: &Path) -> anyhow::Result<TestScript> {
+        let script: TestScript = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if script.assertions.is_empty() {
+            bail!("the test script does not have any assertions");
+        }
+        Ok(script)
+    }
+
+    /// The number of ticks the test runs for
+    pub fn length(&self) -> u64 {
+        let last_input = self.inputs.iter().map(|(tick, _)| *tick);
+        let last_assertion = self.assertions.iter().map(|assertion| assertion.tick);
+        last_input.chain(last_assertion).max().unwrap_or(0)
+    }
+
+    pub fn inputs_at(&self, tick: u64) -> impl Iterator<Item = InputEvent> + '_ {
+        self.inputs
+            .iter()
+            .filter(move |(input_tick, _)| *input_tick == tick)
+            .map(|&(_, event)| event)
+    }
+
+    /// Checks that every input is a node of the running circuit, since the backend can't give
+    /// inputs to other blocks
+    pub fn check_inputs(&self, compiler: &Compiler) -> anyhow::Result<()> {
+        for (tick, event) in &self.inputs {
+            let pos = match *event {
+                InputEvent::Use(pos) => pos,
+                InputEvent::PressurePlate(pos, _) => pos,
+                InputEvent::HitTarget { pos, .. } => pos,
+            };
+            compiler.get_signal_strength(pos).with_context(|| {
+                format!("the input at {} on tick {} is not compiled", pos, tick)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Checks the assertions of `tick` against the running circuit
+    pub fn check(&self, tick: u64, compiler: &Compiler) -> Vec<AssertionResult> {
+        self.assertions
+            .iter()
+            .filter(|assertion| assertion.tick == tick)
+            .map(|assertion| AssertionResult {
+                assertion: assertion.clone(),
+                actual: compiler.get_signal_strength(assertion.pos),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    /// `None` if the block is not a node of the running circuit
+    pub actual: Option<u8>,
+}
+
+impl AssertionResult {
+    pub fn passed(&self) -> bool {
+        self.actual
+            .map_or(false, |ss| self.assertion.expected.matches(ss))
+    }
+}
+
+impl fmt::Display for AssertionResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let assertion = &self.assertion;
+        match &assertion.name {
+            Some(name) => write!(f, "Tick {}, {}: ", assertion.tick, name)?,
+            None => write!(f, "Tick {}, {}: ", assertion.tick, assertion.pos)?,
+        }
+        match self.actual {
+            _ if self.passed() => write!(f, "{}", assertion.expected),
+            Some(ss) => write!(
+                f,
+                "expected {}, found signal strength {}",
+                assertion.expected, ss
+            ),
+            None => write!(f, "{} is not compiled", assertion.pos),
+        }
+    }
+}
+
+/// Runs `script` against a circuit that is only simulated by redpiler, starting with its
+/// current state
+pub fn run(compiler: &mut Compiler, script: &TestScript) -> anyhow::Result<Vec<AssertionResult>> {
+    script.check_inputs(compiler)?;
+    let length = script.length();
+    let mut results = Vec::new();
+    for tick in 0..=length {
+        results.extend(script.check(tick, compiler));
+        for event in script.inputs_at(tick) {
+            compiler.input(event);
+        }
+        if tick < length {
+            compiler.tick();
+        }
+    }
+    Ok(results)
+}
+
+#[test]
+fn test_scripts_report_every_assertion() {
+    use crate::plot::{build_lever_line, compile_test_world, redpiler_test_world};
+    use crate::world::World;
+    use serde_json::json;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let lamp_pos = build_lever_line(&mut world, lever_pos, &[]);
+    world.flush_block_changes();
+
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+
+    let script: TestScript = serde_json::from_value(json!({
+        "inputs": [[0, {"Use": lever_pos}]],
+        "assertions": [
+            {"tick": 0, "pos": lamp_pos, "powered": false},
+            {"tick": 1, "pos": lamp_pos, "ss": 15, "name": "lamp on"},
+            {"tick": 1, "pos": lamp_pos, "ss": 7},
+            {"tick": 1, "pos": BlockPos::new(0, 8, 0), "powered": false},
+        ]
+    }))
+    .unwrap();
+    assert_eq!(script.length(), 1);
+    assert_eq!(script.assertions[1].expected, Expected::Ss(15));
+
+    let results = run(&mut redpiler, &script).unwrap();
+    let passed: Vec<bool> = results.iter().map(|result| result.passed()).collect();
+    assert_eq!(passed, [true, true, false, false]);
+    assert_eq!(
+        results[1].to_string(),
+        "Tick 1, lamp on: signal strength 15"
+    );
+    assert_eq!(results[3].actual, None);
+
+    // Inputs have to be nodes of the circuit
+    let script = TestScript {
+        inputs: vec![(0, InputEvent::Use(BlockPos::new(0, 8, 0)))],
+        ..script
+    };
+    assert!(run(&mut redpiler, &script).is_err());
+}
@@ -0,0 +1,141 @@
+//! Progress reporting and cancellation for compiles. Compiles run on the plot thread, so nothing
+//! else can look at them while they run. Instead, the monitor calls back into whoever started the
+//! compile every so often, which lets them show the progress to players and cancel the compile.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Progress within a pass is reported at most this often
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct CompileProgress {
+    pub pass: &'static str,
+    /// The number of passes that came before this one
+    pub pass_index: usize,
+    pub pass_count: usize,
+    /// How many of the nodes the pass has gone through so far, if it reports that
+    pub nodes_processed: Option<usize>,
+    pub node_count: usize,
+}
+
+impl CompileProgress {
+    /// How far the whole compile has come in percent
+    pub fn percent(&self) -> u32 {
+        let pass_fraction = match self.nodes_processed {
+            Some(processed) if self.node_count > 0 => processed as f32 / self.node_count as f32,
+            _ => 0.0,
+        };
+        let fraction = (self.pass_index as f32 + pass_fraction) / self.pass_count.max(1) as f32;
+        (fraction * 100.0).min(100.0) as u32
+    }
+}
+
+/// Returns true if the compile should be cancelled
+type ReportFn<'a> = dyn Fn(&CompileProgress) -> bool + 'a;
+
+#[derive(Default)]
+pub struct TaskMonitor<'a> {
+    report: Option<&'a ReportFn<'a>>,
+    cancelled: Cell<bool>,
+    pass: Cell<&'static str>,
+    pass_index: Cell<usize>,
+    pass_count: Cell<usize>,
+    node_count: Cell<usize>,
+    last_report: Cell<Option<Instant>>,
+}
+
+impl<'a> TaskMonitor<'a> {
+    pub fn new(report: &'a ReportFn<'a>) -> TaskMonitor<'a> {
+        TaskMonitor {
+            report: Some(report),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+
+    pub fn start_pass(
+        &self,
+        pass: &'static str,
+        pass_index: usize,
+        pass_count: usize,
+        node_count: usize,
+    ) {
+        self.pass.set(pass);
+        self.pass_index.set(pass_index);
+        self.pass_count.set(pass_count);
+        self.node_count.set(node_count);
+        self.report(None);
+    }
+
+    /// Reports the progress of a pass that goes through the nodes one by one. This is cheap to
+    /// call, since it only reports again after some time has passed.
+    pub fn set_nodes_processed(&self, processed: usize) {
+        if self.report.is_none() {
+            return;
+        }
+        let due = self
+            .last_report
+            .get()
+            .map_or(true, |last| last.elapsed() >= REPORT_INTERVAL);
+        if due {
+            self.report(Some(processed));
+        }
+    }
+
+    fn report(&self, nodes_processed: Option<usize>) {
+        let Some(report) = self.report else {
+            return;
+        };
+        self.last_report.set(Some(Instant::now()));
+        let progress = CompileProgress {
+            pass: self.pass.get(),
+            pass_index: self.pass_index.get(),
+            pass_count: self.pass_count.get(),
+            nodes_processed,
+            node_count: self.node_count.get(),
+        };
+        if report(&progress) {
+            self.cancelled.set(true);
+        }
+    }
+}
+
+#[test]
+fn redpiler_compile_reports_progress_and_can_be_cancelled() {
+    use crate::plot::{is_cancel_command, redpiler_test_world};
+    use crate::redpiler::{CompileError, Compiler};
+    use crate::world::World;
+    use mchprs_blocks::blocks::Block;
+    use mchprs_blocks::BlockPos;
+    use std::cell::RefCell;
+
+    let mut world = redpiler_test_world();
+    world.set_block(BlockPos::new(10, 8, 10), Block::RedstoneBlock {});
+    world.set_block(BlockPos::new(11, 8, 10), Block::RedstoneLamp { lit: false });
+    let bounds = world.get_corners();
+
+    let passes = RefCell::new(Vec::new());
+    let report = |progress: &CompileProgress| {
+        passes.borrow_mut().push(progress.pass);
+        false
+    };
+    let monitor = TaskMonitor::new(&report);
+    let mut redpiler = Compiler::default();
+    redpiler
+        .compile_with_monitor(&mut world, bounds, Default::default(), Vec::new(), &monitor)
+        .unwrap();
+    assert!(passes.borrow().contains(&"input-search"));
+
+    let cancel = |_: &CompileProgress| true;
+    let monitor = TaskMonitor::new(&cancel);
+    let mut redpiler = Compiler::default();
+    let result =
+        redpiler.compile_with_monitor(&mut world, bounds, Default::default(), Vec::new(), &monitor);
+    assert!(matches!(result, Err(CompileError::Cancelled)));
+    assert!(!redpiler.is_active());
+    assert!(is_cancel_command("/rp cancel"));
+    assert!(!is_cancel_command("/rp compile"));
+}
@@ -0,0 +1,183 @@
+//! Equivalence checking between two compilations of the same circuit. Both are driven with the
+//! same random sequence of inputs, and their outputs are compared after every tick. Comparing
+//! a circuit compiled with `--optimize` to the same circuit compiled without it catches
+//! miscompilations in the passes before they break someone's build.
+
+use super::{Compiler, CompilerOptions, UnsupportedFeatureError};
+use crate::world::{for_each_block_optimized, World};
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickEntry;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+
+/// The chance that an input is used on a tick
+const INPUT_PROBABILITY: f64 = 1.0 / 16.0;
+
+fn is_input(block: Block) -> bool {
+    matches!(block, Block::Lever { .. } | Block::StoneButton { .. })
+}
+
+fn is_output(block: Block) -> bool {
+    matches!(
+        block,
+        Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. }
+    )
+}
+
+/// The first output that differed between the two compilations
+#[derive(Debug)]
+pub struct Divergence {
+    /// The tick after which the outputs differed, starting at 1
+    pub tick: u32,
+    pub pos: BlockPos,
+    pub block: Block,
+    pub reference: bool,
+    pub candidate: bool,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = |powered| match powered {
+            true => "powered",
+            false => "unpowered",
+        };
+        write!(
+            f,
+            "{} at {} was {} instead of {} after {} ticks",
+            self.block.get_name(),
+            self.pos,
+            state(self.candidate),
+            state(self.reference),
+            self.tick
+        )
+    }
+}
+
+pub struct EquivalenceReport {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub divergence: Option<Divergence>,
+}
+
+pub struct EquivalenceCheck {
+    /// The compilation that is trusted to be correct
+    pub reference: CompilerOptions,
+    pub candidate: CompilerOptions,
+    pub ticks: u32,
+    /// Running a check with the same seed uses the same inputs on the same ticks
+    pub seed: u64,
+}
+
+impl EquivalenceCheck {
+    /// Compiles the circuit inside of `bounds` twice and runs both compilations side by side.
+    /// The world is left untouched.
+    pub fn run<W: World>(
+        self,
+        world: &mut W,
+        bounds: (BlockPos, BlockPos),
+        pending_ticks: Vec<TickEntry>,
+    ) -> Result<EquivalenceReport, UnsupportedFeatureError> {
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for_each_block_optimized(world, bounds.0, bounds.1, |pos| {
+            let block = world.get_block(pos);
+            if is_input(block) {
+                inputs.push(pos);
+            } else if is_output(block) {
+                outputs.push(pos);
+            }
+        });
+
+        let mut reference = Compiler::default();
+        reference.compile(world, bounds, self.reference, pending_ticks.clone())?;
+        let mut candidate = Compiler::default();
+        candidate.compile(world, bounds, self.candidate, pending_ticks)?;
+        // Inputs that don't lead anywhere might have been optimized out
+        inputs.retain(|&pos| {
+            reference.is_powered(pos).is_some() && candidate.is_powered(pos).is_some()
+        });
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut divergence = None;
+        'ticks: for tick in 1..=self.ticks {
+            for &pos in &inputs {
+                if rng.gen_bool(INPUT_PROBABILITY) {
+                    reference.on_use_block(pos);
+                    candidate.on_use_block(pos);
+                }
+            }
+            reference.tick();
+            candidate.tick();
+
+            for &pos in &outputs {
+                // Outputs that were optimized out of either graph can't be compared
+                let (Some(expected), Some(actual)) =
+                    (reference.is_powered(pos), candidate.is_powered(pos))
+                else {
+                    continue;
+                };
+                if expected != actual {
+                    divergence = Some(Divergence {
+                        tick,
+                        pos,
+                        block: world.get_block(pos),
+                        reference: expected,
+                        candidate: actual,
+                    });
+                    break 'ticks;
+                }
+            }
+        }
+
+        Ok(EquivalenceReport {
+            inputs: inputs.len(),
+            outputs: outputs.len(),
+            divergence,
+        })
+    }
+}
+
+#[test]
+fn optimized_redpiler_is_equivalent() {
+    use crate::plot::{build_lever_line, redpiler_test_world};
+    use mchprs_blocks::blocks::RedstoneRepeater;
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let wire = Block::RedstoneWire {
+        wire: Default::default(),
+    };
+    let repeater = RedstoneRepeater {
+        delay: 2,
+        facing: BlockDirection::West,
+        locked: false,
+        powered: false,
+    };
+    let repeater = Block::RedstoneRepeater { repeater };
+    build_lever_line(
+        &mut world,
+        BlockPos::new(10, 8, 10),
+        &[wire, wire, repeater],
+    );
+    world.flush_block_changes();
+
+    let check = EquivalenceCheck {
+        reference: Default::default(),
+        candidate: CompilerOptions {
+            optimize: true,
+            ..Default::default()
+        },
+        ticks: 200,
+        seed: 0,
+    };
+    let bounds = world.get_corners();
+    let report = check.run(&mut world, bounds, Vec::new()).unwrap();
+    assert_eq!((report.inputs, report.outputs), (1, 1));
+    if let Some(divergence) = report.divergence {
+        panic!("{}", divergence);
+    }
+    // The world is left untouched
+    assert!(world.changed_positions().is_empty());
+}
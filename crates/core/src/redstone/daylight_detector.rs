@@ -0,0 +1,147 @@
+//! Daylight detectors output a signal that follows the position of the sun. Plots don't have a
+//! day-night cycle, the time of day is set per plot with `/time` and stays where it is put. There
+//! is no light engine either, so every detector is assumed to see the open sky.
+
+use crate::world::{for_each_block_mut_optimized, World};
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use std::f64::consts::PI;
+
+/// The time of day plots start at, in game ticks
+pub const NOON: i64 = 6000;
+
+/// Vanilla's `Mth.cos`, which looks the value up in a table of 65536 sines
+fn table_cos(value: f32) -> f32 {
+    let index = (value * 10430.378 + 16384.0) as i32 & 65535;
+    (index as f64 * PI * 2.0 / 65536.0).sin() as f32
+}
+
+/// How far the sun has moved through the sky, from 0 at noon to 1 at the next noon
+fn time_of_day(time: i64) -> f32 {
+    let x = time as f64 / 24000.0 - 0.25;
+    let fraction = x - x.floor();
+    let eased = 0.5 - (fraction * PI).cos() / 2.0;
+    (fraction * 2.0 + eased) as f32 / 3.0
+}
+
+/// The amount the sky light is darkened by at `time`
+fn sky_darken(time: i64) -> i32 {
+    let cos = table_cos(time_of_day(time) * (PI * 2.0) as f32) as f64;
+    let brightness = 0.5 + 2.0 * cos.clamp(-0.25, 0.25);
+    ((1.0 - brightness) * 11.0) as i32
+}
+
+/// The signal strength of a daylight detector under the open sky at `time`, following the
+/// vanilla curve
+pub fn power(time: i64, inverted: bool) -> u8 {
+    let mut power = 15 - sky_darken(time);
+    if inverted {
+        power = 15 - power;
+    } else if power > 0 {
+        let mut angle = time_of_day(time) * (PI * 2.0) as f32;
+        // The angle is pulled towards noon, which makes the signal rise and fall more steeply
+        let noon = if angle < PI as f32 {
+            0.0
+        } else {
+            (PI * 2.0) as f32
+        };
+        angle += (noon - angle) * 0.2;
+        power = (power as f32 * table_cos(angle)).round() as i32;
+    }
+    power.clamp(0, 15) as u8
+}
+
+/// Sets the power of the daylight detector at `pos` for the time of day of the world. Returns
+/// true if it changed.
+pub fn update(world: &mut impl World, pos: BlockPos) -> bool {
+    let Block::DaylightDetector {
+        inverted,
+        power: old_power,
+    } = world.get_block(pos)
+    else {
+        return false;
+    };
+    let power = power(world.daytime(), inverted);
+    if power == old_power {
+        return false;
+    }
+    super::set_block(world, pos, Block::DaylightDetector { inverted, power });
+    super::update_surrounding_blocks(world, pos);
+    true
+}
+
+/// Updates every daylight detector between `first_pos` and `second_pos` after the time of day
+/// changed. Returns the number of detectors whose power changed.
+pub fn update_all(world: &mut impl World, first_pos: BlockPos, second_pos: BlockPos) -> usize {
+    let mut changed = 0;
+    for_each_block_mut_optimized(world, first_pos, second_pos, |world, pos| {
+        if update(world, pos) {
+            changed += 1;
+        }
+    });
+    changed
+}
+
+#[test]
+fn power_follows_the_sun() {
+    assert_eq!(power(NOON, false), 15);
+    assert_eq!(power(NOON, true), 0);
+    assert_eq!(power(18000, false), 0);
+    assert_eq!(power(18000, true), 11);
+    // The signal rises through the morning and falls in the evening
+    let morning: Vec<u8> = (0..=6000).step_by(500).map(|t| power(t, false)).collect();
+    assert!(morning.windows(2).all(|w| w[0] <= w[1]));
+    let evening: Vec<u8> = (6000..=12000)
+        .step_by(500)
+        .map(|t| power(t, false))
+        .collect();
+    assert!(evening.windows(2).all(|w| w[0] >= w[1]));
+    // Times wrap around every day
+    assert_eq!(power(NOON + 24000 * 3, false), 15);
+    assert_eq!(power(NOON - 24000, false), 15);
+}
+
+#[test]
+fn daylight_detectors_follow_the_plot_time_under_redpiler() {
+    use crate::plot::{compile_test_world, redpiler_test_world};
+    use crate::redpiler::CompilerOptions;
+    use crate::redstone;
+
+    let mut world = redpiler_test_world();
+    let detector_pos = BlockPos::new(10, 8, 10);
+    let wire_pos = BlockPos::new(11, 8, 10);
+    world.set_block(
+        detector_pos,
+        Block::DaylightDetector {
+            inverted: false,
+            power: 15,
+        },
+    );
+    world.set_block(
+        wire_pos,
+        Block::RedstoneWire {
+            wire: Default::default(),
+        },
+    );
+    redstone::update_surrounding_blocks(&mut world, detector_pos);
+    world.flush_block_changes();
+
+    let options = CompilerOptions {
+        incremental: true,
+        ..Default::default()
+    };
+    let mut redpiler = compile_test_world(&mut world, options);
+    assert_eq!(redpiler.get_signal_strength(wire_pos), Some(15));
+
+    // Detectors are constants, so changing the time recompiles them
+    assert!(redpiler.suspend(&mut world));
+    world.daytime = 18000;
+    let (first_pos, second_pos) = world.get_corners();
+    assert_eq!(update_all(&mut world, first_pos, second_pos), 1);
+    let changed = world.changed_positions();
+    let ticks = std::mem::take(&mut world.to_be_ticked);
+    redpiler.recompile(&mut world, &changed, ticks).unwrap();
+    redpiler.tick();
+    assert_eq!(redpiler.get_signal_strength(detector_pos), Some(0));
+    assert_eq!(redpiler.get_signal_strength(wire_pos), Some(0));
+}
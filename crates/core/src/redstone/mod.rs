@@ -3,14 +3,22 @@
 //! scenerio (i.e. regular buiding)
 
 pub mod comparator;
+pub mod daylight_detector;
+pub mod observer;
+pub mod piston;
 pub mod repeater;
+pub mod target;
+pub mod update_order;
+pub mod updates;
 pub mod wire;
 
+use crate::interaction;
 use crate::world::World;
 use mchprs_blocks::block_entities::BlockEntity;
-use mchprs_blocks::blocks::{Block, ButtonFace, LeverFace};
+use mchprs_blocks::blocks::{Block, ButtonFace, Instrument, LeverFace};
 use mchprs_blocks::{BlockDirection, BlockFace, BlockPos};
 use mchprs_world::TickPriority;
+use updates::BlockUpdate;
 
 fn get_weak_power(
     block: Block,
@@ -26,6 +34,9 @@ fn get_weak_power(
         Block::StonePressurePlate { powered: true } => 15,
         Block::Lever { lever } if lever.powered => 15,
         Block::StoneButton { button } if button.powered => 15,
+        Block::Observer { facing, powered } if powered && facing.block_face() == side => 15,
+        Block::Target { power } => power,
+        Block::DaylightDetector { power, .. } => power,
         Block::RedstoneRepeater { repeater }
             if repeater.facing.block_face() == side && repeater.powered =>
         {
@@ -82,6 +93,7 @@ fn get_strong_power(
             _ => 0,
         },
         Block::StonePressurePlate { powered: true } if side == BlockFace::Top => 15,
+        Block::Observer { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneWire { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneRepeater { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneComparator { .. } => get_weak_power(block, world, pos, side, dust_power),
@@ -111,7 +123,9 @@ pub fn get_redstone_power(
     facing: BlockFace,
 ) -> u8 {
     if block.is_solid() {
-        get_max_strong_power(world, pos, true)
+        // Target blocks are conductors that also give off power themselves
+        let weak_power = get_weak_power(block, world, pos, facing, true);
+        weak_power.max(get_max_strong_power(world, pos, true))
     } else {
         get_weak_power(block, world, pos, facing, true)
     }
@@ -124,7 +138,8 @@ fn get_redstone_power_no_dust(
     facing: BlockFace,
 ) -> u8 {
     if block.is_solid() {
-        get_max_strong_power(world, pos, false)
+        let weak_power = get_weak_power(block, world, pos, facing, false);
+        weak_power.max(get_max_strong_power(world, pos, false))
     } else {
         get_weak_power(block, world, pos, facing, false)
     }
@@ -161,6 +176,13 @@ pub fn redstone_lamp_should_be_lit(world: &impl World, pos: BlockPos) -> bool {
     false
 }
 
+/// Note blocks only make a sound if the block above them is air
+pub fn play_note_block(world: &mut impl World, pos: BlockPos, instrument: Instrument, note: u32) {
+    if matches!(world.get_block(pos.offset(BlockFace::Top)), Block::Air {}) {
+        world.play_note(pos, instrument, note);
+    }
+}
+
 fn diode_get_input_strength(world: &impl World, pos: BlockPos, facing: BlockDirection) -> u8 {
     let input_pos = pos.offset(facing.block_face());
     let input_block = world.get_block(input_pos);
@@ -199,7 +221,25 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
             if lit && !should_be_lit {
                 world.schedule_tick(pos, 2, TickPriority::Normal);
             } else if !lit && should_be_lit {
-                world.set_block(pos, Block::RedstoneLamp { lit: true });
+                set_block(world, pos, Block::RedstoneLamp { lit: true });
+            }
+        }
+        Block::NoteBlock {
+            instrument,
+            note,
+            powered,
+        } => {
+            let should_be_powered = redstone_lamp_should_be_lit(world, pos);
+            if powered != should_be_powered {
+                let new_block = Block::NoteBlock {
+                    instrument,
+                    note,
+                    powered: should_be_powered,
+                };
+                set_block(world, pos, new_block);
+                if should_be_powered {
+                    play_note_block(world, pos, instrument, note);
+                }
             }
         }
         Block::IronTrapdoor {
@@ -214,9 +254,12 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
                     half,
                     powered: should_be_powered,
                 };
-                world.set_block(pos, new_block);
+                set_block(world, pos, new_block);
             }
         }
+        Block::Piston { extended, facing } | Block::StickyPiston { extended, facing } => {
+            piston::update(world, pos, extended, facing);
+        }
         _ => {}
     }
 }
@@ -232,33 +275,33 @@ pub fn tick(block: Block, world: &mut impl World, pos: BlockPos) {
         Block::RedstoneTorch { lit } => {
             let should_be_off = torch_should_be_off(world, pos);
             if lit && should_be_off {
-                world.set_block(pos, Block::RedstoneTorch { lit: false });
+                set_block(world, pos, Block::RedstoneTorch { lit: false });
                 update_surrounding_blocks(world, pos);
             } else if !lit && !should_be_off {
-                world.set_block(pos, Block::RedstoneTorch { lit: true });
+                set_block(world, pos, Block::RedstoneTorch { lit: true });
                 update_surrounding_blocks(world, pos);
             }
         }
         Block::RedstoneWallTorch { lit, facing } => {
             let should_be_off = wall_torch_should_be_off(world, pos, facing);
             if lit && should_be_off {
-                world.set_block(pos, Block::RedstoneWallTorch { lit: false, facing });
+                set_block(world, pos, Block::RedstoneWallTorch { lit: false, facing });
                 update_surrounding_blocks(world, pos);
             } else if !lit && !should_be_off {
-                world.set_block(pos, Block::RedstoneWallTorch { lit: true, facing });
+                set_block(world, pos, Block::RedstoneWallTorch { lit: true, facing });
                 update_surrounding_blocks(world, pos);
             }
         }
         Block::RedstoneLamp { lit } => {
             let should_be_lit = redstone_lamp_should_be_lit(world, pos);
             if lit && !should_be_lit {
-                world.set_block(pos, Block::RedstoneLamp { lit: false });
+                set_block(world, pos, Block::RedstoneLamp { lit: false });
             }
         }
         Block::StoneButton { mut button } => {
             if button.powered {
                 button.powered = false;
-                world.set_block(pos, Block::StoneButton { button });
+                set_block(world, pos, Block::StoneButton { button });
                 update_surrounding_blocks(world, pos);
                 match button.face {
                     ButtonFace::Ceiling => {
@@ -274,39 +317,88 @@ pub fn tick(block: Block, world: &mut impl World, pos: BlockPos) {
                 }
             }
         }
+        Block::Piston { extended, facing } | Block::StickyPiston { extended, facing } => {
+            piston::tick(world, pos, extended, facing);
+        }
+        Block::Observer { facing, powered } => {
+            observer::tick(world, pos, facing, powered);
+        }
+        Block::Target { power } => {
+            target::tick(world, pos, power);
+        }
         _ => {}
     }
 }
 
+/// Sets the block at `pos` and lets the observers that are watching it know if it changed.
+/// Returns true if the block changed.
+pub fn set_block(world: &mut impl World, pos: BlockPos, block: Block) -> bool {
+    let changed = world.set_block(pos, block);
+    if changed {
+        observer::notify(world, pos);
+    }
+    changed
+}
+
+/// Queues `updates` and processes them along with every update they cause. If updates are
+/// already being processed further up the call stack, they are processed right after the
+/// current update instead, which is the same order they would be processed in recursively.
+pub fn queue_updates(world: &mut impl World, updates: impl IntoIterator<Item = BlockUpdate>) {
+    let mut process = false;
+    for block_update in updates {
+        process |= world.update_queue().push(block_update);
+    }
+    if !process {
+        return;
+    }
+
+    world.update_queue().start_processing();
+    while let Some(block_update) = world.update_queue().next() {
+        match block_update {
+            BlockUpdate::Update(pos) => {
+                let block = world.get_block(pos);
+                update(block, world, pos);
+            }
+            BlockUpdate::Change(pos, direction) => {
+                let block = world.get_block(pos);
+                interaction::change(block, world, pos, direction);
+            }
+        }
+    }
+}
+
 pub fn update_wire_neighbors(world: &mut impl World, pos: BlockPos) {
+    let mut updates = Vec::with_capacity(42);
+    if world.locational_updates() {
+        for pos in update_order::wire_update_set(pos) {
+            for direction in update_order::NEIGHBOR_ORDER {
+                updates.push(BlockUpdate::Update(pos.offset(direction)));
+            }
+        }
+        queue_updates(world, updates);
+        return;
+    }
     for direction in &BlockFace::values() {
         let neighbor_pos = pos.offset(*direction);
-        let block = world.get_block(neighbor_pos);
-        update(block, world, neighbor_pos);
+        updates.push(BlockUpdate::Update(neighbor_pos));
         for n_direction in &BlockFace::values() {
-            let n_neighbor_pos = neighbor_pos.offset(*n_direction);
-            let block = world.get_block(n_neighbor_pos);
-            update(block, world, n_neighbor_pos);
+            updates.push(BlockUpdate::Update(neighbor_pos.offset(*n_direction)));
         }
     }
+    queue_updates(world, updates);
 }
 
 pub fn update_surrounding_blocks(world: &mut impl World, pos: BlockPos) {
+    let mut updates = Vec::with_capacity(18);
     for direction in &BlockFace::values() {
         let neighbor_pos = pos.offset(*direction);
-        let block = world.get_block(neighbor_pos);
-        update(block, world, neighbor_pos);
+        updates.push(BlockUpdate::Update(neighbor_pos));
 
         // Also update diagonal blocks
-
-        let up_pos = neighbor_pos.offset(BlockFace::Top);
-        let up_block = world.get_block(up_pos);
-        update(up_block, world, up_pos);
-
-        let down_pos = neighbor_pos.offset(BlockFace::Bottom);
-        let down_block = world.get_block(down_pos);
-        update(down_block, world, down_pos);
+        updates.push(BlockUpdate::Update(neighbor_pos.offset(BlockFace::Top)));
+        updates.push(BlockUpdate::Update(neighbor_pos.offset(BlockFace::Bottom)));
     }
+    queue_updates(world, updates);
 }
 
 pub fn has_comparator_override(block: Block) -> bool {
@@ -0,0 +1,89 @@
+//! Observers watch the block in front of them and emit a short pulse out of their back when
+//! that block changes. Like in vanilla, they react to any change of the block state, not to
+//! block updates, so changes have to go through [`super::set_block`] to be seen.
+
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+use mchprs_world::TickPriority;
+
+/// Schedules a pulse for every observer that is watching `pos`
+pub fn notify(world: &mut impl World, pos: BlockPos) {
+    for face in &BlockFace::values() {
+        let observer_pos = pos.offset(*face);
+        if let Block::Observer {
+            facing,
+            powered: false,
+        } = world.get_block(observer_pos)
+        {
+            if facing.offset_pos(observer_pos, 1) == pos && !world.pending_tick_at(observer_pos) {
+                world.schedule_tick(observer_pos, 1, TickPriority::Normal);
+            }
+        }
+    }
+}
+
+pub fn tick(world: &mut impl World, pos: BlockPos, facing: BlockFacing, powered: bool) {
+    let block = Block::Observer {
+        facing,
+        powered: !powered,
+    };
+    super::set_block(world, pos, block);
+    if !powered {
+        world.schedule_tick(pos, 1, TickPriority::Normal);
+    }
+    update_neighbors_in_front(world, pos, facing);
+}
+
+/// Updates the block that is powered by the observer and the blocks around it
+fn update_neighbors_in_front(world: &mut impl World, pos: BlockPos, facing: BlockFacing) {
+    let front_pos = facing.opposite().offset_pos(pos, 1);
+    let front_block = world.get_block(front_pos);
+    super::update(front_block, world, front_pos);
+    for face in &BlockFace::values() {
+        let neighbor_pos = front_pos.offset(*face);
+        if neighbor_pos == pos {
+            continue;
+        }
+        let block = world.get_block(neighbor_pos);
+        super::update(block, world, neighbor_pos);
+    }
+}
+
+#[test]
+fn observers_pulse_when_the_observed_block_changes() {
+    use crate::interaction;
+    use crate::plot::{assert_redpiler_resumes_identically, redpiler_test_world, PlotWorld};
+    use mchprs_blocks::blocks::{Lever, LeverFace};
+    use mchprs_blocks::BlockDirection;
+
+    // A lever watched by an observer, which powers a lamp behind it
+    let lever_pos = BlockPos::new(9, 8, 10);
+    let observer_pos = BlockPos::new(10, 8, 10);
+    let lamp_pos = BlockPos::new(11, 8, 10);
+    let build = |world: &mut PlotWorld| {
+        let lever = Lever::new(LeverFace::Floor, BlockDirection::North, false);
+        world.set_block(lever_pos, Block::Lever { lever });
+        world.set_block(
+            observer_pos,
+            Block::Observer {
+                facing: BlockFacing::West,
+                powered: false,
+            },
+        );
+        world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+        interaction::flip_lever(world, lever_pos, lever);
+    };
+
+    let mut world = redpiler_test_world();
+    build(&mut world);
+    let mut lit = Vec::new();
+    for _ in 0..5 {
+        world.tick_interpreted();
+        lit.push(world.get_block(lamp_pos) == Block::RedstoneLamp { lit: true });
+    }
+    // The pulse lasts one tick, after which the lamp takes two ticks to turn off
+    assert_eq!(lit, [true, true, true, false, false]);
+
+    assert_redpiler_resumes_identically(build, &[observer_pos, lamp_pos], 5);
+}
@@ -0,0 +1,265 @@
+//! Pistons and sticky pistons.
+//!
+//! Unlike vanilla, blocks are not turned into moving pistons while they move. A piston that
+//! gets powered or unpowered schedules a tick, and the blocks are moved to their destination
+//! when that tick runs. This gives the same 2 game tick delay as vanilla, but blocks never
+//! appear in between their old and new position.
+
+use crate::world::World;
+use mchprs_blocks::blocks::{Block, PistonHead};
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+use mchprs_world::TickPriority;
+
+/// The maximum amount of blocks a piston can push
+const MAX_PUSHED_BLOCKS: usize = 12;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum PushBehavior {
+    /// The block moves along with the piston
+    Normal,
+    /// The block is destroyed when a piston pushes into it
+    Destroy,
+    /// The block can't be moved, and stops the piston from extending
+    Block,
+    Empty,
+}
+
+fn push_behavior(block: Block) -> PushBehavior {
+    match block {
+        Block::Air {} => PushBehavior::Empty,
+        Block::RedstoneWire { .. }
+        | Block::RedstoneTorch { .. }
+        | Block::RedstoneWallTorch { .. }
+        | Block::RedstoneRepeater { .. }
+        | Block::RedstoneComparator { .. }
+        | Block::Lever { .. }
+        | Block::StoneButton { .. }
+        | Block::StonePressurePlate { .. }
+        | Block::TripwireHook { .. }
+        | Block::SeaPickle { .. }
+        | Block::Cake { .. } => PushBehavior::Destroy,
+        Block::Piston { extended: true, .. }
+        | Block::StickyPiston { extended: true, .. }
+        | Block::PistonHead { .. }
+        | Block::MovingPiston { .. }
+        | Block::DaylightDetector { .. }
+        | Block::Unknown { .. } => PushBehavior::Block,
+        // Moving block entities isn't supported
+        block if block.has_block_entity() => PushBehavior::Block,
+        block if block.can_place_block_in() => PushBehavior::Destroy,
+        _ => PushBehavior::Normal,
+    }
+}
+
+fn piston_block(sticky: bool, extended: bool, facing: BlockFacing) -> Block {
+    if sticky {
+        Block::StickyPiston { extended, facing }
+    } else {
+        Block::Piston { extended, facing }
+    }
+}
+
+/// Pistons are quasi-connected: they are also powered by anything that would power the block
+/// above them.
+pub fn should_extend(world: &impl World, pos: BlockPos, facing: BlockFacing) -> bool {
+    for face in &BlockFace::values() {
+        if *face == facing.block_face() {
+            continue;
+        }
+        let neighbor_pos = pos.offset(*face);
+        if super::get_redstone_power(world.get_block(neighbor_pos), world, neighbor_pos, *face) > 0
+        {
+            return true;
+        }
+    }
+
+    let up_pos = pos.offset(BlockFace::Top);
+    for face in &BlockFace::values() {
+        if *face == BlockFace::Bottom {
+            continue;
+        }
+        let neighbor_pos = up_pos.offset(*face);
+        if super::get_redstone_power(world.get_block(neighbor_pos), world, neighbor_pos, *face) > 0
+        {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn update(world: &mut impl World, pos: BlockPos, extended: bool, facing: BlockFacing) {
+    if extended != should_extend(world, pos, facing) && !world.pending_tick_at(pos) {
+        world.schedule_tick(pos, 1, TickPriority::Normal);
+    }
+}
+
+pub fn tick(world: &mut impl World, pos: BlockPos, extended: bool, facing: BlockFacing) {
+    let should_extend = should_extend(world, pos, facing);
+    if should_extend != extended {
+        for changed_pos in set_extended(world, pos, should_extend) {
+            super::observer::notify(world, changed_pos);
+            super::update_surrounding_blocks(world, changed_pos);
+        }
+    }
+}
+
+/// Returns the positions of the blocks that will be pushed, starting with the block right in
+/// front of `pos`, or `None` if the piston can't extend.
+pub fn blocks_to_push(
+    world: &impl World,
+    pos: BlockPos,
+    facing: BlockFacing,
+) -> Option<Vec<BlockPos>> {
+    let mut pushed = Vec::new();
+    let mut block_pos = facing.offset_pos(pos, 1);
+    loop {
+        match push_behavior(world.get_block(block_pos)) {
+            PushBehavior::Empty | PushBehavior::Destroy => return Some(pushed),
+            PushBehavior::Block => return None,
+            PushBehavior::Normal => {
+                if pushed.len() == MAX_PUSHED_BLOCKS {
+                    return None;
+                }
+                pushed.push(block_pos);
+                block_pos = facing.offset_pos(block_pos, 1);
+            }
+        }
+    }
+}
+
+/// Returns true if a sticky piston would pull the block back when retracting
+pub fn is_pullable(block: Block) -> bool {
+    push_behavior(block) == PushBehavior::Normal
+}
+
+/// Extends or retracts the piston at `pos` and moves the blocks in front of it, without
+/// updating any neighbors. Returns the positions that changed, which is empty if the piston
+/// already was in that state or if it is not able to extend.
+pub fn set_extended(world: &mut impl World, pos: BlockPos, extended: bool) -> Vec<BlockPos> {
+    let (sticky, facing) = match world.get_block(pos) {
+        Block::Piston {
+            extended: current,
+            facing,
+        } if current != extended => (false, facing),
+        Block::StickyPiston {
+            extended: current,
+            facing,
+        } if current != extended => (true, facing),
+        _ => return Vec::new(),
+    };
+    if extended {
+        extend(world, pos, sticky, facing)
+    } else {
+        retract(world, pos, sticky, facing)
+    }
+}
+
+fn extend(
+    world: &mut impl World,
+    pos: BlockPos,
+    sticky: bool,
+    facing: BlockFacing,
+) -> Vec<BlockPos> {
+    let pushed = match blocks_to_push(world, pos, facing) {
+        Some(pushed) => pushed,
+        None => return Vec::new(),
+    };
+
+    let end_pos = facing.offset_pos(pos, pushed.len() as i32 + 1);
+    if world.get_block(end_pos) != (Block::Air {}) {
+        world.delete_block_entity(end_pos);
+        world.set_block(end_pos, Block::Air {});
+    }
+    for &block_pos in pushed.iter().rev() {
+        let block = world.get_block(block_pos);
+        world.set_block(facing.offset_pos(block_pos, 1), block);
+    }
+
+    let head = PistonHead {
+        facing,
+        short: false,
+        sticky,
+    };
+    world.set_block(facing.offset_pos(pos, 1), Block::PistonHead { head });
+    world.set_block(pos, piston_block(sticky, true, facing));
+
+    let mut changed = vec![pos];
+    changed.extend((1..=pushed.len() as i32 + 1).map(|offset| facing.offset_pos(pos, offset)));
+    changed
+}
+
+fn retract(
+    world: &mut impl World,
+    pos: BlockPos,
+    sticky: bool,
+    facing: BlockFacing,
+) -> Vec<BlockPos> {
+    let head_pos = facing.offset_pos(pos, 1);
+    if matches!(world.get_block(head_pos), Block::PistonHead { head } if head.facing == facing) {
+        world.set_block(head_pos, Block::Air {});
+    }
+
+    let pull_pos = facing.offset_pos(pos, 2);
+    let pulled = sticky
+        && world.get_block(head_pos) == (Block::Air {})
+        && is_pullable(world.get_block(pull_pos));
+    if pulled {
+        let block = world.get_block(pull_pos);
+        world.set_block(head_pos, block);
+        world.set_block(pull_pos, Block::Air {});
+    }
+    world.set_block(pos, piston_block(sticky, false, facing));
+
+    let mut changed = vec![pos, head_pos];
+    if pulled {
+        changed.push(pull_pos);
+    }
+    changed
+}
+
+#[test]
+fn sticky_piston_pushes_and_pulls() {
+    use crate::interaction;
+    use crate::plot::redpiler_test_world;
+    use mchprs_blocks::blocks::{Lever, LeverFace};
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(9, 8, 10);
+    let piston_pos = BlockPos::new(10, 8, 10);
+    let lever = Lever::new(LeverFace::Floor, BlockDirection::North, false);
+    world.set_block(lever_pos, Block::Lever { lever });
+    world.set_block(
+        piston_pos,
+        Block::StickyPiston {
+            extended: false,
+            facing: BlockFacing::East,
+        },
+    );
+    world.set_block(BlockPos::new(11, 8, 10), Block::Stone {});
+
+    interaction::flip_lever(&mut world, lever_pos, lever);
+    world.tick_interpreted();
+    let head = PistonHead {
+        facing: BlockFacing::East,
+        short: false,
+        sticky: true,
+    };
+    assert_eq!(
+        world.get_block(BlockPos::new(11, 8, 10)),
+        Block::PistonHead { head }
+    );
+    assert_eq!(world.get_block(BlockPos::new(12, 8, 10)), Block::Stone {});
+
+    let Block::Lever { lever } = world.get_block(lever_pos) else {
+        unreachable!()
+    };
+    interaction::flip_lever(&mut world, lever_pos, lever);
+    world.tick_interpreted();
+    assert_eq!(world.get_block(BlockPos::new(11, 8, 10)), Block::Stone {});
+    assert_eq!(world.get_block(BlockPos::new(12, 8, 10)), Block::Air {});
+
+    // Quasi-connectivity: a power source next to the block above also powers the piston
+    world.set_block(BlockPos::new(10, 9, 11), Block::RedstoneBlock {});
+    assert!(should_extend(&world, piston_pos, BlockFacing::East));
+}
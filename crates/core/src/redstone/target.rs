@@ -0,0 +1,107 @@
+//! Target blocks output a signal for a short time after they are hit. The closer the hit is to
+//! the center of the face, the stronger the signal. The server doesn't simulate projectiles, so
+//! players hit them by using them with an empty hand instead.
+
+use crate::config::CONFIG;
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockPos};
+use mchprs_world::TickPriority;
+
+/// How long a target stays powered after a hit, in redstone ticks. Redpiler can't schedule
+/// ticks further than 15 ticks ahead, so longer pulses are cut short.
+pub fn pulse_ticks() -> u32 {
+    CONFIG.target_pulse_ticks.clamp(1, 15) as u32
+}
+
+/// Calculates the signal strength of a hit like vanilla does. `cursor` is the position of the
+/// hit relative to the block, with every component between 0 and 1.
+pub fn hit_strength(face: BlockFace, cursor: (f32, f32, f32)) -> u8 {
+    let (x, y, z) = cursor;
+    let dx = (x - 0.5).abs();
+    let dy = (y - 0.5).abs();
+    let dz = (z - 0.5).abs();
+    let distance = match face {
+        BlockFace::Top | BlockFace::Bottom => dx.max(dz),
+        BlockFace::North | BlockFace::South => dx.max(dy),
+        BlockFace::East | BlockFace::West => dy.max(dz),
+    };
+    let strength = (15.0 * ((0.5 - distance) / 0.5).clamp(0.0, 1.0)).ceil() as u8;
+    strength.max(1)
+}
+
+/// Powers the target with `power` for `ticks` redstone ticks. Like in vanilla, hits are ignored
+/// while the target is still powered from an earlier hit.
+pub fn hit(world: &mut impl World, pos: BlockPos, power: u8, ticks: u32) {
+    if world.pending_tick_at(pos) {
+        return;
+    }
+    super::set_block(world, pos, Block::Target { power });
+    world.schedule_tick(pos, ticks, TickPriority::Normal);
+    super::update_surrounding_blocks(world, pos);
+}
+
+pub fn tick(world: &mut impl World, pos: BlockPos, power: u8) {
+    if power != 0 {
+        super::set_block(world, pos, Block::Target { power: 0 });
+        super::update_surrounding_blocks(world, pos);
+    }
+}
+
+#[test]
+fn target_blocks_output_the_strength_of_the_hit() {
+    use crate::plot::{compile_test_world, redpiler_test_world, PlotWorld};
+
+    // The closer to the center of the face, the stronger the signal
+    assert_eq!(hit_strength(BlockFace::Top, (0.5, 1.0, 0.5)), 15);
+    assert_eq!(hit_strength(BlockFace::Top, (0.75, 1.0, 0.5)), 8);
+    assert_eq!(hit_strength(BlockFace::North, (0.5, 0.0, 0.0)), 1);
+
+    let target_pos = BlockPos::new(10, 8, 10);
+    let wire_pos = BlockPos::new(11, 8, 10);
+    let build = |world: &mut PlotWorld| {
+        world.set_block(target_pos, Block::Target { power: 0 });
+        world.set_block(
+            wire_pos,
+            Block::RedstoneWire {
+                wire: Default::default(),
+            },
+        );
+    };
+    let wire_power = |world: &PlotWorld| match world.get_block(wire_pos) {
+        Block::RedstoneWire { wire } => wire.power,
+        block => panic!("expected wire, found {:?}", block),
+    };
+
+    let mut world = redpiler_test_world();
+    build(&mut world);
+    hit(&mut world, target_pos, 8, 3);
+    assert_eq!(wire_power(&world), 8);
+    // Hits are ignored until the pulse is over
+    hit(&mut world, target_pos, 15, 3);
+    assert_eq!(wire_power(&world), 8);
+    let mut powers = Vec::new();
+    for _ in 0..3 {
+        world.tick_interpreted();
+        powers.push(wire_power(&world));
+    }
+    assert_eq!(powers, [8, 8, 0]);
+    assert_eq!(world.get_block(target_pos), Block::Target { power: 0 });
+
+    let mut world = redpiler_test_world();
+    build(&mut world);
+    let mut redpiler = compile_test_world(&mut world, Default::default());
+    redpiler.hit_target(target_pos, 8, 3);
+    redpiler.hit_target(target_pos, 15, 3);
+    redpiler.flush(&mut world);
+    assert_eq!(redpiler.get_signal_strength(target_pos), Some(8));
+    assert_eq!(wire_power(&world), 8);
+    let mut powers = Vec::new();
+    for _ in 0..3 {
+        redpiler.tick();
+        redpiler.flush(&mut world);
+        powers.push(wire_power(&world));
+    }
+    assert_eq!(powers, [8, 8, 0]);
+    assert_eq!(world.get_block(target_pos), Block::Target { power: 0 });
+}
@@ -0,0 +1,131 @@
+//! Vanilla's locational update order for dust. When dust changes, vanilla collects the dust and
+//! its six neighbors in a `HashSet` and updates the neighbors of each of them in the iteration
+//! order of that set. That order depends on the hash of the positions, so the same circuit can
+//! behave differently depending on where it is built. Circuits that rely on it only work when
+//! the order is emulated, which is done when the `locational-updates` feature is enabled.
+//!
+//! Redpiler doesn't emulate this order, so circuits that could depend on it are warned about
+//! when they are compiled.
+
+use crate::world::World;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockPos};
+
+/// The order of `Direction.values()` in vanilla, in which dust adds its neighbors to the set
+const SET_ORDER: [BlockFace; 6] = [
+    BlockFace::Bottom,
+    BlockFace::Top,
+    BlockFace::North,
+    BlockFace::South,
+    BlockFace::West,
+    BlockFace::East,
+];
+
+/// The order in which vanilla updates the neighbors of a block
+pub const NEIGHBOR_ORDER: [BlockFace; 6] = [
+    BlockFace::West,
+    BlockFace::East,
+    BlockFace::Bottom,
+    BlockFace::Top,
+    BlockFace::North,
+    BlockFace::South,
+];
+
+/// `Vec3i.hashCode()` spread like `HashMap.hash()` does
+fn java_hash(pos: BlockPos) -> i32 {
+    let h = pos
+        .y
+        .wrapping_add(pos.z.wrapping_mul(31))
+        .wrapping_mul(31)
+        .wrapping_add(pos.x);
+    h ^ ((h as u32) >> 16) as i32
+}
+
+/// Sorts `positions` in the order a java `HashSet` they were inserted into iterates over them
+fn hash_set_order(positions: &mut [BlockPos]) {
+    // The table starts with 16 buckets and doubles when it is more than 3/4 full
+    let mut capacity = 16;
+    while positions.len() * 4 > capacity * 3 {
+        capacity *= 2;
+    }
+    // The sort is stable, so positions in the same bucket stay in insertion order
+    positions.sort_by_key(|&pos| java_hash(pos) & (capacity as i32 - 1));
+}
+
+/// The blocks whose neighbors are updated when the dust at `pos` changes, in vanilla's order
+pub fn wire_update_set(pos: BlockPos) -> [BlockPos; 7] {
+    let mut set = [pos; 7];
+    for (i, face) in SET_ORDER.iter().enumerate() {
+        set[i + 1] = pos.offset(*face);
+    }
+    hash_set_order(&mut set);
+    set
+}
+
+/// Whether a block schedules a tick when it is updated, which makes the order of updates
+/// observable in the order of the ticks
+fn schedules_ticks(block: Block) -> bool {
+    matches!(
+        block,
+        Block::RedstoneRepeater { .. }
+            | Block::RedstoneComparator { .. }
+            | Block::RedstoneTorch { .. }
+            | Block::RedstoneWallTorch { .. }
+            | Block::RedstoneLamp { .. }
+            | Block::Observer { .. }
+    )
+}
+
+/// Counts the dust between `first_pos` and `second_pos` that updates more than one block that
+/// schedules ticks. The order those blocks are updated in, and so the order of their ticks,
+/// depends on the locational update order.
+pub fn count_order_dependent_wires(
+    world: &impl World,
+    first_pos: BlockPos,
+    second_pos: BlockPos,
+) -> usize {
+    let start = first_pos.min(second_pos);
+    let end = first_pos.max(second_pos);
+    let mut count = 0;
+    for y in start.y..=end.y {
+        for z in start.z..=end.z {
+            for x in start.x..=end.x {
+                let pos = BlockPos::new(x, y, z);
+                if !matches!(world.get_block(pos), Block::RedstoneWire { .. }) {
+                    continue;
+                }
+                let mut updated = Vec::new();
+                for neighbor in wire_update_set(pos)
+                    .iter()
+                    .flat_map(|&pos| NEIGHBOR_ORDER.map(|face| pos.offset(face)))
+                {
+                    if schedules_ticks(world.get_block(neighbor)) && !updated.contains(&neighbor) {
+                        updated.push(neighbor);
+                    }
+                }
+                if updated.len() > 1 {
+                    count += 1;
+                }
+            }
+        }
+    }
+    count
+}
+
+#[test]
+fn wire_update_set_follows_java_hash_set_order() {
+    // Worked out from the hashes of the positions. The center, north and west all land in the
+    // first bucket, so they stay in the order they were inserted in
+    let pos = BlockPos::new(0, 0, 0);
+    let set = wire_update_set(pos);
+    let expected = [
+        pos,
+        BlockPos::new(0, 0, -1),
+        BlockPos::new(-1, 0, 0),
+        BlockPos::new(0, 0, 1),
+        BlockPos::new(1, 0, 0),
+        BlockPos::new(0, -1, 0),
+        BlockPos::new(0, 1, 0),
+    ];
+    assert_eq!(set, expected);
+}
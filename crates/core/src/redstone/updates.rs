@@ -0,0 +1,182 @@
+//! Block updates that cause more block updates are not processed recursively. Instead, they
+//! are pushed onto an explicit stack that is processed by whoever queued the first update. The
+//! updates are still processed in the same order as if they were called recursively, but long
+//! chains of updates can't overflow the stack anymore.
+//!
+//! During a bulk edit, see [`crate::world::World::begin_bulk_edit`], updates are not processed
+//! at all. They are deferred until the bulk edit ends and then processed once each, no matter
+//! how many of the edited blocks caused them.
+//!
+//! The amount of updates that are processed each tick is limited by a budget. When a plot runs
+//! out of budget, the rest of the queued updates are dropped so that the server stays
+//! responsive, and the players in the plot are told about it.
+
+use mchprs_blocks::{BlockFace, BlockPos};
+use rustc_hash::FxHashSet;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Players are told about dropped updates at most this often
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockUpdate {
+    /// The block checks if it should change its state, see [`super::update`]
+    Update(BlockPos),
+    /// The neighbor of the block in `direction` was placed or removed, see
+    /// [`crate::interaction::change`]
+    Change(BlockPos, BlockFace),
+}
+
+pub struct UpdateQueue {
+    /// The updates that still have to be processed, the next one is at the end
+    stack: Vec<BlockUpdate>,
+    /// The updates that were queued while processing the current update, in the order they
+    /// were queued. They are processed before the rest of the stack.
+    queued: Vec<BlockUpdate>,
+    processing: bool,
+    /// The maximum amount of updates per tick
+    budget: u64,
+    /// The amount of updates that were processed since the start of the tick
+    processed: u64,
+    /// Where the budget first ran out since the last report
+    dropped_at: Option<BlockPos>,
+    last_report: Option<Instant>,
+    /// The amount of bulk edits that were started and not ended yet
+    bulk_edits: u32,
+    /// The updates that are deferred until the bulk edit ends, in the order they were queued
+    deferred: Vec<BlockUpdate>,
+    deferred_set: FxHashSet<BlockUpdate>,
+}
+
+impl UpdateQueue {
+    pub fn new(budget: u64) -> UpdateQueue {
+        UpdateQueue {
+            stack: Vec::new(),
+            queued: Vec::new(),
+            processing: false,
+            budget,
+            processed: 0,
+            dropped_at: None,
+            last_report: None,
+            bulk_edits: 0,
+            deferred: Vec::new(),
+            deferred_set: FxHashSet::default(),
+        }
+    }
+
+    /// Resets the amount of updates that can be processed
+    pub fn start_tick(&mut self) {
+        self.processed = 0;
+    }
+
+    /// Queues an update. Returns true if the caller has to process the queue, which is the case
+    /// when it isn't already being processed further up the call stack.
+    pub(super) fn push(&mut self, update: BlockUpdate) -> bool {
+        if self.bulk_edits > 0 {
+            if self.deferred_set.insert(update) {
+                self.deferred.push(update);
+            }
+            return false;
+        }
+        self.queued.push(update);
+        !self.processing
+    }
+
+    pub(crate) fn begin_bulk_edit(&mut self) {
+        self.bulk_edits += 1;
+    }
+
+    /// Returns the deferred updates once the outermost bulk edit ends
+    pub(crate) fn end_bulk_edit(&mut self) -> Vec<BlockUpdate> {
+        self.bulk_edits = self.bulk_edits.saturating_sub(1);
+        if self.bulk_edits > 0 {
+            return Vec::new();
+        }
+        self.deferred_set.clear();
+        std::mem::take(&mut self.deferred)
+    }
+
+    pub(super) fn start_processing(&mut self) {
+        self.processing = true;
+    }
+
+    /// Returns the next update to process, or `None` once the queue is empty
+    pub(super) fn next(&mut self) -> Option<BlockUpdate> {
+        self.stack.extend(self.queued.drain(..).rev());
+        let Some(update) = self.stack.pop() else {
+            self.processing = false;
+            return None;
+        };
+        if self.processed >= self.budget {
+            if self.dropped_at.is_none() {
+                let (BlockUpdate::Update(pos) | BlockUpdate::Change(pos, _)) = update;
+                warn!(
+                    "Ran out of the budget of {} block updates at {}, dropping {} updates",
+                    self.budget,
+                    pos,
+                    self.stack.len() + 1
+                );
+                self.dropped_at = Some(pos);
+            }
+            self.stack.clear();
+            self.processing = false;
+            return None;
+        }
+        self.processed += 1;
+        Some(update)
+    }
+
+    /// Returns where updates were dropped because the budget ran out, if it has been long
+    /// enough since this was last reported
+    pub fn take_dropped(&mut self) -> Option<BlockPos> {
+        if self
+            .last_report
+            .map_or(false, |last| last.elapsed() < REPORT_INTERVAL)
+        {
+            return None;
+        }
+        let pos = self.dropped_at.take()?;
+        self.last_report = Some(Instant::now());
+        Some(pos)
+    }
+}
+
+impl Default for UpdateQueue {
+    /// A queue without a limit on the amount of updates
+    fn default() -> UpdateQueue {
+        UpdateQueue::new(u64::MAX)
+    }
+}
+
+#[test]
+fn block_updates_over_budget_are_dropped() {
+    use crate::interaction;
+    use crate::plot::redpiler_test_world;
+    use crate::redstone;
+    use crate::world::World;
+    use mchprs_blocks::blocks::{Block, Lever, LeverFace};
+    use mchprs_blocks::BlockDirection;
+
+    let mut world = redpiler_test_world();
+    let lever_pos = BlockPos::new(10, 8, 10);
+    let lamp_pos = BlockPos::new(11, 8, 10);
+    let lever = Lever::new(LeverFace::Floor, BlockDirection::North, false);
+    world.set_block(lever_pos, Block::Lever { lever });
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    // The lamp to the east is only updated after the blocks above, below, north and south
+    world.update_queue = UpdateQueue::new(3);
+    interaction::flip_lever(&mut world, lever_pos, lever);
+    assert_eq!(
+        world.get_block(lamp_pos),
+        Block::RedstoneLamp { lit: false }
+    );
+    assert!(world.update_queue.take_dropped().is_some());
+
+    // The lever stays powered, so the lamp turns on once it is updated
+    world.update_queue = UpdateQueue::new(100);
+    redstone::update_surrounding_blocks(&mut world, lever_pos);
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+    assert!(world.update_queue.take_dropped().is_none());
+}
@@ -28,49 +28,29 @@ pub fn get_state_for_placement(world: &impl World, pos: BlockPos) -> RedstoneWir
     wire
 }
 
+/// Updates the shape of the wire after the block on `side` of it changed. Like in vanilla, a
+/// side that stays connected or disconnected is updated on its own, while a change in
+/// connectivity or a change next to a cross reshapes the whole wire.
 pub fn on_neighbor_changed(
     mut wire: RedstoneWire,
     world: &impl World,
     pos: BlockPos,
     side: BlockFace,
 ) -> RedstoneWire {
-    let old_state = wire;
-    let new_side;
-    match side {
+    let direction = match side {
         BlockFace::Top => return wire,
-        BlockFace::Bottom => {
-            return get_regulated_sides(wire, world, pos);
-        }
-        BlockFace::North => {
-            wire.south = get_side(world, pos, BlockDirection::South);
-            new_side = wire.south;
-        }
-        BlockFace::South => {
-            wire.north = get_side(world, pos, BlockDirection::North);
-            new_side = wire.north;
-        }
-
-        BlockFace::East => {
-            wire.west = get_side(world, pos, BlockDirection::West);
-            new_side = wire.west;
-        }
-        BlockFace::West => {
-            wire.east = get_side(world, pos, BlockDirection::East);
-            new_side = wire.east;
-        }
-    }
-    wire = get_regulated_sides(wire, world, pos);
-    if is_cross(old_state) && new_side.is_none() {
-        // Don't mess up the cross
-        return old_state;
-    }
-    if !is_dot(old_state) && is_dot(wire) {
-        // Save the power until the transformation into cross is complete
-        let power = wire.power;
-        // Become the cross it always wanted to be
-        wire = make_cross(power);
+        BlockFace::Bottom => return get_regulated_sides(wire, world, pos),
+        _ => side.to_direction().opposite(),
+    };
+    let new_side = get_side(world, pos, direction);
+    let old_side = get_current_side(wire, direction);
+    if new_side.is_none() == old_side.is_none() && !is_cross(wire) {
+        *side_mut(&mut wire, direction) = new_side;
+        return wire;
     }
-    wire
+    let mut cross = make_cross(wire.power);
+    *side_mut(&mut cross, direction) = new_side;
+    get_regulated_sides(cross, world, pos)
 }
 
 pub fn on_neighbor_updated(mut wire: RedstoneWire, world: &mut impl World, pos: BlockPos) {
@@ -78,7 +58,7 @@ pub fn on_neighbor_updated(mut wire: RedstoneWire, world: &mut impl World, pos:
 
     if wire.power != new_power {
         wire.power = new_power;
-        world.set_block(pos, Block::RedstoneWire { wire });
+        super::set_block(world, pos, Block::RedstoneWire { wire });
         RedstoneWireTurbo::update_surrounding_neighbors(world, pos);
     }
 }
@@ -93,7 +73,7 @@ pub fn on_use(wire: RedstoneWire, world: &mut impl World, pos: BlockPos) -> Acti
         new_wire.power = wire.power;
         new_wire = get_regulated_sides(new_wire, world, pos);
         if wire != new_wire {
-            world.set_block(pos, Block::RedstoneWire { wire: new_wire });
+            super::set_block(world, pos, Block::RedstoneWire { wire: new_wire });
             super::update_wire_neighbors(world, pos);
             return ActionResult::Success;
         }
@@ -112,11 +92,12 @@ fn can_connect_to(block: Block, side: BlockDirection) -> bool {
         | Block::TripwireHook { .. }
         | Block::StoneButton { .. }
         | Block::Target { .. }
+        | Block::DaylightDetector { .. }
         | Block::Lever { .. } => true,
         Block::RedstoneRepeater { repeater } => {
             repeater.facing == side || repeater.facing == side.opposite()
         }
-        Block::Observer { facing } => facing == side.block_facing(),
+        Block::Observer { facing, .. } => facing == side.block_facing(),
         _ => false,
     }
 }
@@ -135,23 +116,43 @@ pub fn get_current_side(wire: RedstoneWire, side: BlockDirection) -> RedstoneWir
     }
 }
 
+fn side_mut(wire: &mut RedstoneWire, side: BlockDirection) -> &mut RedstoneWireSide {
+    use BlockDirection::*;
+    match side {
+        North => &mut wire.north,
+        South => &mut wire.south,
+        East => &mut wire.east,
+        West => &mut wire.west,
+    }
+}
+
+/// Dust only climbs up the side of a block if that side is a full face
+fn has_full_side(block: Block) -> bool {
+    block.is_cube()
+        && !matches!(
+            block,
+            Block::SmoothStoneSlab { .. } | Block::QuartzSlab { .. } | Block::Hopper { .. }
+        )
+}
+
 pub fn get_side(world: &impl World, pos: BlockPos, side: BlockDirection) -> RedstoneWireSide {
     let neighbor_pos = pos.offset(side.block_face());
     let neighbor = world.get_block(neighbor_pos);
 
-    if can_connect_to(neighbor, side) {
-        return RedstoneWireSide::Side;
-    }
-
-    let up_pos = pos.offset(BlockFace::Top);
-    let up = world.get_block(up_pos);
-
+    // Dust on top of the neighbor takes precedence over the neighbor itself
+    let up = world.get_block(pos.offset(BlockFace::Top));
     if !up.is_solid()
         && can_connect_diagonal_to(world.get_block(neighbor_pos.offset(BlockFace::Top)))
     {
-        RedstoneWireSide::Up
-    } else if !neighbor.is_solid()
-        && can_connect_diagonal_to(world.get_block(neighbor_pos.offset(BlockFace::Bottom)))
+        return match has_full_side(neighbor) {
+            true => RedstoneWireSide::Up,
+            false => RedstoneWireSide::Side,
+        };
+    }
+
+    if can_connect_to(neighbor, side)
+        || (!neighbor.is_solid()
+            && can_connect_diagonal_to(world.get_block(neighbor_pos.offset(BlockFace::Bottom))))
     {
         RedstoneWireSide::Side
     } else {
@@ -247,3 +248,53 @@ fn calculate_power(world: &impl World, pos: BlockPos) -> u8 {
 
     block_power.max(wire_power.saturating_sub(1))
 }
+
+#[test]
+fn wire_shapes_match_vanilla() {
+    use crate::plot::redpiler_test_world;
+
+    let mut world = redpiler_test_world();
+    let pos = BlockPos::new(10, 8, 10);
+    let dot = RedstoneWire::default();
+    world.set_block(pos, Block::RedstoneWire { wire: dot });
+
+    // A dot stays a dot next to blocks it doesn't connect to
+    world.set_block(BlockPos::new(11, 8, 10), Block::RedstoneLamp { lit: false });
+    assert_eq!(on_neighbor_changed(dot, &world, pos, BlockFace::West), dot);
+    // and turns into a line when something connects to it
+    world.set_block(
+        BlockPos::new(10, 8, 11),
+        Block::RedstoneWire {
+            wire: Default::default(),
+        },
+    );
+    let line = on_neighbor_changed(dot, &world, pos, BlockFace::North);
+    assert_eq!(
+        (line.north, line.south, line.east, line.west),
+        (
+            RedstoneWireSide::Side,
+            RedstoneWireSide::Side,
+            RedstoneWireSide::None,
+            RedstoneWireSide::None
+        )
+    );
+
+    // Dust only climbs up full faces
+    let dust_above = BlockPos::new(9, 9, 10);
+    world.set_block(
+        dust_above,
+        Block::RedstoneWire {
+            wire: Default::default(),
+        },
+    );
+    world.set_block(BlockPos::new(9, 8, 10), Block::Stone {});
+    assert_eq!(
+        get_side(&world, pos, BlockDirection::West),
+        RedstoneWireSide::Up
+    );
+    world.set_block(BlockPos::new(9, 8, 10), Block::SmoothStoneSlab {});
+    assert_eq!(
+        get_side(&world, pos, BlockDirection::West),
+        RedstoneWireSide::Side
+    );
+}
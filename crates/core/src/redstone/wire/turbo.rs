@@ -390,7 +390,7 @@ impl RedstoneWireTurbo {
         }
         if i != j {
             wire.power = j;
-            world.set_block(pos, Block::RedstoneWire { wire });
+            crate::redstone::set_block(world, pos, Block::RedstoneWire { wire });
         }
         wire
     }
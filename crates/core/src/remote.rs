@@ -0,0 +1,348 @@
+//! Remote control of plots, so that external tools like assemblers and test harnesses can drive
+//! the inputs of a circuit and read its outputs. Clients connect with a WebSocket and send one json
+//! request per text message, and every request is answered with one json text message:
+//!
+//! ```json
+//! {"type": "auth", "token": "secret"}
+//! {"id": 1, "plot": [0, 0], "type": "set", "port": "CLK", "powered": true}
+//! {"id": 2, "plot": [0, 0], "type": "step", "ticks": 4}
+//! {"id": 3, "plot": [0, 0], "type": "get", "pos": {"x": 8, "y": 9, "z": 4}}
+//! ```
+//!
+//! The first message of a connection has to authenticate it with `remote_api_token`. The api is
+//! only started if `remote_api_address` is set.
+
+use crate::config::CONFIG;
+use crate::server::Message;
+use crate::websocket::{self, Incoming, MessageReader, Opcode};
+use mchprs_blocks::BlockPos;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{error, info, warn};
+
+/// The most ticks a single `step` request can run
+pub const MAX_STEP_TICKS: u32 = 100_000;
+/// Requests longer than this are rejected and the connection is closed
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// An input or output of a circuit, either by the name of a port or by position
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Target {
+    Port { port: String },
+    Pos { pos: BlockPos },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    /// Turns a lever or pressure plate on or off, or presses a button
+    Set {
+        #[serde(flatten)]
+        target: Target,
+        powered: bool,
+    },
+    /// Reads the signal strength of a block
+    Get {
+        #[serde(flatten)]
+        target: Target,
+    },
+    /// Runs the plot for a number of ticks before answering
+    Step { ticks: u32 },
+    /// Sends a change event every time the signal strength of one of the targets changes
+    Subscribe { targets: Vec<Target> },
+    /// Stops all change events of the plot
+    Unsubscribe,
+    /// Answers with the manifest of the ports of the plot
+    Manifest,
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<u64>,
+    plot: (i32, i32),
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Auth {
+    Auth { token: String },
+}
+
+/// A connected client, which plots send their answers and events to
+#[derive(Debug, Clone)]
+pub struct RemoteClient {
+    pub id: u64,
+    sender: Sender<String>,
+    connected: Arc<AtomicBool>,
+}
+
+impl RemoteClient {
+    /// A client whose messages are sent through `sender` as json text messages
+    pub fn new(id: u64, sender: Sender<String>) -> RemoteClient {
+        RemoteClient {
+            id,
+            sender,
+            connected: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Sends a json message to the client. Returns false if the client disconnected.
+    pub fn send(&self, message: &Value) -> bool {
+        self.is_connected() && self.sender.send(message.to_string()).is_ok()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
+
+/// A request for a plot, which gets sent to the plot thread to be answered there
+#[derive(Debug)]
+pub struct RemoteRequest {
+    pub id: Option<u64>,
+    pub command: Command,
+    pub client: RemoteClient,
+}
+
+impl RemoteRequest {
+    /// Answers the request, merging the fields of `result` into the answer
+    pub fn reply(&self, result: Result<Value, String>) {
+        let mut answer = match result {
+            Ok(Value::Object(fields)) => Value::Object(fields),
+            Ok(Value::Null) => json!({}),
+            Ok(value) => json!({ "value": value }),
+            Err(err) => json!({ "error": err }),
+        };
+        answer["ok"] = json!(answer.get("error").is_none());
+        if let Some(id) = self.id {
+            answer["id"] = json!(id);
+        }
+        self.client.send(&answer);
+    }
+}
+
+/// Starts listening for remote clients if `remote_api_address` is set
+pub fn start(plot_sender: Sender<Message>) {
+    let address = CONFIG.remote_api_address.clone();
+    if address.is_empty() {
+        return;
+    }
+    if CONFIG.remote_api_token.is_empty() {
+        error!("The remote api is not started because `remote_api_token` is not set");
+        return;
+    }
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to bind the remote api to {}: {}", address, err);
+            return;
+        }
+    };
+    info!("Remote api listening on {}", address);
+    thread::Builder::new()
+        .name("Remote api".to_owned())
+        .spawn(move || {
+            let next_id = AtomicU64::new(0);
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept a remote client: {}", err);
+                        continue;
+                    }
+                };
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                let plot_sender = plot_sender.clone();
+                thread::spawn(move || {
+                    if let Err(err) = handle_client(id, stream, plot_sender) {
+                        warn!("Remote client {} disconnected: {}", id, err);
+                    }
+                });
+            }
+        })
+        .unwrap();
+}
+
+fn handle_client(id: u64, stream: TcpStream, plot_sender: Sender<Message>) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    websocket::accept(&mut reader, &mut &stream)?;
+
+    // Pongs and close frames are written by the reader, so the stream is shared with the writer
+    let stream = Arc::new(Mutex::new(stream));
+    let (sender, receiver) = mpsc::channel::<String>();
+    let write_stream = stream.clone();
+    thread::spawn(move || {
+        for message in receiver {
+            let mut stream = write_stream.lock().unwrap();
+            if websocket::write_frame(&mut *stream, Opcode::Text, message.as_bytes()).is_err() {
+                break;
+            }
+        }
+    });
+    let client = RemoteClient::new(id, sender);
+
+    let messages = MessageReader::new(reader, MAX_MESSAGE_LEN);
+    let result = read_requests(&client, messages, &stream, &plot_sender);
+    // The connection is closed by the writer once the answers that are left have been sent and
+    // the plots have dropped the client
+    client.connected.store(false, Ordering::Relaxed);
+    info!("Remote client {} ({}) disconnected", id, peer);
+    result
+}
+
+fn read_requests(
+    client: &RemoteClient,
+    mut messages: MessageReader<BufReader<TcpStream>>,
+    stream: &Mutex<TcpStream>,
+    plot_sender: &Sender<Message>,
+) -> io::Result<()> {
+    let mut authenticated = false;
+    loop {
+        let message = match messages.read() {
+            Ok(Incoming::Text(message)) => message,
+            Ok(Incoming::Ping(payload)) => {
+                websocket::write_frame(&mut *stream.lock().unwrap(), Opcode::Pong, &payload)?;
+                continue;
+            }
+            Ok(Incoming::Close) => {
+                client.connected.store(false, Ordering::Relaxed);
+                let mut stream = stream.lock().unwrap();
+                return websocket::write_close(&mut *stream, websocket::CLOSE_NORMAL, "");
+            }
+            Ok(Incoming::TooLong) => {
+                client.connected.store(false, Ordering::Relaxed);
+                let mut stream = stream.lock().unwrap();
+                return websocket::write_close(
+                    &mut *stream,
+                    websocket::CLOSE_TOO_BIG,
+                    "The request is too long.",
+                );
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if message.trim().is_empty() {
+            continue;
+        }
+
+        if !authenticated {
+            match serde_json::from_str(&message) {
+                Ok(Auth::Auth { token }) if token == CONFIG.remote_api_token => {
+                    authenticated = true;
+                    client.send(&json!({ "ok": true }));
+                    continue;
+                }
+                _ => {
+                    client.send(&json!({ "ok": false, "error": "Authentication failed." }));
+                    return Ok(());
+                }
+            }
+        }
+
+        match parse_request(&message) {
+            Ok(request) => {
+                let (plot_x, plot_z) = request.plot;
+                let request = RemoteRequest {
+                    id: request.id,
+                    command: request.command,
+                    client: client.clone(),
+                };
+                if plot_sender
+                    .send(Message::Remote(plot_x, plot_z, request))
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+            Err(err) => {
+                // Try to answer with the id of the request even if the rest of it is invalid
+                let id = serde_json::from_str::<Value>(&message)
+                    .ok()
+                    .and_then(|value| value.get("id").cloned());
+                let mut answer = json!({ "ok": false, "error": err });
+                if let Some(id) = id {
+                    answer["id"] = id;
+                }
+                client.send(&answer);
+            }
+        }
+    }
+}
+
+fn parse_request(message: &str) -> Result<Request, String> {
+    let request: Request = serde_json::from_str(message).map_err(|err| err.to_string())?;
+    if let Command::Step { ticks } = request.command {
+        if ticks > MAX_STEP_TICKS {
+            return Err(format!(
+                "A single step can run at most {} ticks.",
+                MAX_STEP_TICKS
+            ));
+        }
+    }
+    Ok(request)
+}
+
+#[test]
+fn requests_target_ports_or_positions() {
+    let request = parse_request(
+        r#"{"id": 1, "plot": [0, -2], "type": "set", "port": "CLK", "powered": true}"#,
+    )
+    .unwrap();
+    assert_eq!(request.id, Some(1));
+    assert_eq!(request.plot, (0, -2));
+    assert_eq!(
+        request.command,
+        Command::Set {
+            target: Target::Port {
+                port: "CLK".to_owned()
+            },
+            powered: true
+        }
+    );
+
+    let request =
+        parse_request(r#"{"plot": [1, 1], "type": "get", "pos": {"x": 8, "y": 9, "z": 4}}"#)
+            .unwrap();
+    assert_eq!(request.id, None);
+    assert_eq!(
+        request.command,
+        Command::Get {
+            target: Target::Pos {
+                pos: BlockPos::new(8, 9, 4)
+            }
+        }
+    );
+
+    let request = parse_request(
+        r#"{"plot": [0, 0], "type": "subscribe", "targets": [{"port": "A0"}, {"pos": {"x": 1, "y": 2, "z": 3}}]}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        request.command,
+        Command::Subscribe {
+            targets: vec![
+                Target::Port {
+                    port: "A0".to_owned()
+                },
+                Target::Pos {
+                    pos: BlockPos::new(1, 2, 3)
+                }
+            ]
+        }
+    );
+
+    assert!(parse_request(r#"{"plot": [0, 0], "type": "step", "ticks": 4}"#).is_ok());
+    assert!(parse_request(r#"{"plot": [0, 0], "type": "step", "ticks": 1000000}"#).is_err());
+    assert!(parse_request(r#"{"plot": [0, 0], "type": "set", "powered": true}"#).is_err());
+}
@@ -1,24 +1,30 @@
+use crate::auth::Authenticator;
 use crate::chat::ChatComponent;
 use crate::config::CONFIG;
+use crate::metrics;
 use crate::permissions;
 use crate::player::{Gamemode, PacketSender, Player};
 use crate::plot::commands::DECLARE_COMMANDS;
-use crate::plot::{self, database, Plot, PLOT_BLOCK_HEIGHT};
+use crate::plot::{self, database, Plot, PLOT_SIZE};
+use crate::profile::PlayerProfile;
+use crate::remote::{self, RemoteRequest};
 use crate::utils::HyphenatedUUID;
+use crate::velocity;
 use backtrace::Backtrace;
 use bus::Bus;
 use mchprs_network::packets::clientbound::{
     CDisconnectLogin, CHeldItemChange, CJoinGame, CJoinGameBiomeEffects,
     CJoinGameBiomeEffectsMoodSound, CJoinGameBiomeElement, CJoinGameDimensionCodec,
-    CJoinGameDimensionElement, CLoginSuccess, CPlayerInfo, CPlayerInfoAddPlayer,
-    CPlayerPositionAndLook, CPluginMessage, CPong, CResponse, CSetCompression, CTimeUpdate,
-    CWindowItems, ClientBoundPacket,
+    CJoinGameDimensionElement, CLoginPluginRequest, CLoginSuccess, CPlayerInfo,
+    CPlayerInfoAddPlayer, CPlayerPositionAndLook, CPluginMessage, CPong, CResponse,
+    CSetCompression, CTimeUpdate, CWindowItems, ClientBoundPacket,
 };
 use mchprs_network::packets::serverbound::{
-    SHandshake, SLoginStart, SPing, SRequest, ServerBoundPacketHandler,
+    SEncryptionResponse, SHandshake, SLoginPluginResponse, SLoginStart, SPing, SRequest,
+    ServerBoundPacketHandler,
 };
 use mchprs_network::packets::{PacketEncoderExt, SlotData};
-use mchprs_network::{NetworkServer, NetworkState, PlayerPacketSender};
+use mchprs_network::{HandshakingConn, NetworkServer, NetworkState, PlayerPacketSender};
 use mchprs_utils::map;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
@@ -27,6 +33,7 @@ use std::fs::{self, File};
 use std::path::Path;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
 use tracing::{debug, error, info, warn};
 
 pub const MC_VERSION: &str = "1.18.2";
@@ -57,6 +64,12 @@ pub enum Message {
     WhitelistRemove(u128, PlayerPacketSender),
     /// This message is sent to the server thread when a player runs /stop.
     Shutdown,
+    /// This message is sent to the server thread by the remote api when a client sends a
+    /// request for the plot at the given coordinates.
+    Remote(i32, i32, RemoteRequest),
+    /// This message is sent to the server thread when the session server answered whether the
+    /// player logging in on the connection with the given id owns their account.
+    LoginVerified(u32, anyhow::Result<Option<PlayerProfile>>),
 }
 
 /// `BroadcastMessage` gets broadcasted from the server thread to all the plot threads.
@@ -81,11 +94,13 @@ pub enum BroadcastMessage {
 }
 
 /// `PrivMessage` gets send from the server thread directly to a plot thread.
-/// This only happens when a player is getting transfered to a plot.
+/// This happens when a player is getting transfered to a plot, or when a remote client sends
+/// a request for a plot.
 #[derive(Debug)]
 pub enum PrivMessage {
     PlayerEnterPlot(Player),
     PlayerTeleportOther(Player, String),
+    Remote(RemoteRequest),
 }
 
 /// This is the data that gets sent in the `PlayerJoinedInfo` broadcast message.
@@ -125,6 +140,8 @@ pub struct MinecraftServer {
     online_players: FxHashMap<u128, PlayerListEntry>,
     running_plots: Vec<PlotListEntry>,
     whitelist: Option<Vec<WhitelistEntry>>,
+    /// Only set in online mode
+    auth: Option<Authenticator>,
 }
 
 impl MinecraftServer {
@@ -142,6 +159,7 @@ impl MinecraftServer {
         fs::create_dir_all("./world/players").unwrap();
         fs::create_dir_all("./world/plots").unwrap();
         fs::create_dir_all("./schems").unwrap();
+        fs::create_dir_all("./music").unwrap();
 
         plot::database::init();
 
@@ -167,6 +185,12 @@ impl MinecraftServer {
             .unwrap_or_default()
         });
 
+        let proxied = CONFIG.bungeecord || !CONFIG.velocity_secret.is_empty();
+        if CONFIG.online_mode && proxied {
+            warn!("Online mode is ignored because players are authenticated by the proxy");
+        }
+        let auth = (CONFIG.online_mode && !proxied).then(Authenticator::new);
+
         if let Some(permissions_config) = &CONFIG.luckperms {
             permissions::init(permissions_config.clone()).unwrap();
         }
@@ -180,6 +204,7 @@ impl MinecraftServer {
             online_players: FxHashMap::default(),
             running_plots: Vec::new(),
             whitelist,
+            auth,
         };
 
         // Load the spawn area plot on server start
@@ -200,6 +225,9 @@ impl MinecraftServer {
             priv_message_sender: spawn_tx,
         });
 
+        remote::start(server.plot_sender.clone());
+        metrics::start();
+
         info!("Done! Start took {:?}", start_time.elapsed());
 
         loop {
@@ -295,16 +323,82 @@ impl MinecraftServer {
         }
     }
 
-    fn handle_player_login(&mut self, client_idx: usize, login_start: SLoginStart) {
+    /// Sends a request of a remote client to its plot, loading the plot if it isn't running
+    fn send_remote_request(&mut self, plot_x: i32, plot_z: i32, request: RemoteRequest) {
+        let plot_loaded = self
+            .running_plots
+            .iter()
+            .any(|p| p.plot_x == plot_x && p.plot_z == plot_z);
+        if !plot_loaded {
+            let (priv_tx, priv_rx) = mpsc::channel();
+            Plot::load_and_run(
+                plot_x,
+                plot_z,
+                self.broadcaster.add_rx(),
+                self.plot_sender.clone(),
+                priv_rx,
+                false,
+                None,
+            );
+            self.running_plots.push(PlotListEntry {
+                plot_x,
+                plot_z,
+                priv_message_sender: priv_tx,
+            });
+        }
+        let plot_list_entry = self
+            .running_plots
+            .iter()
+            .find(|p| p.plot_x == plot_x && p.plot_z == plot_z)
+            .unwrap();
+        let result = plot_list_entry
+            .priv_message_sender
+            .send(PrivMessage::Remote(request));
+        // The plot stopped but its unload message hasn't arrived yet
+        if let Err(mpsc::SendError(PrivMessage::Remote(request))) = result {
+            request.reply(Err("The plot is unloading, try again.".to_owned()));
+        }
+    }
+
+    fn handle_login_verified(
+        &mut self,
+        client_id: u32,
+        profile: anyhow::Result<Option<PlayerProfile>>,
+    ) {
+        let clients = &mut self.network.handshaking_clients;
+        // The player might have disconnected while they were being authenticated
+        let Some(client_idx) = clients.iter().position(|client| client.id() == client_id) else {
+            return;
+        };
+        match profile {
+            Ok(Some(profile)) => {
+                clients[client_idx].uuid = Some(profile.uuid.0);
+                self.handle_player_login(client_idx, profile.username);
+            }
+            Ok(None) => disconnect_login(&clients[client_idx], "Failed to verify username!"),
+            Err(err) => {
+                error!("Error asking the session server about a login: {:?}", err);
+                disconnect_login(
+                    &clients[client_idx],
+                    "Authentication servers are down. Please try again later.",
+                );
+            }
+        }
+    }
+
+    fn handle_player_login(&mut self, client_idx: usize, username: String) {
         let clients = &mut self.network.handshaking_clients;
-        let username = login_start.name;
         clients[client_idx].username = Some(username.clone());
+        if let Some(address) = clients[client_idx].address {
+            info!("{} is logging in from {}", username, address);
+        }
         let set_compression = CSetCompression { threshold: 256 }.encode();
         clients[client_idx].send_packet(&set_compression);
         clients[client_idx].set_compressed(true);
 
         if let Some(whitelist) = &self.whitelist {
-            // uuid will only be present if bungeecord is enabled in config
+            // uuid will only be present if it was forwarded by a proxy or the player was
+            // authenticated
             let whitelisted = if let Some(uuid) = clients[client_idx].uuid {
                 whitelist.iter().any(|entry| entry.uuid.0 == uuid)
             } else {
@@ -331,6 +425,7 @@ impl MinecraftServer {
             username: username.clone(),
         }
         .encode();
+        clients[client_idx].set_logged_in();
         clients[client_idx].send_packet(&login_success);
 
         let client = clients.remove(client_idx);
@@ -347,12 +442,12 @@ impl MinecraftServer {
             ultrawarm: 0,
             has_raids: 0,
             min_y: 0,
-            height: PLOT_BLOCK_HEIGHT,
+            height: PLOT_SIZE.block_height(),
             respawn_anchor_works: 0,
             bed_works: 0,
             coordinate_scale: 1.0,
             piglin_safe: 0,
-            logical_height: PLOT_BLOCK_HEIGHT,
+            logical_height: PLOT_SIZE.block_height(),
             infiniburn: "#minecraft:infiniburn_overworld".to_owned(),
         };
 
@@ -558,6 +653,12 @@ impl MinecraftServer {
             Message::PlayerLeavePlot(player) => {
                 self.send_player_to_plot(player, false);
             }
+            Message::Remote(plot_x, plot_z, request) => {
+                self.send_remote_request(plot_x, plot_z, request);
+            }
+            Message::LoginVerified(client_id, profile) => {
+                self.handle_login_verified(client_id, profile);
+            }
             Message::Shutdown => {
                 self.graceful_shutdown();
             }
@@ -696,6 +797,7 @@ impl ServerBoundPacketHandler for MinecraftServer {
         } else if next_state == NetworkState::Login && CONFIG.bungeecord {
             let split: Vec<&str> = handshake.server_address.split('\u{0}').collect();
             if split.len() == 3 || split.len() == 4 {
+                client.address = split[1].parse().ok().or(client.address);
                 client.uuid = u128::from_str_radix(split[2], 16).ok();
             } else {
                 let disconnect = CDisconnectLogin {
@@ -744,6 +846,86 @@ impl ServerBoundPacketHandler for MinecraftServer {
     }
 
     fn handle_login_start(&mut self, login_start: SLoginStart, client_idx: usize) {
-        self.handle_player_login(client_idx, login_start);
+        if !CONFIG.velocity_secret.is_empty() {
+            // The player logs in once the proxy has sent their details
+            let client = &mut self.network.handshaking_clients[client_idx];
+            client.username = Some(login_start.name);
+            let request = CLoginPluginRequest {
+                message_id: velocity::MESSAGE_ID,
+                channel: velocity::CHANNEL.to_owned(),
+                data: velocity::request_data(),
+            }
+            .encode();
+            client.send_packet(&request);
+            return;
+        }
+        if let Some(auth) = &mut self.auth {
+            if Authenticator::requires_auth(&login_start.name) {
+                // The player logs in once the session server has verified them
+                let client = &mut self.network.handshaking_clients[client_idx];
+                let request = auth.request_encryption(client.id(), login_start.name.clone());
+                client.username = Some(login_start.name);
+                client.send_packet(&request.encode());
+                return;
+            }
+        }
+        self.handle_player_login(client_idx, login_start.name);
+    }
+
+    fn handle_encryption_response(&mut self, response: SEncryptionResponse, client_idx: usize) {
+        let Some(auth) = &mut self.auth else {
+            return;
+        };
+        let client = &self.network.handshaking_clients[client_idx];
+        let login = match auth.finish_encryption(client.id(), &response) {
+            Ok(login) => login,
+            Err(reason) => {
+                warn!("A player failed to log in: {}", reason);
+                disconnect_login(client, reason);
+                return;
+            }
+        };
+        client.enable_encryption(&login.shared_secret);
+        let client_id = client.id();
+        let sender = self.plot_sender.clone();
+        std::thread::spawn(move || {
+            let rt = Runtime::new().unwrap();
+            let profile = rt.block_on(PlayerProfile::has_joined(
+                &login.username,
+                &login.server_hash,
+            ));
+            let _ = sender.send(Message::LoginVerified(client_id, profile));
+        });
+    }
+
+    fn handle_login_plugin_response(&mut self, response: SLoginPluginResponse, client_idx: usize) {
+        if CONFIG.velocity_secret.is_empty() || response.message_id != velocity::MESSAGE_ID {
+            return;
+        }
+        let player = match response.data {
+            Some(data) => velocity::read_player_info(&CONFIG.velocity_secret, &data),
+            None => Err("This server requires you to connect with Velocity."),
+        };
+        let client = &mut self.network.handshaking_clients[client_idx];
+        match player {
+            Ok(player) => {
+                client.uuid = Some(player.uuid);
+                client.address = player.address.or(client.address);
+                self.handle_player_login(client_idx, player.username);
+            }
+            Err(reason) => {
+                warn!("A player failed to log in through Velocity: {}", reason);
+                disconnect_login(client, reason);
+            }
+        }
+    }
+}
+
+fn disconnect_login(client: &HandshakingConn, reason: &str) {
+    let disconnect = CDisconnectLogin {
+        reason: json!({ "text": reason }).to_string(),
     }
+    .encode();
+    client.send_packet(&disconnect);
+    client.close_connection();
 }
@@ -0,0 +1,93 @@
+//! Velocity's modern forwarding. After the login start packet, the server asks the proxy for the
+//! details of the player with a login plugin request. The proxy answers with the address, uuid
+//! and name of the player, signed with the forwarding secret that both of them are configured
+//! with, so that players can't connect to the server directly with a made up uuid.
+//!
+//! https://docs.papermc.io/velocity/player-information-forwarding
+
+use hmac::{Hmac, Mac};
+use mchprs_network::packets::{DecodeResult, PacketDecoderExt};
+use sha2::Sha256;
+use std::io::Cursor;
+use std::net::IpAddr;
+
+pub const CHANNEL: &str = "velocity:player_info";
+/// Only one request is sent during a login, so its id doesn't matter
+pub const MESSAGE_ID: i32 = 0;
+/// The forwarding version that is requested, which is the only one without chat signing keys
+const MODERN_FORWARDING_DEFAULT: u8 = 1;
+const SIGNATURE_LEN: usize = 32;
+
+/// The data of the login plugin request that asks for the details of the player
+pub fn request_data() -> Vec<u8> {
+    vec![MODERN_FORWARDING_DEFAULT]
+}
+
+pub struct ForwardedPlayer {
+    pub address: Option<IpAddr>,
+    pub uuid: u128,
+    pub username: String,
+}
+
+fn decode_player_info(info: &[u8]) -> DecodeResult<Option<ForwardedPlayer>> {
+    let mut reader = Cursor::new(info);
+    if reader.read_varint()? < MODERN_FORWARDING_DEFAULT as i32 {
+        return Ok(None);
+    }
+    let address = reader.read_string()?.parse().ok();
+    let most = reader.read_long()? as u64 as u128;
+    let least = reader.read_long()? as u64 as u128;
+    let username = reader.read_string()?;
+    // The properties, like the skin of the player, come after the name but aren't used
+    Ok(Some(ForwardedPlayer {
+        address,
+        uuid: most << 64 | least,
+        username,
+    }))
+}
+
+/// Checks the signature of the login plugin response the proxy sent and reads the details of the
+/// player from it. The error is the reason the player is disconnected with.
+pub fn read_player_info(secret: &str, data: &[u8]) -> Result<ForwardedPlayer, &'static str> {
+    if data.len() < SIGNATURE_LEN {
+        return Err("Unable to verify player details");
+    }
+    let (signature, info) = data.split_at(SIGNATURE_LEN);
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(info);
+    if mac.verify_slice(signature).is_err() {
+        return Err("Unable to verify player details");
+    }
+    match decode_player_info(info) {
+        Ok(Some(player)) => Ok(player),
+        Ok(None) => Err("Your proxy uses an unsupported forwarding version"),
+        Err(_) => Err("Unable to read player details"),
+    }
+}
+
+#[test]
+fn player_info_is_verified() {
+    use mchprs_network::packets::PacketEncoderExt;
+
+    let mut info = Vec::new();
+    info.write_varint(1);
+    info.write_string(255, "127.0.0.1");
+    info.write_uuid(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+    info.write_string(16, "Steve");
+    info.write_varint(0);
+    let sign = |secret: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(&info);
+        let mut data = mac.finalize().into_bytes().to_vec();
+        data.extend_from_slice(&info);
+        data
+    };
+
+    let player = read_player_info("secret", &sign("secret")).unwrap();
+    assert_eq!(player.address, Some(IpAddr::from([127, 0, 0, 1])));
+    assert_eq!(player.uuid, 0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+    assert_eq!(player.username, "Steve");
+    assert!(read_player_info("secret", &sign("other secret")).is_err());
+    assert!(read_player_info("secret", &[0; 8]).is_err());
+}
@@ -0,0 +1,254 @@
+//! Just enough of the WebSocket protocol (RFC 6455) for the remote api. The server accepts the
+//! handshake, reads text messages, which can be split into fragments, and writes unfragmented
+//! frames. Extensions and subprotocols aren't supported.
+
+use sha1::{Digest, Sha1};
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+
+/// Appended to the key of the client to get the key the server accepts the handshake with
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Handshakes with more bytes than this are rejected
+const MAX_HANDSHAKE_LEN: u64 = 16 * 1024;
+
+/// Status code of a close frame for a normal closure
+pub const CLOSE_NORMAL: u16 = 1000;
+/// Status code of a close frame for a message that is too big to process
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xA,
+}
+
+impl Opcode {
+    fn from_u8(opcode: u8) -> Option<Opcode> {
+        Some(match opcode {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            _ => return None,
+        })
+    }
+}
+
+/// What the client sent
+#[derive(Debug, PartialEq, Eq)]
+pub enum Incoming {
+    Text(String),
+    /// Has to be answered with a pong with the same payload
+    Ping(Vec<u8>),
+    /// The client closes the connection
+    Close,
+    /// The message is longer than the limit of the reader, the rest of it hasn't been read
+    TooLong,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | ((byte as u32) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[((bits >> (18 - 6 * i)) & 0x3F) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// The `Sec-WebSocket-Accept` header that answers the `Sec-WebSocket-Key` of a client
+fn accept_key(key: &str) -> String {
+    let hash = Sha1::new()
+        .chain_update(key)
+        .chain_update(HANDSHAKE_GUID)
+        .finalize();
+    base64(&hash)
+}
+
+/// Reads the upgrade request of a client and accepts it. `reader` has to be used to read the
+/// messages afterwards, because it may already have buffered the first frames.
+pub fn accept(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+    let mut request = reader.take(MAX_HANDSHAKE_LEN);
+    let mut key = None;
+    let mut line = String::new();
+    for i in 0.. {
+        line.clear();
+        if request.read_line(&mut line)? == 0 {
+            return Err(invalid_data("the handshake ended early"));
+        }
+        let header = line.trim_end();
+        if i == 0 {
+            if !header.starts_with("GET ") {
+                return Err(invalid_data("the handshake has to be a GET request"));
+            }
+        } else if header.is_empty() {
+            break;
+        } else if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_owned());
+            }
+        }
+    }
+    let key = key.ok_or_else(|| invalid_data("the handshake has no Sec-WebSocket-Key"))?;
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )?;
+    writer.flush()
+}
+
+/// Writes a single unmasked frame, like servers send them
+pub fn write_frame(writer: &mut impl Write, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode as u8);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Writes a close frame with a status code and a reason
+pub fn write_close(writer: &mut impl Write, code: u16, reason: &str) -> io::Result<()> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    write_frame(writer, Opcode::Close, &payload)
+}
+
+/// Reads the messages of a client, putting fragmented messages back together
+pub struct MessageReader<R> {
+    reader: R,
+    max_len: usize,
+    /// The fragments of the message that is being read
+    message: Vec<u8>,
+}
+
+impl<R: Read> MessageReader<R> {
+    pub fn new(reader: R, max_len: usize) -> MessageReader<R> {
+        MessageReader {
+            reader,
+            max_len,
+            message: Vec::new(),
+        }
+    }
+
+    pub fn read(&mut self) -> io::Result<Incoming> {
+        loop {
+            let mut header = [0; 2];
+            self.reader.read_exact(&mut header)?;
+            let fin = header[0] & 0x80 != 0;
+            let opcode = Opcode::from_u8(header[0] & 0x0F)
+                .ok_or_else(|| invalid_data("the frame has an unknown opcode"))?;
+            if header[1] & 0x80 == 0 {
+                return Err(invalid_data("frames from clients have to be masked"));
+            }
+            let len = match header[1] & 0x7F {
+                126 => {
+                    let mut len = [0; 2];
+                    self.reader.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0; 8];
+                    self.reader.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            if self.message.len() as u64 + len > self.max_len as u64 {
+                return Ok(Incoming::TooLong);
+            }
+            let mut mask = [0; 4];
+            self.reader.read_exact(&mut mask)?;
+            let mut payload = vec![0; len as usize];
+            self.reader.read_exact(&mut payload)?;
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+
+            match opcode {
+                Opcode::Text | Opcode::Continuation => {
+                    self.message.extend_from_slice(&payload);
+                    if fin {
+                        let message = mem::take(&mut self.message);
+                        return String::from_utf8(message)
+                            .map(Incoming::Text)
+                            .map_err(|_| invalid_data("text messages have to be valid utf-8"));
+                    }
+                }
+                Opcode::Binary => return Err(invalid_data("only text messages are supported")),
+                Opcode::Close => return Ok(Incoming::Close),
+                Opcode::Ping => return Ok(Incoming::Ping(payload)),
+                Opcode::Pong => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn handshakes_and_frames_follow_the_rfc() {
+    use std::io::BufReader;
+
+    // The examples of RFC 6455
+    let handshake = "GET /chat HTTP/1.1\r\n\
+                     Host: server.example.com\r\n\
+                     Upgrade: websocket\r\n\
+                     Connection: Upgrade\r\n\
+                     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                     Sec-WebSocket-Version: 13\r\n\r\n";
+    // A masked "Hel" fragment, a ping, then the masked "lo" continuation
+    let mut frames = vec![0x01, 0x83, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d];
+    frames.extend_from_slice(&[0x89, 0x80, 0, 0, 0, 0]);
+    frames.extend_from_slice(&[0x80, 0x82, 0x37, 0xfa, 0x21, 0x3d, 0x5b, 0x95]);
+    let input = [handshake.as_bytes(), &frames].concat();
+
+    let mut reader = BufReader::new(input.as_slice());
+    let mut response = Vec::new();
+    accept(&mut reader, &mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    let mut messages = MessageReader::new(reader, 5);
+    assert_eq!(messages.read().unwrap(), Incoming::Ping(Vec::new()));
+    assert_eq!(messages.read().unwrap(), Incoming::Text("Hello".to_owned()));
+    assert!(messages.read().is_err());
+
+    let mut frame = Vec::new();
+    write_frame(&mut frame, Opcode::Text, b"Hello").unwrap();
+    assert_eq!(frame, [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+    // Longer messages than the limit aren't read
+    let mut messages = MessageReader::new([0x81, 0x86].as_slice(), 5);
+    assert_eq!(messages.read().unwrap(), Incoming::TooLong);
+}
@@ -1,7 +1,10 @@
 pub mod storage;
 
+use crate::redstone;
+use crate::redstone::updates::UpdateQueue;
+use mchprs_blocks::aabb::Aabb;
 use mchprs_blocks::block_entities::BlockEntity;
-use mchprs_blocks::blocks::Block;
+use mchprs_blocks::blocks::{Block, Instrument};
 use mchprs_blocks::BlockPos;
 use mchprs_world::TickPriority;
 use storage::Chunk;
@@ -50,12 +53,60 @@ pub trait World {
     /// Returns true if there is a tick entry with `pos`
     fn pending_tick_at(&mut self, pos: BlockPos) -> bool;
 
+    /// Plays a note block sound at `pos` for any players that can hear it
+    fn play_note(&mut self, _pos: BlockPos, _instrument: Instrument, _note: u32) {}
+
     fn is_cursed(&self) -> bool {
         false
     }
+
+    /// Whether dust updates its neighbors in vanilla's locational order, see
+    /// [`crate::redstone::update_order`]
+    fn locational_updates(&self) -> bool {
+        false
+    }
+
+    /// The time of day in game ticks that daylight detectors are powered for
+    fn daytime(&self) -> i64 {
+        redstone::daylight_detector::NOON
+    }
+
+    /// Returns the queue that block updates are processed with, see [`crate::redstone::updates`]
+    fn update_queue(&mut self) -> &mut UpdateQueue;
+
+    /// Defers block updates until [`World::end_bulk_edit`] is called, so that editing many
+    /// blocks doesn't process the same updates over and over. Bulk edits can be nested.
+    fn begin_bulk_edit(&mut self) {
+        self.update_queue().begin_bulk_edit();
+    }
+
+    /// Processes the updates that were deferred during the bulk edit, each of them only once
+    fn end_bulk_edit(&mut self)
+    where
+        Self: Sized,
+    {
+        let updates = self.update_queue().end_bulk_edit();
+        redstone::queue_updates(self, updates);
+    }
 }
 
-// TODO: I have no idea how to deduplicate this in a sane way
+/// Splits the box between `first_pos` and `second_pos` along chunk section borders, leaving
+/// out the sections that only contain air
+pub fn non_empty_sections<W: World>(
+    world: &W,
+    first_pos: BlockPos,
+    second_pos: BlockPos,
+) -> Vec<Aabb> {
+    Aabb::new(first_pos, second_pos)
+        .sections()
+        .filter(|section| {
+            let chunk = world
+                .get_chunk(section.min.x.div_euclid(16), section.min.z.div_euclid(16))
+                .unwrap();
+            chunk.sections[section.min.y as usize / 16].block_count() > 0
+        })
+        .collect()
+}
 
 /// Executes the given function for each block excluding most air blocks
 pub fn for_each_block_optimized<F, W: World>(
@@ -66,41 +117,8 @@ pub fn for_each_block_optimized<F, W: World>(
 ) where
     F: FnMut(BlockPos),
 {
-    let start_x = i32::min(first_pos.x, second_pos.x);
-    let end_x = i32::max(first_pos.x, second_pos.x);
-
-    let start_y = i32::min(first_pos.y, second_pos.y);
-    let end_y = i32::max(first_pos.y, second_pos.y);
-
-    let start_z = i32::min(first_pos.z, second_pos.z);
-    let end_z = i32::max(first_pos.z, second_pos.z);
-
-    // Iterate over chunks
-    for chunk_start_x in (start_x..=end_x).step_by(16) {
-        for chunk_start_z in (start_z..=end_z).step_by(16) {
-            let chunk = world
-                .get_chunk(chunk_start_x.div_euclid(16), chunk_start_z.div_euclid(16))
-                .unwrap();
-            for chunk_start_y in (start_y..=end_y).step_by(16) {
-                // Check if the chunk even has non air blocks
-                if chunk.sections[chunk_start_y as usize / 16].block_count() > 0 {
-                    // Calculate the end position of the current chunk
-                    let chunk_end_x = i32::min(chunk_start_x + 16 - 1, end_x);
-                    let chunk_end_y = i32::min(chunk_start_y + 16 - 1, end_y);
-                    let chunk_end_z = i32::min(chunk_start_z + 16 - 1, end_z);
-
-                    // Iterate over each position within the current chunk
-                    for y in chunk_start_y..=chunk_end_y {
-                        for z in chunk_start_z..=chunk_end_z {
-                            for x in chunk_start_x..=chunk_end_x {
-                                let pos = BlockPos::new(x, y, z);
-                                f(pos);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    for section in non_empty_sections(world, first_pos, second_pos) {
+        section.iter().for_each(&mut f);
     }
 }
 
@@ -113,43 +131,9 @@ pub fn for_each_block_mut_optimized<F, W: World>(
 ) where
     F: FnMut(&mut W, BlockPos),
 {
-    let start_x = i32::min(first_pos.x, second_pos.x);
-    let end_x = i32::max(first_pos.x, second_pos.x);
-
-    let start_y = i32::min(first_pos.y, second_pos.y);
-    let end_y = i32::max(first_pos.y, second_pos.y);
-
-    let start_z = i32::min(first_pos.z, second_pos.z);
-    let end_z = i32::max(first_pos.z, second_pos.z);
-
-    // Iterate over chunks
-    for chunk_start_x in (start_x..=end_x).step_by(16) {
-        for chunk_start_z in (start_z..=end_z).step_by(16) {
-            for chunk_start_y in (start_y..=end_y).step_by(16) {
-                // Check if the chunk even has non air blocks
-                if world
-                    .get_chunk(chunk_start_x.div_euclid(16), chunk_start_z.div_euclid(16))
-                    .unwrap()
-                    .sections[chunk_start_y as usize / 16]
-                    .block_count()
-                    > 0
-                {
-                    // Calculate the end position of the current chunk
-                    let chunk_end_x = i32::min(chunk_start_x + 16 - 1, end_x);
-                    let chunk_end_y = i32::min(chunk_start_y + 16 - 1, end_y);
-                    let chunk_end_z = i32::min(chunk_start_z + 16 - 1, end_z);
-
-                    // Iterate over each position within the current chunk
-                    for y in chunk_start_y..=chunk_end_y {
-                        for z in chunk_start_z..=chunk_end_z {
-                            for x in chunk_start_x..=chunk_end_x {
-                                let pos = BlockPos::new(x, y, z);
-                                f(world, pos);
-                            }
-                        }
-                    }
-                }
-            }
+    for section in non_empty_sections(world, first_pos, second_pos) {
+        for pos in section {
+            f(world, pos);
         }
     }
 }
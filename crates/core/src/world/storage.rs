@@ -1,17 +1,20 @@
-use crate::plot::{PLOT_BLOCK_HEIGHT, PLOT_SECTIONS};
-use itertools::Itertools;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::BlockPos;
-use mchprs_save_data::plot_data::{ChunkData, ChunkSectionData};
+use mchprs_save_data::plot_data::{ChunkData, ChunkSectionData, PlotLoadError};
 
 use mchprs_network::packets::clientbound::{
     C3BMultiBlockChangeRecord, CChunkData, CChunkDataBlockEntity, CChunkDataSection,
     CMultiBlockChange, ClientBoundPacket,
 };
 use mchprs_network::packets::{PacketEncoder, PalettedContainer};
+use once_cell::unsync::OnceCell;
 use rustc_hash::FxHashMap;
-use std::convert::TryInto;
+use std::cell::Cell;
 use std::mem;
+use std::sync::Arc;
+use tracing::error;
+
+pub use mchprs_save_data::plot_data::PlotRegion;
 
 #[derive(Clone)]
 pub struct BitBuffer {
@@ -220,6 +223,11 @@ impl PalettedBitBuffer {
         self.data.entries
     }
 
+    /// The amount of bytes the entries and the palette take up
+    pub fn memory_size(&self) -> usize {
+        self.data.longs.len() * mem::size_of::<u64>() + self.palette.len() * mem::size_of::<u32>()
+    }
+
     fn encode_packet(&self) -> PalettedContainer {
         if self.use_palette && self.palette.len() == 1 {
             PalettedContainer {
@@ -239,11 +247,41 @@ impl PalettedBitBuffer {
     }
 }
 
+/// Where a section that hasn't been loaded yet can be loaded from
+struct SectionSource {
+    region: Arc<PlotRegion>,
+    chunk: usize,
+    section: usize,
+}
+
+impl SectionSource {
+    fn load(&self) -> Option<ChunkSectionData> {
+        match self.region.load_section(self.chunk, self.section) {
+            Ok(data) => data,
+            Err(err) => {
+                error!(
+                    "Failed to load section {} of chunk {}: {}",
+                    self.section, self.chunk, err
+                );
+                None
+            }
+        }
+    }
+}
+
 pub struct ChunkSection {
-    buffer: PalettedBitBuffer,
+    /// Loaded from `source` the first time it is needed
+    buffer: OnceCell<PalettedBitBuffer>,
+    /// The region file that has the same blocks as this section. This is cleared when the
+    /// section is changed, after which it can't be unloaded until it is saved again.
+    source: Option<SectionSource>,
+    /// The amount of unload passes since the section was last accessed, see `age`
+    age: Cell<u8>,
     block_count: u32,
     multi_block: CMultiBlockChange,
-    changed_blocks: [i16; 16 * 16 * 16],
+    /// The blocks changed since the last multi block change packet, or -1 if they weren't
+    /// changed. Most sections never change, so this is only allocated once one does.
+    changed_blocks: Option<Box<[i16; 16 * 16 * 16]>>,
     changed: bool,
 }
 
@@ -252,15 +290,27 @@ impl ChunkSection {
         ((y << 8) | (z << 4) | x) as usize
     }
 
+    fn buffer(&self) -> &PalettedBitBuffer {
+        self.age.set(0);
+        self.buffer.get_or_init(|| {
+            let data = self.source.as_ref().and_then(SectionSource::load);
+            ChunkSection::load_buffer(data)
+        })
+    }
+
     fn get_block(&self, x: u32, y: u32, z: u32) -> u32 {
         let idx = ChunkSection::get_index(x, y, z);
-        if self.changed_blocks[idx] >= 0 {
-            self.changed_blocks[idx] as u32
-        } else {
-            self.buffer.get_entry(idx)
+        match &self.changed_blocks {
+            Some(changed_blocks) if changed_blocks[idx] >= 0 => changed_blocks[idx] as u32,
+            _ => self.buffer().get_entry(idx),
         }
     }
 
+    fn changed_blocks_mut(&mut self) -> &mut [i16; 16 * 16 * 16] {
+        self.changed_blocks
+            .get_or_insert_with(|| Box::new([-1; 16 * 16 * 16]))
+    }
+
     /// Sets a block in the chunk sections. Returns true if a block was changed.
     fn set_block(&mut self, x: u32, y: u32, z: u32, block: u32) -> bool {
         let old_block = self.get_block(x, y, z);
@@ -273,80 +323,128 @@ impl ChunkSection {
         let changed = old_block != block;
         if changed {
             self.changed = true;
-            self.changed_blocks[idx] = block as i16;
+            self.changed_blocks_mut()[idx] = block as i16;
+            self.source = None;
         }
         changed
     }
 
-    fn load(data: Option<ChunkSectionData>) -> ChunkSection {
+    /// Marks a block as changed without changing it
+    fn mark_changed(&mut self, x: u32, y: u32, z: u32) {
+        let block = self.get_block(x, y, z);
+        let idx = ChunkSection::get_index(x, y, z);
+        self.changed = true;
+        self.changed_blocks_mut()[idx] = block as i16;
+    }
+
+    fn load_buffer(data: Option<ChunkSectionData>) -> PalettedBitBuffer {
         let data = match data {
             Some(data) => data,
-            None => return Default::default(),
+            None => return PalettedBitBuffer::new(4096, 9),
         };
 
         let loaded_longs = data.data.into_iter().map(|x| x as u64).collect();
         let bits_per_entry = data.bits_per_block as u8;
         let palette = data.palette.into_iter().map(|x| x as u32).collect();
-        let buffer =
-            PalettedBitBuffer::load(data.entries, bits_per_entry, loaded_longs, palette, 9);
+        PalettedBitBuffer::load(data.entries, bits_per_entry, loaded_longs, palette, 9)
+    }
+
+    fn load(data: Option<ChunkSectionData>) -> ChunkSection {
+        let block_count = data.as_ref().map_or(0, |data| data.block_count as u32);
         ChunkSection {
-            buffer,
-            block_count: data.block_count as u32,
-            multi_block: CMultiBlockChange {
-                chunk_x: 0,
-                chunk_y: 0,
-                chunk_z: 0,
-                records: Vec::new(),
-            },
-            changed_blocks: [-1; 16 * 16 * 16],
-            changed: false,
+            buffer: OnceCell::with_value(ChunkSection::load_buffer(data)),
+            block_count,
+            ..Default::default()
+        }
+    }
+
+    /// A loaded copy of the section with the same blocks, including unsent changes
+    fn duplicate(&self) -> ChunkSection {
+        ChunkSection {
+            buffer: OnceCell::with_value(self.buffer().clone()),
+            block_count: self.block_count,
+            changed_blocks: self.changed_blocks.clone(),
+            changed: self.changed,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a section that is only loaded from `region` once it is accessed
+    fn load_lazy(region: &Arc<PlotRegion>, chunk: usize, section: usize) -> ChunkSection {
+        ChunkSection {
+            buffer: OnceCell::new(),
+            source: Some(SectionSource {
+                region: region.clone(),
+                chunk,
+                section,
+            }),
+            block_count: region.section_block_count(chunk, section),
+            ..Default::default()
         }
     }
 
-    fn save(&mut self) -> Option<ChunkSectionData> {
+    /// Stops loading the section from its region file. Sections that aren't loaded yet are
+    /// loaded from `data`, which has to have the same blocks.
+    fn detach_region(&mut self, data: &Option<ChunkSectionData>) {
+        if self.source.take().is_some() && self.buffer.get().is_none() {
+            let _ = self.buffer.set(ChunkSection::load_buffer(data.clone()));
+        }
+    }
+
+    /// Fails if the section is still in its region file and can't be read from it, in which
+    /// case the plot must not be saved, or the blocks of the section would be lost
+    fn save(&mut self) -> Result<Option<ChunkSectionData>, PlotLoadError> {
+        if let Some(source) = &self.source {
+            // The section wasn't changed since it was saved. It is read from the region file
+            // again, so that a section that failed to load isn't saved as air, and sections
+            // that were never loaded are saved without keeping them loaded.
+            return source.region.load_section(source.chunk, source.section);
+        }
         self.flush();
-        if self.buffer.use_palette && self.buffer.palette.len() == 1 && self.buffer.palette[0] == 0
-        {
+        let buffer = self.buffer();
+        if buffer.use_palette && buffer.palette.len() == 1 && buffer.palette[0] == 0 {
             // chunk section is completely air
-            return None;
+            return Ok(None);
         }
 
-        let longs: Vec<i64> = self
-            .buffer
+        let longs: Vec<i64> = buffer
             .data
             .longs
             .clone()
             .into_iter()
             .map(|x| x as i64)
             .collect();
-        let palette: Vec<i32> = self
-            .buffer
+        let palette: Vec<i32> = buffer
             .palette
             .clone()
             .into_iter()
             .map(|x| x as i32)
             .collect();
-        Some(ChunkSectionData {
+        Ok(Some(ChunkSectionData {
             data: longs,
             palette,
-            bits_per_block: self.buffer.data.bits_per_entry as i8,
+            bits_per_block: buffer.data.bits_per_entry as i8,
             block_count: self.block_count as i32,
-            entries: self.buffer.entries(),
-        })
+            entries: buffer.entries(),
+        }))
     }
 
     fn compress(&mut self) {
+        // Sections that aren't loaded are already compact when they are loaded
+        let Some(buffer) = self.buffer.get_mut() else {
+            return;
+        };
         let mut new_buffer = PalettedBitBuffer::new(4096, 9);
         for i in 0..4096 {
-            new_buffer.set_entry(i, self.buffer.get_entry(i));
+            new_buffer.set_entry(i, buffer.get_entry(i));
         }
-        self.buffer = new_buffer;
+        *buffer = new_buffer;
     }
 
     fn encode_packet(&self) -> CChunkDataSection {
         CChunkDataSection {
             block_count: self.block_count as i16,
-            block_states: self.buffer.encode_packet(),
+            block_states: self.buffer().encode_packet(),
             biomes: PalettedContainer {
                 bits_per_entry: 0,
                 data_array: vec![],
@@ -357,9 +455,16 @@ impl ChunkSection {
 
     fn flush(&mut self) {
         if self.changed {
-            for (i, block) in self.changed_blocks.iter().enumerate() {
+            self.buffer();
+            let buffer = self.buffer.get_mut().unwrap();
+            for (i, block) in self
+                .changed_blocks
+                .iter()
+                .flat_map(|blocks| blocks.iter())
+                .enumerate()
+            {
                 if *block >= 0 {
-                    self.buffer.set_entry(i, *block as u32);
+                    buffer.set_entry(i, *block as u32);
                 }
             }
         }
@@ -370,9 +475,16 @@ impl ChunkSection {
         self.multi_block.chunk_y = chunk_y;
         self.multi_block.chunk_z = chunk_z;
         if self.changed {
-            for (i, block) in self.changed_blocks.iter().enumerate() {
+            self.buffer();
+            let buffer = self.buffer.get_mut().unwrap();
+            for (i, block) in self
+                .changed_blocks
+                .iter()
+                .flat_map(|blocks| blocks.iter())
+                .enumerate()
+            {
                 if *block >= 0 {
-                    self.buffer.set_entry(i, *block as u32);
+                    buffer.set_entry(i, *block as u32);
                     self.multi_block.records.push(C3BMultiBlockChangeRecord {
                         block_id: *block as u32,
                         x: (i & 0xF) as u8,
@@ -382,7 +494,7 @@ impl ChunkSection {
                 }
             }
             self.changed = false;
-            self.changed_blocks = [-1; 16 * 16 * 16];
+            self.changed_blocks = None;
         }
         &self.multi_block
     }
@@ -390,12 +502,29 @@ impl ChunkSection {
     pub fn block_count(&self) -> u32 {
         self.block_count
     }
+
+    fn is_loaded(&self) -> bool {
+        self.buffer.get().is_some()
+    }
+
+    /// Counts an unload pass. Returns the amount of passes since the section was last accessed
+    /// before this one if it can be unloaded, which it can't if it was accessed since the last
+    /// pass or if it can't be loaded from its region file again.
+    fn age(&self) -> Option<u8> {
+        if !self.is_loaded() {
+            return None;
+        }
+        let age = self.age.replace(self.age.get().saturating_add(1));
+        (age > 0 && self.source.is_some() && !self.changed).then_some(age)
+    }
 }
 
 impl Default for ChunkSection {
     fn default() -> ChunkSection {
         ChunkSection {
-            buffer: PalettedBitBuffer::new(4096, 9),
+            buffer: OnceCell::with_value(PalettedBitBuffer::new(4096, 9)),
+            source: None,
+            age: Cell::new(0),
             block_count: 0,
             multi_block: CMultiBlockChange {
                 chunk_x: 0,
@@ -403,26 +532,37 @@ impl Default for ChunkSection {
                 chunk_z: 0,
                 records: Vec::new(),
             },
-            changed_blocks: [-1; 16 * 16 * 16],
+            changed_blocks: None,
             changed: false,
         }
     }
 }
 
 pub struct Chunk {
-    pub sections: [ChunkSection; PLOT_SECTIONS],
+    /// One section for every 16 blocks of the world height, from the bottom up
+    pub sections: Vec<ChunkSection>,
     pub x: i32,
     pub z: i32,
     pub block_entities: FxHashMap<BlockPos, BlockEntity>,
 }
 
 impl Chunk {
+    /// A copy of the chunk with every section loaded
+    pub fn duplicate(&self) -> Chunk {
+        Chunk {
+            sections: self.sections.iter().map(ChunkSection::duplicate).collect(),
+            x: self.x,
+            z: self.z,
+            block_entities: self.block_entities.clone(),
+        }
+    }
+
     pub fn encode_packet(&self) -> PacketEncoder {
         // Integer arithmetic trick: ceil(log2(x)) can be calculated with 32 - (x - 1).leading_zeros().
         // See also: https://wiki.vg/Protocol#Chunk_Data_and_Update_Light
-        const HEIGHTMAP_BITS: u8 =
-            (32 - ((PLOT_BLOCK_HEIGHT as u32 + 1) - 1).leading_zeros()) as u8;
-        let mut heightmap_buffer = BitBuffer::create(HEIGHTMAP_BITS, 16 * 16);
+        let height = self.sections.len() as u32 * 16;
+        let heightmap_bits = (32 - ((height + 1) - 1).leading_zeros()) as u8;
+        let mut heightmap_buffer = BitBuffer::create(heightmap_bits, 16 * 16);
         for x in 0..16 {
             for z in 0..16 {
                 heightmap_buffer
@@ -465,9 +605,9 @@ impl Chunk {
         .encode()
     }
 
-    pub fn encode_empty_packet(x: i32, z: i32) -> PacketEncoder {
+    pub fn encode_empty_packet(x: i32, z: i32, num_sections: usize) -> PacketEncoder {
         CChunkData {
-            chunk_sections: (0..PLOT_SECTIONS)
+            chunk_sections: (0..num_sections)
                 .map(|_| CChunkDataSection {
                     block_count: 0,
                     block_states: PalettedContainer {
@@ -510,6 +650,13 @@ impl Chunk {
         section.set_block(x, y & 0xF, z, block_id)
     }
 
+    /// Marks a block as changed, so it is sent to players and reported by
+    /// [`Chunk::changed_positions`] even though its state stayed the same
+    pub fn mark_changed(&mut self, x: u32, y: u32, z: u32) {
+        let section_y = (y >> 4) as usize;
+        self.sections[section_y].mark_changed(x, y & 0xF, z);
+    }
+
     pub fn get_block(&self, x: u32, y: u32, z: u32) -> u32 {
         let section_y = (y / 16) as usize;
         match self.sections.get(section_y) {
@@ -530,42 +677,104 @@ impl Chunk {
         self.block_entities.insert(pos, block_entity);
     }
 
-    pub fn save(&mut self) -> ChunkData<PLOT_SECTIONS> {
-        ChunkData {
+    /// Fails if a section that is still in the region file can't be read from it
+    pub fn save(&mut self) -> Result<ChunkData, PlotLoadError> {
+        Ok(ChunkData {
             sections: self
                 .sections
                 .iter_mut()
                 .map(|s| s.save())
-                .collect_vec()
-                .try_into()
-                .unwrap(),
+                .collect::<Result<_, _>>()?,
             block_entities: self.block_entities.clone(),
-        }
+        })
     }
 
-    pub fn load(x: i32, z: i32, chunk_data: ChunkData<PLOT_SECTIONS>) -> Chunk {
+    /// Loads a chunk with `num_sections` sections. Sections missing from `chunk_data` are
+    /// empty, and sections above them are dropped.
+    pub fn load(x: i32, z: i32, chunk_data: ChunkData, num_sections: usize) -> Chunk {
+        let mut sections: Vec<ChunkSection> = chunk_data
+            .sections
+            .into_iter()
+            .take(num_sections)
+            .map(ChunkSection::load)
+            .collect();
+        sections.resize_with(num_sections, Default::default);
         Chunk {
             x,
             z,
-            sections: IntoIterator::into_iter(chunk_data.sections)
-                .map(ChunkSection::load)
-                .collect_vec()
-                .try_into()
-                .map_err(|_| ())
-                .unwrap(),
+            sections,
             block_entities: chunk_data.block_entities,
         }
     }
 
+    /// Creates a chunk whose sections are only loaded from `region` once they are accessed
+    pub fn load_lazy(
+        x: i32,
+        z: i32,
+        region: &Arc<PlotRegion>,
+        idx: usize,
+        num_sections: usize,
+    ) -> Result<Chunk, PlotLoadError> {
+        let sections = (0..num_sections)
+            .map(|y| match y < region.num_sections() {
+                true => ChunkSection::load_lazy(region, idx, y),
+                false => Default::default(),
+            })
+            .collect();
+        Ok(Chunk {
+            x,
+            z,
+            sections,
+            block_entities: region.load_block_entities(idx)?,
+        })
+    }
+
+    /// Stops using the region file the chunk was loaded from, with `data` being the chunk as
+    /// it was just saved. Every section that wasn't loaded yet is loaded from `data`.
+    pub fn detach_region(&mut self, data: &ChunkData) {
+        for (section, data) in self.sections.iter_mut().zip(&data.sections) {
+            section.detach_region(data);
+        }
+    }
+
+    /// Lets the sections be unloaded again after the chunk was saved to `region`
+    pub fn attach_region(&mut self, region: &Arc<PlotRegion>, idx: usize) {
+        for (y, section) in self.sections.iter_mut().enumerate() {
+            section.source = Some(SectionSource {
+                region: region.clone(),
+                chunk: idx,
+                section: y,
+            });
+        }
+    }
+
+    pub fn loaded_sections(&self) -> usize {
+        self.sections.iter().filter(|s| s.is_loaded()).count()
+    }
+
+    /// Counts an unload pass for every section. Returns the sections that can be unloaded by
+    /// their index, with the amount of passes since they were last accessed.
+    pub fn age_sections(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter_map(|(y, section)| Some((y, section.age()?)))
+    }
+
+    /// Unloads a section returned by `age_sections`, it is loaded again once it is accessed
+    pub fn unload_section(&mut self, y: usize) {
+        self.sections[y].buffer.take();
+    }
+
     pub fn compress(&mut self) {
         self.sections
             .iter_mut()
             .for_each(|section| section.compress());
     }
 
-    pub fn empty(x: i32, z: i32) -> Chunk {
+    pub fn empty(x: i32, z: i32, num_sections: usize) -> Chunk {
         Chunk {
-            sections: std::array::from_fn(|_| Default::default()),
+            sections: (0..num_sections).map(|_| Default::default()).collect(),
             x,
             z,
             block_entities: FxHashMap::default(),
@@ -585,9 +794,119 @@ impl Chunk {
             })
     }
 
+    /// Returns the positions of the blocks that changed since the last multi block change
+    /// packets were made
+    pub fn changed_positions(&self) -> Vec<BlockPos> {
+        let mut positions = Vec::new();
+        for (section_y, section) in self.sections.iter().enumerate() {
+            if !section.changed {
+                continue;
+            }
+            for (i, block) in section
+                .changed_blocks
+                .iter()
+                .flat_map(|blocks| blocks.iter())
+                .enumerate()
+            {
+                if *block >= 0 {
+                    positions.push(BlockPos::new(
+                        (self.x << 4) | (i & 0xF) as i32,
+                        ((section_y << 4) | (i >> 8)) as i32,
+                        (self.z << 4) | ((i & 0xF0) >> 4) as i32,
+                    ));
+                }
+            }
+        }
+        positions
+    }
+
     pub fn reset_multi_blocks(&mut self) {
         for section in &mut self.sections {
             section.multi_block.records.clear();
         }
     }
 }
+
+#[test]
+fn chunk_save_and_load_test() {
+    let mut chunk = Chunk::empty(1, 1, 16);
+    chunk.set_block(13, 63, 12, 332);
+    chunk.set_block(13, 62, 12, 331);
+    let chunk_data = chunk.save().unwrap();
+    let loaded_chunk = Chunk::load(1, 1, chunk_data, 16);
+    assert_eq!(loaded_chunk.get_block(13, 63, 12), 332);
+    assert_eq!(loaded_chunk.get_block(13, 62, 12), 331);
+    assert_eq!(loaded_chunk.get_block(13, 64, 12), 0);
+}
+
+#[test]
+fn region_sections_are_loaded_lazily() {
+    use crate::plot::{redpiler_test_world, PlotSize, PlotWorld};
+    use crate::world::World;
+    use mchprs_blocks::blocks::Block;
+    use mchprs_save_data::plot_data::{PlotData, Tps};
+
+    let mut world = redpiler_test_world();
+    let pos = BlockPos::new(10, 40, 10);
+    world.set_block(pos, Block::RedstoneBlock {});
+    let data = PlotData {
+        tps: Tps::Limited(10),
+        chunk_data: world.chunks.iter_mut().map(|c| c.save().unwrap()).collect(),
+        pending_ticks: Vec::new(),
+    };
+    let path = std::env::temp_dir().join(format!("mchprs_region_test_{}", std::process::id()));
+    data.save_to_file(&path).unwrap();
+    let size = PlotSize::default();
+    let load = |path: &std::path::Path| {
+        let region = Arc::new(PlotRegion::open(path).unwrap());
+        (0..region.num_chunks())
+            .map(|i| {
+                let (x, z) = (i as i32 / size.width(), i as i32 % size.width());
+                Chunk::load_lazy(x, z, &region, i, size.sections).unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut world = PlotWorld {
+        chunks: load(&path),
+        ..redpiler_test_world()
+    };
+    assert_eq!(world.loaded_sections(), 0);
+    assert_eq!(world.get_block(pos), Block::RedstoneBlock {});
+    assert_eq!(world.loaded_sections(), 1);
+
+    // The first pass only notices that the section was accessed
+    assert_eq!(world.unload_cold_sections(0), 0);
+    assert_eq!(world.unload_cold_sections(0), 1);
+    assert_eq!(world.get_block(pos), Block::RedstoneBlock {});
+
+    // The sections that were used least recently are unloaded first
+    let other = BlockPos::new(10, 100, 10);
+    world.get_block(other);
+    world.unload_cold_sections(usize::MAX);
+    world.get_block(pos);
+    world.unload_cold_sections(usize::MAX);
+    assert_eq!(world.unload_cold_sections(1), 1);
+    assert_eq!(world.loaded_sections(), 1);
+    assert_eq!(world.unload_cold_sections(0), 1);
+
+    // Changed sections stay loaded until they are saved again
+    world.set_block(pos, Block::Stone {});
+    assert_eq!(world.unload_cold_sections(0), 0);
+    assert_eq!(world.unload_cold_sections(0), 0);
+    assert_eq!(world.get_block(pos), Block::Stone {});
+    drop(world);
+
+    // A section that can't be read makes the save fail instead of being saved as air. The
+    // length of the first section of the last chunk is too short for its size prefix.
+    let chunk = data.chunk_data.len() - 1;
+    let entry = 1 + chunk * (1 + size.sections) + 1;
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[20 + entry * 16 + 8..][..4].copy_from_slice(&1u32.to_le_bytes());
+    std::fs::write(&path, bytes).unwrap();
+    let mut chunks = load(&path);
+    assert!(chunks[chunk].save().is_err());
+
+    drop(chunks);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,24 @@
+use mchprs_core::parity::{load_corpus, Engine};
+use std::path::Path;
+
+#[test]
+fn vanilla_parity() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/parity");
+    let cases = load_corpus(&corpus).unwrap();
+    assert!(!cases.is_empty(), "the parity corpus is empty");
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        for engine in Engine::all() {
+            if let Err(err) = case.run(engine) {
+                failures.push(format!("{} with {}: {:#}", case.name, engine, err));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} parity failures:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
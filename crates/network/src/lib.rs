@@ -1,17 +1,114 @@
 mod nbt_map;
 pub mod packets;
 
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use packets::serverbound::ServerBoundPacket;
-use packets::{read_packet, PacketEncoder};
-use std::net::{Shutdown, TcpListener, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{mpsc, Arc};
+use packets::{read_packet, PacketDecoderExt, PacketEncoder};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use tracing::warn;
 
+/// The number of bytes sent to all clients since the server started
+pub static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+/// The number of bytes received from all clients since the server started
+pub static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// Adds the bytes that are read from or written to a stream to a counter
+struct Counted<'a, S> {
+    stream: S,
+    counter: &'a AtomicU64,
+}
+
+impl<'a, S> Counted<'a, S> {
+    fn new(stream: S, counter: &'a AtomicU64) -> Counted<'a, S> {
+        Counted { stream, counter }
+    }
+}
+
+impl<S: Read> Read for Counted<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        self.counter.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<S: Read> PacketDecoderExt for Counted<'_, S> {}
+
+impl<S: Write> Write for Counted<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.stream.write(buf)?;
+        self.counter.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+type Encryptor = cfb8::Encryptor<aes::Aes128>;
+type Decryptor = cfb8::Decryptor<aes::Aes128>;
+
+/// The ciphers of a connection once encryption was enabled with
+/// [`HandshakingConn::enable_encryption`]. Every byte sent after that is encrypted, so packets
+/// are written to the stream while the encryptor is locked to keep them in order.
+#[derive(Default)]
+struct Encryption {
+    encryptor: Mutex<Option<Encryptor>>,
+    decryptor: Mutex<Option<Decryptor>>,
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encryption").finish_non_exhaustive()
+    }
+}
+
+impl Encryption {
+    fn write_packet(&self, stream: &TcpStream, data: &PacketEncoder, compressed: bool) {
+        let mut buf = Vec::new();
+        let _ = match compressed {
+            true => data.write_compressed(&mut buf),
+            false => data.write_uncompressed(&mut buf),
+        };
+        let mut encryptor = self.encryptor.lock().unwrap();
+        if let Some(encryptor) = encryptor.as_mut() {
+            // CFB8 works on one byte at a time
+            for byte in buf.chunks_mut(1) {
+                encryptor.encrypt_block_mut(GenericArray::from_mut_slice(byte));
+            }
+        }
+        let _ = Counted::new(stream, &BYTES_SENT).write_all(&buf);
+    }
+}
+
+/// Decrypts the bytes read from a stream once encryption is enabled
+struct Decrypted<S> {
+    stream: S,
+    encryption: Arc<Encryption>,
+}
+
+impl<S: Read> Read for Decrypted<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        if let Some(decryptor) = self.encryption.decryptor.lock().unwrap().as_mut() {
+            for byte in buf[..read].chunks_mut(1) {
+                decryptor.decrypt_block_mut(GenericArray::from_mut_slice(byte));
+            }
+        }
+        Ok(read)
+    }
+}
+
 #[derive(Debug)]
 pub struct PlayerPacketSender {
     stream: Option<TcpStream>,
+    encryption: Arc<Encryption>,
 }
 
 impl PlayerPacketSender {
@@ -20,13 +117,16 @@ impl PlayerPacketSender {
         if stream.is_none() {
             warn!("Creating PlayerPacketSender with dead stream")
         }
-        PlayerPacketSender { stream }
+        PlayerPacketSender {
+            stream,
+            encryption: conn.client.encryption.clone(),
+        }
     }
 
     pub fn send_packet(&self, data: &PacketEncoder) {
         if let Some(stream) = &self.stream {
             // Going to assume stream is compressed since it should be after login
-            let _ = data.write_compressed(stream);
+            self.encryption.write_packet(stream, data, true);
         }
     }
 }
@@ -44,6 +144,8 @@ pub struct HandshakingConn {
     client: NetworkClient,
     pub username: Option<String>,
     pub uuid: Option<u128>,
+    /// The address the client connected from, which is forwarded by the proxy if there is one
+    pub address: Option<IpAddr>,
 }
 
 impl HandshakingConn {
@@ -59,6 +161,27 @@ impl HandshakingConn {
         self.client.compressed.store(compressed, Ordering::Relaxed)
     }
 
+    /// The id of the connection, which stays the same while other clients connect and leave
+    pub fn id(&self) -> u32 {
+        self.client.id
+    }
+
+    /// Encrypts everything sent and received from now on with the shared secret the client sent
+    /// in its encryption response
+    pub fn enable_encryption(&self, shared_secret: &[u8; 16]) {
+        let encryption = &self.client.encryption;
+        // The shared secret is both the key and the initial vector
+        let key = GenericArray::from_slice(shared_secret);
+        *encryption.encryptor.lock().unwrap() = Some(Encryptor::new(key, key));
+        *encryption.decryptor.lock().unwrap() = Some(Decryptor::new(key, key));
+    }
+
+    /// Moves the client to the play state. This has to be called before the login success
+    /// packet is sent, so that the packets the client sends after it are read as play packets.
+    pub fn set_logged_in(&self) {
+        self.client.logged_in.store(true, Ordering::Release)
+    }
+
     pub fn close_connection(&self) {
         self.client.close_connection();
     }
@@ -105,17 +228,23 @@ pub struct NetworkClient {
     stream: TcpStream,
     packets: mpsc::Receiver<Box<dyn ServerBoundPacket>>,
     compressed: Arc<AtomicBool>,
+    logged_in: Arc<AtomicBool>,
+    encryption: Arc<Encryption>,
 }
 
 impl NetworkClient {
     fn listen(
-        mut stream: TcpStream,
+        stream: TcpStream,
         sender: mpsc::Sender<Box<dyn ServerBoundPacket>>,
         compressed: Arc<AtomicBool>,
+        logged_in: Arc<AtomicBool>,
+        encryption: Arc<Encryption>,
     ) {
+        let stream = Decrypted { stream, encryption };
+        let mut stream = Counted::new(stream, &BYTES_RECEIVED);
         let mut state = NetworkState::Handshake;
         loop {
-            let packet = match read_packet(&mut stream, &compressed, &mut state) {
+            let packet = match read_packet(&mut stream, &compressed, &logged_in, &mut state) {
                 Ok(packet) => packet,
                 // This will cause the client to disconnect
                 Err(_) => return,
@@ -143,11 +272,8 @@ impl NetworkClient {
     }
 
     pub fn send_packet(&self, data: &PacketEncoder) {
-        if self.compressed.load(Ordering::Relaxed) {
-            let _ = data.write_compressed(&self.stream);
-        } else {
-            let _ = data.write_uncompressed(&self.stream);
-        }
+        let compressed = self.compressed.load(Ordering::Relaxed);
+        self.encryption.write_packet(&self.stream, data, compressed);
     }
 
     pub fn close_connection(&self) {
@@ -170,10 +296,20 @@ impl NetworkServer {
             let stream = stream.unwrap();
             let (packet_sender, packet_receiver) = mpsc::channel();
             let compressed = Arc::new(AtomicBool::new(false));
+            let logged_in = Arc::new(AtomicBool::new(false));
+            let encryption = Arc::new(Encryption::default());
             let client_stream = stream.try_clone().unwrap();
             let client_compressed = compressed.clone();
+            let client_logged_in = logged_in.clone();
+            let client_encryption = encryption.clone();
             thread::spawn(move || {
-                NetworkClient::listen(client_stream, packet_sender, client_compressed);
+                NetworkClient::listen(
+                    client_stream,
+                    packet_sender,
+                    client_compressed,
+                    client_logged_in,
+                    client_encryption,
+                );
             });
             sender
                 .send(NetworkClient {
@@ -182,6 +318,8 @@ impl NetworkServer {
                     stream,
                     packets: packet_receiver,
                     compressed,
+                    logged_in,
+                    encryption,
                 })
                 .unwrap();
         }
@@ -201,6 +339,7 @@ impl NetworkServer {
         loop {
             match self.client_receiver.try_recv() {
                 Ok(client) => self.handshaking_clients.push(HandshakingConn {
+                    address: client.stream.peer_addr().ok().map(|addr| addr.ip()),
                     client,
                     username: None,
                     uuid: None,
@@ -75,6 +75,40 @@ impl ClientBoundPacket for CSetCompression {
     }
 }
 
+pub struct CEncryptionRequest {
+    pub server_id: String,
+    pub public_key: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl ClientBoundPacket for CEncryptionRequest {
+    fn encode(&self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_string(20, &self.server_id);
+        buf.write_varint(self.public_key.len() as i32);
+        buf.write_bytes(&self.public_key);
+        buf.write_varint(self.verify_token.len() as i32);
+        buf.write_bytes(&self.verify_token);
+        PacketEncoder::new(buf, 0x01)
+    }
+}
+
+pub struct CLoginPluginRequest {
+    pub message_id: i32,
+    pub channel: String,
+    pub data: Vec<u8>,
+}
+
+impl ClientBoundPacket for CLoginPluginRequest {
+    fn encode(&self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_varint(self.message_id);
+        buf.write_string(32767, &self.channel);
+        buf.write_bytes(&self.data);
+        PacketEncoder::new(buf, 0x04)
+    }
+}
+
 pub struct CSpawnEntity {
     pub entity_id: i32,
     pub object_uuid: u128,
@@ -201,6 +235,27 @@ impl ClientBoundPacket for CBlockEntityData {
     }
 }
 
+pub struct CBlockAction {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub action_id: u8,
+    pub action_param: u8,
+    /// The block registry id (not the block state id)
+    pub block_type: i32,
+}
+
+impl ClientBoundPacket for CBlockAction {
+    fn encode(&self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_position(self.x, self.y, self.z);
+        buf.write_unsigned_byte(self.action_id);
+        buf.write_unsigned_byte(self.action_param);
+        buf.write_varint(self.block_type);
+        PacketEncoder::new(buf, 0x0B)
+    }
+}
+
 pub struct CBlockChange {
     pub x: i32,
     pub y: i32,
@@ -402,6 +457,31 @@ impl ClientBoundPacket for CPluginMessage {
     }
 }
 
+pub struct CNamedSoundEffect {
+    pub sound_name: String,
+    pub sound_category: i32,
+    /// The position multiplied by 8
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub volume: f32,
+    pub pitch: f32,
+}
+
+impl ClientBoundPacket for CNamedSoundEffect {
+    fn encode(&self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_string(32767, &self.sound_name);
+        buf.write_varint(self.sound_category);
+        buf.write_int(self.x);
+        buf.write_int(self.y);
+        buf.write_int(self.z);
+        buf.write_float(self.volume);
+        buf.write_float(self.pitch);
+        PacketEncoder::new(buf, 0x19)
+    }
+}
+
 pub struct CDisconnect {
     pub reason: String,
 }
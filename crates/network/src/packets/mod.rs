@@ -91,10 +91,9 @@ fn read_decompressed<T: PacketDecoderExt>(
         }
         NetworkState::Status if packet_id == 0x00 => Box::new(SRequest::decode(reader)?),
         NetworkState::Status if packet_id == 0x01 => Box::new(SPing::decode(reader)?),
-        NetworkState::Login if packet_id == 0x00 => {
-            *state = NetworkState::Play;
-            Box::new(SLoginStart::decode(reader)?)
-        }
+        NetworkState::Login if packet_id == 0x00 => Box::new(SLoginStart::decode(reader)?),
+        NetworkState::Login if packet_id == 0x01 => Box::new(SEncryptionResponse::decode(reader)?),
+        NetworkState::Login if packet_id == 0x02 => Box::new(SLoginPluginResponse::decode(reader)?),
         _ => match packet_id {
             0x03 => Box::new(SChatMessage::decode(reader)?),
             0x05 => Box::new(SClientSettings::decode(reader)?),
@@ -113,18 +112,28 @@ fn read_decompressed<T: PacketDecoderExt>(
             0x2B => Box::new(SUpdateSign::decode(reader)?),
             0x2C => Box::new(SAnimation::decode(reader)?),
             0x2E => Box::new(SPlayerBlockPlacemnt::decode(reader)?),
+            0x2F => Box::new(SUseItem::decode(reader)?),
             _ => Box::new(SUnknown),
         },
     })
 }
 
+/// Reads a packet from `reader`. The client stays in the login state until `logged_in` is set,
+/// which the server does right before it sends the login success packet.
 pub fn read_packet<T: PacketDecoderExt>(
     reader: &mut T,
     compressed: &Arc<AtomicBool>,
+    logged_in: &Arc<AtomicBool>,
     network_state: &mut NetworkState,
 ) -> DecodeResult<Box<dyn ServerBoundPacket>> {
     let length = reader.read_varint()?;
     let data = reader.read_bytes(length as usize)?;
+    // Checked once the packet was read, since the reader is usually already waiting for the
+    // next packet when the flag is set. The client only sends play packets after it received
+    // the login success packet, so the flag is always set by the time one arrives.
+    if *network_state == NetworkState::Login && logged_in.load(Ordering::Acquire) {
+        *network_state = NetworkState::Play;
+    }
     let mut cursor = Cursor::new(data);
     if compressed.load(Ordering::Relaxed) {
         read_compressed(&mut cursor, network_state)
@@ -440,3 +449,41 @@ impl PacketEncoder {
         Ok(())
     }
 }
+
+#[test]
+fn play_packets_right_after_login_are_read_as_play_packets() {
+    /// Sets `logged_in` once the reader is waiting for the next packet, like the server does
+    struct LoggedInWhileReading {
+        data: Cursor<Vec<u8>>,
+        logged_in: Arc<AtomicBool>,
+    }
+    impl Read for LoggedInWhileReading {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.logged_in.store(true, Ordering::Release);
+            self.data.read(buf)
+        }
+    }
+    impl PacketDecoderExt for LoggedInWhileReading {}
+
+    #[derive(Default)]
+    struct LoginStarts(usize);
+    impl ServerBoundPacketHandler for LoginStarts {
+        fn handle_login_start(&mut self, _packet: SLoginStart, _player_idx: usize) {
+            self.0 += 1;
+        }
+    }
+
+    // Packet 0x00 with a string in it, which is a login start packet in the login state
+    let logged_in = Arc::new(AtomicBool::new(false));
+    let mut reader = LoggedInWhileReading {
+        data: Cursor::new(vec![3, 0x00, 1, b'a']),
+        logged_in: logged_in.clone(),
+    };
+    let mut state = NetworkState::Login;
+    let compressed = Arc::new(AtomicBool::new(false));
+    let packet = read_packet(&mut reader, &compressed, &logged_in, &mut state).unwrap();
+    assert!(state == NetworkState::Play);
+    let mut handler = LoginStarts::default();
+    packet.handle(&mut handler, 0);
+    assert_eq!(handler.0, 0);
+}
@@ -5,6 +5,8 @@ pub trait ServerBoundPacketHandler {
     fn handle_request(&mut self, _packet: SRequest, _player_idx: usize) {}
     fn handle_ping(&mut self, _packet: SPing, _player_idx: usize) {}
     fn handle_login_start(&mut self, _packet: SLoginStart, _player_idx: usize) {}
+    fn handle_encryption_response(&mut self, _packet: SEncryptionResponse, _player_idx: usize) {}
+    fn handle_login_plugin_response(&mut self, _packet: SLoginPluginResponse, _player_idx: usize) {}
     fn handle_chat_message(&mut self, _packet: SChatMessage, _player_idx: usize) {}
     fn handle_client_settings(&mut self, _packet: SClientSettings, _player_idx: usize) {}
     fn handle_tab_complete(&mut self, _packet: STabComplete, _player_idx: usize) {}
@@ -25,6 +27,7 @@ pub trait ServerBoundPacketHandler {
     fn handle_animation(&mut self, _packet: SAnimation, _player_idx: usize) {}
     fn handle_player_block_placement(&mut self, _packet: SPlayerBlockPlacemnt, _player_idx: usize) {
     }
+    fn handle_use_item(&mut self, _packet: SUseItem, _player_idx: usize) {}
     fn handle_held_item_change(&mut self, _packet: SHeldItemChange, _player_idx: usize) {}
     fn handle_creative_inventory_action(
         &mut self,
@@ -42,6 +45,11 @@ pub trait ServerBoundPacket: Send {
         Self: Sized;
 
     fn handle(self: Box<Self>, handler: &mut dyn ServerBoundPacketHandler, player_idx: usize);
+
+    /// The message of a chat packet. This lets a packet be looked at without handling it.
+    fn chat_message(&self) -> Option<&str> {
+        None
+    }
 }
 
 pub struct SUnknown;
@@ -122,6 +130,49 @@ impl ServerBoundPacket for SLoginStart {
     }
 }
 
+pub struct SEncryptionResponse {
+    pub shared_secret: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl ServerBoundPacket for SEncryptionResponse {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let len = decoder.read_varint()?;
+        let shared_secret = decoder.read_bytes(len as usize)?;
+        let len = decoder.read_varint()?;
+        let verify_token = decoder.read_bytes(len as usize)?;
+        Ok(SEncryptionResponse {
+            shared_secret,
+            verify_token,
+        })
+    }
+
+    fn handle(self: Box<Self>, handler: &mut dyn ServerBoundPacketHandler, player_idx: usize) {
+        handler.handle_encryption_response(*self, player_idx);
+    }
+}
+
+pub struct SLoginPluginResponse {
+    pub message_id: i32,
+    /// `None` if the client didn't understand the request
+    pub data: Option<Vec<u8>>,
+}
+
+impl ServerBoundPacket for SLoginPluginResponse {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let message_id = decoder.read_varint()?;
+        let data = match decoder.read_bool()? {
+            true => Some(decoder.read_to_end()?),
+            false => None,
+        };
+        Ok(SLoginPluginResponse { message_id, data })
+    }
+
+    fn handle(self: Box<Self>, handler: &mut dyn ServerBoundPacketHandler, player_idx: usize) {
+        handler.handle_login_plugin_response(*self, player_idx);
+    }
+}
+
 pub struct SChatMessage {
     pub message: String,
 }
@@ -136,6 +187,10 @@ impl ServerBoundPacket for SChatMessage {
     fn handle(self: Box<Self>, handler: &mut dyn ServerBoundPacketHandler, player_idx: usize) {
         handler.handle_chat_message(*self, player_idx);
     }
+
+    fn chat_message(&self) -> Option<&str> {
+        Some(&self.message)
+    }
 }
 
 pub struct SClientSettings {
@@ -422,6 +477,24 @@ impl ServerBoundPacket for SPlayerBlockPlacemnt {
     }
 }
 
+/// Sent when the player uses the item in their hand without aiming at a block in reach, or when
+/// using it on the block they aim at did nothing
+pub struct SUseItem {
+    pub hand: i32,
+}
+
+impl ServerBoundPacket for SUseItem {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SUseItem {
+            hand: decoder.read_varint()?,
+        })
+    }
+
+    fn handle(self: Box<Self>, handler: &mut dyn ServerBoundPacketHandler, player_idx: usize) {
+        handler.handle_use_item(*self, player_idx);
+    }
+}
+
 pub struct SHeldItemChange {
     pub slot: i16,
 }
@@ -41,6 +41,10 @@ pub enum NodeType {
     Trapdoor,
     Wire,
     Constant,
+    Piston,
+    NoteBlock,
+    Observer,
+    Target,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -1,20 +1,28 @@
+mod backup;
 mod fixer;
+mod region;
 
 use self::fixer::FixInfo;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use bincode::Options;
+use byteorder::{LittleEndian, ReadBytesExt};
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
-use serde_big_array::BigArray;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
 use std::{fmt, io};
 use thiserror::Error;
 
-const VERSION: u32 = 0;
+pub use backup::PlotBackups;
+pub use region::PlotRegion;
+
+/// Version 0 stores the whole plot at once, version 1 is a region file and version 2 is a
+/// region file with compressed entries
+const VERSION: u32 = 2;
 
 #[derive(Error, Debug)]
 pub enum PlotLoadError {
@@ -30,6 +38,12 @@ pub enum PlotLoadError {
     #[error("plot data version {0} failed to be converted")]
     ConversionFailed(u32),
 
+    #[error("plot region index is corrupted")]
+    Corrupted,
+
+    #[error("plot region entry decompression error")]
+    Decompress(#[from] lz4_flex::block::DecompressError),
+
     #[error(transparent)]
     Io(#[from] io::Error),
 }
@@ -63,10 +77,10 @@ pub struct ChunkSectionData {
     pub entries: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ChunkData<const NUM_SECTIONS: usize> {
-    #[serde(with = "BigArray")]
-    pub sections: [Option<ChunkSectionData>; NUM_SECTIONS],
+#[derive(Debug, Clone)]
+pub struct ChunkData {
+    /// One entry for every section of the world height, from the bottom up
+    pub sections: Vec<Option<ChunkSectionData>>,
     pub block_entities: FxHashMap<BlockPos, BlockEntity>,
 }
 
@@ -74,6 +88,30 @@ pub struct ChunkData<const NUM_SECTIONS: usize> {
 pub enum Tps {
     Limited(u32),
     Unlimited,
+    /// Ticks per second in thousandths of a tick, for rates that aren't whole numbers
+    Fractional(u32),
+}
+
+impl Tps {
+    /// The time between two ticks, or `None` if the rate isn't limited or ticking is paused
+    pub fn tick_duration(self) -> Option<Duration> {
+        match self {
+            Tps::Limited(0) | Tps::Fractional(0) | Tps::Unlimited => None,
+            Tps::Limited(tps) => Some(Duration::from_nanos(1_000_000_000 / tps as u64)),
+            Tps::Fractional(millis) => {
+                Some(Duration::from_nanos(1_000_000_000_000 / millis as u64))
+            }
+        }
+    }
+
+    /// The rate in thousandths of a tick per second, or `None` if it isn't limited
+    pub fn millis(self) -> Option<u32> {
+        match self {
+            Tps::Limited(tps) => Some(tps.saturating_mul(1000)),
+            Tps::Fractional(millis) => Some(millis),
+            Tps::Unlimited => None,
+        }
+    }
 }
 
 impl fmt::Display for Tps {
@@ -81,27 +119,31 @@ impl fmt::Display for Tps {
         match self {
             Tps::Limited(tps) => write!(f, "{}", tps),
             Tps::Unlimited => write!(f, "unlimited"),
+            Tps::Fractional(millis) => write!(f, "{}", *millis as f64 / 1000.0),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct PlotData<const NUM_CHUNK_SECTIONS: usize> {
+#[derive(Debug, Clone)]
+pub struct PlotData {
     pub tps: Tps,
-    pub chunk_data: Vec<ChunkData<NUM_CHUNK_SECTIONS>>,
+    pub chunk_data: Vec<ChunkData>,
     pub pending_ticks: Vec<TickEntry>,
 }
 
-impl<const NUM_CHUNK_SECTIONS: usize> PlotData<NUM_CHUNK_SECTIONS> {
+impl PlotData {
+    /// Loads a plot from `path`. Files saved before region files don't store the height of the
+    /// world, so they are read as having `num_sections` sections per chunk.
     pub fn load_from_file(
         path: impl AsRef<Path>,
-    ) -> Result<PlotData<NUM_CHUNK_SECTIONS>, PlotLoadError> {
+        num_sections: usize,
+    ) -> Result<PlotData, PlotLoadError> {
         let mut file = File::open(&path)?;
 
         let mut magic = [0; 8];
         file.read_exact(&mut magic)?;
         if &magic != PLOT_MAGIC {
-            return fixer::try_fix(path, FixInfo::InvalidHeader)?
+            return fixer::try_fix(path, FixInfo::InvalidHeader, num_sections)?
                 .ok_or(PlotLoadError::InvalidHeader);
         }
 
@@ -112,20 +154,44 @@ impl<const NUM_CHUNK_SECTIONS: usize> PlotData<NUM_CHUNK_SECTIONS> {
         if version > VERSION {
             return Err(PlotLoadError::TooNew(version));
         }
+        if version > 0 {
+            return PlotRegion::open(path)?.load();
+        }
 
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        Ok(bincode::deserialize(&buf)?)
+        Ok(deserialize_v0(&buf, num_sections)?)
     }
 
+    /// Saves the plot as a region file. Any [`PlotRegion`] of `path` has to be dropped first,
+    /// because the file can't be replaced while it is mapped on Windows.
     pub fn save_to_file(&self, path: impl AsRef<Path>) -> Result<(), PlotSaveError> {
-        let mut file = OpenOptions::new().write(true).create(true).open(path)?;
-
-        file.write_all(PLOT_MAGIC)?;
-        file.write_u32::<LittleEndian>(VERSION)?;
-        let data = bincode::serialize(self)?;
-        file.write_all(&data)?;
-        file.sync_data()?;
-        Ok(())
+        region::write(path.as_ref(), self)
+    }
+}
+
+/// Version 0 is the whole plot serialized with bincode, with the sections of every chunk stored
+/// as an array of the size of the world height, so it is read one field at a time
+fn deserialize_v0(buf: &[u8], num_sections: usize) -> bincode::Result<PlotData> {
+    let options = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes();
+    let mut de = bincode::Deserializer::from_slice(buf, options);
+    let tps = Tps::deserialize(&mut de)?;
+    let num_chunks = u64::deserialize(&mut de)?;
+    let mut chunk_data = Vec::new();
+    for _ in 0..num_chunks {
+        let sections = (0..num_sections)
+            .map(|_| Option::<ChunkSectionData>::deserialize(&mut de))
+            .collect::<bincode::Result<_>>()?;
+        chunk_data.push(ChunkData {
+            sections,
+            block_entities: FxHashMap::deserialize(&mut de)?,
+        });
     }
+    Ok(PlotData {
+        tps,
+        chunk_data,
+        pending_ticks: Vec::deserialize(&mut de)?,
+    })
 }
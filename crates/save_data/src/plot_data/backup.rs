@@ -0,0 +1,268 @@
+//! Backups of a plot, which are kept in a directory with a file for every backup. A backup only
+//! stores the chunks that changed since the backup before it, so a plot is restored by going
+//! back through the backups until every chunk was found. The oldest backup always has every
+//! chunk, which stays true when it is removed because it is merged into the next one.
+//!
+//! Every file is a [`Backup`] serialized with bincode and compressed with LZ4.
+
+use super::{ChunkData, ChunkSectionData, PlotData, PlotLoadError, PlotSaveError, Tps};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickEntry;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+struct BackupChunk {
+    index: u32,
+    sections: Vec<Option<ChunkSectionData>>,
+    block_entities: FxHashMap<BlockPos, BlockEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Backup {
+    tps: Tps,
+    num_chunks: u32,
+    pending_ticks: Vec<TickEntry>,
+    /// The chunks that changed since the previous backup, ordered by index
+    chunks: Vec<BackupChunk>,
+}
+
+impl Backup {
+    /// Fills in the chunks of `chunks` that haven't been found yet
+    fn fill(self, chunks: &mut [Option<ChunkData>]) {
+        for chunk in self.chunks {
+            match chunks.get_mut(chunk.index as usize) {
+                Some(slot) if slot.is_none() => {
+                    *slot = Some(ChunkData {
+                        sections: chunk.sections,
+                        block_entities: chunk.block_entities,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn hash_chunk(chunk: &ChunkData) -> Result<u64, PlotSaveError> {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&bincode::serialize(&chunk.sections)?);
+    hasher.write(&bincode::serialize(&chunk.block_entities)?);
+    Ok(hasher.finish())
+}
+
+pub struct PlotBackups {
+    dir: PathBuf,
+    /// The ids of the backups, which are the unix time in seconds they were made at, from old to
+    /// new
+    ids: Vec<u64>,
+    /// The hashes of the chunks in the newest backup, which are used to find the chunks that
+    /// changed since
+    hashes: Option<Vec<u64>>,
+}
+
+impl PlotBackups {
+    /// Opens the backups in `dir`, which is created if it doesn't exist
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<PlotBackups> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut ids: Vec<u64> = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".bak"))
+                .and_then(|id| id.parse::<u64>().ok());
+            ids.extend(id);
+        }
+        ids.sort_unstable();
+        Ok(PlotBackups {
+            dir,
+            ids,
+            hashes: None,
+        })
+    }
+
+    /// The ids of the backups, from old to new
+    pub fn ids(&self) -> &[u64] {
+        &self.ids
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.bak", id))
+    }
+
+    fn read(&self, id: u64) -> Result<Backup, PlotLoadError> {
+        let data = fs::read(self.path(id))?;
+        Ok(bincode::deserialize(&lz4_flex::decompress_size_prepended(
+            &data,
+        )?)?)
+    }
+
+    fn write(&self, id: u64, backup: &Backup) -> Result<(), PlotSaveError> {
+        let data = lz4_flex::compress_prepend_size(&bincode::serialize(backup)?);
+        // A backup that is rewritten when the oldest one is merged into it is never left half
+        // written
+        let tmp_path = self.dir.join(format!("{}.tmp", id));
+        fs::write(&tmp_path, data)?;
+        fs::rename(tmp_path, self.path(id))?;
+        Ok(())
+    }
+
+    /// Makes a backup of `data`, which gets an id of at least `time`. Nothing is written if no
+    /// chunk changed since the last backup, in which case `None` is returned.
+    pub fn create(&mut self, time: u64, data: &PlotData) -> Result<Option<u64>, PlotLoadError> {
+        let old_hashes = match self.hashes.take() {
+            Some(hashes) => hashes,
+            // If the newest backup can't be restored, the new one gets every chunk
+            None => match self.ids.last() {
+                Some(&id) => self
+                    .restore(id)
+                    .ok()
+                    .map(|data| {
+                        data.chunk_data
+                            .iter()
+                            .map(hash_chunk)
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            },
+        };
+        let hashes = data
+            .chunk_data
+            .iter()
+            .map(hash_chunk)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let chunks: Vec<BackupChunk> = data
+            .chunk_data
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| old_hashes.get(i) != Some(&hashes[i]))
+            .map(|(i, chunk)| BackupChunk {
+                index: i as u32,
+                sections: chunk.sections.clone(),
+                block_entities: chunk.block_entities.clone(),
+            })
+            .collect();
+        if chunks.is_empty() && old_hashes.len() == hashes.len() {
+            self.hashes = Some(hashes);
+            return Ok(None);
+        }
+
+        let id = match self.ids.last() {
+            Some(&last) => time.max(last + 1),
+            None => time,
+        };
+        let backup = Backup {
+            tps: data.tps,
+            num_chunks: data.chunk_data.len() as u32,
+            pending_ticks: data.pending_ticks.clone(),
+            chunks,
+        };
+        self.write(id, &backup)?;
+        self.ids.push(id);
+        self.hashes = Some(hashes);
+        Ok(Some(id))
+    }
+
+    /// Loads the plot as it was when backup `id` was made
+    pub fn restore(&self, id: u64) -> Result<PlotData, PlotLoadError> {
+        let Ok(pos) = self.ids.binary_search(&id) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound).into());
+        };
+        let mut backup = self.read(id)?;
+        let (tps, pending_ticks) = (backup.tps, std::mem::take(&mut backup.pending_ticks));
+        let mut chunks = vec![None; backup.num_chunks as usize];
+        backup.fill(&mut chunks);
+        for &older in self.ids[..pos].iter().rev() {
+            if chunks.iter().all(Option::is_some) {
+                break;
+            }
+            self.read(older)?.fill(&mut chunks);
+        }
+        let chunk_data = chunks
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or(PlotLoadError::Corrupted)?;
+        Ok(PlotData {
+            tps,
+            chunk_data,
+            pending_ticks,
+        })
+    }
+
+    /// Removes the oldest backups until at most `keep` are left, but always keeps the newest one
+    pub fn prune(&mut self, keep: usize) -> Result<(), PlotLoadError> {
+        while self.ids.len() > keep.max(1) {
+            let (oldest_id, next_id) = (self.ids[0], self.ids[1]);
+            let oldest = self.read(oldest_id)?;
+            let mut next = self.read(next_id)?;
+            for chunk in oldest.chunks {
+                let missing = !next.chunks.iter().any(|c| c.index == chunk.index);
+                if missing && chunk.index < next.num_chunks {
+                    next.chunks.push(chunk);
+                }
+            }
+            next.chunks.sort_by_key(|chunk| chunk.index);
+            self.write(next_id, &next)?;
+            fs::remove_file(self.path(oldest_id))?;
+            self.ids.remove(0);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn backups_only_store_changed_chunks() {
+    let path = std::env::temp_dir().join(format!("mchprs_backup_test_{}", std::process::id()));
+    let mut backups = PlotBackups::open(&path).unwrap();
+    // Every chunk has a single section, which is told apart by its first long
+    let chunk = |id: i64| ChunkData {
+        sections: vec![Some(ChunkSectionData {
+            data: vec![id],
+            palette: vec![0],
+            bits_per_block: 4,
+            block_count: 0,
+            entries: 4096,
+        })],
+        block_entities: FxHashMap::default(),
+    };
+    let plot = |chunks: &[i64]| PlotData {
+        tps: Tps::Limited(10),
+        chunk_data: chunks.iter().map(|&id| chunk(id)).collect(),
+        pending_ticks: Vec::new(),
+    };
+    let ids = |data: PlotData| -> Vec<i64> {
+        data.chunk_data
+            .iter()
+            .map(|chunk| chunk.sections[0].as_ref().unwrap().data[0])
+            .collect()
+    };
+
+    let first = backups.create(100, &plot(&[0, 0])).unwrap().unwrap();
+    assert_eq!(backups.create(200, &plot(&[0, 0])).unwrap(), None);
+    let second = backups.create(300, &plot(&[1, 0])).unwrap().unwrap();
+    let third = backups.create(300, &plot(&[1, 2])).unwrap().unwrap();
+    assert_eq!(backups.ids(), &[first, second, third]);
+    assert_eq!(third, 301);
+    assert_eq!(backups.read(second).unwrap().chunks.len(), 1);
+
+    assert_eq!(ids(backups.restore(first).unwrap()), [0, 0]);
+    assert_eq!(ids(backups.restore(second).unwrap()), [1, 0]);
+
+    // The oldest backup is merged into the next one when it is removed
+    backups.prune(1).unwrap();
+    assert_eq!(backups.ids(), &[third]);
+    assert_eq!(ids(backups.restore(third).unwrap()), [1, 2]);
+
+    std::fs::remove_dir_all(path).unwrap();
+}
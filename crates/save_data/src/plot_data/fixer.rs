@@ -34,17 +34,17 @@ fn make_backup(path: impl AsRef<Path>) -> Result<(), PlotLoadError> {
     Ok(())
 }
 
-pub fn try_fix<const NUM_SECTIONS: usize>(
+pub fn try_fix(
     path: impl AsRef<Path>,
     info: FixInfo,
-) -> Result<Option<PlotData<NUM_SECTIONS>>, PlotLoadError> {
+    num_sections: usize,
+) -> Result<Option<PlotData>, PlotLoadError> {
     let result = match info {
         FixInfo::InvalidHeader => {
             let data = fs::read(&path)?;
-            pre_header::try_fix(&data)
-        }
-        // We don't have old versions yet
-        // FixInfo::OldVersion(_) => None,
+            pre_header::try_fix(&data, num_sections)
+        } // We don't have old versions yet
+          // FixInfo::OldVersion(_) => None,
     };
 
     Ok(match result {
@@ -24,7 +24,7 @@ pub struct PreHeaderPlotData {
     pub pending_ticks: Vec<TickEntry>,
 }
 
-pub fn try_fix<const NUM_SECTIONS: usize>(data: &[u8]) -> Option<PlotData<NUM_SECTIONS>> {
+pub fn try_fix(data: &[u8], num_sections: usize) -> Option<PlotData> {
     let old_data: PreHeaderPlotData = bincode::deserialize(data).ok()?;
 
     let data = PlotData {
@@ -36,8 +36,7 @@ pub fn try_fix<const NUM_SECTIONS: usize>(data: &[u8]) -> Option<PlotData<NUM_SE
             .chunk_data
             .into_iter()
             .map(|chunk| {
-                const INIT: Option<ChunkSectionData> = None;
-                let mut sections = [INIT; NUM_SECTIONS];
+                let mut sections: Vec<Option<ChunkSectionData>> = vec![None; num_sections];
                 for (y, section) in chunk.sections.into_iter() {
                     if (y as usize) < sections.len() {
                         sections[y as usize] = Some(section);
@@ -0,0 +1,309 @@
+//! Region files store every chunk section of a plot on its own, behind an index at the start of
+//! the file. The file is memory-mapped when it is opened, so opening a plot only reads the index,
+//! and each section is only decoded once it is needed.
+//!
+//! After the magic and the version, a region file contains the number of chunks and the number
+//! of sections per chunk, followed by the index. The first entry of the index points to the tps
+//! and the pending ticks of the plot, after which every chunk has an entry for its block entities
+//! followed by an entry for each of its sections. Sections that are completely air are not
+//! stored and have an entry with a length of 0.
+//!
+//! Since version 2, every entry is compressed with LZ4 on its own, prefixed with its
+//! uncompressed length. Version 1 region files are read without decompressing them.
+
+use super::{
+    ChunkData, ChunkSectionData, PlotData, PlotLoadError, PlotSaveError, Tps, PLOT_MAGIC, VERSION,
+};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickEntry;
+use memmap2::Mmap;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Magic, version, number of chunks and number of sections per chunk
+const HEADER_LEN: usize = 8 + 4 + 4 + 4;
+const ENTRY_LEN: usize = 8 + 4 + 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct IndexEntry {
+    offset: u64,
+    len: u32,
+    /// Only used for sections, so that the amount of blocks in a section is known without
+    /// decoding it
+    block_count: u32,
+}
+
+impl IndexEntry {
+    fn read(data: &[u8]) -> IndexEntry {
+        IndexEntry {
+            offset: LittleEndian::read_u64(&data[0..8]),
+            len: LittleEndian::read_u32(&data[8..12]),
+            block_count: LittleEndian::read_u32(&data[12..16]),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        out.write_u64::<LittleEndian>(self.offset)?;
+        out.write_u32::<LittleEndian>(self.len)?;
+        out.write_u32::<LittleEndian>(self.block_count)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegionMeta {
+    tps: Tps,
+    pending_ticks: Vec<TickEntry>,
+}
+
+/// The first version that region files were saved as
+const FIRST_REGION_VERSION: u32 = 1;
+
+/// The data of `entry`, which is decompressed if the region file is `version` 2 or later
+fn entry_data(data: &[u8], entry: IndexEntry, version: u32) -> Result<Cow<[u8]>, PlotLoadError> {
+    let start = entry.offset as usize;
+    let data = &data[start..start + entry.len as usize];
+    if version == FIRST_REGION_VERSION {
+        return Ok(Cow::Borrowed(data));
+    }
+    Ok(Cow::Owned(lz4_flex::decompress_size_prepended(data)?))
+}
+
+pub struct PlotRegion {
+    mmap: Mmap,
+    version: u32,
+    meta: RegionMeta,
+    index: Vec<IndexEntry>,
+    num_sections: usize,
+}
+
+impl PlotRegion {
+    /// Returns true if the file at `path` was saved as a region file
+    pub fn is_region_file(path: impl AsRef<Path>) -> io::Result<bool> {
+        let mut header = [0; 12];
+        match File::open(path)?.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        let version = LittleEndian::read_u32(&header[8..]);
+        Ok(&header[..8] == PLOT_MAGIC && (FIRST_REGION_VERSION..=VERSION).contains(&version))
+    }
+
+    /// Maps the region file at `path` and reads its index. None of the chunks are loaded yet.
+    pub fn open(path: impl AsRef<Path>) -> Result<PlotRegion, PlotLoadError> {
+        let file = File::open(path)?;
+        // Safety: region files are never changed in place. Saving a plot writes a new file and
+        // renames it over the old one, which leaves the mapped file untouched. The mapping has
+        // to be dropped before that, since Windows doesn't allow replacing a mapped file.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_LEN || &mmap[..8] != PLOT_MAGIC {
+            return Err(PlotLoadError::InvalidHeader);
+        }
+        let version = LittleEndian::read_u32(&mmap[8..12]);
+        if version > VERSION {
+            return Err(PlotLoadError::TooNew(version));
+        } else if version < FIRST_REGION_VERSION {
+            // Older versions store the whole plot at once, see `PlotData::load_from_file`
+            return Err(PlotLoadError::InvalidHeader);
+        }
+        let num_chunks = LittleEndian::read_u32(&mmap[12..16]) as usize;
+        let num_sections = LittleEndian::read_u32(&mmap[16..20]) as usize;
+
+        // The counts come from the file, so they can be large enough to overflow
+        let index_end = num_sections
+            .checked_add(1)
+            .and_then(|n| n.checked_mul(num_chunks))
+            .and_then(|n| n.checked_add(1))
+            .and_then(|num_entries| num_entries.checked_mul(ENTRY_LEN))
+            .and_then(|n| n.checked_add(HEADER_LEN));
+        let Some(index_end) = index_end.filter(|&end| end <= mmap.len()) else {
+            return Err(PlotLoadError::Corrupted);
+        };
+        let index: Vec<IndexEntry> = mmap[HEADER_LEN..index_end]
+            .chunks_exact(ENTRY_LEN)
+            .map(IndexEntry::read)
+            .collect();
+        // A corrupted offset can be so large that adding the length to it overflows
+        let out_of_bounds = |entry: &IndexEntry| match entry.offset.checked_add(entry.len as u64) {
+            Some(end) => end > mmap.len() as u64,
+            None => true,
+        };
+        if index.iter().any(out_of_bounds) {
+            return Err(PlotLoadError::Corrupted);
+        }
+
+        let meta = bincode::deserialize(&entry_data(&mmap, index[0], version)?)?;
+        Ok(PlotRegion {
+            mmap,
+            version,
+            meta,
+            index,
+            num_sections,
+        })
+    }
+
+    fn data(&self, entry: IndexEntry) -> Result<Cow<[u8]>, PlotLoadError> {
+        entry_data(&self.mmap, entry, self.version)
+    }
+
+    fn chunk_entry(&self, chunk: usize) -> usize {
+        1 + chunk * (1 + self.num_sections)
+    }
+
+    fn section_entry(&self, chunk: usize, section: usize) -> IndexEntry {
+        self.index[self.chunk_entry(chunk) + 1 + section]
+    }
+
+    pub fn tps(&self) -> Tps {
+        self.meta.tps
+    }
+
+    pub fn pending_ticks(&self) -> &[TickEntry] {
+        &self.meta.pending_ticks
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        (self.index.len() - 1) / (1 + self.num_sections)
+    }
+
+    /// The number of sections every chunk was saved with, which is the height of the world
+    /// the plot was saved in
+    pub fn num_sections(&self) -> usize {
+        self.num_sections
+    }
+
+    /// The amount of blocks that aren't air in a section, which doesn't require loading it
+    pub fn section_block_count(&self, chunk: usize, section: usize) -> u32 {
+        self.section_entry(chunk, section).block_count
+    }
+
+    pub fn load_section(
+        &self,
+        chunk: usize,
+        section: usize,
+    ) -> Result<Option<ChunkSectionData>, PlotLoadError> {
+        let entry = self.section_entry(chunk, section);
+        if entry.len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(bincode::deserialize(&self.data(entry)?)?))
+    }
+
+    pub fn load_block_entities(
+        &self,
+        chunk: usize,
+    ) -> Result<FxHashMap<BlockPos, BlockEntity>, PlotLoadError> {
+        let entry = self.index[self.chunk_entry(chunk)];
+        Ok(bincode::deserialize(&self.data(entry)?)?)
+    }
+
+    pub fn load_chunk(&self, chunk: usize) -> Result<ChunkData, PlotLoadError> {
+        let mut sections = Vec::with_capacity(self.num_sections);
+        for section in 0..self.num_sections {
+            sections.push(self.load_section(chunk, section)?);
+        }
+        Ok(ChunkData {
+            sections,
+            block_entities: self.load_block_entities(chunk)?,
+        })
+    }
+
+    /// Loads every chunk of the plot at once
+    pub fn load(&self) -> Result<PlotData, PlotLoadError> {
+        let chunk_data = (0..self.num_chunks())
+            .map(|chunk| self.load_chunk(chunk))
+            .collect::<Result<_, _>>()?;
+        Ok(PlotData {
+            tps: self.meta.tps,
+            chunk_data,
+            pending_ticks: self.meta.pending_ticks.clone(),
+        })
+    }
+}
+
+pub(super) fn write(path: &Path, data: &PlotData) -> Result<(), PlotSaveError> {
+    let num_sections = data
+        .chunk_data
+        .first()
+        .map_or(0, |chunk| chunk.sections.len());
+    debug_assert!(data
+        .chunk_data
+        .iter()
+        .all(|chunk| chunk.sections.len() == num_sections));
+    let num_entries = 1 + data.chunk_data.len() * (1 + num_sections);
+    let data_start = (HEADER_LEN + num_entries * ENTRY_LEN) as u64;
+    let mut index = Vec::with_capacity(num_entries);
+    let mut blobs = Vec::new();
+    let mut push_blob = |blob: Vec<u8>, block_count: u32| {
+        let blob = lz4_flex::compress_prepend_size(&blob);
+        let entry = IndexEntry {
+            offset: data_start + blobs.len() as u64,
+            len: blob.len() as u32,
+            block_count,
+        };
+        blobs.extend(blob);
+        entry
+    };
+
+    let meta = RegionMeta {
+        tps: data.tps,
+        pending_ticks: data.pending_ticks.clone(),
+    };
+    index.push(push_blob(bincode::serialize(&meta)?, 0));
+    for chunk in &data.chunk_data {
+        index.push(push_blob(bincode::serialize(&chunk.block_entities)?, 0));
+        for section in &chunk.sections {
+            index.push(match section {
+                Some(section) => {
+                    push_blob(bincode::serialize(section)?, section.block_count as u32)
+                }
+                None => IndexEntry::default(),
+            });
+        }
+    }
+
+    let mut header = Vec::with_capacity(data_start as usize);
+    header.extend_from_slice(PLOT_MAGIC);
+    header.write_u32::<LittleEndian>(VERSION)?;
+    header.write_u32::<LittleEndian>(data.chunk_data.len() as u32)?;
+    header.write_u32::<LittleEndian>(num_sections as u32)?;
+    for entry in &index {
+        entry.write(&mut header)?;
+    }
+
+    // A failed save leaves the old file intact, since it is replaced instead of written to
+    let tmp_path = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    file.write_all(&header)?;
+    file.write_all(&blobs)?;
+    file.sync_data()?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+#[test]
+fn huge_counts_are_corrupted() {
+    let path =
+        std::env::temp_dir().join(format!("mchprs_region_count_test_{}", std::process::id()));
+    let mut data = PLOT_MAGIC.to_vec();
+    data.extend(VERSION.to_le_bytes());
+    data.extend(u32::MAX.to_le_bytes());
+    data.extend(u32::MAX.to_le_bytes());
+    fs::write(&path, data).unwrap();
+    assert!(matches!(
+        PlotRegion::open(&path),
+        Err(PlotLoadError::Corrupted)
+    ));
+    fs::remove_file(path).unwrap();
+}
@@ -1,20 +1,35 @@
+use mchprs_blocks::BlockPos;
+use mchprs_core::anvil;
+use mchprs_core::bench;
+use mchprs_core::circuit_test;
+use mchprs_core::convert::{self, ConvertFormat};
+use mchprs_core::plot::worldedit::schematic::SchematicVersion;
+use mchprs_core::redpiler::CompilerOptions;
 use mchprs_core::server::MinecraftServer;
 use std::fs;
 use std::path::Path;
-use tracing::debug;
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::filter::LevelFilter;
-use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
 use tracing_subscriber::EnvFilter;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let subcommand = args.get(1).map(String::as_str);
+
     // Setup logging
     let logfile = tracing_appender::rolling::daily("./logs", "mchprs.log");
+    // The bench report is printed to stdout, so logs go to stderr instead
+    let writer = match subcommand {
+        Some("bench") => BoxMakeWriter::new(logfile.and(std::io::stderr)),
+        _ => BoxMakeWriter::new(logfile.and(std::io::stdout)),
+    };
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::INFO.into())
         .with_env_var("MCHPRS_LOG")
         .from_env_lossy();
     tracing_subscriber::fmt()
-        .with_writer(logfile.and(std::io::stdout))
+        .with_writer(writer)
         .with_env_filter(env_filter)
         .init();
 
@@ -29,5 +44,167 @@ fn main() {
         );
     }
 
+    match subcommand {
+        Some("convert") => {
+            run_convert(&args[2..]);
+            return;
+        }
+        Some("import") => {
+            run_import(&args[2..]);
+            return;
+        }
+        Some("export") => {
+            run_export(&args[2..]);
+            return;
+        }
+        Some("bench") => {
+            run_bench(&args[2..]);
+            return;
+        }
+        Some("test") => {
+            run_test(&args[2..]);
+            return;
+        }
+        _ => {}
+    }
+
+    if args.iter().any(|arg| arg == "--safe-mode") {
+        warn!("Starting in safe mode: plots will be loaded without pending ticks or automatic redpiler");
+        mchprs_core::plot::enable_safe_mode();
+    }
+
     MinecraftServer::run();
 }
+
+fn run_convert(args: &[String]) {
+    let v3 = args.iter().any(|arg| arg == "--sponge-v3");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let [input, output] = paths[..] else {
+        error!("Usage: mchprs convert <input> <output> [--sponge-v3]");
+        std::process::exit(1);
+    };
+    let output_format = v3.then_some(ConvertFormat::Sponge(SchematicVersion::V3));
+    if let Err(err) = convert::convert(Path::new(input), Path::new(output), output_format) {
+        error!("Conversion failed: {:?}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Parses the `<world> [x1 y1 z1 x2 y2 z2]` arguments of the import and export subcommands
+fn parse_world_args<'a>(
+    command: &str,
+    args: &'a [String],
+) -> (&'a Path, Option<(BlockPos, BlockPos)>) {
+    fn usage(command: &str) -> ! {
+        error!("Usage: mchprs {} <world> [x1 y1 z1 x2 y2 z2]", command);
+        std::process::exit(1);
+    }
+    let Some((world, coords)) = args.split_first() else {
+        usage(command)
+    };
+    let coords: Vec<i32> = match coords.iter().map(|c| c.parse()).collect() {
+        Ok(coords) => coords,
+        Err(_) => usage(command),
+    };
+    let bounds = match coords[..] {
+        [] => None,
+        [x1, y1, z1, x2, y2, z2] => Some((BlockPos::new(x1, y1, z1), BlockPos::new(x2, y2, z2))),
+        _ => usage(command),
+    };
+    (Path::new(world), bounds)
+}
+
+fn run_import(args: &[String]) {
+    let (world, bounds) = parse_world_args("import", args);
+    let report = match anvil::import(world, bounds) {
+        Ok(report) => report,
+        Err(err) => {
+            error!("Import failed: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "Imported {} chunks into {} plots",
+        report.chunks,
+        report.plots.len()
+    );
+    for (name, count) in &report.unsupported {
+        warn!("{} unsupported {} blocks were replaced by air", count, name);
+    }
+    if report.outside_height > 0 {
+        warn!(
+            "{} blocks outside of the world height were left out",
+            report.outside_height
+        );
+    }
+}
+
+fn run_export(args: &[String]) {
+    let (world, bounds) = parse_world_args("export", args);
+    let report = match anvil::export(world, bounds) {
+        Ok(report) => report,
+        Err(err) => {
+            error!("Export failed: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "Exported {} chunks from {} plots",
+        report.chunks,
+        report.plots.len()
+    );
+    if report.outside_height > 0 {
+        warn!(
+            "{} blocks above the height of a vanilla world were left out",
+            report.outside_height
+        );
+    }
+}
+
+fn run_bench(args: &[String]) {
+    let Some((input, args)) = args.split_first() else {
+        error!("Usage: mchprs bench <input> [--ticks=ticks] [--use=x,y,z] [compile flags]");
+        std::process::exit(1);
+    };
+    let result =
+        bench::BenchOptions::parse(args).and_then(|options| bench::run(Path::new(input), options));
+    match result {
+        Ok(report) => println!("{}", report.to_json()),
+        Err(err) => {
+            error!("Benchmark failed: {:?}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_test(args: &[String]) {
+    let [input, script, flags @ ..] = args else {
+        error!("Usage: mchprs test <input> <script> [compile flags]");
+        std::process::exit(1);
+    };
+    let options = CompilerOptions::parse(&flags.join(" "));
+    let results = match circuit_test::run(Path::new(input), Path::new(script), options) {
+        Ok(results) => results,
+        Err(err) => {
+            error!("Test failed to run: {:?}", err);
+            std::process::exit(1);
+        }
+    };
+    let mut failed = 0;
+    for result in &results {
+        if result.passed() {
+            info!("Passed: {}", result);
+        } else {
+            error!("Failed: {}", result);
+            failed += 1;
+        }
+    }
+    info!(
+        "{} of {} assertions passed",
+        results.len() - failed,
+        results.len()
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}